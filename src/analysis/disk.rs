@@ -0,0 +1,145 @@
+//! Tracking of small bright disks, such as planets, for alignment. The center of mass of every
+//! pixel above a fixed threshold is pulled around by moons, hot pixels and gradients in the sky,
+//! which matter little against the Sun or Moon filling the frame but are a large error against a
+//! disk a few dozen pixels across. Instead the largest connected bright region is found and its
+//! center refined over a circle of the same area.
+
+use sciimg::prelude::{Image, Offset};
+
+use crate::imageutil;
+
+/// Fraction of the way from the background to the peak level above which pixels count as part
+/// of the disk
+const DISK_LEVEL: f32 = 0.25;
+
+/// Smallest region, in pixels, accepted as a disk
+const MIN_DISK_AREA: usize = 9;
+
+/// The frame averaged to one band
+fn luminance(frame: &Image) -> Vec<f32> {
+    let bands = frame.num_bands();
+    (0..frame.height)
+        .flat_map(|y| {
+            (0..frame.width).map(move |x| {
+                (0..bands).map(|b| frame.get_band(b).get(x, y)).sum::<f32>() / bands as f32
+            })
+        })
+        .collect()
+}
+
+/// Indices of the largest 4-connected region of pixels set in `mask`
+fn largest_region(mask: &[bool], width: usize, height: usize) -> Vec<usize> {
+    let mut visited = vec![false; mask.len()];
+    let mut largest = vec![];
+    let mut stack = vec![];
+    for start in 0..mask.len() {
+        if !mask[start] || visited[start] {
+            continue;
+        }
+        let mut region = vec![];
+        visited[start] = true;
+        stack.push(start);
+        while let Some(i) = stack.pop() {
+            region.push(i);
+            let (x, y) = (i % width, i / width);
+            let neighbours = [
+                (x > 0).then(|| i - 1),
+                (x + 1 < width).then(|| i + 1),
+                (y > 0).then(|| i - width),
+                (y + 1 < height).then(|| i + width),
+            ];
+            neighbours.into_iter().flatten().for_each(|n| {
+                if mask[n] && !visited[n] {
+                    visited[n] = true;
+                    stack.push(n);
+                }
+            });
+        }
+        if region.len() > largest.len() {
+            largest = region;
+        }
+    }
+    largest
+}
+
+/// Level weighted centroid of the pixels in `indices`, with levels measured above `floor`
+fn centroid(
+    levels: &[f32],
+    width: usize,
+    floor: f32,
+    indices: impl Iterator<Item = usize>,
+) -> Option<(f32, f32)> {
+    let (sum_x, sum_y, total) = indices.fold((0.0, 0.0, 0.0), |(sx, sy, t), i| {
+        let w = (levels[i] - floor).max(0.0);
+        (
+            sx + (i % width) as f32 * w,
+            sy + (i / width) as f32 * w,
+            t + w,
+        )
+    });
+    (total > 0.0).then(|| (sum_x / total, sum_y / total))
+}
+
+/// The largest bright disk in an image, in pixel coordinates
+#[derive(Debug, Clone, Copy)]
+pub struct Disk {
+    pub x: f32,
+    pub y: f32,
+    /// Radius of a circle with the disk's area
+    pub radius: f32,
+}
+
+/// Offset of the center of the largest bright disk in `frame` from the frame's center, or None if
+/// no disk stands out from the background
+pub fn disk_offset(frame: &Image) -> Option<Offset> {
+    find_disk(frame).map(|disk| Offset {
+        h: disk.x - (frame.width / 2) as f32,
+        v: disk.y - (frame.height / 2) as f32,
+    })
+}
+
+/// The largest bright disk in `frame`, or None if no disk stands out from the background
+pub fn find_disk(frame: &Image) -> Option<Disk> {
+    let (width, height) = (frame.width, frame.height);
+    let levels = luminance(frame);
+    let background = imageutil::median(&mut levels.clone());
+    let peak = levels.iter().copied().fold(f32::MIN, f32::max);
+    if peak <= background {
+        return None;
+    }
+    let threshold = background + (peak - background) * DISK_LEVEL;
+
+    let mask: Vec<bool> = levels.iter().map(|v| *v >= threshold).collect();
+    let region = largest_region(&mask, width, height);
+    if region.len() < MIN_DISK_AREA {
+        return None;
+    }
+    let (cx, cy) = centroid(&levels, width, background, region.iter().copied())?;
+
+    // Refine over the circle with the region's area, which takes in the fainter limb evenly on
+    // all sides and leaves out anything touching the disk
+    let area_radius = (region.len() as f32 / std::f32::consts::PI).sqrt();
+    let radius = area_radius * 1.1;
+    let (x0, x1) = (
+        (cx - radius).floor().max(0.0) as usize,
+        ((cx + radius).ceil() as usize).min(width - 1),
+    );
+    let (y0, y1) = (
+        (cy - radius).floor().max(0.0) as usize,
+        ((cy + radius).ceil() as usize).min(height - 1),
+    );
+    let circle = (y0..=y1)
+        .flat_map(|y| (x0..=x1).map(move |x| (x, y)))
+        .filter(|(x, y)| {
+            let (dx, dy) = (*x as f32 - cx, *y as f32 - cy);
+            dx * dx + dy * dy <= radius * radius
+        })
+        .map(|(x, y)| y * width + x);
+    let (cx, cy) = centroid(&levels, width, background, circle).unwrap_or((cx, cy));
+
+    Some(Disk {
+        x: cx,
+        y: cy,
+        radius: area_radius,
+    })
+}
@@ -0,0 +1,143 @@
+//! Focus assistant: measures the quality of frames as they're added to a light file being
+//! captured, for judging focus and seeing at the telescope.
+
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use egui::{RichText, Ui};
+use egui_plot::{Line, Plot, PlotPoints};
+use epaint::Color32;
+use solhat::datasource::DataSource;
+
+use crate::analysis::sigma::{frame_quality, AnalysisOptions};
+use crate::process::framesource::FrameSource;
+use crate::state::ApplicationState;
+
+/// Number of scores kept for the rolling chart
+const HISTORY_LENGTH: usize = 200;
+
+/// Most frames measured each time frames are added. When capture outpaces measurement, only the
+/// newest frames are measured.
+const MAX_FRAMES_PER_UPDATE: usize = 5;
+
+/// Number of recent scores averaged for the readout
+const RECENT_AVERAGE_LENGTH: usize = 10;
+
+#[derive(Default)]
+pub struct FocusAssistant {
+    scores: Arc<Mutex<VecDeque<f64>>>,
+    /// Frames of the file which have been measured or skipped
+    frames_seen: usize,
+    busy: Arc<AtomicBool>,
+}
+
+impl FocusAssistant {
+    pub fn reset(&mut self) {
+        self.scores.lock().unwrap().clear();
+        self.frames_seen = 0;
+    }
+
+    /// Measures the newest of the frames added to `light` since the last update, on a
+    /// background thread. Skipped if the previous measurement is still running.
+    pub fn update(&mut self, light: &str, frame_count: usize, app_state: &ApplicationState) {
+        if frame_count < self.frames_seen {
+            // The file was replaced
+            self.reset();
+        }
+        if frame_count == self.frames_seen || self.busy.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        let frame_ids = frame_count
+            .saturating_sub(MAX_FRAMES_PER_UPDATE)
+            .max(self.frames_seen)..frame_count;
+        self.frames_seen = frame_count;
+
+        let light = light.to_owned();
+        let options = AnalysisOptions::from_state(app_state);
+        let threshold = app_state.obj_detection_threshold as f32;
+        let scores = self.scores.clone();
+        let busy = self.busy.clone();
+        std::thread::spawn(move || {
+            match FrameSource::open(&[light]) {
+                Ok(source) => {
+                    for frame_id in frame_ids {
+                        match source.get_frame(frame_id) {
+                            Ok(frame) => {
+                                let (_, score) = frame_quality(&frame.buffer, threshold, &options);
+                                let mut scores = scores.lock().unwrap();
+                                scores.push_back(score);
+                                while scores.len() > HISTORY_LENGTH {
+                                    scores.pop_front();
+                                }
+                            }
+                            Err(why) => warn!("Focus assistant failed to read frame: {}", why),
+                        }
+                    }
+                }
+                Err(why) => warn!("Focus assistant failed to open the light file: {}", why),
+            }
+            busy.store(false, Ordering::SeqCst);
+        });
+    }
+
+    pub fn ui(&mut self, ui: &mut Ui) {
+        let scores: Vec<f64> = self.scores.lock().unwrap().iter().cloned().collect();
+
+        ui.horizontal(|ui| {
+            if ui.button(t!("focus.reset")).clicked() {
+                self.reset();
+            }
+            ui.label(t!("focus.hint"));
+        });
+
+        let Some(latest) = scores.last() else {
+            ui.vertical_centered(|ui| {
+                ui.label(t!("focus.waiting"));
+            });
+            return;
+        };
+        let recent = &scores[scores.len().saturating_sub(RECENT_AVERAGE_LENGTH)..];
+        let recent_average = recent.iter().sum::<f64>() / recent.len() as f64;
+        let best = scores.iter().cloned().fold(f64::MIN, f64::max);
+
+        ui.horizontal(|ui| {
+            ui.vertical(|ui| {
+                ui.label(t!("focus.latest"));
+                ui.label(RichText::new(format!("{:.2}", latest)).size(64.0).strong());
+            });
+            ui.add_space(40.0);
+            ui.vertical(|ui| {
+                ui.label(t!("focus.recent_average", count = RECENT_AVERAGE_LENGTH));
+                ui.label(RichText::new(format!("{:.2}", recent_average)).size(40.0));
+            });
+            ui.add_space(40.0);
+            ui.vertical(|ui| {
+                ui.label(t!("focus.best"));
+                ui.label(
+                    RichText::new(format!("{:.2}", best))
+                        .size(40.0)
+                        .color(Color32::from_rgb(100, 200, 100)),
+                );
+            });
+        });
+
+        let points: PlotPoints = scores
+            .iter()
+            .enumerate()
+            .map(|(i, v)| [i as f64, *v])
+            .collect();
+        Plot::new("focus_assistant")
+            .allow_scroll(false)
+            .allow_zoom(false)
+            .allow_drag(false)
+            .show(ui, |plot_ui| {
+                plot_ui.line(
+                    Line::new(points)
+                        .color(Color32::LIGHT_BLUE)
+                        .width(2.0)
+                        .name(t!("focus.score")),
+                );
+            });
+    }
+}
@@ -3,6 +3,8 @@ use egui::{Response, Ui};
 use egui_plot::{Legend, Line, LineStyle, Plot, PlotPoints};
 use epaint::Color32;
 
+pub mod disk;
+pub mod focus;
 #[allow(dead_code)]
 pub mod sigma;
 pub mod threshold;
@@ -13,6 +15,10 @@ pub struct AnalysisChart {
     sma_period: usize,
     show_axes: bool,
     show_grid: bool,
+    /// Length of the capture time windows frames are grouped by, in minutes
+    group_minutes: f64,
+    /// Groups the user asked to queue a stacking job for each of
+    queue_request: Option<Vec<sigma::FrameGroup>>,
 }
 
 impl Default for AnalysisChart {
@@ -22,6 +28,8 @@ impl Default for AnalysisChart {
             sma_period: 5,
             show_axes: true,
             show_grid: true,
+            group_minutes: 2.0,
+            queue_request: None,
         }
     }
 }
@@ -34,6 +42,8 @@ impl AnalysisChart {
             sma_period: 5,
             show_axes: true,
             show_grid: true,
+            group_minutes: 2.0,
+            queue_request: None,
         }
     }
 
@@ -94,6 +104,7 @@ impl AnalysisChart {
             sma_period,
             show_axes,
             show_grid,
+            ..
         } = self;
         ui.horizontal(|ui| {
             ui.label(t!("dataanalysis.sma_period"));
@@ -107,17 +118,82 @@ impl AnalysisChart {
             ui.checkbox(show_grid, t!("dataanalysis.show_grid"));
         });
     }
+
+    /// Takes the frame groups the user asked to stack separately, if any
+    pub fn take_queue_request(&mut self) -> Option<Vec<sigma::FrameGroup>> {
+        self.queue_request.take()
+    }
+
+    fn groups_ui(&mut self, ui: &mut Ui) {
+        ui.horizontal(|ui| {
+            ui.label(t!("dataanalysis.group_minutes"));
+            ui.add(
+                egui::DragValue::new(&mut self.group_minutes)
+                    .speed(0.1)
+                    .clamp_range(0.1..=60.0)
+                    .max_decimals(1)
+                    .suffix(" min"),
+            );
+        });
+        let groups = self.data.groups(self.group_minutes);
+        egui::ScrollArea::vertical()
+            .max_height(180.0)
+            .show(ui, |ui| {
+                egui::Grid::new("analysis_groups")
+                    .num_columns(6)
+                    .spacing([20.0, 4.0])
+                    .striped(true)
+                    .show(ui, |ui| {
+                        ui.strong(t!("dataanalysis.group_start"));
+                        ui.strong(t!("dataanalysis.group_end"));
+                        ui.strong(t!("dataanalysis.group_frames"));
+                        ui.strong(t!("dataanalysis.group_mean"));
+                        ui.strong(t!("dataanalysis.group_min"));
+                        ui.strong(t!("dataanalysis.group_max"));
+                        ui.end_row();
+                        groups.iter().for_each(|group| {
+                            ui.label(group.start.format("%H:%M:%S").to_string());
+                            ui.label(group.end.format("%H:%M:%S").to_string());
+                            ui.label(group.count.to_string()).on_hover_text(t!(
+                                "dataanalysis.group_frame_ids",
+                                first = group.first_frame,
+                                last = group.last_frame
+                            ));
+                            ui.label(format!("{:.3}", group.mean_sigma));
+                            ui.label(format!("{:.3}", group.min_sigma));
+                            ui.label(format!("{:.3}", group.max_sigma));
+                            ui.end_row();
+                        });
+                    });
+            });
+        if ui
+            .add_enabled(
+                groups.len() > 1,
+                egui::Button::new(t!("dataanalysis.queue_groups", count = groups.len())),
+            )
+            .on_hover_text(t!("dataanalysis.queue_groups_hint"))
+            .clicked()
+        {
+            self.queue_request = Some(groups);
+        }
+    }
 }
 
 impl AnalysisChart {
     pub fn ui(&mut self, ui: &mut Ui) -> Response {
         self.options_ui(ui);
+        if !self.data.timestamps.is_empty() {
+            egui::CollapsingHeader::new(t!("dataanalysis.grouping"))
+                .id_source("analysis_grouping")
+                .show(ui, |ui| self.groups_ui(ui));
+        }
 
         let Self {
             data: _,
             sma_period: _,
             show_axes,
             show_grid,
+            ..
         } = self;
 
         let plot = Plot::new("data_analysis")
@@ -1,16 +1,21 @@
+use std::collections::BTreeMap;
 use std::sync::{Arc, Mutex};
 
 use anyhow::Result;
+use chrono::{DateTime, Utc};
 use rayon::prelude::*;
+use sciimg::prelude::{Image, ImageBuffer, Offset};
 use sciimg::{max, min, quality};
 use solhat::calibrationframe::CalibrationImage;
 use solhat::context::ProcessContext;
 use solhat::datasource::DataSource;
 use solhat::framerecord::FrameRecord;
-use solhat::ser::SerFile;
 
+use crate::analysis::disk;
 use crate::cancel::{self, *};
-use crate::state::ApplicationState;
+use crate::imageutil;
+use crate::process::framesource::FrameSource;
+use crate::state::{AnalysisChannel, ApplicationState};
 use crate::taskstatus::*;
 
 ///////////////////////////////////////////////////////
@@ -29,16 +34,136 @@ pub struct AnalysisRange {
     max: f64,
 }
 
+/// Options controlling how per-frame quality is estimated.
+#[derive(Debug, Clone)]
+pub struct AnalysisOptions {
+    pub window_size: usize,
+    pub binning: usize,
+    /// Sample point offsets, in full resolution pixels, relative to the frame's center of mass.
+    /// Quality is averaged over all points.
+    pub points: Vec<[i32; 2]>,
+    pub channel: AnalysisChannel,
+    /// Align on a fit of the largest bright disk rather than the center of mass
+    pub disk_tracking: bool,
+}
+
+impl AnalysisOptions {
+    pub fn from_state(app_state: &ApplicationState) -> Self {
+        AnalysisOptions {
+            window_size: app_state.analysis_window_size,
+            binning: app_state.analysis_binning.factor(),
+            points: app_state.analysis_point_offsets(),
+            channel: app_state.analysis_channel,
+            disk_tracking: app_state.planetary,
+        }
+    }
+}
+
+/// Averages the quality estimation over each sample point in `options` which fits within the
+/// buffer. Falls back to the center of mass if no sample points fit.
+///
+/// With binning, only the window around each sample point is binned rather than the whole buffer.
+fn estimate_quality(
+    buffer: &ImageBuffer,
+    options: &AnalysisOptions,
+    center_x: i64,
+    center_y: i64,
+) -> f64 {
+    let binning = options.binning.max(1) as i64;
+    let width = buffer.width as i64 / binning;
+    let height = buffer.height as i64 / binning;
+    if width == 0 || height == 0 {
+        // Binned away entirely, leaving nothing to measure
+        return 0.0;
+    }
+    let window_size = (options.window_size / binning as usize).max(2);
+    let half_window = (window_size / 2) as i64;
+
+    let in_bounds = |x: i64, y: i64| {
+        x >= half_window && y >= half_window && x + half_window < width && y + half_window < height
+    };
+
+    // Quality at binned coordinates `x`, `y`
+    let quality_at = |x: i64, y: i64| {
+        if binning == 1 {
+            return quality::get_point_quality_estimation_on_buffer(
+                buffer,
+                window_size,
+                x as usize,
+                y as usize,
+            ) as f64;
+        }
+        let x0 = (x - half_window).max(0);
+        let y0 = (y - half_window).max(0);
+        let x1 = (x + half_window + 1).min(width);
+        let y1 = (y + half_window + 1).min(height);
+        let window = imageutil::bin_region(
+            buffer,
+            binning as usize,
+            x0 as usize,
+            y0 as usize,
+            (x1 - x0) as usize,
+            (y1 - y0) as usize,
+        );
+        quality::get_point_quality_estimation_on_buffer(
+            &window,
+            window_size,
+            (x - x0) as usize,
+            (y - y0) as usize,
+        ) as f64
+    };
+
+    let qualities: Vec<f64> = options
+        .points
+        .iter()
+        .map(|[dx, dy]| {
+            (
+                (center_x + *dx as i64) / binning,
+                (center_y + *dy as i64) / binning,
+            )
+        })
+        .filter(|(x, y)| in_bounds(*x, *y))
+        .map(|(x, y)| quality_at(x, y))
+        .collect();
+
+    if qualities.is_empty() {
+        let x = (center_x / binning).clamp(0, width - 1);
+        let y = (center_y / binning).clamp(0, height - 1);
+        quality_at(x, y)
+    } else {
+        qualities.iter().sum::<f64>() / qualities.len() as f64
+    }
+}
+
 #[derive(Debug, Clone, Default)]
 pub struct AnalysisSeries {
     pub sigma_list: Vec<f64>,
+    /// Frame id of each value in `sigma_list`
+    pub frame_ids: Vec<usize>,
+    /// Capture time of each value in `sigma_list`
+    pub timestamps: Vec<DateTime<Utc>>,
+}
+
+/// Frames captured within one window of time, with their quality statistics
+#[derive(Debug, Clone)]
+pub struct FrameGroup {
+    /// Capture times of the group's first and last frames
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    /// Lowest and highest frame ids in the group
+    pub first_frame: usize,
+    pub last_frame: usize,
+    pub count: usize,
+    pub mean_sigma: f64,
+    pub min_sigma: f64,
+    pub max_sigma: f64,
 }
 
 #[allow(dead_code)]
 impl AnalysisSeries {
     pub fn sorted_list(&self) -> Vec<f64> {
         let mut sorted = self.sigma_list.clone();
-        sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        sorted.sort_by(f64::total_cmp);
         sorted.reverse();
         sorted
     }
@@ -55,6 +180,42 @@ impl AnalysisSeries {
         AnalysisRange { min: mn, max: mx }
     }
 
+    /// Splits the frames into consecutive windows of `minutes`, counted from the first frame's
+    /// capture time. Windows without frames are left out.
+    pub fn groups(&self, minutes: f64) -> Vec<FrameGroup> {
+        let Some(first) = self.timestamps.iter().min() else {
+            return vec![];
+        };
+        let window_ms = (minutes * 60_000.0).max(1.0);
+        let mut windows: BTreeMap<i64, Vec<usize>> = BTreeMap::new();
+        self.timestamps.iter().enumerate().for_each(|(i, t)| {
+            let elapsed = (*t - *first).num_milliseconds() as f64;
+            windows
+                .entry((elapsed / window_ms).floor() as i64)
+                .or_default()
+                .push(i);
+        });
+
+        windows
+            .values()
+            .map(|members| {
+                let sigmas: Vec<f64> = members.iter().map(|i| self.sigma_list[*i]).collect();
+                let times = members.iter().map(|i| self.timestamps[*i]);
+                let ids = members.iter().map(|i| self.frame_ids[*i]);
+                FrameGroup {
+                    start: times.clone().min().unwrap_or(*first),
+                    end: times.max().unwrap_or(*first),
+                    first_frame: ids.clone().min().unwrap_or_default(),
+                    last_frame: ids.max().unwrap_or_default(),
+                    count: members.len(),
+                    mean_sigma: sigmas.iter().sum::<f64>() / sigmas.len() as f64,
+                    min_sigma: sigmas.iter().copied().fold(f64::MAX, f64::min),
+                    max_sigma: sigmas.iter().copied().fold(f64::MIN, f64::max),
+                }
+            })
+            .collect()
+    }
+
     pub fn sma(&self, window: usize) -> Vec<f64> {
         let half_win = window / 2;
         let mut sma: Vec<f64> = vec![];
@@ -77,7 +238,8 @@ pub async fn run_sigma_analysis(
     app_state: ApplicationState,
 ) -> Result<AnalysisSeries, TaskCompletion> {
     let params = app_state.to_parameters();
-    let context = match ProcessContext::<SerFile>::create_with_calibration_frames(
+    let options = AnalysisOptions::from_state(&app_state);
+    let context = match ProcessContext::<FrameSource>::create_with_calibration_frames(
         &params,
         CalibrationImage::new_empty(),
         CalibrationImage::new_empty(),
@@ -105,69 +267,117 @@ pub async fn run_sigma_analysis(
         // check_cancel_status(&sender)
     };
 
-    let frame_records =
-        match frame_analysis_window_size(&context, context.parameters.analysis_window_size, f) {
-            Ok(frame_records) => frame_records,
-            Err(why) => return Err(cancel::TaskCompletion::Error(format!("Error: {:?}", why))),
-        };
+    let frames = match frame_analysis_with_timestamps(&context, &options, f) {
+        Ok(frames) => frames,
+        Err(why) => return Err(cancel::TaskCompletion::Error(format!("Error: {:?}", why))),
+    };
 
-    let mut sigma_list: Vec<f64> = vec![];
-    frame_records
+    let mut series = AnalysisSeries::default();
+    frames
         .iter()
-        .filter(|fr| {
+        .filter(|(fr, _)| {
             let min_sigma = context.parameters.min_sigma.unwrap_or(std::f64::MIN);
             let max_sigma = context.parameters.max_sigma.unwrap_or(std::f64::MAX);
             fr.sigma >= min_sigma && fr.sigma <= max_sigma
         })
-        .for_each(|fr| {
-            sigma_list.push(fr.sigma);
+        .for_each(|(fr, timestamp)| {
+            series.sigma_list.push(fr.sigma);
+            series.frame_ids.push(fr.frame_id);
+            series.timestamps.push(*timestamp);
         });
 
-    Ok(AnalysisSeries { sigma_list })
+    Ok(series)
+}
+
+/// Center of mass offset of a frame, and its quality estimated around the center of mass. With
+/// disk tracking the center of the disk is used, falling back to the center of mass if no disk is
+/// found.
+pub fn frame_quality(frame: &Image, threshold: f32, options: &AnalysisOptions) -> (Offset, f64) {
+    let offset = options
+        .disk_tracking
+        .then(|| disk::disk_offset(frame))
+        .flatten()
+        .unwrap_or_else(|| frame.calc_center_of_mass_offset(threshold, 0));
+
+    let x = (frame.width / 2) as i64 + offset.h as i64;
+    let y = (frame.height / 2) as i64 + offset.v as i64;
+
+    // If monochrome, this will perform the analysis on the only band. If RGB, we perform analysis
+    // on the selected channel.
+    let luminance;
+    let band = if frame.num_bands() < 3 {
+        frame.get_band(0)
+    } else {
+        match options.channel {
+            AnalysisChannel::Red => frame.get_band(0),
+            AnalysisChannel::Green => frame.get_band(1),
+            AnalysisChannel::Blue => frame.get_band(2),
+            AnalysisChannel::Luminance => {
+                luminance = imageutil::luminance_buffer(frame);
+                &luminance
+            }
+        }
+    };
+    (offset, estimate_quality(band, options, x, y))
 }
 
 /// Combined method of center-of-mass and sigma analysis. This is to limit the number of
 /// frame reads from disk which are rather expensive in terms of CPU and time.
+///
+/// When `options.binning` is greater than one, the quality estimation is performed on binned
+/// copies of the windows around the sample points. Center of mass is always computed at full
+/// resolution as it's used for alignment. Quality is averaged over the sample points in
+/// `options`.
 pub fn frame_analysis_window_size<C, F>(
     context: &ProcessContext<F>,
-    window_size: usize,
+    options: &AnalysisOptions,
     on_frame_checked: C,
 ) -> Result<Vec<FrameRecord>>
+where
+    C: Fn(&FrameRecord) + Send + Sync + 'static,
+    F: DataSource + Send + Sync + 'static,
+{
+    Ok(
+        frame_analysis_with_timestamps(context, options, on_frame_checked)?
+            .into_iter()
+            .map(|(fr, _)| fr)
+            .collect(),
+    )
+}
+
+/// As [`frame_analysis_window_size`], also returning the capture time of each frame
+pub fn frame_analysis_with_timestamps<C, F>(
+    context: &ProcessContext<F>,
+    options: &AnalysisOptions,
+    on_frame_checked: C,
+) -> Result<Vec<(FrameRecord, DateTime<Utc>)>>
 where
     C: Fn(&FrameRecord) + Send + Sync + 'static,
     F: DataSource + Send + Sync + 'static,
 {
     //Err(anyhow!("Testing Error"))
 
-    let frame_records: Vec<FrameRecord> = context
+    // Frames are processed on the rayon pool, outside of the job's task
+    let slot = current_slot();
+    let frame_records: Vec<(FrameRecord, DateTime<Utc>)> = context
         .frame_records
         .par_iter()
         .map(|fr| {
             // If the process has been cancelled we will return a None. The rest will
             // cycle through, but keep returning Nones.
-            if !is_cancel_requested() {
+            wait_while_slot_paused(slot);
+            if !is_slot_cancel_requested(slot) {
                 let mut fr_copy = fr.clone();
                 let frame = fr.get_frame(context).expect("");
 
-                fr_copy.offset = frame.buffer.calc_center_of_mass_offset(
+                (fr_copy.offset, fr_copy.sigma) = frame_quality(
+                    &frame.buffer,
                     context.parameters.obj_detection_threshold as f32,
-                    0,
+                    options,
                 );
 
-                let x = frame.buffer.width / 2 + fr_copy.offset.h as usize;
-                let y = frame.buffer.height / 2 + fr_copy.offset.v as usize;
-
-                // If monochrome, this will perform the analysis on the only band. If RGB, we perform analysis
-                // on the red band.
-                fr_copy.sigma = quality::get_point_quality_estimation_on_buffer(
-                    frame.buffer.get_band(0),
-                    window_size,
-                    x,
-                    y,
-                ) as f64;
-
                 on_frame_checked(&fr_copy);
-                Some(fr_copy)
+                Some((fr_copy, frame.timestamp.to_chrono_utc()))
             } else {
                 None
             }
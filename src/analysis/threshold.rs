@@ -2,9 +2,9 @@ use anyhow::Result;
 use sciimg::prelude::*;
 use solhat::calibrationframe::CalibrationImage;
 use solhat::context::{ProcessContext, ProcessParameters};
-use solhat::ser::SerFile;
 use solhat::threshtest::compute_rgb_threshtest_image;
 
+use crate::process::framesource::FrameSource;
 use crate::taskstatus::*;
 
 ///////////////////////////////////////////////////////
@@ -13,7 +13,7 @@ use crate::taskstatus::*;
 
 pub fn run_thresh_test(params: &ProcessParameters) -> Result<Image> {
     set_task_status(&t!("tasks.threshold_test"), 2, 1);
-    let context: ProcessContext<SerFile> = ProcessContext::create_with_calibration_frames(
+    let context: ProcessContext<FrameSource> = ProcessContext::create_with_calibration_frames(
         params,
         CalibrationImage::new_empty(),
         CalibrationImage::new_empty(),
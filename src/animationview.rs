@@ -0,0 +1,147 @@
+use std::path::{Path, PathBuf};
+
+use egui::Context;
+
+use crate::process::animation::{AnimationFormat, AnimationOptions};
+
+/// Exports a range of a capture's frames to an animated GIF or MP4
+pub struct AnimationWindow {
+    pub open: bool,
+    source: Option<String>,
+    frame_count: usize,
+    options: AnimationOptions,
+}
+
+impl Default for AnimationWindow {
+    fn default() -> Self {
+        Self {
+            open: false,
+            source: None,
+            frame_count: 0,
+            options: AnimationOptions {
+                format: AnimationFormat::Gif,
+                first_frame: 0,
+                last_frame: 0,
+                downscale: 2,
+                frame_rate: 15,
+            },
+        }
+    }
+}
+
+impl AnimationWindow {
+    /// Opens the window for `source`, with the whole capture selected
+    pub fn show_for(&mut self, source: String, frame_count: usize) {
+        if self.source.as_ref() != Some(&source) || self.frame_count != frame_count {
+            self.options.first_frame = 0;
+            self.options.last_frame = frame_count.saturating_sub(1);
+        }
+        self.source = Some(source);
+        self.frame_count = frame_count;
+        self.open = true;
+    }
+
+    /// Shows the window. Returns the capture, options and output path once an export is chosen.
+    /// Exports can't be started while `task_running`.
+    pub fn ui(
+        &mut self,
+        ctx: &Context,
+        default_folder: PathBuf,
+        task_running: bool,
+    ) -> Option<(String, AnimationOptions, PathBuf)> {
+        let mut export = None;
+        let mut open = self.open;
+        let last = self.frame_count.saturating_sub(1);
+        egui::Window::new(t!("animation.title"))
+            .open(&mut open)
+            .resizable(false)
+            .show(ctx, |ui| {
+                let Some(source) = &self.source else {
+                    return;
+                };
+                ui.label(t!("animation.description"));
+                ui.monospace(source);
+                ui.separator();
+
+                egui::Grid::new("animation_options")
+                    .num_columns(2)
+                    .spacing([20.0, 6.0])
+                    .show(ui, |ui| {
+                        ui.label(t!("animation.format"));
+                        ui.horizontal(|ui| {
+                            for format in AnimationFormat::ALL {
+                                ui.selectable_value(
+                                    &mut self.options.format,
+                                    format,
+                                    format.as_str(),
+                                );
+                            }
+                        });
+                        ui.end_row();
+
+                        ui.label(t!("animation.frames"));
+                        ui.horizontal(|ui| {
+                            ui.add(
+                                egui::DragValue::new(&mut self.options.first_frame)
+                                    .clamp_range(0..=self.options.last_frame),
+                            );
+                            ui.label("–");
+                            ui.add(
+                                egui::DragValue::new(&mut self.options.last_frame)
+                                    .clamp_range(self.options.first_frame..=last),
+                            );
+                        });
+                        ui.end_row();
+
+                        ui.label(t!("animation.downscale"));
+                        ui.add(
+                            egui::DragValue::new(&mut self.options.downscale)
+                                .clamp_range(1..=8)
+                                .prefix("1/"),
+                        )
+                        .on_hover_text(t!("animation.downscale_hint"));
+                        ui.end_row();
+
+                        ui.label(t!("animation.frame_rate"));
+                        ui.add(
+                            egui::DragValue::new(&mut self.options.frame_rate)
+                                .clamp_range(1..=60)
+                                .suffix(" fps"),
+                        );
+                        ui.end_row();
+                    });
+
+                let frames = self.options.last_frame + 1 - self.options.first_frame;
+                ui.label(t!(
+                    "animation.summary",
+                    count = frames,
+                    duration = format!("{:.1}", frames as f32 / self.options.frame_rate as f32)
+                ));
+                if self.options.format == AnimationFormat::Mp4 {
+                    ui.weak(t!("animation.mp4_hint"));
+                }
+
+                if ui
+                    .add_enabled(!task_running, egui::Button::new(t!("animation.export")))
+                    .clicked()
+                {
+                    let extension = self.options.format.extension();
+                    let stem = Path::new(source)
+                        .file_stem()
+                        .map(|s| s.to_string_lossy().to_string())
+                        .unwrap_or_default();
+                    if let Some(path) = rfd::FileDialog::new()
+                        .set_title(t!("animation.export"))
+                        .set_directory(&default_folder)
+                        .set_file_name(format!("{}.{}", stem, extension))
+                        .add_filter(self.options.format.as_str(), &[extension])
+                        .save_file()
+                    {
+                        export = Some((source.clone(), self.options.clone(), path));
+                    }
+                }
+            });
+        self.open = open && export.is_none();
+        export
+    }
+}
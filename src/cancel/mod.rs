@@ -1,6 +1,8 @@
 use crate::taskstatus::*;
 use anyhow::Result;
+use std::collections::BTreeMap;
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use std::{error::Error, fmt};
 
 #[derive(Debug, PartialEq, Eq)]
@@ -25,38 +27,116 @@ impl fmt::Display for TaskCompletion {
     }
 }
 
+/// Cancel and pause state of one job slot
 pub struct CancelContainer {
     pub status: CancelStatus,
+    pub paused: bool,
+}
+
+impl Default for CancelContainer {
+    fn default() -> Self {
+        CancelContainer {
+            status: CancelStatus::NoStatus,
+            paused: false,
+        }
+    }
 }
 
 lazy_static! {
-    pub static ref CANCEL_TASK: Arc<Mutex<CancelContainer>> =
-        Arc::new(Mutex::new(CancelContainer {
-            status: CancelStatus::NoStatus
-        }));
+    // Keyed by job slot, as task statuses are. The primary slot's state applies to every job so a
+    // parallel run can be cancelled or paused as a whole.
+    pub static ref CANCEL_TASK: Arc<Mutex<BTreeMap<usize, CancelContainer>>> =
+        Arc::new(Mutex::new(BTreeMap::new()));
 }
 
+/// How often a paused task checks whether it has been resumed
+const PAUSE_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+fn update_slot<F: FnOnce(&mut CancelContainer)>(slot: usize, update: F) {
+    update(CANCEL_TASK.lock().unwrap().entry(slot).or_default());
+}
+
+fn slot_state<R, F: Fn(&CancelContainer) -> R>(slot: usize, read: F) -> R {
+    read(
+        CANCEL_TASK
+            .lock()
+            .unwrap()
+            .get(&slot)
+            .unwrap_or(&CancelContainer::default()),
+    )
+}
+
+/// Requests cancellation of the primary slot's task, and with it every job of a parallel run
 pub fn set_request_cancel() {
-    CANCEL_TASK.lock().unwrap().status = CancelStatus::CancelRequested;
+    set_slot_request_cancel(PRIMARY_SLOT);
 }
 
-pub fn set_task_cancelled() {
-    CANCEL_TASK.lock().unwrap().status = CancelStatus::Cancelled;
+pub fn set_slot_request_cancel(slot: usize) {
+    update_slot(slot, |c| c.status = CancelStatus::CancelRequested);
 }
 
+/// Clears the cancel and pause state of the primary slot
 pub fn reset_cancel_status() {
-    CANCEL_TASK.lock().unwrap().status = CancelStatus::NoStatus;
+    clear_slot(PRIMARY_SLOT);
+}
+
+/// Clears the cancel and pause state of `slot`, once its job has stopped
+pub fn clear_slot(slot: usize) {
+    CANCEL_TASK.lock().unwrap().remove(&slot);
 }
 
-pub fn is_cancel_requested() -> bool {
-    CANCEL_TASK.lock().unwrap().status == CancelStatus::CancelRequested
+/// True if cancellation of `slot`, or of the primary slot, has been requested
+pub fn is_slot_cancel_requested(slot: usize) -> bool {
+    let requested = |s| slot_state(s, |c| c.status == CancelStatus::CancelRequested);
+    requested(slot) || (slot != PRIMARY_SLOT && requested(PRIMARY_SLOT))
+}
+
+/// Pauses or resumes the primary slot's task, and with it every job of a parallel run
+pub fn set_paused(paused: bool) {
+    set_slot_paused(PRIMARY_SLOT, paused);
+}
+
+pub fn set_slot_paused(slot: usize, paused: bool) {
+    update_slot(slot, |c| c.paused = paused);
+}
+
+pub fn is_paused() -> bool {
+    slot_state(PRIMARY_SLOT, |c| c.paused)
+}
+
+/// True if `slot`, or the primary slot, is paused
+pub fn is_slot_paused(slot: usize) -> bool {
+    slot_state(slot, |c| c.paused) || (slot != PRIMARY_SLOT && is_paused())
+}
+
+/// Blocks the calling thread while processing is paused. Returns early if cancellation is
+/// requested so a paused task can still be cancelled.
+pub fn wait_while_paused() {
+    wait_while_slot_paused(current_slot());
+}
+
+/// As [`wait_while_paused`], for the job in `slot`. Work running on other threads, such as rayon
+/// pools, has to name the slot of the job it belongs to.
+pub fn wait_while_slot_paused(slot: usize) {
+    while is_slot_paused(slot) && !is_slot_cancel_requested(slot) {
+        std::thread::sleep(PAUSE_POLL_INTERVAL);
+    }
 }
 
 pub fn check_cancel_status() -> Result<TaskCompletion, TaskCompletion> {
-    if is_cancel_requested() {
-        set_task_cancelled();
-        set_task_completed();
-        reset_cancel_status();
+    check_slot_cancel_status(current_slot())
+}
+
+/// As [`check_cancel_status`], for the job in `slot`. A cancellation of the whole run is left in
+/// place for the other jobs to see.
+pub fn check_slot_cancel_status(slot: usize) -> Result<TaskCompletion, TaskCompletion> {
+    wait_while_slot_paused(slot);
+    if is_slot_cancel_requested(slot) {
+        update_slot(slot, |c| {
+            c.status = CancelStatus::Cancelled;
+            c.paused = false;
+        });
+        set_slot_task_completed(slot);
         warn!("Task cancellation request detected. Stopping progress");
         Err(TaskCompletion::Cancelled)
     } else {
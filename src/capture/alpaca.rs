@@ -0,0 +1,247 @@
+//! Minimal ASCOM Alpaca camera client, over plain HTTP/1.1 with JSON responses
+
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Result};
+use chrono::Utc;
+use serde_json::Value;
+
+use super::{Camera, CaptureSettings, CapturedFrame};
+
+/// Time allowed for an exposure to be read out, beyond its length
+const DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How often the camera is asked whether the image is ready
+const IMAGE_READY_POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+/// Identifies this application to the Alpaca server
+const CLIENT_ID: u32 = 7624;
+
+/// Decodes a body sent with chunked transfer encoding
+fn decode_chunked(body: &[u8]) -> Result<Vec<u8>> {
+    let mut decoded = vec![];
+    let mut rest = body;
+    loop {
+        let line_end = rest
+            .windows(2)
+            .position(|w| w == b"\r\n")
+            .ok_or_else(|| anyhow!("Malformed chunked response"))?;
+        let size_text = String::from_utf8_lossy(&rest[..line_end]);
+        let size = usize::from_str_radix(size_text.split(';').next().unwrap_or("").trim(), 16)?;
+        if size == 0 {
+            return Ok(decoded);
+        }
+        let chunk = rest
+            .get(line_end + 2..line_end + 2 + size)
+            .ok_or_else(|| anyhow!("Chunked response is truncated"))?;
+        decoded.extend_from_slice(chunk);
+        rest = rest.get(line_end + 4 + size..).unwrap_or_default();
+    }
+}
+
+pub struct AlpacaCamera {
+    host: String,
+    port: u16,
+    device: u32,
+    transaction_id: u32,
+    name: String,
+    sensor_size: (u32, u32),
+    pixel_depth: usize,
+}
+
+impl AlpacaCamera {
+    pub fn connect(host: &str, port: u16, device: u32) -> Result<AlpacaCamera> {
+        let mut camera = AlpacaCamera {
+            host: host.to_owned(),
+            port,
+            device,
+            transaction_id: 0,
+            name: String::new(),
+            sensor_size: (0, 0),
+            pixel_depth: 16,
+        };
+        camera.put("connected", &[("Connected", "true".to_owned())])?;
+        camera.name = camera.get("name")?.as_str().unwrap_or_default().to_owned();
+        camera.sensor_size = (
+            camera.get("cameraxsize")?.as_u64().unwrap_or(0) as u32,
+            camera.get("cameraysize")?.as_u64().unwrap_or(0) as u32,
+        );
+        let max_adu = camera.get("maxadu")?.as_u64().unwrap_or(65535);
+        camera.pixel_depth = if max_adu <= 255 { 8 } else { 16 };
+        info!(
+            "Connected to Alpaca camera {} ({}x{}, max ADU {})",
+            camera.name, camera.sensor_size.0, camera.sensor_size.1, max_adu
+        );
+        Ok(camera)
+    }
+
+    fn request(
+        &mut self,
+        method: &str,
+        property: &str,
+        params: &[(&str, String)],
+    ) -> Result<Value> {
+        self.transaction_id += 1;
+        let mut params = params.to_vec();
+        params.push(("ClientID", CLIENT_ID.to_string()));
+        params.push(("ClientTransactionID", self.transaction_id.to_string()));
+        let params = params
+            .iter()
+            .map(|(k, v)| format!("{}={}", k, v))
+            .collect::<Vec<String>>()
+            .join("&");
+
+        let path = format!("/api/v1/camera/{}/{}", self.device, property);
+        let request = if method == "GET" {
+            format!(
+                "GET {}?{} HTTP/1.1\r\nHost: {}:{}\r\nAccept: application/json\r\nConnection: close\r\n\r\n",
+                path, params, self.host, self.port
+            )
+        } else {
+            format!(
+                "PUT {} HTTP/1.1\r\nHost: {}:{}\r\nAccept: application/json\r\nContent-Type: application/x-www-form-urlencoded\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                path, self.host, self.port, params.len(), params
+            )
+        };
+
+        let mut stream = TcpStream::connect((self.host.as_str(), self.port)).map_err(|why| {
+            anyhow!(
+                "Failed to connect to Alpaca server {}:{}: {}",
+                self.host,
+                self.port,
+                why
+            )
+        })?;
+        stream.set_read_timeout(Some(DOWNLOAD_TIMEOUT))?;
+        stream.write_all(request.as_bytes())?;
+        let mut response = vec![];
+        stream.read_to_end(&mut response)?;
+
+        let header_end = response
+            .windows(4)
+            .position(|w| w == b"\r\n\r\n")
+            .ok_or_else(|| anyhow!("Malformed response from the Alpaca server"))?;
+        let headers = String::from_utf8_lossy(&response[..header_end]).to_lowercase();
+        let status = headers.split_whitespace().nth(1).unwrap_or_default();
+        if status != "200" {
+            return Err(anyhow!(
+                "Alpaca server responded to {} with HTTP status {}",
+                property,
+                status
+            ));
+        }
+        let body = &response[header_end + 4..];
+        let body = if headers.contains("transfer-encoding: chunked") {
+            decode_chunked(body)?
+        } else {
+            body.to_vec()
+        };
+
+        let json: Value = serde_json::from_slice(&body)?;
+        let error_number = json["ErrorNumber"].as_i64().unwrap_or(0);
+        if error_number != 0 {
+            return Err(anyhow!(
+                "Camera reported error {} for {}: {}",
+                error_number,
+                property,
+                json["ErrorMessage"].as_str().unwrap_or_default()
+            ));
+        }
+        Ok(json["Value"].clone())
+    }
+
+    fn get(&mut self, property: &str) -> Result<Value> {
+        self.request("GET", property, &[])
+    }
+
+    fn put(&mut self, property: &str, params: &[(&str, String)]) -> Result<Value> {
+        self.request("PUT", property, params)
+    }
+}
+
+impl Camera for AlpacaCamera {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn configure(&mut self, settings: &CaptureSettings) -> Result<()> {
+        if let Err(why) = self.put("gain", &[("Gain", (settings.gain as i32).to_string())]) {
+            warn!("Failed to set the camera's gain: {}", why);
+        }
+        let (x, y, width, height) = if settings.use_roi {
+            (
+                settings.roi.x,
+                settings.roi.y,
+                settings.roi.width,
+                settings.roi.height,
+            )
+        } else {
+            (0, 0, self.sensor_size.0, self.sensor_size.1)
+        };
+        self.put("startx", &[("StartX", x.to_string())])?;
+        self.put("starty", &[("StartY", y.to_string())])?;
+        self.put("numx", &[("NumX", width.to_string())])?;
+        self.put("numy", &[("NumY", height.to_string())])?;
+        Ok(())
+    }
+
+    fn expose(&mut self, exposure_secs: f64) -> Result<CapturedFrame> {
+        let timestamp = Utc::now();
+        self.put(
+            "startexposure",
+            &[
+                ("Duration", exposure_secs.to_string()),
+                ("Light", "true".to_owned()),
+            ],
+        )?;
+        let deadline = Instant::now() + Duration::from_secs_f64(exposure_secs) + DOWNLOAD_TIMEOUT;
+        while !self.get("imageready")?.as_bool().unwrap_or(false) {
+            if Instant::now() > deadline {
+                return Err(anyhow!("Timed out waiting for the exposure"));
+            }
+            std::thread::sleep(IMAGE_READY_POLL_INTERVAL);
+        }
+
+        // The image array is indexed [x][y], or [x][y][band] for color cameras
+        let columns = self.get("imagearray")?;
+        let columns = columns
+            .as_array()
+            .ok_or_else(|| anyhow!("Camera sent no image"))?;
+        let width = columns.len();
+        let height = columns
+            .first()
+            .and_then(|c| c.as_array())
+            .map(|c| c.len())
+            .unwrap_or(0);
+        let bands = columns
+            .first()
+            .and_then(|c| c.get(0))
+            .and_then(|p| p.as_array())
+            .map(|p| p.len())
+            .unwrap_or(1);
+        if width == 0 || height == 0 {
+            return Err(anyhow!("Camera sent an empty image"));
+        }
+
+        let mut data = vec![0_u16; width * height * bands];
+        for (x, column) in columns.iter().enumerate() {
+            for y in 0..height {
+                let pixel = &column[y];
+                for b in 0..bands {
+                    let value = if bands == 1 { pixel } else { &pixel[b] };
+                    data[(y * width + x) * bands + b] = value.as_u64().unwrap_or(0) as u16;
+                }
+            }
+        }
+        Ok(CapturedFrame {
+            width,
+            height,
+            bands,
+            pixel_depth: self.pixel_depth,
+            data,
+            timestamp,
+        })
+    }
+}
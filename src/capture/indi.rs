@@ -0,0 +1,320 @@
+//! Minimal INDI client: enough of the XML protocol to connect a CCD device, set its gain and
+//! frame, and receive exposures as FITS BLOBs.
+
+use std::collections::HashSet;
+use std::io::{ErrorKind, Read, Write};
+use std::net::TcpStream;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Result};
+use base64::Engine;
+use chrono::Utc;
+
+use super::{Camera, CaptureSettings, CapturedFrame};
+use crate::fits;
+
+/// How long to wait for the server to respond to anything but an exposure
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Time allowed for an exposure to be read out and sent, beyond its length
+const DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(30);
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Value of `name` in the first tag of `element`
+fn attribute(element: &str, name: &str) -> Option<String> {
+    let tag = &element[..element.find('>').unwrap_or(element.len())];
+    let start = tag.find(&format!(" {}=\"", name))? + name.len() + 3;
+    let end = tag[start..].find('"')? + start;
+    Some(tag[start..end].to_owned())
+}
+
+/// Text of the member element, e.g. `oneNumber`, having the name `name`
+fn member_text(element: &str, member: &str, name: &str) -> Option<String> {
+    let open = format!("<{}", member);
+    let mut rest = element;
+    while let Some(start) = rest.find(&open) {
+        rest = &rest[start..];
+        let content_start = rest.find('>')? + 1;
+        let content_end = rest.find(&format!("</{}>", member))?;
+        if attribute(rest, "name").as_deref() == Some(name) {
+            return Some(rest[content_start..content_end].trim().to_owned());
+        }
+        rest = &rest[content_end..];
+    }
+    None
+}
+
+pub struct IndiCamera {
+    stream: TcpStream,
+    device: String,
+    /// Data received but not yet parsed into elements
+    buffer: Vec<u8>,
+    /// Properties the device has defined
+    properties: HashSet<String>,
+    /// Sensor size, from `CCD_INFO`
+    sensor_size: Option<(u32, u32)>,
+}
+
+impl IndiCamera {
+    /// Connects to the INDI server and the camera `device` on it. If `device` is empty, the
+    /// first device defining an exposure property is used.
+    pub fn connect(host: &str, port: u16, device: &str) -> Result<IndiCamera> {
+        let stream = TcpStream::connect((host, port)).map_err(|why| {
+            anyhow!(
+                "Failed to connect to INDI server {}:{}: {}",
+                host,
+                port,
+                why
+            )
+        })?;
+        stream.set_read_timeout(Some(Duration::from_millis(500)))?;
+        let mut camera = IndiCamera {
+            stream,
+            device: device.to_owned(),
+            buffer: vec![],
+            properties: HashSet::new(),
+            sensor_size: None,
+        };
+
+        if device.is_empty() {
+            camera.send("<getProperties version=\"1.7\"/>")?;
+        } else {
+            camera.send(&format!(
+                "<getProperties version=\"1.7\" device=\"{}\"/>",
+                escape(device)
+            ))?;
+        }
+        let exposure = camera
+            .wait_for(RESPONSE_TIMEOUT, |e| {
+                e.starts_with("<defNumberVector")
+                    && attribute(e, "name").as_deref() == Some("CCD_EXPOSURE")
+            })
+            .map_err(|_| match device.is_empty() {
+                true => anyhow!("No camera found on the INDI server"),
+                false => anyhow!("Camera '{}' not found on the INDI server", device),
+            })?;
+        camera.device = attribute(&exposure, "device").unwrap_or_default();
+        info!("Connecting to INDI camera {}", camera.device);
+
+        camera.send(&format!(
+            "<enableBLOB device=\"{}\">Also</enableBLOB>",
+            escape(&camera.device)
+        ))?;
+        camera.set_switch("CONNECTION", "CONNECT")?;
+        camera.wait_for_state("setSwitchVector", "CONNECTION", RESPONSE_TIMEOUT)?;
+        camera.set_switch("UPLOAD_MODE", "UPLOAD_CLIENT")?;
+        Ok(camera)
+    }
+
+    fn send(&mut self, xml: &str) -> Result<()> {
+        self.stream.write_all(xml.as_bytes())?;
+        self.stream.write_all(b"\n")?;
+        Ok(())
+    }
+
+    fn set_switch(&mut self, property: &str, switch: &str) -> Result<()> {
+        self.send(&format!(
+            "<newSwitchVector device=\"{}\" name=\"{}\"><oneSwitch name=\"{}\">On</oneSwitch></newSwitchVector>",
+            escape(&self.device),
+            property,
+            switch
+        ))
+    }
+
+    fn set_numbers(&mut self, property: &str, values: &[(&str, f64)]) -> Result<()> {
+        let members: String = values
+            .iter()
+            .map(|(name, value)| format!("<oneNumber name=\"{}\">{}</oneNumber>", name, value))
+            .collect();
+        self.send(&format!(
+            "<newNumberVector device=\"{}\" name=\"{}\">{}</newNumberVector>",
+            escape(&self.device),
+            property,
+            members
+        ))
+    }
+
+    /// Takes the next complete top-level element from the data received, if there is one
+    fn take_element(&mut self) -> Option<String> {
+        let start = self.buffer.iter().position(|b| *b == b'<')?;
+        let tag_end = self.buffer[start..].iter().position(|b| *b == b'>')? + start;
+        let end = if self.buffer[tag_end - 1] == b'/' {
+            tag_end + 1
+        } else {
+            let name_end = self.buffer[start + 1..tag_end]
+                .iter()
+                .position(|b| b.is_ascii_whitespace())
+                .map(|p| p + start + 1)
+                .unwrap_or(tag_end);
+            let closing = format!(
+                "</{}>",
+                String::from_utf8_lossy(&self.buffer[start + 1..name_end])
+            );
+            self.buffer[tag_end..]
+                .windows(closing.len())
+                .position(|w| w == closing.as_bytes())?
+                + tag_end
+                + closing.len()
+        };
+        let element = String::from_utf8_lossy(&self.buffer[start..end]).to_string();
+        self.buffer.drain(..end);
+        Some(element)
+    }
+
+    /// Reads the next element from the server, noting the properties it defines
+    fn next_element(&mut self, deadline: Instant) -> Result<String> {
+        let mut chunk = [0_u8; 65536];
+        loop {
+            if let Some(element) = self.take_element() {
+                if element.starts_with("<def") {
+                    if let Some(name) = attribute(&element, "name") {
+                        if name == "CCD_INFO" {
+                            let max_x = member_text(&element, "defNumber", "CCD_MAX_X");
+                            let max_y = member_text(&element, "defNumber", "CCD_MAX_Y");
+                            if let (Some(x), Some(y)) = (max_x, max_y) {
+                                self.sensor_size = x
+                                    .parse::<f64>()
+                                    .ok()
+                                    .zip(y.parse::<f64>().ok())
+                                    .map(|(x, y)| (x as u32, y as u32));
+                            }
+                        }
+                        self.properties.insert(name);
+                    }
+                }
+                return Ok(element);
+            }
+            if Instant::now() > deadline {
+                return Err(anyhow!("Timed out waiting for the INDI server"));
+            }
+            match self.stream.read(&mut chunk) {
+                Ok(0) => return Err(anyhow!("INDI server closed the connection")),
+                Ok(n) => self.buffer.extend_from_slice(&chunk[..n]),
+                Err(why) if matches!(why.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {}
+                Err(why) => return Err(why.into()),
+            }
+        }
+    }
+
+    fn wait_for<P: Fn(&str) -> bool>(&mut self, timeout: Duration, predicate: P) -> Result<String> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let element = self.next_element(deadline)?;
+            if predicate(&element) {
+                return Ok(element);
+            }
+        }
+    }
+
+    /// Waits for the device to report `property` as done, failing if it reports an alert
+    fn wait_for_state(&mut self, kind: &str, property: &str, timeout: Duration) -> Result<()> {
+        let element = self.wait_for(timeout, |e| {
+            e.starts_with(&format!("<{}", kind))
+                && attribute(e, "name").as_deref() == Some(property)
+                && matches!(attribute(e, "state").as_deref(), Some("Ok") | Some("Alert"))
+        })?;
+        if attribute(&element, "state").as_deref() == Some("Alert") {
+            Err(anyhow!(
+                "Camera reported an error setting {}: {}",
+                property,
+                attribute(&element, "message").unwrap_or_default()
+            ))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl Camera for IndiCamera {
+    fn name(&self) -> String {
+        self.device.clone()
+    }
+
+    fn configure(&mut self, settings: &CaptureSettings) -> Result<()> {
+        if self.properties.contains("CCD_GAIN") {
+            self.set_numbers("CCD_GAIN", &[("GAIN", settings.gain)])?;
+            self.wait_for_state("setNumberVector", "CCD_GAIN", RESPONSE_TIMEOUT)?;
+        } else if self.properties.contains("CCD_CONTROLS") {
+            self.set_numbers("CCD_CONTROLS", &[("Gain", settings.gain)])?;
+            self.wait_for_state("setNumberVector", "CCD_CONTROLS", RESPONSE_TIMEOUT)?;
+        } else {
+            warn!("Camera {} has no gain control", self.device);
+        }
+
+        let frame = if settings.use_roi {
+            Some((
+                settings.roi.x,
+                settings.roi.y,
+                settings.roi.width,
+                settings.roi.height,
+            ))
+        } else {
+            self.sensor_size.map(|(w, h)| (0, 0, w, h))
+        };
+        if let Some((x, y, width, height)) = frame {
+            self.set_numbers(
+                "CCD_FRAME",
+                &[
+                    ("X", x as f64),
+                    ("Y", y as f64),
+                    ("WIDTH", width as f64),
+                    ("HEIGHT", height as f64),
+                ],
+            )?;
+            self.wait_for_state("setNumberVector", "CCD_FRAME", RESPONSE_TIMEOUT)?;
+        }
+        Ok(())
+    }
+
+    fn expose(&mut self, exposure_secs: f64) -> Result<CapturedFrame> {
+        let timestamp = Utc::now();
+        self.set_numbers("CCD_EXPOSURE", &[("CCD_EXPOSURE_VALUE", exposure_secs)])?;
+        let deadline = Instant::now() + Duration::from_secs_f64(exposure_secs) + DOWNLOAD_TIMEOUT;
+        loop {
+            let element = self.next_element(deadline)?;
+            let name = attribute(&element, "name");
+            if element.starts_with("<setNumberVector")
+                && name.as_deref() == Some("CCD_EXPOSURE")
+                && attribute(&element, "state").as_deref() == Some("Alert")
+            {
+                return Err(anyhow!(
+                    "Camera reported an error during the exposure: {}",
+                    attribute(&element, "message").unwrap_or_default()
+                ));
+            }
+            if !element.starts_with("<setBLOBVector") || name.as_deref() != Some("CCD1") {
+                continue;
+            }
+
+            let blob_start = element
+                .find("<oneBLOB")
+                .ok_or_else(|| anyhow!("Exposure was sent without an image"))?;
+            let blob = &element[blob_start..];
+            let format = attribute(blob, "format").unwrap_or_default();
+            if format != ".fits" {
+                return Err(anyhow!(
+                    "Camera sent images as '{}', only uncompressed FITS is supported",
+                    format
+                ));
+            }
+            let content_start = blob.find('>').unwrap_or(0) + 1;
+            let content_end = blob.find("</oneBLOB>").unwrap_or(blob.len());
+            let encoded: String = blob[content_start..content_end]
+                .chars()
+                .filter(|c| !c.is_ascii_whitespace())
+                .collect();
+            let bytes = base64::engine::general_purpose::STANDARD.decode(encoded)?;
+            let image = fits::parse_fits(&bytes)?;
+
+            let mut frame = CapturedFrame::from_image(&image, 16);
+            frame.timestamp = timestamp;
+            return Ok(frame);
+        }
+    }
+}
@@ -0,0 +1,260 @@
+//! Captures SER files directly from a camera, through an INDI server or an ASCOM Alpaca device,
+//! so they can be watched live and queued for processing as soon as they're complete. Built with
+//! the `capture` feature.
+
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Local, Utc};
+use itertools::iproduct;
+use sciimg::prelude::Image;
+use serde::{Deserialize, Serialize};
+
+mod alpaca;
+mod indi;
+mod serwriter;
+pub mod view;
+
+use serwriter::SerWriter;
+
+#[derive(Debug, Eq, PartialEq, Deserialize, Serialize, Clone, Copy, Default)]
+pub enum CameraProtocol {
+    #[default]
+    Indi,
+    /// ASCOM Alpaca. Classic ASCOM drivers on Windows can be reached through ASCOM Remote.
+    Alpaca,
+}
+
+impl CameraProtocol {
+    pub fn default_port(&self) -> u16 {
+        match self {
+            CameraProtocol::Indi => 7624,
+            CameraProtocol::Alpaca => 11111,
+        }
+    }
+}
+
+/// Sensor region to read out, in unbinned pixels
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+pub struct Roi {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+#[serde(default)]
+pub struct CaptureSettings {
+    pub protocol: CameraProtocol,
+    pub host: String,
+    pub port: u16,
+    /// Name of the camera device on the INDI server
+    pub indi_device: String,
+    /// Number of the camera device on the Alpaca server
+    pub alpaca_device: u32,
+    pub exposure_ms: f64,
+    pub gain: f64,
+    pub use_roi: bool,
+    pub roi: Roi,
+    pub frame_count: usize,
+    pub output_dir: Option<String>,
+    /// Start of the captured file's name, which is followed by the capture's start time
+    pub file_prefix: String,
+    /// Open the file as the light input while it's captured, watching it live
+    pub open_as_light: bool,
+    /// Queue the file for processing with the current options once it's captured
+    pub queue_when_done: bool,
+}
+
+impl Default for CaptureSettings {
+    fn default() -> Self {
+        CaptureSettings {
+            protocol: CameraProtocol::Indi,
+            host: "localhost".to_owned(),
+            port: CameraProtocol::Indi.default_port(),
+            indi_device: String::new(),
+            alpaca_device: 0,
+            exposure_ms: 5.0,
+            gain: 0.0,
+            use_roi: false,
+            roi: Roi {
+                x: 0,
+                y: 0,
+                width: 640,
+                height: 480,
+            },
+            frame_count: 1000,
+            output_dir: None,
+            file_prefix: "capture".to_owned(),
+            open_as_light: true,
+            queue_when_done: false,
+        }
+    }
+}
+
+/// A frame read from the camera. Color frames have their bands interleaved per pixel, as in SER.
+pub struct CapturedFrame {
+    pub width: usize,
+    pub height: usize,
+    pub bands: usize,
+    pub pixel_depth: usize,
+    pub data: Vec<u16>,
+    pub timestamp: DateTime<Utc>,
+}
+
+impl CapturedFrame {
+    pub fn from_image(image: &Image, pixel_depth: usize) -> CapturedFrame {
+        let bands = image.num_bands();
+        let mut data = vec![0_u16; image.width * image.height * bands];
+        iproduct!(0..image.height, 0..image.width, 0..bands).for_each(|(y, x, b)| {
+            data[(y * image.width + x) * bands + b] = image.get_band(b).get(x, y) as u16;
+        });
+        CapturedFrame {
+            width: image.width,
+            height: image.height,
+            bands,
+            pixel_depth,
+            data,
+            timestamp: Utc::now(),
+        }
+    }
+}
+
+pub trait Camera {
+    /// Name written to the SER header's instrument field
+    fn name(&self) -> String;
+
+    /// Applies the gain and region of interest
+    fn configure(&mut self, settings: &CaptureSettings) -> Result<()>;
+
+    fn expose(&mut self, exposure_secs: f64) -> Result<CapturedFrame>;
+}
+
+pub fn connect(settings: &CaptureSettings) -> Result<Box<dyn Camera>> {
+    Ok(match settings.protocol {
+        CameraProtocol::Indi => Box::new(indi::IndiCamera::connect(
+            &settings.host,
+            settings.port,
+            &settings.indi_device,
+        )?),
+        CameraProtocol::Alpaca => Box::new(alpaca::AlpacaCamera::connect(
+            &settings.host,
+            settings.port,
+            settings.alpaca_device,
+        )?),
+    })
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum CaptureStatus {
+    #[default]
+    Idle,
+    Connecting,
+    Capturing {
+        path: PathBuf,
+        frames: usize,
+        total: usize,
+    },
+    Finished {
+        path: PathBuf,
+        frames: usize,
+    },
+    Failed(String),
+}
+
+lazy_static! {
+    static ref CAPTURE_STATUS: Arc<Mutex<CaptureStatus>> =
+        Arc::new(Mutex::new(CaptureStatus::Idle));
+}
+
+static STOP_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+pub fn status() -> CaptureStatus {
+    CAPTURE_STATUS.lock().unwrap().clone()
+}
+
+fn set_status(status: CaptureStatus) {
+    *CAPTURE_STATUS.lock().unwrap() = status;
+}
+
+pub fn is_capturing() -> bool {
+    matches!(
+        status(),
+        CaptureStatus::Connecting | CaptureStatus::Capturing { .. }
+    )
+}
+
+/// Ends the capture after the frame being exposed. The frames captured so far are kept.
+pub fn stop_capture() {
+    STOP_REQUESTED.store(true, Ordering::Relaxed);
+}
+
+fn capture_path(settings: &CaptureSettings) -> Result<PathBuf> {
+    let output_dir = settings
+        .output_dir
+        .as_ref()
+        .ok_or_else(|| anyhow!("No capture output folder set"))?;
+    Ok(PathBuf::from(output_dir).join(format!(
+        "{}_{}.ser",
+        settings.file_prefix,
+        Local::now().format("%Y%m%d_%H%M%S")
+    )))
+}
+
+fn run_capture(settings: &CaptureSettings, path: &PathBuf) -> Result<usize> {
+    let mut camera = connect(settings)?;
+    camera.configure(settings)?;
+    info!("Capturing {} frames to {:?}", settings.frame_count, path);
+
+    let exposure_secs = settings.exposure_ms / 1000.0;
+    let mut writer: Option<SerWriter> = None;
+    while writer.as_ref().map(|w| w.frame_count()).unwrap_or(0) < settings.frame_count
+        && !STOP_REQUESTED.load(Ordering::Relaxed)
+    {
+        let frame = camera.expose(exposure_secs)?;
+        let writer = match &mut writer {
+            Some(writer) => writer,
+            None => writer.insert(SerWriter::create(path, &frame, &camera.name())?),
+        };
+        writer.write_frame(&frame)?;
+        set_status(CaptureStatus::Capturing {
+            path: path.to_owned(),
+            frames: writer.frame_count(),
+            total: settings.frame_count,
+        });
+    }
+
+    match writer {
+        Some(writer) => {
+            let frames = writer.frame_count();
+            writer.finish()?;
+            Ok(frames)
+        }
+        None => Err(anyhow!("Capture stopped before any frames were captured")),
+    }
+}
+
+/// Starts capturing on a background thread. Progress is reported through `status`.
+pub fn start_capture(settings: &CaptureSettings) -> Result<()> {
+    if is_capturing() {
+        return Err(anyhow!("A capture is already running"));
+    }
+    let path = capture_path(settings)?;
+    let settings = settings.clone();
+    STOP_REQUESTED.store(false, Ordering::Relaxed);
+    set_status(CaptureStatus::Connecting);
+    std::thread::spawn(move || match run_capture(&settings, &path) {
+        Ok(frames) => {
+            info!("Captured {} frames to {:?}", frames, path);
+            set_status(CaptureStatus::Finished { path, frames });
+        }
+        Err(why) => {
+            error!("Capture failed: {}", why);
+            set_status(CaptureStatus::Failed(why.to_string()));
+        }
+    });
+    Ok(())
+}
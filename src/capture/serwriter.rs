@@ -0,0 +1,110 @@
+//! Writes captured frames straight to a SER file, in the layout read back by the processing
+//! pipeline.
+
+use std::fs::File;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use anyhow::{anyhow, Result};
+
+use super::CapturedFrame;
+
+use crate::process::framesource::ser_ticks;
+use crate::process::serheader::{SerHeader, COLOR_ID_RGB};
+
+/// Writes frames to a SER file as they're captured. The header's frame count is kept up to date
+/// after each frame so the file can be read while it's still being written.
+pub struct SerWriter {
+    writer: BufWriter<File>,
+    header: SerHeader,
+    bands: usize,
+    timestamps: Vec<u64>,
+}
+
+impl SerWriter {
+    /// Creates the SER file for frames the size and depth of `first_frame`
+    pub fn create(path: &Path, first_frame: &CapturedFrame, instrument: &str) -> Result<SerWriter> {
+        let start = ser_ticks(&first_frame.timestamp);
+        let header = SerHeader {
+            file_id: "LUCAM-RECORDER".to_owned(),
+            lu_id: 0,
+            color_id: if first_frame.bands == 3 {
+                COLOR_ID_RGB
+            } else {
+                0
+            },
+            // Written as most capture software reads it, 0 for little endian
+            little_endian: 0,
+            width: first_frame.width,
+            height: first_frame.height,
+            pixel_depth: first_frame.pixel_depth,
+            frame_count: 0,
+            observer: String::new(),
+            instrument: instrument.to_owned(),
+            telescope: String::new(),
+            date_time: start,
+            date_time_utc: start,
+            file_size: 0,
+        };
+        let mut writer = BufWriter::new(File::create(path)?);
+        writer.write_all(&header.to_bytes())?;
+
+        Ok(SerWriter {
+            writer,
+            header,
+            bands: first_frame.bands,
+            timestamps: vec![],
+        })
+    }
+
+    pub fn frame_count(&self) -> usize {
+        self.timestamps.len()
+    }
+
+    pub fn write_frame(&mut self, frame: &CapturedFrame) -> Result<()> {
+        if (frame.width, frame.height, frame.bands)
+            != (self.header.width, self.header.height, self.bands)
+        {
+            return Err(anyhow!(
+                "Frame size changed during capture from {}x{} to {}x{}",
+                self.header.width,
+                self.header.height,
+                frame.width,
+                frame.height
+            ));
+        }
+        if self.header.pixel_depth > 8 {
+            for value in frame.data.iter() {
+                self.writer.write_all(&value.to_le_bytes())?;
+            }
+        } else {
+            let bytes: Vec<u8> = frame.data.iter().map(|v| *v as u8).collect();
+            self.writer.write_all(&bytes)?;
+        }
+        self.timestamps.push(ser_ticks(&frame.timestamp));
+        self.header.frame_count = self.timestamps.len();
+        self.rewrite_header()
+    }
+
+    /// Writes the header over the one at the start of the file, leaving the position at the end
+    fn rewrite_header(&mut self) -> Result<()> {
+        let end = self.writer.stream_position()?;
+        self.writer.seek(SeekFrom::Start(0))?;
+        self.writer.write_all(&self.header.to_bytes())?;
+        self.writer.seek(SeekFrom::Start(end))?;
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    /// Writes the trailer of frame timestamps and closes the file
+    pub fn finish(mut self) -> Result<()> {
+        for timestamp in self.timestamps.iter() {
+            self.writer.write_all(&timestamp.to_le_bytes())?;
+        }
+        if let Some(first) = self.timestamps.first() {
+            self.header.date_time = *first;
+            self.header.date_time_utc = *first;
+        }
+        self.rewrite_header()
+    }
+}
@@ -0,0 +1,222 @@
+use std::path::PathBuf;
+
+use egui::Context;
+
+use super::{CameraProtocol, CaptureSettings, CaptureStatus};
+
+/// Changes in the capture's progress the rest of the application acts on
+pub enum CaptureEvent {
+    /// The first frame has been written to the file
+    FirstFrame(PathBuf),
+    Finished(PathBuf),
+}
+
+#[derive(Default)]
+pub struct CaptureWindow {
+    pub open: bool,
+    last_status: CaptureStatus,
+    error: Option<String>,
+}
+
+impl CaptureWindow {
+    /// Reports how the capture has progressed since the last poll
+    pub fn poll(&mut self, ctx: &Context) -> Option<CaptureEvent> {
+        let status = super::status();
+        if super::is_capturing() {
+            ctx.request_repaint_after(std::time::Duration::from_millis(250));
+        }
+        if status == self.last_status {
+            return None;
+        }
+        let event = match (&self.last_status, &status) {
+            (CaptureStatus::Capturing { .. }, CaptureStatus::Capturing { .. }) => None,
+            (_, CaptureStatus::Capturing { path, .. }) => {
+                Some(CaptureEvent::FirstFrame(path.to_owned()))
+            }
+            (_, CaptureStatus::Finished { path, .. }) => {
+                Some(CaptureEvent::Finished(path.to_owned()))
+            }
+            _ => None,
+        };
+        self.last_status = status;
+        event
+    }
+
+    pub fn ui(&mut self, ctx: &Context, settings: &mut CaptureSettings, default_folder: PathBuf) {
+        let mut open = self.open;
+        let capturing = super::is_capturing();
+        egui::Window::new(t!("capture.title"))
+            .open(&mut open)
+            .resizable(false)
+            .show(ctx, |ui| {
+                ui.add_enabled_ui(!capturing, |ui| {
+                    egui::Grid::new("capture_settings")
+                        .num_columns(2)
+                        .spacing([40.0, 4.0])
+                        .striped(true)
+                        .show(ui, |ui| {
+                            Self::settings_grid_contents(ui, settings, default_folder)
+                        });
+                });
+                ui.separator();
+
+                ui.horizontal(|ui| {
+                    if capturing {
+                        if ui.button(t!("capture.stop")).clicked() {
+                            super::stop_capture();
+                        }
+                    } else if ui
+                        .add_enabled(
+                            settings.output_dir.is_some(),
+                            egui::Button::new(t!("capture.start")),
+                        )
+                        .clicked()
+                    {
+                        self.error = super::start_capture(settings).err().map(|e| e.to_string());
+                    }
+                    match &self.last_status {
+                        CaptureStatus::Idle => {}
+                        CaptureStatus::Connecting => {
+                            ui.spinner();
+                            ui.label(t!("capture.connecting"));
+                        }
+                        CaptureStatus::Capturing { frames, total, .. } => {
+                            ui.add(
+                                egui::ProgressBar::new(*frames as f32 / *total as f32).text(t!(
+                                    "capture.progress",
+                                    frames = frames,
+                                    total = total
+                                )),
+                            );
+                        }
+                        CaptureStatus::Finished { path, frames } => {
+                            ui.label(t!(
+                                "capture.finished",
+                                frames = frames,
+                                path = path.display()
+                            ));
+                        }
+                        CaptureStatus::Failed(why) => {
+                            ui.colored_label(ui.visuals().error_fg_color, why);
+                        }
+                    }
+                });
+                if let Some(error) = &self.error {
+                    ui.colored_label(ui.visuals().error_fg_color, error);
+                }
+            });
+        self.open = open;
+    }
+
+    fn settings_grid_contents(
+        ui: &mut egui::Ui,
+        settings: &mut CaptureSettings,
+        default_folder: PathBuf,
+    ) {
+        ui.label(t!("capture.protocol"));
+        ui.horizontal(|ui| {
+            let previous = settings.protocol;
+            ui.radio_value(&mut settings.protocol, CameraProtocol::Indi, "INDI");
+            ui.radio_value(
+                &mut settings.protocol,
+                CameraProtocol::Alpaca,
+                "ASCOM Alpaca",
+            )
+            .on_hover_text(t!("capture.alpaca_hint"));
+            if settings.protocol != previous && settings.port == previous.default_port() {
+                settings.port = settings.protocol.default_port();
+            }
+        });
+        ui.end_row();
+
+        ui.label(t!("capture.server"));
+        ui.horizontal(|ui| {
+            ui.text_edit_singleline(&mut settings.host);
+            ui.label(":");
+            ui.add(egui::DragValue::new(&mut settings.port));
+        });
+        ui.end_row();
+
+        ui.label(t!("capture.device"));
+        match settings.protocol {
+            CameraProtocol::Indi => {
+                ui.text_edit_singleline(&mut settings.indi_device)
+                    .on_hover_text(t!("capture.indi_device_hint"));
+            }
+            CameraProtocol::Alpaca => {
+                ui.add(egui::DragValue::new(&mut settings.alpaca_device));
+            }
+        }
+        ui.end_row();
+
+        ui.label(t!("capture.exposure"));
+        ui.add(
+            egui::DragValue::new(&mut settings.exposure_ms)
+                .clamp_range(0.01..=60000.0)
+                .speed(0.1)
+                .suffix(" ms"),
+        );
+        ui.end_row();
+
+        ui.label(t!("capture.gain"));
+        ui.add(egui::DragValue::new(&mut settings.gain).clamp_range(0.0..=10000.0));
+        ui.end_row();
+
+        ui.label(t!("capture.roi"));
+        ui.vertical(|ui| {
+            ui.checkbox(&mut settings.use_roi, t!("capture.use_roi"));
+            ui.add_enabled_ui(settings.use_roi, |ui| {
+                ui.horizontal(|ui| {
+                    ui.label("X");
+                    ui.add(egui::DragValue::new(&mut settings.roi.x));
+                    ui.label("Y");
+                    ui.add(egui::DragValue::new(&mut settings.roi.y));
+                    ui.label(t!("capture.roi_size"));
+                    ui.add(egui::DragValue::new(&mut settings.roi.width).clamp_range(1..=65535));
+                    ui.label("×");
+                    ui.add(egui::DragValue::new(&mut settings.roi.height).clamp_range(1..=65535));
+                });
+            });
+        });
+        ui.end_row();
+
+        ui.label(t!("capture.frame_count"));
+        ui.add(egui::DragValue::new(&mut settings.frame_count).clamp_range(1..=1000000));
+        ui.end_row();
+
+        ui.label(t!("capture.output_folder"));
+        ui.horizontal(|ui| {
+            if ui.button(t!("capture.choose_folder")).clicked() {
+                if let Some(folder) = rfd::FileDialog::new()
+                    .set_title(t!("capture.output_folder"))
+                    .set_directory(
+                        settings
+                            .output_dir
+                            .as_ref()
+                            .map(PathBuf::from)
+                            .unwrap_or(default_folder),
+                    )
+                    .pick_folder()
+                {
+                    settings.output_dir = Some(folder.display().to_string());
+                }
+            }
+            ui.monospace(settings.output_dir.clone().unwrap_or_default());
+        });
+        ui.end_row();
+
+        ui.label(t!("capture.file_prefix"));
+        ui.text_edit_singleline(&mut settings.file_prefix);
+        ui.end_row();
+
+        ui.label(t!("capture.open_as_light"));
+        ui.checkbox(&mut settings.open_as_light, "")
+            .on_hover_text(t!("capture.open_as_light_hint"));
+        ui.end_row();
+
+        ui.label(t!("capture.queue_when_done"));
+        ui.checkbox(&mut settings.queue_when_done, "")
+            .on_hover_text(t!("capture.queue_when_done_hint"));
+        ui.end_row();
+    }
+}
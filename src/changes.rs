@@ -0,0 +1,53 @@
+//! Options changed since the last completed run. Rows of the options panel whose values differ
+//! from those the displayed result was made with are marked, and the result pane says when the
+//! result no longer matches the options.
+
+use crate::optionfilter::SECTIONS;
+use crate::state::ApplicationState;
+
+/// True if the input files, or how the calibration frames and the target are treated, differ
+/// between `last` and `current`
+fn inputs_changed(last: &ApplicationState, current: &ApplicationState) -> bool {
+    last.light != current.light
+        || last.prominence_light != current.prominence_light
+        || last.composite_blend_radius != current.composite_blend_radius
+        || last.dark != current.dark
+        || last.flat != current.flat
+        || last.darkflat != current.darkflat
+        || last.bias != current.bias
+        || last.additional_darks != current.additional_darks
+        || last.additional_flats != current.additional_flats
+        || last.additional_darkflats != current.additional_darkflats
+        || last.additional_biases != current.additional_biases
+        || last.dark_method != current.dark_method
+        || last.flat_method != current.flat_method
+        || last.darkflat_method != current.darkflat_method
+        || last.bias_method != current.bias_method
+        || last.bayer != current.bayer
+        || last.hot_pixel_map != current.hot_pixel_map
+        || last.bad_lines != current.bad_lines
+        || last.bad_line_correction != current.bad_line_correction
+        || last.bad_line_threshold != current.bad_line_threshold
+        || last.target != current.target
+        || last.planetary != current.planetary
+        || last.rotation_model != current.rotation_model
+        || last.rotation_rate != current.rotation_rate
+        || last.rotation_per_frame != current.rotation_per_frame
+        || last.obs_latitude != current.obs_latitude
+        || last.obs_longitude != current.obs_longitude
+}
+
+/// Translation keys of the labels of the option rows changed since the run made with `last`
+pub fn changed_options(last: &ApplicationState, current: &ApplicationState) -> Vec<&'static str> {
+    SECTIONS
+        .iter()
+        .flat_map(|section| section.options.iter())
+        .filter(|row| (row.changed)(last, current))
+        .map(|row| row.key)
+        .collect()
+}
+
+/// True if a run with `current` would be made differently from the run made with `last`
+pub fn settings_differ(last: &ApplicationState, current: &ApplicationState) -> bool {
+    inputs_changed(last, current) || !changed_options(last, current).is_empty()
+}
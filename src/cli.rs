@@ -0,0 +1,128 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Result};
+
+use crate::state::{ApplicationState, PROJECT_EXTENSION};
+
+const USAGE: &str = "Usage: solhat-egui [OPTIONS] [LIGHT.ser | PROJECT.shproj]
+
+Options:
+  --light <FILE>          Light SER file
+  --prominences <FILE>    Light SER file exposed for prominences, composited outside the limb
+  --dark <FILE>           Dark SER file
+  --flat <FILE>           Flat SER file
+  --darkflat <FILE>       Dark flat SER file
+  --bias <FILE>           Bias SER file
+  --hot-pixel-map <FILE>  Hot pixel map TOML file
+  --output-dir <DIR>      Output folder
+  --project <FILE>        Project file to open before applying the other options
+  --autorun               Start processing as soon as the window opens
+  --safe-mode             Start with default settings and window geometry, without graphics
+                          acceleration or preview loading, leaving the saved settings untouched
+  -h, --help              Print this help";
+
+/// Inputs and actions given on the command line, applied over the restored application state
+#[derive(Debug, Default)]
+pub struct CommandLineArgs {
+    pub light: Option<String>,
+    pub prominence_light: Option<String>,
+    pub dark: Option<String>,
+    pub flat: Option<String>,
+    pub darkflat: Option<String>,
+    pub bias: Option<String>,
+    pub hot_pixel_map: Option<String>,
+    pub output_dir: Option<String>,
+    pub project: Option<PathBuf>,
+    pub autorun: bool,
+    /// Ignore the saved settings and start with everything that could fail to load turned off
+    pub safe_mode: bool,
+    pub help: bool,
+}
+
+/// Expands a relative path against the working directory, as the launching process's working
+/// directory means nothing once stored in the application state.
+fn absolute_path(path: &str) -> String {
+    std::fs::canonicalize(path)
+        .map(|p| p.display().to_string())
+        .unwrap_or_else(|_| path.to_owned())
+}
+
+impl CommandLineArgs {
+    /// Parses the program arguments, excluding the program name
+    pub fn parse<I>(args: I) -> Result<Self>
+    where
+        I: IntoIterator<Item = String>,
+    {
+        let mut parsed = CommandLineArgs::default();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let mut value = |name: &str| {
+                args.next()
+                    .map(|v| absolute_path(&v))
+                    .ok_or_else(|| anyhow!("Missing value for {}", name))
+            };
+            match arg.as_str() {
+                "--light" => parsed.light = Some(value(&arg)?),
+                "--prominences" => parsed.prominence_light = Some(value(&arg)?),
+                "--dark" => parsed.dark = Some(value(&arg)?),
+                "--flat" => parsed.flat = Some(value(&arg)?),
+                "--darkflat" => parsed.darkflat = Some(value(&arg)?),
+                "--bias" => parsed.bias = Some(value(&arg)?),
+                "--hot-pixel-map" => parsed.hot_pixel_map = Some(value(&arg)?),
+                "--output-dir" => parsed.output_dir = Some(value(&arg)?),
+                "--project" => parsed.project = Some(PathBuf::from(value(&arg)?)),
+                "--autorun" => parsed.autorun = true,
+                "--safe-mode" => parsed.safe_mode = true,
+                "-h" | "--help" => parsed.help = true,
+                // Process serial number passed by macOS when launched from the Finder
+                _ if arg.starts_with("-psn_") => {}
+                _ if arg.starts_with('-') => warn!("Ignoring unknown option: {}", arg),
+                _ => {
+                    let is_project = Path::new(&arg)
+                        .extension()
+                        .map(|e| e == PROJECT_EXTENSION)
+                        .unwrap_or(false);
+                    if is_project {
+                        parsed.project = Some(PathBuf::from(absolute_path(&arg)));
+                    } else {
+                        parsed.light = Some(absolute_path(&arg));
+                    }
+                }
+            }
+        }
+        Ok(parsed)
+    }
+
+    pub fn usage() -> &'static str {
+        USAGE
+    }
+
+    /// Opens the project, if any, then replaces the inputs of `state` with those given
+    pub fn apply_to(&self, state: &mut ApplicationState) -> Result<()> {
+        if let Some(project) = &self.project {
+            *state = state.load_project(project)?;
+            state.window.add_recent_project(project);
+        }
+
+        macro_rules! apply_input {
+            ($property:ident) => {
+                if let Some(path) = &self.$property {
+                    state.$property = Some(path.to_owned());
+                }
+            };
+        }
+        apply_input!(light);
+        apply_input!(prominence_light);
+        apply_input!(dark);
+        apply_input!(flat);
+        apply_input!(darkflat);
+        apply_input!(bias);
+        apply_input!(hot_pixel_map);
+        apply_input!(output_dir);
+
+        if let Some(light) = &self.light {
+            state.window.add_recent_file(light);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,335 @@
+//! Tags exported images with an ICC color profile so color-managed editors and browsers show them
+//! as intended. The profiles are built here rather than shipped. TIFFs are written with the
+//! profile by `tiffwriter`, other formats have it embedded into the files the image encoder
+//! writes, which has no way to include one itself.
+
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+
+use anyhow::{anyhow, Result};
+use flate2::write::ZlibEncoder;
+use flate2::{Compression, Crc};
+use itertools::iproduct;
+use sciimg::prelude::{Image, ImageMode};
+use serde::{Deserialize, Serialize};
+
+use crate::tiffwriter::{self, TiffOptions};
+
+/// Color profile exported images are tagged with
+#[derive(Debug, Eq, PartialEq, Deserialize, Serialize, Clone, Copy, Default)]
+pub enum ColorProfile {
+    /// Untagged, with the linear stacked data written as is
+    #[default]
+    None,
+    /// sRGB, with the stacked data encoded with the sRGB transfer curve
+    Srgb,
+    /// sRGB primaries with a linear transfer curve, with the stacked data written as is
+    Linear,
+}
+
+impl ColorProfile {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            ColorProfile::None => "None",
+            ColorProfile::Srgb => "sRGB",
+            ColorProfile::Linear => "Linear",
+        }
+    }
+
+    fn description(&self) -> &'static str {
+        match *self {
+            ColorProfile::None => "",
+            ColorProfile::Srgb => "sRGB",
+            ColorProfile::Linear => "Linear sRGB",
+        }
+    }
+}
+
+/// D50 illuminant, the profile connection space white point
+const D50: [f64; 3] = [0.9642, 1.0, 0.8249];
+
+/// sRGB primaries adapted to D50
+const SRGB_PRIMARIES: [[f64; 3]; 3] = [
+    [0.4361, 0.2225, 0.0139],
+    [0.3851, 0.7169, 0.0971],
+    [0.1431, 0.0606, 0.7141],
+];
+
+/// Entries in the sampled sRGB tone curve
+const SRGB_CURVE_ENTRIES: usize = 1024;
+
+/// Most profile bytes in one JPEG `APP2` segment, after its length, identifier and sequence
+/// numbers
+const JPEG_ICC_CHUNK_SIZE: usize = 65535 - 2 - 12 - 2;
+
+/// Encodes linear `value`, from 0 to 1, with the sRGB transfer curve
+fn srgb_encode(value: f64) -> f64 {
+    if value <= 0.0031308 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// Decodes sRGB encoded `value`, from 0 to 1, to linear
+fn srgb_decode(value: f64) -> f64 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Converts the linear 16-bit data of `image` to the encoding of `profile`
+pub fn encode(image: &Image, profile: ColorProfile) -> Image {
+    let mut encoded = image.clone();
+    if profile == ColorProfile::Srgb {
+        iproduct!(0..image.num_bands(), 0..image.height, 0..image.width).for_each(|(b, y, x)| {
+            let value = (image.get_band(b).get(x, y) as f64 / 65535.0).clamp(0.0, 1.0);
+            encoded.put(x, y, (srgb_encode(value) * 65535.0) as f32, b);
+        });
+    }
+    encoded
+}
+
+fn s15_fixed16(value: f64) -> [u8; 4] {
+    ((value * 65536.0).round() as i32).to_be_bytes()
+}
+
+fn xyz_tag(xyz: &[f64; 3]) -> Vec<u8> {
+    let mut tag = b"XYZ \0\0\0\0".to_vec();
+    xyz.iter()
+        .for_each(|v| tag.extend_from_slice(&s15_fixed16(*v)));
+    tag
+}
+
+fn curve_tag(profile: ColorProfile) -> Vec<u8> {
+    let mut tag = b"curv\0\0\0\0".to_vec();
+    if profile == ColorProfile::Srgb {
+        tag.extend_from_slice(&(SRGB_CURVE_ENTRIES as u32).to_be_bytes());
+        (0..SRGB_CURVE_ENTRIES).for_each(|i| {
+            let value = srgb_decode(i as f64 / (SRGB_CURVE_ENTRIES - 1) as f64);
+            tag.extend_from_slice(&((value * 65535.0).round() as u16).to_be_bytes());
+        });
+    } else {
+        // No entries is the identity curve
+        tag.extend_from_slice(&0_u32.to_be_bytes());
+    }
+    tag
+}
+
+fn description_tag(text: &str) -> Vec<u8> {
+    let mut tag = b"desc\0\0\0\0".to_vec();
+    tag.extend_from_slice(&(text.len() as u32 + 1).to_be_bytes());
+    tag.extend_from_slice(text.as_bytes());
+    tag.push(0);
+    // Empty Unicode and ScriptCode descriptions
+    tag.extend_from_slice(&[0; 4 + 4 + 2 + 1 + 67]);
+    tag
+}
+
+fn text_tag(text: &str) -> Vec<u8> {
+    let mut tag = b"text\0\0\0\0".to_vec();
+    tag.extend_from_slice(text.as_bytes());
+    tag.push(0);
+    tag
+}
+
+/// Builds a version 2 display profile for `profile`, for RGB or grayscale images
+pub fn icc_profile(profile: ColorProfile, gray: bool) -> Vec<u8> {
+    let curve = curve_tag(profile);
+    let mut tags: Vec<(&[u8; 4], Vec<u8>)> = vec![
+        (b"desc", description_tag(profile.description())),
+        (b"cprt", text_tag("No copyright, use freely")),
+        (b"wtpt", xyz_tag(&D50)),
+    ];
+    if gray {
+        tags.push((b"kTRC", curve));
+    } else {
+        tags.push((b"rXYZ", xyz_tag(&SRGB_PRIMARIES[0])));
+        tags.push((b"gXYZ", xyz_tag(&SRGB_PRIMARIES[1])));
+        tags.push((b"bXYZ", xyz_tag(&SRGB_PRIMARIES[2])));
+        tags.push((b"rTRC", curve.clone()));
+        tags.push((b"gTRC", curve.clone()));
+        tags.push((b"bTRC", curve));
+    }
+
+    // Tag data follows the header and tag table, each tag aligned to four bytes
+    let mut table = (tags.len() as u32).to_be_bytes().to_vec();
+    let mut data = vec![];
+    let data_start = 128 + 4 + tags.len() * 12;
+    for (signature, tag) in tags.iter() {
+        table.extend_from_slice(*signature);
+        table.extend_from_slice(&((data_start + data.len()) as u32).to_be_bytes());
+        table.extend_from_slice(&(tag.len() as u32).to_be_bytes());
+        data.extend_from_slice(tag);
+        data.resize(data.len().div_ceil(4) * 4, 0);
+    }
+
+    let size = data_start + data.len();
+    let mut header = vec![0_u8; 128];
+    header[0..4].copy_from_slice(&(size as u32).to_be_bytes());
+    header[8..12].copy_from_slice(&[2, 0x10, 0, 0]);
+    header[12..16].copy_from_slice(b"mntr");
+    header[16..20].copy_from_slice(if gray { b"GRAY" } else { b"RGB " });
+    header[20..24].copy_from_slice(b"XYZ ");
+    for (i, value) in [2024_u16, 1, 1, 0, 0, 0].iter().enumerate() {
+        header[24 + i * 2..26 + i * 2].copy_from_slice(&value.to_be_bytes());
+    }
+    header[36..40].copy_from_slice(b"acsp");
+    for (i, value) in D50.iter().enumerate() {
+        header[68 + i * 4..72 + i * 4].copy_from_slice(&s15_fixed16(*value));
+    }
+
+    [header, table, data].concat()
+}
+
+/// Compresses `bytes` into a zlib stream, as `iCCP` chunks hold their profile
+fn zlib_compress(bytes: &[u8]) -> Result<Vec<u8>> {
+    let mut encoder = ZlibEncoder::new(vec![], Compression::default());
+    encoder.write_all(bytes)?;
+    Ok(encoder.finish()?)
+}
+
+/// Adds an `iCCP` chunk after the header of a PNG, replacing any color space chunks
+fn embed_png(bytes: &[u8], profile: ColorProfile) -> Result<Vec<u8>> {
+    if bytes.len() < 33 || &bytes[12..16] != b"IHDR" {
+        return Err(anyhow!("Not a PNG file"));
+    }
+    // Gray and gray with alpha color types
+    let gray = matches!(bytes[25], 0 | 4);
+
+    let mut chunk_data = profile.description().as_bytes().to_vec();
+    chunk_data.extend_from_slice(&[0, 0]);
+    chunk_data.extend_from_slice(&zlib_compress(&icc_profile(profile, gray))?);
+    let mut iccp = (chunk_data.len() as u32).to_be_bytes().to_vec();
+    let typed = [b"iCCP".as_slice(), &chunk_data].concat();
+    iccp.extend_from_slice(&typed);
+    // PNG chunks use the same CRC-32 as gzip
+    let mut crc = Crc::new();
+    crc.update(&typed);
+    iccp.extend_from_slice(&crc.sum().to_be_bytes());
+
+    let mut output = bytes[..33].to_vec();
+    output.extend_from_slice(&iccp);
+    let mut offset = 33;
+    while offset + 8 <= bytes.len() {
+        let length = u32::from_be_bytes(bytes[offset..offset + 4].try_into()?) as usize;
+        let end = (offset + 12 + length).min(bytes.len());
+        if !matches!(&bytes[offset + 4..offset + 8], b"iCCP" | b"sRGB" | b"gAMA") {
+            output.extend_from_slice(&bytes[offset..end]);
+        }
+        offset = end;
+    }
+    Ok(output)
+}
+
+/// Adds `APP2` segments holding `icc_profile` after the start of image marker, and any `APP0`
+/// segment following it, of a JPEG. Profiles longer than a segment can hold are split across
+/// several.
+fn embed_jpeg(bytes: &[u8], icc_profile: &[u8]) -> Result<Vec<u8>> {
+    if bytes.len() < 4 || bytes[..2] != [0xff, 0xd8] {
+        return Err(anyhow!("Not a JPEG file"));
+    }
+    let mut insert_at = 2;
+    if bytes[2..4] == [0xff, 0xe0] && bytes.len() >= 6 {
+        insert_at += 2 + u16::from_be_bytes([bytes[4], bytes[5]]) as usize;
+    }
+
+    let chunks: Vec<&[u8]> = icc_profile.chunks(JPEG_ICC_CHUNK_SIZE).collect();
+    let mut output = bytes[..insert_at].to_vec();
+    for (n, chunk) in chunks.iter().enumerate() {
+        output.extend_from_slice(&[0xff, 0xe2]);
+        output.extend_from_slice(&((2 + 12 + 2 + chunk.len()) as u16).to_be_bytes());
+        output.extend_from_slice(b"ICC_PROFILE\0");
+        output.extend_from_slice(&[n as u8 + 1, chunks.len() as u8]);
+        output.extend_from_slice(chunk);
+    }
+    output.extend_from_slice(&bytes[insert_at..]);
+    Ok(output)
+}
+
+/// Saves `image` as a JPEG of `quality`, scaled to 8 bits per sample
+fn save_jpeg(image: &Image, path: &Path, quality: u8) -> Result<()> {
+    let color_type = match image.num_bands() {
+        1 => image::ExtendedColorType::L8,
+        3 => image::ExtendedColorType::Rgb8,
+        n => return Err(anyhow!("Can't write a JPEG of an image with {} bands", n)),
+    };
+    let scale = if image.get_mode() == ImageMode::U8BIT {
+        1.0
+    } else {
+        1.0 / 257.0
+    };
+    let samples: Vec<u8> = iproduct!(0..image.height, 0..image.width, 0..image.num_bands())
+        .map(|(y, x, b)| {
+            (image.get_band(b).get(x, y) * scale)
+                .round()
+                .clamp(0.0, 255.0) as u8
+        })
+        .collect();
+    let mut bytes = vec![];
+    image::codecs::jpeg::JpegEncoder::new_with_quality(&mut bytes, quality.clamp(1, 100)).encode(
+        &samples,
+        image.width as u32,
+        image.height as u32,
+        color_type,
+    )?;
+    fs::write(path, bytes)?;
+    Ok(())
+}
+
+/// Saves `image` to `path` converted to, and tagged with, `profile`. The format is given by the
+/// extension, as with `Image::save`. TIFFs are written with the compression and layout of `tiff`,
+/// JPEGs with `jpeg_quality`.
+pub fn save_with_profile(
+    image: &Image,
+    path: &Path,
+    profile: ColorProfile,
+    tiff: &TiffOptions,
+    jpeg_quality: u8,
+) -> Result<()> {
+    let extension = path
+        .extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    if matches!(extension.as_str(), "tif" | "tiff") {
+        return match profile {
+            ColorProfile::None => tiffwriter::save_tiff(image, path, tiff, None),
+            _ => tiffwriter::save_tiff(
+                &encode(image, profile),
+                path,
+                tiff,
+                Some(&icc_profile(profile, image.num_bands() == 1)),
+            ),
+        };
+    }
+    if matches!(extension.as_str(), "jpg" | "jpeg") {
+        if profile == ColorProfile::None {
+            return save_jpeg(image, path, jpeg_quality);
+        }
+        save_jpeg(&encode(image, profile), path, jpeg_quality)?;
+        let tagged = embed_jpeg(
+            &fs::read(path)?,
+            &icc_profile(profile, image.num_bands() == 1),
+        )?;
+        fs::write(path, tagged)?;
+        return Ok(());
+    }
+    if profile == ColorProfile::None {
+        return image.save(&path.to_string_lossy());
+    }
+    encode(image, profile).save(&path.to_string_lossy())?;
+
+    let bytes = fs::read(path)?;
+    let tagged = match extension.as_str() {
+        "png" => embed_png(&bytes, profile)?,
+        _ => {
+            warn!("Color profiles can't be embedded in {:?}", path);
+            return Ok(());
+        }
+    };
+    fs::write(path, tagged)?;
+    Ok(())
+}
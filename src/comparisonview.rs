@@ -0,0 +1,239 @@
+use egui::{Context, TextureHandle, Ui};
+
+use crate::imageutil;
+use crate::process::comparison::{AlgorithmResult, ComparisonReport};
+use crate::state::StackMethod;
+
+/// The best of the measures of `results`, the highest if `higher` is better or else the lowest
+fn best(
+    results: &[AlgorithmResult],
+    measure: impl Fn(&AlgorithmResult) -> f64,
+    higher: bool,
+) -> Option<f64> {
+    results
+        .iter()
+        .map(measure)
+        .reduce(|a, b| if (b > a) == higher { b } else { a })
+}
+
+/// What the user asked for from the comparison window
+pub enum ComparisonAction {
+    /// Stack up to `max_frames` frames with each of `algorithms`
+    Run {
+        algorithms: Vec<StackMethod>,
+        max_frames: usize,
+    },
+    /// Use the algorithm for processing
+    Use(StackMethod),
+}
+
+/// Runs the stacking algorithms on the same frames and shows the results side by side, with
+/// measures of their sharpness, noise and contrast
+pub struct ComparisonWindow {
+    pub open: bool,
+    /// Whether each of `StackMethod::ALL` is compared
+    selected: [bool; 5],
+    max_frames: usize,
+    /// Magnification of the center of each result
+    zoom: f32,
+    running: bool,
+    report: Option<ComparisonReport>,
+    textures: Vec<TextureHandle>,
+}
+
+impl Default for ComparisonWindow {
+    fn default() -> Self {
+        Self {
+            open: false,
+            selected: [true, true, true, false, false],
+            max_frames: 200,
+            zoom: 1.0,
+            running: false,
+            report: None,
+            textures: vec![],
+        }
+    }
+}
+
+impl ComparisonWindow {
+    /// Clears the previous results while the comparison runs
+    pub fn start(&mut self) {
+        self.running = true;
+        self.report = None;
+        self.textures.clear();
+    }
+
+    pub fn set_report(&mut self, ctx: &Context, report: ComparisonReport) {
+        self.textures = report
+            .results
+            .iter()
+            .map(|r| {
+                ctx.load_texture(
+                    imageutil::gen_random_texture_name(),
+                    imageutil::sciimg_to_color_image(&r.image),
+                    Default::default(),
+                )
+            })
+            .collect();
+        self.report = Some(report);
+        self.running = false;
+    }
+
+    pub fn failed(&mut self) {
+        self.running = false;
+    }
+
+    fn settings_ui(&mut self, ui: &mut Ui, task_running: bool) -> Option<ComparisonAction> {
+        let mut action = None;
+        ui.horizontal(|ui| {
+            StackMethod::ALL
+                .iter()
+                .zip(self.selected.iter_mut())
+                .for_each(|(algorithm, selected)| {
+                    ui.checkbox(selected, algorithm.name());
+                });
+            ui.separator();
+            ui.label(t!("comparison.max_frames"));
+            ui.add(
+                egui::DragValue::new(&mut self.max_frames)
+                    .clamp_range(2..=5000)
+                    .speed(10.0),
+            )
+            .on_hover_text(t!("comparison.max_frames_hint"));
+        });
+        let algorithms: Vec<StackMethod> = StackMethod::ALL
+            .iter()
+            .zip(self.selected.iter())
+            .filter(|(_, selected)| **selected)
+            .map(|(algorithm, _)| *algorithm)
+            .collect();
+        ui.horizontal(|ui| {
+            if ui
+                .add_enabled(
+                    !task_running && algorithms.len() > 1,
+                    egui::Button::new(t!("comparison.run")),
+                )
+                .clicked()
+            {
+                action = Some(ComparisonAction::Run {
+                    algorithms,
+                    max_frames: self.max_frames,
+                });
+            }
+            if self.running {
+                ui.spinner();
+                ui.label(t!("comparison.running"));
+            }
+        });
+        action
+    }
+
+    fn results_ui(&mut self, ui: &mut Ui) -> Option<ComparisonAction> {
+        let report = self.report.as_ref()?;
+        let mut action = None;
+        ui.label(t!("comparison.summary", frames = report.frames_stacked));
+        ui.horizontal(|ui| {
+            ui.label(t!("comparison.zoom"));
+            ui.add(egui::Slider::new(&mut self.zoom, 1.0..=8.0).suffix("x"));
+        });
+
+        let uv = egui::Rect::from_center_size(
+            egui::pos2(0.5, 0.5),
+            egui::vec2(1.0 / self.zoom, 1.0 / self.zoom),
+        );
+        ui.columns(report.results.len(), |columns| {
+            columns
+                .iter_mut()
+                .zip(report.results.iter().zip(self.textures.iter()))
+                .for_each(|(ui, (result, texture))| {
+                    ui.strong(result.algorithm.name());
+                    ui.add(
+                        egui::Image::from_texture(texture)
+                            .uv(uv)
+                            .max_height(420.0)
+                            .shrink_to_fit(),
+                    );
+                });
+        });
+        ui.separator();
+
+        // Higher sharpness and contrast and lower noise are better
+        let best_sharpness = best(&report.results, |r| r.sharpness, true);
+        let best_noise = best(&report.results, |r| r.noise, false);
+        let best_contrast = best(&report.results, |r| r.contrast, true);
+
+        egui::Grid::new("comparison_metrics")
+            .num_columns(6)
+            .spacing([30.0, 4.0])
+            .striped(true)
+            .show(ui, |ui| {
+                ui.strong(t!("processoptions.algorithm"));
+                ui.strong(t!("comparison.sharpness"))
+                    .on_hover_text(t!("comparison.sharpness_hint"));
+                ui.strong(t!("comparison.noise"))
+                    .on_hover_text(t!("comparison.noise_hint"));
+                ui.strong(t!("comparison.contrast"))
+                    .on_hover_text(t!("comparison.contrast_hint"));
+                ui.strong(t!("comparison.time"));
+                ui.label("");
+                ui.end_row();
+
+                let metric = |ui: &mut Ui, value: f64, text: String, best: Option<f64>| {
+                    if Some(value) == best {
+                        ui.strong(format!("{} ★", text));
+                    } else {
+                        ui.monospace(text);
+                    }
+                };
+                report.results.iter().for_each(|result| {
+                    ui.label(result.algorithm.name());
+                    metric(
+                        ui,
+                        result.sharpness,
+                        format!("{:.3}", result.sharpness),
+                        best_sharpness,
+                    );
+                    metric(
+                        ui,
+                        result.noise,
+                        format!("{:.3}%", result.noise),
+                        best_noise,
+                    );
+                    metric(
+                        ui,
+                        result.contrast,
+                        format!("{:.1}%", result.contrast),
+                        best_contrast,
+                    );
+                    ui.monospace(format!("{:.1} s", result.duration.as_secs_f64()));
+                    if ui.button(t!("comparison.use")).clicked() {
+                        action = Some(ComparisonAction::Use(result.algorithm));
+                    }
+                    ui.end_row();
+                });
+            });
+        ui.weak(t!("comparison.hint"));
+        action
+    }
+
+    /// Shows the window. Comparisons can't be started while `task_running`.
+    pub fn ui(&mut self, ctx: &Context, task_running: bool) -> Option<ComparisonAction> {
+        let mut action = None;
+        let mut open = self.open;
+        egui::Window::new(t!("comparison.title"))
+            .open(&mut open)
+            .default_width(900.0)
+            .show(ctx, |ui| {
+                ui.weak(t!("comparison.description"));
+                action = self.settings_ui(ui, task_running);
+                ui.separator();
+                egui::ScrollArea::vertical().show(ui, |ui| {
+                    if let Some(chosen) = self.results_ui(ui) {
+                        action = Some(chosen);
+                    }
+                });
+            });
+        self.open = open;
+        action
+    }
+}
@@ -0,0 +1,135 @@
+use egui::{Context, TextureHandle};
+
+use crate::imageutil;
+use crate::process::contribution::{ContributionReport, StackGeometry};
+
+/// Size the aligned frame patches are shown at
+const PATCH_DISPLAY_SIZE: f32 = 96.0;
+
+/// Lists the frames which contributed to a region of the stack, those deviating most from the
+/// others first, with the region of each frame as it was aligned
+pub struct ContributionWindow {
+    pub open: bool,
+    /// Radius, in output pixels, of the region inspected
+    pub radius: usize,
+    request: Option<(StackGeometry, [usize; 2])>,
+    report: Option<ContributionReport>,
+    textures: Vec<TextureHandle>,
+}
+
+impl Default for ContributionWindow {
+    fn default() -> Self {
+        Self {
+            open: false,
+            radius: 8,
+            request: None,
+            report: None,
+            textures: vec![],
+        }
+    }
+}
+
+impl ContributionWindow {
+    /// Clears the previous report while the frames stacked at `point` are read
+    pub fn start(&mut self, geometry: StackGeometry, point: [usize; 2]) {
+        self.open = true;
+        self.request = Some((geometry, point));
+        self.report = None;
+        self.textures.clear();
+    }
+
+    pub fn set_report(&mut self, ctx: &Context, report: ContributionReport) {
+        self.textures = report
+            .contributions
+            .iter()
+            .map(|c| {
+                ctx.load_texture(
+                    imageutil::gen_random_texture_name(),
+                    imageutil::sciimg_to_color_image(&c.patch),
+                    egui::TextureOptions::NEAREST,
+                )
+            })
+            .collect();
+        self.report = Some(report);
+    }
+
+    /// Shows the window. Returns the request to inspect again when the radius is changed and
+    /// the inspection repeated.
+    pub fn ui(&mut self, ctx: &Context) -> Option<(StackGeometry, [usize; 2])> {
+        let mut inspect_again = false;
+        let mut open = self.open;
+        egui::Window::new(t!("contribution.title"))
+            .open(&mut open)
+            .default_size([520.0, 600.0])
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    ui.label(t!("contribution.radius"));
+                    ui.add(egui::DragValue::new(&mut self.radius).clamp_range(1..=64));
+                    if ui
+                        .add_enabled(
+                            self.report.is_some(),
+                            egui::Button::new(t!("contribution.inspect_again")),
+                        )
+                        .clicked()
+                    {
+                        inspect_again = true;
+                    }
+                });
+
+                let Some(report) = &self.report else {
+                    ui.horizontal(|ui| {
+                        ui.spinner();
+                        ui.label(t!("contribution.reading_frames"));
+                    });
+                    return;
+                };
+                ui.label(t!(
+                    "contribution.summary",
+                    x = report.center[0],
+                    y = report.center[1],
+                    radius = report.radius,
+                    count = report.frames_covering,
+                    level = format!("{:.1}", report.mean_level)
+                ));
+                ui.weak(t!("contribution.hint"));
+                ui.separator();
+
+                egui::ScrollArea::vertical().show(ui, |ui| {
+                    egui::Grid::new("contribution_grid")
+                        .num_columns(6)
+                        .striped(true)
+                        .show(ui, |ui| {
+                            ui.label("");
+                            ui.strong(t!("contribution.frame"));
+                            ui.strong(t!("contribution.sigma"));
+                            ui.strong(t!("contribution.coverage"));
+                            ui.strong(t!("contribution.level"));
+                            ui.strong(t!("contribution.deviation"));
+                            ui.end_row();
+
+                            report
+                                .contributions
+                                .iter()
+                                .zip(self.textures.iter())
+                                .for_each(|(c, texture)| {
+                                    ui.add(egui::Image::from_texture(texture).fit_to_exact_size(
+                                        egui::vec2(PATCH_DISPLAY_SIZE, PATCH_DISPLAY_SIZE),
+                                    ));
+                                    ui.monospace(c.frame_id.to_string());
+                                    ui.monospace(format!("{:.3}", c.sigma));
+                                    ui.monospace(format!("{:.0}%", c.coverage * 100.0));
+                                    ui.monospace(format!("{:.1}", c.level));
+                                    ui.monospace(format!("{:+.1}", c.deviation));
+                                    ui.end_row();
+                                });
+                        });
+                });
+            });
+        self.open = open;
+        if inspect_again {
+            self.request.clone()
+        } else {
+            None
+        }
+    }
+}
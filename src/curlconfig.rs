@@ -0,0 +1,70 @@
+//! Credentials for curl, written to a config file readable only by the user rather than passed on
+//! the command line, where any other process could read them from the process list.
+
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use anyhow::Result;
+
+/// Counts the files written, so concurrent sends from the one process don't share a file
+static NEXT_FILE: AtomicUsize = AtomicUsize::new(0);
+
+/// A curl config file holding a username and password, removed when dropped. Passed to curl
+/// with `--config`.
+pub struct CredentialsFile {
+    path: PathBuf,
+}
+
+impl CredentialsFile {
+    pub fn new(username: &str, password: &str) -> Result<CredentialsFile> {
+        let path = std::env::temp_dir().join(format!(
+            "solhat-curl-{}-{}.conf",
+            std::process::id(),
+            NEXT_FILE.fetch_add(1, Ordering::Relaxed)
+        ));
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        let mut file = options.open(&path)?;
+        // Created before writing, so it's removed even if the write fails
+        let credentials = CredentialsFile { path };
+        writeln!(file, "user = \"{}:{}\"", quoted(username), quoted(password))?;
+        Ok(credentials)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for CredentialsFile {
+    fn drop(&mut self) {
+        if let Err(why) = fs::remove_file(&self.path) {
+            warn!(
+                "Failed to remove curl credentials file {:?}: {}",
+                self.path, why
+            );
+        }
+    }
+}
+
+/// Escapes `value` for use inside a double quoted curl config parameter
+fn quoted(value: &str) -> String {
+    value
+        .chars()
+        .flat_map(|c| match c {
+            '\\' => vec!['\\', '\\'],
+            '"' => vec!['\\', '"'],
+            '\t' => vec!['\\', 't'],
+            '\n' => vec!['\\', 'n'],
+            '\r' => vec!['\\', 'r'],
+            c => vec![c],
+        })
+        .collect()
+}
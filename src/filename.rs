@@ -0,0 +1,41 @@
+//! Output filename templates. Tokens in braces, e.g. `{basename}`, are replaced with values from
+//! the processing options.
+
+use anyhow::{anyhow, Result};
+
+use crate::template::{self, Separators};
+
+/// Produces the names used before templates could be edited
+pub const DEFAULT_TEMPLATE: &str = "{basename}_{target}_{drizzle}_{orientation}_{freetext}";
+
+/// Tokens recognized in a template
+pub const TOKENS: &[&str] = &[
+    "basename",
+    "target",
+    "drizzle",
+    "orientation",
+    "date",
+    "freetext",
+];
+
+/// Replaces each token in `template` with its value from `value_of`, which returns None for
+/// tokens it doesn't recognize. The result has no file extension.
+pub fn render<F>(template: &str, value_of: F) -> Result<String>
+where
+    F: Fn(&str) -> Option<String>,
+{
+    let name = template::render(template, Separators::Collapse, value_of)?;
+    if name.is_empty() {
+        Err(anyhow!("Filename template produces an empty filename"))
+    } else if name.contains(['/', '\\']) {
+        Err(anyhow!("Filename template cannot contain path separators"))
+    } else {
+        Ok(name)
+    }
+}
+
+/// Checks `template` for unknown tokens, unbalanced braces and path separators
+pub fn validate(template: &str) -> Result<()> {
+    template::validate(template, TOKENS)?;
+    render(template, |token| Some(token.to_owned())).map(|_| ())
+}
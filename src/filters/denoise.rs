@@ -0,0 +1,236 @@
+use std::fmt;
+
+use itertools::iproduct;
+use rayon::prelude::*;
+use sciimg::prelude::Image;
+use serde::{Deserialize, Serialize};
+
+/// Half width of the patches compared by non-local means
+const NLM_PATCH_RADIUS: isize = 1;
+
+/// Half width of the window searched for similar patches by non-local means
+const NLM_SEARCH_RADIUS: isize = 5;
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+pub enum DenoiseMethod {
+    None,
+    Gaussian,
+    Bilateral,
+    NonLocalMeans,
+}
+
+impl fmt::Display for DenoiseMethod {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DenoiseMethod::None => f.write_str(&t!("results.denoise_none")),
+            DenoiseMethod::Gaussian => f.write_str(&t!("results.denoise_gaussian")),
+            DenoiseMethod::Bilateral => f.write_str(&t!("results.denoise_bilateral")),
+            DenoiseMethod::NonLocalMeans => f.write_str(&t!("results.denoise_nlm")),
+        }
+    }
+}
+
+impl DenoiseMethod {
+    pub fn all() -> [DenoiseMethod; 4] {
+        [
+            DenoiseMethod::None,
+            DenoiseMethod::Gaussian,
+            DenoiseMethod::Bilateral,
+            DenoiseMethod::NonLocalMeans,
+        ]
+    }
+
+    /// True if the method blurs over a spatial `sigma`
+    pub fn uses_sigma(&self) -> bool {
+        matches!(self, DenoiseMethod::Gaussian | DenoiseMethod::Bilateral)
+    }
+
+    /// True if the method preserves edges to a degree set by `strength`
+    pub fn uses_strength(&self) -> bool {
+        matches!(
+            self,
+            DenoiseMethod::Bilateral | DenoiseMethod::NonLocalMeans
+        )
+    }
+}
+
+/// Options for reducing noise in the stacked image
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct DenoiseOptions {
+    pub method: DenoiseMethod,
+    /// Spatial standard deviation, in pixels, of the Gaussian and bilateral filters
+    pub sigma: f64,
+    /// Differences in value, as a fraction of the band's maximum, below which pixels are treated
+    /// as noise and averaged together. Larger values smooth more, at the cost of fine detail.
+    pub strength: f64,
+}
+
+impl Default for DenoiseOptions {
+    fn default() -> Self {
+        DenoiseOptions {
+            method: DenoiseMethod::None,
+            sigma: 1.0,
+            strength: 0.05,
+        }
+    }
+}
+
+/// Single band of an image as a row-major vector, for filters which need random access
+pub(super) struct Plane {
+    pub(super) width: usize,
+    pub(super) height: usize,
+    pub(super) values: Vec<f32>,
+}
+
+impl Plane {
+    pub(super) fn from_band(image: &Image, band: usize) -> Self {
+        let buffer = image.get_band(band);
+        Plane {
+            width: image.width,
+            height: image.height,
+            values: iproduct!(0..image.height, 0..image.width)
+                .map(|(y, x)| buffer.get(x, y))
+                .collect(),
+        }
+    }
+
+    /// Value at (`x`, `y`), with coordinates outside the plane clamped to its edges
+    pub(super) fn get(&self, x: isize, y: isize) -> f32 {
+        let x = x.clamp(0, self.width as isize - 1) as usize;
+        let y = y.clamp(0, self.height as isize - 1) as usize;
+        self.values[y * self.width + x]
+    }
+
+    fn max(&self) -> f32 {
+        self.values.iter().cloned().fold(0.0, f32::max)
+    }
+
+    /// Builds a new plane by evaluating `f` at every pixel, in parallel by row
+    pub(super) fn map<F>(&self, f: F) -> Plane
+    where
+        F: Fn(isize, isize) -> f32 + Sync,
+    {
+        let mut values = vec![0.0; self.values.len()];
+        values
+            .par_chunks_mut(self.width)
+            .enumerate()
+            .for_each(|(y, row)| {
+                row.iter_mut()
+                    .enumerate()
+                    .for_each(|(x, v)| *v = f(x as isize, y as isize));
+            });
+        Plane {
+            width: self.width,
+            height: self.height,
+            values,
+        }
+    }
+
+    pub(super) fn put_band(&self, image: &mut Image, band: usize) {
+        iproduct!(0..self.height, 0..self.width).for_each(|(y, x)| {
+            image.put(x, y, self.values[y * self.width + x], band);
+        });
+    }
+}
+
+fn gaussian_kernel(sigma: f64) -> Vec<f32> {
+    let radius = (sigma * 3.0).ceil().max(1.0) as isize;
+    let kernel: Vec<f32> = (-radius..=radius)
+        .map(|i| (-(i * i) as f64 / (2.0 * sigma * sigma)).exp() as f32)
+        .collect();
+    let sum: f32 = kernel.iter().sum();
+    kernel.iter().map(|k| k / sum).collect()
+}
+
+/// Separable Gaussian blur
+fn gaussian(plane: &Plane, sigma: f64) -> Plane {
+    let kernel = gaussian_kernel(sigma);
+    let radius = (kernel.len() / 2) as isize;
+    let horizontal = plane.map(|x, y| {
+        kernel
+            .iter()
+            .enumerate()
+            .map(|(i, k)| k * plane.get(x + i as isize - radius, y))
+            .sum()
+    });
+    horizontal.map(|x, y| {
+        kernel
+            .iter()
+            .enumerate()
+            .map(|(i, k)| k * horizontal.get(x, y + i as isize - radius))
+            .sum()
+    })
+}
+
+/// Gaussian blur which only averages pixels of similar value, leaving edges intact
+fn bilateral(plane: &Plane, sigma: f64, range_sigma: f32) -> Plane {
+    let radius = (sigma * 2.0).ceil().max(1.0) as isize;
+    let spatial_denom = (2.0 * sigma * sigma) as f32;
+    let range_denom = 2.0 * range_sigma * range_sigma;
+    plane.map(|x, y| {
+        let center = plane.get(x, y);
+        let (sum, weights) = iproduct!(-radius..=radius, -radius..=radius).fold(
+            (0.0, 0.0),
+            |(sum, weights), (dy, dx)| {
+                let v = plane.get(x + dx, y + dy);
+                let d = v - center;
+                let w = (-((dx * dx + dy * dy) as f32) / spatial_denom - d * d / range_denom).exp();
+                (sum + w * v, weights + w)
+            },
+        );
+        sum / weights
+    })
+}
+
+/// Mean squared difference between the patches centered on (`x1`, `y1`) and (`x2`, `y2`)
+fn patch_distance(plane: &Plane, x1: isize, y1: isize, x2: isize, y2: isize) -> f32 {
+    let n = ((2 * NLM_PATCH_RADIUS + 1) * (2 * NLM_PATCH_RADIUS + 1)) as f32;
+    iproduct!(
+        -NLM_PATCH_RADIUS..=NLM_PATCH_RADIUS,
+        -NLM_PATCH_RADIUS..=NLM_PATCH_RADIUS
+    )
+    .map(|(dy, dx)| {
+        let d = plane.get(x1 + dx, y1 + dy) - plane.get(x2 + dx, y2 + dy);
+        d * d
+    })
+    .sum::<f32>()
+        / n
+}
+
+/// Averages each pixel with the pixels in its neighborhood whose surrounding patches look alike,
+/// weighted by patch similarity. `h` sets how alike patches must be to contribute.
+fn non_local_means(plane: &Plane, h: f32) -> Plane {
+    let h2 = (h * h).max(f32::EPSILON);
+    plane.map(|x, y| {
+        let (sum, weights) = iproduct!(
+            -NLM_SEARCH_RADIUS..=NLM_SEARCH_RADIUS,
+            -NLM_SEARCH_RADIUS..=NLM_SEARCH_RADIUS
+        )
+        .fold((0.0, 0.0), |(sum, weights), (dy, dx)| {
+            let w = (-patch_distance(plane, x, y, x + dx, y + dy) / h2).exp();
+            (sum + w * plane.get(x + dx, y + dy), weights + w)
+        });
+        sum / weights
+    })
+}
+
+/// Applies the selected noise reduction to each band of `image`
+pub fn denoise(image: &Image, options: &DenoiseOptions) -> Image {
+    if options.method == DenoiseMethod::None {
+        return image.clone();
+    }
+    let mut denoised = image.clone();
+    (0..image.num_bands()).for_each(|b| {
+        let plane = Plane::from_band(image, b);
+        let strength = options.strength as f32 * plane.max();
+        let filtered = match options.method {
+            DenoiseMethod::None => return,
+            DenoiseMethod::Gaussian => gaussian(&plane, options.sigma),
+            DenoiseMethod::Bilateral => bilateral(&plane, options.sigma, strength),
+            DenoiseMethod::NonLocalMeans => non_local_means(&plane, strength),
+        };
+        filtered.put_band(&mut denoised, b);
+    });
+    denoised
+}
@@ -0,0 +1,335 @@
+use anyhow::{Error, Result};
+use itertools::iproduct;
+use sciimg::prelude::{Image, ImageBuffer};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+use crate::imageutil;
+
+/// Half width, in pixels, of the box around each sample point whose median is used as the
+/// background level. Keeps single hot pixels or stars from skewing the model.
+const SAMPLE_RADIUS: usize = 5;
+
+/// Spacing, in pixels, of the nodes at which the mesh model is interpolated from the sample
+/// points. Pixels between the nodes are interpolated bilinearly.
+const MESH_NODE_SPACING: usize = 16;
+
+/// How the background is modeled from the sample points
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Default)]
+pub enum BackgroundModelKind {
+    /// A smooth polynomial surface fitted to all the samples. Suits gradients across the whole
+    /// image, such as from sky brightness.
+    #[default]
+    Polynomial,
+    /// Interpolated between the samples, weighted by their inverse squared distance. Follows
+    /// uneven vignetting the polynomial can't, but needs samples spread over the whole image.
+    Mesh,
+}
+
+impl fmt::Display for BackgroundModelKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BackgroundModelKind::Polynomial => f.write_str(&t!("results.gradient_polynomial")),
+            BackgroundModelKind::Mesh => f.write_str(&t!("results.gradient_mesh")),
+        }
+    }
+}
+
+impl BackgroundModelKind {
+    pub fn all() -> [BackgroundModelKind; 2] {
+        [BackgroundModelKind::Polynomial, BackgroundModelKind::Mesh]
+    }
+}
+
+/// Options for fitting and removing a smooth background gradient
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct GradientOptions {
+    pub model: BackgroundModelKind,
+    /// Order of the fitted two dimensional polynomial, 1 (a plane) through 4
+    pub order: usize,
+    /// Number of automatically placed sample points along each axis
+    pub grid_size: usize,
+    /// Sample points placed by the user, in image pixel coordinates. The automatic grid is used
+    /// when empty.
+    pub points: Vec<[usize; 2]>,
+    /// Automatic samples brighter than the median by more than this many robust standard
+    /// deviations are taken to be on the target and are rejected.
+    pub rejection_sigma: f64,
+}
+
+impl Default for GradientOptions {
+    fn default() -> Self {
+        GradientOptions {
+            model: BackgroundModelKind::Polynomial,
+            order: 2,
+            grid_size: 8,
+            points: vec![],
+            rejection_sigma: 2.0,
+        }
+    }
+}
+
+fn sample_level(buffer: &ImageBuffer, x: usize, y: usize) -> f32 {
+    let x0 = x.saturating_sub(SAMPLE_RADIUS);
+    let y0 = y.saturating_sub(SAMPLE_RADIUS);
+    let x1 = (x + SAMPLE_RADIUS).min(buffer.width - 1);
+    let y1 = (y + SAMPLE_RADIUS).min(buffer.height - 1);
+    let mut values: Vec<f32> = iproduct!(y0..=y1, x0..=x1)
+        .map(|(sy, sx)| buffer.get(sx, sy))
+        .collect();
+    imageutil::median(&mut values)
+}
+
+/// Evenly spaced grid of sample points inset from the image edges
+pub fn grid_points(width: usize, height: usize, grid_size: usize) -> Vec<[usize; 2]> {
+    let n = grid_size.max(2);
+    let step_x = (width - 1) as f64 / n as f64;
+    let step_y = (height - 1) as f64 / n as f64;
+    iproduct!(0..n, 0..n)
+        .map(|(gy, gx)| {
+            [
+                ((gx as f64 + 0.5) * step_x) as usize,
+                ((gy as f64 + 0.5) * step_y) as usize,
+            ]
+        })
+        .collect()
+}
+
+/// Exponents (i, j) of the x^i * y^j terms of a polynomial of `order`
+fn terms(order: usize) -> Vec<(i32, i32)> {
+    (0..=order as i32)
+        .flat_map(|total| (0..=total).map(move |j| (total - j, j)))
+        .collect()
+}
+
+/// Solves the square system `a` * x = `b` by Gaussian elimination with partial pivoting
+fn solve(mut a: Vec<Vec<f64>>, mut b: Vec<f64>) -> Result<Vec<f64>> {
+    let n = b.len();
+    for col in 0..n {
+        let pivot = (col..n)
+            .max_by(|r1, r2| a[*r1][col].abs().total_cmp(&a[*r2][col].abs()))
+            .unwrap();
+        if a[pivot][col].abs() < 1e-12 {
+            return Err(Error::msg(
+                "Background model is underdetermined. Add more sample points.",
+            ));
+        }
+        a.swap(col, pivot);
+        b.swap(col, pivot);
+        let pivot_row = a[col].clone();
+        for row in col + 1..n {
+            let f = a[row][col] / pivot_row[col];
+            a[row]
+                .iter_mut()
+                .zip(pivot_row.iter())
+                .skip(col)
+                .for_each(|(v, p)| *v -= f * p);
+            b[row] -= f * b[col];
+        }
+    }
+    let mut x = vec![0.0; n];
+    for row in (0..n).rev() {
+        let s: f64 = (row + 1..n).map(|k| a[row][k] * x[k]).sum();
+        x[row] = (b[row] - s) / a[row][row];
+    }
+    Ok(x)
+}
+
+/// Smooth polynomial background fitted to sample points of a single band
+struct BackgroundModel {
+    terms: Vec<(i32, i32)>,
+    coefficients: Vec<f64>,
+    width: usize,
+    height: usize,
+}
+
+impl BackgroundModel {
+    /// Coordinates are scaled to -1..1 to keep the normal equations well conditioned
+    fn normalized(&self, x: f64, y: f64) -> (f64, f64) {
+        (
+            2.0 * x / self.width as f64 - 1.0,
+            2.0 * y / self.height as f64 - 1.0,
+        )
+    }
+
+    fn fit(buffer: &ImageBuffer, points: &[[usize; 2]], order: usize) -> Result<Self> {
+        let mut model = BackgroundModel {
+            terms: terms(order),
+            coefficients: vec![],
+            width: buffer.width,
+            height: buffer.height,
+        };
+        let n = model.terms.len();
+        if points.len() < n {
+            return Err(Error::msg(
+                "Background model is underdetermined. Add more sample points.",
+            ));
+        }
+
+        let mut ata = vec![vec![0.0; n]; n];
+        let mut atb = vec![0.0; n];
+        points.iter().for_each(|[x, y]| {
+            let value = sample_level(buffer, *x, *y) as f64;
+            let (nx, ny) = model.normalized(*x as f64, *y as f64);
+            let row: Vec<f64> = model
+                .terms
+                .iter()
+                .map(|(i, j)| nx.powi(*i) * ny.powi(*j))
+                .collect();
+            (0..n).for_each(|r| {
+                (0..n).for_each(|c| ata[r][c] += row[r] * row[c]);
+                atb[r] += row[r] * value;
+            });
+        });
+        model.coefficients = solve(ata, atb)?;
+        Ok(model)
+    }
+
+    fn value_at(&self, x: usize, y: usize) -> f32 {
+        let (nx, ny) = self.normalized(x as f64, y as f64);
+        self.terms
+            .iter()
+            .zip(self.coefficients.iter())
+            .map(|((i, j), c)| c * nx.powi(*i) * ny.powi(*j))
+            .sum::<f64>() as f32
+    }
+}
+
+/// Background of a single band interpolated between sample points by inverse distance weighting
+struct MeshModel {
+    /// Model values at nodes `MESH_NODE_SPACING` pixels apart, row by row
+    nodes: Vec<f32>,
+    columns: usize,
+    rows: usize,
+}
+
+impl MeshModel {
+    fn fit(buffer: &ImageBuffer, points: &[[usize; 2]]) -> Result<Self> {
+        if points.is_empty() {
+            return Err(Error::msg(
+                "Background model is underdetermined. Add more sample points.",
+            ));
+        }
+        let samples: Vec<(f64, f64, f64)> = points
+            .iter()
+            .map(|[x, y]| (*x as f64, *y as f64, sample_level(buffer, *x, *y) as f64))
+            .collect();
+        let columns = (buffer.width - 1) / MESH_NODE_SPACING + 2;
+        let rows = (buffer.height - 1) / MESH_NODE_SPACING + 2;
+        let nodes = iproduct!(0..rows, 0..columns)
+            .map(|(row, column)| {
+                let x = (column * MESH_NODE_SPACING) as f64;
+                let y = (row * MESH_NODE_SPACING) as f64;
+                let (weighted, weights) =
+                    samples
+                        .iter()
+                        .fold((0.0, 0.0), |(weighted, weights), (sx, sy, v)| {
+                            let weight = 1.0 / ((x - sx).powi(2) + (y - sy).powi(2)).max(1.0);
+                            (weighted + weight * v, weights + weight)
+                        });
+                (weighted / weights) as f32
+            })
+            .collect();
+        Ok(MeshModel {
+            nodes,
+            columns,
+            rows,
+        })
+    }
+
+    fn node(&self, column: usize, row: usize) -> f32 {
+        self.nodes[row.min(self.rows - 1) * self.columns + column.min(self.columns - 1)]
+    }
+
+    fn value_at(&self, x: usize, y: usize) -> f32 {
+        let (column, row) = (x / MESH_NODE_SPACING, y / MESH_NODE_SPACING);
+        let fx = (x % MESH_NODE_SPACING) as f32 / MESH_NODE_SPACING as f32;
+        let fy = (y % MESH_NODE_SPACING) as f32 / MESH_NODE_SPACING as f32;
+        let top = self.node(column, row) * (1.0 - fx) + self.node(column + 1, row) * fx;
+        let bottom = self.node(column, row + 1) * (1.0 - fx) + self.node(column + 1, row + 1) * fx;
+        top * (1.0 - fy) + bottom * fy
+    }
+}
+
+/// Drops automatic sample points which fall on the target rather than the background
+fn reject_bright_points(
+    buffer: &ImageBuffer,
+    points: Vec<[usize; 2]>,
+    rejection_sigma: f64,
+) -> Vec<[usize; 2]> {
+    let levels: Vec<f32> = points
+        .iter()
+        .map(|[x, y]| sample_level(buffer, *x, *y))
+        .collect();
+    let m = imageutil::median(&mut levels.clone());
+    let mut deviations: Vec<f32> = levels.iter().map(|v| (v - m).abs()).collect();
+    let sigma = imageutil::median(&mut deviations) as f64 * imageutil::MAD_TO_SIGMA;
+    points
+        .into_iter()
+        .zip(levels)
+        .filter(|(_, v)| ((v - m) as f64) <= rejection_sigma * sigma.max(f64::EPSILON))
+        .map(|(p, _)| p)
+        .collect()
+}
+
+/// Sample points used for `image` with `options`: the user's points, or the automatic grid less
+/// any points rejected as being on the target.
+pub fn sample_points(image: &Image, options: &GradientOptions) -> Vec<[usize; 2]> {
+    if !options.points.is_empty() {
+        options
+            .points
+            .iter()
+            .filter(|[x, y]| *x < image.width && *y < image.height)
+            .cloned()
+            .collect()
+    } else {
+        reject_bright_points(
+            image.get_band(0),
+            grid_points(image.width, image.height, options.grid_size),
+            options.rejection_sigma,
+        )
+    }
+}
+
+/// Fits a background model to each band of `image`
+pub fn model_background(image: &Image, options: &GradientOptions) -> Result<Image> {
+    let points = sample_points(image, options);
+    let mut background = image.clone();
+    for b in 0..image.num_bands() {
+        match options.model {
+            BackgroundModelKind::Polynomial => {
+                let model = BackgroundModel::fit(image.get_band(b), &points, options.order)?;
+                iproduct!(0..image.height, 0..image.width).for_each(|(y, x)| {
+                    background.put(x, y, model.value_at(x, y), b);
+                });
+            }
+            BackgroundModelKind::Mesh => {
+                let model = MeshModel::fit(image.get_band(b), &points)?;
+                iproduct!(0..image.height, 0..image.width).for_each(|(y, x)| {
+                    background.put(x, y, model.value_at(x, y), b);
+                });
+            }
+        }
+    }
+    Ok(background)
+}
+
+/// Subtracts the modeled background from `image`, keeping the mean background level so the
+/// overall brightness is unchanged.
+pub fn remove_gradient(image: &Image, options: &GradientOptions) -> Result<Image> {
+    let background = model_background(image, options)?;
+    let mut corrected = image.clone();
+    for b in 0..image.num_bands() {
+        let band = background.get_band(b);
+        let mean = iproduct!(0..image.height, 0..image.width)
+            .map(|(y, x)| band.get(x, y) as f64)
+            .sum::<f64>()
+            / (image.width * image.height) as f64;
+        iproduct!(0..image.height, 0..image.width).for_each(|(y, x)| {
+            let v = image.get_band(b).get(x, y) - band.get(x, y) + mean as f32;
+            corrected.put(x, y, v.max(0.0), b);
+        });
+    }
+    Ok(corrected)
+}
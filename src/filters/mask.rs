@@ -0,0 +1,139 @@
+use std::fmt;
+
+use itertools::iproduct;
+use sciimg::prelude::Image;
+use serde::{Deserialize, Serialize};
+
+/// Fraction of the brightest level above which pixels are taken to be on the solar disk
+const DISK_THRESHOLD: f32 = 0.5;
+
+/// Where sharpening is applied relative to the solar disk
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+pub enum SharpeningMask {
+    Everywhere,
+    DiskOnly,
+    OutsideDisk,
+}
+
+impl fmt::Display for SharpeningMask {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SharpeningMask::Everywhere => f.write_str(&t!("results.mask_everywhere")),
+            SharpeningMask::DiskOnly => f.write_str(&t!("results.mask_disk_only")),
+            SharpeningMask::OutsideDisk => f.write_str(&t!("results.mask_outside_disk")),
+        }
+    }
+}
+
+impl SharpeningMask {
+    pub fn all() -> [SharpeningMask; 3] {
+        [
+            SharpeningMask::Everywhere,
+            SharpeningMask::DiskOnly,
+            SharpeningMask::OutsideDisk,
+        ]
+    }
+}
+
+/// Position and size of the solar disk in an image
+#[derive(Debug, Clone, Copy)]
+pub struct Disk {
+    pub center_x: f32,
+    pub center_y: f32,
+    pub radius: f32,
+}
+
+/// Finds the solar disk as the pixels of the first band brighter than half the 99.5th percentile
+/// level. The center is their centroid and the radius that of a circle of the same area, which
+/// holds up when part of the limb is clipped by the frame edge better than the extent does.
+pub fn detect_disk(image: &Image) -> Option<Disk> {
+    let band = image.get_band(0);
+    let mut levels: Vec<f32> = iproduct!(0..image.height, 0..image.width)
+        .map(|(y, x)| band.get(x, y))
+        .filter(|v| v.is_finite())
+        .collect();
+    levels.sort_by(f32::total_cmp);
+    let bright = *levels.get(levels.len() * 995 / 1000)?;
+    let threshold = bright * DISK_THRESHOLD;
+    if threshold <= 0.0 {
+        return None;
+    }
+
+    let (mut sum_x, mut sum_y, mut count) = (0.0, 0.0, 0);
+    iproduct!(0..image.height, 0..image.width)
+        .filter(|(y, x)| band.get(*x, *y) > threshold)
+        .for_each(|(y, x)| {
+            sum_x += x as f64;
+            sum_y += y as f64;
+            count += 1;
+        });
+    if count == 0 {
+        return None;
+    }
+    Some(Disk {
+        center_x: (sum_x / count as f64) as f32,
+        center_y: (sum_y / count as f64) as f32,
+        radius: (count as f64 / std::f64::consts::PI).sqrt() as f32,
+    })
+}
+
+/// Per-pixel weights, 0.0 to 1.0, of how much sharpening to apply. The disk edge is ramped over
+/// `feather` pixels so the transition between sharpened and unsharpened areas isn't visible.
+pub fn mask_weights(
+    width: usize,
+    height: usize,
+    mask: SharpeningMask,
+    disk: &Disk,
+    feather: f32,
+) -> Vec<f32> {
+    iproduct!(0..height, 0..width)
+        .map(|(y, x)| {
+            let dx = x as f32 - disk.center_x;
+            let dy = y as f32 - disk.center_y;
+            let from_edge = disk.radius - (dx * dx + dy * dy).sqrt();
+            let inside = (from_edge / feather.max(1.0) + 0.5).clamp(0.0, 1.0);
+            match mask {
+                SharpeningMask::Everywhere => 1.0,
+                SharpeningMask::DiskOnly => inside,
+                SharpeningMask::OutsideDisk => 1.0 - inside,
+            }
+        })
+        .collect()
+}
+
+/// Mixes `sharpened` back into `original` in proportion to `weights`
+pub fn blend(original: &Image, sharpened: &Image, weights: &[f32]) -> Image {
+    let mut blended = original.clone();
+    (0..original.num_bands()).for_each(|b| {
+        let o = original.get_band(b);
+        let s = sharpened.get_band(b);
+        iproduct!(0..original.height, 0..original.width).for_each(|(y, x)| {
+            let w = weights[y * original.width + x];
+            blended.put(x, y, o.get(x, y) + w * (s.get(x, y) - o.get(x, y)), b);
+        });
+    });
+    blended
+}
+
+/// Weights which protect low contrast detail, mostly noise, from sharpening. The detail the
+/// sharpening added at each pixel is recovered from the difference between `sharpened` and
+/// `original`.
+/// Detail below `threshold`, as a fraction of the band's maximum level, isn't sharpened at all,
+/// and the weight ramps up to full sharpening at twice the threshold.
+pub fn edge_weights(original: &Image, sharpened: &Image, amount: f32, threshold: f32) -> Vec<f32> {
+    let mut weights = vec![0.0_f32; original.width * original.height];
+    (0..original.num_bands()).for_each(|b| {
+        let o = original.get_band(b);
+        let s = sharpened.get_band(b);
+        let max = iproduct!(0..original.height, 0..original.width)
+            .map(|(y, x)| o.get(x, y))
+            .fold(f32::EPSILON, f32::max);
+        iproduct!(0..original.height, 0..original.width).for_each(|(y, x)| {
+            let detail = (s.get(x, y) - o.get(x, y)).abs() / amount.max(f32::EPSILON) / max;
+            let w = ((detail - threshold) / threshold).clamp(0.0, 1.0);
+            let i = y * original.width + x;
+            weights[i] = weights[i].max(w);
+        });
+    });
+    weights
+}
@@ -0,0 +1,274 @@
+pub mod denoise;
+pub mod gradient;
+pub mod mask;
+pub mod palette;
+pub mod tonemap;
+pub mod wavelets;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Result};
+use sciimg::prelude::Image;
+use sciimg::unsharp::RgbImageUnsharpMask;
+use serde::{Deserialize, Serialize};
+
+use crate::cancel::*;
+use crate::partialfile;
+use crate::taskstatus::*;
+use denoise::DenoiseOptions;
+use gradient::GradientOptions;
+use mask::SharpeningMask;
+use palette::PaletteOptions;
+use tonemap::ToneMapping;
+use wavelets::WaveletOptions;
+
+/// Adjustments applied to a stacked image for display and export, in the order they're applied:
+/// gradient removal, noise reduction, tone mapping, levels, wavelet and unsharp mask sharpening,
+/// then colorization.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct FilterSettings {
+    pub exposure: f64,
+    pub gamma: f64,
+    pub gradient_removal: bool,
+    pub gradient: GradientOptions,
+    pub denoise: DenoiseOptions,
+    pub tone_mapping: ToneMapping,
+    pub tone_mapping_strength: f64,
+    pub wavelets: WaveletOptions,
+    pub unsharp_mask: bool,
+    pub unsharp_sigma: f64,
+    pub unsharp_amount: f64,
+    pub sharpening_mask: SharpeningMask,
+    pub mask_feather: f64,
+    pub edge_threshold: f64,
+    pub palette: PaletteOptions,
+    /// Show the fitted background rather than the image with it removed
+    #[serde(skip)]
+    pub show_background_model: bool,
+}
+
+impl Default for FilterSettings {
+    fn default() -> Self {
+        FilterSettings {
+            exposure: 0.0,
+            gamma: 1.0,
+            gradient_removal: false,
+            gradient: GradientOptions::default(),
+            denoise: DenoiseOptions::default(),
+            tone_mapping: ToneMapping::None,
+            tone_mapping_strength: 10.0,
+            wavelets: WaveletOptions::default(),
+            unsharp_mask: false,
+            unsharp_sigma: 1.3,
+            unsharp_amount: 1.0,
+            sharpening_mask: SharpeningMask::Everywhere,
+            mask_feather: 10.0,
+            edge_threshold: 0.0,
+            palette: PaletteOptions::default(),
+            show_background_model: false,
+        }
+    }
+}
+
+impl FilterSettings {
+    /// Gradient removal, noise reduction, tone mapping and levels: everything ahead of sharpening
+    pub fn apply_adjustments(&self, image: &Image) -> Image {
+        let mut image_adjusted = if self.gradient_removal {
+            let filtered = if self.show_background_model {
+                gradient::model_background(image, &self.gradient)
+            } else {
+                gradient::remove_gradient(image, &self.gradient)
+            };
+            filtered.unwrap_or_else(|why| {
+                warn!("Gradient removal skipped: {}", why);
+                image.clone()
+            })
+        } else {
+            image.clone()
+        };
+
+        // Noise is reduced ahead of sharpening, which would otherwise amplify it
+        image_adjusted = denoise::denoise(&image_adjusted, &self.denoise);
+
+        image_adjusted = tonemap::tone_map(
+            &image_adjusted,
+            self.tone_mapping,
+            self.tone_mapping_strength,
+        );
+
+        image_adjusted.levels_with_gamma(0.0, 1.0 - self.exposure as f32, 1.0 / self.gamma as f32);
+        image_adjusted
+    }
+
+    pub fn apply(&self, image: &Image) -> Image {
+        let image_adjusted = self.apply_adjustments(image);
+
+        let sharpened = if self.sharpens() {
+            let sharpened = self.sharpened_copy(&image_adjusted);
+            match self.sharpening_weights(&image_adjusted, &sharpened) {
+                Some(weights) => mask::blend(&image_adjusted, &sharpened, &weights),
+                None => sharpened,
+            }
+        } else {
+            image_adjusted
+        };
+        palette::colorize(&sharpened, &self.palette)
+    }
+
+    /// True if either wavelet or unsharp mask sharpening is applied
+    pub fn sharpens(&self) -> bool {
+        self.unsharp_mask || self.wavelets.is_active()
+    }
+
+    pub fn sharpened_copy(&self, image: &Image) -> Image {
+        let mut sharpened = wavelets::sharpen(image, &self.wavelets);
+        if self.unsharp_mask {
+            sharpened.unsharp_mask(self.unsharp_sigma as f32, self.unsharp_amount as f32);
+        }
+        sharpened
+    }
+
+    /// How strongly detail is amplified, for recovering the detail the sharpening added
+    fn sharpening_amount(&self) -> f64 {
+        let unsharp = if self.unsharp_mask {
+            self.unsharp_amount
+        } else {
+            0.0
+        };
+        let wavelets = if self.wavelets.is_active() {
+            self.wavelets
+                .layers
+                .iter()
+                .map(|l| l.gain - 1.0)
+                .fold(0.0, f64::max)
+        } else {
+            0.0
+        };
+        unsharp + wavelets
+    }
+
+    /// How much of the sharpening to keep at each pixel, limited to inside or outside the solar
+    /// disk and to detail above the edge protection threshold. None if sharpening applies
+    /// everywhere in full.
+    pub fn sharpening_weights(&self, original: &Image, sharpened: &Image) -> Option<Vec<f32>> {
+        let region = if self.sharpening_mask == SharpeningMask::Everywhere {
+            None
+        } else if let Some(disk) = mask::detect_disk(original) {
+            Some(mask::mask_weights(
+                original.width,
+                original.height,
+                self.sharpening_mask,
+                &disk,
+                self.mask_feather as f32,
+            ))
+        } else {
+            warn!("Solar disk not found. Sharpening mask not applied");
+            None
+        };
+
+        let edges = if self.edge_threshold > 0.0 {
+            Some(mask::edge_weights(
+                original,
+                sharpened,
+                self.sharpening_amount() as f32,
+                self.edge_threshold as f32,
+            ))
+        } else {
+            None
+        };
+
+        match (region, edges) {
+            (Some(r), Some(e)) => Some(r.iter().zip(e.iter()).map(|(r, e)| r * e).collect()),
+            (r, e) => r.or(e),
+        }
+    }
+
+    /// Settings suited to a preset: sample points placed on one image mean nothing on another
+    pub fn without_gradient_points(&self) -> Self {
+        let mut settings = self.clone();
+        settings.gradient.points.clear();
+        settings
+    }
+}
+
+/// Directory in which named filter presets are stored
+fn filter_presets_dir() -> PathBuf {
+    dirs::home_dir().unwrap().join(".solhat/filter_presets/")
+}
+
+fn filter_preset_path(name: &str) -> Result<PathBuf> {
+    let name = name.trim();
+    if name.is_empty() || name.contains(['/', '\\']) || name.starts_with('.') {
+        Err(anyhow!("Invalid filter preset name: '{}'", name))
+    } else {
+        Ok(filter_presets_dir().join(format!("{}.toml", name)))
+    }
+}
+
+/// Names of all saved filter presets, sorted alphabetically
+pub fn list_filter_presets() -> Vec<String> {
+    let mut names: Vec<String> = if let Ok(entries) = fs::read_dir(filter_presets_dir()) {
+        entries
+            .filter_map(|e| e.ok())
+            .map(|e| e.path())
+            .filter(|p| p.extension().map(|e| e == "toml").unwrap_or(false))
+            .filter_map(|p| p.file_stem().map(|s| s.to_string_lossy().to_string()))
+            .collect()
+    } else {
+        vec![]
+    };
+    names.sort();
+    names
+}
+
+pub fn save_filter_preset(name: &str, settings: &FilterSettings) -> Result<()> {
+    let path = filter_preset_path(name)?;
+    fs::create_dir_all(filter_presets_dir())?;
+    fs::write(&path, toml::to_string(&settings.without_gradient_points())?)?;
+    info!("Saved filter preset '{}' to {:?}", name, path);
+    Ok(())
+}
+
+pub fn load_filter_preset(name: &str) -> Result<FilterSettings> {
+    let t = fs::read_to_string(filter_preset_path(name)?)?;
+    Ok(toml::from_str(&t)?)
+}
+
+/// Path alongside `source` with `suffix` appended to the file stem, e.g. `sun.tif` with
+/// `_sharp` becomes `sun_sharp.tif`
+pub fn reexport_path(source: &Path, suffix: &str) -> PathBuf {
+    let stem = source
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_default();
+    source.with_file_name(format!("{}{}.tif", stem, suffix))
+}
+
+/// Applies `settings` to each of the previously stacked images in `sources`, saving each next to
+/// its source with `suffix` appended to the name. Returns the files written.
+pub async fn reexport_async(
+    sources: Vec<PathBuf>,
+    settings: FilterSettings,
+    suffix: String,
+) -> Result<Vec<PathBuf>> {
+    if suffix.trim().is_empty() {
+        return Err(anyhow!(
+            "A suffix is required so the original images aren't overwritten"
+        ));
+    }
+    let mut written = vec![];
+    for (n, source) in sources.iter().enumerate() {
+        check_cancel_status()?;
+        set_task_status(&t!("tasks.reexporting"), sources.len(), n);
+        let image = Image::open(&source.to_string_lossy())?;
+        let output = reexport_path(source, &suffix);
+        let filtered = settings.apply(&image);
+        partialfile::write_atomically(&output, |path| filtered.save(&path.to_string_lossy()))?;
+        info!("Re-exported {:?} to {:?}", source, output);
+        written.push(output);
+    }
+    set_task_status(&t!("tasks.reexporting"), sources.len(), sources.len());
+    Ok(written)
+}
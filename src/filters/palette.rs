@@ -0,0 +1,146 @@
+use std::fmt;
+
+use sciimg::prelude::Image;
+use serde::{Deserialize, Serialize};
+
+use crate::imageutil;
+
+/// Colors given to monochrome levels, from black to white, to suggest how the wavelength the
+/// image was taken at looks
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+pub enum Palette {
+    None,
+    HydrogenAlpha,
+    CalciumK,
+    Custom,
+}
+
+impl fmt::Display for Palette {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Palette::None => f.write_str(&t!("results.palette_none")),
+            Palette::HydrogenAlpha => f.write_str(&t!("results.palette_ha")),
+            Palette::CalciumK => f.write_str(&t!("results.palette_cak")),
+            Palette::Custom => f.write_str(&t!("results.palette_custom")),
+        }
+    }
+}
+
+impl Palette {
+    pub fn all() -> [Palette; 4] {
+        [
+            Palette::None,
+            Palette::HydrogenAlpha,
+            Palette::CalciumK,
+            Palette::Custom,
+        ]
+    }
+}
+
+/// A color at a level of the gradient, from 0.0 (black) to 1.0 (white)
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+pub struct ColorStop {
+    pub position: f32,
+    pub color: [u8; 3],
+}
+
+impl ColorStop {
+    const fn new(position: f32, color: [u8; 3]) -> Self {
+        ColorStop { position, color }
+    }
+}
+
+const HYDROGEN_ALPHA: [ColorStop; 5] = [
+    ColorStop::new(0.0, [0, 0, 0]),
+    ColorStop::new(0.35, [140, 20, 0]),
+    ColorStop::new(0.65, [240, 110, 10]),
+    ColorStop::new(0.88, [255, 210, 120]),
+    ColorStop::new(1.0, [255, 255, 235]),
+];
+
+const CALCIUM_K: [ColorStop; 5] = [
+    ColorStop::new(0.0, [0, 0, 0]),
+    ColorStop::new(0.35, [55, 0, 110]),
+    ColorStop::new(0.65, [140, 70, 230]),
+    ColorStop::new(0.88, [215, 190, 255]),
+    ColorStop::new(1.0, [250, 245, 255]),
+];
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+#[serde(default)]
+pub struct PaletteOptions {
+    pub palette: Palette,
+    /// Gradient of the custom palette. Stops may be in any order.
+    pub custom: Vec<ColorStop>,
+}
+
+impl Default for PaletteOptions {
+    fn default() -> Self {
+        PaletteOptions {
+            palette: Palette::None,
+            custom: HYDROGEN_ALPHA.to_vec(),
+        }
+    }
+}
+
+impl PaletteOptions {
+    /// Stops of the selected palette, ordered by position. Empty if no palette is selected.
+    pub fn stops(&self) -> Vec<ColorStop> {
+        let mut stops = match self.palette {
+            Palette::None => vec![],
+            Palette::HydrogenAlpha => HYDROGEN_ALPHA.to_vec(),
+            Palette::CalciumK => CALCIUM_K.to_vec(),
+            Palette::Custom => self.custom.clone(),
+        };
+        stops.sort_by(|a, b| a.position.total_cmp(&b.position));
+        stops
+    }
+}
+
+/// Color of `level`, from 0.0 to 1.0, interpolated between the surrounding `stops`. Each
+/// channel is returned in the range 0.0 to 1.0.
+fn color_at(stops: &[ColorStop], level: f32) -> [f32; 3] {
+    let channel = |stop: &ColorStop, c: usize| stop.color[c] as f32 / 255.0;
+    let upper = stops.iter().position(|s| s.position >= level);
+    match upper {
+        None => {
+            let last = &stops[stops.len() - 1];
+            [0, 1, 2].map(|c| channel(last, c))
+        }
+        Some(0) => [0, 1, 2].map(|c| channel(&stops[0], c)),
+        Some(i) => {
+            let (a, b) = (&stops[i - 1], &stops[i]);
+            let span = (b.position - a.position).max(f32::EPSILON);
+            let t = (level - a.position) / span;
+            [0, 1, 2].map(|c| channel(a, c) + (channel(b, c) - channel(a, c)) * t)
+        }
+    }
+}
+
+/// Colors the brightness of `image` with the selected palette, giving an RGB image with the same
+/// range of levels. Color images are colored by their luminance.
+pub fn colorize(image: &Image, options: &PaletteOptions) -> Image {
+    let stops = options.stops();
+    if stops.is_empty() {
+        return image.clone();
+    }
+    let luminance = imageutil::luminance_buffer(image);
+    let (min, max) = (0..image.height)
+        .flat_map(|y| (0..image.width).map(move |x| (x, y)))
+        .map(|(x, y)| luminance.get(x, y))
+        .fold((f32::MAX, f32::MIN), |(lo, hi), v| (lo.min(v), hi.max(v)));
+    let range = (max - min).max(f32::EPSILON);
+
+    let mut colored = Image::new_with_bands(image.width, image.height, 3, image.get_mode())
+        .expect("Failed to allocate colorized image");
+    (0..image.height).for_each(|y| {
+        (0..image.width).for_each(|x| {
+            let level = (luminance.get(x, y) - min) / range;
+            color_at(&stops, level)
+                .iter()
+                .enumerate()
+                .for_each(|(b, c)| colored.put(x, y, min + c * range, b));
+        });
+    });
+    colored
+}
@@ -0,0 +1,77 @@
+use std::fmt;
+
+use itertools::iproduct;
+use sciimg::prelude::Image;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+pub enum ToneMapping {
+    None,
+    Asinh,
+    Log,
+    Reinhard,
+}
+
+impl fmt::Display for ToneMapping {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ToneMapping::None => f.write_str(&t!("results.tonemap_none")),
+            ToneMapping::Asinh => f.write_str(&t!("results.tonemap_asinh")),
+            ToneMapping::Log => f.write_str(&t!("results.tonemap_log")),
+            ToneMapping::Reinhard => f.write_str(&t!("results.tonemap_reinhard")),
+        }
+    }
+}
+
+impl ToneMapping {
+    pub fn all() -> [ToneMapping; 4] {
+        [
+            ToneMapping::None,
+            ToneMapping::Asinh,
+            ToneMapping::Log,
+            ToneMapping::Reinhard,
+        ]
+    }
+
+    /// Maps `x`, in the range 0.0 to 1.0, onto the same range. Each curve passes through 0 and 1,
+    /// with `strength` setting how far faint levels are lifted relative to bright ones.
+    fn map(&self, x: f32, strength: f32) -> f32 {
+        let s = strength.max(f32::EPSILON);
+        match self {
+            ToneMapping::None => x,
+            ToneMapping::Asinh => (s * x).asinh() / s.asinh(),
+            ToneMapping::Log => (s * x).ln_1p() / s.ln_1p(),
+            ToneMapping::Reinhard => {
+                let scaled = s * x;
+                (scaled / (1.0 + scaled)) / (s / (1.0 + s))
+            }
+        }
+    }
+}
+
+/// Compresses the dynamic range of `image` so faint prominences and the bright disk can be shown
+/// together. All bands share the same curve, scaled to the brightest level of any band, which
+/// keeps colors from shifting.
+pub fn tone_map(image: &Image, mapping: ToneMapping, strength: f64) -> Image {
+    if mapping == ToneMapping::None {
+        return image.clone();
+    }
+    let max = (0..image.num_bands())
+        .flat_map(|b| {
+            let band = image.get_band(b);
+            iproduct!(0..image.height, 0..image.width).map(move |(y, x)| band.get(x, y))
+        })
+        .fold(0.0, f32::max);
+    if max <= 0.0 {
+        return image.clone();
+    }
+
+    let mut mapped = image.clone();
+    (0..image.num_bands()).for_each(|b| {
+        iproduct!(0..image.height, 0..image.width).for_each(|(y, x)| {
+            let v = image.get_band(b).get(x, y).max(0.0) / max;
+            mapped.put(x, y, mapping.map(v, strength as f32) * max, b);
+        });
+    });
+    mapped
+}
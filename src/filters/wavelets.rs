@@ -0,0 +1,147 @@
+//! Multi-scale wavelet sharpening, as in Registax. The image is split with the à trous wavelet
+//! transform into layers of detail at doubling scales, from single pixels up, and a smooth
+//! residual. Each layer is amplified by its own gain after small coefficients, most likely noise,
+//! are shrunk towards zero, so fine granulation can be brought out without boosting the noise at
+//! the finest scale.
+
+use sciimg::prelude::Image;
+use serde::{Deserialize, Serialize};
+
+use crate::filters::denoise::Plane;
+use crate::imageutil;
+
+/// Number of detail layers, at scales of 1, 2, 4, 8, 16 and 32 pixels
+pub const LAYER_COUNT: usize = 6;
+
+/// B3 spline smoothing kernel of the transform
+const KERNEL: [f32; 5] = [1.0 / 16.0, 4.0 / 16.0, 6.0 / 16.0, 4.0 / 16.0, 1.0 / 16.0];
+
+/// Gain and noise threshold of one detail layer
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+pub struct WaveletLayer {
+    /// Factor the layer's detail is multiplied by. 1 leaves it unchanged.
+    pub gain: f64,
+    /// Coefficients below this many standard deviations of the layer's noise are shrunk to zero
+    pub denoise: f64,
+}
+
+impl Default for WaveletLayer {
+    fn default() -> Self {
+        WaveletLayer {
+            gain: 1.0,
+            denoise: 0.0,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+#[serde(default)]
+pub struct WaveletOptions {
+    pub enabled: bool,
+    /// Finest scale first
+    pub layers: [WaveletLayer; LAYER_COUNT],
+}
+
+impl Default for WaveletOptions {
+    fn default() -> Self {
+        WaveletOptions {
+            enabled: false,
+            layers: [WaveletLayer::default(); LAYER_COUNT],
+        }
+    }
+}
+
+impl WaveletOptions {
+    /// True if applying the options would change the image
+    pub fn is_active(&self) -> bool {
+        self.enabled && self.layers.iter().any(|l| l.gain != 1.0 || l.denoise > 0.0)
+    }
+}
+
+/// One pass of the à trous transform: `plane` smoothed with the kernel dilated by `step`
+fn smooth(plane: &Plane, step: isize) -> Plane {
+    let horizontal = plane.map(|x, y| {
+        KERNEL
+            .iter()
+            .enumerate()
+            .map(|(i, k)| k * plane.get(x + (i as isize - 2) * step, y))
+            .sum()
+    });
+    horizontal.map(|x, y| {
+        KERNEL
+            .iter()
+            .enumerate()
+            .map(|(i, k)| k * horizontal.get(x, y + (i as isize - 2) * step))
+            .sum()
+    })
+}
+
+/// Robust estimate of the noise in a detail layer
+fn noise_sigma(detail: &[f32]) -> f32 {
+    let mut magnitudes: Vec<f32> = detail.iter().map(|d| d.abs()).collect();
+    imageutil::median(&mut magnitudes) * imageutil::MAD_TO_SIGMA as f32
+}
+
+/// `value` moved `threshold` towards zero, or zero if it's smaller
+fn soft_threshold(value: f32, threshold: f32) -> f32 {
+    value.signum() * (value.abs() - threshold).max(0.0)
+}
+
+/// Sharpens one band, returning its values in row-major order
+fn sharpen_plane(plane: Plane, layers: &[WaveletLayer]) -> Vec<f32> {
+    let mut result = vec![0.0; plane.values.len()];
+    let mut current = plane;
+    layers.iter().enumerate().for_each(|(scale, layer)| {
+        let smoothed = smooth(&current, 1 << scale);
+        let detail: Vec<f32> = current
+            .values
+            .iter()
+            .zip(smoothed.values.iter())
+            .map(|(c, s)| c - s)
+            .collect();
+        let threshold = if layer.denoise > 0.0 {
+            noise_sigma(&detail) * layer.denoise as f32
+        } else {
+            0.0
+        };
+        result
+            .iter_mut()
+            .zip(detail.iter())
+            .for_each(|(r, d)| *r += soft_threshold(*d, threshold) * layer.gain as f32);
+        current = smoothed;
+    });
+    // What remains after the last layer is the residual, kept as is
+    result
+        .iter_mut()
+        .zip(current.values.iter())
+        .for_each(|(r, c)| *r += c);
+    result
+}
+
+/// Applies the wavelet layer gains and noise thresholds to each band of `image`. Values are
+/// clipped to the band's original range.
+pub fn sharpen(image: &Image, options: &WaveletOptions) -> Image {
+    if !options.is_active() {
+        return image.clone();
+    }
+    let mut sharpened = image.clone();
+    (0..image.num_bands()).for_each(|b| {
+        let plane = Plane::from_band(image, b);
+        let (min, max) = plane
+            .values
+            .iter()
+            .fold((f32::MAX, f32::MIN), |(lo, hi), v| (lo.min(*v), hi.max(*v)));
+        let (width, height) = (plane.width, plane.height);
+        let values = sharpen_plane(plane, &options.layers)
+            .into_iter()
+            .map(|v| v.clamp(min, max))
+            .collect();
+        Plane {
+            width,
+            height,
+            values,
+        }
+        .put_band(&mut sharpened, b);
+    });
+    sharpened
+}
@@ -0,0 +1,269 @@
+//! Minimal reader for the primary image of FITS files, enough to load calibration masters made
+//! with other tools, and a writer for saving stacked output as FITS with its observation details
+//! in the header.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use anyhow::{anyhow, Result};
+use itertools::iproduct;
+use sciimg::prelude::{Image, ImageMode};
+
+/// FITS files are made of blocks of this many bytes
+const BLOCK_SIZE: usize = 2880;
+
+/// Length of each header card
+const CARD_SIZE: usize = 80;
+
+/// Extensions of FITS files
+pub const FITS_EXTENSIONS: &[&str] = &["fits", "fit", "fts"];
+
+pub fn is_fits_file(path: &Path) -> bool {
+    path.extension()
+        .map(|e| {
+            FITS_EXTENSIONS
+                .iter()
+                .any(|ext| e.to_string_lossy().eq_ignore_ascii_case(ext))
+        })
+        .unwrap_or(false)
+}
+
+/// Parses the header cards up to `END` into keyword/value pairs, with string values unquoted and
+/// comments removed. Returns the header and the offset of the data following it.
+fn parse_header(bytes: &[u8]) -> Result<(HashMap<String, String>, usize)> {
+    let mut header = HashMap::new();
+    for (n, card) in bytes.chunks(CARD_SIZE).enumerate() {
+        let card = String::from_utf8_lossy(card);
+        let keyword = card.get(..8).unwrap_or(&card).trim().to_owned();
+        if keyword == "END" {
+            let header_size = (n + 1) * CARD_SIZE;
+            return Ok((header, header_size.div_ceil(BLOCK_SIZE) * BLOCK_SIZE));
+        }
+        if card.get(8..10) != Some("= ") {
+            continue;
+        }
+        let value = &card[10..];
+        let value = if let Some(quoted) = value.trim_start().strip_prefix('\'') {
+            quoted.split('\'').next().unwrap_or_default().trim_end()
+        } else {
+            value.split('/').next().unwrap_or_default().trim()
+        };
+        header.insert(keyword, value.to_owned());
+    }
+    Err(anyhow!("FITS header has no END card"))
+}
+
+fn header_number<T: std::str::FromStr>(header: &HashMap<String, String>, key: &str) -> Option<T> {
+    header.get(key).and_then(|v| v.parse().ok())
+}
+
+/// Reads the primary image of a FITS file. Pixel values are scaled to the 16-bit range the rest
+/// of processing works in: floating point data normalized to 0-1 is multiplied out to 0-65535.
+/// Rows are flipped from the FITS bottom-up order to the top-down order of SER frames unless
+/// the header says they're already top-down.
+pub fn read_fits(path: &Path) -> Result<Image> {
+    parse_fits(&fs::read(path)?)
+        .map_err(|why| anyhow!("Failed to read {}: {}", path.display(), why))
+}
+
+/// Reads the primary image of FITS data held in memory, as with `read_fits`
+pub fn parse_fits(bytes: &[u8]) -> Result<Image> {
+    let (header, data_offset) = parse_header(bytes)?;
+    if header.get("SIMPLE").map(|v| v.as_str()) != Some("T") {
+        return Err(anyhow!("Not a standard FITS file"));
+    }
+
+    let bitpix: i32 =
+        header_number(&header, "BITPIX").ok_or_else(|| anyhow!("FITS header has no BITPIX"))?;
+    let naxis: usize =
+        header_number(&header, "NAXIS").ok_or_else(|| anyhow!("FITS header has no NAXIS"))?;
+    if ![8, 16, 32, -32, -64].contains(&bitpix) {
+        return Err(anyhow!("Unsupported FITS BITPIX {}", bitpix));
+    }
+    if !(2..=3).contains(&naxis) {
+        return Err(anyhow!("FITS image has {} axes, expected 2 or 3", naxis));
+    }
+    let width: usize = header_number(&header, "NAXIS1").unwrap_or(0);
+    let height: usize = header_number(&header, "NAXIS2").unwrap_or(0);
+    let num_bands: usize = if naxis == 3 {
+        header_number(&header, "NAXIS3").unwrap_or(1)
+    } else {
+        1
+    };
+    if width == 0 || height == 0 || !(num_bands == 1 || num_bands == 3) {
+        return Err(anyhow!(
+            "Unsupported FITS image size {}x{}x{}",
+            width,
+            height,
+            num_bands
+        ));
+    }
+    let bzero: f64 = header_number(&header, "BZERO").unwrap_or(0.0);
+    let bscale: f64 = header_number(&header, "BSCALE").unwrap_or(1.0);
+    let top_down = header.get("ROWORDER").map(|v| v.as_str()) == Some("TOP-DOWN");
+
+    let bytes_per_value = (bitpix.unsigned_abs() / 8) as usize;
+    let num_values = width * height * num_bands;
+    let data = bytes
+        .get(data_offset..data_offset + num_values * bytes_per_value)
+        .ok_or_else(|| anyhow!("FITS file is shorter than its header says"))?;
+
+    let values: Vec<f64> = data
+        .chunks_exact(bytes_per_value)
+        .map(|b| match bitpix {
+            8 => b[0] as f64,
+            16 => i16::from_be_bytes([b[0], b[1]]) as f64,
+            32 => i32::from_be_bytes([b[0], b[1], b[2], b[3]]) as f64,
+            -32 => f32::from_be_bytes([b[0], b[1], b[2], b[3]]) as f64,
+            _ => f64::from_be_bytes([b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]]),
+        })
+        .map(|v| bzero + bscale * v)
+        .collect();
+
+    let scale = match bitpix {
+        8 => 257.0,
+        -32 | -64 if values.iter().cloned().fold(0.0, f64::max) <= 1.0 => 65535.0,
+        _ => 1.0,
+    };
+
+    let mut image = Image::new_with_bands(width, height, num_bands, ImageMode::U16BIT)?;
+    iproduct!(0..num_bands, 0..height, 0..width).for_each(|(b, y, x)| {
+        let row = if top_down { y } else { height - 1 - y };
+        let value = values[(b * height + row) * width + x] * scale;
+        let value = if value.is_nan() { 0.0 } else { value };
+        image.put(x, y, value.clamp(0.0, 65535.0) as f32, b);
+    });
+    Ok(image)
+}
+
+/// Value of a header card written by `write_fits`
+#[derive(Debug, Clone, PartialEq)]
+pub enum FitsValue {
+    Text(String),
+    Integer(i64),
+    Real(f64),
+    Logical(bool),
+}
+
+impl FitsValue {
+    /// The value as written in a card, strings quoted and numbers right aligned to column 30
+    fn format(&self) -> String {
+        match self {
+            FitsValue::Text(text) => {
+                let text: String = text
+                    .chars()
+                    .take(68)
+                    .collect::<String>()
+                    .replace('\'', "''");
+                format!("'{:<8}'", text)
+            }
+            FitsValue::Integer(value) => format!("{:>20}", value),
+            FitsValue::Real(value) => format!("{:>20}", format!("{:.6}", value)),
+            FitsValue::Logical(value) => format!("{:>20}", if *value { "T" } else { "F" }),
+        }
+    }
+}
+
+/// A header keyword written to saved FITS files, in addition to those describing the data
+#[derive(Debug, Clone, PartialEq)]
+pub struct FitsKeyword {
+    pub keyword: &'static str,
+    pub value: FitsValue,
+    pub comment: &'static str,
+}
+
+impl FitsKeyword {
+    pub fn new(keyword: &'static str, value: FitsValue, comment: &'static str) -> Self {
+        FitsKeyword {
+            keyword,
+            value,
+            comment,
+        }
+    }
+}
+
+/// A header card padded to 80 characters. Characters outside printable ASCII, which FITS headers
+/// can't hold, are replaced.
+fn card(keyword: &str, value: &FitsValue, comment: &str) -> String {
+    let mut card = format!("{:<8}= {}", keyword, value.format());
+    if !comment.is_empty() {
+        card.push_str(" / ");
+        card.push_str(comment);
+    }
+    let card: String = card
+        .chars()
+        .map(|c| {
+            if c == ' ' || c.is_ascii_graphic() {
+                c
+            } else {
+                '?'
+            }
+        })
+        .collect();
+    format!("{:<80.80}", card)
+}
+
+/// Saves `image` as a FITS file with `keywords` added to its header. 8-bit images are written as
+/// bytes, all others as unsigned 16-bit integers. Rows are written bottom-up, as FITS viewers
+/// expect, and color images as three planes.
+pub fn write_fits(image: &Image, path: &Path, keywords: &[FitsKeyword]) -> Result<()> {
+    let num_bands = image.num_bands();
+    if num_bands != 1 && num_bands != 3 {
+        return Err(anyhow!(
+            "Can't write a FITS file of an image with {} bands",
+            num_bands
+        ));
+    }
+    let eight_bit = image.get_mode() == ImageMode::U8BIT;
+
+    let mut cards = vec![
+        card("SIMPLE", &FitsValue::Logical(true), ""),
+        card(
+            "BITPIX",
+            &FitsValue::Integer(if eight_bit { 8 } else { 16 }),
+            "",
+        ),
+        card(
+            "NAXIS",
+            &FitsValue::Integer(if num_bands == 1 { 2 } else { 3 }),
+            "",
+        ),
+        card("NAXIS1", &FitsValue::Integer(image.width as i64), ""),
+        card("NAXIS2", &FitsValue::Integer(image.height as i64), ""),
+    ];
+    if num_bands > 1 {
+        cards.push(card("NAXIS3", &FitsValue::Integer(num_bands as i64), ""));
+    }
+    if !eight_bit {
+        cards.push(card(
+            "BZERO",
+            &FitsValue::Integer(32768),
+            "Data are unsigned 16-bit integers",
+        ));
+        cards.push(card("BSCALE", &FitsValue::Integer(1), ""));
+    }
+    cards.extend(
+        keywords
+            .iter()
+            .map(|k| card(k.keyword, &k.value, k.comment)),
+    );
+    cards.push(format!("{:<80}", "END"));
+
+    let mut file: Vec<u8> = cards.concat().into_bytes();
+    file.resize(file.len().div_ceil(BLOCK_SIZE) * BLOCK_SIZE, b' ');
+
+    iproduct!(0..num_bands, (0..image.height).rev(), 0..image.width).for_each(|(b, y, x)| {
+        let value = image.get_band(b).get(x, y).round();
+        if eight_bit {
+            file.push(value.clamp(0.0, 255.0) as u8);
+        } else {
+            let value = (value.clamp(0.0, 65535.0) as i32 - 32768) as i16;
+            file.extend_from_slice(&value.to_be_bytes());
+        }
+    });
+    file.resize(file.len().div_ceil(BLOCK_SIZE) * BLOCK_SIZE, 0);
+
+    fs::write(path, file)?;
+    Ok(())
+}
@@ -0,0 +1,48 @@
+//! Thread running the gtk main loop, which winit doesn't provide. The tray icon and the recent
+//! documents list both need it. It's started the first time it's used and kept for the life of
+//! the application as gtk can only be initialized once.
+
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+
+/// How often the gtk thread picks up work sent to it
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+type GtkTask = Box<dyn FnOnce() + Send>;
+
+lazy_static! {
+    static ref GTK_THREAD: Arc<Mutex<Option<Sender<GtkTask>>>> = Arc::new(Mutex::new(None));
+}
+
+fn run_gtk_thread(receiver: Receiver<GtkTask>) {
+    if let Err(why) = gtk::init() {
+        error!("Failed to initialize gtk: {}", why);
+        return;
+    }
+    gtk::glib::timeout_add_local(POLL_INTERVAL, move || {
+        while let Ok(task) = receiver.try_recv() {
+            task();
+        }
+        gtk::glib::ControlFlow::Continue
+    });
+    gtk::main();
+}
+
+/// Runs `task` on the gtk thread, starting it if needed
+pub fn run_on_gtk_thread<F>(task: F) -> Result<()>
+where
+    F: FnOnce() + Send + 'static,
+{
+    let mut gtk_thread = GTK_THREAD.lock().unwrap();
+    let sender = gtk_thread.get_or_insert_with(|| {
+        let (sender, receiver) = mpsc::channel();
+        std::thread::spawn(move || run_gtk_thread(receiver));
+        sender
+    });
+    sender
+        .send(Box::new(task))
+        .map_err(|_| anyhow!("The gtk thread has exited"))
+}
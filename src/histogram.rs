@@ -4,6 +4,9 @@ use epaint::Color32;
 use itertools::iproduct;
 use sciimg::prelude::Image;
 
+/// Most bins shown, so deep data isn't drawn with more bins than the plot has pixels
+const MAX_BINS: usize = 1024;
+
 #[derive(Default, Debug, Copy, Clone)]
 struct Bin {
     count: u32,
@@ -16,6 +19,22 @@ pub struct Histogram {
     max_value: f32,
     bins: Vec<Bin>,
     pub logarithmic: bool,
+    /// Range of the data's pixel depth, used unless a manual range is set
+    default_range: [f32; 2],
+    manual_range: Option<[f32; 2]>,
+    /// Values of the last image below and above the range
+    underflow: usize,
+    overflow: usize,
+    /// Values of the last image which are NaN or infinite
+    invalid: usize,
+}
+
+/// Where a value falls relative to the bins
+enum Binned {
+    Bin(usize),
+    Underflow,
+    Overflow,
+    Invalid,
 }
 
 impl Histogram {
@@ -26,37 +45,94 @@ impl Histogram {
             max_value,
             logarithmic: false,
             bins: (0..num_bins).map(|_| Bin::default()).collect(),
+            default_range: [min_value, max_value],
+            manual_range: None,
+            underflow: 0,
+            overflow: 0,
+            invalid: 0,
         }
     }
 
+    /// A histogram over the values of data `pixel_depth` bits deep, with a bin per value up to
+    /// `MAX_BINS`
+    pub fn for_pixel_depth(pixel_depth: usize) -> Self {
+        let max_value = 2_f32.powi(pixel_depth.clamp(1, 16) as i32);
+        Histogram::new((max_value as usize).min(MAX_BINS), 0.0, max_value)
+    }
+
+    /// Changes the range to that of data `pixel_depth` bits deep, unless a manual range is set.
+    /// The bins are emptied.
+    pub fn set_pixel_depth(&mut self, pixel_depth: usize) {
+        let depth_histogram = Histogram::for_pixel_depth(pixel_depth);
+        self.default_range = depth_histogram.default_range;
+        if self.manual_range.is_none() {
+            self.num_bins = depth_histogram.num_bins;
+            self.min_value = depth_histogram.min_value;
+            self.max_value = depth_histogram.max_value;
+        }
+        self.reset();
+    }
+
+    fn apply_range(&mut self) {
+        let [min_value, max_value] = self.manual_range.unwrap_or(self.default_range);
+        self.min_value = min_value;
+        self.max_value = max_value.max(min_value + 1.0);
+        self.num_bins = ((self.max_value - self.min_value).ceil() as usize).clamp(1, MAX_BINS);
+        self.reset();
+    }
+
     pub fn reset(&mut self) {
         self.bins = (0..self.num_bins).map(|_| Bin::default()).collect();
+        self.underflow = 0;
+        self.overflow = 0;
+        self.invalid = 0;
+    }
+
+    /// Bins `v`. The maximum itself goes in the last bin, so data filling the full range of its
+    /// pixel depth isn't counted as overflow.
+    fn value_to_bin(&self, v: f32) -> Binned {
+        if !v.is_finite() {
+            Binned::Invalid
+        } else if v < self.min_value {
+            Binned::Underflow
+        } else if v > self.max_value {
+            Binned::Overflow
+        } else {
+            let bin = (self.num_bins as f32
+                * ((v - self.min_value) / (self.max_value - self.min_value)))
+                .floor() as usize;
+            Binned::Bin(bin.min(self.num_bins - 1))
+        }
     }
 
-    fn value_to_bin(&self, v: f32) -> usize {
-        (self.num_bins as f32 * ((v - self.min_value) / (self.max_value - self.min_value))).floor()
-            as usize
+    /// Value at the middle of bin `bin_no`
+    fn bin_value(&self, bin_no: usize) -> f64 {
+        let bin_width = (self.max_value - self.min_value) as f64 / self.num_bins as f64;
+        self.min_value as f64 + (bin_no as f64 + 0.5) * bin_width
     }
 
     pub fn compute_from_image(&mut self, img: &Image) {
         iproduct!(0..img.height, 0..img.width).for_each(|(y, x)| {
             let v = img.get_band(0).get(x, y);
-            let bin_no = self.value_to_bin(v);
-            self.bins[bin_no].count += 1;
+            match self.value_to_bin(v) {
+                Binned::Bin(bin_no) => self.bins[bin_no].count += 1,
+                Binned::Underflow => self.underflow += 1,
+                Binned::Overflow => self.overflow += 1,
+                Binned::Invalid => self.invalid += 1,
+            }
         });
     }
 
     pub fn to_line(&self) -> Line {
         let points: PlotPoints = self
             .bins
-            .clone()
-            .into_iter()
+            .iter()
             .enumerate()
             .map(|(i, b)| {
                 if self.logarithmic && b.count > 0 {
-                    [i as f64, (b.count as f64).log10()]
+                    [self.bin_value(i), (b.count as f64).log10()]
                 } else {
-                    [i as f64, b.count as f64]
+                    [self.bin_value(i), b.count as f64]
                 }
             })
             .collect();
@@ -68,28 +144,71 @@ impl Histogram {
             .width(2.0)
     }
 
-    pub fn ui(&mut self, ui: &mut Ui) {
+    /// Shows the histogram. Returns true if its range was changed, in which case it's been emptied
+    /// and the image needs to be binned again.
+    pub fn ui(&mut self, ui: &mut Ui) -> bool {
+        let mut range_changed = false;
         let plot = Plot::new("histogram")
             .legend(Legend::default())
             .y_axis_width(4)
-            .show_axes(false)
+            .show_axes([true, false])
             .auto_bounds(egui::Vec2b::new(true, true))
             .allow_scroll(false)
             .allow_boxed_zoom(false)
             .allow_drag(false)
             .allow_zoom(false)
             .show_grid(true);
-        plot.show(ui, |plot_ui| {
-            plot_ui.line(self.to_line());
-        })
-        .response
-        .context_menu(|ui| {
+        let response = plot
+            .show(ui, |plot_ui| {
+                plot_ui.line(self.to_line());
+            })
+            .response;
+        let response = if self.underflow > 0 || self.overflow > 0 {
+            response.on_hover_text(t!(
+                "histogram.out_of_range",
+                below = self.underflow,
+                above = self.overflow
+            ))
+        } else {
+            response
+        };
+        if self.invalid > 0 {
+            ui.colored_label(
+                ui.visuals().warn_fg_color,
+                t!("histogram.invalid", count = self.invalid),
+            );
+        }
+        response.context_menu(|ui| {
             if ui
                 .checkbox(&mut self.logarithmic, t!("histogram.logarithmic"))
                 .clicked()
             {
                 ui.close_menu();
             }
+
+            let mut manual = self.manual_range.is_some();
+            if ui
+                .checkbox(&mut manual, t!("histogram.manual_range"))
+                .changed()
+            {
+                self.manual_range = manual.then_some([self.min_value, self.max_value]);
+                range_changed = true;
+            }
+            if let Some([min_value, max_value]) = &mut self.manual_range {
+                ui.horizontal(|ui| {
+                    range_changed |= ui
+                        .add(egui::DragValue::new(min_value).clamp_range(0.0..=65535.0))
+                        .changed();
+                    ui.label("–");
+                    range_changed |= ui
+                        .add(egui::DragValue::new(max_value).clamp_range(1.0..=65536.0))
+                        .changed();
+                });
+            }
         });
+        if range_changed {
+            self.apply_range();
+        }
+        range_changed
     }
 }
@@ -0,0 +1,121 @@
+use std::fs;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::resources::ResourceUsage;
+use crate::state::{ApplicationState, WindowState};
+
+/// Number of runs kept in the history. The oldest are dropped first.
+const MAX_HISTORY_ENTRIES: usize = 200;
+
+lazy_static! {
+    // Serializes updates to the history file, which jobs running at the same time all append to
+    static ref HISTORY_LOCK: Mutex<()> = Mutex::new(());
+}
+
+// Incremented whenever the history file changes so views know to reload it
+static REVISION: AtomicUsize = AtomicUsize::new(0);
+
+/// A completed processing run and the exact options it was run with
+#[derive(Deserialize, Serialize, Clone)]
+pub struct HistoryEntry {
+    /// Local time the run completed, RFC 3339 formatted
+    pub completed: String,
+    pub output_filename: PathBuf,
+    pub frames_used: usize,
+    pub duration_secs: f64,
+    #[serde(default)]
+    pub resources: ResourceUsage,
+    pub state: ApplicationState,
+}
+
+impl HistoryEntry {
+    /// File name of the light input
+    pub fn light_name(&self) -> String {
+        self.state
+            .light
+            .as_ref()
+            .and_then(|l| {
+                PathBuf::from(l)
+                    .file_name()
+                    .map(|f| f.to_string_lossy().to_string())
+            })
+            .unwrap_or_default()
+    }
+}
+
+#[derive(Default, Deserialize, Serialize)]
+#[serde(default)]
+struct HistoryFile {
+    entries: Vec<HistoryEntry>,
+}
+
+fn history_path() -> PathBuf {
+    dirs::home_dir().unwrap().join(".solhat/history.toml")
+}
+
+fn load_file() -> HistoryFile {
+    fs::read_to_string(history_path())
+        .ok()
+        .and_then(|t| toml::from_str(&t).ok())
+        .unwrap_or_default()
+}
+
+fn save_file(history: &HistoryFile) -> Result<()> {
+    let path = history_path();
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, toml::to_string(history)?)?;
+    REVISION.fetch_add(1, Ordering::Relaxed);
+    Ok(())
+}
+
+/// All recorded runs, most recent first
+pub fn entries() -> Vec<HistoryEntry> {
+    let _lock = HISTORY_LOCK.lock().unwrap();
+    load_file().entries
+}
+
+/// Changes each time a run is recorded or the history is cleared
+pub fn revision() -> usize {
+    REVISION.load(Ordering::Relaxed)
+}
+
+/// Records a successful run of `state`
+pub fn record(
+    state: &ApplicationState,
+    output_filename: PathBuf,
+    frames_used: usize,
+    duration: Duration,
+    resources: ResourceUsage,
+) -> Result<()> {
+    let mut state = state.clone();
+    state.window = WindowState::default();
+
+    let _lock = HISTORY_LOCK.lock().unwrap();
+    let mut history = load_file();
+    history.entries.insert(
+        0,
+        HistoryEntry {
+            completed: chrono::Local::now().to_rfc3339(),
+            output_filename,
+            frames_used,
+            duration_secs: duration.as_secs_f64(),
+            resources,
+            state,
+        },
+    );
+    history.entries.truncate(MAX_HISTORY_ENTRIES);
+    save_file(&history)
+}
+
+pub fn clear() -> Result<()> {
+    let _lock = HISTORY_LOCK.lock().unwrap();
+    save_file(&HistoryFile::default())
+}
@@ -0,0 +1,93 @@
+use egui::Ui;
+
+use crate::history::{self, HistoryEntry};
+
+/// Lists past processing runs, with a button to restore each run's options
+#[derive(Default)]
+pub struct HistoryViewPane {
+    entries: Vec<HistoryEntry>,
+    loaded_revision: Option<usize>,
+    /// Start processing as soon as a run's options are restored
+    pub start_on_rerun: bool,
+}
+
+impl HistoryViewPane {
+    /// Reloads the history if it has changed since it was last read
+    pub fn refresh(&mut self) {
+        let revision = history::revision();
+        if self.loaded_revision != Some(revision) {
+            self.entries = history::entries();
+            self.loaded_revision = Some(revision);
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Returns the entry to re-run, if the user asked for one
+    pub fn ui(&mut self, ui: &mut Ui, task_running: bool) -> Option<HistoryEntry> {
+        let mut rerun = None;
+
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut self.start_on_rerun, t!("history.start_on_rerun"));
+            ui.separator();
+            if ui.button(t!("history.clear")).clicked() {
+                if let Err(why) = history::clear() {
+                    error!("Failed to clear history: {}", why);
+                }
+            }
+        });
+        ui.separator();
+
+        egui::ScrollArea::vertical().show(ui, |ui| {
+            egui::Grid::new("run_history")
+                .num_columns(9)
+                .spacing([30.0, 4.0])
+                .striped(true)
+                .show(ui, |ui| {
+                    ui.strong(t!("history.completed"));
+                    ui.strong(t!("history.light"));
+                    ui.strong(t!("history.output"));
+                    ui.strong(t!("history.frames_used"));
+                    ui.strong(t!("history.duration"));
+                    ui.strong(t!("history.peak_memory"));
+                    ui.strong(t!("history.cpu_time"));
+                    ui.strong(t!("history.bytes_read"));
+                    ui.end_row();
+
+                    self.entries.iter().for_each(|entry| {
+                        ui.label(
+                            chrono::DateTime::parse_from_rfc3339(&entry.completed)
+                                .map(|t| t.format("%Y-%m-%d %H:%M:%S").to_string())
+                                .unwrap_or_else(|_| entry.completed.clone()),
+                        );
+                        ui.label(entry.light_name());
+                        ui.monospace(
+                            entry
+                                .output_filename
+                                .file_name()
+                                .map(|f| f.to_string_lossy().to_string())
+                                .unwrap_or_default(),
+                        )
+                        .on_hover_text(entry.output_filename.to_string_lossy());
+                        ui.label(entry.frames_used.to_string());
+                        ui.label(format!("{:.1} s", entry.duration_secs));
+                        ui.label(entry.resources.peak_memory_text());
+                        ui.label(entry.resources.cpu_time_text());
+                        ui.label(entry.resources.bytes_read_text());
+                        if ui
+                            .add_enabled(!task_running, egui::Button::new(t!("history.rerun")))
+                            .on_hover_text(t!("history.rerun_hint"))
+                            .clicked()
+                        {
+                            rerun = Some(entry.clone());
+                        }
+                        ui.end_row();
+                    });
+                });
+        });
+
+        rerun
+    }
+}
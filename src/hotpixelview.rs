@@ -0,0 +1,163 @@
+use std::path::PathBuf;
+
+use egui::{Color32, Context, TextureHandle};
+use sciimg::prelude::Image;
+
+use crate::imageutil;
+use crate::process::hotpixels::{self, DarkLevel, HotPixelMap};
+
+/// Share of the sensor above which the detected pixels are more likely a threshold set too low
+/// than hot pixels
+const SUSPICIOUS_FRACTION: f64 = 0.01;
+
+/// Most hot pixels marked over the preview, to keep drawing quick when the threshold is too low
+const MAX_MARKERS: usize = 5000;
+
+/// Generates a hot pixel map from a master dark, showing the pixels found over it as the
+/// threshold is adjusted
+pub struct HotPixelWindow {
+    pub open: bool,
+    threshold: f32,
+    master_dark: Option<Image>,
+    level: Option<DarkLevel>,
+    texture: Option<TextureHandle>,
+    hot_pixels: Vec<[usize; 2]>,
+    texture_name: String,
+}
+
+impl Default for HotPixelWindow {
+    fn default() -> Self {
+        Self {
+            open: false,
+            threshold: 8.0,
+            master_dark: None,
+            level: None,
+            texture: None,
+            hot_pixels: vec![],
+            texture_name: imageutil::gen_random_texture_name(),
+        }
+    }
+}
+
+impl HotPixelWindow {
+    /// Clears the previous master dark while a new one is computed
+    pub fn start(&mut self) {
+        self.open = true;
+        self.master_dark = None;
+        self.level = None;
+        self.texture = None;
+        self.hot_pixels.clear();
+    }
+
+    pub fn set_master_dark(&mut self, ctx: &Context, master_dark: Image) {
+        let level = hotpixels::dark_level(&master_dark);
+        info!(
+            "Master dark level {} with sigma {}",
+            level.median, level.sigma
+        );
+        self.texture = Some(ctx.load_texture(
+            &self.texture_name,
+            imageutil::sciimg_to_color_image(&master_dark),
+            Default::default(),
+        ));
+        self.level = Some(level);
+        self.master_dark = Some(master_dark);
+        self.detect();
+    }
+
+    fn detect(&mut self) {
+        if let (Some(master_dark), Some(level)) = (&self.master_dark, self.level) {
+            self.hot_pixels = hotpixels::detect_hot_pixels(master_dark, level, self.threshold);
+        }
+    }
+
+    /// Shows the window. Returns the path the map was saved to when it's saved.
+    pub fn ui(&mut self, ctx: &Context, default_folder: PathBuf) -> Option<PathBuf> {
+        let mut saved = None;
+        let mut open = self.open;
+        egui::Window::new(t!("hotpixels.title"))
+            .open(&mut open)
+            .default_size([640.0, 560.0])
+            .show(ctx, |ui| {
+                let Some(master_dark) = &self.master_dark else {
+                    ui.horizontal(|ui| {
+                        ui.spinner();
+                        ui.label(t!("hotpixels.computing"));
+                    });
+                    return;
+                };
+                let (width, height) = (master_dark.width, master_dark.height);
+
+                ui.horizontal(|ui| {
+                    ui.label(t!("hotpixels.threshold"));
+                    if ui
+                        .add(
+                            egui::Slider::new(&mut self.threshold, 2.0..=50.0)
+                                .logarithmic(true)
+                                .suffix(" σ"),
+                        )
+                        .changed()
+                    {
+                        self.detect();
+                    }
+                });
+                ui.label(t!("hotpixels.found", count = self.hot_pixels.len()));
+                if self.hot_pixels.len() as f64 > (width * height) as f64 * SUSPICIOUS_FRACTION {
+                    ui.colored_label(ui.visuals().warn_fg_color, t!("hotpixels.too_many"));
+                }
+
+                ui.horizontal(|ui| {
+                    if ui
+                        .add_enabled(
+                            !self.hot_pixels.is_empty(),
+                            egui::Button::new(t!("hotpixels.save")),
+                        )
+                        .clicked()
+                    {
+                        if let Some(path) = rfd::FileDialog::new()
+                            .set_title(t!("hotpixels.save"))
+                            .set_directory(&default_folder)
+                            .set_file_name("hotpixels.toml")
+                            .add_filter("TOML", &["toml"])
+                            .save_file()
+                        {
+                            let map = HotPixelMap {
+                                sensor_width: width,
+                                sensor_height: height,
+                                hotpixels: self.hot_pixels.clone(),
+                            };
+                            match map.save(&path) {
+                                Ok(_) => {
+                                    info!("Saved {} hot pixels to {:?}", map.hotpixels.len(), path);
+                                    saved = Some(path);
+                                }
+                                Err(why) => error!("Failed to save hot pixel map: {}", why),
+                            }
+                        }
+                    }
+                });
+                ui.separator();
+
+                if let Some(texture) = &self.texture {
+                    egui::ScrollArea::both().show(ui, |ui| {
+                        let rect = ui
+                            .add(egui::Image::from_texture(texture).shrink_to_fit())
+                            .rect;
+                        let painter = ui.painter_at(rect);
+                        self.hot_pixels.iter().take(MAX_MARKERS).for_each(|[x, y]| {
+                            let pos = egui::pos2(
+                                rect.left() + (*x as f32 + 0.5) / width as f32 * rect.width(),
+                                rect.top() + (*y as f32 + 0.5) / height as f32 * rect.height(),
+                            );
+                            painter.circle_stroke(pos, 4.0, egui::Stroke::new(1.0, Color32::RED));
+                        });
+                    });
+                }
+            });
+        self.open = open;
+        if saved.is_some() {
+            self.open = false;
+        }
+        saved
+    }
+}
@@ -1,7 +1,11 @@
+use std::cmp::Ordering;
+
 use egui::ColorImage;
 use itertools::iproduct;
 use rand::{distributions::Alphanumeric, Rng};
-use sciimg::prelude::Image;
+use sciimg::prelude::{Image, ImageBuffer};
+
+use crate::state::FrameOrientation;
 
 pub fn sciimg_to_color_image(ser_frame: &Image) -> ColorImage {
     let mut copied = ser_frame.clone();
@@ -29,6 +33,126 @@ pub fn sciimg_to_color_image(ser_frame: &Image) -> ColorImage {
     ColorImage::from_rgb(size, &rgb)
 }
 
+/// Renders per-pixel weights in the range 0.0 to 1.0 as a grayscale image
+pub fn weights_to_color_image(width: usize, height: usize, weights: &[f32]) -> ColorImage {
+    let gray: Vec<u8> = weights
+        .iter()
+        .map(|w| (w.clamp(0.0, 1.0) * 255.0) as u8)
+        .collect();
+    ColorImage::from_gray([width, height], &gray)
+}
+
+/// Averages `factor` x `factor` blocks of pixels into a reduced resolution buffer. Partial blocks
+/// along the right and bottom edges are dropped.
+pub fn bin_buffer(buffer: &ImageBuffer, factor: usize) -> ImageBuffer {
+    bin_region(
+        buffer,
+        factor,
+        0,
+        0,
+        buffer.width / factor,
+        buffer.height / factor,
+    )
+}
+
+/// As [`bin_buffer`], binning only the `width` x `height` region whose top left corner is at
+/// `x0`, `y0`. The region is given in binned pixels and must lie within the binned buffer.
+pub fn bin_region(
+    buffer: &ImageBuffer,
+    factor: usize,
+    x0: usize,
+    y0: usize,
+    width: usize,
+    height: usize,
+) -> ImageBuffer {
+    let mut binned = ImageBuffer::new(width, height).expect("Failed to allocate binned buffer");
+    iproduct!(0..height, 0..width).for_each(|(y, x)| {
+        let sum: f32 = iproduct!(0..factor, 0..factor)
+            .map(|(by, bx)| buffer.get((x0 + x) * factor + bx, (y0 + y) * factor + by))
+            .sum();
+        binned.put(x, y, sum / (factor * factor) as f32);
+    });
+    binned
+}
+
+/// Reduces the resolution of every band of `image` by averaging `factor` x `factor` blocks
+pub fn downscale_image(image: &Image, factor: usize) -> Image {
+    if factor <= 1 {
+        return image.clone();
+    }
+    let mut downscaled = Image::new_with_bands(
+        image.width / factor,
+        image.height / factor,
+        image.num_bands(),
+        image.get_mode(),
+    )
+    .expect("Failed to allocate downscaled image");
+    (0..image.num_bands()).for_each(|b| {
+        let binned = bin_buffer(image.get_band(b), factor);
+        iproduct!(0..binned.height, 0..binned.width).for_each(|(y, x)| {
+            downscaled.put(x, y, binned.get(x, y), b);
+        });
+    });
+    downscaled
+}
+
+/// Computes a Rec. 709 luminance buffer from an RGB image. Monochrome images return a copy of
+/// their only band.
+pub fn luminance_buffer(image: &Image) -> ImageBuffer {
+    if image.num_bands() < 3 {
+        return image.get_band(0).clone();
+    }
+    let mut luminance =
+        ImageBuffer::new(image.width, image.height).expect("Failed to allocate luminance buffer");
+    iproduct!(0..image.height, 0..image.width).for_each(|(y, x)| {
+        luminance.put(
+            x,
+            y,
+            0.2126 * image.get_band(0).get(x, y)
+                + 0.7152 * image.get_band(1).get(x, y)
+                + 0.0722 * image.get_band(2).get(x, y),
+        );
+    });
+    luminance
+}
+
+/// Applies a flip or 180 degree rotation to every band of `image`
+pub fn reorient_image(image: &Image, orientation: FrameOrientation) -> Image {
+    if orientation == FrameOrientation::Normal {
+        return image.clone();
+    }
+    let mut reoriented = image.clone();
+    iproduct!(0..image.height, 0..image.width).for_each(|(y, x)| {
+        let src_x = if orientation.flips_horizontal() {
+            image.width - 1 - x
+        } else {
+            x
+        };
+        let src_y = if orientation.flips_vertical() {
+            image.height - 1 - y
+        } else {
+            y
+        };
+        (0..image.num_bands()).for_each(|b| {
+            reoriented.put(x, y, image.get_band(b).get(src_x, src_y), b);
+        });
+    });
+    reoriented
+}
+
+/// Replaces NaN and infinite values in every band of `image` with zero, which would otherwise
+/// carry through normalization and spoil the whole image. Returns the number replaced.
+pub fn replace_invalid_pixels(image: &mut Image) -> usize {
+    let mut replaced = 0;
+    iproduct!(0..image.num_bands(), 0..image.height, 0..image.width).for_each(|(b, y, x)| {
+        if !image.get_band(b).get(x, y).is_finite() {
+            image.put(x, y, 0.0, b);
+            replaced += 1;
+        }
+    });
+    replaced
+}
+
 // https://stackoverflow.com/questions/54275459/how-do-i-create-a-random-string-by-sampling-from-alphanumeric-characters
 pub fn gen_random_texture_name() -> String {
     rand::thread_rng()
@@ -37,3 +161,33 @@ pub fn gen_random_texture_name() -> String {
         .map(char::from)
         .collect()
 }
+
+/// Scale from the median absolute deviation to the standard deviation of Gaussian noise
+pub const MAD_TO_SIGMA: f64 = 1.4826;
+
+/// Sample values with a total order, which medians are taken of
+pub trait Sample: Copy + Default {
+    fn total_order(&self, other: &Self) -> Ordering;
+}
+
+impl Sample for f32 {
+    fn total_order(&self, other: &Self) -> Ordering {
+        self.total_cmp(other)
+    }
+}
+
+impl Sample for f64 {
+    fn total_order(&self, other: &Self) -> Ordering {
+        self.total_cmp(other)
+    }
+}
+
+/// Median of `values`, which are reordered, or zero if there are none. Of an even number of
+/// values, the upper of the middle two.
+pub fn median<T: Sample>(values: &mut [T]) -> T {
+    if values.is_empty() {
+        return T::default();
+    }
+    let middle = values.len() / 2;
+    *values.select_nth_unstable_by(middle, T::total_order).1
+}
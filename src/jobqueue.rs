@@ -0,0 +1,399 @@
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+
+use crate::analysis::sigma::FrameGroup;
+use crate::cancel::{clear_slot, is_slot_cancel_requested, reset_cancel_status};
+use crate::process::error::ProcessError;
+use crate::process::{self, RunResultsContainer};
+use crate::state::{ApplicationState, OutputFormat};
+use crate::taskstatus::*;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum JobStatus {
+    Pending,
+    Running,
+    Completed,
+    Failed(String),
+    Cancelled,
+}
+
+/// Output options a job uses in place of the ones it was queued with. Unset options keep the
+/// queued value.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct JobOverrides {
+    pub output_dir: Option<String>,
+    pub freetext: Option<String>,
+    pub output_format: Option<OutputFormat>,
+}
+
+impl JobOverrides {
+    pub fn is_empty(&self) -> bool {
+        self.output_dir.is_none() && self.freetext.is_none() && self.output_format.is_none()
+    }
+
+    fn apply(&self, state: &mut ApplicationState) {
+        if let Some(output_dir) = &self.output_dir {
+            state.output_dir = Some(output_dir.to_owned());
+        }
+        if let Some(freetext) = &self.freetext {
+            state.freetext = freetext.to_owned();
+        }
+        if let Some(output_format) = self.output_format {
+            state.output_format = output_format;
+        }
+    }
+}
+
+/// A stacking run with a snapshot of the options it was queued with
+#[derive(Clone)]
+pub struct Job {
+    pub id: usize,
+    pub state: ApplicationState,
+    pub overrides: JobOverrides,
+    pub output_filename: PathBuf,
+    pub status: JobStatus,
+    /// Queued as one file of a batch folder
+    pub batch: bool,
+}
+
+impl Job {
+    /// The queued options with the job's overrides applied
+    pub fn effective_state(&self) -> ApplicationState {
+        let mut state = self.state.clone();
+        self.overrides.apply(&mut state);
+        state
+    }
+
+    /// Short description of the job, the name of its light file
+    pub fn name(&self) -> String {
+        self.state
+            .light
+            .as_ref()
+            .and_then(|l| {
+                PathBuf::from(l)
+                    .file_name()
+                    .map(|f| f.to_string_lossy().to_string())
+            })
+            .unwrap_or_default()
+    }
+}
+
+#[derive(Default)]
+pub struct JobQueue {
+    jobs: Vec<Job>,
+    next_id: usize,
+}
+
+lazy_static! {
+    static ref JOB_QUEUE: Arc<Mutex<JobQueue>> = Arc::new(Mutex::new(JobQueue::default()));
+}
+
+/// Snapshot of all jobs in the queue, in run order
+pub fn jobs() -> Vec<Job> {
+    JOB_QUEUE.lock().unwrap().jobs.clone()
+}
+
+pub fn has_pending_jobs() -> bool {
+    JOB_QUEUE
+        .lock()
+        .unwrap()
+        .jobs
+        .iter()
+        .any(|j| j.status == JobStatus::Pending)
+}
+
+fn push_job(state: &ApplicationState, batch: bool) -> Result<()> {
+    let output_filename = state.assemble_output_filename()?;
+    let mut queue = JOB_QUEUE.lock().unwrap();
+    let id = queue.next_id;
+    queue.next_id += 1;
+    queue.jobs.push(Job {
+        id,
+        state: state.clone(),
+        overrides: JobOverrides::default(),
+        output_filename,
+        status: JobStatus::Pending,
+        batch,
+    });
+    info!("Queued job {}", id);
+    Ok(())
+}
+
+/// Adds a job for the current options. The output filename is fixed at the time it's queued.
+pub fn add_job(state: &ApplicationState) -> Result<()> {
+    push_job(state, false)
+}
+
+/// SER files directly within `folder`, sorted by name
+fn light_files_in(folder: &Path) -> Result<Vec<String>> {
+    let mut files: Vec<String> = std::fs::read_dir(folder)?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.is_file()
+                && path
+                    .extension()
+                    .map(|e| e.to_string_lossy().eq_ignore_ascii_case("ser"))
+                    .unwrap_or(false)
+        })
+        .map(|path| path.to_string_lossy().to_string())
+        .collect();
+    files.sort();
+    Ok(files)
+}
+
+/// Adds a job for each SER file in `folder`, using the current options with that file as the
+/// light input. Returns the number of jobs added.
+pub fn add_folder_jobs(state: &ApplicationState, folder: &Path) -> Result<usize> {
+    let files = light_files_in(folder)?;
+    if files.is_empty() {
+        return Err(anyhow!("No SER files found in {}", folder.display()));
+    }
+    for file in files.iter() {
+        let mut job_state = state.clone();
+        job_state.light = Some(file.to_owned());
+        push_job(&job_state, true)?;
+    }
+    Ok(files.len())
+}
+
+/// Adds a job stacking only the frames of each of `groups`, using the current options. The start
+/// time of each group is added to the free text so each job writes its own output. Returns the
+/// number of jobs added.
+pub fn add_group_jobs(state: &ApplicationState, groups: &[FrameGroup]) -> Result<usize> {
+    if groups.is_empty() {
+        return Err(anyhow!("No frame groups to queue"));
+    }
+    for group in groups.iter() {
+        let mut job_state = state.clone();
+        let start = group.start.format("%H%M%S").to_string();
+        job_state.freetext = if state.freetext.is_empty() {
+            start
+        } else {
+            format!("{}_{}", state.freetext, start)
+        };
+        job_state.frame_range = Some([group.first_frame, group.last_frame]);
+        push_job(&job_state, false)?;
+    }
+    Ok(groups.len())
+}
+
+/// Removes a job which isn't running
+pub fn remove_job(id: usize) {
+    JOB_QUEUE
+        .lock()
+        .unwrap()
+        .jobs
+        .retain(|j| j.id != id || j.status == JobStatus::Running);
+}
+
+/// Moves a job `offset` places later (positive) or earlier (negative) in the queue
+pub fn move_job(id: usize, offset: isize) {
+    let mut queue = JOB_QUEUE.lock().unwrap();
+    if let Some(index) = queue.jobs.iter().position(|j| j.id == id) {
+        let new_index = index as isize + offset;
+        if new_index >= 0 && (new_index as usize) < queue.jobs.len() {
+            queue.jobs.swap(index, new_index as usize);
+        }
+    }
+}
+
+/// Removes completed, failed and cancelled jobs
+pub fn clear_finished() {
+    JOB_QUEUE
+        .lock()
+        .unwrap()
+        .jobs
+        .retain(|j| j.status == JobStatus::Pending || j.status == JobStatus::Running);
+}
+
+/// Returns failed and cancelled jobs to pending so they run again
+pub fn requeue_unsuccessful() {
+    JOB_QUEUE.lock().unwrap().jobs.iter_mut().for_each(|j| {
+        if matches!(j.status, JobStatus::Failed(_) | JobStatus::Cancelled) {
+            j.status = JobStatus::Pending;
+        }
+    });
+}
+
+/// Replaces the overrides of a job which hasn't started, updating its output filename to match
+pub fn set_job_overrides(id: usize, overrides: JobOverrides) -> Result<()> {
+    let mut queue = JOB_QUEUE.lock().unwrap();
+    let job = queue
+        .jobs
+        .iter_mut()
+        .find(|j| j.id == id && j.status != JobStatus::Running)
+        .ok_or_else(|| anyhow!("Job {} is running or no longer queued", id))?;
+    let mut state = job.state.clone();
+    overrides.apply(&mut state);
+    job.output_filename = state.assemble_output_filename()?;
+    job.overrides = overrides;
+    Ok(())
+}
+
+fn set_job_status(id: usize, status: JobStatus) {
+    if let Some(job) = JOB_QUEUE
+        .lock()
+        .unwrap()
+        .jobs
+        .iter_mut()
+        .find(|j| j.id == id)
+    {
+        job.status = status;
+    }
+}
+
+/// Takes the next pending job, marking it as running
+fn next_pending_job() -> Option<Job> {
+    let mut queue = JOB_QUEUE.lock().unwrap();
+    let job = queue
+        .jobs
+        .iter_mut()
+        .find(|j| j.status == JobStatus::Pending)?;
+    job.status = JobStatus::Running;
+    Some(job.clone())
+}
+
+/// Upper limit on the number of jobs run at the same time. Each job holds its frames and stacking
+/// buffers in memory, and competes with the others for the same cores.
+pub const MAX_CONCURRENT_JOBS: usize = 3;
+
+fn count_jobs<P: Fn(&Job) -> bool>(predicate: P) -> usize {
+    JOB_QUEUE
+        .lock()
+        .unwrap()
+        .jobs
+        .iter()
+        .filter(|j| predicate(j))
+        .count()
+}
+
+/// Number of times a job failing with a recoverable error is retried before it's marked failed
+const MAX_JOB_RETRIES: usize = 2;
+
+/// Wait before retrying a job, giving a transient problem such as a network share a chance to
+/// clear
+const RETRY_DELAY: Duration = Duration::from_secs(10);
+
+/// Runs `job`, recording how it ended. Jobs failing with a recoverable error are retried. Returns
+/// false if it was cancelled.
+async fn run_job<C>(job: &Job, on_job_finished: &C) -> bool
+where
+    C: Fn(RunResultsContainer),
+{
+    info!("Running job {} ({})", job.id, job.name());
+    let mut retries = 0;
+    loop {
+        match process::run_async(job.output_filename.clone(), job.effective_state()).await {
+            Ok(results) => {
+                set_job_status(job.id, JobStatus::Completed);
+                on_job_finished(results);
+                return true;
+            }
+            Err(why) => match ProcessError::from(why) {
+                ProcessError::Cancelled => {
+                    set_job_status(job.id, JobStatus::Cancelled);
+                    return false;
+                }
+                error if error.is_recoverable() && retries < MAX_JOB_RETRIES => {
+                    retries += 1;
+                    warn!(
+                        "Job {} failed: {}. Retrying ({} of {})",
+                        job.id, error, retries, MAX_JOB_RETRIES
+                    );
+                    set_task_status(&t!("queue.retrying"), 0, 0);
+                    tokio::time::sleep(RETRY_DELAY).await;
+                }
+                error => {
+                    error!("Job {} failed: {}", job.id, error);
+                    set_job_status(job.id, JobStatus::Failed(error.describe()));
+                    return true;
+                }
+            },
+        }
+    }
+}
+
+/// Runs the pending jobs until none remain or a job is cancelled, up to `concurrency` at a time.
+/// Jobs added while the queue is running are picked up in turn. `on_job_finished` receives the
+/// results of each job.
+pub async fn run_queue_async<C>(concurrency: usize, on_job_finished: C)
+where
+    C: Fn(RunResultsContainer) + Send + Sync + 'static,
+{
+    let concurrency = concurrency.clamp(1, MAX_CONCURRENT_JOBS);
+    if concurrency == 1 {
+        run_sequentially(on_job_finished).await;
+    } else {
+        run_concurrently(concurrency, on_job_finished).await;
+    }
+}
+
+async fn run_sequentially<C>(on_job_finished: C)
+where
+    C: Fn(RunResultsContainer),
+{
+    let mut job_number = 0;
+    while let Some(job) = next_pending_job() {
+        job_number += 1;
+        let remaining = count_jobs(|j| j.status == JobStatus::Pending);
+        let kind = if job.batch {
+            QueueKind::Files
+        } else {
+            QueueKind::Jobs
+        };
+        set_queue_progress(job_number, job_number + remaining, kind);
+
+        if !run_job(&job, &on_job_finished).await {
+            break;
+        }
+    }
+    clear_queue_progress();
+}
+
+/// Runs jobs on `concurrency` workers, each reporting its progress in its own task status slot.
+/// The primary slot counts the jobs finished.
+async fn run_concurrently<C>(concurrency: usize, on_job_finished: C)
+where
+    C: Fn(RunResultsContainer) + Send + Sync + 'static,
+{
+    let on_job_finished = Arc::new(on_job_finished);
+    let total = count_jobs(|j| j.status == JobStatus::Pending);
+    set_slot_task_status(PRIMARY_SLOT, &t!("queue.jobs_finished"), total, 0);
+
+    let workers: Vec<_> = (1..=concurrency)
+        .map(|slot| {
+            let on_job_finished = on_job_finished.clone();
+            tokio::spawn(run_in_slot(slot, async move {
+                // A job cancelled on its own leaves the worker to carry on with the next. Only a
+                // cancellation of the whole run stops it.
+                while !is_slot_cancel_requested(PRIMARY_SLOT) {
+                    let Some(job) = next_pending_job() else {
+                        break;
+                    };
+                    set_task_label(&job.name());
+                    run_job(&job, on_job_finished.as_ref()).await;
+
+                    let finished = count_jobs(|j| {
+                        j.status != JobStatus::Pending && j.status != JobStatus::Running
+                    });
+                    let total = finished + count_jobs(|j| j.status == JobStatus::Pending);
+                    set_slot_task_status(PRIMARY_SLOT, &t!("queue.jobs_finished"), total, finished);
+                }
+                clear_slot(slot);
+                set_task_completed();
+            }))
+        })
+        .collect();
+
+    for worker in workers {
+        if let Err(why) = worker.await {
+            error!("Queue worker failed: {}", why);
+        }
+    }
+    // Every worker has seen the cancellation of the run by now
+    reset_cancel_status();
+}
@@ -0,0 +1,80 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::state::ApplicationState;
+
+/// Options remembered for an individual light file, restored when it's selected again
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct LightFileOptions {
+    pub obj_detection_threshold: f64,
+    pub crop_width: usize,
+    pub crop_height: usize,
+    pub vert_offset: i32,
+    pub horiz_offset: i32,
+    pub min_sigma: f64,
+    pub max_sigma: f64,
+    pub top_percentage: f64,
+    pub max_frames: usize,
+}
+
+impl LightFileOptions {
+    pub fn from_state(state: &ApplicationState) -> Self {
+        LightFileOptions {
+            obj_detection_threshold: state.obj_detection_threshold,
+            crop_width: state.crop_width,
+            crop_height: state.crop_height,
+            vert_offset: state.vert_offset,
+            horiz_offset: state.horiz_offset,
+            min_sigma: state.min_sigma,
+            max_sigma: state.max_sigma,
+            top_percentage: state.top_percentage,
+            max_frames: state.max_frames,
+        }
+    }
+
+    pub fn apply_to(&self, state: &mut ApplicationState) {
+        state.obj_detection_threshold = self.obj_detection_threshold;
+        state.crop_width = self.crop_width;
+        state.crop_height = self.crop_height;
+        state.vert_offset = self.vert_offset;
+        state.horiz_offset = self.horiz_offset;
+        state.min_sigma = self.min_sigma;
+        state.max_sigma = self.max_sigma;
+        state.top_percentage = self.top_percentage;
+        state.max_frames = self.max_frames;
+    }
+}
+
+fn memory_path() -> PathBuf {
+    dirs::home_dir().unwrap().join(".solhat/light_options.toml")
+}
+
+fn load_all() -> HashMap<String, LightFileOptions> {
+    fs::read_to_string(memory_path())
+        .ok()
+        .and_then(|t| toml::from_str(&t).ok())
+        .unwrap_or_default()
+}
+
+/// Remembers the current options of `state` for its light file
+pub fn remember(state: &ApplicationState) -> Result<()> {
+    if let Some(light) = &state.light {
+        let mut all = load_all();
+        all.insert(light.to_owned(), LightFileOptions::from_state(state));
+        let path = memory_path();
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, toml::to_string(&all)?)?;
+    }
+    Ok(())
+}
+
+/// Options last used with `light`, if it has been processed before
+pub fn recall(light: &str) -> Option<LightFileOptions> {
+    load_all().remove(light)
+}
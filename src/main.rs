@@ -7,9 +7,10 @@ extern crate rust_i18n;
 #[macro_use]
 extern crate stump;
 
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::sync::Mutex;
+use std::time::{Duration, Instant};
 
 use anyhow::Result;
 use eframe::{egui, glow};
@@ -18,33 +19,92 @@ use egui_extras::install_image_loaders;
 use native_dialog::MessageDialog;
 use native_dialog::MessageType;
 use serde::{Deserialize, Serialize};
+use solhat::datasource::DataSource;
 use solhat::drizzle::Scale;
-use solhat::drizzle::StackAlgorithm;
-use solhat::ser::SerFile;
 use solhat::target::Target;
 
 use analysis::sigma::AnalysisSeries;
 use analysis::*;
+use optionfilter::{OptionFilter, OptionSection};
+use process::badlines::BadLineMap;
+use process::dryrun::DryRunResults;
+use process::error::{user_message, ProcessError};
+use process::framesource::FrameSource;
 use process::RunResultsContainer;
+use resources::format_bytes;
 use state::*;
 use taskstatus::*;
-use toggle::toggle;
+use toggle::{lock, toggle};
+use validation::{warning_marker, ValidatedOption};
 
+mod animationview;
+mod comparisonview;
+mod contributionview;
+#[cfg(target_os = "linux")]
+mod gtkthread;
 mod histogram;
+mod history;
+mod historyview;
+mod hotpixelview;
 mod imageutil;
+mod jobqueue;
+mod lightmemory;
+mod memory;
+#[cfg(feature = "notifications")]
+mod notifications;
+mod optionfilter;
+mod osrecent;
+mod partialfile;
+mod power;
 mod preview;
+mod projectview;
+mod queueview;
+mod resources;
 mod resultview;
+mod rotationview;
+mod schedule;
+mod serheaderview;
+mod tray;
 
 mod cancel;
 mod taskstatus;
 mod toggle;
+mod undo;
 
 mod analysis;
+#[cfg(feature = "capture")]
+mod capture;
+mod changes;
+mod cli;
+mod colorprofile;
+#[cfg(any(feature = "notifications", feature = "publish"))]
+mod curlconfig;
+mod filename;
+mod filters;
+mod fits;
+mod metadata;
+mod presets;
 mod process;
+#[cfg(feature = "publish")]
+mod publish;
 mod state;
+mod template;
+mod tiffwriter;
+mod validation;
 
 i18n!("locales", fallback = "en");
 
+const AUTOSAVE_INTERVAL: Duration = Duration::from_secs(60);
+
+const UNDO_SHORTCUT: egui::KeyboardShortcut =
+    egui::KeyboardShortcut::new(egui::Modifiers::COMMAND, egui::Key::Z);
+const REDO_SHORTCUT: egui::KeyboardShortcut =
+    egui::KeyboardShortcut::new(egui::Modifiers::COMMAND, egui::Key::Y);
+const REDO_SHORTCUT_ALT: egui::KeyboardShortcut = egui::KeyboardShortcut::new(
+    egui::Modifiers::COMMAND.plus(egui::Modifiers::SHIFT),
+    egui::Key::Z,
+);
+
 struct AnalysisResultsContainer {
     series: Option<AnalysisSeries>,
 }
@@ -53,11 +113,121 @@ struct ImageResultsContainer {
     results: Option<RunResultsContainer>,
 }
 
+struct BadLineResultsContainer {
+    bad_lines: Option<BadLineMap>,
+    error: Option<String>,
+}
+
+struct HotPixelResultsContainer {
+    master_dark: Option<sciimg::prelude::Image>,
+    error: Option<String>,
+}
+
+struct ContributionResultsContainer {
+    report: Option<process::contribution::ContributionReport>,
+    error: Option<String>,
+}
+
+struct ComparisonResultsContainer {
+    report: Option<process::comparison::ComparisonReport>,
+    error: Option<String>,
+}
+
+struct AnimationResultsContainer {
+    /// Path written and the number of frames in it
+    written: Option<(PathBuf, usize)>,
+    error: Option<String>,
+}
+
+#[cfg(feature = "notifications")]
+struct NotificationTestResultsContainer {
+    sent: bool,
+    error: Option<String>,
+}
+
+#[cfg(feature = "publish")]
+struct PublishResultsContainer {
+    /// URL of the uploaded image
+    uploaded: Option<String>,
+    error: Option<String>,
+}
+
+struct ReexportResultsContainer {
+    written: Option<Vec<PathBuf>>,
+    error: Option<String>,
+}
+
+/// Images chosen for re-export and the filter preset to apply to them
+#[derive(Default)]
+struct ReexportRequest {
+    files: Vec<PathBuf>,
+    preset: Option<String>,
+    suffix: String,
+}
+
+struct DryRunResultsContainer {
+    results: Option<DryRunResults>,
+    error: Option<String>,
+}
+
 lazy_static! {
     static ref ANALYSIS_RESULTS: Arc<Mutex<AnalysisResultsContainer>> =
         Arc::new(Mutex::new(AnalysisResultsContainer { series: None }));
     static ref IMAGE_RESULTS: Arc<Mutex<ImageResultsContainer>> =
         Arc::new(Mutex::new(ImageResultsContainer { results: None }));
+    static ref DRY_RUN_RESULTS: Arc<Mutex<DryRunResultsContainer>> =
+        Arc::new(Mutex::new(DryRunResultsContainer {
+            results: None,
+            error: None
+        }));
+    static ref REEXPORT_RESULTS: Arc<Mutex<ReexportResultsContainer>> =
+        Arc::new(Mutex::new(ReexportResultsContainer {
+            written: None,
+            error: None
+        }));
+    static ref BAD_LINE_RESULTS: Arc<Mutex<BadLineResultsContainer>> =
+        Arc::new(Mutex::new(BadLineResultsContainer {
+            bad_lines: None,
+            error: None
+        }));
+    static ref HOT_PIXEL_RESULTS: Arc<Mutex<HotPixelResultsContainer>> =
+        Arc::new(Mutex::new(HotPixelResultsContainer {
+            master_dark: None,
+            error: None
+        }));
+    static ref CONTRIBUTION_RESULTS: Arc<Mutex<ContributionResultsContainer>> =
+        Arc::new(Mutex::new(ContributionResultsContainer {
+            report: None,
+            error: None
+        }));
+    static ref COMPARISON_RESULTS: Arc<Mutex<ComparisonResultsContainer>> =
+        Arc::new(Mutex::new(ComparisonResultsContainer {
+            report: None,
+            error: None
+        }));
+    static ref ANIMATION_RESULTS: Arc<Mutex<AnimationResultsContainer>> =
+        Arc::new(Mutex::new(AnimationResultsContainer {
+            written: None,
+            error: None
+        }));
+}
+
+#[cfg(feature = "notifications")]
+lazy_static! {
+    static ref NOTIFICATION_TEST_RESULTS: Arc<Mutex<NotificationTestResultsContainer>> =
+        Arc::new(Mutex::new(NotificationTestResultsContainer {
+            sent: false,
+            error: None
+        }));
+}
+
+#[cfg(feature = "publish")]
+lazy_static! {
+    static ref PUBLISH_RESULTS: Arc<Mutex<PublishResultsContainer>> =
+        Arc::new(Mutex::new(PublishResultsContainer {
+            uploaded: None,
+            error: None
+        }));
 }
 
 // https://github.com/emilk/egui/discussions/1574
@@ -84,26 +254,82 @@ struct SolHat {
     state: state::ApplicationState,
 
     #[serde(skip_serializing, skip_deserializing)]
-    preview_light: preview::SerPreviewPane<SerFile>,
+    preview_light: preview::SerPreviewPane<FrameSource>,
 
     #[serde(skip_serializing, skip_deserializing)]
-    preview_dark: preview::SerPreviewPane<SerFile>,
+    preview_dark: preview::SerPreviewPane<FrameSource>,
 
     #[serde(skip_serializing, skip_deserializing)]
-    preview_flat: preview::SerPreviewPane<SerFile>,
+    preview_flat: preview::SerPreviewPane<FrameSource>,
 
     #[serde(skip_serializing, skip_deserializing)]
-    preview_darkflat: preview::SerPreviewPane<SerFile>,
+    preview_darkflat: preview::SerPreviewPane<FrameSource>,
 
     #[serde(skip_serializing, skip_deserializing)]
-    preview_bias: preview::SerPreviewPane<SerFile>,
+    preview_bias: preview::SerPreviewPane<FrameSource>,
 
     #[serde(skip_serializing, skip_deserializing)]
     analysis_chart: analysis::AnalysisChart,
 
+    #[serde(skip_serializing, skip_deserializing)]
+    focus_assistant: analysis::focus::FocusAssistant,
+
+    #[cfg(feature = "capture")]
+    #[serde(skip_serializing, skip_deserializing)]
+    capture_view: capture::view::CaptureWindow,
+
+    #[serde(skip_serializing, skip_deserializing)]
+    ser_header_view: serheaderview::SerHeaderWindow,
+
+    #[serde(skip_serializing, skip_deserializing)]
+    hot_pixel_view: hotpixelview::HotPixelWindow,
+
+    #[serde(skip_serializing, skip_deserializing)]
+    contribution_view: contributionview::ContributionWindow,
+
+    #[serde(skip_serializing, skip_deserializing)]
+    animation_view: animationview::AnimationWindow,
+
+    #[serde(skip_serializing, skip_deserializing)]
+    comparison_view: comparisonview::ComparisonWindow,
+
+    #[serde(skip_serializing, skip_deserializing)]
+    project_viewer: projectview::ProjectViewer,
+
+    #[cfg(feature = "publish")]
+    #[serde(skip_serializing, skip_deserializing)]
+    publish_view: publish::PublishWindow,
+
+    #[serde(skip_serializing, skip_deserializing)]
+    rotation_chart: rotationview::RotationChart,
+
+    #[serde(skip_serializing, skip_deserializing)]
+    undo_history: undo::UndoHistory,
+
+    /// Start processing on the first update, as requested on the command line
+    #[serde(skip_serializing, skip_deserializing)]
+    autorun_pending: bool,
+
+    /// Started in safe mode: the saved settings are left untouched and previews aren't loaded
+    /// until the user leaves safe mode
+    #[serde(skip_serializing, skip_deserializing)]
+    safe_mode: bool,
+
+    #[serde(skip_serializing, skip_deserializing)]
+    new_bad_column: usize,
+
+    #[serde(skip_serializing, skip_deserializing)]
+    new_bad_row: usize,
+
     #[serde(skip_serializing, skip_deserializing)]
     result_view: resultview::ResultViewPane,
 
+    #[serde(skip_serializing, skip_deserializing)]
+    queue_view: queueview::QueueViewPane,
+
+    #[serde(skip_serializing, skip_deserializing)]
+    history_view: historyview::HistoryViewPane,
+
     #[serde(skip_serializing, skip_deserializing)]
     image_loaders_installed: bool,
 
@@ -112,6 +338,59 @@ struct SolHat {
 
     #[serde(skip_serializing, skip_deserializing)]
     error_message: Option<String>,
+
+    #[serde(skip_serializing, skip_deserializing)]
+    window_title: String,
+
+    #[serde(skip_serializing, skip_deserializing)]
+    dry_run_results: Option<DryRunResults>,
+
+    #[serde(skip_serializing, skip_deserializing)]
+    reexport: Option<ReexportRequest>,
+
+    #[serde(skip_serializing, skip_deserializing)]
+    deferred_start: schedule::DeferredStart,
+
+    /// Shown in place of the window while processing runs in the background
+    #[serde(skip_serializing, skip_deserializing)]
+    tray: Option<tray::Tray>,
+
+    #[serde(skip_serializing, skip_deserializing)]
+    background_task_was_running: bool,
+
+    #[serde(skip_serializing, skip_deserializing)]
+    power_monitor: power::PowerMonitor,
+
+    #[serde(skip_serializing, skip_deserializing)]
+    memory_monitor: memory::MemoryMonitor,
+
+    #[serde(skip_serializing, skip_deserializing)]
+    preferences_open: bool,
+
+    #[serde(skip_serializing, skip_deserializing)]
+    selected_preset: Option<String>,
+
+    #[serde(skip_serializing, skip_deserializing)]
+    preset_name_edit: String,
+
+    /// Text typed in the options filter box
+    #[serde(skip_serializing, skip_deserializing)]
+    option_filter: String,
+
+    /// Translation keys of the option rows changed since the run the displayed result was made by
+    #[serde(skip_serializing, skip_deserializing)]
+    changed_options: Vec<&'static str>,
+
+    #[serde(skip_serializing, skip_deserializing)]
+    last_autosave: Option<Instant>,
+
+    /// Frames were added to the light file being watched live, and are yet to be analyzed
+    #[serde(skip_serializing, skip_deserializing)]
+    live_analysis_pending: bool,
+
+    /// Light file the automatic analysis and threshold test were last started for
+    #[serde(skip_serializing, skip_deserializing)]
+    auto_analyzed_light: Option<String>,
 }
 
 #[tokio::main]
@@ -122,6 +401,18 @@ async fn main() -> Result<(), eframe::Error> {
         println!("{}", s);
     });
 
+    let args = match cli::CommandLineArgs::parse(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(why) => {
+            eprintln!("{}\n\n{}", why, cli::CommandLineArgs::usage());
+            std::process::exit(2);
+        }
+    };
+    if args.help {
+        println!("{}", cli::CommandLineArgs::usage());
+        return Ok(());
+    }
+
     let mut options = eframe::NativeOptions {
         viewport: egui::ViewportBuilder::default()
             .with_icon(load_icon())
@@ -135,9 +426,56 @@ async fn main() -> Result<(), eframe::Error> {
         ..Default::default()
     };
 
+    // If the previous session didn't exit cleanly, offer safe mode in case the settings or
+    // graphics driver caused the crash, then to restore the last autosaved state instead of the
+    // state saved on the last clean exit.
+    let mut safe_mode = args.safe_mode;
+    let recovered_state = if state::mark_session_started() {
+        warn!("Previous session did not shut down cleanly");
+        partialfile::remove_stale_partial_files();
+        if !safe_mode {
+            safe_mode = MessageDialog::new()
+                .set_type(MessageType::Warning)
+                .set_title(&t!("recovery.title"))
+                .set_text(&t!("recovery.offer_safe_mode"))
+                .show_confirm()
+                .unwrap_or(false);
+        }
+        match ApplicationState::load_autosave() {
+            _ if safe_mode => None,
+            Ok(autosaved) => {
+                let restore = MessageDialog::new()
+                    .set_type(MessageType::Warning)
+                    .set_title(&t!("recovery.title"))
+                    .set_text(&t!("recovery.restore_previous_session"))
+                    .show_confirm()
+                    .unwrap_or(false);
+                if restore {
+                    Some(autosaved)
+                } else {
+                    None
+                }
+            }
+            Err(_) => None,
+        }
+    } else {
+        None
+    };
+
     // If the config file (literally a serialized version of the last run window state) errors on read
     // or doesn't exist, we'll just ignore it and start from scratch.
-    let solhat = if let Ok(app_state) = ApplicationState::load_from_userhome() {
+    let mut solhat = if safe_mode {
+        info!("Starting in safe mode");
+        options.centered = true;
+        options.vsync = false;
+        options.hardware_acceleration = eframe::HardwareAcceleration::Off;
+        Box::new(SolHat {
+            safe_mode: true,
+            ..Default::default()
+        })
+    } else if let Some(app_state) =
+        recovered_state.or_else(|| ApplicationState::load_from_userhome().ok())
+    {
         // if either value is zero, then egui will panic with an invalid window
         // geometry error. This value isn't always persisted resulting in zeros in the toml file.
         if app_state.window.window_width > 0 && app_state.window.window_height > 0 {
@@ -157,12 +495,23 @@ async fn main() -> Result<(), eframe::Error> {
         Box::<SolHat>::default()
     };
 
+    // The light file restored from the last session isn't newly selected
+    solhat.auto_analyzed_light = solhat.state.light.clone();
+    if let Err(why) = args.apply_to(&mut solhat.state) {
+        solhat.error_message = Some(user_message(&why));
+    }
+    solhat.autorun_pending = args.autorun;
+
     eframe::run_native(&t!("apptitle"), options, Box::new(|_cc| solhat))
 }
 
 impl eframe::App for SolHat {
     fn on_exit(&mut self, _gl: Option<&glow::Context>) {
-        self.state.save_to_userhome();
+        if !self.safe_mode {
+            self.state.save_to_userhome();
+            self.state.autosave();
+        }
+        state::mark_session_ended();
     }
 
     fn update(&mut self, ctx: &egui::Context, frame: &mut eframe::Frame) {
@@ -183,39 +532,95 @@ fn truncate_to(s: &str, max_len: usize) -> String {
 }
 
 macro_rules! create_file_input {
-    ($ui:expr, $name:expr, $state:expr, $state_property:expr, $preview_property:expr, $open_type_name:expr, $open_type_ext:expr) => {{
+    ($ui:expr, $name:expr, $state:expr, $state_property:expr, $preview_property:expr, $open_type_name:expr, $open_type_ext:expr) => {
+        create_file_input!(
+            $ui,
+            $name,
+            $state,
+            $state_property,
+            $preview_property,
+            $open_type_name,
+            $open_type_ext,
+            false,
+            false
+        )
+    };
+    ($ui:expr, $name:expr, $state:expr, $state_property:expr, $preview_property:expr, $open_type_name:expr, $open_type_ext:expr, $allow_folder:expr, $allow_master:expr) => {{
         $ui.label(&format!("{}:", $name));
         $ui.monospace(truncate_to(
             &$state_property.clone().unwrap_or("".to_owned()),
             35,
         ))
         .on_hover_text(&$state_property.clone().unwrap_or("".to_owned()));
-        if $ui.button(t!("inputs.open_file")).clicked() {
-            if let Some(path) = rfd::FileDialog::new()
-                .set_title(&format!("Open {}", $name))
-                .set_directory($state.window.get_last_opened_folder())
-                .add_filter($open_type_name, &[$open_type_ext])
-                .pick_file()
-            {
-                $state_property = Some(path.display().to_string());
 
-                if $open_type_name == "SER" {
-                    $preview_property
-                        .load_ser($ui.ctx(), &path.display().to_string())
-                        .expect("Failed to load ser file");
+        let mut selected_path: Option<PathBuf> = None;
+        $ui.horizontal(|ui| {
+            if ui.button(t!("inputs.open_file")).clicked() {
+                let mut dialog = rfd::FileDialog::new()
+                    .set_title(&format!("Open {}", $name))
+                    .set_directory($state.window.get_last_opened_folder())
+                    .add_filter($open_type_name, &[$open_type_ext]);
+                if $allow_master {
+                    dialog = dialog.add_filter(
+                        t!("inputs.master_image"),
+                        process::framesource::STILL_IMAGE_EXTENSIONS,
+                    );
+                }
+                selected_path = dialog.pick_file();
+            }
+            if $allow_folder
+                && ui
+                    .button(t!("inputs.open_folder"))
+                    .on_hover_text(t!("inputs.open_folder_hint"))
+                    .clicked()
+            {
+                selected_path = rfd::FileDialog::new()
+                    .set_title(&format!("Open {}", $name))
+                    .set_directory($state.window.get_last_opened_folder())
+                    .pick_folder();
+            }
+        });
 
-                    if let Ok(tex_size) = $preview_property.size() {
-                        if $state.crop_width == 0 {
-                            $state.crop_width = tex_size[0];
-                        }
-                        if $state.crop_height == 0 {
-                            $state.crop_height = tex_size[1];
-                        }
+        let recent_files =
+            $state
+                .window
+                .recent_inputs($open_type_ext, $allow_folder, $allow_master);
+        $ui.add_enabled_ui(!recent_files.is_empty(), |ui| {
+            ui.menu_button(t!("inputs.recent"), |ui| {
+                for recent in recent_files.iter() {
+                    if ui
+                        .button(truncate_to(recent, 50))
+                        .on_hover_text(recent)
+                        .clicked()
+                    {
+                        selected_path = Some(PathBuf::from(recent));
+                        ui.close_menu();
                     }
                 }
+            });
+        });
+
+        if let Some(path) = selected_path {
+            $state_property = Some(path.display().to_string());
+
+            if $open_type_name == "SER" {
+                if let Err(why) = $preview_property.load_ser($ui.ctx(), &path.display().to_string())
+                {
+                    error!("Failed to load {}: {}", path.display(), why);
+                }
 
-                $state.window.update_last_opened_folder(&path);
+                if let Ok(tex_size) = $preview_property.size() {
+                    if $state.crop_width == 0 {
+                        $state.crop_width = tex_size[0];
+                    }
+                    if $state.crop_height == 0 {
+                        $state.crop_height = tex_size[1];
+                    }
+                }
             }
+
+            $state.window.update_last_opened_folder(&path);
+            $state.window.add_recent_file(&path.display().to_string());
         }
         if $ui.button(t!("inputs.clear")).clicked() {
             $preview_property.unload_ser();
@@ -225,10 +630,85 @@ macro_rules! create_file_input {
     }};
 }
 
+/// Grid row listing the files combined with a calibration input into its master, and the method
+/// they're combined with. Shown only once the input itself is set, and emptied when it's cleared.
+fn additional_inputs_row(
+    ui: &mut egui::Ui,
+    name: &str,
+    window: &mut WindowState,
+    primary_set: bool,
+    additional: &mut Vec<String>,
+    method: &mut MasterMethod,
+) {
+    if !primary_set {
+        additional.clear();
+        return;
+    }
+    ui.label("");
+    let names = additional
+        .iter()
+        .map(|f| {
+            Path::new(f)
+                .file_name()
+                .map(|n| n.to_string_lossy().to_string())
+                .unwrap_or_default()
+        })
+        .collect::<Vec<String>>()
+        .join(", ");
+    if additional.is_empty() {
+        ui.label("");
+    } else {
+        ui.monospace(truncate_to(
+            &format!("+{}: {}", additional.len(), names),
+            35,
+        ))
+        .on_hover_text(additional.join("\n"));
+    }
+    if ui
+        .button(t!("inputs.add_files"))
+        .on_hover_text(t!("inputs.add_files_hint"))
+        .clicked()
+    {
+        if let Some(paths) = rfd::FileDialog::new()
+            .set_title(t!("inputs.add_files_title", name = name))
+            .set_directory(window.get_last_opened_folder())
+            .add_filter("SER", &["ser"])
+            .pick_files()
+        {
+            for path in paths {
+                let path = path.display().to_string();
+                if !additional.contains(&path) {
+                    window.add_recent_file(&path);
+                    additional.push(path);
+                }
+            }
+        }
+    }
+    if ui
+        .add_enabled(
+            !additional.is_empty(),
+            egui::Button::new(t!("inputs.clear")),
+        )
+        .clicked()
+    {
+        additional.clear();
+    }
+    egui::ComboBox::from_id_source(format!("master_method_{}", name))
+        .selected_text(method.name())
+        .show_ui(ui, |ui| {
+            for m in MasterMethod::ALL {
+                ui.selectable_value(method, m, m.name());
+            }
+        })
+        .response
+        .on_hover_text(t!("inputs.method_hint"));
+    ui.end_row();
+}
+
 impl SolHat {
-    fn ensure_texture_loaded(
+    fn ensure_texture_loaded<F: DataSource>(
         ctx: &egui::Context,
-        preview_pane: &mut preview::SerPreviewPane<SerFile>,
+        preview_pane: &mut preview::SerPreviewPane<F>,
         ser_path: &Option<String>,
     ) -> Result<()> {
         if preview_pane.is_empty() && ser_path.is_some() {
@@ -239,6 +719,47 @@ impl SolHat {
         Ok(())
     }
 
+    /// Loads the input previews. While memory is low the preview and result textures are
+    /// downscaled, and the calibration previews are only kept loaded while they're shown.
+    fn update_preview_memory_use(&mut self, ctx: &egui::Context) -> Result<()> {
+        let low_memory = self.memory_monitor.is_low();
+        let downscale = if low_memory {
+            memory::LOW_MEMORY_PREVIEW_DOWNSCALE
+        } else {
+            1
+        };
+        let selected = self.state.window.selected_preview_pane.clone();
+
+        self.result_view.set_downscale(ctx, downscale)?;
+        self.preview_light.set_downscale(ctx, downscale)?;
+        // Files opened in safe mode are still previewed, as they're loaded as they're opened
+        if self.safe_mode {
+            return Ok(());
+        }
+        SolHat::ensure_texture_loaded(ctx, &mut self.preview_light, &self.state.light)?;
+
+        for (pane, preview, path) in [
+            (PreviewPane::Dark, &mut self.preview_dark, &self.state.dark),
+            (PreviewPane::Flat, &mut self.preview_flat, &self.state.flat),
+            (
+                PreviewPane::DarkFlat,
+                &mut self.preview_darkflat,
+                &self.state.darkflat,
+            ),
+            (PreviewPane::Bias, &mut self.preview_bias, &self.state.bias),
+        ] {
+            if low_memory && pane != selected {
+                if !preview.is_empty() {
+                    preview.unload_ser();
+                }
+                continue;
+            }
+            preview.set_downscale(ctx, downscale)?;
+            SolHat::ensure_texture_loaded(ctx, preview, path)?;
+        }
+        Ok(())
+    }
+
     fn on_update(&mut self, ctx: &egui::Context, frame: &mut eframe::Frame) -> Result<()> {
         if !self.image_loaders_installed {
             install_image_loaders(ctx);
@@ -262,17 +783,34 @@ impl SolHat {
             }
         }
 
+        if self.rotation_chart.is_empty()
+            && self.state.window.selected_preview_pane == PreviewPane::Rotation
+        {
+            self.state.window.selected_preview_pane = PreviewPane::Light;
+        }
+
         if let Ok(mut img_results) = IMAGE_RESULTS.lock() {
             if let Some(results) = &mut img_results.results {
-                if results.was_success {
+                if results.was_success && !self.safe_mode {
+                    self.state.autosave();
                     self.result_view.set_image(results, ctx)?;
+                    self.rotation_chart.set_rotations(&results.frame_rotations);
+                    if !results.warnings.is_empty() {
+                        MessageDialog::new()
+                            .set_type(MessageType::Warning)
+                            .set_title(&t!("exposure.warning_title"))
+                            .set_text(&results.warnings.join("\n\n"))
+                            .show_alert()
+                            .unwrap();
+                    }
                     self.state.window.selected_preview_pane = PreviewPane::Results;
                     img_results.results = None;
-                } else if results.error.is_some() {
-                    println!("Error Detected!");
-                    self.error_window_visible = true;
-                    self.error_message = results.error.clone();
-                    results.error = None;
+                } else if let Some(error) = results.error.take() {
+                    if error != ProcessError::Cancelled {
+                        error!("Processing failed: {}", error);
+                        self.error_window_visible = true;
+                        self.error_message = Some(error.describe());
+                    }
                 }
             } else if self.result_view.is_empty()
                 && self.state.window.selected_preview_pane == PreviewPane::Results
@@ -281,24 +819,170 @@ impl SolHat {
             }
         }
 
-        SolHat::ensure_texture_loaded(ctx, &mut self.preview_light, &self.state.light)?;
-        SolHat::ensure_texture_loaded(ctx, &mut self.preview_dark, &self.state.dark)?;
-        SolHat::ensure_texture_loaded(ctx, &mut self.preview_flat, &self.state.flat)?;
-        SolHat::ensure_texture_loaded(ctx, &mut self.preview_darkflat, &self.state.darkflat)?;
-        SolHat::ensure_texture_loaded(ctx, &mut self.preview_bias, &self.state.bias)?;
+        if let Ok(mut detected) = BAD_LINE_RESULTS.lock() {
+            if let Some(bad_lines) = detected.bad_lines.take() {
+                self.state.bad_lines = bad_lines;
+                self.state.bad_line_correction = true;
+            }
+            if let Some(error) = detected.error.take() {
+                self.error_message = Some(error);
+            }
+        }
+
+        if let Ok(mut generated) = HOT_PIXEL_RESULTS.lock() {
+            if let Some(master_dark) = generated.master_dark.take() {
+                self.hot_pixel_view.set_master_dark(ctx, master_dark);
+            }
+            if let Some(error) = generated.error.take() {
+                self.hot_pixel_view.open = false;
+                self.error_message = Some(error);
+            }
+        }
+
+        if let Ok(mut inspected) = CONTRIBUTION_RESULTS.lock() {
+            if let Some(report) = inspected.report.take() {
+                self.contribution_view.set_report(ctx, report);
+            }
+            if let Some(error) = inspected.error.take() {
+                self.contribution_view.open = false;
+                self.error_message = Some(error);
+            }
+        }
+
+        if let Ok(mut compared) = COMPARISON_RESULTS.lock() {
+            if let Some(report) = compared.report.take() {
+                self.comparison_view.set_report(ctx, report);
+            }
+            if let Some(error) = compared.error.take() {
+                self.comparison_view.failed();
+                self.error_message = Some(error);
+            }
+        }
+
+        if let Ok(mut animation) = ANIMATION_RESULTS.lock() {
+            if let Some((path, count)) = animation.written.take() {
+                MessageDialog::new()
+                    .set_type(MessageType::Info)
+                    .set_title(&t!("animation.title"))
+                    .set_text(&t!(
+                        "animation.completed",
+                        count = count,
+                        path = path.display()
+                    ))
+                    .show_alert()
+                    .unwrap();
+            }
+            if let Some(error) = animation.error.take() {
+                self.error_message = Some(error);
+            }
+        }
+
+        #[cfg(feature = "notifications")]
+        if let Ok(mut test) = NOTIFICATION_TEST_RESULTS.lock() {
+            if test.sent {
+                test.sent = false;
+                MessageDialog::new()
+                    .set_type(MessageType::Info)
+                    .set_title(&t!("notifications.title"))
+                    .set_text(&t!("notifications.test_sent"))
+                    .show_alert()
+                    .unwrap();
+            }
+            if let Some(error) = test.error.take() {
+                self.error_message = Some(error);
+            }
+        }
+
+        #[cfg(feature = "publish")]
+        if let Ok(mut publish) = PUBLISH_RESULTS.lock() {
+            if let Some(url) = publish.uploaded.take() {
+                MessageDialog::new()
+                    .set_type(MessageType::Info)
+                    .set_title(&t!("publish.title"))
+                    .set_text(&t!("publish.completed", url = url))
+                    .show_alert()
+                    .unwrap();
+            }
+            if let Some(error) = publish.error.take() {
+                self.error_message = Some(error);
+            }
+        }
+
+        if let Ok(mut reexport) = REEXPORT_RESULTS.lock() {
+            if let Some(written) = reexport.written.take() {
+                MessageDialog::new()
+                    .set_type(MessageType::Info)
+                    .set_title(&t!("reexport.title"))
+                    .set_text(&t!("reexport.completed", count = written.len()))
+                    .show_alert()
+                    .unwrap();
+            }
+            if let Some(error) = reexport.error.take() {
+                self.error_message = Some(error);
+            }
+        }
+
+        if let Ok(mut dry_run) = DRY_RUN_RESULTS.lock() {
+            if let Some(results) = dry_run.results.take() {
+                self.dry_run_results = Some(results);
+            }
+            if let Some(error) = dry_run.error.take() {
+                self.error_message = Some(error);
+            }
+        }
+
+        self.memory_monitor.update();
+        self.update_preview_memory_use(ctx)?;
 
         self.state.enforce_value_bounds();
         self.state.window.update_from_window_info(ctx, frame);
 
         let task_running = taskstatus::is_task_running();
 
+        // A pause requested as a task finished would otherwise hold up the next one
+        if !task_running && cancel::is_paused() {
+            cancel::set_paused(false);
+        }
+
+        self.power_monitor
+            .update(&self.state.window.power, task_running);
+
+        if !self.safe_mode
+            && self
+                .last_autosave
+                .map(|t| t.elapsed() >= AUTOSAVE_INTERVAL)
+                .unwrap_or(true)
+        {
+            self.state.autosave();
+            self.last_autosave = Some(Instant::now());
+        }
+
+        let window_title = self.assemble_window_title();
+        if window_title != self.window_title {
+            ctx.send_viewport_cmd(egui::ViewportCommand::Title(window_title.clone()));
+            if let Some(tray) = &self.tray {
+                tray.set_tooltip(&window_title);
+            }
+            self.window_title = window_title;
+        }
+
+        if self.tray.is_some() {
+            self.background_update(ctx, task_running);
+        }
+
+        // Keep the title progressing even when the window isn't focused and nothing else is
+        // driving repaints.
+        if task_running {
+            ctx.request_repaint_after(Duration::from_millis(500));
+        }
+
         ///////////////////////////
         // Error Message Modal
         ///////////////////////////
         if self.error_message.is_some() {
             MessageDialog::new()
                 .set_type(MessageType::Error)
-                .set_title("Error")
+                .set_title(&t!("errors.title"))
                 .set_text(
                     &self
                         .error_message
@@ -312,6 +996,118 @@ impl SolHat {
             self.error_message = None;
         }
 
+        self.dry_run_window(ctx);
+        self.preferences_window(ctx);
+        self.reexport_window(ctx);
+        self.ser_header_window(ctx);
+        self.hot_pixel_window(ctx);
+        if let Some(path) = self.project_viewer.ui(ctx) {
+            self.open_project(&path);
+        }
+        self.contribution_window(ctx, task_running);
+        self.animation_window(ctx, task_running);
+        self.comparison_window(ctx, task_running);
+        #[cfg(feature = "publish")]
+        self.publish_window(ctx, task_running);
+        #[cfg(feature = "capture")]
+        self.capture_window(ctx);
+
+        if !task_running {
+            if let Some(scheduled) = self.deferred_start.take_due() {
+                info!("Starting scheduled processing");
+                self.run_with_state(scheduled.state, scheduled.output_filename);
+            }
+        }
+        if self.deferred_start.scheduled.is_some() {
+            ctx.request_repaint_after(Duration::from_secs(1));
+        }
+        if !task_running && self.queue_view.schedule.take_due() {
+            if jobqueue::has_pending_jobs() {
+                info!("Starting the scheduled queue");
+                self.state.window.selected_preview_pane = PreviewPane::Queue;
+                self.run_queue();
+            } else {
+                warn!("The queue was scheduled to start, but has no pending jobs");
+            }
+        }
+        if self.queue_view.schedule.start_at.is_some() {
+            ctx.request_repaint_after(Duration::from_secs(1));
+        }
+
+        if self.autorun_pending && !task_running {
+            self.autorun_pending = false;
+            match self.state.assemble_output_filename() {
+                Ok(output_filename) => {
+                    info!("Starting processing as requested on the command line");
+                    self.run(output_filename);
+                }
+                Err(why) => self.error_message = Some(user_message(&why)),
+            }
+        }
+
+        self.sync_bayer_overrides(ctx);
+
+        match self.preview_light.poll_live(ctx) {
+            Ok(true) => {
+                self.live_analysis_pending |= self.state.window.live_analysis;
+                if let (Some(light), Some(frame_count)) =
+                    (&self.state.light, self.preview_light.frame_count())
+                {
+                    self.focus_assistant.update(light, frame_count, &self.state);
+                }
+            }
+            Ok(false) => {}
+            Err(why) => warn!("Failed to check the light file for new frames: {}", why),
+        }
+        if self.live_analysis_pending && !task_running {
+            self.live_analysis_pending = false;
+            info!("Analyzing frames added to the light file");
+            self.run_analysis();
+        }
+        self.start_auto_analysis(ctx, task_running);
+
+        if !task_running {
+            self.update_undo_history(ctx);
+        }
+
+        egui::TopBottomPanel::top("menu_bar").show(ctx, |ui| {
+            egui::menu::bar(ui, |ui| {
+                ui.add_enabled_ui(!task_running, |ui| {
+                    self.file_menu(ui);
+                    self.edit_menu(ui);
+                });
+                if self.memory_monitor.is_low() {
+                    ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                        let label = ui.colored_label(ui.visuals().warn_fg_color, t!("memory.low"));
+                        if let Some(status) = self.memory_monitor.status() {
+                            label.on_hover_text(t!(
+                                "memory.low_hint",
+                                available = format_bytes(status.available_bytes as usize),
+                                total = format_bytes(status.total_bytes as usize),
+                                used = format_bytes(status.process_bytes as usize)
+                            ));
+                        }
+                    });
+                }
+            });
+        });
+
+        if self.safe_mode {
+            egui::TopBottomPanel::top("safe_mode_banner").show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    ui.colored_label(ui.visuals().warn_fg_color, t!("safe_mode.banner"));
+                    if ui
+                        .button(t!("safe_mode.leave"))
+                        .on_hover_text(t!("safe_mode.leave_hint"))
+                        .clicked()
+                    {
+                        info!("Leaving safe mode");
+                        self.safe_mode = false;
+                    }
+                });
+            });
+        }
+
         egui::SidePanel::left("left_panel")
             .resizable(true)
             .show(ctx, |ui| {
@@ -319,10 +1115,41 @@ impl SolHat {
                 // Left side controls:
                 /////////////////////////////////
 
-                ui.add_enabled_ui(!task_running, |ui| {
-                    self.inputs_frame_contents(ui, ctx);
-                    ui.separator();
-
+                ui.horizontal(|ui| {
+                    ui.add(
+                        egui::TextEdit::singleline(&mut self.option_filter)
+                            .hint_text(t!("processoptions.filter_hint")),
+                    );
+                    if !self.option_filter.is_empty()
+                        && ui
+                            .small_button("✖")
+                            .on_hover_text(t!("processoptions.filter_clear"))
+                            .clicked()
+                    {
+                        self.option_filter.clear();
+                    }
+                    ui.separator();
+                    ui.selectable_value(
+                        &mut self.state.window.advanced_options,
+                        false,
+                        t!("processoptions.basic"),
+                    )
+                    .on_hover_text(t!("processoptions.basic_hint"));
+                    ui.selectable_value(
+                        &mut self.state.window.advanced_options,
+                        true,
+                        t!("processoptions.advanced"),
+                    );
+                });
+                ui.separator();
+
+                ui.add_enabled_ui(!task_running, |ui| {
+                    self.targets_frame_contents(ui);
+                    ui.separator();
+
+                    self.inputs_frame_contents(ui, ctx);
+                    ui.separator();
+
                     self.outputs_frame_contents(ui, ctx);
                     ui.separator();
 
@@ -334,45 +1161,164 @@ impl SolHat {
                 });
 
                 match get_task_status() {
-                    Some(TaskStatus::TaskPercentage(task_name, len, cnt)) => {
+                    Some(status) => {
+                        let TaskStatus::TaskPercentage(task_name, _, _) = &status;
+                        let task_fraction = status.fraction();
                         ui.vertical_centered(|ui| {
                             ui.spacing_mut().button_padding = Vec2::new(18.0, 14.0);
-                            let cancel_icon = egui::include_image!("../assets/cancel.svg");
-                            if ui
-                                .add(egui::Button::image_and_text(cancel_icon, t!("cancel")))
-                                .clicked()
-                            {
-                                cancel::set_request_cancel();
-                                ctx.request_repaint();
+                            let paused = cancel::is_paused();
+                            ui.horizontal(|ui| {
+                                let cancel_icon = egui::include_image!("../assets/cancel.svg");
+                                if ui
+                                    .add(egui::Button::image_and_text(cancel_icon, t!("cancel")))
+                                    .clicked()
+                                {
+                                    cancel::set_request_cancel();
+                                    ctx.request_repaint();
+                                }
+                                let pause_label = if paused {
+                                    format!("▶ {}", t!("resume"))
+                                } else {
+                                    format!("⏸ {}", t!("pause"))
+                                };
+                                if ui
+                                    .button(pause_label)
+                                    .on_hover_text(t!("pause_hint"))
+                                    .clicked()
+                                {
+                                    cancel::set_paused(!paused);
+                                    ctx.request_repaint();
+                                }
+                                if ui
+                                    .button(t!("tray.run_in_background"))
+                                    .on_hover_text(t!("tray.run_in_background_hint"))
+                                    .clicked()
+                                {
+                                    self.enter_background(ctx);
+                                }
+                            });
+
+                            let queue = get_queue_progress().filter(|q| q.job_count > 1);
+                            if let Some(queue) = &queue {
+                                ui.monospace(queue.describe());
                             }
 
                             ui.horizontal(|ui| {
                                 ui.monospace(task_name);
-                                ui.spinner();
+                                if paused {
+                                    ui.monospace(t!("paused"));
+                                } else {
+                                    ui.spinner();
+                                }
                             });
+                            if self.power_monitor.paused_for_battery() {
+                                ui.colored_label(
+                                    ui.visuals().warn_fg_color,
+                                    t!("power.paused_on_battery"),
+                                );
+                            }
 
-                            let pct = if len > 0 {
-                                cnt as f32 / len as f32
-                            } else {
-                                0.0
-                            };
-                            ui.add(egui::ProgressBar::new(pct).show_percentage());
+                            ui.add(egui::ProgressBar::new(task_fraction).show_percentage());
+
+                            if let Some(queue) = &queue {
+                                let overall = queue.overall_fraction(task_fraction);
+                                ui.add(egui::ProgressBar::new(overall).text(t!(
+                                    "queue.overall_progress",
+                                    pct = format!("{:.0}", overall * 100.0)
+                                )));
+                            }
+
+                            for (slot, job) in get_job_statuses() {
+                                if let Some(status) = &job.status {
+                                    let TaskStatus::TaskPercentage(task_name, _, _) = status;
+                                    ui.separator();
+                                    ui.horizontal(|ui| {
+                                        ui.monospace(job.label.clone().unwrap_or_default());
+                                        if ui
+                                            .small_button("✖")
+                                            .on_hover_text(t!("cancel"))
+                                            .clicked()
+                                        {
+                                            cancel::set_slot_request_cancel(slot);
+                                        }
+                                        let job_paused = cancel::is_slot_paused(slot);
+                                        let (icon, hint) = if job_paused {
+                                            ("▶", t!("resume"))
+                                        } else {
+                                            ("⏸", t!("pause"))
+                                        };
+                                        if ui
+                                            .add_enabled(!paused, egui::Button::new(icon).small())
+                                            .on_hover_text(hint)
+                                            .clicked()
+                                        {
+                                            cancel::set_slot_paused(slot, !job_paused);
+                                        }
+                                    });
+                                    ui.add(egui::ProgressBar::new(status.fraction()).text(
+                                        format!("{} {:.0}%", task_name, status.fraction() * 100.0),
+                                    ));
+                                }
+                            }
                         });
                     }
                     None => {
                         ui.vertical_centered(|ui| {
-                            ui.add_enabled_ui(self.enable_start(), |ui| {
-                                let start_icon = egui::include_image!("../assets/solve.svg");
-                                ui.spacing_mut().button_padding = Vec2::new(18.0, 14.0);
-                                if ui
-                                    .add(egui::Button::image_and_text(start_icon, t!("start")))
-                                    .clicked()
-                                {
-                                    let output_filename =
-                                        self.state.assemble_output_filename().unwrap();
-                                    self.run(output_filename);
-                                    ctx.request_repaint();
-                                }
+                            let warnings = self.validate_options();
+                            if !warnings.is_empty() {
+                                ui.label(
+                                    egui::RichText::new(format!(
+                                        "⚠ {}",
+                                        t!("validation.warning_count", count = warnings.len())
+                                    ))
+                                    .color(egui::Color32::YELLOW),
+                                )
+                                .on_hover_text(
+                                    warnings
+                                        .iter()
+                                        .map(|w| w.message.to_owned())
+                                        .collect::<Vec<String>>()
+                                        .join("\n"),
+                                );
+                            }
+                            if self.deferred_start.scheduled.is_some() {
+                                self.scheduled_start_contents(ui);
+                            } else {
+                                ui.add_enabled_ui(self.enable_start(), |ui| {
+                                    let start_icon = egui::include_image!("../assets/solve.svg");
+                                    ui.spacing_mut().button_padding = Vec2::new(18.0, 14.0);
+                                    if ui
+                                        .add(egui::Button::image_and_text(start_icon, t!("start")))
+                                        .clicked()
+                                    {
+                                        let output_filename =
+                                            self.state.assemble_output_filename().unwrap();
+                                        self.run(output_filename);
+                                        ctx.request_repaint();
+                                    }
+                                    self.deferred_start_contents(ui);
+                                });
+                            }
+                            ui.horizontal(|ui| {
+                                ui.add_enabled_ui(self.state.light.is_some(), |ui| {
+                                    if ui.button(t!("dry_run")).clicked() {
+                                        self.run_dry();
+                                        ctx.request_repaint();
+                                    }
+                                });
+                                ui.add_enabled_ui(self.enable_start(), |ui| {
+                                    if ui.button(t!("queue.add_job")).clicked() {
+                                        match jobqueue::add_job(&self.state) {
+                                            Ok(_) => {
+                                                self.state.window.selected_preview_pane =
+                                                    PreviewPane::Queue
+                                            }
+                                            Err(why) => {
+                                                self.error_message = Some(user_message(&why))
+                                            }
+                                        }
+                                    }
+                                });
                             });
                         });
                     }
@@ -449,6 +1395,20 @@ impl SolHat {
                             t!("analysis"),
                         );
                     }
+                    if self.preview_light.is_live() {
+                        ui.selectable_value(
+                            &mut self.state.window.selected_preview_pane,
+                            PreviewPane::Focus,
+                            t!("focus.title"),
+                        );
+                    }
+                    if !self.rotation_chart.is_empty() {
+                        ui.selectable_value(
+                            &mut self.state.window.selected_preview_pane,
+                            PreviewPane::Rotation,
+                            t!("rotation.title"),
+                        );
+                    }
                     if !self.result_view.is_empty() {
                         ui.selectable_value(
                             &mut self.state.window.selected_preview_pane,
@@ -456,20 +1416,73 @@ impl SolHat {
                             t!("result"),
                         );
                     }
+                    self.history_view.refresh();
+                    if !self.history_view.is_empty() {
+                        ui.selectable_value(
+                            &mut self.state.window.selected_preview_pane,
+                            PreviewPane::History,
+                            t!("history.title"),
+                        );
+                    }
+                    if !self.queue_view.is_empty() {
+                        ui.selectable_value(
+                            &mut self.state.window.selected_preview_pane,
+                            PreviewPane::Queue,
+                            t!("queue.title"),
+                        );
+                    }
                 });
                 ui.separator();
 
+                let crop = self.crop_overlay();
                 match self.state.window.selected_preview_pane {
-                    PreviewPane::Light => self.preview_light.ui(ui),
-                    PreviewPane::Dark => self.preview_dark.ui(ui),
-                    PreviewPane::Flat => self.preview_flat.ui(ui),
-                    PreviewPane::DarkFlat => self.preview_darkflat.ui(ui),
-                    PreviewPane::Bias => self.preview_bias.ui(ui),
+                    PreviewPane::Light => {
+                        if self.preview_light.is_live() {
+                            ui.checkbox(
+                                &mut self.state.window.live_analysis,
+                                t!("preview.live_analysis"),
+                            );
+                        }
+                        self.preview_light.ui(ui, crop.as_ref())
+                    }
+                    PreviewPane::Dark => self.preview_dark.ui(ui, crop.as_ref()),
+                    PreviewPane::Flat => self.preview_flat.ui(ui, crop.as_ref()),
+                    PreviewPane::DarkFlat => self.preview_darkflat.ui(ui, crop.as_ref()),
+                    PreviewPane::Bias => self.preview_bias.ui(ui, crop.as_ref()),
                     PreviewPane::Analysis => {
                         self.analysis_chart.ui(ui);
+                        if let Some(groups) = self.analysis_chart.take_queue_request() {
+                            match jobqueue::add_group_jobs(&self.state, &groups) {
+                                Ok(count) => {
+                                    info!("Queued {} frame groups", count);
+                                    self.state.window.selected_preview_pane = PreviewPane::Queue;
+                                }
+                                Err(why) => self.error_message = Some(user_message(&why)),
+                            }
+                        }
+                    }
+                    PreviewPane::Focus => {
+                        self.focus_assistant.ui(ui);
+                    }
+                    PreviewPane::Rotation => {
+                        self.rotation_chart.ui(ui);
                     }
                     PreviewPane::Results => {
-                        self.result_view.ui(ui);
+                        self.result_view.ui(ui, &self.state);
+                    }
+                    PreviewPane::History => {
+                        if let Some(entry) = self.history_view.ui(ui, task_running) {
+                            self.rerun_from_history(&entry);
+                        }
+                    }
+                    PreviewPane::Queue => {
+                        if self.queue_view.ui(
+                            ui,
+                            task_running,
+                            &mut self.state.window.concurrent_jobs,
+                        ) {
+                            self.run_queue();
+                        }
                     }
                 }
             });
@@ -478,6 +1491,486 @@ impl SolHat {
         Ok(())
     }
 
+    fn file_menu(&mut self, ui: &mut egui::Ui) {
+        ui.menu_button(t!("menu.file"), |ui| {
+            if ui.button(t!("menu.open_project")).clicked() {
+                ui.close_menu();
+                if let Some(path) = rfd::FileDialog::new()
+                    .set_title(t!("menu.open_project"))
+                    .set_directory(self.state.window.get_last_opened_folder())
+                    .add_filter("SolHat Project", &[PROJECT_EXTENSION])
+                    .pick_file()
+                {
+                    self.open_project(&path);
+                }
+            }
+            if ui.button(t!("menu.view_project")).clicked() {
+                ui.close_menu();
+                if let Some(path) = rfd::FileDialog::new()
+                    .set_title(t!("menu.view_project"))
+                    .set_directory(self.state.window.get_last_opened_folder())
+                    .add_filter("SolHat Project", &[PROJECT_EXTENSION])
+                    .pick_file()
+                {
+                    if let Err(why) = self.project_viewer.open(ui.ctx(), &path, &self.state) {
+                        self.error_message = Some(user_message(&why));
+                    }
+                }
+            }
+            if ui.button(t!("menu.save_project")).clicked() {
+                ui.close_menu();
+                if let Some(path) = self.state.window.current_project.clone() {
+                    self.save_project(&path);
+                } else {
+                    self.save_project_as();
+                }
+            }
+            if ui.button(t!("menu.save_project_as")).clicked() {
+                ui.close_menu();
+                self.save_project_as();
+            }
+            ui.separator();
+
+            if ui.button(t!("menu.import_settings")).clicked() {
+                ui.close_menu();
+                if let Some(path) = rfd::FileDialog::new()
+                    .set_title(t!("menu.import_settings"))
+                    .set_directory(self.state.window.get_last_opened_folder())
+                    .add_filter("TOML", &["toml"])
+                    .pick_file()
+                {
+                    match presets::import_settings(&path) {
+                        Ok(settings) => self.state.apply_options_from(&settings),
+                        Err(why) => self.error_message = Some(user_message(&why)),
+                    }
+                }
+            }
+            if ui.button(t!("menu.export_settings")).clicked() {
+                ui.close_menu();
+                if let Some(path) = rfd::FileDialog::new()
+                    .set_title(t!("menu.export_settings"))
+                    .set_directory(self.state.window.get_last_opened_folder())
+                    .set_file_name("solhat-settings.toml")
+                    .add_filter("TOML", &["toml"])
+                    .save_file()
+                {
+                    if let Err(why) = presets::export_settings(&path, &self.state) {
+                        self.error_message = Some(user_message(&why));
+                    }
+                }
+            }
+            ui.separator();
+
+            ui.add_enabled_ui(!is_task_running(), |ui| {
+                if ui.button(t!("menu.process_folder")).clicked() {
+                    ui.close_menu();
+                    if let Some(folder) = rfd::FileDialog::new()
+                        .set_title(t!("menu.process_folder"))
+                        .set_directory(self.state.window.get_last_opened_folder())
+                        .pick_folder()
+                    {
+                        self.process_folder(&folder);
+                    }
+                }
+                if ui
+                    .add_enabled(
+                        self.state.light.is_some(),
+                        egui::Button::new(t!("menu.compare_algorithms")),
+                    )
+                    .clicked()
+                {
+                    ui.close_menu();
+                    self.comparison_view.open = true;
+                }
+                if ui.button(t!("menu.reexport")).clicked() {
+                    ui.close_menu();
+                    self.reexport = Some(ReexportRequest {
+                        suffix: "_reexport".to_owned(),
+                        ..Default::default()
+                    });
+                }
+            });
+            if ui.button(t!("menu.ser_header")).clicked() {
+                ui.close_menu();
+                self.ser_header_view.open = true;
+            }
+            #[cfg(feature = "capture")]
+            if ui.button(t!("menu.capture")).clicked() {
+                ui.close_menu();
+                self.capture_view.open = true;
+            }
+            ui.separator();
+
+            let recent_projects = self.state.window.recent_projects.clone();
+            ui.add_enabled_ui(!recent_projects.is_empty(), |ui| {
+                ui.menu_button(t!("menu.recent_projects"), |ui| {
+                    for project in recent_projects.iter() {
+                        let label = project.display().to_string();
+                        if ui.button(truncate_to(&label, 60)).clicked() {
+                            ui.close_menu();
+                            self.open_project(project);
+                        }
+                    }
+                });
+            });
+
+            let recent_lights = self.state.window.recent_inputs("ser", true, false);
+            ui.add_enabled_ui(!recent_lights.is_empty(), |ui| {
+                ui.menu_button(t!("menu.recent_light_files"), |ui| {
+                    for light in recent_lights.iter() {
+                        if ui.button(truncate_to(light, 60)).clicked() {
+                            ui.close_menu();
+                            self.state.light = Some(light.to_owned());
+                            self.state.window.add_recent_file(light);
+                            self.preview_light.unload_ser();
+                            self.light_file_selected();
+                        }
+                    }
+                });
+            });
+        });
+    }
+
+    fn edit_menu(&mut self, ui: &mut egui::Ui) {
+        ui.menu_button(t!("menu.edit"), |ui| {
+            let undo_shortcut = ui.ctx().format_shortcut(&UNDO_SHORTCUT);
+            if ui
+                .add_enabled(
+                    self.undo_history.can_undo(),
+                    egui::Button::new(t!("menu.undo")).shortcut_text(undo_shortcut),
+                )
+                .clicked()
+            {
+                ui.close_menu();
+                self.undo();
+            }
+            let redo_shortcut = ui.ctx().format_shortcut(&REDO_SHORTCUT);
+            if ui
+                .add_enabled(
+                    self.undo_history.can_redo(),
+                    egui::Button::new(t!("menu.redo")).shortcut_text(redo_shortcut),
+                )
+                .clicked()
+            {
+                ui.close_menu();
+                self.redo();
+            }
+            ui.separator();
+            if ui.button(t!("menu.preferences")).clicked() {
+                ui.close_menu();
+                self.preferences_open = true;
+            }
+        });
+    }
+
+    fn preferences_window(&mut self, ctx: &egui::Context) {
+        let mut open = self.preferences_open;
+        egui::Window::new(t!("preferences.title"))
+            .open(&mut open)
+            .resizable(false)
+            .show(ctx, |ui| {
+                ui.heading(t!("preferences.power"));
+                let power = &mut self.state.window.power;
+                egui::Grid::new("preferences_power")
+                    .num_columns(2)
+                    .spacing([40.0, 4.0])
+                    .striped(true)
+                    .show(ui, |ui| {
+                        ui.label(t!("preferences.prevent_sleep"));
+                        ui.add(toggle(&mut power.prevent_sleep))
+                            .on_hover_text(t!("preferences.prevent_sleep_hint"));
+                        ui.end_row();
+
+                        ui.label(t!("preferences.on_battery"));
+                        ui.vertical(|ui| {
+                            ui.radio_value(
+                                &mut power.battery_policy,
+                                power::BatteryPolicy::Ignore,
+                                t!("preferences.battery_keep_running"),
+                            );
+                            ui.radio_value(
+                                &mut power.battery_policy,
+                                power::BatteryPolicy::PauseOnBattery,
+                                t!("preferences.battery_pause"),
+                            );
+                            ui.horizontal(|ui| {
+                                ui.radio_value(
+                                    &mut power.battery_policy,
+                                    power::BatteryPolicy::PauseBelowLevel,
+                                    t!("preferences.battery_pause_below"),
+                                );
+                                ui.add_enabled(
+                                    power.battery_policy == power::BatteryPolicy::PauseBelowLevel,
+                                    egui::DragValue::new(&mut power.battery_pause_level)
+                                        .clamp_range(1..=99)
+                                        .suffix("%"),
+                                );
+                            });
+                        });
+                        ui.end_row();
+                    });
+
+                ui.separator();
+                ui.heading(t!("preferences.analysis"));
+                let window = &mut self.state.window;
+                egui::Grid::new("preferences_analysis")
+                    .num_columns(2)
+                    .spacing([40.0, 4.0])
+                    .striped(true)
+                    .show(ui, |ui| {
+                        ui.label(t!("preferences.auto_analysis"));
+                        ui.add(toggle(&mut window.auto_analysis))
+                            .on_hover_text(t!("preferences.auto_analysis_hint"));
+                        ui.end_row();
+
+                        ui.label(t!("preferences.auto_threshold_test"));
+                        ui.add(toggle(&mut window.auto_threshold_test))
+                            .on_hover_text(t!("preferences.auto_threshold_test_hint"));
+                        ui.end_row();
+                    });
+
+                #[cfg(feature = "notifications")]
+                {
+                    ui.separator();
+                    ui.heading(t!("notifications.title"));
+                    notifications::settings_ui(ui, &mut self.state.window.notifications);
+                    if ui.button(t!("notifications.send_test")).clicked() {
+                        let settings = self.state.window.notifications.clone();
+                        tokio::spawn(async move {
+                            let sent = notifications::send_test(&settings);
+                            let mut test = NOTIFICATION_TEST_RESULTS.lock().unwrap();
+                            match sent {
+                                Ok(()) => test.sent = true,
+                                Err(why) => test.error = Some(user_message(&why)),
+                            }
+                        });
+                    }
+                }
+
+                #[cfg(feature = "publish")]
+                {
+                    ui.separator();
+                    ui.heading(t!("publish.title"));
+                    publish::settings_ui(ui, &mut self.state.window.publish);
+                }
+            });
+        self.preferences_open = open;
+    }
+
+    /// Shows the SER header window for the SER inputs, reloading the previews of a file whose
+    /// header override changed
+    fn ser_header_window(&mut self, ctx: &egui::Context) {
+        let inputs: Vec<(String, String)> = [
+            (t!("light"), &self.state.light),
+            (t!("dark"), &self.state.dark),
+            (t!("flat"), &self.state.flat),
+            (t!("darkflat"), &self.state.darkflat),
+            (t!("bias"), &self.state.bias),
+        ]
+        .into_iter()
+        .filter_map(|(name, path)| {
+            path.as_ref()
+                .filter(|p| {
+                    let p = Path::new(p);
+                    p.is_file() && !process::framesource::is_still_image(p)
+                })
+                .map(|p| (name.to_string(), p.to_owned()))
+        })
+        .collect();
+
+        let Some(changed) = self.ser_header_view.ui(ctx, &inputs) else {
+            return;
+        };
+        for (path, preview) in [
+            (&self.state.light, &mut self.preview_light),
+            (&self.state.dark, &mut self.preview_dark),
+            (&self.state.flat, &mut self.preview_flat),
+            (&self.state.darkflat, &mut self.preview_darkflat),
+            (&self.state.bias, &mut self.preview_bias),
+        ] {
+            if path.as_ref() == Some(&changed) && !preview.is_empty() {
+                if let Err(why) = preview.reload(ctx) {
+                    warn!("Failed to reload {}: {}", changed, why);
+                }
+            }
+        }
+    }
+
+    /// Shows the capture window, and opens or queues captured files as they're written
+    #[cfg(feature = "capture")]
+    fn capture_window(&mut self, ctx: &egui::Context) {
+        let event = self.capture_view.poll(ctx);
+        let default_folder = self.state.window.get_last_opened_folder();
+        self.capture_view
+            .ui(ctx, &mut self.state.window.capture, default_folder);
+
+        match event {
+            Some(capture::view::CaptureEvent::FirstFrame(path))
+                if self.state.window.capture.open_as_light =>
+            {
+                let path = path.display().to_string();
+                self.state.light = Some(path.clone());
+                self.state.window.add_recent_file(&path);
+                self.preview_light.unload_ser();
+                match self.preview_light.load_ser(ctx, &path) {
+                    Ok(_) => self.preview_light.set_live(true),
+                    Err(why) => error!("Failed to load {}: {}", path, why),
+                }
+                self.focus_assistant.reset();
+                self.state.window.selected_preview_pane = PreviewPane::Light;
+            }
+            Some(capture::view::CaptureEvent::Finished(path))
+                if self.state.window.capture.queue_when_done =>
+            {
+                let mut state = self.state.clone();
+                state.light = Some(path.display().to_string());
+                match jobqueue::add_job(&state) {
+                    Ok(_) => info!("Queued captured file {:?}", path),
+                    Err(why) => self.error_message = Some(user_message(&why)),
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn undo(&mut self) {
+        let previous = self.state.clone();
+        if self.undo_history.undo(&mut self.state) {
+            self.reload_changed_previews(&previous);
+        }
+    }
+
+    fn redo(&mut self) {
+        let previous = self.state.clone();
+        if self.undo_history.redo(&mut self.state) {
+            self.reload_changed_previews(&previous);
+        }
+    }
+
+    /// Handles the undo and redo shortcuts and records finished edits as undo steps. Text fields
+    /// keep their own undo while they have focus.
+    fn update_undo_history(&mut self, ctx: &egui::Context) {
+        if ctx.memory(|m| m.focused().is_some()) {
+            return;
+        }
+        if ctx.input_mut(|i| {
+            i.consume_shortcut(&REDO_SHORTCUT) || i.consume_shortcut(&REDO_SHORTCUT_ALT)
+        }) {
+            self.redo();
+        } else if ctx.input_mut(|i| i.consume_shortcut(&UNDO_SHORTCUT)) {
+            self.undo();
+        }
+        if !ctx.input(|i| i.pointer.any_down()) {
+            self.undo_history.track(&self.state);
+        }
+    }
+
+    fn reload_changed_previews(&mut self, previous: &ApplicationState) {
+        if previous.light != self.state.light {
+            self.preview_light.unload_ser();
+        }
+        if previous.dark != self.state.dark {
+            self.preview_dark.unload_ser();
+        }
+        if previous.flat != self.state.flat {
+            self.preview_flat.unload_ser();
+        }
+        if previous.darkflat != self.state.darkflat {
+            self.preview_darkflat.unload_ser();
+        }
+        if previous.bias != self.state.bias {
+            self.preview_bias.unload_ser();
+        }
+    }
+
+    fn open_project(&mut self, path: &Path) {
+        match self.state.load_project(path) {
+            Ok(project) => {
+                self.state = project;
+                self.state.window.update_last_opened_folder(path);
+                self.state.window.add_recent_project(path);
+                self.unload_previews();
+            }
+            Err(why) => self.error_message = Some(user_message(&why)),
+        }
+    }
+
+    fn save_project(&mut self, path: &Path) {
+        match self.state.save_project(path) {
+            Ok(()) => {
+                self.state.window.current_project = Some(path.to_path_buf());
+                self.state.window.update_last_opened_folder(path);
+                self.state.window.add_recent_project(path);
+            }
+            Err(why) => self.error_message = Some(user_message(&why)),
+        }
+    }
+
+    fn save_project_as(&mut self) {
+        if let Some(mut path) = rfd::FileDialog::new()
+            .set_title(t!("menu.save_project_as"))
+            .set_directory(self.state.window.get_last_opened_folder())
+            .add_filter("SolHat Project", &[PROJECT_EXTENSION])
+            .save_file()
+        {
+            if path.extension().is_none() {
+                path.set_extension(PROJECT_EXTENSION);
+            }
+            self.save_project(&path);
+        }
+    }
+
+    /// Restores the options a past run was made with, starting it again if the user asked to
+    fn rerun_from_history(&mut self, entry: &history::HistoryEntry) {
+        let window = self.state.window.clone();
+        self.state = entry.state.clone();
+        self.state.window = window;
+        self.state.window.current_project = None;
+        self.unload_previews();
+
+        if self.history_view.start_on_rerun {
+            match self.state.assemble_output_filename() {
+                Ok(output_filename) => self.run(output_filename),
+                Err(why) => self.error_message = Some(user_message(&why)),
+            }
+        }
+    }
+
+    /// Unloads all input previews. They will be reloaded from the current state on the next
+    /// update.
+    fn unload_previews(&mut self) {
+        self.preview_light.unload_ser();
+        self.preview_dark.unload_ser();
+        self.preview_flat.unload_ser();
+        self.preview_darkflat.unload_ser();
+        self.preview_bias.unload_ser();
+    }
+
+    /// The crop drawn over the input previews, if a crop size or offset is set. Frames are
+    /// aligned on their center of mass, so the crop is centered on the center of mass of the
+    /// light frame shown, which calibration frames are lined up with before alignment.
+    fn crop_overlay(&mut self) -> Option<preview::CropOverlay> {
+        if self.state.crop_width == 0
+            && self.state.crop_height == 0
+            && self.state.horiz_offset == 0
+            && self.state.vert_offset == 0
+        {
+            return None;
+        }
+        let center = self
+            .preview_light
+            .center_of_mass(self.state.obj_detection_threshold as f32)
+            .unwrap_or([0.0, 0.0]);
+        Some(preview::CropOverlay {
+            width: self.state.crop_width,
+            height: self.state.crop_height,
+            offset: [
+                center[0] + self.state.horiz_offset as f32,
+                center[1] + self.state.vert_offset as f32,
+            ],
+        })
+    }
+
     fn outputs_frame_contents(&mut self, ui: &mut egui::Ui, _ctx: &egui::Context) {
         ui.heading(t!("output.title"));
         egui::Grid::new("process_grid_outputs")
@@ -498,29 +1991,236 @@ impl SolHat {
                 });
                 ui.end_row();
 
-                if let Ok(output_filename) = self.state.assemble_output_filename() {
-                    ui.label(t!("output.output_filename"));
-                    ui.monospace(truncate_to(output_filename.to_string_lossy().as_ref(), 55))
-                        .on_hover_text(output_filename.to_string_lossy().as_ref());
+                ui.label(t!("output.filename_template"));
+                ui.vertical(|ui| {
+                    ui.horizontal(|ui| {
+                        ui.add(
+                            egui::TextEdit::singleline(&mut self.state.output_template)
+                                .desired_width(300.0),
+                        )
+                        .on_hover_text(t!("output.filename_template_hint"));
+                        if ui
+                            .add_enabled(
+                                self.state.output_template != filename::DEFAULT_TEMPLATE,
+                                egui::Button::new(t!("output.reset_template")),
+                            )
+                            .clicked()
+                        {
+                            self.state.output_template = filename::DEFAULT_TEMPLATE.to_owned();
+                        }
+                    });
+                    if let Err(why) = filename::validate(&self.state.output_template) {
+                        ui.colored_label(ui.visuals().error_fg_color, why.to_string());
+                    }
+                });
+                ui.end_row();
+
+                ui.label(t!("output.output_format"));
+                ui.horizontal(|ui| {
+                    for format in OutputFormat::ALL {
+                        ui.selectable_value(&mut self.state.output_format, format, format.as_str());
+                    }
+                });
+                ui.end_row();
+
+                ui.label(t!("output.color_profile"));
+                ui.horizontal(|ui| {
+                    for profile in [
+                        colorprofile::ColorProfile::None,
+                        colorprofile::ColorProfile::Srgb,
+                        colorprofile::ColorProfile::Linear,
+                    ] {
+                        ui.selectable_value(
+                            &mut self.state.output_color_profile,
+                            profile,
+                            profile.as_str(),
+                        );
+                    }
+                })
+                .response
+                .on_hover_text(t!("output.color_profile_hint"));
+                ui.end_row();
+
+                if self.state.output_format == OutputFormat::Tiff {
+                    let tiff = &mut self.state.output_tiff;
+                    ui.label(t!("output.tiff_compression"));
+                    ui.horizontal(|ui| {
+                        for compression in [
+                            tiffwriter::TiffCompression::None,
+                            tiffwriter::TiffCompression::Lzw,
+                            tiffwriter::TiffCompression::Deflate,
+                        ] {
+                            ui.selectable_value(
+                                &mut tiff.compression,
+                                compression,
+                                compression.as_str(),
+                            );
+                        }
+                    })
+                    .response
+                    .on_hover_text(t!("output.tiff_compression_hint"));
+                    ui.end_row();
+
+                    ui.label(t!("output.tiff_planar"));
+                    ui.add(toggle(&mut tiff.planar))
+                        .on_hover_text(t!("output.tiff_planar_hint"));
+                    ui.end_row();
+
+                    ui.label(t!("output.tiff_rows_per_strip"));
+                    ui.add(egui::DragValue::new(&mut tiff.rows_per_strip).clamp_range(1..=65535))
+                        .on_hover_text(t!("output.tiff_rows_per_strip_hint"));
+                    ui.end_row();
+
+                    ui.label(t!("output.tiff_float"));
+                    ui.add(toggle(&mut tiff.float))
+                        .on_hover_text(t!("output.tiff_float_hint"));
+                    ui.end_row();
                 }
-            });
-    }
 
-    fn inputs_frame_contents(&mut self, ui: &mut egui::Ui, _ctx: &egui::Context) {
-        ui.heading(t!("inputs.title"));
-        egui::Grid::new("inputs_3x3_lights")
-            .num_columns(4)
-            .spacing([40.0, 4.0])
-            .striped(true)
-            .show(ui, |ui| {
-                create_file_input!(
+                ui.label(t!("output.sidecar"));
+                ui.horizontal(|ui| {
+                    for format in process::sidecar::SidecarFormat::ALL {
+                        ui.selectable_value(
+                            &mut self.state.output_sidecar,
+                            format,
+                            format.as_str(),
+                        );
+                    }
+                })
+                .response
+                .on_hover_text(t!("output.sidecar_hint"));
+                ui.end_row();
+
+                ui.label(t!("output.aligned_frames"));
+                ui.horizontal(|ui| {
+                    for export in process::alignedframes::AlignedFrameExport::ALL {
+                        ui.selectable_value(
+                            &mut self.state.export_aligned_frames,
+                            export,
+                            export.as_str(),
+                        );
+                    }
+                })
+                .response
+                .on_hover_text(t!("output.aligned_frames_hint"));
+                ui.end_row();
+
+                ui.label(t!("output.report"));
+                ui.add(toggle(&mut self.state.output_report))
+                    .on_hover_text(t!("output.report_hint"));
+                ui.end_row();
+
+                ui.label(t!("output.caption"));
+                ui.add(toggle(&mut self.state.output_caption))
+                    .on_hover_text(t!("output.caption_hint"));
+                ui.end_row();
+
+                if self.state.output_caption {
+                    ui.label(t!("output.caption_template"));
+                    ui.vertical(|ui| {
+                        ui.horizontal(|ui| {
+                            ui.add(
+                                egui::TextEdit::singleline(&mut self.state.caption_template)
+                                    .desired_width(300.0),
+                            )
+                            .on_hover_text(t!(
+                                "output.caption_template_hint",
+                                tokens = metadata::TOKENS.join("}, {")
+                            ));
+                            if ui
+                                .add_enabled(
+                                    self.state.caption_template
+                                        != process::caption::DEFAULT_TEMPLATE,
+                                    egui::Button::new(t!("output.reset_template")),
+                                )
+                                .clicked()
+                            {
+                                self.state.caption_template =
+                                    process::caption::DEFAULT_TEMPLATE.to_owned();
+                            }
+                        });
+                        if let Err(why) = metadata::validate(&self.state.caption_template) {
+                            ui.colored_label(ui.visuals().error_fg_color, why.to_string());
+                        }
+                    });
+                    ui.end_row();
+                }
+
+                if self.state.output_format == OutputFormat::Jpeg {
+                    ui.label(t!("output.jpeg_quality"));
+                    ui.add(egui::Slider::new(
+                        &mut self.state.output_jpeg_quality,
+                        1..=100,
+                    ))
+                    .on_hover_text(t!("output.jpeg_quality_hint"));
+                    ui.end_row();
+                }
+
+                if let Ok(output_filename) = self.state.assemble_output_filename() {
+                    ui.label(t!("output.output_filename"));
+                    ui.monospace(truncate_to(output_filename.to_string_lossy().as_ref(), 55))
+                        .on_hover_text(output_filename.to_string_lossy().as_ref());
+                    ui.end_row();
+                }
+
+                if let Ok(frame_size) = self.preview_light.size() {
+                    let [width, height] = self.state.output_dimensions(frame_size);
+                    let num_bands = self.preview_light.num_bands().unwrap_or(1);
+                    ui.label(t!("output.output_dimensions"));
+                    ui.monospace(format!("{} x {}", width, height));
+                    ui.end_row();
+
+                    ui.label(t!("output.estimated_size"));
+                    ui.monospace(format!(
+                        "{} ({} {})",
+                        format_bytes(self.state.estimated_output_bytes(frame_size, num_bands)),
+                        self.state.output_bytes_per_sample() * 8,
+                        t!("preview.bits")
+                    ));
+                    ui.end_row();
+                }
+            });
+
+        egui::CollapsingHeader::new(t!("output.session_notes"))
+            .default_open(!self.state.session_notes.is_empty())
+            .show(ui, |ui| {
+                ui.add(
+                    egui::TextEdit::multiline(&mut self.state.session_notes)
+                        .hint_text(t!("output.session_notes_hint"))
+                        .desired_rows(4)
+                        .desired_width(f32::INFINITY),
+                );
+            });
+    }
+
+    /// Restores the options last used with the selected light file, if any
+    fn light_file_selected(&mut self) {
+        if let Some(light) = &self.state.light {
+            if let Some(options) = lightmemory::recall(light) {
+                info!("Restoring options last used with {}", light);
+                options.apply_to(&mut self.state);
+            }
+        }
+    }
+
+    fn inputs_frame_contents(&mut self, ui: &mut egui::Ui, _ctx: &egui::Context) {
+        ui.heading(t!("inputs.title"));
+        let previous_light = self.state.light.clone();
+        egui::Grid::new("inputs_3x3_lights")
+            .num_columns(5)
+            .spacing([40.0, 4.0])
+            .striped(true)
+            .show(ui, |ui| {
+                create_file_input!(
                     ui,
                     t!("light"),
                     self.state,
                     self.state.light,
                     self.preview_light,
                     "SER",
-                    "ser"
+                    "ser",
+                    true,
+                    false
                 );
                 create_file_input!(
                     ui,
@@ -529,7 +2229,17 @@ impl SolHat {
                     self.state.dark,
                     self.preview_dark,
                     "SER",
-                    "ser"
+                    "ser",
+                    false,
+                    true
+                );
+                additional_inputs_row(
+                    ui,
+                    &t!("dark"),
+                    &mut self.state.window,
+                    self.state.dark.is_some(),
+                    &mut self.state.additional_darks,
+                    &mut self.state.dark_method,
                 );
                 create_file_input!(
                     ui,
@@ -538,7 +2248,17 @@ impl SolHat {
                     self.state.flat,
                     self.preview_flat,
                     "SER",
-                    "ser"
+                    "ser",
+                    false,
+                    true
+                );
+                additional_inputs_row(
+                    ui,
+                    &t!("flat"),
+                    &mut self.state.window,
+                    self.state.flat.is_some(),
+                    &mut self.state.additional_flats,
+                    &mut self.state.flat_method,
                 );
                 create_file_input!(
                     ui,
@@ -547,7 +2267,17 @@ impl SolHat {
                     self.state.darkflat,
                     self.preview_darkflat,
                     "SER",
-                    "ser"
+                    "ser",
+                    false,
+                    true
+                );
+                additional_inputs_row(
+                    ui,
+                    &t!("darkflat"),
+                    &mut self.state.window,
+                    self.state.darkflat.is_some(),
+                    &mut self.state.additional_darkflats,
+                    &mut self.state.darkflat_method,
                 );
                 create_file_input!(
                     ui,
@@ -556,7 +2286,17 @@ impl SolHat {
                     self.state.bias,
                     self.preview_bias,
                     "SER",
-                    "ser"
+                    "ser",
+                    false,
+                    true
+                );
+                additional_inputs_row(
+                    ui,
+                    &t!("bias"),
+                    &mut self.state.window,
+                    self.state.bias.is_some(),
+                    &mut self.state.additional_biases,
+                    &mut self.state.bias_method,
                 );
                 create_file_input!(
                     ui,
@@ -567,275 +2307,2019 @@ impl SolHat {
                     "TOML",
                     "toml"
                 );
+                ui.label("");
+                ui.label("");
+                ui.add_enabled_ui(self.state.dark.is_some(), |ui| {
+                    if ui
+                        .button(t!("hotpixels.generate"))
+                        .on_hover_text(t!("hotpixels.generate_hint"))
+                        .clicked()
+                    {
+                        self.run_hot_pixel_generation();
+                    }
+                });
+                ui.end_row();
             });
         ui.end_row();
-    }
 
-    fn observation_frame_contents(&mut self, ui: &mut egui::Ui, _ctx: &egui::Context) {
-        ui.heading(t!("observation.title"));
+        if self.state.light != previous_light {
+            self.light_file_selected();
+        }
 
-        egui::Grid::new("process_grid_observation")
-            .num_columns(2)
-            .spacing([40.0, 4.0])
-            .striped(true)
+        egui::CollapsingHeader::new(t!("bayer.title"))
+            .default_open(!self.state.bayer.is_empty())
             .show(ui, |ui| {
-                ui.label(t!("observation.title"));
-                ui.horizontal(|ui| {
-                    ui.selectable_value(&mut self.state.target, Target::Sun, t!("observation.sun"));
-                    ui.selectable_value(
-                        &mut self.state.target,
-                        Target::Moon,
-                        t!("observation.moon"),
-                    );
-                    ui.selectable_value(
-                        &mut self.state.target,
-                        Target::None,
-                        t!("observation.none"),
-                    );
-                });
+                self.bayer_contents(ui);
             });
 
-        ui.add_enabled_ui(self.state.target != Target::None, |ui| {
-            egui::Grid::new("process_grid_observation_latlon")
-                .num_columns(2)
-                .spacing([40.0, 4.0])
-                .striped(true)
-                .show(ui, |ui| {
-                    ui.label(t!("observation.obs_latitude"));
-                    ui.add(
-                        egui::DragValue::new(&mut self.state.obs_latitude)
-                            .min_decimals(1)
-                            .max_decimals(4)
-                            .speed(1.0),
-                    );
-                    ui.end_row();
+        egui::CollapsingHeader::new(t!("badlines.title"))
+            .default_open(self.state.bad_line_correction)
+            .show(ui, |ui| {
+                self.bad_lines_contents(ui);
+            });
 
-                    ui.label(t!("observation.obs_longitude"));
-                    ui.add(
-                        egui::DragValue::new(&mut self.state.obs_longitude)
-                            .min_decimals(1)
-                            .max_decimals(4)
-                            .speed(1.0),
-                    );
-                    ui.end_row();
-                });
-        });
+        egui::CollapsingHeader::new(t!("composite.title"))
+            .default_open(self.state.prominence_light.is_some())
+            .show(ui, |ui| {
+                self.composite_contents(ui);
+            });
     }
 
-    fn options_frame_contents(&mut self, ui: &mut egui::Ui, _ctx: &egui::Context) {
-        ui.heading(t!("processoptions.title"));
-        egui::Grid::new("process_grid_options")
-            .num_columns(3)
+    fn composite_contents(&mut self, ui: &mut egui::Ui) {
+        ui.label(t!("composite.hint"));
+        egui::Grid::new("inputs_grid_composite")
+            .num_columns(2)
             .spacing([40.0, 4.0])
             .striped(true)
             .show(ui, |ui| {
-                let threshtest_icon = egui::include_image!("../assets/ellipse.svg");
-                ui.label(t!("processoptions.obj_detect_thresh"));
-                ui.add(egui::DragValue::new(&mut self.state.obj_detection_threshold).speed(10.0));
-
-                ui.add_enabled_ui(!self.preview_light.is_empty(), |ui| {
-                    if ui
-                        .add(egui::Button::image_and_text(
-                            threshtest_icon,
-                            t!("processoptions.obj_detect_test"),
-                        ))
-                        .clicked()
-                    {
-                        self.preview_light
-                            .threshold_test(ui, &self.state)
-                            .expect("Failed threshold test");
-                        self.state.window.selected_preview_pane = PreviewPane::Light;
-                        // Do stuff
+                ui.label(format!("{}:", t!("composite.prominence_light")));
+                ui.horizontal(|ui| {
+                    let path = self.state.prominence_light.clone().unwrap_or_default();
+                    ui.monospace(truncate_to(&path, 35)).on_hover_text(&path);
+                    if ui.button(t!("inputs.open_file")).clicked() {
+                        if let Some(path) = rfd::FileDialog::new()
+                            .set_title(t!("composite.prominence_light"))
+                            .set_directory(self.state.window.get_last_opened_folder())
+                            .add_filter("SER", &["ser"])
+                            .pick_file()
+                        {
+                            self.state.window.update_last_opened_folder(&path);
+                            self.state.prominence_light = Some(path.display().to_string());
+                        }
                     }
-                });
-                ui.end_row();
-
-                let analysis_icon = egui::include_image!("../assets/chart.svg");
-                ui.label(t!("processoptions.analysis_window_size"));
-                ui.add(egui::DragValue::new(&mut self.state.analysis_window_size).speed(1.0));
-                ui.add_enabled_ui(!self.preview_light.is_empty(), |ui| {
-                    if ui
-                        .add(egui::Button::image_and_text(
-                            analysis_icon,
-                            t!("processoptions.analysis_run"),
-                        ))
-                        .clicked()
+                    if self.state.prominence_light.is_some()
+                        && ui.button(t!("inputs.clear")).clicked()
                     {
-                        // Do stuff
-                        self.run_analysis();
+                        self.state.prominence_light = None;
                     }
                 });
                 ui.end_row();
 
-                ui.label(t!("processoptions.drizzle"));
-                ui.horizontal(|ui| {
-                    ui.selectable_value(
-                        &mut self.state.drizzle_scale,
-                        Scale::Scale1_0,
-                        t!("processoptions.drizzle_none"),
-                    );
-                    ui.selectable_value(
-                        &mut self.state.drizzle_scale,
-                        Scale::Scale1_5,
-                        t!("processoptions.drizzle_15x"),
-                    );
-                    ui.selectable_value(
-                        &mut self.state.drizzle_scale,
-                        Scale::Scale2_0,
-                        t!("processoptions.drizzle_20x"),
-                    );
-                    ui.selectable_value(
-                        &mut self.state.drizzle_scale,
-                        Scale::Scale3_0,
-                        t!("processoptions.drizzle_30x"),
-                    );
-                });
-                ui.end_row();
-
-                ui.label(t!("processoptions.algorithm"));
-                ui.horizontal(|ui| {
-                    ui.selectable_value(
-                        &mut self.state.algorithm,
-                        StackAlgorithm::Average,
-                        t!("processoptions.algorithm_average"),
-                    );
-                    ui.selectable_value(
-                        &mut self.state.algorithm,
-                        StackAlgorithm::Median,
-                        t!("processoptions.algorithm_median"),
-                    );
-                    ui.selectable_value(
-                        &mut self.state.algorithm,
-                        StackAlgorithm::Minimum,
-                        t!("processoptions.algorithm_minimum"),
-                    );
-                });
-
-                ui.end_row();
-
-                ui.label(t!("processoptions.use_max_frames"));
-                ui.add(egui::DragValue::new(&mut self.state.max_frames).speed(10.0));
-                ui.end_row();
-
-                ui.label(t!("processoptions.minimum_sigma"));
-                ui.add(egui::DragValue::new(&mut self.state.min_sigma).speed(1.0));
-                ui.end_row();
-
-                ui.label(t!("processoptions.maximum_sigma"));
-                ui.add(egui::DragValue::new(&mut self.state.max_sigma).speed(1.0));
+                ui.label(t!("composite.blend_radius"));
+                ui.add(
+                    egui::DragValue::new(&mut self.state.composite_blend_radius)
+                        .clamp_range(0.0..=200.0)
+                        .speed(0.5)
+                        .suffix(" px"),
+                )
+                .on_hover_text(t!("composite.blend_radius_hint"));
                 ui.end_row();
+            });
+    }
 
-                ui.label(t!("processoptions.include_top_percent"));
-                ui.add(egui::DragValue::new(&mut self.state.top_percentage).speed(1.0));
-                ui.end_row();
+    fn bayer_contents(&mut self, ui: &mut egui::Ui) {
+        ui.label(t!("bayer.hint"));
+        egui::Grid::new("inputs_grid_bayer")
+            .num_columns(2)
+            .spacing([40.0, 4.0])
+            .striped(true)
+            .show(ui, |ui| {
+                let bayer = &mut self.state.bayer;
+                for (name, pattern) in [
+                    (t!("light"), &mut bayer.light),
+                    (t!("dark"), &mut bayer.dark),
+                    (t!("flat"), &mut bayer.flat),
+                    (t!("darkflat"), &mut bayer.darkflat),
+                    (t!("bias"), &mut bayer.bias),
+                ] {
+                    ui.label(&format!("{}:", name));
+                    egui::ComboBox::from_id_source(format!("bayer_{}", name))
+                        .selected_text(pattern.label())
+                        .show_ui(ui, |ui| {
+                            for option in process::bayer::BayerPattern::ALL {
+                                ui.selectable_value(pattern, option, option.label());
+                            }
+                        });
+                    ui.end_row();
+                }
+            });
+    }
 
-                ui.label(t!("processoptions.decorrelated_colors"));
-                ui.add(toggle(&mut self.state.decorrelated_colors));
-                ui.end_row();
+    /// Applies the Bayer pattern overrides to the files they're set for, reloading the preview
+    /// of any input whose override has changed
+    fn sync_bayer_overrides(&mut self, ctx: &egui::Context) {
+        let overrides = self.state.bayer.clone();
+        for (path, pattern, preview) in [
+            (&self.state.light, overrides.light, &mut self.preview_light),
+            (&self.state.dark, overrides.dark, &mut self.preview_dark),
+            (&self.state.flat, overrides.flat, &mut self.preview_flat),
+            (
+                &self.state.darkflat,
+                overrides.darkflat,
+                &mut self.preview_darkflat,
+            ),
+            (&self.state.bias, overrides.bias, &mut self.preview_bias),
+        ] {
+            let Some(path) = path else {
+                continue;
+            };
+            if process::bayer::override_for(path) != pattern {
+                process::bayer::set_override(path, pattern);
+                if !preview.is_empty() {
+                    if let Err(why) = preview.reload(ctx) {
+                        warn!("Failed to reload {}: {}", path, why);
+                    }
+                }
+            }
+        }
+        process::bayer::register_overrides(&self.state);
+    }
 
-                ui.label(t!("processoptions.limb_dark_correction"));
-                ui.add(toggle(&mut self.state.ld_correction));
+    fn bad_lines_contents(&mut self, ui: &mut egui::Ui) {
+        egui::Grid::new("process_grid_bad_lines")
+            .num_columns(2)
+            .spacing([40.0, 4.0])
+            .striped(true)
+            .show(ui, |ui| {
+                ui.label(t!("badlines.correct"));
+                ui.add(toggle(&mut self.state.bad_line_correction));
                 ui.end_row();
 
-                ui.add_enabled_ui(self.state.ld_correction, |ui| {
-                    ui.label(t!("processoptions.ldc_coefficient"));
-                });
-
-                ui.add_enabled_ui(self.state.ld_correction, |ui| {
-                    ui.add(egui::DragValue::new(&mut self.state.ld_coefficient).speed(0.1));
+                ui.label(t!("badlines.detect_threshold"));
+                ui.horizontal(|ui| {
+                    ui.add(
+                        egui::DragValue::new(&mut self.state.bad_line_threshold)
+                            .clamp_range(1.0..=100.0)
+                            .speed(0.1),
+                    );
+                    ui.add_enabled_ui(self.state.dark.is_some(), |ui| {
+                        if ui.button(t!("badlines.detect_from_dark")).clicked() {
+                            self.run_bad_line_detection();
+                        }
+                    });
                 });
                 ui.end_row();
 
-                ui.add_enabled_ui(self.state.ld_correction, |ui| {
-                    ui.label(t!("processoptions.ldc_solar_radius"));
-                });
-                ui.add_enabled_ui(self.state.ld_correction, |ui| {
-                    ui.add(egui::DragValue::new(&mut self.state.solar_radius_pixels).speed(1.0));
+                ui.label(t!("badlines.columns"));
+                ui.horizontal_wrapped(|ui| {
+                    let mut remove = None;
+                    for column in &self.state.bad_lines.columns {
+                        if ui
+                            .small_button(format!("{} ✖", column))
+                            .on_hover_text(t!("badlines.remove"))
+                            .clicked()
+                        {
+                            remove = Some(*column);
+                        }
+                    }
+                    if let Some(column) = remove {
+                        self.state.bad_lines.columns.retain(|c| *c != column);
+                    }
+                    ui.add(egui::DragValue::new(&mut self.new_bad_column).speed(1.0));
+                    if ui.button(t!("badlines.add")).clicked() {
+                        self.state.bad_lines.add_column(self.new_bad_column);
+                    }
                 });
                 ui.end_row();
 
-                let refresh_icon = egui::include_image!("../assets/refresh.svg");
-
-                ui.label(t!("processoptions.crop_width"));
-                ui.add(egui::DragValue::new(&mut self.state.crop_width).speed(1.0));
-                if !self.preview_light.is_empty()
-                    && ui
-                        .add(egui::Button::image_and_text(
-                            refresh_icon.clone(),
-                            t!("processoptions.reset"),
-                        ))
-                        .clicked()
-                {
-                    if let Ok(size) = self.preview_light.size() {
-                        self.state.crop_width = size[0];
+                ui.label(t!("badlines.rows"));
+                ui.horizontal_wrapped(|ui| {
+                    let mut remove = None;
+                    for row in &self.state.bad_lines.rows {
+                        if ui
+                            .small_button(format!("{} ✖", row))
+                            .on_hover_text(t!("badlines.remove"))
+                            .clicked()
+                        {
+                            remove = Some(*row);
+                        }
                     }
-                }
+                    if let Some(row) = remove {
+                        self.state.bad_lines.rows.retain(|r| *r != row);
+                    }
+                    ui.add(egui::DragValue::new(&mut self.new_bad_row).speed(1.0));
+                    if ui.button(t!("badlines.add")).clicked() {
+                        self.state.bad_lines.add_row(self.new_bad_row);
+                    }
+                });
                 ui.end_row();
+            });
+    }
 
-                ui.label(t!("processoptions.crop_height"));
-                ui.add(egui::DragValue::new(&mut self.state.crop_height).speed(1.0));
-                if self.state.light.is_some()
-                    && ui
-                        .add(egui::Button::image_and_text(
-                            refresh_icon,
-                            t!("processoptions.reset"),
-                        ))
-                        .clicked()
-                {
-                    if let Ok(size) = self.preview_light.size() {
-                        self.state.crop_height = size[1];
-                    }
-                }
+    fn run_bad_line_detection(&mut self) {
+        let state_copy = self.state.clone();
+        set_task_status(&t!("tasks.starting"), 1, 1);
 
-                ui.end_row();
+        tokio::spawn(async move {
+            {
+                let results = process::badlines::detect_from_dark_async(state_copy).await;
+                let mut detected = BAD_LINE_RESULTS.lock().unwrap();
+                match results {
+                    Ok(bad_lines) => detected.bad_lines = Some(bad_lines),
+                    Err(why) => detected.error = Some(user_message(&why)),
+                }
+                set_task_completed();
+            }
+        });
+    }
 
-                ui.label(t!("processoptions.horiz_offset"));
-                ui.add(egui::DragValue::new(&mut self.state.horiz_offset).speed(1.0));
-                ui.end_row();
+    fn run_hot_pixel_generation(&mut self) {
+        let state_copy = self.state.clone();
+        self.hot_pixel_view.start();
+        set_task_status(&t!("tasks.starting"), 1, 1);
 
-                ui.label(t!("processoptions.vert_offset"));
-                ui.add(egui::DragValue::new(&mut self.state.vert_offset).speed(1.0));
-                ui.end_row();
+        tokio::spawn(async move {
+            {
+                let results = process::hotpixels::master_dark_async(state_copy).await;
+                let mut generated = HOT_PIXEL_RESULTS.lock().unwrap();
+                match results {
+                    Ok(master_dark) => generated.master_dark = Some(master_dark),
+                    Err(why) => generated.error = Some(user_message(&why)),
+                }
+                set_task_completed();
+            }
+        });
+    }
 
-                ui.label(t!("processoptions.filename_free_text"));
-                ui.add(
-                    egui::TextEdit::singleline(&mut self.state.freetext)
-                        .hint_text(t!("processoptions.filename_hint")),
-                );
-                ui.end_row();
+    fn run_contribution_inspection(
+        &mut self,
+        geometry: process::contribution::StackGeometry,
+        point: [usize; 2],
+    ) {
+        let radius = self.contribution_view.radius;
+        self.contribution_view.start(geometry.clone(), point);
+        set_task_status(&t!("tasks.starting"), 1, 1);
 
-                ui.label(t!("processoptions.save_masters"));
-                ui.add(toggle(&mut self.state.save_masters));
-                ui.end_row();
-            });
+        tokio::spawn(async move {
+            {
+                let results = process::contribution::inspect_async(geometry, point, radius).await;
+                let mut inspected = CONTRIBUTION_RESULTS.lock().unwrap();
+                match results {
+                    Ok(report) => inspected.report = Some(report),
+                    Err(why) => inspected.error = Some(user_message(&why)),
+                }
+                set_task_completed();
+            }
+        });
     }
 
-    fn enable_start(&self) -> bool {
-        self.state.light.is_some() && self.state.output_dir.is_some()
+    /// Shows the frame contribution inspector, starting inspections requested from it or the
+    /// result view once any running task is done
+    fn contribution_window(&mut self, ctx: &egui::Context, task_running: bool) {
+        let again = self.contribution_view.ui(ctx);
+        if task_running {
+            return;
+        }
+        if let Some((geometry, point)) =
+            again.or_else(|| self.result_view.take_inspection_request())
+        {
+            self.run_contribution_inspection(geometry, point);
+        }
     }
 
-    fn run(&mut self, output_filename: PathBuf) {
+    fn run_comparison(&mut self, algorithms: Vec<StackMethod>, max_frames: usize) {
         let state_copy = self.state.clone();
+        self.comparison_view.start();
         set_task_status(&t!("tasks.starting"), 1, 1);
 
         tokio::spawn(async move {
             {
-                let results = process::run_async(output_filename, state_copy)
-                    .await
-                    .unwrap_or_else(|why| RunResultsContainer {
-                        was_success: false,
-                        image: None,
-                        error: Some(why.to_string()),
-                        context: None,
-                        output_filename: None,
-                        num_frames_used: 0,
-                    });
-                IMAGE_RESULTS.lock().unwrap().results = Some(results);
+                let results =
+                    process::comparison::compare_async(state_copy, algorithms, max_frames).await;
+                let mut compared = COMPARISON_RESULTS.lock().unwrap();
+                match results {
+                    Ok(report) => compared.report = Some(report),
+                    Err(why) => compared.error = Some(user_message(&why)),
+                }
                 set_task_completed();
             }
         });
     }
 
+    /// Shows the algorithm comparison, starting comparisons requested from it and applying the
+    /// algorithm chosen in it
+    fn comparison_window(&mut self, ctx: &egui::Context, task_running: bool) {
+        match self.comparison_view.ui(ctx, task_running) {
+            Some(comparisonview::ComparisonAction::Run {
+                algorithms,
+                max_frames,
+            }) => self.run_comparison(algorithms, max_frames),
+            Some(comparisonview::ComparisonAction::Use(algorithm)) => {
+                self.state.algorithm = algorithm
+            }
+            None => {}
+        }
+    }
+
+    /// Shows the animation exporter, for any preview the user asked to export, and starts the
+    /// export chosen in it
+    fn animation_window(&mut self, ctx: &egui::Context, task_running: bool) {
+        if let Some((source, frame_count)) = [
+            &mut self.preview_light,
+            &mut self.preview_dark,
+            &mut self.preview_flat,
+            &mut self.preview_darkflat,
+            &mut self.preview_bias,
+        ]
+        .into_iter()
+        .find_map(|preview| preview.take_export_request())
+        {
+            self.animation_view.show_for(source, frame_count);
+        }
+
+        let default_folder = self.state.window.get_last_opened_folder();
+        let Some((source, options, output)) =
+            self.animation_view.ui(ctx, default_folder, task_running)
+        else {
+            return;
+        };
+        set_task_status(&t!("tasks.starting"), 1, 1);
+        tokio::spawn(async move {
+            {
+                let results =
+                    process::animation::export_async(source, options, output.clone()).await;
+                let mut animation = ANIMATION_RESULTS.lock().unwrap();
+                match results {
+                    Ok(count) => animation.written = Some((output, count)),
+                    Err(why) => match ProcessError::from(&why) {
+                        ProcessError::Cancelled => {}
+                        _ => animation.error = Some(user_message(&why)),
+                    },
+                }
+                set_task_completed();
+            }
+        });
+    }
+
+    /// Shows the publishing window for any result the user asked to publish, and starts the
+    /// upload confirmed in it
+    #[cfg(feature = "publish")]
+    fn publish_window(&mut self, ctx: &egui::Context, task_running: bool) {
+        if let Some((image, results)) = self.result_view.take_publish_request() {
+            let metadata = metadata::ResultMetadata::new(
+                &results.fits_header,
+                results.num_frames_used,
+                &self.state.session_notes,
+                results.output_filename.as_deref(),
+            );
+            let color_profile = results.color_profile;
+            self.publish_view
+                .show_for(image, color_profile, &metadata, &self.state.window.publish);
+        }
+        let Some(upload) = self
+            .publish_view
+            .ui(ctx, &self.state.window.publish, task_running)
+        else {
+            return;
+        };
+        set_task_status(&t!("tasks.starting"), 1, 1);
+        tokio::spawn(async move {
+            {
+                let uploaded = publish::upload_async(upload).await;
+                let mut publish = PUBLISH_RESULTS.lock().unwrap();
+                match uploaded {
+                    Ok(url) => publish.uploaded = Some(url),
+                    Err(why) => publish.error = Some(user_message(&why)),
+                }
+                set_task_completed();
+            }
+        });
+    }
+
+    /// Shows the hot pixel map generator, and uses the map once it's saved
+    fn hot_pixel_window(&mut self, ctx: &egui::Context) {
+        let default_folder = self.state.window.get_last_opened_folder();
+        if let Some(path) = self.hot_pixel_view.ui(ctx, default_folder) {
+            let path = path.display().to_string();
+            self.state.window.add_recent_file(&path);
+            self.state.hot_pixel_map = Some(path);
+        }
+    }
+
+    fn targets_frame_contents(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.heading(t!("targets.title"));
+            if ui
+                .button(t!("targets.add"))
+                .on_hover_text(t!("targets.add_hint"))
+                .clicked()
+            {
+                let number = self.state.session_targets.len().max(1) + 1;
+                self.state.add_target(
+                    &t!("targets.default_name", number = 1),
+                    &t!("targets.default_name", number = number),
+                );
+            }
+        });
+        if self.state.session_targets.is_empty() {
+            ui.weak(t!("targets.single"));
+            return;
+        }
+
+        let mut switch_to = None;
+        let mut duplicate = None;
+        let mut remove = None;
+        egui::Grid::new("session_targets")
+            .num_columns(2)
+            .spacing([20.0, 4.0])
+            .striped(true)
+            .show(ui, |ui| {
+                let active = self.state.active_target;
+                self.state
+                    .session_targets
+                    .iter_mut()
+                    .enumerate()
+                    .for_each(|(index, target)| {
+                        if index == active {
+                            ui.horizontal(|ui| {
+                                ui.label("▶");
+                                ui.add(
+                                    egui::TextEdit::singleline(&mut target.name)
+                                        .desired_width(200.0),
+                                )
+                                .on_hover_text(t!("targets.rename"));
+                            });
+                        } else if ui
+                            .selectable_label(false, &target.name)
+                            .on_hover_text(target.light.clone().unwrap_or_default())
+                            .clicked()
+                        {
+                            switch_to = Some(index);
+                        }
+                        ui.horizontal(|ui| {
+                            if ui
+                                .small_button("⧉")
+                                .on_hover_text(t!("targets.duplicate"))
+                                .clicked()
+                            {
+                                duplicate = Some((
+                                    index,
+                                    t!("targets.copy_name", name = target.name).to_string(),
+                                ));
+                            }
+                            if ui
+                                .small_button("✖")
+                                .on_hover_text(t!("targets.remove"))
+                                .clicked()
+                            {
+                                remove = Some(index);
+                            }
+                        });
+                        ui.end_row();
+                    });
+            });
+        if switch_to.is_some() || duplicate.is_some() || remove.is_some() {
+            let previous = self.state.clone();
+            if let Some(index) = switch_to {
+                self.state.switch_target(index);
+            }
+            if let Some((index, name)) = duplicate {
+                self.state.duplicate_target(index, &name);
+            }
+            if let Some(index) = remove {
+                self.state.remove_target(index);
+            }
+            self.reload_changed_previews(&previous);
+        }
+
+        if ui
+            .button(t!("targets.queue_all"))
+            .on_hover_text(t!("targets.queue_all_hint"))
+            .clicked()
+        {
+            match self
+                .state
+                .target_states()
+                .iter()
+                .try_for_each(jobqueue::add_job)
+            {
+                Ok(()) => self.state.window.selected_preview_pane = PreviewPane::Queue,
+                Err(why) => self.error_message = Some(user_message(&why)),
+            }
+        }
+    }
+
+    fn observation_frame_contents(&mut self, ui: &mut egui::Ui, _ctx: &egui::Context) {
+        ui.heading(t!("observation.title"));
+        let previous_target = (self.state.target, self.state.planetary);
+
+        egui::Grid::new("process_grid_observation")
+            .num_columns(2)
+            .spacing([40.0, 4.0])
+            .striped(true)
+            .show(ui, |ui| {
+                ui.label(t!("observation.title"));
+                ui.horizontal(|ui| {
+                    let choices = [
+                        (Target::Sun, false, t!("observation.sun"), None),
+                        (Target::Moon, false, t!("observation.moon"), None),
+                        (
+                            Target::None,
+                            true,
+                            t!("observation.planet"),
+                            Some(t!("observation.planet_hint")),
+                        ),
+                        (Target::None, false, t!("observation.none"), None),
+                    ];
+                    for (target, planetary, label, hint) in choices {
+                        let selected =
+                            self.state.target == target && self.state.planetary == planetary;
+                        let response = ui.selectable_label(selected, label);
+                        if response.clicked() {
+                            self.state.target = target;
+                            self.state.planetary = planetary;
+                        }
+                        if let Some(hint) = hint {
+                            response.on_hover_text(hint);
+                        }
+                    }
+                });
+                ui.end_row();
+
+                ui.label(t!("observation.target_defaults"));
+                ui.horizontal(|ui| {
+                    ui.checkbox(
+                        &mut self.state.window.use_target_defaults,
+                        t!("observation.load_on_change"),
+                    );
+                    if ui.button(t!("observation.save_target_defaults")).clicked() {
+                        if let Err(why) = presets::save_target_defaults(&self.state) {
+                            error!("Failed to save target defaults: {:?}", why);
+                        }
+                    }
+                });
+                ui.end_row();
+            });
+
+        if (self.state.target, self.state.planetary) != previous_target
+            && self.state.window.use_target_defaults
+        {
+            match presets::load_target_defaults(&self.state) {
+                Ok(defaults) => {
+                    info!(
+                        "Applying {} defaults: {:?}",
+                        self.state.target_name(),
+                        defaults
+                    );
+                    defaults.apply_to(&mut self.state);
+                }
+                Err(why) => error!("Failed to load target defaults: {:?}", why),
+            }
+        }
+
+        ui.add_enabled_ui(self.state.target != Target::None, |ui| {
+            egui::Grid::new("process_grid_observation_latlon")
+                .num_columns(2)
+                .spacing([40.0, 4.0])
+                .striped(true)
+                .show(ui, |ui| {
+                    ui.label(t!("observation.obs_latitude"));
+                    ui.add(
+                        egui::DragValue::new(&mut self.state.obs_latitude)
+                            .min_decimals(1)
+                            .max_decimals(4)
+                            .speed(1.0),
+                    );
+                    ui.end_row();
+
+                    ui.label(t!("observation.obs_longitude"));
+                    ui.add(
+                        egui::DragValue::new(&mut self.state.obs_longitude)
+                            .min_decimals(1)
+                            .max_decimals(4)
+                            .speed(1.0),
+                    );
+                    ui.end_row();
+                });
+        });
+
+        egui::Grid::new("process_grid_observation_rotation")
+            .num_columns(2)
+            .spacing([40.0, 4.0])
+            .striped(true)
+            .show(ui, |ui| {
+                ui.label(t!("observation.rotation_model"));
+                ui.horizontal(|ui| {
+                    ui.selectable_value(
+                        &mut self.state.rotation_model,
+                        RotationModel::Ephemeris,
+                        t!("observation.rotation_ephemeris"),
+                    );
+                    ui.selectable_value(
+                        &mut self.state.rotation_model,
+                        RotationModel::RatePerMinute,
+                        t!("observation.rotation_rate"),
+                    );
+                    ui.selectable_value(
+                        &mut self.state.rotation_model,
+                        RotationModel::FixedPerFrame,
+                        t!("observation.rotation_per_frame"),
+                    );
+                });
+                ui.end_row();
+
+                match self.state.rotation_model {
+                    RotationModel::Ephemeris => {}
+                    RotationModel::RatePerMinute => {
+                        ui.label(t!("observation.degrees_per_minute"));
+                        ui.add(
+                            egui::DragValue::new(&mut self.state.rotation_rate)
+                                .min_decimals(2)
+                                .max_decimals(5)
+                                .speed(0.01),
+                        );
+                        ui.end_row();
+                    }
+                    RotationModel::FixedPerFrame => {
+                        ui.label(t!("observation.degrees_per_frame"));
+                        ui.add(
+                            egui::DragValue::new(&mut self.state.rotation_per_frame)
+                                .min_decimals(3)
+                                .max_decimals(6)
+                                .speed(0.001),
+                        );
+                        ui.end_row();
+                    }
+                }
+            });
+    }
+
+    fn validate_options(&self) -> Vec<validation::OptionWarning> {
+        validation::validate_options(
+            &self.state,
+            self.preview_light.size().ok(),
+            &self.analysis_chart.data.sigma_list,
+        )
+    }
+
+    fn presets_contents(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal_wrapped(|ui| {
+            ui.label(t!("presets.preset"));
+            egui::ComboBox::from_id_source("presets_combo")
+                .selected_text(self.selected_preset.clone().unwrap_or_default())
+                .show_ui(ui, |ui| {
+                    for name in presets::list_presets() {
+                        if ui
+                            .selectable_label(self.selected_preset.as_ref() == Some(&name), &name)
+                            .clicked()
+                        {
+                            self.preset_name_edit = name.clone();
+                            self.selected_preset = Some(name);
+                        }
+                    }
+                });
+
+            ui.add_enabled_ui(self.selected_preset.is_some(), |ui| {
+                if ui.button(t!("presets.apply")).clicked() {
+                    if let Some(name) = &self.selected_preset {
+                        match presets::load_preset(name) {
+                            Ok(preset) => self.state.apply_options_from(&preset),
+                            Err(why) => self.error_message = Some(user_message(&why)),
+                        }
+                    }
+                }
+                if ui.button(t!("presets.delete")).clicked() {
+                    if let Some(name) = self.selected_preset.take() {
+                        if let Err(why) = presets::delete_preset(&name) {
+                            self.error_message = Some(user_message(&why));
+                        }
+                    }
+                }
+            });
+        });
+
+        ui.horizontal(|ui| {
+            ui.add(
+                egui::TextEdit::singleline(&mut self.preset_name_edit)
+                    .hint_text(t!("presets.name_hint"))
+                    .desired_width(160.0),
+            );
+            ui.add_enabled_ui(!self.preset_name_edit.trim().is_empty(), |ui| {
+                if ui.button(t!("presets.save")).clicked() {
+                    match presets::save_preset(&self.preset_name_edit, &self.state) {
+                        Ok(()) => {
+                            self.selected_preset = Some(self.preset_name_edit.trim().to_owned())
+                        }
+                        Err(why) => self.error_message = Some(user_message(&why)),
+                    }
+                }
+                ui.add_enabled_ui(self.selected_preset.is_some(), |ui| {
+                    if ui.button(t!("presets.rename")).clicked() {
+                        if let Some(name) = &self.selected_preset {
+                            match presets::rename_preset(name, &self.preset_name_edit) {
+                                Ok(()) => {
+                                    self.selected_preset =
+                                        Some(self.preset_name_edit.trim().to_owned())
+                                }
+                                Err(why) => self.error_message = Some(user_message(&why)),
+                            }
+                        }
+                    }
+                });
+            });
+        });
+    }
+
+    fn options_frame_contents(&mut self, ui: &mut egui::Ui, _ctx: &egui::Context) {
+        let warnings = self.validate_options();
+        let filter = OptionFilter::new(&self.option_filter, self.state.window.advanced_options);
+        self.changed_options = self
+            .result_view
+            .options()
+            .map(|last| changes::changed_options(last, &self.state))
+            .unwrap_or_default();
+        ui.heading(t!("processoptions.title"));
+        self.presets_contents(ui);
+        self.option_section(ui, &filter, &optionfilter::ANALYSIS, |app, ui| {
+            app.analysis_options_contents(ui, &filter)
+        });
+        self.option_section(ui, &filter, &optionfilter::STACKING, |app, ui| {
+            app.stacking_options_contents(ui, &filter, &warnings)
+        });
+        self.option_section(ui, &filter, &optionfilter::FRAME_SELECTION, |app, ui| {
+            app.frame_selection_options_contents(ui, &filter, &warnings)
+        });
+        self.option_section(ui, &filter, &optionfilter::CORRECTIONS, |app, ui| {
+            app.corrections_options_contents(ui, &filter, &warnings)
+        });
+        self.option_section(ui, &filter, &optionfilter::FRAMING, |app, ui| {
+            app.framing_options_contents(ui, &filter, &warnings)
+        });
+        self.option_section(ui, &filter, &optionfilter::CALIBRATION, |app, ui| {
+            app.calibration_options_contents(ui, &filter)
+        });
+        if filter.is_active()
+            && !optionfilter::SECTIONS
+                .iter()
+                .any(|section| filter.shows_any(section.options))
+        {
+            ui.weak(t!("processoptions.filter_no_matches"));
+        }
+    }
+
+    /// Collapsible section of the options panel. Hidden if the filter matches none of its
+    /// options, and held open while filtering so the matches are visible.
+    fn option_section(
+        &mut self,
+        ui: &mut egui::Ui,
+        filter: &OptionFilter,
+        section: &OptionSection,
+        contents: impl FnOnce(&mut Self, &mut egui::Ui),
+    ) {
+        if !filter.shows_any(section.options) {
+            return;
+        }
+        let collapsed = self
+            .state
+            .window
+            .collapsed_option_sections
+            .contains(section.id);
+        let response = egui::CollapsingHeader::new(t!(section.title))
+            .id_source(section.id)
+            .open(Some(filter.is_active() || !collapsed))
+            .show(ui, |ui| contents(self, ui));
+        if response.header_response.clicked() && !filter.is_active() {
+            if collapsed {
+                self.state
+                    .window
+                    .collapsed_option_sections
+                    .remove(section.id);
+            } else {
+                self.state
+                    .window
+                    .collapsed_option_sections
+                    .insert(section.id.to_owned());
+            }
+        }
+    }
+
+    /// Label of the option row `key`, marked if the option has changed since the run the
+    /// displayed result was made by
+    fn option_label(&self, ui: &mut egui::Ui, key: &str) -> egui::Response {
+        ui.horizontal(|ui| {
+            let label = ui.label(t!(key));
+            if self.changed_options.contains(&key) {
+                ui.label(egui::RichText::new("●").color(ui.visuals().warn_fg_color))
+                    .on_hover_text(t!("processoptions.changed_since_run"));
+            }
+            label
+        })
+        .inner
+    }
+
+    /// Lock button for the option labelled `key`, returning whether the option is locked. Locked
+    /// options can't be dragged, so stray scrolling and dragging over the panel leave them be.
+    fn option_lock(&mut self, ui: &mut egui::Ui, key: &str) -> bool {
+        let locked_options = &mut self.state.window.locked_options;
+        let mut locked = locked_options.contains(key);
+        if ui
+            .add(lock(&mut locked))
+            .on_hover_text(t!("processoptions.lock_hint"))
+            .changed()
+        {
+            if locked {
+                locked_options.insert(key.to_owned());
+            } else {
+                locked_options.remove(key);
+            }
+        }
+        locked
+    }
+
+    fn analysis_options_contents(&mut self, ui: &mut egui::Ui, filter: &OptionFilter) {
+        egui::Grid::new("process_grid_options_analysis")
+            .num_columns(3)
+            .spacing([40.0, 4.0])
+            .striped(true)
+            .show(ui, |ui| {
+                if filter.shows("processoptions.obj_detect_thresh") {
+                    let threshtest_icon = egui::include_image!("../assets/ellipse.svg");
+                    self.option_label(ui, "processoptions.obj_detect_thresh");
+                    ui.horizontal(|ui| {
+                        let locked = self.option_lock(ui, "processoptions.obj_detect_thresh");
+                        ui.add_enabled(
+                            !locked,
+                            egui::DragValue::new(&mut self.state.obj_detection_threshold)
+                                .speed(10.0),
+                        );
+                    });
+
+                    ui.add_enabled_ui(!self.preview_light.is_empty(), |ui| {
+                        if ui
+                            .add(egui::Button::image_and_text(
+                                threshtest_icon,
+                                t!("processoptions.obj_detect_test"),
+                            ))
+                            .clicked()
+                        {
+                            self.preview_light
+                                .threshold_test(ui.ctx(), &self.state)
+                                .expect("Failed threshold test");
+                            self.state.window.selected_preview_pane = PreviewPane::Light;
+                            // Do stuff
+                        }
+                    });
+                    ui.end_row();
+                }
+
+                if filter.shows("processoptions.analysis_window_size") {
+                    let analysis_icon = egui::include_image!("../assets/chart.svg");
+                    self.option_label(ui, "processoptions.analysis_window_size");
+                    ui.add(egui::DragValue::new(&mut self.state.analysis_window_size).speed(1.0));
+                    ui.add_enabled_ui(!self.preview_light.is_empty(), |ui| {
+                        if ui
+                            .add(egui::Button::image_and_text(
+                                analysis_icon,
+                                t!("processoptions.analysis_run"),
+                            ))
+                            .clicked()
+                        {
+                            // Do stuff
+                            self.run_analysis();
+                        }
+                    });
+                    ui.end_row();
+                }
+
+                if filter.shows("processoptions.analysis_binning") {
+                    self.option_label(ui, "processoptions.analysis_binning");
+                    ui.horizontal(|ui| {
+                        ui.selectable_value(
+                            &mut self.state.analysis_binning,
+                            AnalysisBinning::None,
+                            t!("processoptions.analysis_binning_none"),
+                        );
+                        ui.selectable_value(
+                            &mut self.state.analysis_binning,
+                            AnalysisBinning::Bin2x2,
+                            t!("processoptions.analysis_binning_2x2"),
+                        );
+                        ui.selectable_value(
+                            &mut self.state.analysis_binning,
+                            AnalysisBinning::Bin4x4,
+                            t!("processoptions.analysis_binning_4x4"),
+                        );
+                    });
+                    ui.end_row();
+                }
+
+                if filter.shows("processoptions.analysis_channel") {
+                    self.option_label(ui, "processoptions.analysis_channel")
+                        .on_hover_text(t!("processoptions.analysis_channel_hint"));
+                    ui.horizontal(|ui| {
+                        ui.selectable_value(
+                            &mut self.state.analysis_channel,
+                            AnalysisChannel::Red,
+                            t!("processoptions.channel_red"),
+                        );
+                        ui.selectable_value(
+                            &mut self.state.analysis_channel,
+                            AnalysisChannel::Green,
+                            t!("processoptions.channel_green"),
+                        );
+                        ui.selectable_value(
+                            &mut self.state.analysis_channel,
+                            AnalysisChannel::Blue,
+                            t!("processoptions.channel_blue"),
+                        );
+                        ui.selectable_value(
+                            &mut self.state.analysis_channel,
+                            AnalysisChannel::Luminance,
+                            t!("processoptions.channel_luminance"),
+                        );
+                    });
+                    ui.end_row();
+                }
+
+                if filter.shows("processoptions.analysis_points") {
+                    self.option_label(ui, "processoptions.analysis_points");
+                    ui.horizontal(|ui| {
+                        ui.selectable_value(
+                            &mut self.state.analysis_points,
+                            AnalysisPoints::CenterOfMass,
+                            t!("processoptions.analysis_points_com"),
+                        );
+                        ui.selectable_value(
+                            &mut self.state.analysis_points,
+                            AnalysisPoints::Grid,
+                            t!("processoptions.analysis_points_grid"),
+                        );
+                        ui.selectable_value(
+                            &mut self.state.analysis_points,
+                            AnalysisPoints::Custom,
+                            t!("processoptions.analysis_points_custom"),
+                        );
+                    });
+                    ui.end_row();
+                }
+
+                match self.state.analysis_points {
+                    AnalysisPoints::Grid if filter.shows("processoptions.analysis_grid") => {
+                        self.option_label(ui, "processoptions.analysis_grid");
+                        ui.horizontal(|ui| {
+                            ui.add(
+                                egui::DragValue::new(&mut self.state.analysis_grid_size)
+                                    .clamp_range(1..=9)
+                                    .prefix("n: "),
+                            );
+                            ui.add(
+                                egui::DragValue::new(&mut self.state.analysis_grid_spacing)
+                                    .speed(1.0)
+                                    .prefix(t!("processoptions.analysis_grid_spacing")),
+                            );
+                        });
+                        ui.end_row();
+                    }
+                    AnalysisPoints::Custom
+                        if filter.shows("processoptions.analysis_custom_points") =>
+                    {
+                        self.option_label(ui, "processoptions.analysis_custom_points");
+                        ui.vertical(|ui| {
+                            let mut remove = None;
+                            for (i, point) in
+                                self.state.analysis_custom_points.iter_mut().enumerate()
+                            {
+                                ui.horizontal(|ui| {
+                                    ui.add(egui::DragValue::new(&mut point[0]).prefix("dx: "));
+                                    ui.add(egui::DragValue::new(&mut point[1]).prefix("dy: "));
+                                    if ui.small_button("🗙").clicked() {
+                                        remove = Some(i);
+                                    }
+                                });
+                            }
+                            if let Some(i) = remove {
+                                self.state.analysis_custom_points.remove(i);
+                            }
+                            if ui.button(t!("processoptions.analysis_add_point")).clicked() {
+                                self.state.analysis_custom_points.push([0, 0]);
+                            }
+                        });
+                        ui.end_row();
+                    }
+                    _ => {}
+                }
+            });
+    }
+
+    fn stacking_options_contents(
+        &mut self,
+        ui: &mut egui::Ui,
+        filter: &OptionFilter,
+        warnings: &[validation::OptionWarning],
+    ) {
+        egui::Grid::new("process_grid_options_stacking")
+            .num_columns(3)
+            .spacing([40.0, 4.0])
+            .striped(true)
+            .show(ui, |ui| {
+                if filter.shows("processoptions.drizzle") {
+                    self.option_label(ui, "processoptions.drizzle");
+                    ui.horizontal(|ui| {
+                        for (scale, label) in [
+                            (Scale::Scale1_0, t!("processoptions.drizzle_none")),
+                            (Scale::Scale1_5, t!("processoptions.drizzle_15x")),
+                            (Scale::Scale2_0, t!("processoptions.drizzle_20x")),
+                            (Scale::Scale3_0, t!("processoptions.drizzle_30x")),
+                        ] {
+                            let selected = self.state.custom_drizzle_scale.is_none()
+                                && self.state.drizzle_scale == scale;
+                            if ui.selectable_label(selected, label).clicked() {
+                                self.state.drizzle_scale = scale;
+                                self.state.custom_drizzle_scale = None;
+                            }
+                        }
+                        if ui
+                            .selectable_label(
+                                self.state.custom_drizzle_scale.is_some(),
+                                t!("processoptions.drizzle_custom"),
+                            )
+                            .on_hover_text(t!("processoptions.drizzle_custom_hint"))
+                            .clicked()
+                            && self.state.custom_drizzle_scale.is_none()
+                        {
+                            self.state.custom_drizzle_scale = Some(self.state.drizzle_factor());
+                        }
+                        if let Some(custom) = &mut self.state.custom_drizzle_scale {
+                            ui.add(
+                                egui::DragValue::new(custom)
+                                    .clamp_range(1.0..=4.0)
+                                    .speed(0.01)
+                                    .fixed_decimals(2)
+                                    .suffix("x"),
+                            );
+                        }
+                        warning_marker(ui, warnings, ValidatedOption::Drizzle);
+                    });
+                    ui.end_row();
+                }
+
+                if filter.shows("processoptions.drizzle_pixfrac") {
+                    self.option_label(ui, "processoptions.drizzle_pixfrac");
+                    ui.horizontal(|ui| {
+                        ui.add(
+                            egui::DragValue::new(&mut self.state.drizzle_pixfrac)
+                                .clamp_range(0.1..=1.0)
+                                .speed(0.01)
+                                .fixed_decimals(2),
+                        )
+                        .on_hover_text(t!("processoptions.drizzle_pixfrac_hint"));
+                        warning_marker(ui, warnings, ValidatedOption::Drizzle);
+                    });
+                    ui.end_row();
+                }
+
+                if filter.shows("processoptions.algorithm") {
+                    self.option_label(ui, "processoptions.algorithm");
+                    ui.horizontal_wrapped(|ui| {
+                        for algorithm in StackMethod::ALL {
+                            ui.selectable_value(
+                                &mut self.state.algorithm,
+                                algorithm,
+                                algorithm.name(),
+                            );
+                        }
+                    });
+
+                    ui.end_row();
+                }
+
+                if self.state.algorithm == StackMethod::SigmaClipped
+                    && filter.shows("processoptions.clip_kappa")
+                {
+                    self.option_label(ui, "processoptions.clip_kappa");
+                    ui.horizontal(|ui| {
+                        ui.add(
+                            egui::DragValue::new(&mut self.state.clip_kappa)
+                                .clamp_range(1.0..=5.0)
+                                .speed(0.05)
+                                .prefix("κ: "),
+                        )
+                        .on_hover_text(t!("processoptions.clip_kappa_hint"));
+                        ui.add(
+                            egui::DragValue::new(&mut self.state.clip_iterations)
+                                .clamp_range(1..=10)
+                                .suffix(t!("processoptions.clip_iterations")),
+                        )
+                        .on_hover_text(t!("processoptions.clip_iterations_hint"));
+                    });
+                    ui.end_row();
+                }
+
+                if filter.shows("processoptions.alignment_mode") {
+                    self.option_label(ui, "processoptions.alignment_mode");
+                    ui.horizontal(|ui| {
+                        ui.selectable_value(
+                            &mut self.state.alignment_mode,
+                            AlignmentMode::Centroid,
+                            t!("processoptions.alignment_centroid"),
+                        )
+                        .on_hover_text(t!("processoptions.alignment_centroid_hint"));
+                        ui.selectable_value(
+                            &mut self.state.alignment_mode,
+                            AlignmentMode::AlignmentPoints,
+                            t!("processoptions.alignment_points"),
+                        )
+                        .on_hover_text(t!("processoptions.alignment_points_hint"));
+                    });
+                    ui.end_row();
+                }
+
+                if filter.shows("processoptions.ap_size") {
+                    if self.state.alignment_mode == AlignmentMode::AlignmentPoints {
+                        self.option_label(ui, "processoptions.ap_size");
+                        ui.add(
+                            egui::DragValue::new(&mut self.state.ap_size)
+                                .clamp_range(16..=512)
+                                .suffix(" px"),
+                        )
+                        .on_hover_text(t!("processoptions.ap_size_hint"));
+                        ui.end_row();
+                    }
+
+                    if filter.shows("processoptions.ap_search_radius") {
+                        self.option_label(ui, "processoptions.ap_search_radius");
+                        ui.add(
+                            egui::DragValue::new(&mut self.state.ap_search_radius)
+                                .clamp_range(1..=64)
+                                .suffix(" px"),
+                        )
+                        .on_hover_text(t!("processoptions.ap_search_radius_hint"));
+                        ui.end_row();
+                    }
+
+                    if filter.shows("processoptions.ap_keep_percentage") {
+                        self.option_label(ui, "processoptions.ap_keep_percentage");
+                        ui.add(
+                            egui::Slider::new(&mut self.state.ap_keep_percentage, 1.0..=100.0)
+                                .suffix("%"),
+                        )
+                        .on_hover_text(t!("processoptions.ap_keep_percentage_hint"));
+                        ui.end_row();
+                    }
+
+                    if filter.shows("processoptions.ap_min_brightness") {
+                        self.option_label(ui, "processoptions.ap_min_brightness");
+                        ui.add(
+                            egui::Slider::new(&mut self.state.ap_min_brightness, 0.0..=1.0)
+                                .max_decimals(2),
+                        )
+                        .on_hover_text(t!("processoptions.ap_min_brightness_hint"));
+                        ui.end_row();
+                    }
+                }
+
+                if filter.shows("processoptions.saturation_exclusion") {
+                    self.option_label(ui, "processoptions.saturation_exclusion");
+                    ui.horizontal(|ui| {
+                        ui.add(toggle(&mut self.state.saturation_exclusion))
+                            .on_hover_text(t!("processoptions.saturation_exclusion_hint"));
+                        ui.add_enabled(
+                            self.state.saturation_exclusion,
+                            egui::DragValue::new(&mut self.state.saturation_threshold)
+                                .clamp_range(0.5..=1.0)
+                                .speed(0.001)
+                                .max_decimals(3),
+                        )
+                        .on_hover_text(t!("processoptions.saturation_threshold_hint"));
+                    });
+                    ui.end_row();
+                }
+            });
+    }
+
+    fn frame_selection_options_contents(
+        &mut self,
+        ui: &mut egui::Ui,
+        filter: &OptionFilter,
+        warnings: &[validation::OptionWarning],
+    ) {
+        egui::Grid::new("process_grid_options_frame_selection")
+            .num_columns(3)
+            .spacing([40.0, 4.0])
+            .striped(true)
+            .show(ui, |ui| {
+                if filter.shows("processoptions.use_max_frames") {
+                    self.option_label(ui, "processoptions.use_max_frames");
+                    ui.horizontal(|ui| {
+                        ui.add(egui::DragValue::new(&mut self.state.max_frames).speed(10.0));
+                        warning_marker(ui, warnings, ValidatedOption::MaxFrames);
+                    });
+                    ui.end_row();
+                }
+
+                if filter.shows("processoptions.frame_range") {
+                    if let Some([first, last]) = self.state.frame_range {
+                        self.option_label(ui, "processoptions.frame_range");
+                        ui.horizontal(|ui| {
+                            ui.label(t!(
+                                "processoptions.frame_range_value",
+                                first = first,
+                                last = last
+                            ));
+                            if ui.button(t!("processoptions.frame_range_clear")).clicked() {
+                                self.state.frame_range = None;
+                            }
+                        });
+                        ui.end_row();
+                    }
+                }
+
+                if filter.shows("processoptions.frame_sampling") {
+                    self.option_label(ui, "processoptions.frame_sampling");
+                    ui.horizontal(|ui| {
+                        ui.selectable_value(
+                            &mut self.state.frame_sampling,
+                            FrameSampling::Truncate,
+                            t!("processoptions.frame_sampling_truncate"),
+                        );
+                        ui.selectable_value(
+                            &mut self.state.frame_sampling,
+                            FrameSampling::EvenlySpaced,
+                            t!("processoptions.frame_sampling_even"),
+                        );
+                        ui.selectable_value(
+                            &mut self.state.frame_sampling,
+                            FrameSampling::BestN,
+                            t!("processoptions.frame_sampling_best"),
+                        );
+                        ui.selectable_value(
+                            &mut self.state.frame_sampling,
+                            FrameSampling::Random,
+                            t!("processoptions.frame_sampling_random"),
+                        );
+                    });
+                    ui.end_row();
+                }
+
+                if filter.shows("processoptions.sampling_seed") {
+                    ui.add_enabled_ui(self.state.frame_sampling == FrameSampling::Random, |ui| {
+                        self.option_label(ui, "processoptions.sampling_seed");
+                    });
+                    ui.add_enabled_ui(self.state.frame_sampling == FrameSampling::Random, |ui| {
+                        ui.add(egui::DragValue::new(&mut self.state.sampling_seed).speed(1.0));
+                    });
+                    ui.end_row();
+                }
+
+                if filter.shows("processoptions.minimum_sigma") {
+                    self.option_label(ui, "processoptions.minimum_sigma");
+                    ui.horizontal(|ui| {
+                        let locked = self.option_lock(ui, "processoptions.minimum_sigma");
+                        ui.add_enabled(
+                            !locked,
+                            egui::DragValue::new(&mut self.state.min_sigma).speed(1.0),
+                        );
+                        warning_marker(ui, warnings, ValidatedOption::MinimumSigma);
+                    });
+                    ui.end_row();
+                }
+
+                if filter.shows("processoptions.maximum_sigma") {
+                    self.option_label(ui, "processoptions.maximum_sigma");
+                    ui.horizontal(|ui| {
+                        let locked = self.option_lock(ui, "processoptions.maximum_sigma");
+                        ui.add_enabled(
+                            !locked,
+                            egui::DragValue::new(&mut self.state.max_sigma).speed(1.0),
+                        );
+                        warning_marker(ui, warnings, ValidatedOption::MaximumSigma);
+                    });
+                    ui.end_row();
+                }
+
+                if filter.shows("processoptions.include_top_percent") {
+                    self.option_label(ui, "processoptions.include_top_percent");
+                    ui.add(egui::DragValue::new(&mut self.state.top_percentage).speed(1.0));
+                    ui.end_row();
+                }
+
+                if filter.shows("processoptions.quality_weighting") {
+                    self.option_label(ui, "processoptions.quality_weighting");
+                    ui.horizontal(|ui| {
+                        ui.add(toggle(&mut self.state.quality_weighting))
+                            .on_hover_text(t!("processoptions.quality_weighting_hint"));
+                        ui.add_enabled(
+                            self.state.quality_weighting,
+                            egui::DragValue::new(&mut self.state.quality_weight_power)
+                                .clamp_range(0.5..=8.0)
+                                .speed(0.05)
+                                .prefix(t!("processoptions.quality_weight_power")),
+                        )
+                        .on_hover_text(t!("processoptions.quality_weight_power_hint"));
+                        warning_marker(ui, warnings, ValidatedOption::QualityWeighting);
+                    });
+                    ui.end_row();
+                }
+            });
+    }
+
+    fn corrections_options_contents(
+        &mut self,
+        ui: &mut egui::Ui,
+        filter: &OptionFilter,
+        warnings: &[validation::OptionWarning],
+    ) {
+        egui::Grid::new("process_grid_options_corrections")
+            .num_columns(3)
+            .spacing([40.0, 4.0])
+            .striped(true)
+            .show(ui, |ui| {
+                if filter.shows("processoptions.decorrelated_colors") {
+                    self.option_label(ui, "processoptions.decorrelated_colors");
+                    ui.add(toggle(&mut self.state.decorrelated_colors));
+                    ui.end_row();
+                }
+
+                if filter.shows("processoptions.limb_dark_correction") {
+                    self.option_label(ui, "processoptions.limb_dark_correction");
+                    ui.horizontal(|ui| {
+                        ui.add(toggle(&mut self.state.ld_correction));
+                        warning_marker(ui, warnings, ValidatedOption::LimbDarkeningCorrection);
+                    });
+                    ui.end_row();
+                }
+
+                if filter.shows("processoptions.ldc_coefficient") {
+                    ui.add_enabled_ui(self.state.ld_correction, |ui| {
+                        self.option_label(ui, "processoptions.ldc_coefficient");
+                    });
+
+                    ui.add_enabled_ui(self.state.ld_correction, |ui| {
+                        ui.add(egui::DragValue::new(&mut self.state.ld_coefficient).speed(0.1));
+                    });
+                    ui.end_row();
+                }
+
+                if filter.shows("processoptions.ldc_solar_radius") {
+                    ui.add_enabled_ui(self.state.ld_correction, |ui| {
+                        self.option_label(ui, "processoptions.ldc_solar_radius");
+                    });
+                    ui.add_enabled_ui(self.state.ld_correction, |ui| {
+                        ui.add(
+                            egui::DragValue::new(&mut self.state.solar_radius_pixels).speed(1.0),
+                        );
+                        warning_marker(ui, warnings, ValidatedOption::SolarRadius);
+                    });
+                    ui.end_row();
+                }
+            });
+    }
+
+    fn framing_options_contents(
+        &mut self,
+        ui: &mut egui::Ui,
+        filter: &OptionFilter,
+        warnings: &[validation::OptionWarning],
+    ) {
+        let refresh_icon = egui::include_image!("../assets/refresh.svg");
+        egui::Grid::new("process_grid_options_framing")
+            .num_columns(3)
+            .spacing([40.0, 4.0])
+            .striped(true)
+            .show(ui, |ui| {
+                if filter.shows("processoptions.crop_width") {
+                    self.option_label(ui, "processoptions.crop_width");
+                    ui.horizontal(|ui| {
+                        ui.add(egui::DragValue::new(&mut self.state.crop_width).speed(1.0));
+                        warning_marker(ui, warnings, ValidatedOption::CropWidth);
+                    });
+                    if !self.preview_light.is_empty()
+                        && ui
+                            .add(egui::Button::image_and_text(
+                                refresh_icon.clone(),
+                                t!("processoptions.reset"),
+                            ))
+                            .clicked()
+                    {
+                        if let Ok(size) = self.preview_light.size() {
+                            self.state.crop_width = size[0];
+                        }
+                    }
+                    ui.end_row();
+                }
+
+                if filter.shows("processoptions.crop_height") {
+                    self.option_label(ui, "processoptions.crop_height");
+                    ui.horizontal(|ui| {
+                        ui.add(egui::DragValue::new(&mut self.state.crop_height).speed(1.0));
+                        warning_marker(ui, warnings, ValidatedOption::CropHeight);
+                    });
+                    if self.state.light.is_some()
+                        && ui
+                            .add(egui::Button::image_and_text(
+                                refresh_icon,
+                                t!("processoptions.reset"),
+                            ))
+                            .clicked()
+                    {
+                        if let Ok(size) = self.preview_light.size() {
+                            self.state.crop_height = size[1];
+                        }
+                    }
+
+                    ui.end_row();
+                }
+
+                if filter.shows("processoptions.horiz_offset") {
+                    self.option_label(ui, "processoptions.horiz_offset");
+                    ui.add(egui::DragValue::new(&mut self.state.horiz_offset).speed(1.0));
+                    ui.end_row();
+                }
+
+                if filter.shows("processoptions.vert_offset") {
+                    self.option_label(ui, "processoptions.vert_offset");
+                    ui.add(egui::DragValue::new(&mut self.state.vert_offset).speed(1.0));
+                    ui.end_row();
+                }
+
+                if filter.shows("processoptions.frame_orientation") {
+                    self.option_label(ui, "processoptions.frame_orientation");
+                    ui.horizontal(|ui| {
+                        ui.selectable_value(
+                            &mut self.state.frame_orientation,
+                            FrameOrientation::Normal,
+                            t!("processoptions.orientation_normal"),
+                        );
+                        ui.selectable_value(
+                            &mut self.state.frame_orientation,
+                            FrameOrientation::FlipHorizontal,
+                            t!("processoptions.orientation_flip_horizontal"),
+                        );
+                        ui.selectable_value(
+                            &mut self.state.frame_orientation,
+                            FrameOrientation::FlipVertical,
+                            t!("processoptions.orientation_flip_vertical"),
+                        );
+                        ui.selectable_value(
+                            &mut self.state.frame_orientation,
+                            FrameOrientation::Rotate180,
+                            t!("processoptions.orientation_rotate_180"),
+                        );
+                    });
+                    ui.end_row();
+                }
+
+                if filter.shows("processoptions.filename_free_text") {
+                    self.option_label(ui, "processoptions.filename_free_text");
+                    ui.add(
+                        egui::TextEdit::singleline(&mut self.state.freetext)
+                            .hint_text(t!("processoptions.filename_hint")),
+                    );
+                    ui.end_row();
+                }
+            });
+    }
+
+    fn calibration_options_contents(&mut self, ui: &mut egui::Ui, filter: &OptionFilter) {
+        egui::Grid::new("process_grid_options_calibration")
+            .num_columns(3)
+            .spacing([40.0, 4.0])
+            .striped(true)
+            .show(ui, |ui| {
+                if filter.shows("processoptions.save_masters") {
+                    self.option_label(ui, "processoptions.save_masters");
+                    ui.add(toggle(&mut self.state.save_masters));
+                    ui.end_row();
+                }
+
+                if filter.shows("processoptions.masters_folder") {
+                    if self.state.save_masters {
+                        self.option_label(ui, "processoptions.masters_folder");
+                        ui.horizontal(|ui| {
+                            match &self.state.masters_dir {
+                                Some(masters_dir) => {
+                                    ui.monospace(masters_dir);
+                                }
+                                None => {
+                                    ui.weak(t!("processoptions.masters_folder_output"));
+                                }
+                            }
+                            if ui.button(t!("output.open_folder")).clicked() {
+                                if let Some(path) = rfd::FileDialog::new().pick_folder() {
+                                    self.state.masters_dir = Some(path.display().to_string());
+                                }
+                            }
+                            if self.state.masters_dir.is_some()
+                                && ui
+                                    .button(t!("processoptions.masters_folder_reset"))
+                                    .clicked()
+                            {
+                                self.state.masters_dir = None;
+                            }
+                        });
+                        ui.end_row();
+                    }
+
+                    if filter.shows("processoptions.masters_format") {
+                        self.option_label(ui, "processoptions.masters_format");
+                        ui.horizontal(|ui| {
+                            for format in MasterFormat::ALL {
+                                ui.selectable_value(
+                                    &mut self.state.masters_format,
+                                    format,
+                                    format.as_str(),
+                                );
+                            }
+                        });
+                        ui.end_row();
+                    }
+                }
+
+                if filter.shows("processoptions.calibration_max_frames") {
+                    self.option_label(ui, "processoptions.calibration_max_frames");
+                    ui.add(
+                        egui::DragValue::new(&mut self.state.calibration_max_frames).speed(10.0),
+                    )
+                    .on_hover_text(t!("processoptions.calibration_max_frames_hint"));
+                    ui.end_row();
+                }
+
+                if filter.shows("processoptions.dark_scale") {
+                    self.option_label(ui, "processoptions.dark_scale");
+                    ui.horizontal(|ui| {
+                        ui.selectable_value(
+                            &mut self.state.dark_scale_mode,
+                            DarkScaleMode::Off,
+                            t!("processoptions.dark_scale_off"),
+                        );
+                        ui.selectable_value(
+                            &mut self.state.dark_scale_mode,
+                            DarkScaleMode::Manual,
+                            t!("processoptions.dark_scale_manual"),
+                        );
+                        ui.selectable_value(
+                            &mut self.state.dark_scale_mode,
+                            DarkScaleMode::Automatic,
+                            t!("processoptions.dark_scale_automatic"),
+                        )
+                        .on_hover_text(t!("processoptions.dark_scale_automatic_hint"));
+                    });
+                    ui.end_row();
+                }
+
+                if self.state.dark_scale_mode == DarkScaleMode::Manual
+                    && filter.shows("processoptions.dark_scale_factor")
+                {
+                    self.option_label(ui, "processoptions.dark_scale_factor");
+                    ui.add(
+                        egui::Slider::new(&mut self.state.dark_scale_factor, 0.0..=3.0)
+                            .fixed_decimals(3),
+                    )
+                    .on_hover_text(t!("processoptions.dark_scale_factor_hint"));
+                    ui.end_row();
+                }
+
+                if filter.shows("processoptions.dark_scaling") {
+                    self.option_label(ui, "processoptions.dark_scaling");
+                    ui.add(toggle(&mut self.state.dark_scaling))
+                        .on_hover_text(t!("processoptions.dark_scaling_hint"));
+                    ui.end_row();
+                }
+
+                if filter.shows("processoptions.exposure_tolerance") {
+                    self.option_label(ui, "processoptions.exposure_tolerance");
+                    ui.add(
+                        egui::DragValue::new(&mut self.state.exposure_tolerance)
+                            .clamp_range(0.01..=10.0)
+                            .speed(0.01),
+                    );
+                    ui.end_row();
+                }
+            });
+    }
+
+    /// Builds the native window title from the application name, the running task and its
+    /// progress, and the name of the open project or, lacking one, the light file.
+    fn assemble_window_title(&self) -> String {
+        let mut parts = vec![t!("apptitle").to_string()];
+
+        if let Some(status) = get_task_status() {
+            let TaskStatus::TaskPercentage(task_name, _, _) = &status;
+            if let Some(queue) = get_queue_progress() {
+                if queue.job_count > 1 {
+                    parts.push(queue.describe());
+                }
+            }
+            parts.push(format!(
+                "{} {:.0}%",
+                task_name.to_lowercase(),
+                status.fraction() * 100.0
+            ));
+            if cancel::is_paused() {
+                parts.push(t!("paused").to_string());
+            }
+        }
+
+        if let Some(project) = &self.state.window.current_project {
+            if let Some(stem) = project.file_stem() {
+                parts.push(stem.to_string_lossy().to_string());
+            }
+        } else if let Some(light) = &self.state.light {
+            if let Some(stem) = Path::new(light).file_stem() {
+                parts.push(stem.to_string_lossy().to_string());
+            }
+        }
+
+        parts.join(" — ")
+    }
+
+    /// Hides the window, leaving a tray icon showing the progress of the running task
+    fn enter_background(&mut self, ctx: &egui::Context) {
+        let icon = load_icon();
+        let image = tray::TrayIconImage {
+            rgba: icon.rgba,
+            width: icon.width,
+            height: icon.height,
+        };
+        match tray::Tray::show(&image, &self.window_title) {
+            Ok(tray) => {
+                self.tray = Some(tray);
+                self.background_task_was_running = true;
+                ctx.send_viewport_cmd(egui::ViewportCommand::Visible(false));
+            }
+            Err(why) => {
+                self.error_message = Some(t!("tray.failed", error = why.to_string()).to_string())
+            }
+        }
+    }
+
+    /// Removes the tray icon and brings the window back
+    fn leave_background(&mut self, ctx: &egui::Context) {
+        self.tray = None;
+        ctx.send_viewport_cmd(egui::ViewportCommand::Visible(true));
+        ctx.send_viewport_cmd(egui::ViewportCommand::Focus);
+    }
+
+    /// Handles tray menu selections, and notifies when the background task finishes
+    fn background_update(&mut self, ctx: &egui::Context, task_running: bool) {
+        match tray::poll_action() {
+            Some(tray::TrayAction::ShowWindow) => self.leave_background(ctx),
+            Some(tray::TrayAction::Cancel) => cancel::set_request_cancel(),
+            None => {}
+        }
+
+        if self.background_task_was_running && !task_running {
+            match &self.error_message {
+                Some(error) => {
+                    tray::notify(&t!("tray.failed_title"), error);
+                    // The error dialog needs the window to be seen
+                    self.leave_background(ctx);
+                }
+                None => tray::notify(&t!("tray.completed_title"), &t!("tray.completed")),
+            }
+        }
+        self.background_task_was_running = task_running;
+
+        // Menu selections are only picked up as the window updates
+        ctx.request_repaint_after(Duration::from_millis(250));
+    }
+
+    fn enable_start(&self) -> bool {
+        self.state.light.is_some() && self.state.output_dir.is_some()
+    }
+
+    fn run(&mut self, output_filename: PathBuf) {
+        self.run_with_state(self.state.clone(), output_filename);
+    }
+
+    fn run_with_state(&mut self, state_copy: ApplicationState, output_filename: PathBuf) {
+        if let Err(why) = lightmemory::remember(&state_copy) {
+            warn!("Failed to remember light file options: {:?}", why);
+        }
+        set_task_status(&t!("tasks.starting"), 1, 1);
+        #[cfg(feature = "notifications")]
+        let notification_settings = self.state.window.notifications.clone();
+
+        tokio::spawn(async move {
+            {
+                let results = process::run_async(output_filename, state_copy)
+                    .await
+                    .unwrap_or_else(|why| RunResultsContainer::failed(ProcessError::from(why)));
+                #[cfg(feature = "notifications")]
+                notifications::send(
+                    &notification_settings,
+                    notifications::Notification::for_run(&results),
+                );
+                IMAGE_RESULTS.lock().unwrap().results = Some(results);
+                set_task_completed();
+            }
+        });
+    }
+
+    /// Options for starting the run later, after a delay or at a time of day
+    fn deferred_start_contents(&mut self, ui: &mut egui::Ui) {
+        egui::CollapsingHeader::new(t!("schedule.start_later"))
+            .id_source("deferred_start")
+            .show(ui, |ui| {
+                let deferred = &mut self.deferred_start;
+                deferred.time.ui(ui);
+                if ui.button(t!("schedule.schedule")).clicked() {
+                    if let Err(why) = deferred.schedule(&self.state) {
+                        self.error_message = Some(user_message(&why));
+                    }
+                }
+            });
+    }
+
+    /// Countdown shown in place of the Start button while a run is scheduled
+    fn scheduled_start_contents(&mut self, ui: &mut egui::Ui) {
+        let Some(scheduled) = &self.deferred_start.scheduled else {
+            return;
+        };
+        ui.label(t!(
+            "schedule.starting_at",
+            time = scheduled.start_at.format("%H:%M:%S")
+        ));
+        ui.heading(scheduled.countdown());
+        ui.horizontal(|ui| {
+            if ui.button(t!("schedule.start_now")).clicked() {
+                if let Some(scheduled) = self.deferred_start.scheduled.take() {
+                    self.run_with_state(scheduled.state, scheduled.output_filename);
+                }
+            }
+            if ui.button(t!("schedule.cancel")).clicked() {
+                self.deferred_start.scheduled = None;
+            }
+        });
+    }
+
+    /// Queues a job for each light file in `folder`, with the current options, and runs the queue
+    fn process_folder(&mut self, folder: &Path) {
+        match jobqueue::add_folder_jobs(&self.state, folder) {
+            Ok(count) => {
+                info!("Queued {} files from {}", count, folder.display());
+                self.state.window.selected_preview_pane = PreviewPane::Queue;
+                self.run_queue();
+            }
+            Err(why) => self.error_message = Some(user_message(&why)),
+        }
+    }
+
+    fn run_queue(&mut self) {
+        let concurrent_jobs = self.state.window.concurrent_jobs;
+        set_task_status(&t!("tasks.starting"), 1, 1);
+        #[cfg(feature = "notifications")]
+        let notification_settings = self.state.window.notifications.clone();
+        #[cfg(feature = "notifications")]
+        let finished_before: Vec<usize> = jobqueue::jobs()
+            .iter()
+            .filter(|j| j.status != jobqueue::JobStatus::Pending)
+            .map(|j| j.id)
+            .collect();
+
+        tokio::spawn(async move {
+            {
+                jobqueue::run_queue_async(concurrent_jobs, |results| {
+                    IMAGE_RESULTS.lock().unwrap().results = Some(results);
+                })
+                .await;
+                #[cfg(feature = "notifications")]
+                {
+                    let run: Vec<jobqueue::Job> = jobqueue::jobs()
+                        .into_iter()
+                        .filter(|j| !finished_before.contains(&j.id))
+                        .collect();
+                    notifications::send(
+                        &notification_settings,
+                        notifications::Notification::for_queue(&run),
+                    );
+                }
+                set_task_completed();
+            }
+        });
+    }
+
+    fn run_dry(&mut self) {
+        let state_copy = self.state.clone();
+        set_task_status(&t!("tasks.starting"), 1, 1);
+
+        tokio::spawn(async move {
+            {
+                let results = process::dryrun::run_dry_async(state_copy).await;
+                let mut dry_run = DRY_RUN_RESULTS.lock().unwrap();
+                match results {
+                    Ok(results) => dry_run.results = Some(results),
+                    Err(why) => match ProcessError::from(why) {
+                        ProcessError::Cancelled => {}
+                        error => {
+                            error!("Dry run failed: {}", error);
+                            dry_run.error = Some(error.describe());
+                        }
+                    },
+                }
+                set_task_completed();
+            }
+        });
+    }
+
+    fn dry_run_window(&mut self, ctx: &egui::Context) {
+        let mut open = self.dry_run_results.is_some();
+        if let Some(results) = &self.dry_run_results {
+            egui::Window::new(t!("dryrun.title"))
+                .open(&mut open)
+                .resizable(false)
+                .show(ctx, |ui| {
+                    egui::Grid::new("dry_run_results")
+                        .num_columns(2)
+                        .spacing([40.0, 4.0])
+                        .striped(true)
+                        .show(ui, |ui| {
+                            ui.label(t!("dryrun.frames_analyzed"));
+                            ui.label(results.frames_analyzed.to_string());
+                            ui.end_row();
+
+                            ui.label(t!("dryrun.frames_used"));
+                            ui.label(results.frames_used.to_string());
+                            ui.end_row();
+
+                            ui.label(t!("dryrun.sigma_range"));
+                            ui.label(format!(
+                                "{:.3} – {:.3}",
+                                results.sigma_min, results.sigma_max
+                            ));
+                            ui.end_row();
+
+                            ui.label(t!("dryrun.rotation_span"));
+                            ui.label(format!(
+                                "{:.3}° ({:.3}° – {:.3}°)",
+                                results.rotation_max - results.rotation_min,
+                                results.rotation_min,
+                                results.rotation_max
+                            ));
+                            ui.end_row();
+
+                            ui.label(t!("dryrun.elapsed"));
+                            ui.label(format!("{:.1} s", results.elapsed.as_secs_f64()));
+                            ui.end_row();
+
+                            ui.label(t!("dryrun.estimated_stacking"));
+                            ui.label(format!("{:.1} s", results.estimated_stacking.as_secs_f64()));
+                            ui.end_row();
+                        });
+                    for warning in &results.warnings {
+                        ui.colored_label(ui.visuals().warn_fg_color, warning);
+                    }
+                });
+        }
+        if !open {
+            self.dry_run_results = None;
+        }
+    }
+
+    fn run_reexport(&mut self, files: Vec<PathBuf>, preset: &str, suffix: String) {
+        let settings = match filters::load_filter_preset(preset) {
+            Ok(settings) => settings,
+            Err(why) => {
+                self.error_message = Some(user_message(&why));
+                return;
+            }
+        };
+        set_task_status(&t!("tasks.starting"), 1, 1);
+
+        tokio::spawn(async move {
+            {
+                let results = filters::reexport_async(files, settings, suffix).await;
+                let mut reexport = REEXPORT_RESULTS.lock().unwrap();
+                match results {
+                    Ok(written) => reexport.written = Some(written),
+                    Err(why) => reexport.error = Some(user_message(&why)),
+                }
+                set_task_completed();
+            }
+        });
+    }
+
+    /// Window for choosing previously stacked images and the filter preset to re-export them with
+    fn reexport_window(&mut self, ctx: &egui::Context) {
+        let mut open = self.reexport.is_some();
+        let mut start = false;
+        let last_folder = self
+            .state
+            .output_dir
+            .clone()
+            .map(PathBuf::from)
+            .unwrap_or_else(|| self.state.window.get_last_opened_folder());
+        if let Some(request) = &mut self.reexport {
+            egui::Window::new(t!("reexport.title"))
+                .open(&mut open)
+                .resizable(false)
+                .show(ctx, |ui| {
+                    ui.label(t!("reexport.description"));
+                    ui.separator();
+
+                    let mut removed = None;
+                    egui::ScrollArea::vertical()
+                        .max_height(200.0)
+                        .show(ui, |ui| {
+                            request.files.iter().enumerate().for_each(|(i, file)| {
+                                ui.horizontal(|ui| {
+                                    if ui.small_button("✖").clicked() {
+                                        removed = Some(i);
+                                    }
+                                    ui.monospace(file.to_string_lossy());
+                                });
+                            });
+                        });
+                    if let Some(i) = removed {
+                        request.files.remove(i);
+                    }
+                    if ui.button(t!("reexport.add_images")).clicked() {
+                        if let Some(files) = rfd::FileDialog::new()
+                            .set_title(t!("reexport.add_images"))
+                            .set_directory(&last_folder)
+                            .add_filter("TIFF", &["tif", "tiff"])
+                            .pick_files()
+                        {
+                            files.into_iter().for_each(|f| {
+                                if !request.files.contains(&f) {
+                                    request.files.push(f);
+                                }
+                            });
+                        }
+                    }
+                    ui.separator();
+
+                    egui::Grid::new("reexport_options")
+                        .num_columns(2)
+                        .spacing([40.0, 4.0])
+                        .show(ui, |ui| {
+                            ui.label(t!("reexport.preset"));
+                            egui::ComboBox::from_id_source("reexport_preset")
+                                .selected_text(request.preset.clone().unwrap_or_default())
+                                .show_ui(ui, |ui| {
+                                    for name in filters::list_filter_presets() {
+                                        let selected = request.preset.as_ref() == Some(&name);
+                                        if ui.selectable_label(selected, &name).clicked() {
+                                            request.preset = Some(name);
+                                        }
+                                    }
+                                });
+                            ui.end_row();
+
+                            ui.label(t!("reexport.suffix"));
+                            ui.text_edit_singleline(&mut request.suffix);
+                            ui.end_row();
+                        });
+
+                    ui.add_enabled_ui(
+                        !is_task_running()
+                            && !request.files.is_empty()
+                            && request.preset.is_some()
+                            && !request.suffix.trim().is_empty(),
+                        |ui| {
+                            if ui.button(t!("reexport.start")).clicked() {
+                                start = true;
+                            }
+                        },
+                    );
+                });
+        }
+        if start {
+            if let Some(request) = self.reexport.take() {
+                let preset = request.preset.unwrap_or_default();
+                self.run_reexport(request.files, &preset, request.suffix);
+            }
+        } else if !open {
+            self.reexport = None;
+        }
+    }
+
+    /// Starts the analysis and threshold test chosen to run on a newly selected light file, once
+    /// it's loaded and any running task is done
+    fn start_auto_analysis(&mut self, ctx: &egui::Context, task_running: bool) {
+        let window = &self.state.window;
+        if !(window.auto_analysis || window.auto_threshold_test)
+            || task_running
+            || self.preview_light.is_empty()
+            || self.state.light == self.auto_analyzed_light
+        {
+            return;
+        }
+        self.auto_analyzed_light = self.state.light.clone();
+
+        if self.state.window.auto_threshold_test {
+            info!("Running threshold test on the selected light file");
+            match self.preview_light.threshold_test(ctx, &self.state) {
+                Ok(_) => self.state.window.selected_preview_pane = PreviewPane::Light,
+                Err(why) => warn!("Automatic threshold test failed: {}", why),
+            }
+        }
+        if self.state.window.auto_analysis {
+            info!("Analyzing the selected light file");
+            self.run_analysis();
+        }
+    }
+
     fn run_analysis(&mut self) {
         let state_copy = self.state.clone();
         set_task_status(&t!("tasks.starting"), 1, 1);
@@ -0,0 +1,243 @@
+//! Watches system memory so previews can be scaled back before the application runs out of
+//! memory, which otherwise gets it killed with no chance to save anything.
+
+use std::time::{Duration, Instant};
+
+/// How often memory use is read
+const CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Fraction of total memory left available below which memory is considered low
+const LOW_FRACTION: f64 = 0.1;
+
+/// Fraction of total memory available at which memory is no longer considered low. Higher than
+/// `LOW_FRACTION` so freeing previews doesn't immediately load them again.
+const RECOVERED_FRACTION: f64 = 0.2;
+
+/// Binning factor applied to preview textures while memory is low
+pub const LOW_MEMORY_PREVIEW_DOWNSCALE: usize = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MemoryStatus {
+    /// Resident memory of this process
+    pub process_bytes: u64,
+    pub available_bytes: u64,
+    pub total_bytes: u64,
+}
+
+impl MemoryStatus {
+    pub fn available_fraction(&self) -> f64 {
+        if self.total_bytes == 0 {
+            1.0
+        } else {
+            self.available_bytes as f64 / self.total_bytes as f64
+        }
+    }
+}
+
+/// Reads the process and system memory use. None if it can't be read on this platform.
+pub fn read_memory_status() -> Option<MemoryStatus> {
+    platform::read_memory_status()
+}
+
+/// Tracks whether memory is low. Updated with the UI.
+#[derive(Default)]
+pub struct MemoryMonitor {
+    last_check: Option<Instant>,
+    status: Option<MemoryStatus>,
+    low: bool,
+}
+
+impl MemoryMonitor {
+    pub fn is_low(&self) -> bool {
+        self.low
+    }
+
+    pub fn status(&self) -> Option<MemoryStatus> {
+        self.status
+    }
+
+    /// Reads memory use if it's due. Returns true if memory has just become low.
+    pub fn update(&mut self) -> bool {
+        if self
+            .last_check
+            .map(|t| t.elapsed() < CHECK_INTERVAL)
+            .unwrap_or(false)
+        {
+            return false;
+        }
+        self.last_check = Some(Instant::now());
+
+        self.status = read_memory_status();
+        let Some(status) = self.status else {
+            return false;
+        };
+        let was_low = self.low;
+        self.low = if was_low {
+            status.available_fraction() < RECOVERED_FRACTION
+        } else {
+            status.available_fraction() < LOW_FRACTION
+        };
+
+        if self.low && !was_low {
+            warn!(
+                "Memory is low: {} MB available of {} MB, {} MB used by SolHat",
+                status.available_bytes / 1048576,
+                status.total_bytes / 1048576,
+                status.process_bytes / 1048576
+            );
+        } else if was_low && !self.low {
+            info!("Memory is no longer low");
+        }
+        self.low && !was_low
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod platform {
+    use super::MemoryStatus;
+
+    /// Value in bytes of a `Name:    1234 kB` line from a /proc status file
+    fn proc_value(text: &str, name: &str) -> Option<u64> {
+        text.lines()
+            .find_map(|line| line.strip_prefix(name)?.strip_prefix(':'))
+            .and_then(|value| value.split_whitespace().next())
+            .and_then(|kb| kb.parse::<u64>().ok())
+            .map(|kb| kb * 1024)
+    }
+
+    pub fn read_memory_status() -> Option<MemoryStatus> {
+        let meminfo = std::fs::read_to_string("/proc/meminfo").ok()?;
+        let status = std::fs::read_to_string("/proc/self/status").ok()?;
+        Some(MemoryStatus {
+            process_bytes: proc_value(&status, "VmRSS")?,
+            available_bytes: proc_value(&meminfo, "MemAvailable")?,
+            total_bytes: proc_value(&meminfo, "MemTotal")?,
+        })
+    }
+}
+
+#[cfg(target_os = "macos")]
+mod platform {
+    use std::process::Command;
+
+    use super::MemoryStatus;
+
+    fn command_output(program: &str, args: &[&str]) -> Option<String> {
+        let output = Command::new(program).args(args).output().ok()?;
+        Some(String::from_utf8_lossy(&output.stdout).to_string())
+    }
+
+    /// Free and inactive memory from `vm_stat`, whose first line gives the page size, e.g.
+    /// `Mach Virtual Memory Statistics: (page size of 16384 bytes)`
+    fn available_bytes() -> Option<u64> {
+        let text = command_output("vm_stat", &[])?;
+        let page_size: u64 = text
+            .split("page size of ")
+            .nth(1)?
+            .split_whitespace()
+            .next()?
+            .parse()
+            .ok()?;
+        let pages = |name: &str| -> Option<u64> {
+            text.lines()
+                .find_map(|line| line.strip_prefix(name))
+                .and_then(|value| value.trim().trim_end_matches('.').parse().ok())
+        };
+        Some((pages("Pages free:")? + pages("Pages inactive:").unwrap_or(0)) * page_size)
+    }
+
+    pub fn read_memory_status() -> Option<MemoryStatus> {
+        let pid = std::process::id().to_string();
+        let rss_kb: u64 = command_output("ps", &["-o", "rss=", "-p", &pid])?
+            .trim()
+            .parse()
+            .ok()?;
+        let total_bytes = command_output("sysctl", &["-n", "hw.memsize"])?
+            .trim()
+            .parse()
+            .ok()?;
+        Some(MemoryStatus {
+            process_bytes: rss_kb * 1024,
+            available_bytes: available_bytes()?,
+            total_bytes,
+        })
+    }
+}
+
+#[cfg(target_os = "windows")]
+mod platform {
+    use std::ffi::c_void;
+
+    use super::MemoryStatus;
+
+    #[repr(C)]
+    struct MemoryStatusEx {
+        /// Size of the struct, read by Windows
+        _length: u32,
+        _memory_load: u32,
+        total_phys: u64,
+        avail_phys: u64,
+        _total_page_file: u64,
+        _avail_page_file: u64,
+        _total_virtual: u64,
+        _avail_virtual: u64,
+        _avail_extended_virtual: u64,
+    }
+
+    #[repr(C)]
+    #[derive(Default)]
+    struct ProcessMemoryCounters {
+        cb: u32,
+        _page_fault_count: u32,
+        _peak_working_set_size: usize,
+        working_set_size: usize,
+        _quota_peak_paged_pool_usage: usize,
+        _quota_paged_pool_usage: usize,
+        _quota_peak_non_paged_pool_usage: usize,
+        _quota_non_paged_pool_usage: usize,
+        _pagefile_usage: usize,
+        _peak_pagefile_usage: usize,
+    }
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn GlobalMemoryStatusEx(buffer: *mut MemoryStatusEx) -> i32;
+        fn GetCurrentProcess() -> *mut c_void;
+        fn K32GetProcessMemoryInfo(
+            process: *mut c_void,
+            counters: *mut ProcessMemoryCounters,
+            cb: u32,
+        ) -> i32;
+    }
+
+    pub fn read_memory_status() -> Option<MemoryStatus> {
+        let mut memory = MemoryStatusEx {
+            _length: std::mem::size_of::<MemoryStatusEx>() as u32,
+            _memory_load: 0,
+            total_phys: 0,
+            avail_phys: 0,
+            _total_page_file: 0,
+            _avail_page_file: 0,
+            _total_virtual: 0,
+            _avail_virtual: 0,
+            _avail_extended_virtual: 0,
+        };
+        let mut counters = ProcessMemoryCounters {
+            cb: std::mem::size_of::<ProcessMemoryCounters>() as u32,
+            ..Default::default()
+        };
+        let cb = counters.cb;
+        unsafe {
+            if GlobalMemoryStatusEx(&mut memory) == 0
+                || K32GetProcessMemoryInfo(GetCurrentProcess(), &mut counters, cb) == 0
+            {
+                return None;
+            }
+        }
+        Some(MemoryStatus {
+            process_bytes: counters.working_set_size as u64,
+            available_bytes: memory.avail_phys,
+            total_bytes: memory.total_phys,
+        })
+    }
+}
@@ -0,0 +1,107 @@
+//! Text templates describing a result, such as captions and published descriptions. Tokens in
+//! braces, e.g. `{target}`, are replaced with values from the output's FITS keywords.
+
+use std::path::Path;
+
+use anyhow::Result;
+
+use crate::fits::{FitsKeyword, FitsValue};
+use crate::template::{self, Separators};
+
+/// Tokens recognized in a template
+pub const TOKENS: &[&str] = &[
+    "target",
+    "date",
+    "time",
+    "frames",
+    "algorithm",
+    "drizzle",
+    "instrument",
+    "telescope",
+    "observer",
+    "notes",
+    "filename",
+    "software",
+];
+
+/// Values of the template tokens for a result
+#[derive(Debug, Clone, Default)]
+pub struct ResultMetadata {
+    values: Vec<(&'static str, String)>,
+}
+
+impl ResultMetadata {
+    /// Metadata of an output written with `fits_header`, stacked from `frames_used` frames
+    pub fn new(
+        fits_header: &[FitsKeyword],
+        frames_used: usize,
+        session_notes: &str,
+        output_filename: Option<&Path>,
+    ) -> ResultMetadata {
+        let keyword = |name: &str| {
+            fits_header
+                .iter()
+                .find(|k| k.keyword == name)
+                .map(|k| match &k.value {
+                    FitsValue::Text(s) => s.clone(),
+                    FitsValue::Integer(i) => i.to_string(),
+                    FitsValue::Real(r) => r.to_string(),
+                    FitsValue::Logical(b) => b.to_string(),
+                })
+                .unwrap_or_default()
+        };
+        // DATE-OBS is written as e.g. 2024-04-08T18:21:04.512
+        let observed = Some(keyword("DATE-OBS"))
+            .filter(|d| !d.is_empty())
+            .unwrap_or_else(|| chrono::Utc::now().format("%Y-%m-%dT%H:%M:%S").to_string());
+        let (date, time) = observed.split_once('T').unwrap_or((&observed, ""));
+        let filename = output_filename
+            .and_then(|p| p.file_stem())
+            .map(|s| s.to_string_lossy().to_string())
+            .unwrap_or_default();
+
+        ResultMetadata {
+            values: vec![
+                ("target", keyword("OBJECT")),
+                ("date", date.to_owned()),
+                (
+                    "time",
+                    time.split('.').next().unwrap_or_default().to_owned(),
+                ),
+                ("frames", frames_used.to_string()),
+                ("algorithm", keyword("STACKALG")),
+                ("drizzle", keyword("DRIZZLE")),
+                ("instrument", keyword("INSTRUME")),
+                ("telescope", keyword("TELESCOP")),
+                ("observer", keyword("OBSERVER")),
+                ("notes", session_notes.trim().to_owned()),
+                ("filename", filename),
+                ("software", keyword("SWCREATE")),
+            ],
+        }
+    }
+
+    /// Value of `token`, empty if it's unknown
+    pub fn value(&self, token: &str) -> &str {
+        self.values
+            .iter()
+            .find(|(t, _)| *t == token)
+            .map(|(_, v)| v.as_str())
+            .unwrap_or_default()
+    }
+
+    /// Replaces each token in `template` with its value
+    pub fn fill(&self, template: &str) -> Result<String> {
+        template::render(template, Separators::Keep, |token| {
+            TOKENS
+                .contains(&token)
+                .then(|| self.value(token).to_owned())
+        })
+        .map(|filled| filled.trim().to_owned())
+    }
+}
+
+/// Checks `template` for unknown tokens and unbalanced braces
+pub fn validate(template: &str) -> Result<()> {
+    template::validate(template, TOKENS)
+}
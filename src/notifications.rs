@@ -0,0 +1,405 @@
+//! Webhook and email notifications of finished runs and queues, for batches left running
+//! unattended. Both are sent through curl, which must be installed and on the PATH, so HTTPS
+//! webhooks and SMTP servers using TLS work without further dependencies.
+
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+use anyhow::{anyhow, Result};
+use egui::Ui;
+use serde::{Deserialize, Serialize};
+
+use crate::curlconfig::CredentialsFile;
+use crate::jobqueue::{Job, JobStatus};
+use crate::process::error::ProcessError;
+use crate::process::report;
+use crate::process::RunResultsContainer;
+use crate::toggle::toggle;
+
+/// Longest a webhook post or email may take, in seconds
+const SEND_TIMEOUT: &str = "60";
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+#[serde(default)]
+pub struct NotificationSettings {
+    pub on_success: bool,
+    pub on_failure: bool,
+    /// URL a JSON summary is posted to. Not posted if empty.
+    pub webhook_url: String,
+    /// SMTP server, e.g. `smtps://smtp.example.com:465`. No email is sent if empty.
+    pub smtp_url: String,
+    /// Refuse to send without TLS, upgrading `smtp://` connections with STARTTLS
+    pub smtp_require_tls: bool,
+    pub smtp_username: String,
+    /// Saved unencrypted with the window state and autosave, whose files only the user can read.
+    /// Given to curl through a `CredentialsFile`, never on its command line.
+    pub smtp_password: String,
+    pub email_from: String,
+    /// Recipients, separated by commas
+    pub email_to: String,
+}
+
+impl Default for NotificationSettings {
+    fn default() -> Self {
+        NotificationSettings {
+            on_success: true,
+            on_failure: true,
+            webhook_url: String::new(),
+            smtp_url: String::new(),
+            smtp_require_tls: true,
+            smtp_username: String::new(),
+            smtp_password: String::new(),
+            email_from: String::new(),
+            email_to: String::new(),
+        }
+    }
+}
+
+impl NotificationSettings {
+    fn has_webhook(&self) -> bool {
+        !self.webhook_url.trim().is_empty()
+    }
+
+    fn has_email(&self) -> bool {
+        !self.smtp_url.trim().is_empty() && !self.recipients().is_empty()
+    }
+
+    fn recipients(&self) -> Vec<&str> {
+        self.email_to
+            .split(',')
+            .map(|r| r.trim())
+            .filter(|r| !r.is_empty())
+            .collect()
+    }
+
+    fn wants(&self, notification: &Notification) -> bool {
+        (self.has_webhook() || self.has_email())
+            && if notification.succeeded {
+                self.on_success
+            } else {
+                self.on_failure
+            }
+    }
+}
+
+/// Summary of a finished run or queue
+pub struct Notification {
+    pub succeeded: bool,
+    pub subject: String,
+    pub body: String,
+    /// Files written, the outputs and their reports
+    pub outputs: Vec<PathBuf>,
+}
+
+impl Notification {
+    /// Summary of a single run. Cancelled runs aren't notified.
+    pub fn for_run(results: &RunResultsContainer) -> Option<Notification> {
+        if results.error == Some(ProcessError::Cancelled) {
+            return None;
+        }
+        let mut outputs = vec![];
+        let mut lines = vec![];
+        if let (true, Some(output_filename)) = (results.was_success, &results.output_filename) {
+            lines.push(t!("notifications.output", path = output_filename.display()).to_string());
+            lines
+                .push(t!("notifications.frames_used", count = results.num_frames_used).to_string());
+            if !results.resources.is_empty() {
+                lines.push(
+                    t!(
+                        "results.resources",
+                        memory = results.resources.peak_memory_text(),
+                        cpu = results.resources.cpu_time_text(),
+                        read = results.resources.bytes_read_text()
+                    )
+                    .to_string(),
+                );
+            }
+            outputs.push(output_filename.clone());
+            let report_path = report::report_path(output_filename);
+            if report_path.exists() {
+                lines.push(t!("notifications.report", path = report_path.display()).to_string());
+                outputs.push(report_path);
+            }
+            outputs.extend(results.saved_masters.iter().cloned());
+            lines.extend(results.warnings.iter().cloned());
+        }
+        if let Some(error) = &results.error {
+            lines.push(error.describe());
+        }
+        let succeeded = results.was_success;
+        Some(Notification {
+            succeeded,
+            subject: if succeeded {
+                t!("notifications.run_completed").to_string()
+            } else {
+                t!("notifications.run_failed").to_string()
+            },
+            body: lines.join("\n"),
+            outputs,
+        })
+    }
+
+    /// Summary of the queued `jobs` which were run, with the outcome of each
+    pub fn for_queue(jobs: &[Job]) -> Option<Notification> {
+        let count = |f: fn(&JobStatus) -> bool| jobs.iter().filter(|j| f(&j.status)).count();
+        let completed = count(|s| *s == JobStatus::Completed);
+        let failed = count(|s| matches!(s, JobStatus::Failed(_)));
+        if completed + failed == 0 {
+            return None;
+        }
+
+        let mut outputs = vec![];
+        let mut lines = vec![t!(
+            "notifications.queue_summary",
+            completed = completed,
+            failed = failed,
+            cancelled = count(|s| *s == JobStatus::Cancelled)
+        )
+        .to_string()];
+        jobs.iter().for_each(|job| {
+            let output_filename = job
+                .effective_state()
+                .assemble_output_filename()
+                .unwrap_or_else(|_| job.output_filename.clone());
+            match &job.status {
+                JobStatus::Completed => {
+                    lines.push(format!("✔ {}: {}", job.name(), output_filename.display()));
+                    outputs.push(output_filename);
+                }
+                JobStatus::Failed(why) => lines.push(format!("✖ {}: {}", job.name(), why)),
+                _ => {}
+            }
+        });
+        Some(Notification {
+            succeeded: failed == 0,
+            subject: if failed == 0 {
+                t!("notifications.queue_completed").to_string()
+            } else {
+                t!("notifications.queue_failed", count = failed).to_string()
+            },
+            body: lines.join("\n"),
+            outputs,
+        })
+    }
+
+    fn text(&self) -> String {
+        format!("{}\n\n{}", self.subject, self.body)
+    }
+}
+
+/// Runs curl with `args`, writing `input` to it. Fails with curl's error if it exits unsuccessfully.
+fn curl(args: &[String], input: &[u8]) -> Result<()> {
+    let mut curl = Command::new("curl")
+        .args([
+            "--silent",
+            "--show-error",
+            "--fail",
+            "--max-time",
+            SEND_TIMEOUT,
+        ])
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|why| {
+            warn!("Failed to start curl: {}", why);
+            anyhow!(t!("notifications.curl_missing").to_string())
+        })?;
+    curl.stdin
+        .take()
+        .ok_or_else(|| anyhow!("Failed to open curl's input"))?
+        .write_all(input)?;
+    let output = curl.wait_with_output()?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "curl failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(())
+}
+
+/// Posts `notification` as JSON. `text` is included for chat services which show it as the
+/// message.
+fn post_webhook(settings: &NotificationSettings, notification: &Notification) -> Result<()> {
+    let payload = serde_json::json!({
+        "event": if notification.succeeded { "completed" } else { "failed" },
+        "subject": notification.subject,
+        "text": notification.text(),
+        "outputs": notification.outputs,
+        "software": format!("SolHat {}", env!("CARGO_PKG_VERSION")),
+    });
+    curl(
+        &[
+            "--header".to_owned(),
+            "Content-Type: application/json".to_owned(),
+            "--data-binary".to_owned(),
+            "@-".to_owned(),
+            settings.webhook_url.trim().to_owned(),
+        ],
+        payload.to_string().as_bytes(),
+    )?;
+    info!("Posted notification to {}", settings.webhook_url.trim());
+    Ok(())
+}
+
+/// Sends `notification` as a plain text email
+fn send_email(settings: &NotificationSettings, notification: &Notification) -> Result<()> {
+    let recipients = settings.recipients();
+    let from = if settings.email_from.trim().is_empty() {
+        settings.smtp_username.trim()
+    } else {
+        settings.email_from.trim()
+    };
+    let mut body = notification.body.clone();
+    if !notification.outputs.is_empty() {
+        body.push_str("\n\n");
+        notification.outputs.iter().for_each(|output| {
+            body.push_str(&format!("{}\n", output.display()));
+        });
+    }
+    let message = format!(
+        "From: {}\r\nTo: {}\r\nSubject: {}\r\nDate: {}\r\nMIME-Version: 1.0\r\nContent-Type: text/plain; charset=utf-8\r\n\r\n{}\r\n",
+        from,
+        recipients.join(", "),
+        notification.subject,
+        chrono::Local::now().to_rfc2822(),
+        body.replace('\n', "\r\n")
+    );
+
+    let mut args = vec!["--url".to_owned(), settings.smtp_url.trim().to_owned()];
+    if settings.smtp_require_tls {
+        args.push("--ssl-reqd".to_owned());
+    }
+    let credentials = if settings.smtp_username.is_empty() {
+        None
+    } else {
+        Some(CredentialsFile::new(
+            &settings.smtp_username,
+            &settings.smtp_password,
+        )?)
+    };
+    if let Some(credentials) = &credentials {
+        args.push("--config".to_owned());
+        args.push(credentials.path().to_string_lossy().into_owned());
+    }
+    args.push("--mail-from".to_owned());
+    args.push(from.to_owned());
+    recipients.iter().for_each(|r| {
+        args.push("--mail-rcpt".to_owned());
+        args.push(r.to_string());
+    });
+    args.push("--upload-file".to_owned());
+    args.push("-".to_owned());
+    curl(&args, message.as_bytes())?;
+    info!("Emailed notification to {}", recipients.join(", "));
+    Ok(())
+}
+
+/// Sends `notification` to the configured webhook and email recipients
+fn deliver(settings: &NotificationSettings, notification: &Notification) -> Result<()> {
+    let mut errors = vec![];
+    if settings.has_webhook() {
+        if let Err(why) = post_webhook(settings, notification) {
+            errors.push(why.to_string());
+        }
+    }
+    if settings.has_email() {
+        if let Err(why) = send_email(settings, notification) {
+            errors.push(why.to_string());
+        }
+    }
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(anyhow!(errors.join("\n")))
+    }
+}
+
+/// Sends `notification`, if wanted, from its own thread so a slow server doesn't hold up the run
+/// or queue. Failures are logged.
+pub fn send(settings: &NotificationSettings, notification: Option<Notification>) {
+    let Some(notification) = notification.filter(|n| settings.wants(n)) else {
+        return;
+    };
+    let settings = settings.clone();
+    std::thread::spawn(move || {
+        if let Err(why) = deliver(&settings, &notification) {
+            warn!("Failed to send notification: {}", why);
+        }
+    });
+}
+
+/// Sends a test notification, returning any error. Blocks until it has been sent.
+pub fn send_test(settings: &NotificationSettings) -> Result<()> {
+    if !settings.has_webhook() && !settings.has_email() {
+        return Err(anyhow!(t!("notifications.not_configured").to_string()));
+    }
+    deliver(
+        settings,
+        &Notification {
+            succeeded: true,
+            subject: t!("notifications.test_subject").to_string(),
+            body: t!("notifications.test_body").to_string(),
+            outputs: vec![],
+        },
+    )
+}
+
+/// Preferences for when and where notifications are sent
+pub fn settings_ui(ui: &mut Ui, settings: &mut NotificationSettings) {
+    egui::Grid::new("preferences_notifications")
+        .num_columns(2)
+        .spacing([40.0, 4.0])
+        .striped(true)
+        .show(ui, |ui| {
+            ui.label(t!("notifications.on_success"));
+            ui.add(toggle(&mut settings.on_success));
+            ui.end_row();
+
+            ui.label(t!("notifications.on_failure"));
+            ui.add(toggle(&mut settings.on_failure));
+            ui.end_row();
+
+            ui.label(t!("notifications.webhook_url"));
+            ui.add(
+                egui::TextEdit::singleline(&mut settings.webhook_url)
+                    .hint_text("https://example.com/hooks/solhat"),
+            )
+            .on_hover_text(t!("notifications.webhook_url_hint"));
+            ui.end_row();
+
+            ui.label(t!("notifications.smtp_url"));
+            ui.add(
+                egui::TextEdit::singleline(&mut settings.smtp_url)
+                    .hint_text("smtps://smtp.example.com:465"),
+            );
+            ui.end_row();
+
+            ui.label(t!("notifications.smtp_require_tls"));
+            ui.add(toggle(&mut settings.smtp_require_tls));
+            ui.end_row();
+
+            ui.label(t!("notifications.smtp_username"));
+            ui.text_edit_singleline(&mut settings.smtp_username);
+            ui.end_row();
+
+            ui.label(t!("notifications.smtp_password"));
+            ui.add(egui::TextEdit::singleline(&mut settings.smtp_password).password(true))
+                .on_hover_text(t!("notifications.smtp_password_hint"));
+            ui.end_row();
+
+            ui.label(t!("notifications.email_from"));
+            ui.text_edit_singleline(&mut settings.email_from);
+            ui.end_row();
+
+            ui.label(t!("notifications.email_to"));
+            ui.add(
+                egui::TextEdit::singleline(&mut settings.email_to)
+                    .hint_text(t!("notifications.email_to_hint")),
+            );
+            ui.end_row();
+        });
+}
@@ -0,0 +1,271 @@
+//! Filtering and grouping of the process options panel. Options are grouped into collapsible
+//! sections, and rows are shown only if their label, in the current language, contains the text
+//! typed in the filter box. Without filter text, rarely changed options are shown only in the
+//! advanced view. Each row also says which of its values to compare to tell whether it changed
+//! since the last run, so every row is covered.
+
+use crate::state::ApplicationState;
+
+/// A row of the options panel
+pub struct OptionRow {
+    /// Translation key of the row's label
+    pub key: &'static str,
+    /// True if the values set in the row differ between two states
+    pub changed: fn(&ApplicationState, &ApplicationState) -> bool,
+}
+
+const fn row(
+    key: &'static str,
+    changed: fn(&ApplicationState, &ApplicationState) -> bool,
+) -> OptionRow {
+    OptionRow { key, changed }
+}
+
+/// A collapsible group of rows in the options panel
+pub struct OptionSection {
+    /// Name the section's collapsed state is remembered by
+    pub id: &'static str,
+    /// Translation key of the section's title
+    pub title: &'static str,
+    /// The section's rows
+    pub options: &'static [OptionRow],
+}
+
+pub const ANALYSIS: OptionSection = OptionSection {
+    id: "analysis",
+    title: "processoptions.section_analysis",
+    options: &[
+        row("processoptions.obj_detect_thresh", |last, current| {
+            last.obj_detection_threshold != current.obj_detection_threshold
+        }),
+        row("processoptions.analysis_window_size", |last, current| {
+            last.analysis_window_size != current.analysis_window_size
+        }),
+        row("processoptions.analysis_binning", |last, current| {
+            last.analysis_binning != current.analysis_binning
+        }),
+        row("processoptions.analysis_channel", |last, current| {
+            last.analysis_channel != current.analysis_channel
+        }),
+        row("processoptions.analysis_points", |last, current| {
+            last.analysis_points != current.analysis_points
+        }),
+        row("processoptions.analysis_grid", |last, current| {
+            last.analysis_grid_size != current.analysis_grid_size
+                || last.analysis_grid_spacing != current.analysis_grid_spacing
+        }),
+        row("processoptions.analysis_custom_points", |last, current| {
+            last.analysis_custom_points != current.analysis_custom_points
+        }),
+    ],
+};
+
+pub const STACKING: OptionSection = OptionSection {
+    id: "stacking",
+    title: "processoptions.section_stacking",
+    options: &[
+        row("processoptions.drizzle", |last, current| {
+            last.drizzle_scale != current.drizzle_scale
+                || last.custom_drizzle_scale != current.custom_drizzle_scale
+        }),
+        row("processoptions.drizzle_pixfrac", |last, current| {
+            last.drizzle_pixfrac != current.drizzle_pixfrac
+        }),
+        row("processoptions.algorithm", |last, current| {
+            last.algorithm != current.algorithm
+        }),
+        row("processoptions.clip_kappa", |last, current| {
+            last.clip_kappa != current.clip_kappa || last.clip_iterations != current.clip_iterations
+        }),
+        row("processoptions.alignment_mode", |last, current| {
+            last.alignment_mode != current.alignment_mode
+        }),
+        row("processoptions.ap_size", |last, current| {
+            last.ap_size != current.ap_size
+        }),
+        row("processoptions.ap_search_radius", |last, current| {
+            last.ap_search_radius != current.ap_search_radius
+        }),
+        row("processoptions.ap_keep_percentage", |last, current| {
+            last.ap_keep_percentage != current.ap_keep_percentage
+        }),
+        row("processoptions.ap_min_brightness", |last, current| {
+            last.ap_min_brightness != current.ap_min_brightness
+        }),
+        row("processoptions.saturation_exclusion", |last, current| {
+            last.saturation_exclusion != current.saturation_exclusion
+                || last.saturation_threshold != current.saturation_threshold
+        }),
+    ],
+};
+
+pub const FRAME_SELECTION: OptionSection = OptionSection {
+    id: "frame_selection",
+    title: "processoptions.section_frame_selection",
+    options: &[
+        row("processoptions.use_max_frames", |last, current| {
+            last.max_frames != current.max_frames
+        }),
+        row("processoptions.frame_range", |last, current| {
+            last.frame_range != current.frame_range
+        }),
+        row("processoptions.frame_sampling", |last, current| {
+            last.frame_sampling != current.frame_sampling
+        }),
+        row("processoptions.sampling_seed", |last, current| {
+            last.sampling_seed != current.sampling_seed
+        }),
+        row("processoptions.minimum_sigma", |last, current| {
+            last.min_sigma != current.min_sigma
+        }),
+        row("processoptions.maximum_sigma", |last, current| {
+            last.max_sigma != current.max_sigma
+        }),
+        row("processoptions.include_top_percent", |last, current| {
+            last.top_percentage != current.top_percentage
+        }),
+        row("processoptions.quality_weighting", |last, current| {
+            last.quality_weighting != current.quality_weighting
+                || last.quality_weight_power != current.quality_weight_power
+        }),
+    ],
+};
+
+pub const CORRECTIONS: OptionSection = OptionSection {
+    id: "corrections",
+    title: "processoptions.section_corrections",
+    options: &[
+        row("processoptions.decorrelated_colors", |last, current| {
+            last.decorrelated_colors != current.decorrelated_colors
+        }),
+        row("processoptions.limb_dark_correction", |last, current| {
+            last.ld_correction != current.ld_correction
+        }),
+        row("processoptions.ldc_coefficient", |last, current| {
+            last.ld_coefficient != current.ld_coefficient
+        }),
+        row("processoptions.ldc_solar_radius", |last, current| {
+            last.solar_radius_pixels != current.solar_radius_pixels
+        }),
+    ],
+};
+
+pub const FRAMING: OptionSection = OptionSection {
+    id: "framing",
+    title: "processoptions.section_framing",
+    options: &[
+        row("processoptions.crop_width", |last, current| {
+            last.crop_width != current.crop_width
+        }),
+        row("processoptions.crop_height", |last, current| {
+            last.crop_height != current.crop_height
+        }),
+        row("processoptions.horiz_offset", |last, current| {
+            last.horiz_offset != current.horiz_offset
+        }),
+        row("processoptions.vert_offset", |last, current| {
+            last.vert_offset != current.vert_offset
+        }),
+        row("processoptions.frame_orientation", |last, current| {
+            last.frame_orientation != current.frame_orientation
+        }),
+        row("processoptions.filename_free_text", |last, current| {
+            last.freetext != current.freetext
+        }),
+    ],
+};
+
+pub const CALIBRATION: OptionSection = OptionSection {
+    id: "calibration",
+    title: "processoptions.section_calibration",
+    options: &[
+        row("processoptions.save_masters", |last, current| {
+            last.save_masters != current.save_masters
+        }),
+        row("processoptions.masters_folder", |last, current| {
+            last.masters_dir != current.masters_dir
+        }),
+        row("processoptions.masters_format", |last, current| {
+            last.masters_format != current.masters_format
+        }),
+        row("processoptions.calibration_max_frames", |last, current| {
+            last.calibration_max_frames != current.calibration_max_frames
+        }),
+        row("processoptions.dark_scale", |last, current| {
+            last.dark_scale_mode != current.dark_scale_mode
+        }),
+        row("processoptions.dark_scale_factor", |last, current| {
+            last.dark_scale_factor != current.dark_scale_factor
+        }),
+        row("processoptions.dark_scaling", |last, current| {
+            last.dark_scaling != current.dark_scaling
+        }),
+        row("processoptions.exposure_tolerance", |last, current| {
+            last.exposure_tolerance != current.exposure_tolerance
+        }),
+    ],
+};
+
+/// Translation keys of the labels of options hidden in the basic view. Their values still apply.
+const ADVANCED_OPTIONS: [&str; 15] = [
+    "processoptions.analysis_window_size",
+    "processoptions.analysis_binning",
+    "processoptions.analysis_channel",
+    "processoptions.analysis_points",
+    "processoptions.analysis_grid",
+    "processoptions.analysis_custom_points",
+    "processoptions.drizzle_pixfrac",
+    "processoptions.ap_search_radius",
+    "processoptions.ap_min_brightness",
+    "processoptions.sampling_seed",
+    "processoptions.ldc_coefficient",
+    "processoptions.ldc_solar_radius",
+    "processoptions.horiz_offset",
+    "processoptions.vert_offset",
+    "processoptions.exposure_tolerance",
+];
+
+/// Sections of the options panel, in the order they're shown
+pub const SECTIONS: [&OptionSection; 6] = [
+    &ANALYSIS,
+    &STACKING,
+    &FRAME_SELECTION,
+    &CORRECTIONS,
+    &FRAMING,
+    &CALIBRATION,
+];
+
+/// Text typed in the filter box, matched against option labels regardless of case, and whether
+/// the advanced options are shown
+pub struct OptionFilter {
+    text: String,
+    advanced: bool,
+}
+
+impl OptionFilter {
+    pub fn new(text: &str, advanced: bool) -> Self {
+        OptionFilter {
+            text: text.trim().to_lowercase(),
+            advanced,
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        !self.text.is_empty()
+    }
+
+    /// True if the row labelled with the translation of `key` is shown. Filter text searches the
+    /// advanced options too, so they can be found from the basic view.
+    pub fn shows(&self, key: &str) -> bool {
+        if self.is_active() {
+            t!(key).to_lowercase().contains(&self.text)
+        } else {
+            self.advanced || !ADVANCED_OPTIONS.contains(&key)
+        }
+    }
+
+    /// True if any of `rows` is shown
+    pub fn shows_any(&self, rows: &[OptionRow]) -> bool {
+        rows.iter().any(|row| self.shows(row.key))
+    }
+}
@@ -0,0 +1,131 @@
+//! Adds opened files to the operating system's recent documents, so they show up in the taskbar
+//! jump list on Windows, the dock and Apple menu recent items on macOS, and the recent files of
+//! freedesktop compliant desktops on Linux.
+
+use std::path::Path;
+
+/// Notes `path` as recently opened. Failures are logged and otherwise ignored as the application's
+/// own recent lists are unaffected.
+pub fn note_recent_document(path: &Path) {
+    let path = match path.canonicalize() {
+        Ok(path) => path,
+        Err(why) => {
+            warn!("Cannot add {:?} to recent documents: {}", path, why);
+            return;
+        }
+    };
+    if let Err(why) = platform::add_recent_document(&path) {
+        warn!("Failed to add {:?} to recent documents: {}", path, why);
+    }
+}
+
+#[cfg(target_os = "windows")]
+mod platform {
+    use std::ffi::c_void;
+    use std::os::windows::ffi::OsStrExt;
+    use std::path::Path;
+
+    use anyhow::Result;
+
+    /// The `pv` argument of `SHAddToRecentDocs` is a null terminated wide string path
+    const SHARD_PATHW: u32 = 0x00000003;
+
+    #[link(name = "shell32")]
+    extern "system" {
+        fn SHAddToRecentDocs(flags: u32, pv: *const c_void);
+    }
+
+    /// Windows also adds the file to the application's jump list
+    pub fn add_recent_document(path: &Path) -> Result<()> {
+        let wide: Vec<u16> = path.as_os_str().encode_wide().chain(Some(0)).collect();
+        unsafe { SHAddToRecentDocs(SHARD_PATHW, wide.as_ptr() as *const c_void) };
+        Ok(())
+    }
+}
+
+#[cfg(target_os = "macos")]
+mod platform {
+    use std::ffi::{c_char, c_void, CString};
+    use std::path::Path;
+
+    use anyhow::{anyhow, Result};
+
+    type Id = *mut c_void;
+
+    #[link(name = "AppKit", kind = "framework")]
+    #[link(name = "objc")]
+    extern "C" {
+        fn objc_getClass(name: *const c_char) -> Id;
+        fn sel_registerName(name: *const c_char) -> Id;
+        fn objc_msgSend();
+    }
+
+    /// Sends a message taking no arguments to `receiver`
+    unsafe fn send(receiver: Id, selector: &str) -> Id {
+        let selector = CString::new(selector).unwrap();
+        let msg_send: unsafe extern "C" fn(Id, Id) -> Id =
+            std::mem::transmute(objc_msgSend as unsafe extern "C" fn());
+        msg_send(receiver, sel_registerName(selector.as_ptr()))
+    }
+
+    /// Sends a message taking one argument to `receiver`
+    unsafe fn send_with<A>(receiver: Id, selector: &str, arg: A) -> Id {
+        let selector = CString::new(selector).unwrap();
+        let msg_send: unsafe extern "C" fn(Id, Id, A) -> Id =
+            std::mem::transmute(objc_msgSend as unsafe extern "C" fn());
+        msg_send(receiver, sel_registerName(selector.as_ptr()), arg)
+    }
+
+    unsafe fn class(name: &str) -> Id {
+        let name = CString::new(name).unwrap();
+        objc_getClass(name.as_ptr())
+    }
+
+    /// Equivalent to
+    /// `[[NSDocumentController sharedDocumentController] noteNewRecentDocumentURL:url]`, which
+    /// adds the file to the dock menu and the Apple menu's recent items.
+    pub fn add_recent_document(path: &Path) -> Result<()> {
+        let path = CString::new(path.to_string_lossy().as_bytes())?;
+        unsafe {
+            let string = send_with(class("NSString"), "stringWithUTF8String:", path.as_ptr());
+            let url = send_with(class("NSURL"), "fileURLWithPath:", string);
+            if url.is_null() {
+                return Err(anyhow!("Unable to create a file URL"));
+            }
+            let controller = send(class("NSDocumentController"), "sharedDocumentController");
+            send_with(controller, "noteNewRecentDocumentURL:", url);
+        }
+        Ok(())
+    }
+}
+
+/// Added through gtk's recent manager, which keeps the freedesktop recently used list shared with
+/// other applications
+#[cfg(target_os = "linux")]
+mod platform {
+    use std::path::Path;
+
+    use anyhow::Result;
+    use gtk::prelude::*;
+
+    use crate::gtkthread::run_on_gtk_thread;
+
+    pub fn add_recent_document(path: &Path) -> Result<()> {
+        let uri = gtk::glib::filename_to_uri(path, None)?.to_string();
+        run_on_gtk_thread(move || match gtk::RecentManager::default() {
+            Some(manager) if manager.add_item(&uri) => {}
+            _ => warn!("Failed to add {} to recent documents", uri),
+        })
+    }
+}
+
+#[cfg(not(any(target_os = "windows", target_os = "macos", target_os = "linux")))]
+mod platform {
+    use std::path::Path;
+
+    use anyhow::Result;
+
+    pub fn add_recent_document(_path: &Path) -> Result<()> {
+        Ok(())
+    }
+}
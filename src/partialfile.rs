@@ -0,0 +1,213 @@
+//! Outputs are written to a partial file alongside their destination and renamed over it once
+//! complete, so a cancelled or crashed run never leaves a half-written image behind. Folders with
+//! partial files in progress are listed in a file, with the process writing them, so any left by a
+//! crash can be removed at the next startup.
+
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+lazy_static! {
+    // Serializes updates to the list of partial file folders, which other instances also write to
+    static ref PARTIAL_FILES_LOCK: Mutex<()> = Mutex::new(());
+
+    // Folders this instance has listed. The list is only written when a folder is first used, as
+    // runs write many partial files to the same few folders.
+    static ref LISTED_FOLDERS: Mutex<BTreeSet<PathBuf>> = Mutex::new(BTreeSet::new());
+}
+
+/// Folder which partial files are written to by the process `pid`
+#[derive(Clone, Deserialize, Serialize, PartialEq)]
+struct PartialFolder {
+    pid: u32,
+    path: PathBuf,
+}
+
+#[derive(Default, Deserialize, Serialize)]
+#[serde(default)]
+struct PartialFiles {
+    folders: Vec<PartialFolder>,
+}
+
+fn partial_files_path() -> PathBuf {
+    dirs::home_dir().unwrap().join(".solhat/partial_files.toml")
+}
+
+fn load_file() -> PartialFiles {
+    fs::read_to_string(partial_files_path())
+        .ok()
+        .and_then(|t| toml::from_str(&t).ok())
+        .unwrap_or_default()
+}
+
+fn save_file(partial_files: &PartialFiles) -> Result<()> {
+    let path = partial_files_path();
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, toml::to_string(partial_files)?)?;
+    Ok(())
+}
+
+fn update_file<F>(update: F)
+where
+    F: FnOnce(&mut Vec<PartialFolder>),
+{
+    let _lock = PARTIAL_FILES_LOCK.lock().unwrap();
+    let mut partial_files = load_file();
+    update(&mut partial_files.folders);
+    if let Err(why) = save_file(&partial_files) {
+        warn!("Failed to update the list of partial files: {}", why);
+    }
+}
+
+/// Lists the folder of `partial` as written to by this instance, if it isn't already
+fn list_folder(partial: &Path) {
+    let Some(folder) = partial.parent() else {
+        return;
+    };
+    if LISTED_FOLDERS.lock().unwrap().insert(folder.to_owned()) {
+        let entry = PartialFolder {
+            pid: std::process::id(),
+            path: folder.to_owned(),
+        };
+        update_file(|folders| {
+            if !folders.contains(&entry) {
+                folders.push(entry);
+            }
+        });
+    }
+}
+
+/// Part of the name of partial files written by the process `pid`
+fn partial_marker(pid: u32) -> String {
+    format!(".{}.partial", pid)
+}
+
+/// Hidden file alongside `path` that it's written to before being renamed into place. The name
+/// carries the process id so instances never remove each other's files, and the extension is kept
+/// as image encoders choose the format from it.
+pub fn partial_path(path: &Path) -> PathBuf {
+    let stem = path
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let marker = partial_marker(std::process::id());
+    let name = match path.extension() {
+        Some(ext) => format!(".{}{}.{}", stem, marker, ext.to_string_lossy()),
+        None => format!(".{}{}", stem, marker),
+    };
+    path.with_file_name(name)
+}
+
+/// Writes `path` with `write`, which is given the partial file to write to. The partial file is
+/// renamed to `path` if writing succeeds and removed if it fails.
+pub fn write_atomically<F>(path: &Path, write: F) -> Result<()>
+where
+    F: FnOnce(&Path) -> Result<()>,
+{
+    let partial = partial_path(path);
+    list_folder(&partial);
+
+    let result = write(&partial).and_then(|_| Ok(fs::rename(&partial, path)?));
+    if result.is_err() && partial.exists() {
+        if let Err(why) = fs::remove_file(&partial) {
+            warn!("Failed to remove partial file {:?}: {}", partial, why);
+        }
+    }
+    result
+}
+
+/// Removes the partial files written by the process `pid` in `folder`
+fn remove_partial_files(folder: &Path, pid: u32) {
+    let marker = partial_marker(pid);
+    let Ok(entries) = fs::read_dir(folder) else {
+        return;
+    };
+    entries
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|p| {
+            p.file_name()
+                .map(|n| n.to_string_lossy())
+                .map(|n| n.starts_with('.') && n.contains(&marker))
+                .unwrap_or(false)
+        })
+        .for_each(|path| match fs::remove_file(&path) {
+            Ok(_) => info!("Removed partial file {:?} left by a previous session", path),
+            Err(why) => warn!("Failed to remove partial file {:?}: {}", path, why),
+        });
+}
+
+/// Removes partial files left by previous sessions that crashed or were killed while writing.
+/// Folders listed by other instances still running are left alone.
+pub fn remove_stale_partial_files() {
+    let own_pid = std::process::id();
+    update_file(|folders| {
+        folders.retain(|folder| {
+            if folder.pid != own_pid && platform::is_process_running(folder.pid) {
+                true
+            } else {
+                remove_partial_files(&folder.path, folder.pid);
+                false
+            }
+        })
+    });
+}
+
+#[cfg(target_os = "linux")]
+mod platform {
+    pub fn is_process_running(pid: u32) -> bool {
+        std::path::Path::new(&format!("/proc/{}", pid)).exists()
+    }
+}
+
+#[cfg(all(unix, not(target_os = "linux")))]
+mod platform {
+    use std::process::{Command, Stdio};
+
+    pub fn is_process_running(pid: u32) -> bool {
+        Command::new("ps")
+            .args(["-p", &pid.to_string()])
+            .stdout(Stdio::null())
+            .status()
+            .map(|s| s.success())
+            .unwrap_or(false)
+    }
+}
+
+#[cfg(target_os = "windows")]
+mod platform {
+    use std::ffi::c_void;
+
+    /// Access right needed to read a process's exit code
+    const PROCESS_QUERY_LIMITED_INFORMATION: u32 = 0x1000;
+
+    /// Exit code reported for processes which haven't exited
+    const STILL_ACTIVE: u32 = 259;
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn OpenProcess(access: u32, inherit_handle: i32, pid: u32) -> *mut c_void;
+        fn GetExitCodeProcess(process: *mut c_void, exit_code: *mut u32) -> i32;
+        fn CloseHandle(handle: *mut c_void) -> i32;
+    }
+
+    pub fn is_process_running(pid: u32) -> bool {
+        unsafe {
+            let process = OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, 0, pid);
+            if process.is_null() {
+                return false;
+            }
+            let mut exit_code = 0;
+            let running =
+                GetExitCodeProcess(process, &mut exit_code) != 0 && exit_code == STILL_ACTIVE;
+            CloseHandle(process);
+            running
+        }
+    }
+}
@@ -0,0 +1,340 @@
+//! Keeps the system awake while processing runs, and pauses processing when running on battery
+//! if the user has asked for it.
+
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::cancel;
+
+/// How often the battery state is read while a task runs
+const BATTERY_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+/// When processing is paused to save the battery
+#[derive(Debug, Eq, PartialEq, Deserialize, Serialize, Clone, Copy, Default)]
+pub enum BatteryPolicy {
+    #[default]
+    Ignore,
+    /// Pause whenever the system is running from its battery
+    PauseOnBattery,
+    /// Pause when running from the battery and its charge is below the configured level
+    PauseBelowLevel,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+#[serde(default)]
+pub struct PowerSettings {
+    /// Prevent the system from sleeping while a task runs
+    pub prevent_sleep: bool,
+    pub battery_policy: BatteryPolicy,
+    /// Battery charge, in percent, below which processing pauses with `PauseBelowLevel`
+    pub battery_pause_level: u8,
+}
+
+impl Default for PowerSettings {
+    fn default() -> Self {
+        PowerSettings {
+            prevent_sleep: true,
+            battery_policy: BatteryPolicy::Ignore,
+            battery_pause_level: 20,
+        }
+    }
+}
+
+impl PowerSettings {
+    pub fn should_pause(&self, status: &PowerStatus) -> bool {
+        if !status.on_battery {
+            return false;
+        }
+        match self.battery_policy {
+            BatteryPolicy::Ignore => false,
+            BatteryPolicy::PauseOnBattery => true,
+            BatteryPolicy::PauseBelowLevel => status
+                .battery_percent
+                .map(|p| p < self.battery_pause_level)
+                .unwrap_or(false),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PowerStatus {
+    pub on_battery: bool,
+    pub battery_percent: Option<u8>,
+}
+
+/// Reads the current power source and battery charge. None if the system has no battery or it
+/// can't be read.
+pub fn read_power_status() -> Option<PowerStatus> {
+    platform::read_power_status()
+}
+
+/// Prevents the system from sleeping until dropped
+pub struct SleepInhibitor {
+    _handle: platform::InhibitHandle,
+}
+
+pub fn inhibit_sleep() -> Result<SleepInhibitor> {
+    Ok(SleepInhibitor {
+        _handle: platform::InhibitHandle::acquire()?,
+    })
+}
+
+/// Applies the power settings as tasks start and finish. Updated with the UI.
+#[derive(Default)]
+pub struct PowerMonitor {
+    inhibitor: Option<SleepInhibitor>,
+    inhibit_attempted: bool,
+    last_check: Option<Instant>,
+    paused_for_battery: bool,
+    /// The user resumed processing while on battery. It isn't paused again until the system is
+    /// back on mains power.
+    resumed_on_battery: bool,
+}
+
+impl PowerMonitor {
+    pub fn paused_for_battery(&self) -> bool {
+        self.paused_for_battery
+    }
+
+    pub fn update(&mut self, settings: &PowerSettings, task_running: bool) {
+        if task_running && settings.prevent_sleep {
+            if !self.inhibit_attempted {
+                self.inhibit_attempted = true;
+                match inhibit_sleep() {
+                    Ok(inhibitor) => self.inhibitor = Some(inhibitor),
+                    Err(why) => warn!("Unable to prevent system sleep: {}", why),
+                }
+            }
+        } else {
+            self.inhibitor = None;
+            self.inhibit_attempted = false;
+        }
+
+        if !task_running {
+            self.paused_for_battery = false;
+            self.resumed_on_battery = false;
+            self.last_check = None;
+            return;
+        }
+
+        if self.paused_for_battery && !cancel::is_paused() {
+            self.paused_for_battery = false;
+            self.resumed_on_battery = true;
+        }
+
+        if self
+            .last_check
+            .map(|t| t.elapsed() < BATTERY_CHECK_INTERVAL)
+            .unwrap_or(false)
+        {
+            return;
+        }
+        self.last_check = Some(Instant::now());
+
+        let should_pause = read_power_status()
+            .map(|status| settings.should_pause(&status))
+            .unwrap_or(false);
+        if should_pause {
+            if !self.resumed_on_battery && !cancel::is_paused() {
+                warn!("Running on battery. Pausing processing");
+                cancel::set_paused(true);
+                self.paused_for_battery = true;
+            }
+        } else {
+            self.resumed_on_battery = false;
+            if self.paused_for_battery {
+                info!("Back on mains power. Resuming processing");
+                cancel::set_paused(false);
+                self.paused_for_battery = false;
+            }
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod platform {
+    use std::path::Path;
+    use std::process::{Child, Command, Stdio};
+
+    use anyhow::Result;
+
+    use super::PowerStatus;
+
+    fn read_value(path: &Path, name: &str) -> Option<String> {
+        std::fs::read_to_string(path.join(name))
+            .ok()
+            .map(|v| v.trim().to_owned())
+    }
+
+    pub fn read_power_status() -> Option<PowerStatus> {
+        let mut on_mains = false;
+        let mut discharging = false;
+        let mut levels = vec![];
+        for entry in std::fs::read_dir("/sys/class/power_supply").ok()?.flatten() {
+            let path = entry.path();
+            match read_value(&path, "type").as_deref() {
+                Some("Mains") | Some("USB") => {
+                    on_mains |= read_value(&path, "online").as_deref() == Some("1");
+                }
+                Some("Battery") => {
+                    discharging |= read_value(&path, "status").as_deref() == Some("Discharging");
+                    if let Some(level) = read_value(&path, "capacity").and_then(|c| c.parse().ok())
+                    {
+                        levels.push(level);
+                    }
+                }
+                _ => {}
+            }
+        }
+        if levels.is_empty() && !discharging {
+            return None;
+        }
+        Some(PowerStatus {
+            on_battery: discharging && !on_mains,
+            battery_percent: levels.iter().min().copied(),
+        })
+    }
+
+    /// Holds a systemd-inhibit lock for as long as its child runs. The child waits on its
+    /// stdin, so it exits along with the application even if it doesn't shut down cleanly.
+    pub struct InhibitHandle {
+        child: Child,
+    }
+
+    impl InhibitHandle {
+        pub fn acquire() -> Result<InhibitHandle> {
+            let child = Command::new("systemd-inhibit")
+                .args([
+                    "--what=sleep:idle",
+                    "--who=SolHat",
+                    "--why=Processing images",
+                    "--mode=block",
+                    "cat",
+                ])
+                .stdin(Stdio::piped())
+                .stdout(Stdio::null())
+                .spawn()?;
+            Ok(InhibitHandle { child })
+        }
+    }
+
+    impl Drop for InhibitHandle {
+        fn drop(&mut self) {
+            let _ = self.child.kill();
+            let _ = self.child.wait();
+        }
+    }
+}
+
+#[cfg(target_os = "macos")]
+mod platform {
+    use std::process::{Child, Command};
+
+    use anyhow::Result;
+
+    use super::PowerStatus;
+
+    /// Parses `pmset -g batt`, e.g.
+    /// `Now drawing from 'Battery Power'` followed by `-InternalBattery-0 (id=1234)	85%; discharging;`
+    pub fn read_power_status() -> Option<PowerStatus> {
+        let output = Command::new("pmset").args(["-g", "batt"]).output().ok()?;
+        let text = String::from_utf8_lossy(&output.stdout);
+        let battery_percent = text
+            .split_whitespace()
+            .find_map(|word| word.strip_suffix("%;"))
+            .and_then(|p| p.parse().ok());
+        battery_percent?;
+        Some(PowerStatus {
+            on_battery: text.contains("'Battery Power'"),
+            battery_percent,
+        })
+    }
+
+    /// Holds a caffeinate assertion for as long as its child runs. It's tied to the application's
+    /// process so it ends along with it.
+    pub struct InhibitHandle {
+        child: Child,
+    }
+
+    impl InhibitHandle {
+        pub fn acquire() -> Result<InhibitHandle> {
+            let child = Command::new("caffeinate")
+                .args(["-i", "-w", &std::process::id().to_string()])
+                .spawn()?;
+            Ok(InhibitHandle { child })
+        }
+    }
+
+    impl Drop for InhibitHandle {
+        fn drop(&mut self) {
+            let _ = self.child.kill();
+            let _ = self.child.wait();
+        }
+    }
+}
+
+#[cfg(target_os = "windows")]
+mod platform {
+    use anyhow::{anyhow, Result};
+
+    use super::PowerStatus;
+
+    const ES_CONTINUOUS: u32 = 0x80000000;
+    const ES_SYSTEM_REQUIRED: u32 = 0x00000001;
+
+    const AC_LINE_OFFLINE: u8 = 0;
+    const BATTERY_FLAG_NO_BATTERY: u8 = 128;
+    const BATTERY_PERCENT_UNKNOWN: u8 = 255;
+
+    #[repr(C)]
+    #[derive(Default)]
+    struct SystemPowerStatus {
+        ac_line_status: u8,
+        battery_flag: u8,
+        battery_life_percent: u8,
+        _system_status_flag: u8,
+        _battery_life_time: u32,
+        _battery_full_life_time: u32,
+    }
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn SetThreadExecutionState(flags: u32) -> u32;
+        fn GetSystemPowerStatus(status: *mut SystemPowerStatus) -> i32;
+    }
+
+    pub fn read_power_status() -> Option<PowerStatus> {
+        let mut status = SystemPowerStatus::default();
+        if unsafe { GetSystemPowerStatus(&mut status) } == 0
+            || status.battery_flag & BATTERY_FLAG_NO_BATTERY != 0
+        {
+            return None;
+        }
+        Some(PowerStatus {
+            on_battery: status.ac_line_status == AC_LINE_OFFLINE,
+            battery_percent: Some(status.battery_life_percent)
+                .filter(|p| *p != BATTERY_PERCENT_UNKNOWN),
+        })
+    }
+
+    /// The execution state belongs to the thread that sets it, which is the UI thread, kept for
+    /// the life of the application.
+    pub struct InhibitHandle;
+
+    impl InhibitHandle {
+        pub fn acquire() -> Result<InhibitHandle> {
+            if unsafe { SetThreadExecutionState(ES_CONTINUOUS | ES_SYSTEM_REQUIRED) } == 0 {
+                return Err(anyhow!("SetThreadExecutionState failed"));
+            }
+            Ok(InhibitHandle)
+        }
+    }
+
+    impl Drop for InhibitHandle {
+        fn drop(&mut self) {
+            unsafe { SetThreadExecutionState(ES_CONTINUOUS) };
+        }
+    }
+}
@@ -0,0 +1,172 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use solhat::target::Target;
+
+use crate::state::ApplicationState;
+
+/// Directory in which named processing presets are stored
+fn presets_dir() -> PathBuf {
+    dirs::home_dir().unwrap().join(".solhat/presets/")
+}
+
+fn preset_path(name: &str) -> Result<PathBuf> {
+    let name = name.trim();
+    if name.is_empty() || name.contains(['/', '\\']) || name.starts_with('.') {
+        Err(anyhow!("Invalid preset name: '{}'", name))
+    } else {
+        Ok(presets_dir().join(format!("{}.toml", name)))
+    }
+}
+
+/// Names of all saved presets, sorted alphabetically
+pub fn list_presets() -> Vec<String> {
+    let mut names: Vec<String> = if let Ok(entries) = fs::read_dir(presets_dir()) {
+        entries
+            .filter_map(|e| e.ok())
+            .map(|e| e.path())
+            .filter(|p| p.extension().map(|e| e == "toml").unwrap_or(false))
+            .filter_map(|p| p.file_stem().map(|s| s.to_string_lossy().to_string()))
+            .collect()
+    } else {
+        vec![]
+    };
+    names.sort();
+    names
+}
+
+/// Saves the processing options of `state`, without any file paths, as a named preset
+pub fn save_preset(name: &str, state: &ApplicationState) -> Result<()> {
+    let path = preset_path(name)?;
+    fs::create_dir_all(presets_dir())?;
+    fs::write(&path, toml::to_string(&state.without_paths())?)?;
+    info!("Saved preset '{}' to {:?}", name, path);
+    Ok(())
+}
+
+pub fn load_preset(name: &str) -> Result<ApplicationState> {
+    let t = fs::read_to_string(preset_path(name)?)?;
+    Ok(toml::from_str(&t)?)
+}
+
+pub fn rename_preset(name: &str, new_name: &str) -> Result<()> {
+    let new_path = preset_path(new_name)?;
+    if new_path.exists() {
+        return Err(anyhow!("A preset named '{}' already exists", new_name));
+    }
+    fs::rename(preset_path(name)?, new_path)?;
+    Ok(())
+}
+
+pub fn delete_preset(name: &str) -> Result<()> {
+    fs::remove_file(preset_path(name)?)?;
+    Ok(())
+}
+
+/// Writes the processing options of `state`, without any machine specific paths, to a portable
+/// settings file which can be shared with other users.
+pub fn export_settings(path: &Path, state: &ApplicationState) -> Result<()> {
+    fs::write(path, toml::to_string(&state.without_paths())?)?;
+    info!("Exported settings to {:?}", path);
+    Ok(())
+}
+
+/// Reads a settings file written by `export_settings`
+pub fn import_settings(path: &Path) -> Result<ApplicationState> {
+    let t = fs::read_to_string(path)?;
+    info!("Importing settings from {:?}", path);
+    Ok(toml::from_str(&t)?)
+}
+
+/// Options which commonly differ between targets. Unset values leave the current option as is.
+#[derive(Debug, Default, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct TargetDefaults {
+    pub obj_detection_threshold: Option<f64>,
+    pub ld_correction: Option<bool>,
+    pub ld_coefficient: Option<f64>,
+    pub solar_radius_pixels: Option<usize>,
+    pub analysis_window_size: Option<usize>,
+}
+
+impl TargetDefaults {
+    pub fn from_state(state: &ApplicationState) -> Self {
+        TargetDefaults {
+            obj_detection_threshold: Some(state.obj_detection_threshold),
+            ld_correction: Some(state.ld_correction),
+            ld_coefficient: Some(state.ld_coefficient),
+            solar_radius_pixels: Some(state.solar_radius_pixels),
+            analysis_window_size: Some(state.analysis_window_size),
+        }
+    }
+
+    /// Defaults used for a target which has none saved
+    fn builtin(state: &ApplicationState) -> Self {
+        if state.planetary {
+            // Planets cover a small part of the frame, so quality is measured over a smaller
+            // window
+            return TargetDefaults {
+                ld_correction: Some(false),
+                analysis_window_size: Some(64),
+                ..Default::default()
+            };
+        }
+        match state.target {
+            Target::Moon | Target::None => TargetDefaults {
+                ld_correction: Some(false),
+                ..Default::default()
+            },
+            _ => TargetDefaults::default(),
+        }
+    }
+
+    pub fn apply_to(&self, state: &mut ApplicationState) {
+        if let Some(v) = self.obj_detection_threshold {
+            state.obj_detection_threshold = v;
+        }
+        if let Some(v) = self.ld_correction {
+            state.ld_correction = v;
+        }
+        if let Some(v) = self.ld_coefficient {
+            state.ld_coefficient = v;
+        }
+        if let Some(v) = self.solar_radius_pixels {
+            state.solar_radius_pixels = v;
+        }
+        if let Some(v) = self.analysis_window_size {
+            state.analysis_window_size = v;
+        }
+    }
+}
+
+fn target_defaults_path(state: &ApplicationState) -> PathBuf {
+    dirs::home_dir()
+        .unwrap()
+        .join(".solhat/targets/")
+        .join(format!("{}.toml", state.target_name()).to_lowercase())
+}
+
+/// Saves the target specific options of `state` as the defaults for its target
+pub fn save_target_defaults(state: &ApplicationState) -> Result<()> {
+    let path = target_defaults_path(state);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(&path, toml::to_string(&TargetDefaults::from_state(state))?)?;
+    info!("Saved {} defaults to {:?}", state.target_name(), path);
+    Ok(())
+}
+
+/// Loads the saved defaults for the target of `state`, falling back to the built in defaults if
+/// none exist
+pub fn load_target_defaults(state: &ApplicationState) -> Result<TargetDefaults> {
+    let path = target_defaults_path(state);
+    if path.exists() {
+        let t = fs::read_to_string(&path)?;
+        Ok(toml::from_str(&t)?)
+    } else {
+        Ok(TargetDefaults::builtin(state))
+    }
+}
@@ -1,3 +1,6 @@
+use std::fs;
+use std::time::{Duration, Instant, SystemTime};
+
 use anyhow::Error;
 use anyhow::Result;
 use egui::Ui;
@@ -9,13 +12,64 @@ use crate::histogram::Histogram;
 use crate::imageutil;
 use crate::state::ApplicationState;
 
+/// How often a file watched live is checked for changes
+const LIVE_CHECK_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Region of the frame kept by the crop, drawn over the preview
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CropOverlay {
+    /// Crop size in frame pixels. Zero keeps the full width or height of the previewed frame.
+    pub width: usize,
+    pub height: usize,
+    /// Center of the crop relative to the center of the frame, in frame pixels
+    pub offset: [f32; 2],
+}
+
+impl CropOverlay {
+    /// Crop rectangle in frame pixels, as the minimum and maximum corners, for a frame of
+    /// `frame_size`
+    fn frame_rect(&self, frame_size: [usize; 2]) -> egui::Rect {
+        let width = if self.width == 0 {
+            frame_size[0]
+        } else {
+            self.width
+        };
+        let height = if self.height == 0 {
+            frame_size[1]
+        } else {
+            self.height
+        };
+        egui::Rect::from_center_size(
+            egui::pos2(
+                frame_size[0] as f32 / 2.0 + self.offset[0],
+                frame_size[1] as f32 / 2.0 + self.offset[1],
+            ),
+            egui::vec2(width as f32, height as f32),
+        )
+    }
+}
+
 pub struct SerPreviewPane<F: DataSource> {
     texture_handle: Option<egui::TextureHandle>,
     texture_name: String,
     ser_file: Option<F>,
+    /// Path the loaded file was opened from, for reloading it
+    ser_path: Option<String>,
     histogram: Option<Histogram>,
     show_frame_no: usize,
     animate: bool,
+    num_bands: Option<usize>,
+    /// Binning of the frame shown. Only the texture is binned; frames are read at full size.
+    downscale: usize,
+    /// Reload the file whenever it changes on disk, for files still being written by capture
+    /// software
+    live: bool,
+    live_last_check: Option<Instant>,
+    live_modified: Option<SystemTime>,
+    /// The user asked to export the loaded file as an animation
+    export_requested: bool,
+    /// Center of mass of the shown frame and the threshold it was found with
+    center_of_mass: Option<(f32, [f32; 2])>,
 }
 
 impl<F: DataSource> Default for SerPreviewPane<F> {
@@ -23,10 +77,18 @@ impl<F: DataSource> Default for SerPreviewPane<F> {
         Self {
             texture_handle: None,
             ser_file: None,
+            ser_path: None,
             texture_name: imageutil::gen_random_texture_name(),
             histogram: None,
             show_frame_no: 0,
             animate: false,
+            num_bands: None,
+            downscale: 1,
+            live: false,
+            live_last_check: None,
+            live_modified: None,
+            export_requested: false,
+            center_of_mass: None,
         }
     }
 }
@@ -39,7 +101,12 @@ impl<F: DataSource> SerPreviewPane<F> {
     fn update_texture(&mut self, ctx: &egui::Context) -> Result<()> {
         if let Some(ser_file) = &self.ser_file {
             let first_image: DataFrame = ser_file.get_frame(self.show_frame_no)?;
-            let cimage = imageutil::sciimg_to_color_image(&first_image.buffer);
+            self.num_bands = Some(first_image.buffer.num_bands());
+            self.center_of_mass = None;
+            let cimage = imageutil::sciimg_to_color_image(&imageutil::downscale_image(
+                &first_image.buffer,
+                self.downscale,
+            ));
             self.texture_handle =
                 Some(ctx.load_texture(&self.texture_name, cimage, Default::default()));
             Ok(())
@@ -50,10 +117,12 @@ impl<F: DataSource> SerPreviewPane<F> {
 
     fn update_histogram(&mut self) -> Result<()> {
         if let Some(ser_file) = &self.ser_file {
-            let mut histogram = Histogram::new(1500, 0.0, 65536.0);
-            histogram.compute_from_image(&ser_file.get_frame(self.show_frame_no)?.buffer);
-
-            self.histogram = Some(histogram);
+            let frame = ser_file.get_frame(self.show_frame_no)?;
+            let histogram = self
+                .histogram
+                .get_or_insert_with(|| Histogram::for_pixel_depth(ser_file.pixel_depth()));
+            histogram.set_pixel_depth(ser_file.pixel_depth());
+            histogram.compute_from_image(&frame.buffer);
             Ok(())
         } else {
             Err(Error::msg("No ser file loaded"))
@@ -62,6 +131,7 @@ impl<F: DataSource> SerPreviewPane<F> {
 
     pub fn load_ser(&mut self, ctx: &egui::Context, texture_path: &str) -> Result<()> {
         self.ser_file = Some(F::open(&[texture_path.to_string()])?);
+        self.ser_path = Some(texture_path.to_string());
 
         self.update_texture(ctx)?;
         self.update_histogram()?;
@@ -69,19 +139,116 @@ impl<F: DataSource> SerPreviewPane<F> {
         Ok(())
     }
 
+    /// Re-reads the header and shown frame of the loaded file from disk, for files which are
+    /// still being written or have been replaced. The shown frame is kept if it still exists.
+    pub fn reload(&mut self, ctx: &egui::Context) -> Result<()> {
+        let ser_path = self
+            .ser_path
+            .clone()
+            .ok_or_else(|| Error::msg("No ser file loaded"))?;
+        let ser_file = F::open(std::slice::from_ref(&ser_path))?;
+        if self.show_frame_no >= ser_file.frame_count() {
+            self.show_frame_no = ser_file.frame_count().saturating_sub(1);
+        }
+        self.ser_file = Some(ser_file);
+        info!("Reloaded {}", ser_path);
+
+        self.update_texture(ctx)?;
+        self.update_histogram()?;
+
+        Ok(())
+    }
+
+    /// Number of frames in the loaded file, if any
+    pub fn frame_count(&self) -> Option<usize> {
+        self.ser_file.as_ref().map(|f| f.frame_count())
+    }
+
+    /// The loaded file and its frame count, if the user asked to export it as an animation
+    pub fn take_export_request(&mut self) -> Option<(String, usize)> {
+        if !std::mem::take(&mut self.export_requested) {
+            return None;
+        }
+        self.ser_path.clone().zip(self.frame_count())
+    }
+
+    pub fn is_live(&self) -> bool {
+        self.live && self.ser_file.is_some()
+    }
+
+    #[cfg(feature = "capture")]
+    pub fn set_live(&mut self, live: bool) {
+        self.live = live;
+    }
+
+    /// Reloads a file watched live if it has changed on disk since it was last read. Returns true
+    /// if frames were added. The last frame stays shown as frames are added, if it was shown.
+    pub fn poll_live(&mut self, ctx: &egui::Context) -> Result<bool> {
+        let (true, Some(ser_path), Some(ser_file)) = (self.live, &self.ser_path, &self.ser_file)
+        else {
+            return Ok(false);
+        };
+        ctx.request_repaint_after(LIVE_CHECK_INTERVAL);
+        if self
+            .live_last_check
+            .map(|t| t.elapsed() < LIVE_CHECK_INTERVAL)
+            .unwrap_or(false)
+        {
+            return Ok(false);
+        }
+        self.live_last_check = Some(Instant::now());
+
+        let modified = fs::metadata(ser_path)?.modified().ok();
+        if modified == self.live_modified {
+            return Ok(false);
+        }
+        let first_check = self.live_modified.is_none();
+        self.live_modified = modified;
+        if first_check {
+            return Ok(false);
+        }
+
+        let previous_count = ser_file.frame_count();
+        let follow = self.show_frame_no + 1 >= previous_count;
+        self.reload(ctx)?;
+        let frame_count = self.ser_file.as_ref().map(|f| f.frame_count()).unwrap_or(0);
+        if follow && frame_count > previous_count {
+            self.show_frame_no = frame_count - 1;
+            self.update_texture(ctx)?;
+            self.update_histogram()?;
+        }
+        Ok(frame_count > previous_count)
+    }
+
     pub fn unload_ser(&mut self) {
         self.texture_handle = None;
         self.ser_file = None;
+        self.ser_path = None;
+        self.live_modified = None;
         self.histogram = None;
+        self.num_bands = None;
+        self.center_of_mass = None;
     }
 
-    pub fn threshold_test(&mut self, ui: &egui::Ui, state: &ApplicationState) -> Result<()> {
+    /// Offset of the shown frame's center of mass from the frame center, found with `threshold`
+    /// as in alignment. The last result is kept until the frame or threshold changes.
+    pub fn center_of_mass(&mut self, threshold: f32) -> Option<[f32; 2]> {
+        if let Some((cached_threshold, offset)) = self.center_of_mass {
+            if cached_threshold == threshold {
+                return Some(offset);
+            }
+        }
+        let frame = self.ser_file.as_ref()?.get_frame(self.show_frame_no).ok()?;
+        let offset = frame.buffer.calc_center_of_mass_offset(threshold, 0);
+        self.center_of_mass = Some((threshold, [offset.h, offset.v]));
+        Some([offset.h, offset.v])
+    }
+
+    pub fn threshold_test(&mut self, ctx: &egui::Context, state: &ApplicationState) -> Result<()> {
         if self.ser_file.is_some() {
             let result = analysis::threshold::run_thresh_test(&state.to_parameters())?;
             let cimage = imageutil::sciimg_to_color_image(&result);
-            let texture = ui
-                .ctx()
-                .load_texture(&self.texture_name, cimage, Default::default());
+            let texture = ctx.load_texture(&self.texture_name, cimage, Default::default());
             self.texture_handle = Some(texture);
             Ok(())
         } else {
@@ -89,15 +256,34 @@ impl<F: DataSource> SerPreviewPane<F> {
         }
     }
 
+    /// Frame dimensions of the loaded file. The texture may be smaller if downscaled.
     pub fn size(&self) -> Result<[usize; 2]> {
-        if let Some(texture_handle) = &self.texture_handle {
-            Ok(texture_handle.size())
+        if let Some(ser_file) = &self.ser_file {
+            Ok([ser_file.image_width(), ser_file.image_height()])
         } else {
             Err(Error::msg("Texture not loaded"))
         }
     }
 
+    /// Sets the binning factor applied to the texture, reloading it if the factor changed
+    pub fn set_downscale(&mut self, ctx: &egui::Context, factor: usize) -> Result<()> {
+        let factor = factor.max(1);
+        if factor != self.downscale {
+            self.downscale = factor;
+            if self.texture_handle.is_some() {
+                self.update_texture(ctx)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Number of image bands (1 for monochrome, 3 for color) of the loaded file, if any.
+    pub fn num_bands(&self) -> Option<usize> {
+        self.num_bands
+    }
+
     fn metadata_ui(&mut self, ui: &mut Ui) {
+        let mut histogram_range_changed = false;
         if let Some(ser_file) = &self.ser_file {
             ui.horizontal(|ui| {
                 ui.vertical_centered(|ui| {
@@ -141,10 +327,15 @@ impl<F: DataSource> SerPreviewPane<F> {
                 });
 
                 if let Some(histogram) = &mut self.histogram {
-                    histogram.ui(ui);
+                    histogram_range_changed = histogram.ui(ui);
                 }
             });
         }
+        if histogram_range_changed {
+            if let Err(why) = self.update_histogram() {
+                error!("Failed to update histogram: {}", why);
+            }
+        }
     }
     fn options_ui(&mut self, ui: &mut Ui) -> Result<()> {
         if self.animate {
@@ -156,11 +347,20 @@ impl<F: DataSource> SerPreviewPane<F> {
             texture_handle: _,
             texture_name: _,
             ser_file,
+            ser_path: _,
             histogram: _,
             show_frame_no,
             animate,
+            num_bands: _,
+            downscale: _,
+            live,
+            live_last_check: _,
+            live_modified: _,
+            export_requested,
+            center_of_mass: _,
         } = self;
 
+        let mut reload_requested = false;
         if let Some(ser_file) = &ser_file {
             // This is not a very efficient video viewer. Indeed, it's not written to be any good, just enough
             // to preview the frames in the file.
@@ -199,6 +399,18 @@ impl<F: DataSource> SerPreviewPane<F> {
                         *show_frame_no = 0;
                     }
                 }
+                if ui.button("⟳").on_hover_text(t!("preview.reload")).clicked() {
+                    reload_requested = true;
+                }
+                ui.checkbox(live, t!("preview.live"))
+                    .on_hover_text(t!("preview.live_hint"));
+                if ui
+                    .button(t!("preview.export_animation"))
+                    .on_hover_text(t!("preview.export_animation_hint"))
+                    .clicked()
+                {
+                    *export_requested = true;
+                }
             });
             if ui
                 .add(
@@ -212,17 +424,53 @@ impl<F: DataSource> SerPreviewPane<F> {
             };
         }
 
+        if reload_requested {
+            if let Err(why) = self.reload(ui.ctx()) {
+                error!("Failed to reload preview: {}", why);
+            }
+        }
+
         Ok(())
         // Add some options
     }
 }
 
 impl<F: DataSource> SerPreviewPane<F> {
-    pub fn ui(&mut self, ui: &mut Ui) {
+    /// Draws `crop` over the image shown in `image_rect`
+    fn crop_overlay_ui(&self, ui: &mut Ui, image_rect: egui::Rect, crop: &CropOverlay) {
+        let Ok(frame_size) = self.size() else {
+            return;
+        };
+        let scale = image_rect.width() / frame_size[0] as f32;
+        let frame_rect = crop.frame_rect(frame_size);
+        let rect = egui::Rect::from_min_max(
+            image_rect.min + frame_rect.min.to_vec2() * scale,
+            image_rect.min + frame_rect.max.to_vec2() * scale,
+        );
+        let painter = ui.painter_at(image_rect);
+        painter.rect_stroke(
+            rect,
+            0.0,
+            egui::Stroke::new(1.5, egui::Color32::from_rgb(255, 200, 0)),
+        );
+        painter.text(
+            rect.left_top() + egui::vec2(4.0, 2.0),
+            egui::Align2::LEFT_TOP,
+            t!("preview.crop_overlay"),
+            egui::FontId::proportional(12.0),
+            egui::Color32::from_rgb(255, 200, 0),
+        );
+    }
+
+    /// Shows the preview, with `crop` drawn over the frame if given
+    pub fn ui(&mut self, ui: &mut Ui, crop: Option<&CropOverlay>) {
         self.metadata_ui(ui);
 
         if let Some(texture_handle) = &self.texture_handle {
-            ui.add(egui::Image::from_texture(texture_handle).shrink_to_fit());
+            let response = ui.add(egui::Image::from_texture(texture_handle).shrink_to_fit());
+            if let Some(crop) = crop {
+                self.crop_overlay_ui(ui, response.rect, crop);
+            }
         } else {
             ui.horizontal_centered(|ui| {
                 ui.vertical_centered(|ui| {
@@ -0,0 +1,189 @@
+//! Writes out the stacked frames individually, calibrated, centered and derotated as they were
+//! stacked, so alignment can be checked or the frames taken into other tools.
+
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{Error, Result};
+use chrono::{DateTime, Utc};
+use itertools::iproduct;
+use sciimg::prelude::Image;
+use serde::{Deserialize, Serialize};
+use solhat::context::ProcessContext;
+use solhat::datasource::DataSource;
+use solhat::framerecord::FrameRecord;
+
+use crate::cancel::*;
+use crate::partialfile;
+use crate::process::contribution::{self, StackGeometry};
+use crate::process::framesource::{self, FrameSource};
+use crate::process::serheader::{SerHeader, COLOR_ID_RGB};
+use crate::state::ApplicationState;
+use crate::taskstatus::*;
+use crate::tiffwriter::{self, TiffOptions};
+
+/// Where the aligned frames of a run are written, if anywhere
+#[derive(Debug, Eq, PartialEq, Deserialize, Serialize, Clone, Copy, Default)]
+pub enum AlignedFrameExport {
+    #[default]
+    None,
+    /// A folder of TIFFs, one per frame
+    Folder,
+    /// A single SER file
+    Ser,
+}
+
+impl AlignedFrameExport {
+    pub const ALL: [AlignedFrameExport; 3] = [
+        AlignedFrameExport::None,
+        AlignedFrameExport::Folder,
+        AlignedFrameExport::Ser,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            AlignedFrameExport::None => "None",
+            AlignedFrameExport::Folder => "Folder",
+            AlignedFrameExport::Ser => "SER",
+        }
+    }
+
+    /// The folder or SER file the frames are written to, named after the stacked output
+    pub fn path(&self, output_filename: &Path) -> Option<PathBuf> {
+        let stem = output_filename.file_stem()?.to_string_lossy();
+        match *self {
+            AlignedFrameExport::None => None,
+            AlignedFrameExport::Folder => {
+                Some(output_filename.with_file_name(format!("{}_frames", stem)))
+            }
+            AlignedFrameExport::Ser => {
+                Some(output_filename.with_file_name(format!("{}_frames.ser", stem)))
+            }
+        }
+    }
+}
+
+/// Frame `fr` resampled onto the output grid at frame scale, as it was placed in the stack
+fn aligned_frame(geometry: &StackGeometry, fr: &FrameRecord, frame: &Image) -> Result<Image> {
+    let [width, height] = geometry.output_size;
+    let mut aligned = Image::new_with_bands(width, height, frame.num_bands(), frame.get_mode())?;
+    iproduct!(0..height, 0..width).for_each(|(y, x)| {
+        let (fx, fy) = geometry.frame_position(fr, x as f32, y as f32);
+        (0..frame.num_bands()).for_each(|b| {
+            if let Some(v) = contribution::sample_band(frame, b, fx, fy) {
+                aligned.put(x, y, v, b);
+            }
+        });
+    });
+    Ok(aligned)
+}
+
+/// Writes each frame of `context`, in capture order, aligned as it was stacked to the folder or
+/// SER file given by `mode`. Frames keep their calibrated levels and are written without drizzle.
+pub fn export<F>(
+    mode: AlignedFrameExport,
+    output_filename: &Path,
+    context: &ProcessContext<F>,
+    app_state: &ApplicationState,
+) -> Result<()>
+where
+    F: DataSource + Send + Sync + 'static,
+{
+    let Some(path) = mode.path(output_filename) else {
+        return Ok(());
+    };
+    let geometry = StackGeometry::new(app_state, &context.frame_records)
+        .ok_or_else(|| Error::msg("No frames to export"))?
+        .at_frame_scale();
+    let mut frame_records = context.frame_records.clone();
+    frame_records.sort_by_key(|fr| fr.frame_id);
+
+    let frame_count = frame_records.len();
+    set_task_status(&t!("tasks.exporting_aligned_frames"), frame_count, 0);
+    let aligned_frames = frame_records.iter().enumerate().map(|(n, fr)| {
+        check_cancel_status()?;
+        let frame = fr.get_frame(context)?;
+        let aligned = aligned_frame(&geometry, fr, &frame.buffer)?;
+        set_task_status(&t!("tasks.exporting_aligned_frames"), frame_count, n + 1);
+        Ok((fr.frame_id, aligned, frame.timestamp.to_chrono_utc()))
+    });
+
+    match mode {
+        AlignedFrameExport::Ser => {
+            partialfile::write_atomically(&path, |partial| {
+                write_ser(partial, &geometry, frame_count, aligned_frames)
+            })?;
+        }
+        _ => {
+            fs::create_dir_all(&path)?;
+            for frame in aligned_frames {
+                let (frame_id, aligned, _) = frame?;
+                let frame_path = path.join(format!("frame_{:06}.tif", frame_id));
+                tiffwriter::save_tiff(&aligned, &frame_path, &TiffOptions::default(), None)?;
+            }
+        }
+    }
+    info!("Exported {} aligned frames to {:?}", frame_count, path);
+    Ok(())
+}
+
+/// Writes `frames` to a SER file at `path`, with the source's pixel depth and header text and
+/// each frame's capture time in the trailer
+fn write_ser<I>(path: &Path, geometry: &StackGeometry, frame_count: usize, frames: I) -> Result<()>
+where
+    I: Iterator<Item = Result<(usize, Image, DateTime<Utc>)>>,
+{
+    let source = FrameSource::open(std::slice::from_ref(&geometry.light))?;
+    let pixel_depth = source.pixel_depth();
+    let full_scale = ((1_u64 << pixel_depth) - 1) as f32;
+    let [width, height] = geometry.output_size;
+
+    let mut writer = BufWriter::new(File::create(path)?);
+    let mut timestamps = Vec::with_capacity(frame_count);
+    for (n, frame) in frames.enumerate() {
+        let (_, aligned, timestamp) = frame?;
+        if n == 0 {
+            let start = framesource::ser_ticks(&timestamp);
+            let header = SerHeader {
+                file_id: "LUCAM-RECORDER".to_owned(),
+                lu_id: 0,
+                color_id: if aligned.num_bands() == 3 {
+                    COLOR_ID_RGB
+                } else {
+                    0
+                },
+                // Written as most capture software reads it, 0 for little endian
+                little_endian: 0,
+                width,
+                height,
+                pixel_depth,
+                frame_count,
+                observer: source.observer(),
+                instrument: source.instrument(),
+                telescope: source.telescope(),
+                date_time: start,
+                date_time_utc: start,
+                file_size: 0,
+            };
+            writer.write_all(&header.to_bytes())?;
+        }
+        let bands = aligned.num_bands();
+        let mut bytes = Vec::with_capacity(width * height * bands * 2);
+        iproduct!(0..height, 0..width, 0..bands).for_each(|(y, x, b)| {
+            let value = aligned.get_band(b).get(x, y).round().clamp(0.0, full_scale);
+            if pixel_depth > 8 {
+                bytes.extend_from_slice(&(value as u16).to_le_bytes());
+            } else {
+                bytes.push(value as u8);
+            }
+        });
+        writer.write_all(&bytes)?;
+        timestamps.push(framesource::ser_ticks(&timestamp));
+    }
+    timestamps
+        .iter()
+        .try_for_each(|t| writer.write_all(&t.to_le_bytes()))?;
+    writer.flush()?;
+    Ok(())
+}
@@ -0,0 +1,494 @@
+//! Stacking on a grid of alignment points. Rather than shifting each frame as a whole onto its
+//! center of mass, the disk is divided into overlapping boxes which are aligned and quality
+//! ranked separately, so local seeing distortion is corrected and each region is stacked from the
+//! frames sharpest there. Neighbouring boxes are blended with tent weights, which sum to one over
+//! the grid, so no seams show between them.
+//!
+//! Alignment is measured on frames resampled at frame scale. Shifts found there are applied,
+//! scaled, when the frames are resampled onto the drizzled output.
+
+use anyhow::{Error, Result};
+use itertools::iproduct;
+use rayon::prelude::*;
+use sciimg::prelude::{Image, ImageMode};
+use solhat::context::ProcessContext;
+use solhat::datasource::DataSource;
+use solhat::framerecord::FrameRecord;
+
+use crate::cancel::*;
+use crate::process::contribution::{self, StackGeometry};
+use crate::state::{ApplicationState, FrameOrientation};
+use crate::taskstatus::*;
+
+/// Share of the stacked frames, best first, averaged into the reference that alignment points
+/// are matched against
+const REFERENCE_FRACTION: f64 = 0.1;
+
+/// Most frames averaged into the reference
+const MAX_REFERENCE_FRAMES: usize = 25;
+
+/// A box of the reference, at frame scale, which each frame is aligned to locally
+#[derive(Debug, Clone, Copy)]
+struct AlignmentPoint {
+    x: usize,
+    y: usize,
+}
+
+/// Local alignment of one frame at one alignment point
+#[derive(Debug, Clone, Copy)]
+struct LocalMeasurement {
+    /// Offset, in frame scale pixels, of the point's detail in the globally aligned frame
+    shift: [f32; 2],
+    /// Sharpness of the frame around the point. Higher is better.
+    quality: f64,
+}
+
+/// A single band image, missing samples set to NaN
+struct MonoImage {
+    width: usize,
+    height: usize,
+    data: Vec<f32>,
+}
+
+impl MonoImage {
+    fn get(&self, x: usize, y: usize) -> f32 {
+        self.data[y * self.width + x]
+    }
+
+    /// `size` x `size` box with its top left corner at (`x`, `y`), or None if any of it is
+    /// missing or outside the image
+    fn patch(&self, x: isize, y: isize, size: usize) -> Option<Vec<f32>> {
+        if x < 0 || y < 0 || x as usize + size > self.width || y as usize + size > self.height {
+            return None;
+        }
+        let (x, y) = (x as usize, y as usize);
+        let patch: Vec<f32> = iproduct!(y..y + size, x..x + size)
+            .map(|(py, px)| self.get(px, py))
+            .collect();
+        if patch.iter().any(|v| v.is_nan()) {
+            None
+        } else {
+            Some(patch)
+        }
+    }
+}
+
+/// Frame `fr` resampled at frame scale as it's globally aligned, averaged to one band
+fn aligned_mono(geometry: &StackGeometry, fr: &FrameRecord, frame: &Image) -> MonoImage {
+    let [width, height] = geometry.output_size;
+    let bands = frame.num_bands();
+    let data = (0..height)
+        .into_par_iter()
+        .flat_map_iter(|y| {
+            (0..width).map(move |x| {
+                let (fx, fy) = geometry.frame_position(fr, x as f32, y as f32);
+                (0..bands)
+                    .map(|b| contribution::sample_band(frame, b, fx, fy))
+                    .sum::<Option<f32>>()
+                    .map(|sum| sum / bands as f32)
+                    .unwrap_or(f32::NAN)
+            })
+        })
+        .collect();
+    MonoImage {
+        width,
+        height,
+        data,
+    }
+}
+
+fn mean(values: &[f32]) -> f32 {
+    values.iter().sum::<f32>() / values.len().max(1) as f32
+}
+
+/// Mismatch between two patches once each is scaled to the same mean level, so changes in
+/// transparency don't affect the match
+fn patch_cost(reference: &[f32], reference_mean: f32, candidate: &[f32]) -> f32 {
+    let candidate_mean = mean(candidate).max(f32::EPSILON);
+    reference
+        .iter()
+        .zip(candidate.iter())
+        .map(|(r, c)| {
+            let d = r / reference_mean - c / candidate_mean;
+            d * d
+        })
+        .sum::<f32>()
+}
+
+/// Gradient energy of a square patch, relative to its level
+fn patch_quality(patch: &[f32], size: usize) -> f64 {
+    let level = mean(patch).max(f32::EPSILON) as f64;
+    let energy: f64 = iproduct!(0..size - 1, 0..size - 1)
+        .map(|(y, x)| {
+            let v = patch[y * size + x] as f64;
+            let dx = patch[y * size + x + 1] as f64 - v;
+            let dy = patch[(y + 1) * size + x] as f64 - v;
+            dx * dx + dy * dy
+        })
+        .sum();
+    energy / ((size * size) as f64 * level * level)
+}
+
+/// Offset of the vertex of the parabola through three equally spaced costs, between -0.5 and 0.5
+fn subpixel_offset(before: f32, at: f32, after: f32) -> f32 {
+    let curvature = before - 2.0 * at + after;
+    if curvature <= f32::EPSILON {
+        0.0
+    } else {
+        (0.5 * (before - after) / curvature).clamp(-0.5, 0.5)
+    }
+}
+
+/// Finds where the reference box at `ap` lies in `frame`, searching up to `search_radius` pixels
+/// around it. The search is made every other pixel, then refined around the best match.
+fn measure(
+    reference: &MonoImage,
+    frame: &MonoImage,
+    ap: &AlignmentPoint,
+    size: usize,
+    search_radius: isize,
+) -> Option<LocalMeasurement> {
+    let half = (size / 2) as isize;
+    let (left, top) = (ap.x as isize - half, ap.y as isize - half);
+    let reference_patch = reference.patch(left, top, size)?;
+    let reference_mean = mean(&reference_patch).max(f32::EPSILON);
+    let cost = |dx: isize, dy: isize| {
+        frame
+            .patch(left + dx, top + dy, size)
+            .map(|patch| patch_cost(&reference_patch, reference_mean, &patch))
+    };
+
+    let best = |candidates: &mut dyn Iterator<Item = (isize, isize)>| {
+        candidates
+            .filter_map(|(dx, dy)| cost(dx, dy).map(|c| (dx, dy, c)))
+            .min_by(|a, b| a.2.total_cmp(&b.2))
+    };
+    let coarse = (-search_radius..=search_radius).step_by(2);
+    let (cx, cy, _) = best(&mut iproduct!(coarse.clone(), coarse))?;
+    let (dx, dy, at) = best(&mut iproduct!(cx - 1..=cx + 1, cy - 1..=cy + 1))?;
+
+    let refine = |before: Option<f32>, after: Option<f32>| match (before, after) {
+        (Some(before), Some(after)) => subpixel_offset(before, at, after),
+        _ => 0.0,
+    };
+    let shift = [
+        dx as f32 + refine(cost(dx - 1, dy), cost(dx + 1, dy)),
+        dy as f32 + refine(cost(dx, dy - 1), cost(dx, dy + 1)),
+    ];
+    let quality = patch_quality(&frame.patch(left + dx, top + dy, size)?, size);
+    Some(LocalMeasurement { shift, quality })
+}
+
+/// Mean of the globally aligned `frame_records`
+fn reference_image<F>(
+    context: &ProcessContext<F>,
+    geometry: &StackGeometry,
+    frame_records: &[&FrameRecord],
+) -> Result<MonoImage>
+where
+    F: DataSource + Send + Sync + 'static,
+{
+    let [width, height] = geometry.output_size;
+    let mut sums = vec![0.0_f32; width * height];
+    let mut counts = vec![0_u32; width * height];
+    set_task_status(&t!("tasks.ap_reference"), frame_records.len(), 0);
+    for (n, fr) in frame_records.iter().enumerate() {
+        check_cancel_status()?;
+        let aligned = aligned_mono(geometry, fr, &fr.get_frame(context)?.buffer);
+        aligned.data.iter().enumerate().for_each(|(i, v)| {
+            if !v.is_nan() {
+                sums[i] += v;
+                counts[i] += 1;
+            }
+        });
+        set_task_status(&t!("tasks.ap_reference"), frame_records.len(), n + 1);
+    }
+    Ok(MonoImage {
+        width,
+        height,
+        data: sums
+            .iter()
+            .zip(counts.iter())
+            .map(|(s, c)| if *c > 0 { s / *c as f32 } else { f32::NAN })
+            .collect(),
+    })
+}
+
+/// Grid of points, spaced half a box apart, over the parts of the reference at least
+/// `min_brightness` of its peak level, with room around them to search
+fn place_alignment_points(
+    reference: &MonoImage,
+    size: usize,
+    search_radius: usize,
+    min_brightness: f32,
+) -> Vec<AlignmentPoint> {
+    let half = size / 2;
+    let spacing = half.max(1);
+    let margin = half + search_radius + 1;
+    if reference.width <= 2 * margin || reference.height <= 2 * margin {
+        return vec![];
+    }
+    let peak = reference
+        .data
+        .iter()
+        .copied()
+        .filter(|v| !v.is_nan())
+        .fold(0.0_f32, f32::max);
+    let threshold = peak * min_brightness;
+    iproduct!(
+        (margin..reference.height - margin).step_by(spacing),
+        (margin..reference.width - margin).step_by(spacing)
+    )
+    .map(|(y, x)| AlignmentPoint { x, y })
+    .filter(|ap| {
+        reference
+            .patch(
+                ap.x as isize - half as isize,
+                ap.y as isize - half as isize,
+                size,
+            )
+            .map(|patch| mean(&patch) >= threshold)
+            .unwrap_or(false)
+    })
+    .collect()
+}
+
+/// Per-pixel sums and sample counts for each band over a region of the output
+struct RegionAccumulator {
+    left: usize,
+    top: usize,
+    width: usize,
+    height: usize,
+    sums: Vec<Vec<f32>>,
+    counts: Vec<Vec<u32>>,
+}
+
+impl RegionAccumulator {
+    fn new(left: usize, top: usize, width: usize, height: usize, num_bands: usize) -> Self {
+        RegionAccumulator {
+            left,
+            top,
+            width,
+            height,
+            sums: vec![vec![0.0; width * height]; num_bands],
+            counts: vec![vec![0; width * height]; num_bands],
+        }
+    }
+
+    /// Adds `frame` sampled at its position for each output pixel of the region, shifted by
+    /// `shift` output pixels
+    fn add(&mut self, geometry: &StackGeometry, fr: &FrameRecord, frame: &Image, shift: [f32; 2]) {
+        let (left, top, width, height) = (self.left, self.top, self.width, self.height);
+        self.sums
+            .par_iter_mut()
+            .zip(self.counts.par_iter_mut())
+            .enumerate()
+            .for_each(|(b, (sums, counts))| {
+                iproduct!(0..height, 0..width).for_each(|(y, x)| {
+                    let (fx, fy) = geometry.frame_position(
+                        fr,
+                        (left + x) as f32 + shift[0],
+                        (top + y) as f32 + shift[1],
+                    );
+                    if let Some(v) = contribution::sample_band(frame, b, fx, fy) {
+                        let i = y * width + x;
+                        sums[i] += v;
+                        counts[i] += 1;
+                    }
+                });
+            });
+    }
+
+    /// Mean of band `b` at output pixel (`x`, `y`), if any frame covered it
+    fn mean(&self, b: usize, x: usize, y: usize) -> Option<f32> {
+        if x < self.left || y < self.top {
+            return None;
+        }
+        let (x, y) = (x - self.left, y - self.top);
+        if x >= self.width || y >= self.height {
+            return None;
+        }
+        let i = y * self.width + x;
+        let count = self.counts[b][i];
+        (count > 0).then(|| self.sums[b][i] / count as f32)
+    }
+}
+
+/// An alignment point's region of the output, with the frames selected for it
+struct PointStack {
+    /// Center of the point on the output
+    center: [f32; 2],
+    /// Distance from the center at which the point's blending weight falls to zero
+    reach: f32,
+    /// Local measurement of each stacked frame, None if the frame wasn't selected
+    measurements: Vec<Option<LocalMeasurement>>,
+    accumulator: RegionAccumulator,
+}
+
+impl PointStack {
+    /// Tent blending weight of the point at output pixel (`x`, `y`)
+    fn weight(&self, x: usize, y: usize) -> f32 {
+        let tent = |d: f32| (1.0 - d.abs() / self.reach).max(0.0);
+        tent(x as f32 - self.center[0]) * tent(y as f32 - self.center[1])
+    }
+}
+
+/// Keeps the `keep_percentage` best of `measurements` by quality, at least one
+fn select_best(
+    measurements: Vec<Option<LocalMeasurement>>,
+    keep_percentage: f64,
+) -> Vec<Option<LocalMeasurement>> {
+    let mut qualities: Vec<f64> = measurements.iter().flatten().map(|m| m.quality).collect();
+    if qualities.is_empty() {
+        return measurements;
+    }
+    qualities.sort_by(|a, b| b.total_cmp(a));
+    let keep = ((qualities.len() as f64 * keep_percentage / 100.0).ceil() as usize)
+        .clamp(1, qualities.len());
+    let cutoff = qualities[keep - 1];
+    measurements
+        .into_iter()
+        .map(|m| m.filter(|m| m.quality >= cutoff))
+        .collect()
+}
+
+/// Stacks `context`'s frames on a grid of alignment points over the target. Each point is
+/// stacked from the frames sharpest around it, each shifted so its local detail lines up with
+/// the reference, and the points are blended into an average of all the frames. Returns the
+/// stack before reorientation, like the other stacking methods.
+pub fn stack_with_alignment_points<F>(
+    context: &ProcessContext<F>,
+    app_state: &ApplicationState,
+) -> Result<Image>
+where
+    F: DataSource + Send + Sync + 'static,
+{
+    let geometry = StackGeometry {
+        orientation: FrameOrientation::Normal,
+        ..StackGeometry::new(app_state, &context.frame_records)
+            .ok_or_else(|| Error::msg("Zero frames to stack. Cannot continue"))?
+    };
+    let frame_scale = geometry.at_frame_scale();
+    let scale = geometry.scale;
+    let size = app_state.ap_size.max(8);
+    let search_radius = app_state.ap_search_radius;
+
+    let mut by_quality: Vec<&FrameRecord> = context.frame_records.iter().collect();
+    by_quality.sort_by(|a, b| b.sigma.total_cmp(&a.sigma));
+    let reference_count = ((by_quality.len() as f64 * REFERENCE_FRACTION).ceil() as usize)
+        .clamp(1, MAX_REFERENCE_FRAMES);
+    let reference = reference_image(context, &frame_scale, &by_quality[..reference_count])?;
+
+    let points = place_alignment_points(
+        &reference,
+        size,
+        search_radius,
+        app_state.ap_min_brightness as f32,
+    );
+    if points.is_empty() {
+        return Err(Error::msg(
+            "No alignment points could be placed. Try a smaller box size or minimum brightness.",
+        ));
+    }
+    info!(
+        "Placed {} alignment points of {} pixels, matched against {} reference frames",
+        points.len(),
+        size,
+        reference_count
+    );
+
+    // Measure every frame at every point before choosing which frames each point keeps
+    let frame_count = context.frame_records.len();
+    set_task_status(&t!("tasks.ap_analysis"), frame_count, 0);
+    let mut measurements: Vec<Vec<Option<LocalMeasurement>>> =
+        vec![Vec::with_capacity(frame_count); points.len()];
+    for (n, fr) in context.frame_records.iter().enumerate() {
+        check_cancel_status()?;
+        let aligned = aligned_mono(&frame_scale, fr, &fr.get_frame(context)?.buffer);
+        let frame_measurements: Vec<Option<LocalMeasurement>> = points
+            .par_iter()
+            .map(|ap| measure(&reference, &aligned, ap, size, search_radius as isize))
+            .collect();
+        measurements
+            .iter_mut()
+            .zip(frame_measurements)
+            .for_each(|(m, fm)| m.push(fm));
+        set_task_status(&t!("tasks.ap_analysis"), frame_count, n + 1);
+    }
+
+    let [out_width, out_height] = geometry.output_size;
+    let [frame_scale_width, frame_scale_height] = frame_scale.output_size;
+    let num_bands = context
+        .frame_records
+        .first()
+        .map(|fr| fr.get_frame(context))
+        .transpose()?
+        .map(|f| f.buffer.num_bands())
+        .unwrap_or(1);
+    let reach = (size / 2) as f32 * scale;
+    let mut stacks: Vec<PointStack> = points
+        .iter()
+        .zip(measurements)
+        .map(|(ap, measurements)| {
+            let center = [
+                out_width as f32 / 2.0 + (ap.x as f32 - frame_scale_width as f32 / 2.0) * scale,
+                out_height as f32 / 2.0 + (ap.y as f32 - frame_scale_height as f32 / 2.0) * scale,
+            ];
+            let left = (center[0] - reach).floor().max(0.0) as usize;
+            let top = (center[1] - reach).floor().max(0.0) as usize;
+            let right = ((center[0] + reach).ceil() as usize + 1).min(out_width);
+            let bottom = ((center[1] + reach).ceil() as usize + 1).min(out_height);
+            PointStack {
+                center,
+                reach,
+                measurements: select_best(measurements, app_state.ap_keep_percentage),
+                accumulator: RegionAccumulator::new(
+                    left,
+                    top,
+                    right.saturating_sub(left),
+                    bottom.saturating_sub(top),
+                    num_bands,
+                ),
+            }
+        })
+        .collect();
+
+    // The whole output stacked from every frame, for the parts no point covers
+    let mut global = RegionAccumulator::new(0, 0, out_width, out_height, num_bands);
+
+    set_task_status(&t!("tasks.stacking"), frame_count, 0);
+    for (n, fr) in context.frame_records.iter().enumerate() {
+        check_cancel_status()?;
+        let frame = fr.get_frame(context)?.buffer;
+        global.add(&geometry, fr, &frame, [0.0, 0.0]);
+        stacks.par_iter_mut().for_each(|stack| {
+            if let Some(m) = stack.measurements[n] {
+                let shift = [m.shift[0] * scale, m.shift[1] * scale];
+                stack.accumulator.add(&geometry, fr, &frame, shift);
+            }
+        });
+        set_task_status(&t!("tasks.stacking"), frame_count, n + 1);
+    }
+
+    let mut image = Image::new_with_bands(out_width, out_height, num_bands, ImageMode::U16BIT)?;
+    iproduct!(0..num_bands, 0..out_height, 0..out_width).for_each(|(b, y, x)| {
+        let (sum, weight) = stacks
+            .iter()
+            .filter_map(|stack| {
+                let w = stack.weight(x, y);
+                if w > 0.0 {
+                    stack.accumulator.mean(b, x, y).map(|v| (v * w, w))
+                } else {
+                    None
+                }
+            })
+            .fold((0.0, 0.0), |(s, t), (v, w)| (s + v, t + w));
+        let value = match global.mean(b, x, y) {
+            Some(g) if weight < 1.0 => sum + (1.0 - weight) * g,
+            _ if weight > 0.0 => sum / weight,
+            _ => 0.0,
+        };
+        image.put(x, y, value, b);
+    });
+    Ok(image)
+}
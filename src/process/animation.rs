@@ -0,0 +1,198 @@
+//! Animated exports of a capture, for sharing the seeing conditions it was taken in. GIFs are
+//! encoded directly, MP4s through ffmpeg, which must be installed and on the PATH.
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use anyhow::{anyhow, Result};
+use image::codecs::gif::{GifEncoder, Repeat};
+use image::{Delay, Frame, RgbaImage};
+use itertools::iproduct;
+use sciimg::prelude::Image;
+use solhat::datasource::DataSource;
+
+use crate::cancel::*;
+use crate::imageutil;
+use crate::partialfile;
+use crate::process::framesource::FrameSource;
+use crate::taskstatus::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AnimationFormat {
+    #[default]
+    Gif,
+    Mp4,
+}
+
+impl AnimationFormat {
+    pub const ALL: [AnimationFormat; 2] = [AnimationFormat::Gif, AnimationFormat::Mp4];
+
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            AnimationFormat::Gif => "GIF",
+            AnimationFormat::Mp4 => "MP4",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match *self {
+            AnimationFormat::Gif => "gif",
+            AnimationFormat::Mp4 => "mp4",
+        }
+    }
+}
+
+/// Which frames of a capture are exported, and how
+#[derive(Debug, Clone)]
+pub struct AnimationOptions {
+    pub format: AnimationFormat,
+    /// First and last frames exported, inclusive
+    pub first_frame: usize,
+    pub last_frame: usize,
+    /// Binning factor applied to each frame
+    pub downscale: usize,
+    pub frame_rate: u32,
+}
+
+/// Levels mapped to black and white, from the first frame exported. The same levels are used for
+/// every frame so changes in transparency show.
+struct Stretch {
+    min: f32,
+    range: f32,
+}
+
+impl Stretch {
+    fn from_image(image: &Image) -> Self {
+        let (min, max) = image.get_min_max_all_channel();
+        Stretch {
+            min,
+            range: (max - min).max(f32::EPSILON),
+        }
+    }
+
+    /// `image` as interleaved 8-bit RGB, monochrome images repeated across the channels
+    fn to_rgb(&self, image: &Image) -> Vec<u8> {
+        let bands = image.num_bands();
+        iproduct!(0..image.height, 0..image.width, 0..3)
+            .map(|(y, x, c)| {
+                let v = image.get_band(c.min(bands - 1)).get(x, y);
+                ((v - self.min) / self.range * 255.0)
+                    .round()
+                    .clamp(0.0, 255.0) as u8
+            })
+            .collect()
+    }
+}
+
+/// Writes frames `first_frame` to `last_frame` of `source` to an animation at `output`. Returns
+/// the number of frames written.
+pub async fn export_async(
+    source: String,
+    options: AnimationOptions,
+    output: PathBuf,
+) -> Result<usize> {
+    let source = FrameSource::open(std::slice::from_ref(&source))?;
+    let last_frame = options
+        .last_frame
+        .min(source.frame_count().saturating_sub(1));
+    if source.frame_count() == 0 || options.first_frame > last_frame {
+        return Err(anyhow!("No frames in the chosen range"));
+    }
+    let frames = options.first_frame..=last_frame;
+    let frame_count = frames.clone().count();
+    let downscale = options.downscale.max(1);
+
+    let first =
+        imageutil::downscale_image(&source.get_frame(options.first_frame)?.buffer, downscale);
+    let stretch = Stretch::from_image(&first);
+    let size = [first.width, first.height];
+
+    set_task_status(&t!("tasks.exporting_animation"), frame_count, 0);
+    let rgb_frames = frames.enumerate().map(|(n, frame_no)| {
+        check_cancel_status()?;
+        let frame = imageutil::downscale_image(&source.get_frame(frame_no)?.buffer, downscale);
+        if [frame.width, frame.height] != size {
+            return Err(anyhow!(
+                "Frame {} is a different size from the first",
+                frame_no
+            ));
+        }
+        set_task_status(&t!("tasks.exporting_animation"), frame_count, n + 1);
+        Ok(stretch.to_rgb(&frame))
+    });
+
+    partialfile::write_atomically(&output, |partial| match options.format {
+        AnimationFormat::Gif => write_gif(partial, size, options.frame_rate, rgb_frames),
+        AnimationFormat::Mp4 => write_mp4(partial, size, options.frame_rate, rgb_frames),
+    })?;
+    info!("Exported {} frames to {:?}", frame_count, output);
+    Ok(frame_count)
+}
+
+fn write_gif<I>(path: &Path, size: [usize; 2], frame_rate: u32, frames: I) -> Result<()>
+where
+    I: Iterator<Item = Result<Vec<u8>>>,
+{
+    let mut encoder = GifEncoder::new_with_speed(BufWriter::new(File::create(path)?), 10);
+    encoder.set_repeat(Repeat::Infinite)?;
+    let delay = Delay::from_numer_denom_ms(1000, frame_rate.max(1));
+    for rgb in frames {
+        let rgba: Vec<u8> = rgb?
+            .chunks_exact(3)
+            .flat_map(|p| [p[0], p[1], p[2], 255])
+            .collect();
+        let image = RgbaImage::from_raw(size[0] as u32, size[1] as u32, rgba)
+            .ok_or_else(|| anyhow!("Frame buffer doesn't match the animation size"))?;
+        encoder.encode_frame(Frame::from_parts(image, 0, 0, delay))?;
+    }
+    Ok(())
+}
+
+/// Pipes the frames through ffmpeg as raw RGB, encoding them as H.264. Frames are padded to even
+/// dimensions as the encoder requires.
+fn write_mp4<I>(path: &Path, size: [usize; 2], frame_rate: u32, mut frames: I) -> Result<()>
+where
+    I: Iterator<Item = Result<Vec<u8>>>,
+{
+    let mut ffmpeg = Command::new("ffmpeg")
+        .args([
+            "-y",
+            "-loglevel",
+            "error",
+            "-f",
+            "rawvideo",
+            "-pix_fmt",
+            "rgb24",
+        ])
+        .args(["-s", &format!("{}x{}", size[0], size[1])])
+        .args(["-r", &frame_rate.max(1).to_string(), "-i", "-"])
+        .args(["-vf", "pad=ceil(iw/2)*2:ceil(ih/2)*2"])
+        .args(["-c:v", "libx264", "-pix_fmt", "yuv420p", "-f", "mp4"])
+        .arg(path)
+        .stdin(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|why| {
+            warn!("Failed to start ffmpeg: {}", why);
+            anyhow!(t!("animation.ffmpeg_missing").to_string())
+        })?;
+
+    let written = {
+        let mut stdin = ffmpeg
+            .stdin
+            .take()
+            .ok_or_else(|| anyhow!("Failed to open ffmpeg's input"))?;
+        frames.try_for_each(|rgb| -> Result<()> { Ok(stdin.write_all(&rgb?)?) })
+    };
+    let output = ffmpeg.wait_with_output()?;
+    // A failed write is usually ffmpeg exiting early, in which case its own error says why
+    if !output.status.success() {
+        return Err(anyhow!(
+            "ffmpeg failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    written
+}
@@ -0,0 +1,212 @@
+use anyhow::Result;
+use itertools::iproduct;
+use sciimg::prelude::{Image, ImageBuffer};
+use serde::{Deserialize, Serialize};
+use solhat::datasource::{DataFrame, DataSource};
+use solhat::timestamp::TimeStamp;
+
+use crate::imageutil;
+use crate::process::framesource::FrameSource;
+use crate::process::{calibration, exposure};
+use crate::state::ApplicationState;
+use crate::taskstatus::*;
+
+/// Sensor columns and rows which are defective along their full length
+#[derive(Debug, Default, Deserialize, Serialize, Clone, PartialEq)]
+#[serde(default)]
+pub struct BadLineMap {
+    pub columns: Vec<usize>,
+    pub rows: Vec<usize>,
+}
+
+impl BadLineMap {
+    pub fn is_empty(&self) -> bool {
+        self.columns.is_empty() && self.rows.is_empty()
+    }
+
+    pub fn add_column(&mut self, column: usize) {
+        if !self.columns.contains(&column) {
+            self.columns.push(column);
+            self.columns.sort();
+        }
+    }
+
+    pub fn add_row(&mut self, row: usize) {
+        if !self.rows.contains(&row) {
+            self.rows.push(row);
+            self.rows.sort();
+        }
+    }
+
+    /// Replaces each bad column and row in every band of `image` with a linear interpolation of
+    /// the nearest good lines on either side.
+    pub fn correct_image(&self, image: &mut Image) {
+        if self.is_empty() {
+            return;
+        }
+        let (width, height) = (image.width, image.height);
+        (0..image.num_bands()).for_each(|b| {
+            self.columns.iter().filter(|c| **c < width).for_each(|c| {
+                let (left, right) = nearest_good(*c, width, &self.columns);
+                (0..height).for_each(|y| {
+                    let v = interpolate(*c, left, right, |x| image.get_band(b).get(x, y));
+                    image.put(*c, y, v, b);
+                });
+            });
+            self.rows.iter().filter(|r| **r < height).for_each(|r| {
+                let (above, below) = nearest_good(*r, height, &self.rows);
+                (0..width).for_each(|x| {
+                    let v = interpolate(*r, above, below, |y| image.get_band(b).get(x, y));
+                    image.put(x, *r, v, b);
+                });
+            });
+        });
+    }
+}
+
+/// Nearest line indices before and after `line` which are not in `bad`
+fn nearest_good(line: usize, len: usize, bad: &[usize]) -> (Option<usize>, Option<usize>) {
+    let before = (0..line).rev().find(|l| !bad.contains(l));
+    let after = (line + 1..len).find(|l| !bad.contains(l));
+    (before, after)
+}
+
+fn interpolate<F>(line: usize, before: Option<usize>, after: Option<usize>, value_at: F) -> f32
+where
+    F: Fn(usize) -> f32,
+{
+    match (before, after) {
+        (Some(b), Some(a)) => {
+            let t = (line - b) as f32 / (a - b) as f32;
+            value_at(b) * (1.0 - t) + value_at(a) * t
+        }
+        (Some(b), None) => value_at(b),
+        (None, Some(a)) => value_at(a),
+        (None, None) => value_at(line),
+    }
+}
+
+/// Indices of the values which deviate from the median by more than `threshold` robust standard
+/// deviations (median absolute deviation scaled to sigma).
+fn outliers(values: &[f64], threshold: f64) -> Vec<usize> {
+    let mut finite: Vec<f64> = values.iter().copied().filter(|v| v.is_finite()).collect();
+    let m = imageutil::median(&mut finite);
+    let mut deviations: Vec<f64> = finite.iter().map(|v| (v - m).abs()).collect();
+    let sigma = imageutil::median(&mut deviations) * imageutil::MAD_TO_SIGMA;
+    if sigma <= 0.0 {
+        return vec![];
+    }
+    values
+        .iter()
+        .enumerate()
+        .filter(|(_, v)| (*v - m).abs() > threshold * sigma)
+        .map(|(i, _)| i)
+        .collect()
+}
+
+/// Finds columns and rows of `buffer` whose mean level is an outlier relative to the others
+pub fn detect_bad_lines(buffer: &ImageBuffer, threshold: f64) -> BadLineMap {
+    let mut column_sums = vec![0.0; buffer.width];
+    let mut row_sums = vec![0.0; buffer.height];
+    iproduct!(0..buffer.height, 0..buffer.width).for_each(|(y, x)| {
+        let v = buffer.get(x, y) as f64;
+        column_sums[x] += v;
+        row_sums[y] += v;
+    });
+    let column_means: Vec<f64> = column_sums
+        .iter()
+        .map(|s| s / buffer.height as f64)
+        .collect();
+    let row_means: Vec<f64> = row_sums.iter().map(|s| s / buffer.width as f64).collect();
+
+    BadLineMap {
+        columns: outliers(&column_means, threshold),
+        rows: outliers(&row_means, threshold),
+    }
+}
+
+/// Computes a master dark from the dark input and detects bad columns and rows in it
+pub async fn detect_from_dark_async(app_state: ApplicationState) -> Result<BadLineMap> {
+    let image = calibration::compute_master_dark(&app_state)?;
+
+    set_task_status(&t!("tasks.detecting_bad_lines"), 0, 0);
+    let bad_lines = detect_bad_lines(image.get_band(0), app_state.bad_line_threshold);
+    info!(
+        "Detected {} bad columns and {} bad rows",
+        bad_lines.columns.len(),
+        bad_lines.rows.len()
+    );
+    Ok(bad_lines)
+}
+
+/// Light data source which corrects the bad lines, and applies any active dark scaling, of the
+/// job it's assigned to as each frame is read. solhat opens data sources itself, leaving no way to
+/// hand them options, so the job's options are given once the process context is created.
+pub struct CorrectedSerFile {
+    ser_file: FrameSource,
+    bad_lines: BadLineMap,
+    slot: usize,
+}
+
+impl CorrectedSerFile {
+    /// Assigns the file to the job running in `slot`, correcting `bad_lines` in its frames
+    pub fn assign_to_job(&mut self, slot: usize, bad_lines: &BadLineMap) {
+        self.slot = slot;
+        self.bad_lines = bad_lines.clone();
+    }
+}
+
+impl DataSource for CorrectedSerFile {
+    fn open(filenames: &[String]) -> Result<Self> {
+        Ok(CorrectedSerFile {
+            ser_file: FrameSource::open(filenames)?,
+            bad_lines: BadLineMap::default(),
+            slot: PRIMARY_SLOT,
+        })
+    }
+
+    fn image_width(&self) -> usize {
+        self.ser_file.image_width()
+    }
+
+    fn image_height(&self) -> usize {
+        self.ser_file.image_height()
+    }
+
+    fn frame_count(&self) -> usize {
+        self.ser_file.frame_count()
+    }
+
+    fn pixel_depth(&self) -> usize {
+        self.ser_file.pixel_depth()
+    }
+
+    fn get_frame(&self, frame_num: usize) -> Result<DataFrame> {
+        let mut frame = self.ser_file.get_frame(frame_num)?;
+        self.bad_lines.correct_image(&mut frame.buffer);
+        if let Some(dark_scaling) = exposure::active_dark_scaling(self.slot) {
+            dark_scaling.apply(&mut frame.buffer);
+        }
+        Ok(frame)
+    }
+
+    fn source_file(&self) -> String {
+        self.ser_file.source_file()
+    }
+
+    fn observer(&self) -> String {
+        self.ser_file.observer()
+    }
+
+    fn instrument(&self) -> String {
+        self.ser_file.instrument()
+    }
+
+    fn telescope(&self) -> String {
+        self.ser_file.telescope()
+    }
+
+    fn date_time_utc(&self) -> TimeStamp {
+        self.ser_file.date_time_utc()
+    }
+}
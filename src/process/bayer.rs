@@ -0,0 +1,230 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use anyhow::Result;
+use itertools::iproduct;
+use sciimg::prelude::Image;
+use serde::{Deserialize, Serialize};
+
+use crate::process::serheader::SerHeader;
+use crate::state::ApplicationState;
+
+lazy_static! {
+    // Bayer pattern overrides by input path. Keyed by path as inputs are opened through
+    // `DataSource::open`, which takes nothing but the file names.
+    static ref BAYER_OVERRIDES: Arc<Mutex<HashMap<String, BayerPattern>>> =
+        Arc::new(Mutex::new(HashMap::new()));
+}
+
+/// How the color filter array of an input is interpreted
+#[derive(Debug, Default, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+pub enum BayerPattern {
+    /// Trust the color ID in the SER header
+    #[default]
+    FromHeader,
+    /// Read the raw mosaic as monochrome, without debayering
+    Mono,
+    Rggb,
+    Bggr,
+    Grbg,
+    Gbrg,
+}
+
+impl BayerPattern {
+    pub const ALL: [BayerPattern; 6] = [
+        BayerPattern::FromHeader,
+        BayerPattern::Mono,
+        BayerPattern::Rggb,
+        BayerPattern::Bggr,
+        BayerPattern::Grbg,
+        BayerPattern::Gbrg,
+    ];
+
+    pub fn label(&self) -> String {
+        match self {
+            BayerPattern::FromHeader => t!("bayer.from_header").to_string(),
+            BayerPattern::Mono => t!("bayer.mono").to_string(),
+            BayerPattern::Rggb => "RGGB".to_owned(),
+            BayerPattern::Bggr => "BGGR".to_owned(),
+            BayerPattern::Grbg => "GRBG".to_owned(),
+            BayerPattern::Gbrg => "GBRG".to_owned(),
+        }
+    }
+
+    /// Band (0 red, 1 green, 2 blue) of the filter over pixel `x`, `y`, or None if there is no
+    /// mosaic
+    fn band_at(&self, x: usize, y: usize) -> Option<usize> {
+        let layout = match self {
+            BayerPattern::Rggb => [0, 1, 1, 2],
+            BayerPattern::Bggr => [2, 1, 1, 0],
+            BayerPattern::Grbg => [1, 0, 2, 1],
+            BayerPattern::Gbrg => [1, 2, 0, 1],
+            BayerPattern::FromHeader | BayerPattern::Mono => return None,
+        };
+        Some(layout[(y % 2) * 2 + x % 2])
+    }
+
+    /// Pattern described by a SER color ID. None for files which hold full color frames.
+    fn from_color_id(color_id: i32) -> Option<BayerPattern> {
+        match color_id {
+            8 => Some(BayerPattern::Rggb),
+            9 => Some(BayerPattern::Grbg),
+            10 => Some(BayerPattern::Gbrg),
+            11 => Some(BayerPattern::Bggr),
+            100 | 101 => None,
+            _ => Some(BayerPattern::Mono),
+        }
+    }
+}
+
+/// Bayer pattern override of each input. Any additional calibration files share the override of
+/// their input.
+#[derive(Debug, Default, Deserialize, Serialize, Clone, PartialEq)]
+#[serde(default)]
+pub struct BayerOverrides {
+    pub light: BayerPattern,
+    pub dark: BayerPattern,
+    pub flat: BayerPattern,
+    pub darkflat: BayerPattern,
+    pub bias: BayerPattern,
+}
+
+impl BayerOverrides {
+    pub fn is_empty(&self) -> bool {
+        *self == BayerOverrides::default()
+    }
+}
+
+pub fn set_override(path: &str, pattern: BayerPattern) {
+    BAYER_OVERRIDES
+        .lock()
+        .unwrap()
+        .insert(path.to_owned(), pattern);
+}
+
+pub fn override_for(path: &str) -> BayerPattern {
+    BAYER_OVERRIDES
+        .lock()
+        .unwrap()
+        .get(path)
+        .cloned()
+        .unwrap_or_default()
+}
+
+/// Sets the overrides of the inputs of `app_state`, for the files it opens
+pub fn register_overrides(app_state: &ApplicationState) {
+    let overrides = &app_state.bayer;
+    let inputs: [(Vec<String>, BayerPattern); 5] = [
+        (app_state.light.iter().cloned().collect(), overrides.light),
+        (app_state.dark_inputs(), overrides.dark),
+        (app_state.flat_inputs(), overrides.flat),
+        (app_state.darkflat_inputs(), overrides.darkflat),
+        (app_state.bias_inputs(), overrides.bias),
+    ];
+    for (paths, pattern) in inputs {
+        paths.iter().for_each(|path| set_override(path, pattern));
+    }
+}
+
+/// Pattern given by the color ID in the header of the SER file at `path`
+fn header_pattern(path: &Path) -> Result<Option<BayerPattern>> {
+    Ok(BayerPattern::from_color_id(SerHeader::read(path)?.color_id))
+}
+
+/// Reinterpretation of the frames of a SER file whose header has the wrong color ID
+#[derive(Debug, Clone, Copy)]
+pub struct BayerCorrection {
+    /// Pattern the frames were debayered with when read, going by the header
+    header: BayerPattern,
+    pattern: BayerPattern,
+}
+
+impl BayerCorrection {
+    /// The correction for the SER file at `path` given its override, or None if the frames are
+    /// to be read as they are
+    pub fn for_file(path: &str) -> Result<Option<BayerCorrection>> {
+        let pattern = override_for(path);
+        if pattern == BayerPattern::FromHeader {
+            return Ok(None);
+        }
+        match header_pattern(Path::new(path))? {
+            Some(header) if header != pattern => {
+                info!(
+                    "Reading {} as {} rather than {} given by its header",
+                    path,
+                    pattern.label(),
+                    header.label()
+                );
+                Ok(Some(BayerCorrection { header, pattern }))
+            }
+            Some(_) => Ok(None),
+            None => {
+                warn!(
+                    "{} holds full color frames, ignoring its Bayer pattern override",
+                    path
+                );
+                Ok(None)
+            }
+        }
+    }
+
+    pub fn apply(&self, image: &Image) -> Result<Image> {
+        // Debayering leaves each pixel's own filter color as it was, so the raw mosaic is
+        // recovered from frames debayered with the wrong pattern.
+        let mosaic = if image.num_bands() == 1 {
+            image.clone()
+        } else {
+            remosaic(image, self.header)?
+        };
+        if self.pattern == BayerPattern::Mono {
+            Ok(mosaic)
+        } else {
+            debayer(&mosaic, self.pattern)
+        }
+    }
+}
+
+/// The single band raw mosaic of a debayered image
+fn remosaic(image: &Image, pattern: BayerPattern) -> Result<Image> {
+    let mut mosaic = Image::new_with_bands(image.width, image.height, 1, image.get_mode())?;
+    iproduct!(0..image.height, 0..image.width).for_each(|(y, x)| {
+        let band = pattern
+            .band_at(x, y)
+            .unwrap_or(0)
+            .min(image.num_bands() - 1);
+        mosaic.put(x, y, image.get_band(band).get(x, y), 0);
+    });
+    Ok(mosaic)
+}
+
+/// Bilinear demosaic: each missing color is the mean of the neighboring pixels having it
+fn debayer(mosaic: &Image, pattern: BayerPattern) -> Result<Image> {
+    let (width, height) = (mosaic.width, mosaic.height);
+    let raw = mosaic.get_band(0);
+    let mut image = Image::new_with_bands(width, height, 3, mosaic.get_mode())?;
+    iproduct!(0..height, 0..width, 0..3).for_each(|(y, x, band)| {
+        let value = if pattern.band_at(x, y) == Some(band) {
+            raw.get(x, y)
+        } else {
+            let mut sum = 0.0;
+            let mut count = 0;
+            iproduct!(
+                y.saturating_sub(1)..(y + 2).min(height),
+                x.saturating_sub(1)..(x + 2).min(width)
+            )
+            .filter(|(ny, nx)| pattern.band_at(*nx, *ny) == Some(band))
+            .for_each(|(ny, nx)| {
+                sum += raw.get(nx, ny);
+                count += 1;
+            });
+            if count > 0 {
+                sum / count as f32
+            } else {
+                0.0
+            }
+        };
+        image.put(x, y, value, band);
+    });
+    Ok(image)
+}
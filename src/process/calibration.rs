@@ -0,0 +1,267 @@
+use std::path::Path;
+
+use anyhow::{Error, Result};
+use itertools::iproduct;
+use sciimg::prelude::{Image, ImageMode};
+use solhat::calibrationframe::CalibrationImage;
+use solhat::datasource::DataSource;
+
+use crate::cancel::*;
+use crate::process::framesource::{self, FrameSource};
+use crate::state::{ApplicationState, MasterMethod};
+use crate::taskstatus::*;
+
+/// Values further than this many standard deviations from the median are rejected by the
+/// sigma-clipped mean
+const CLIP_KAPPA: f32 = 3.0;
+
+/// Rejection passes of the sigma-clipped mean. Each pass recomputes the median and standard
+/// deviation of the values left by the previous one.
+const CLIP_ITERATIONS: usize = 3;
+
+/// Bytes of frame data held in memory at once when combining frames by their median or
+/// sigma-clipped mean
+const STACK_MEMORY_BUDGET: usize = 512 * 1024 * 1024;
+
+/// Returns the indices of the frames to use for a master when limited to `max_frames`. Frames are
+/// spread evenly across the file. A `max_frames` of zero means all frames are used.
+fn select_frames(frame_count: usize, max_frames: usize) -> Vec<usize> {
+    if max_frames == 0 || frame_count <= max_frames {
+        (0..frame_count).collect()
+    } else {
+        let step = frame_count as f64 / max_frames as f64;
+        (0..max_frames)
+            .map(|i| (i as f64 * step).floor() as usize)
+            .collect()
+    }
+}
+
+/// Computes a master calibration image from one or more SER files, reading at most `max_frames`
+/// frames across all of them. A single still image is taken to be a master made elsewhere and
+/// used as it is. `on_frame_read` is called with the number of frames read so far and
+/// the total to be read. The computation stops with an error if a task cancel is requested.
+pub fn compute_master<C>(
+    input_files: &[String],
+    method: MasterMethod,
+    max_frames: usize,
+    on_frame_read: C,
+) -> Result<CalibrationImage>
+where
+    C: Fn(usize, usize),
+{
+    if let [input_file] = input_files {
+        let path = Path::new(input_file);
+        if framesource::is_still_image(path) {
+            info!("Using {} as the master", input_file);
+            let image = framesource::open_still_image(path)?;
+            on_frame_read(1, 1);
+            return Ok(CalibrationImage { image: Some(image) });
+        }
+    }
+
+    let ser_files = input_files
+        .iter()
+        .map(|f| FrameSource::open(&[f.to_owned()]))
+        .collect::<Result<Vec<FrameSource>>>()?;
+    if let Some(first) = ser_files.first() {
+        let size = (first.image_width(), first.image_height());
+        if let Some((file, ser_file)) = input_files
+            .iter()
+            .zip(ser_files.iter())
+            .find(|(_, s)| (s.image_width(), s.image_height()) != size)
+        {
+            return Err(Error::msg(format!(
+                "{} is {}x{}, other calibration files are {}x{}",
+                file,
+                ser_file.image_width(),
+                ser_file.image_height(),
+                size.0,
+                size.1
+            )));
+        }
+    }
+
+    // Frames of all files, identified by their file's index and their index in the file
+    let all_frames: Vec<(usize, usize)> = ser_files
+        .iter()
+        .enumerate()
+        .flat_map(|(file, s)| (0..s.frame_count()).map(move |frame_id| (file, frame_id)))
+        .collect();
+    let frames: Vec<(usize, usize)> = select_frames(all_frames.len(), max_frames)
+        .into_iter()
+        .map(|i| all_frames[i])
+        .collect();
+
+    if frames.is_empty() {
+        return Err(Error::msg("Calibration files contain no frames"));
+    }
+
+    info!(
+        "Computing master from {} of {} frames in {}",
+        frames.len(),
+        all_frames.len(),
+        input_files.join(", ")
+    );
+
+    let image = match method {
+        MasterMethod::Mean => compute_mean(&ser_files, &frames, on_frame_read)?,
+        MasterMethod::Median => compute_stacked(&ser_files, &frames, on_frame_read, |values| {
+            values[values.len() / 2]
+        })?,
+        MasterMethod::SigmaClippedMean => {
+            compute_stacked(&ser_files, &frames, on_frame_read, sigma_clipped_mean)?
+        }
+    };
+
+    Ok(CalibrationImage { image: Some(image) })
+}
+
+/// Computes the master dark of the dark input, for detecting sensor defects in it
+pub fn compute_master_dark(app_state: &ApplicationState) -> Result<Image> {
+    let dark_inputs = app_state.dark_inputs();
+    if dark_inputs.is_empty() {
+        return Err(Error::msg("Dark input not set"));
+    }
+
+    set_task_status(&t!("tasks.processing_master_dark"), 0, 0);
+    compute_master(
+        &dark_inputs,
+        app_state.dark_method,
+        app_state.calibration_max_frames,
+        |n, total| set_task_status(&t!("tasks.processing_master_dark"), total, n),
+    )?
+    .image
+    .ok_or_else(|| Error::msg("Failed to compute master dark"))
+}
+
+fn read_frame<C>(
+    ser_files: &[FrameSource],
+    (file, frame_id): (usize, usize),
+    num_read: usize,
+    total: usize,
+    on_frame_read: &C,
+) -> Result<Image>
+where
+    C: Fn(usize, usize),
+{
+    check_cancel_status()?;
+    let frame = ser_files[file].get_frame(frame_id)?.buffer;
+    on_frame_read(num_read + 1, total);
+    Ok(frame)
+}
+
+fn compute_mean<C>(
+    ser_files: &[FrameSource],
+    frames: &[(usize, usize)],
+    on_frame_read: C,
+) -> Result<Image>
+where
+    C: Fn(usize, usize),
+{
+    // Dimensions of the first frame read, which the sums are sized to
+    let mut dims: Option<(usize, usize, usize)> = None;
+    let mut sums: Vec<f64> = vec![];
+
+    for (i, frame) in frames.iter().enumerate() {
+        let frame = read_frame(ser_files, *frame, i, frames.len(), &on_frame_read)?;
+        let (width, height, num_bands) =
+            *dims.get_or_insert((frame.width, frame.height, frame.num_bands()));
+        if sums.is_empty() {
+            sums = vec![0.0; width * height * num_bands];
+        }
+        iproduct!(0..num_bands, 0..height, 0..width).for_each(|(b, y, x)| {
+            sums[(b * height + y) * width + x] += frame.get_band(b).get(x, y) as f64;
+        });
+    }
+
+    let (width, height, num_bands) =
+        dims.ok_or_else(|| Error::msg("Calibration files contain no frames"))?;
+    let mut master = Image::new_with_bands(width, height, num_bands, ImageMode::U16BIT)?;
+    iproduct!(0..num_bands, 0..height, 0..width).for_each(|(b, y, x)| {
+        let mean = sums[(b * height + y) * width + x] / frames.len() as f64;
+        master.put(x, y, mean as f32, b);
+    });
+    Ok(master)
+}
+
+/// Mean of the sorted `values` left after rejecting those more than `CLIP_KAPPA` standard
+/// deviations from their median
+fn sigma_clipped_mean(values: &[f32]) -> f32 {
+    let mut kept: &[f32] = values;
+    for _ in 0..CLIP_ITERATIONS {
+        let median = kept[kept.len() / 2];
+        let mean = kept.iter().sum::<f32>() / kept.len() as f32;
+        let sigma =
+            (kept.iter().map(|v| (v - mean).powi(2)).sum::<f32>() / kept.len() as f32).sqrt();
+        let (low, high) = (median - CLIP_KAPPA * sigma, median + CLIP_KAPPA * sigma);
+        // Sorted, so the values kept are a contiguous run
+        let first = kept.partition_point(|v| *v < low);
+        let last = kept.partition_point(|v| *v <= high);
+        if last - first == kept.len() || last <= first {
+            break;
+        }
+        kept = &kept[first..last];
+    }
+    kept.iter().sum::<f32>() / kept.len() as f32
+}
+
+/// Combines the frames pixel by pixel with `combine`, which is given the sorted values of the
+/// pixel in every frame. Frames which together don't fit in `STACK_MEMORY_BUDGET` are combined in
+/// horizontal strips, reading every frame once per strip.
+fn compute_stacked<C, S>(
+    ser_files: &[FrameSource],
+    frames: &[(usize, usize)],
+    on_frame_read: C,
+    combine: S,
+) -> Result<Image>
+where
+    C: Fn(usize, usize),
+    S: Fn(&[f32]) -> f32,
+{
+    let (first_file, _) = frames[0];
+    let width = ser_files[first_file].image_width();
+    let height = ser_files[first_file].image_height();
+
+    // Sized for three bands, as the band count isn't known until a frame is read
+    let frame_row_bytes = frames.len() * width * 3 * std::mem::size_of::<f32>();
+    let strip_rows = (STACK_MEMORY_BUDGET / frame_row_bytes.max(1)).clamp(1, height.max(1));
+    let total = frames.len() * height.div_ceil(strip_rows);
+
+    let mut master: Option<Image> = None;
+    let mut values: Vec<f32> = Vec::with_capacity(frames.len());
+    for (strip, y0) in (0..height).step_by(strip_rows).enumerate() {
+        let rows = strip_rows.min(height - y0);
+        let mut num_bands = 0;
+        let strip_values: Vec<Vec<f32>> = frames
+            .iter()
+            .enumerate()
+            .map(|(i, frame)| {
+                let num_read = strip * frames.len() + i;
+                let image = read_frame(ser_files, *frame, num_read, total, &on_frame_read)?;
+                num_bands = image.num_bands();
+                Ok(iproduct!(0..num_bands, y0..y0 + rows, 0..width)
+                    .map(|(b, y, x)| image.get_band(b).get(x, y))
+                    .collect())
+            })
+            .collect::<Result<Vec<Vec<f32>>>>()?;
+
+        if master.is_none() {
+            master = Some(Image::new_with_bands(
+                width,
+                height,
+                num_bands,
+                ImageMode::U16BIT,
+            )?);
+        }
+        if let Some(master) = master.as_mut() {
+            iproduct!(0..num_bands, 0..rows, 0..width).for_each(|(b, y, x)| {
+                let i = (b * rows + y) * width + x;
+                values.clear();
+                strip_values.iter().for_each(|f| values.push(f[i]));
+                values.sort_by(f32::total_cmp);
+                master.put(x, y0 + y, combine(&values), b);
+            });
+        }
+    }
+    master.ok_or_else(|| Error::msg("Calibration frames are empty"))
+}
@@ -0,0 +1,77 @@
+//! Caption bars summarizing how an image was taken and processed, added beneath exported
+//! images. Text is laid out and rasterized with the fonts built into the user interface.
+
+use epaint::text::{FontDefinitions, FontId, Fonts};
+use epaint::Color32;
+use sciimg::prelude::Image;
+
+/// Used when a caption is enabled before a template has been set
+pub const DEFAULT_TEMPLATE: &str =
+    "{target}   {date} {time} UTC   {instrument}   {frames} frames, {algorithm}, drizzle {drizzle}x";
+
+/// Largest glyph atlas the fonts are rasterized into, in pixels per side
+const MAX_ATLAS_SIDE: usize = 8192;
+
+/// Text height for an image `width` pixels wide, so the caption reads the same at any size
+fn font_size(width: usize) -> f32 {
+    (width as f32 / 80.0).clamp(10.0, 64.0)
+}
+
+/// `image` with a black bar beneath it showing `text` at the image's brightest level. Lines are
+/// separated by newlines and are cut off at the image's edge.
+pub fn add_caption(image: &Image, text: &str) -> Image {
+    let size = font_size(image.width);
+    let fonts = Fonts::new(1.0, MAX_ATLAS_SIDE, FontDefinitions::default());
+    let galley = fonts.layout_no_wrap(text.to_owned(), FontId::proportional(size), Color32::WHITE);
+    let atlas = fonts.image();
+
+    let padding = (size * 0.6).round() as usize;
+    let bar_height = galley.size().y.ceil() as usize + padding * 2;
+    let (_, max) = image.get_min_max_all_channel();
+    let mut captioned = Image::new_with_bands(
+        image.width,
+        image.height + bar_height,
+        image.num_bands(),
+        image.get_mode(),
+    )
+    .expect("Failed to allocate captioned image");
+    (0..image.num_bands()).for_each(|b| {
+        let band = image.get_band(b);
+        (0..image.height).for_each(|y| {
+            (0..image.width).for_each(|x| captioned.put(x, y, band.get(x, y), b));
+        });
+    });
+
+    galley
+        .rows
+        .iter()
+        .flat_map(|row| row.glyphs.iter())
+        .filter(|glyph| !glyph.uv_rect.is_nothing())
+        .for_each(|glyph| {
+            let uv = glyph.uv_rect;
+            let left = (glyph.pos.x + uv.offset.x).round() as i64 + padding as i64;
+            let top = (glyph.pos.y + uv.offset.y).round() as i64 + (image.height + padding) as i64;
+            let [u0, v0] = [uv.min[0] as usize, uv.min[1] as usize];
+            let [u1, v1] = [uv.max[0] as usize, uv.max[1] as usize];
+            (v0..v1).for_each(|v| {
+                (u0..u1).for_each(|u| {
+                    let coverage = atlas.pixels[v * atlas.size[0] + u];
+                    let (x, y) = (left + (u - u0) as i64, top + (v - v0) as i64);
+                    if coverage > 0.0
+                        && x >= 0
+                        && y >= 0
+                        && (x as usize) < captioned.width
+                        && (y as usize) < captioned.height
+                    {
+                        let (x, y) = (x as usize, y as usize);
+                        (0..captioned.num_bands()).for_each(|b| {
+                            // Neighbouring glyphs may overlap
+                            let level = captioned.get_band(b).get(x, y).max(coverage * max);
+                            captioned.put(x, y, level, b)
+                        });
+                    }
+                });
+            });
+        });
+    captioned
+}
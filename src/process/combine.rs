@@ -0,0 +1,368 @@
+//! Stacking methods solhat doesn't provide: the kappa-sigma clipped mean, the maximum, means
+//! weighted by frame quality and drizzle at custom scales and drop sizes. Frames are aligned,
+//! derotated and resampled onto the output as in drizzle stacking, or drizzled onto it as drops,
+//! and combined pixel by pixel without holding every frame in memory. Sigma clipping reads the
+//! frames once more for each rejection pass. Saturated pixels are left out if set, judged on the
+//! raw frames as in the saturation excluding average.
+
+use anyhow::{Error, Result};
+use itertools::iproduct;
+use rayon::prelude::*;
+use sciimg::prelude::{Image, ImageMode};
+use solhat::context::ProcessContext;
+use solhat::datasource::DataSource;
+use solhat::framerecord::FrameRecord;
+
+use crate::cancel::*;
+use crate::process::contribution::{sample_band, StackGeometry};
+use crate::process::error::ProcessError;
+use crate::process::framesource::FrameSource;
+use crate::process::saturation;
+use crate::state::{ApplicationState, FrameOrientation, StackMethod};
+use crate::taskstatus::*;
+
+/// Running statistics of one band of the output, pixel by pixel. Sums are weighted.
+#[derive(Clone)]
+struct BandStatistics {
+    sums: Vec<f64>,
+    squares: Vec<f64>,
+    weights: Vec<f64>,
+    counts: Vec<u32>,
+    maximums: Vec<f32>,
+    /// Unweighted sums and coverage of every value, saturated, rejected or not, for pixels left
+    /// with no accepted values or no weight
+    all_sums: Vec<f64>,
+    all_coverage: Vec<f64>,
+}
+
+impl BandStatistics {
+    fn new(len: usize) -> Self {
+        BandStatistics {
+            sums: vec![0.0; len],
+            squares: vec![0.0; len],
+            weights: vec![0.0; len],
+            counts: vec![0; len],
+            maximums: vec![f32::MIN; len],
+            all_sums: vec![0.0; len],
+            all_coverage: vec![0.0; len],
+        }
+    }
+
+    fn add_unfiltered(&mut self, i: usize, value: f32, coverage: f64) {
+        self.all_sums[i] += coverage * value as f64;
+        self.all_coverage[i] += coverage;
+    }
+
+    fn add(&mut self, i: usize, value: f32, weight: f64) {
+        self.sums[i] += weight * value as f64;
+        self.squares[i] += weight * (value as f64).powi(2);
+        self.weights[i] += weight;
+        self.counts[i] += 1;
+        self.maximums[i] = self.maximums[i].max(value);
+    }
+
+    /// Weighted mean and standard deviation of the values added at pixel `i`
+    fn mean_sigma(&self, i: usize) -> Option<(f64, f64)> {
+        if self.counts[i] == 0 || self.weights[i] <= 0.0 {
+            return None;
+        }
+        let mean = self.sums[i] / self.weights[i];
+        let variance = (self.squares[i] / self.weights[i] - mean * mean).max(0.0);
+        Some((mean, variance.sqrt()))
+    }
+
+    /// Unweighted mean of every value at pixel `i`, including those left out
+    fn unfiltered_mean(&self, i: usize) -> Option<f64> {
+        (self.all_coverage[i] > 0.0).then(|| self.all_sums[i] / self.all_coverage[i])
+    }
+}
+
+/// Sums of drops drizzled onto the output and the coverage of each output pixel
+struct Drops {
+    sums: Vec<f32>,
+    coverage: Vec<f32>,
+}
+
+impl Drops {
+    fn new(len: usize) -> Self {
+        Drops {
+            sums: vec![0.0; len],
+            coverage: vec![0.0; len],
+        }
+    }
+}
+
+/// Band `band` of frame `fr` drizzled onto the output. Each pixel is shrunk to a drop `pixfrac` of
+/// its size and added to the output pixels it overlaps, in proportion to the overlap. Drops are
+/// kept square to the output rather than rotated with the frame. Returns the drops of pixels not
+/// saturated in the `raw` frame, if given with its saturation level, and all the drops.
+fn drizzle_band(
+    frame: &Image,
+    raw: Option<(&Image, f32)>,
+    band: usize,
+    fr: &FrameRecord,
+    geometry: &StackGeometry,
+    pixfrac: f32,
+) -> (Drops, Drops) {
+    let [width, height] = geometry.output_size;
+    let mut unsaturated = Drops::new(width * height);
+    let mut all = Drops::new(width * height);
+    let half = pixfrac * geometry.scale / 2.0;
+    let values = frame.get_band(band);
+    iproduct!(0..frame.height, 0..frame.width).for_each(|(y, x)| {
+        let (out_x, out_y) = geometry.output_position(fr, x as f32, y as f32);
+        let (left, right) = (out_x - half, out_x + half);
+        let (top, bottom) = (out_y - half, out_y + half);
+        // Output pixels are centered on their coordinates
+        if right < -0.5 || bottom < -0.5 || left > width as f32 - 0.5 || top > height as f32 - 0.5 {
+            return;
+        }
+        let value = values.get(x, y);
+        let saturated = raw.is_some_and(|(raw, level)| raw.get_band(band).get(x, y) >= level);
+        let columns = (left + 0.5).floor().max(0.0) as usize
+            ..=(right + 0.5).floor().min(width as f32 - 1.0) as usize;
+        let rows = (top + 0.5).floor().max(0.0) as usize
+            ..=(bottom + 0.5).floor().min(height as f32 - 1.0) as usize;
+        iproduct!(rows, columns).for_each(|(py, px)| {
+            let overlap_x = (right.min(px as f32 + 0.5) - left.max(px as f32 - 0.5)).max(0.0);
+            let overlap_y = (bottom.min(py as f32 + 0.5) - top.max(py as f32 - 0.5)).max(0.0);
+            let overlap = overlap_x * overlap_y;
+            if overlap > 0.0 {
+                let i = py * width + px;
+                all.sums[i] += value * overlap;
+                all.coverage[i] += overlap;
+                if !saturated {
+                    unsaturated.sums[i] += value * overlap;
+                    unsaturated.coverage[i] += overlap;
+                }
+            }
+        });
+    });
+    (unsaturated, all)
+}
+
+/// How frames are put onto the output
+#[derive(Clone, Copy)]
+struct Sampling<'a> {
+    /// Drop size to drizzle frames with, rather than resampling them
+    pixfrac: Option<f32>,
+    /// Raw frames and the level at which their pixels are saturated, to leave saturated pixels out
+    saturation: Option<(&'a FrameSource, f32)>,
+}
+
+/// Reads every stacked frame, puts it onto the output with `sampling` and adds each value
+/// `accept` passes to the statistics of its band, with the weight of its frame. `progress` is the
+/// pass and the number of passes, for the task status.
+fn accumulate<F, A>(
+    context: &ProcessContext<F>,
+    geometry: &StackGeometry,
+    num_bands: usize,
+    weights: &[f64],
+    sampling: Sampling,
+    progress: (usize, usize),
+    accept: A,
+) -> Result<Vec<BandStatistics>>
+where
+    F: DataSource + Send + Sync + 'static,
+    A: Fn(usize, usize, f32) -> bool + Sync,
+{
+    let [width, height] = geometry.output_size;
+    let (pass, passes) = progress;
+    let frame_count = context.frame_records.len();
+    let total = frame_count * passes;
+
+    let mut statistics = vec![BandStatistics::new(width * height); num_bands];
+    for (n, (fr, weight)) in context.frame_records.iter().zip(weights).enumerate() {
+        check_cancel_status()?;
+        wait_while_paused();
+        let frame = fr.get_frame(context)?.buffer;
+        let raw = match sampling.saturation {
+            Some((raw_source, level)) => Some((raw_source.get_frame(fr.frame_id)?.buffer, level)),
+            None => None,
+        };
+        let raw = raw.as_ref().map(|(raw, level)| (raw, *level));
+        statistics
+            .par_iter_mut()
+            .enumerate()
+            .for_each(|(b, band)| match sampling.pixfrac {
+                Some(pixfrac) => {
+                    let (unsaturated, all) = drizzle_band(&frame, raw, b, fr, geometry, pixfrac);
+                    (0..width * height).for_each(|i| {
+                        if all.coverage[i] > 0.0 {
+                            band.add_unfiltered(
+                                i,
+                                all.sums[i] / all.coverage[i],
+                                all.coverage[i] as f64,
+                            );
+                        }
+                        if unsaturated.coverage[i] > 0.0 {
+                            let v = unsaturated.sums[i] / unsaturated.coverage[i];
+                            if accept(b, i, v) {
+                                band.add(i, v, *weight * unsaturated.coverage[i] as f64);
+                            }
+                        }
+                    });
+                }
+                None => {
+                    iproduct!(0..height, 0..width).for_each(|(y, x)| {
+                        let (src_x, src_y) = geometry.frame_position(fr, x as f32, y as f32);
+                        if let Some(v) = sample_band(&frame, b, src_x, src_y) {
+                            let i = y * width + x;
+                            band.add_unfiltered(i, v, 1.0);
+                            let saturated = raw.is_some_and(|(raw, level)| {
+                                saturation::saturated_near(raw, b, src_x, src_y, level)
+                            });
+                            if !saturated && accept(b, i, v) {
+                                band.add(i, v, *weight);
+                            }
+                        }
+                    });
+                }
+            });
+        set_task_status(&t!("tasks.stacking"), total, pass * frame_count + n + 1);
+    }
+    Ok(statistics)
+}
+
+/// Weight of each frame of `context`: its quality relative to the best frame, raised to
+/// `app_state.quality_weight_power`, if frames are weighted by quality, or else one
+fn frame_weights<F>(context: &ProcessContext<F>, app_state: &ApplicationState) -> Vec<f64>
+where
+    F: DataSource + Send + Sync + 'static,
+{
+    let best = context
+        .frame_records
+        .iter()
+        .map(|fr| fr.sigma)
+        .fold(0.0, f64::max);
+    context
+        .frame_records
+        .iter()
+        .map(|fr| {
+            if app_state.quality_weighted() && best > 0.0 {
+                (fr.sigma.max(0.0) / best).powf(app_state.quality_weight_power)
+            } else {
+                1.0
+            }
+        })
+        .collect()
+}
+
+/// Stacks the frames of `context` with the clipped mean or maximum of `app_state.algorithm`, or
+/// with the mean weighted by frame quality. Frames are drizzled as drops if a custom drizzle is
+/// set. Pixels left with no values after saturation exclusion and clipping, or with no weight,
+/// take the unweighted mean of all their values rather than being left black.
+pub fn stack_combined<F>(context: &ProcessContext<F>, app_state: &ApplicationState) -> Result<Image>
+where
+    F: DataSource + Send + Sync + 'static,
+{
+    // The stack is reoriented once combined, like the others
+    let geometry = StackGeometry {
+        orientation: FrameOrientation::Normal,
+        ..StackGeometry::new(app_state, &context.frame_records)
+            .ok_or_else(|| Error::msg("Zero frames to stack. Cannot continue"))?
+    };
+    let [width, height] = geometry.output_size;
+    let num_bands = context.frame_records[0]
+        .get_frame(context)?
+        .buffer
+        .num_bands();
+
+    let clipping = app_state.algorithm == StackMethod::SigmaClipped;
+    let passes = if clipping {
+        app_state.clip_iterations + 1
+    } else {
+        1
+    };
+    let kappa = app_state.clip_kappa;
+    let weights = frame_weights(context, app_state);
+    let raw_source = if app_state.saturation_exclusion {
+        let light = app_state
+            .light
+            .as_ref()
+            .ok_or(ProcessError::MissingInput("light"))?;
+        Some(FrameSource::open(&[light.to_owned()])?)
+    } else {
+        None
+    };
+    let sampling = Sampling {
+        pixfrac: app_state
+            .custom_drizzled()
+            .then_some(app_state.drizzle_pixfrac),
+        saturation: raw_source.as_ref().map(|raw_source| {
+            (
+                raw_source,
+                saturation::saturation_level(raw_source.pixel_depth(), app_state),
+            )
+        }),
+    };
+    set_task_status(
+        &t!("tasks.stacking"),
+        context.frame_records.len() * passes,
+        0,
+    );
+
+    let mut statistics = accumulate(
+        context,
+        &geometry,
+        num_bands,
+        &weights,
+        sampling,
+        (0, passes),
+        |_, _, _| true,
+    )?;
+    for pass in 1..passes {
+        info!("Sigma clipping pass {} of {}", pass, passes - 1);
+        let limits: Vec<Vec<Option<(f64, f64)>>> = statistics
+            .iter()
+            .map(|band| (0..width * height).map(|i| band.mean_sigma(i)).collect())
+            .collect();
+        let clipped = accumulate(
+            context,
+            &geometry,
+            num_bands,
+            &weights,
+            sampling,
+            (pass, passes),
+            |b, i, v| {
+                limits[b][i]
+                    .map(|(mean, sigma)| (v as f64 - mean).abs() <= kappa * sigma)
+                    .unwrap_or(false)
+            },
+        )?;
+        // Pixels where every value was rejected keep the previous pass's statistics
+        statistics = clipped
+            .into_iter()
+            .zip(statistics)
+            .map(|(clipped, previous)| {
+                let mut band = clipped;
+                (0..width * height).for_each(|i| {
+                    if band.counts[i] == 0 {
+                        band.sums[i] = previous.sums[i];
+                        band.squares[i] = previous.squares[i];
+                        band.weights[i] = previous.weights[i];
+                        band.counts[i] = previous.counts[i];
+                    }
+                });
+                band
+            })
+            .collect();
+    }
+
+    let mut image = Image::new_with_bands(width, height, num_bands, ImageMode::U16BIT)?;
+    statistics.iter().enumerate().for_each(|(b, band)| {
+        iproduct!(0..height, 0..width).for_each(|(y, x)| {
+            let i = y * width + x;
+            let value = if app_state.algorithm == StackMethod::Maximum {
+                (band.counts[i] > 0).then_some(band.maximums[i])
+            } else {
+                band.mean_sigma(i).map(|(mean, _)| mean as f32)
+            };
+            let value = value.or_else(|| band.unfiltered_mean(i).map(|mean| mean as f32));
+            if let Some(value) = value {
+                image.put(x, y, value, b);
+            }
+        });
+    });
+    Ok(image)
+}
@@ -0,0 +1,120 @@
+//! Comparison of stacking algorithms on the same frames. The frames are analyzed, limited and
+//! rotated once, then stacked with each algorithm in turn, so the differences between the results
+//! come from the algorithm alone.
+
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use sciimg::prelude::{Image, ImageBuffer};
+
+use crate::analysis::sigma::{frame_quality, AnalysisOptions};
+use crate::cancel::*;
+use crate::imageutil;
+use crate::process::error::{Categorize, ProcessError};
+use crate::process::{build_masters, normalize, prepare_context, stack_frames};
+use crate::state::{ApplicationState, StackMethod};
+
+/// Full scale of the normalized stacks
+const FULL_SCALE: f64 = 65535.0;
+
+/// A stack made with one algorithm, with measures of its quality
+pub struct AlgorithmResult {
+    pub algorithm: StackMethod,
+    /// Normalized to the 16 bit range
+    pub image: Image,
+    /// Quality estimated as in frame analysis, at the same sample points. Higher is sharper.
+    pub sharpness: f64,
+    /// Pixel to pixel noise, as a percentage of full scale
+    pub noise: f64,
+    /// Standard deviation of the levels over their mean, as a percentage
+    pub contrast: f64,
+    pub duration: Duration,
+}
+
+pub struct ComparisonReport {
+    pub frames_stacked: usize,
+    pub results: Vec<AlgorithmResult>,
+}
+
+/// Noise estimated from the difference between each pixel and the mean of its four neighbours,
+/// which removes nearly all detail but the finest
+fn noise_level(buffer: &ImageBuffer) -> f64 {
+    if buffer.width < 3 || buffer.height < 3 {
+        return 0.0;
+    }
+    let mut residuals: Vec<f64> = (1..buffer.height - 1)
+        .flat_map(|y| (1..buffer.width - 1).map(move |x| (x, y)))
+        .map(|(x, y)| {
+            let neighbours = buffer.get(x - 1, y)
+                + buffer.get(x + 1, y)
+                + buffer.get(x, y - 1)
+                + buffer.get(x, y + 1);
+            (buffer.get(x, y) - neighbours / 4.0).abs() as f64
+        })
+        .collect();
+    let median = imageutil::median(&mut residuals);
+    // The residual of Gaussian noise has 1.25 times its variance
+    median * imageutil::MAD_TO_SIGMA / 1.25_f64.sqrt() / FULL_SCALE * 100.0
+}
+
+/// RMS contrast of `buffer`, as a percentage
+fn contrast(buffer: &ImageBuffer) -> f64 {
+    let count = (buffer.width * buffer.height) as f64;
+    let pixels =
+        || (0..buffer.height).flat_map(move |y| (0..buffer.width).map(move |x| buffer.get(x, y)));
+    let mean = pixels().map(|v| v as f64).sum::<f64>() / count;
+    if mean <= 0.0 {
+        return 0.0;
+    }
+    let variance = pixels().map(|v| (v as f64 - mean).powi(2)).sum::<f64>() / count;
+    variance.sqrt() / mean * 100.0
+}
+
+/// Stacks up to `max_frames` frames of the light file with each of `algorithms` and the other
+/// options of `app_state`. Nothing is written to disk.
+pub async fn compare_async(
+    app_state: ApplicationState,
+    algorithms: Vec<StackMethod>,
+    max_frames: usize,
+) -> Result<ComparisonReport> {
+    let mut state = app_state;
+    state.max_frames = state.max_frames.min(max_frames).max(1);
+    let (mut context, _) = prepare_context(&state, &build_masters(&state)?)?;
+    if context.frame_records.is_empty() {
+        return Err(ProcessError::NoFramesAfterLimiting.into());
+    }
+    let options = AnalysisOptions::from_state(&state);
+
+    let mut results = vec![];
+    for algorithm in algorithms {
+        check_cancel_status()?;
+        info!("Comparing {:?} stacking", algorithm);
+        state.algorithm = algorithm;
+        context.parameters.algorithm = state.to_parameters().algorithm;
+        let start = Instant::now();
+        let mut image = stack_frames(&context, &state).categorize(ProcessError::StackingFailed)?;
+        let duration = start.elapsed();
+        imageutil::replace_invalid_pixels(&mut image);
+        normalize(&mut image, &state);
+
+        let (_, sharpness) = frame_quality(
+            &image,
+            context.parameters.obj_detection_threshold as f32,
+            &options,
+        );
+        let luminance = imageutil::luminance_buffer(&image);
+        results.push(AlgorithmResult {
+            algorithm,
+            sharpness,
+            noise: noise_level(&luminance),
+            contrast: contrast(&luminance),
+            image,
+            duration,
+        });
+    }
+
+    Ok(ComparisonReport {
+        frames_stacked: context.frame_records.len(),
+        results,
+    })
+}
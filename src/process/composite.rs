@@ -0,0 +1,78 @@
+//! Composites of a surface stack with a second stack of the same target exposed for prominences.
+//! The longer prominence exposure overexposes the disk, so the surface stack is kept within the
+//! limb and the prominence stack outside it, blended across a band around the limb to hide the
+//! seam.
+
+use anyhow::{anyhow, Result};
+use sciimg::prelude::Image;
+
+use crate::analysis::disk;
+
+/// Weight of the prominence stack at `distance` from the disk's center, rising linearly from zero
+/// to one across `blend_radius` either side of the limb
+fn prominence_weight(distance: f32, limb: f32, blend_radius: f32) -> f32 {
+    if blend_radius <= 0.0 {
+        return if distance > limb { 1.0 } else { 0.0 };
+    }
+    ((distance - limb) / (blend_radius * 2.0) + 0.5).clamp(0.0, 1.0)
+}
+
+/// Blends `prominences` into `surface` outside the limb of the disk. Both stacks must be the same
+/// size and normalized to the same range. The disk is fitted on the surface stack, falling back to
+/// a disk of `fallback_radius` pixels at the image's center if none is found.
+pub fn composite_prominences(
+    surface: &Image,
+    prominences: &Image,
+    blend_radius: f32,
+    fallback_radius: usize,
+) -> Result<Image> {
+    if surface.width != prominences.width
+        || surface.height != prominences.height
+        || surface.num_bands() != prominences.num_bands()
+    {
+        return Err(anyhow!(
+            "The prominence stack is {}x{} with {} bands, but the surface stack is {}x{} with {}",
+            prominences.width,
+            prominences.height,
+            prominences.num_bands(),
+            surface.width,
+            surface.height,
+            surface.num_bands()
+        ));
+    }
+
+    let disk = disk::find_disk(surface).unwrap_or_else(|| {
+        warn!(
+            "No disk found in the surface stack, using a radius of {} pixels at the center",
+            fallback_radius
+        );
+        disk::Disk {
+            x: (surface.width / 2) as f32,
+            y: (surface.height / 2) as f32,
+            radius: fallback_radius as f32,
+        }
+    });
+    info!(
+        "Compositing prominences outside the limb at ({:.1}, {:.1}), radius {:.1}",
+        disk.x, disk.y, disk.radius
+    );
+
+    let mut composite = surface.clone();
+    (0..surface.num_bands()).for_each(|b| {
+        let surface_band = surface.get_band(b);
+        let prominence_band = prominences.get_band(b);
+        (0..surface.height).for_each(|y| {
+            (0..surface.width).for_each(|x| {
+                let (dx, dy) = (x as f32 - disk.x, y as f32 - disk.y);
+                let weight =
+                    prominence_weight((dx * dx + dy * dy).sqrt(), disk.radius, blend_radius);
+                if weight > 0.0 {
+                    let value = surface_band.get(x, y) * (1.0 - weight)
+                        + prominence_band.get(x, y) * weight;
+                    composite.put(x, y, value, b);
+                }
+            });
+        });
+    });
+    Ok(composite)
+}
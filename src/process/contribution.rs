@@ -0,0 +1,231 @@
+use anyhow::{Error, Result};
+use itertools::iproduct;
+use sciimg::prelude::{Image, ImageMode};
+use solhat::datasource::DataSource;
+use solhat::framerecord::FrameRecord;
+
+use crate::cancel::*;
+use crate::process::framesource::FrameSource;
+use crate::state::{ApplicationState, FrameOrientation};
+use crate::taskstatus::*;
+
+/// Half the width, in output pixels, of the aligned patch shown for each frame
+const PATCH_RADIUS: usize = 32;
+
+/// Frames listed for an inspected region
+const MAX_CONTRIBUTIONS: usize = 12;
+
+/// How the stacked frames map onto the output, kept with the results so any region of the stack
+/// can be traced back to the frames that went into it
+#[derive(Debug, Clone)]
+pub struct StackGeometry {
+    pub light: String,
+    pub frame_records: Vec<FrameRecord>,
+    pub output_size: [usize; 2],
+    pub scale: f32,
+    /// User horizontal and vertical offsets, as applied to the frames as captured
+    pub offset: [f32; 2],
+    pub orientation: FrameOrientation,
+}
+
+impl StackGeometry {
+    pub fn new(app_state: &ApplicationState, frame_records: &[FrameRecord]) -> Option<Self> {
+        let first = frame_records.first()?;
+        // Offsets as applied to the frames as captured, before reorientation
+        let parameters = app_state.to_parameters();
+        Some(StackGeometry {
+            light: app_state.light.clone()?,
+            frame_records: frame_records.to_vec(),
+            output_size: app_state.output_dimensions([first.frame_width, first.frame_height]),
+            scale: app_state.drizzle_factor(),
+            offset: [
+                parameters.horiz_offset as f32,
+                parameters.vert_offset as f32,
+            ],
+            orientation: app_state.frame_orientation,
+        })
+    }
+
+    /// The same mapping onto an output the size of the frames, as if stacked without drizzle
+    pub fn at_frame_scale(&self) -> Self {
+        let [width, height] = self.output_size;
+        StackGeometry {
+            output_size: [
+                (width as f32 / self.scale).round() as usize,
+                (height as f32 / self.scale).round() as usize,
+            ],
+            scale: 1.0,
+            ..self.clone()
+        }
+    }
+
+    /// Position in the stack, before it was reoriented, of pixel (`x`, `y`) of the output
+    fn unoriented(&self, x: f32, y: f32) -> (f32, f32) {
+        let [width, height] = self.output_size;
+        let x = if self.orientation.flips_horizontal() {
+            width as f32 - 1.0 - x
+        } else {
+            x
+        };
+        let y = if self.orientation.flips_vertical() {
+            height as f32 - 1.0 - y
+        } else {
+            y
+        };
+        (x, y)
+    }
+
+    /// Position in frame `fr` of (`x`, `y`) in the output. Frames are aligned on their center of
+    /// mass and derotated as in stacking.
+    pub fn frame_position(&self, fr: &FrameRecord, x: f32, y: f32) -> (f32, f32) {
+        let (x, y) = self.unoriented(x, y);
+        let [width, height] = self.output_size;
+        let center_x = (fr.frame_width / 2) as f32 + fr.offset.h + self.offset[0];
+        let center_y = (fr.frame_height / 2) as f32 + fr.offset.v + self.offset[1];
+        let (sin, cos) = (fr.computed_rotation as f32).sin_cos();
+        let dx = (x - width as f32 / 2.0) / self.scale;
+        let dy = (y - height as f32 / 2.0) / self.scale;
+        (
+            center_x + dx * cos - dy * sin,
+            center_y + dx * sin + dy * cos,
+        )
+    }
+
+    /// Position in the output of (`x`, `y`) in frame `fr`, the inverse of `frame_position`
+    pub fn output_position(&self, fr: &FrameRecord, x: f32, y: f32) -> (f32, f32) {
+        let [width, height] = self.output_size;
+        let center_x = (fr.frame_width / 2) as f32 + fr.offset.h + self.offset[0];
+        let center_y = (fr.frame_height / 2) as f32 + fr.offset.v + self.offset[1];
+        let (sin, cos) = (fr.computed_rotation as f32).sin_cos();
+        let (fx, fy) = (x - center_x, y - center_y);
+        let dx = fx * cos + fy * sin;
+        let dy = -fx * sin + fy * cos;
+        // Reorientation flips are their own inverse
+        self.unoriented(
+            dx * self.scale + width as f32 / 2.0,
+            dy * self.scale + height as f32 / 2.0,
+        )
+    }
+}
+
+/// Bilinear sample of band `band` of `frame` at (`x`, `y`), or None outside it
+pub fn sample_band(frame: &Image, band: usize, x: f32, y: f32) -> Option<f32> {
+    if x < 0.0 || y < 0.0 {
+        return None;
+    }
+    let (x0, y0) = (x.floor() as usize, y.floor() as usize);
+    if x0 + 1 >= frame.width || y0 + 1 >= frame.height {
+        return None;
+    }
+    let (fx, fy) = (x - x0 as f32, y - y0 as f32);
+    let band = frame.get_band(band);
+    let top = band.get(x0, y0) * (1.0 - fx) + band.get(x0 + 1, y0) * fx;
+    let bottom = band.get(x0, y0 + 1) * (1.0 - fx) + band.get(x0 + 1, y0 + 1) * fx;
+    Some(top * (1.0 - fy) + bottom * fy)
+}
+
+/// Bilinear sample of the mean of all bands of `frame` at (`x`, `y`), or None outside it
+fn sample(frame: &Image, x: f32, y: f32) -> Option<f32> {
+    let sum = (0..frame.num_bands())
+        .map(|b| sample_band(frame, b, x, y))
+        .sum::<Option<f32>>()?;
+    Some(sum / frame.num_bands() as f32)
+}
+
+/// How one frame contributed to an inspected region of the stack
+#[derive(Debug, Clone)]
+pub struct FrameContribution {
+    pub frame_id: usize,
+    pub sigma: f64,
+    /// Fraction of the region the frame covered
+    pub coverage: f32,
+    /// Mean level of the frame over the region
+    pub level: f32,
+    /// Difference of the frame's level from the mean of all covering frames. Divided by the
+    /// number of covering frames, it's how far the frame pulled an averaged stack.
+    pub deviation: f32,
+    /// The frame around the region, aligned as it was stacked, averaged to one band
+    pub patch: Image,
+}
+
+/// Frames stacked into an inspected region, those pulling the stack furthest from their
+/// consensus first
+#[derive(Debug, Clone)]
+pub struct ContributionReport {
+    pub center: [usize; 2],
+    pub radius: usize,
+    pub frames_covering: usize,
+    pub mean_level: f32,
+    pub contributions: Vec<FrameContribution>,
+}
+
+/// Reads each stacked frame, as captured, and measures its level over the region of radius
+/// `radius` around `center` in the output
+pub async fn inspect_async(
+    geometry: StackGeometry,
+    center: [usize; 2],
+    radius: usize,
+) -> Result<ContributionReport> {
+    let source = FrameSource::open(std::slice::from_ref(&geometry.light))?;
+    let [cx, cy] = [center[0] as f32, center[1] as f32];
+    let r = radius as i32;
+    let region: Vec<(f32, f32)> = iproduct!(-r..=r, -r..=r)
+        .filter(|(dy, dx)| dx * dx + dy * dy <= r * r)
+        .map(|(dy, dx)| (cx + dx as f32, cy + dy as f32))
+        .collect();
+
+    let frame_count = geometry.frame_records.len();
+    set_task_status(&t!("tasks.inspecting_contributions"), frame_count, 0);
+    let mut contributions = vec![];
+    for (n, fr) in geometry.frame_records.iter().enumerate() {
+        check_cancel_status()?;
+        let frame = source.get_frame(fr.frame_id)?.buffer;
+        let samples: Vec<f32> = region
+            .iter()
+            .filter_map(|(x, y)| {
+                let (fx, fy) = geometry.frame_position(fr, *x, *y);
+                sample(&frame, fx, fy)
+            })
+            .collect();
+        if !samples.is_empty() {
+            let mut patch =
+                Image::new_with_bands(PATCH_RADIUS * 2, PATCH_RADIUS * 2, 1, ImageMode::U16BIT)?;
+            iproduct!(0..PATCH_RADIUS * 2, 0..PATCH_RADIUS * 2).for_each(|(py, px)| {
+                let x = cx + px as f32 - PATCH_RADIUS as f32;
+                let y = cy + py as f32 - PATCH_RADIUS as f32;
+                let (fx, fy) = geometry.frame_position(fr, x, y);
+                if let Some(v) = sample(&frame, fx, fy) {
+                    patch.put(px, py, v, 0);
+                }
+            });
+            contributions.push(FrameContribution {
+                frame_id: fr.frame_id,
+                sigma: fr.sigma,
+                coverage: samples.len() as f32 / region.len() as f32,
+                level: samples.iter().sum::<f32>() / samples.len() as f32,
+                deviation: 0.0,
+                patch,
+            });
+        }
+        set_task_status(&t!("tasks.inspecting_contributions"), frame_count, n + 1);
+    }
+
+    if contributions.is_empty() {
+        return Err(Error::msg(t!("contribution.no_frames").to_string()));
+    }
+    let frames_covering = contributions.len();
+    let mean_level = contributions.iter().map(|c| c.level).sum::<f32>() / frames_covering as f32;
+    contributions
+        .iter_mut()
+        .for_each(|c| c.deviation = c.level - mean_level);
+    contributions.sort_by(|a, b| b.deviation.abs().total_cmp(&a.deviation.abs()));
+    contributions.truncate(MAX_CONTRIBUTIONS);
+
+    Ok(ContributionReport {
+        center,
+        radius,
+        frames_covering,
+        mean_level,
+        contributions,
+    })
+}
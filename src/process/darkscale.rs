@@ -0,0 +1,162 @@
+//! Scaling of the master dark for lights taken at a different exposure or temperature than the
+//! darks. The thermal signal of the dark, its level above the bias, is scaled by a factor set by
+//! the user or found by minimizing the fixed pattern noise left in the calibrated light frames.
+
+use anyhow::{Error, Result};
+use itertools::iproduct;
+use sciimg::prelude::Image;
+use solhat::calibrationframe::CalibrationImage;
+use solhat::datasource::DataSource;
+
+use crate::cancel::*;
+use crate::imageutil;
+use crate::process::framesource::FrameSource;
+use crate::taskstatus::*;
+
+/// Number of light frames averaged for the optimization. Averaging lowers the random noise so the
+/// fixed pattern the dark removes dominates.
+const OPTIMIZE_SAMPLES: usize = 16;
+
+/// Range of factors searched by the optimization
+const FACTOR_RANGE: (f64, f64) = (0.0, 3.0);
+
+/// Golden section search iterations, narrowing the range to within 0.001
+const SEARCH_ITERATIONS: usize = 17;
+
+/// Level of `dark` at a pixel above `bias`, if there is one of the same size
+fn thermal(dark: &Image, bias: Option<&Image>, x: usize, y: usize, b: usize) -> f32 {
+    dark.get_band(b).get(x, y) - bias.map(|bias| bias.get_band(b).get(x, y)).unwrap_or(0.0)
+}
+
+/// The bias, if it matches the size of the dark
+fn matching_bias<'a>(dark: &Image, bias: &'a CalibrationImage) -> Option<&'a Image> {
+    bias.image.as_ref().filter(|bias| {
+        bias.width == dark.width
+            && bias.height == dark.height
+            && bias.num_bands() == dark.num_bands()
+    })
+}
+
+/// Scales the thermal signal of `master_dark` by `factor`, leaving the bias level in place
+pub fn scale_master_dark(
+    master_dark: &mut CalibrationImage,
+    master_bias: &CalibrationImage,
+    factor: f64,
+) {
+    let Some(dark) = &master_dark.image else {
+        return;
+    };
+    let bias = matching_bias(dark, master_bias);
+    let mut scaled = dark.clone();
+    (0..dark.num_bands()).for_each(|b| {
+        iproduct!(0..dark.height, 0..dark.width).for_each(|(y, x)| {
+            let v =
+                dark.get_band(b).get(x, y) + thermal(dark, bias, x, y, b) * (factor as f32 - 1.0);
+            scaled.put(x, y, v, b);
+        });
+    });
+    master_dark.image = Some(scaled);
+}
+
+/// Pixel to pixel noise of `light` with `factor` times the thermal signal subtracted, from the
+/// difference between each pixel and the mean of its four neighbours
+fn residual_noise(light: &Image, dark: &Image, bias: Option<&Image>, factor: f32) -> f64 {
+    let calibrated = |x: usize, y: usize, b: usize| {
+        light.get_band(b).get(x, y) - thermal(dark, bias, x, y, b) * factor
+    };
+    let mut residuals: Vec<f64> = (0..light.num_bands())
+        .flat_map(|b| {
+            iproduct!(1..light.height - 1, 1..light.width - 1).map(move |(y, x)| (x, y, b))
+        })
+        .map(|(x, y, b)| {
+            let neighbours = calibrated(x - 1, y, b)
+                + calibrated(x + 1, y, b)
+                + calibrated(x, y - 1, b)
+                + calibrated(x, y + 1, b);
+            (calibrated(x, y, b) - neighbours / 4.0).abs() as f64
+        })
+        .collect();
+    imageutil::median(&mut residuals) * imageutil::MAD_TO_SIGMA
+}
+
+/// Mean of frames spread evenly across the light file
+fn mean_light(light: &str) -> Result<Image> {
+    let ser_file = FrameSource::open(&[light.to_owned()])?;
+    let frame_count = ser_file.frame_count();
+    let samples = frame_count.min(OPTIMIZE_SAMPLES);
+    if samples == 0 {
+        return Err(Error::msg("The light file has no frames"));
+    }
+    let step = frame_count as f64 / samples as f64;
+
+    let mut mean = ser_file.get_frame(0)?.buffer;
+    for n in 1..samples {
+        check_cancel_status()?;
+        let frame = ser_file.get_frame((n as f64 * step).floor() as usize)?;
+        (0..mean.num_bands()).for_each(|b| {
+            iproduct!(0..mean.height, 0..mean.width).for_each(|(y, x)| {
+                let v = mean.get_band(b).get(x, y) + frame.buffer.get_band(b).get(x, y);
+                mean.put(x, y, v, b);
+            });
+        });
+        set_task_status(&t!("tasks.optimizing_dark_scale"), samples, n + 1);
+    }
+    (0..mean.num_bands()).for_each(|b| {
+        iproduct!(0..mean.height, 0..mean.width).for_each(|(y, x)| {
+            let v = mean.get_band(b).get(x, y) / samples as f32;
+            mean.put(x, y, v, b);
+        });
+    });
+    Ok(mean)
+}
+
+/// Finds the dark scale factor which leaves the least pixel to pixel noise in the light frames
+/// of `light` once calibrated with `master_dark`
+pub fn optimize_dark_scale(
+    light: &str,
+    master_dark: &CalibrationImage,
+    master_bias: &CalibrationImage,
+) -> Result<f64> {
+    let dark = master_dark
+        .image
+        .as_ref()
+        .ok_or_else(|| Error::msg("Dark scale optimization needs a master dark"))?;
+    let bias = matching_bias(dark, master_bias);
+
+    set_task_status(&t!("tasks.optimizing_dark_scale"), OPTIMIZE_SAMPLES, 0);
+    let light = mean_light(light)?;
+    if light.width != dark.width
+        || light.height != dark.height
+        || light.num_bands() != dark.num_bands()
+        || light.width < 3
+        || light.height < 3
+    {
+        return Err(Error::msg(
+            "The master dark doesn't match the size of the light frames",
+        ));
+    }
+
+    let noise = |factor: f64| residual_noise(&light, dark, bias, factor as f32);
+    let ratio = (5.0_f64.sqrt() - 1.0) / 2.0;
+    let (mut low, mut high) = FACTOR_RANGE;
+    let mut a = high - ratio * (high - low);
+    let mut b = low + ratio * (high - low);
+    let (mut noise_a, mut noise_b) = (noise(a), noise(b));
+    for _ in 0..SEARCH_ITERATIONS {
+        check_cancel_status()?;
+        if noise_a < noise_b {
+            high = b;
+            b = a;
+            noise_b = noise_a;
+            a = high - ratio * (high - low);
+            noise_a = noise(a);
+        } else {
+            low = a;
+            a = b;
+            noise_a = noise_b;
+            b = low + ratio * (high - low);
+            noise_b = noise(b);
+        }
+    }
+    Ok((low + high) / 2.0)
+}
@@ -0,0 +1,68 @@
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+
+use crate::process::error::ProcessError;
+use crate::process::{build_masters, prepare_context};
+use crate::state::ApplicationState;
+use crate::taskstatus::*;
+
+/// Outcome of a dry run: everything up through frame limiting and rotation, without stacking
+#[derive(Clone, Debug)]
+pub struct DryRunResults {
+    pub frames_analyzed: usize,
+    pub frames_used: usize,
+    pub sigma_min: f64,
+    pub sigma_max: f64,
+    pub rotation_min: f64,
+    pub rotation_max: f64,
+    pub elapsed: Duration,
+    pub estimated_stacking: Duration,
+    pub warnings: Vec<String>,
+}
+
+pub async fn run_dry_async(app_state: ApplicationState) -> Result<DryRunResults> {
+    info!("Dry run started");
+    let start = Instant::now();
+    let masters = build_masters(&app_state)?;
+    let (context, summary) = prepare_context(&app_state, &masters)?;
+
+    if context.frame_records.is_empty() {
+        return Err(ProcessError::NoFramesAfterLimiting.into());
+    }
+
+    let (mut sigma_min, mut sigma_max) = (f64::MAX, f64::MIN);
+    let (mut rotation_min, mut rotation_max) = (f64::MAX, f64::MIN);
+    context.frame_records.iter().for_each(|fr| {
+        sigma_min = sigma_min.min(fr.sigma);
+        sigma_max = sigma_max.max(fr.sigma);
+        rotation_min = rotation_min.min(fr.computed_rotation.to_degrees());
+        rotation_max = rotation_max.max(fr.computed_rotation.to_degrees());
+    });
+
+    // Stacking reads every used frame again, same as analysis, and then spends time proportional
+    // to the drizzled output area. This is a rough estimate, not a promise.
+    let per_frame = if summary.frames_analyzed > 0 {
+        summary.analysis_duration.as_secs_f64() / summary.frames_analyzed as f64
+    } else {
+        0.0
+    };
+    let drizzle_area = (app_state.drizzle_factor() * app_state.drizzle_factor()) as f64;
+    let estimated_stacking = Duration::from_secs_f64(
+        per_frame * context.frame_records.len() as f64 * (1.0 + drizzle_area),
+    );
+
+    set_task_status(&t!("tasks.done"), 1, 1);
+
+    Ok(DryRunResults {
+        frames_analyzed: summary.frames_analyzed,
+        frames_used: context.frame_records.len(),
+        sigma_min,
+        sigma_max,
+        rotation_min,
+        rotation_max,
+        elapsed: start.elapsed(),
+        estimated_stacking,
+        warnings: summary.warnings,
+    })
+}
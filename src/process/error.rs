@@ -0,0 +1,265 @@
+use std::fmt;
+use std::path::PathBuf;
+
+use anyhow::Result;
+
+use crate::cancel::TaskCompletion;
+
+/// Category of a processing failure. Errors are carried through processing as `anyhow::Error`
+/// and tagged with a category at the stage where they occur.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ProcessError {
+    /// A required input, named, wasn't provided
+    MissingInput(&'static str),
+    OpenLightFailed(String),
+    /// Computing the master of the named calibration input failed
+    CalibrationFailed {
+        frame: &'static str,
+        reason: String,
+    },
+    AnalysisFailed(String),
+    NoFramesAfterLimiting,
+    RotationFailed(String),
+    StackingFailed(String),
+    SaveFailed {
+        path: PathBuf,
+        reason: String,
+    },
+    Cancelled,
+    Other(String),
+}
+
+impl std::error::Error for ProcessError {}
+
+impl fmt::Display for ProcessError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ProcessError::MissingInput(input) => write!(f, "No {} input was provided", input),
+            ProcessError::OpenLightFailed(reason) => {
+                write!(f, "Failed to open the light input: {}", reason)
+            }
+            ProcessError::CalibrationFailed { frame, reason } => {
+                write!(f, "Failed to compute the master {}: {}", frame, reason)
+            }
+            ProcessError::AnalysisFailed(reason) => write!(f, "Frame analysis failed: {}", reason),
+            ProcessError::NoFramesAfterLimiting => {
+                write!(f, "Zero frames to stack. Cannot continue")
+            }
+            ProcessError::RotationFailed(reason) => {
+                write!(f, "Frame rotation failed: {}", reason)
+            }
+            ProcessError::StackingFailed(reason) => write!(f, "Stacking failed: {}", reason),
+            ProcessError::SaveFailed { path, reason } => {
+                write!(f, "Failed to save {}: {}", path.display(), reason)
+            }
+            ProcessError::Cancelled => write!(f, "Processing was cancelled"),
+            ProcessError::Other(reason) => write!(f, "{}", reason),
+        }
+    }
+}
+
+impl From<&anyhow::Error> for ProcessError {
+    /// Recovers the category of an error, if it was given one
+    fn from(error: &anyhow::Error) -> Self {
+        if let Some(process_error) = error.downcast_ref::<ProcessError>() {
+            process_error.clone()
+        } else if error.downcast_ref::<TaskCompletion>() == Some(&TaskCompletion::Cancelled) {
+            ProcessError::Cancelled
+        } else {
+            ProcessError::Other(error.to_string())
+        }
+    }
+}
+
+impl From<anyhow::Error> for ProcessError {
+    fn from(error: anyhow::Error) -> Self {
+        ProcessError::from(&error)
+    }
+}
+
+/// Common underlying causes of failures, recognized from the text of the errors raised by
+/// solhat, sciimg and the operating system, which are only ever in English
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Cause {
+    FileNotFound,
+    PermissionDenied,
+    DiskFull,
+    OutOfMemory,
+    InvalidSer,
+    SizeMismatch,
+    UnsupportedFormat,
+}
+
+impl Cause {
+    /// Phrases, lowercase, identifying each cause
+    const PATTERNS: [(Cause, &'static [&'static str]); 7] = [
+        (
+            Cause::FileNotFound,
+            &["no such file", "cannot find the", "not found"],
+        ),
+        (
+            Cause::PermissionDenied,
+            &[
+                "permission denied",
+                "access is denied",
+                "read-only file system",
+            ],
+        ),
+        (
+            Cause::DiskFull,
+            &[
+                "no space left",
+                "disk full",
+                "not enough space",
+                "quota exceeded",
+            ],
+        ),
+        (
+            Cause::OutOfMemory,
+            &["out of memory", "memory allocation", "capacity overflow"],
+        ),
+        (
+            Cause::InvalidSer,
+            &["ser file", "lucam-recorder", "beyond the end of the file"],
+        ),
+        (
+            Cause::SizeMismatch,
+            &["dimension", "size mismatch", "do not match", "don't match"],
+        ),
+        (
+            Cause::UnsupportedFormat,
+            &[
+                "unsupported",
+                "unknown format",
+                "unrecognized",
+                "not supported",
+            ],
+        ),
+    ];
+
+    pub fn detect(reason: &str) -> Option<Cause> {
+        let reason = reason.to_lowercase();
+        Cause::PATTERNS
+            .iter()
+            .find(|(_, phrases)| phrases.iter().any(|p| reason.contains(p)))
+            .map(|(cause, _)| *cause)
+    }
+
+    /// What went wrong and how to fix it, for showing to the user
+    pub fn describe(&self) -> String {
+        match self {
+            Cause::FileNotFound => t!("errors.cause_not_found"),
+            Cause::PermissionDenied => t!("errors.cause_permission_denied"),
+            Cause::DiskFull => t!("errors.cause_disk_full"),
+            Cause::OutOfMemory => t!("errors.cause_out_of_memory"),
+            Cause::InvalidSer => t!("errors.cause_invalid_ser"),
+            Cause::SizeMismatch => t!("errors.cause_size_mismatch"),
+            Cause::UnsupportedFormat => t!("errors.cause_unsupported_format"),
+        }
+        .to_string()
+    }
+}
+
+impl ProcessError {
+    /// True for failures which may not happen again if the run is retried, such as a full disk
+    /// or an unavailable network share
+    pub fn is_recoverable(&self) -> bool {
+        matches!(self, ProcessError::SaveFailed { .. })
+    }
+
+    /// Suggestion for fixing the cause of the failure, if there's anything to suggest
+    pub fn hint(&self) -> Option<String> {
+        let hint = match self {
+            ProcessError::MissingInput(_) => t!("errors.hint_missing_input"),
+            ProcessError::OpenLightFailed(_) => t!("errors.hint_open_light"),
+            ProcessError::CalibrationFailed { .. } => t!("errors.hint_calibration"),
+            ProcessError::AnalysisFailed(_) => t!("errors.hint_analysis"),
+            ProcessError::NoFramesAfterLimiting => t!("errors.hint_no_frames"),
+            ProcessError::RotationFailed(_) => t!("errors.hint_rotation"),
+            ProcessError::StackingFailed(_) => t!("errors.hint_stacking"),
+            ProcessError::SaveFailed { .. } => t!("errors.hint_save"),
+            ProcessError::Cancelled | ProcessError::Other(_) => return None,
+        };
+        Some(hint.to_string())
+    }
+
+    /// The underlying error, as raised where the failure occurred
+    fn reason(&self) -> Option<&str> {
+        match self {
+            ProcessError::OpenLightFailed(reason)
+            | ProcessError::CalibrationFailed { reason, .. }
+            | ProcessError::AnalysisFailed(reason)
+            | ProcessError::RotationFailed(reason)
+            | ProcessError::StackingFailed(reason)
+            | ProcessError::SaveFailed { reason, .. }
+            | ProcessError::Other(reason) => Some(reason),
+            ProcessError::MissingInput(_)
+            | ProcessError::NoFramesAfterLimiting
+            | ProcessError::Cancelled => None,
+        }
+    }
+
+    /// The recognized cause of the failure, if any
+    pub fn cause(&self) -> Option<Cause> {
+        self.reason().and_then(Cause::detect)
+    }
+
+    /// Localized summary of the failure. Errors with no category are shown as raised, as there's
+    /// no knowing what they say.
+    pub fn message(&self) -> String {
+        match self {
+            ProcessError::MissingInput(input) => t!("errors.missing_input", input = input),
+            ProcessError::OpenLightFailed(_) => t!("errors.open_light_failed"),
+            ProcessError::CalibrationFailed { frame, .. } => {
+                t!("errors.calibration_failed", frame = frame)
+            }
+            ProcessError::AnalysisFailed(_) => t!("errors.analysis_failed"),
+            ProcessError::NoFramesAfterLimiting => t!("errors.no_frames"),
+            ProcessError::RotationFailed(_) => t!("errors.rotation_failed"),
+            ProcessError::StackingFailed(_) => t!("errors.stacking_failed"),
+            ProcessError::SaveFailed { path, .. } => {
+                t!("errors.save_failed", path = path.display())
+            }
+            ProcessError::Cancelled => t!("errors.cancelled"),
+            ProcessError::Other(reason) => match Cause::detect(reason) {
+                Some(_) => t!("errors.other_failed"),
+                None => reason.into(),
+            },
+        }
+        .to_string()
+    }
+
+    /// The localized message followed by the cause and how to fix it, for showing to the user.
+    /// The technical detail is left to the log.
+    pub fn describe(&self) -> String {
+        match self.cause().map(|c| c.describe()).or_else(|| self.hint()) {
+            Some(hint) => format!("{}\n\n{}", self.message(), hint),
+            None => self.message(),
+        }
+    }
+}
+
+/// Logs the full detail of `error` and returns its localized description for showing to the user
+pub fn user_message(error: &anyhow::Error) -> String {
+    error!("{:#}", error);
+    ProcessError::from(error).describe()
+}
+
+/// Tags the error of a result with a category, unless it already has one or is a cancellation
+pub trait Categorize<T> {
+    fn categorize<F>(self, category: F) -> Result<T>
+    where
+        F: FnOnce(String) -> ProcessError;
+}
+
+impl<T> Categorize<T> for Result<T> {
+    fn categorize<F>(self, category: F) -> Result<T>
+    where
+        F: FnOnce(String) -> ProcessError,
+    {
+        self.map_err(|error| match ProcessError::from(error) {
+            ProcessError::Other(reason) => category(reason).into(),
+            categorized => categorized.into(),
+        })
+    }
+}
@@ -0,0 +1,158 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use anyhow::Result;
+use itertools::iproduct;
+use sciimg::prelude::Image;
+use solhat::datasource::DataSource;
+
+use crate::cancel::*;
+use crate::imageutil;
+use crate::process::framesource::FrameSource;
+use crate::taskstatus::*;
+
+/// Number of light frames sampled when checking for mixed exposures
+const SURVEY_SAMPLES: usize = 100;
+
+/// Pixel stride used when estimating frame levels. Every pixel isn't needed for a mean level.
+const LEVEL_STRIDE: usize = 4;
+
+lazy_static! {
+    // Dark scaling applied by `CorrectedSerFile` as frames are read, by job slot. Set once the
+    // master dark and reference exposure level are known.
+    static ref ACTIVE_DARK_SCALING: Arc<Mutex<HashMap<usize, Arc<DarkScaling>>>> =
+        Arc::new(Mutex::new(HashMap::new()));
+}
+
+/// Mean signal level, above the dark level, of a sample of light frames
+#[derive(Debug, Clone)]
+pub struct ExposureSurvey {
+    pub levels: Vec<f64>,
+    pub dark_level: f64,
+}
+
+impl ExposureSurvey {
+    /// Median frame level, taken as the exposure the master dark was matched to
+    pub fn reference_level(&self) -> f64 {
+        let mut finite: Vec<f64> = self
+            .levels
+            .iter()
+            .copied()
+            .filter(|v| v.is_finite())
+            .collect();
+        imageutil::median(&mut finite)
+    }
+
+    /// Ratio of the brightest to the dimmest sampled frame
+    pub fn level_spread(&self) -> f64 {
+        let min = self.levels.iter().cloned().fold(f64::MAX, f64::min);
+        let max = self.levels.iter().cloned().fold(f64::MIN, f64::max);
+        if min > 0.0 {
+            max / min
+        } else {
+            f64::MAX
+        }
+    }
+
+    /// True if the sampled frame levels vary by more than `tolerance`, e.g. 0.2 for 20%, which
+    /// suggests the capture was made with more than one exposure time.
+    pub fn is_mixed(&self, tolerance: f64) -> bool {
+        self.levels.len() > 1 && self.level_spread() > 1.0 + tolerance
+    }
+}
+
+/// Mean value of every band of `image`, sampled on a coarse grid
+fn image_level(image: &Image) -> f64 {
+    let mut sum = 0.0;
+    let mut count = 0;
+    (0..image.num_bands()).for_each(|b| {
+        iproduct!(
+            (0..image.height).step_by(LEVEL_STRIDE),
+            (0..image.width).step_by(LEVEL_STRIDE)
+        )
+        .for_each(|(y, x)| {
+            sum += image.get_band(b).get(x, y) as f64;
+            count += 1;
+        });
+    });
+    if count > 0 {
+        sum / count as f64
+    } else {
+        0.0
+    }
+}
+
+/// Samples raw frames across the light file and measures their level above the master dark
+pub fn survey_exposures(light: &str, master_dark: Option<&Image>) -> Result<ExposureSurvey> {
+    let dark_level = master_dark.map(image_level).unwrap_or(0.0);
+
+    let ser_file = FrameSource::open(&[light.to_owned()])?;
+    let frame_count = ser_file.frame_count();
+    let step = (frame_count as f64 / SURVEY_SAMPLES as f64).max(1.0);
+    let samples: Vec<usize> = (0..frame_count.min(SURVEY_SAMPLES))
+        .map(|i| (i as f64 * step).floor() as usize)
+        .collect();
+
+    set_task_status(&t!("tasks.checking_exposures"), samples.len(), 0);
+    let mut levels = vec![];
+    for (n, frame_id) in samples.iter().enumerate() {
+        check_cancel_status()?;
+        let frame = ser_file.get_frame(*frame_id)?;
+        levels.push(image_level(&frame.buffer) - dark_level);
+        set_task_status(&t!("tasks.checking_exposures"), samples.len(), n + 1);
+    }
+
+    Ok(ExposureSurvey { levels, dark_level })
+}
+
+/// Scales the master dark subtracted from each frame in proportion to the frame's exposure
+pub struct DarkScaling {
+    master_dark: Image,
+    dark_level: f64,
+    reference_level: f64,
+}
+
+impl DarkScaling {
+    /// solhat subtracts the unscaled master dark during calibration. Adding `(1 - k)` of the dark
+    /// to the raw frame beforehand leaves the frame with `k` times the dark subtracted, where `k`
+    /// is the frame's level relative to the reference level.
+    pub fn apply(&self, frame: &mut Image) {
+        if frame.width != self.master_dark.width
+            || frame.height != self.master_dark.height
+            || frame.num_bands() != self.master_dark.num_bands()
+            || self.reference_level <= 0.0
+        {
+            return;
+        }
+        let k = (image_level(frame) - self.dark_level) / self.reference_level;
+        let residual = (1.0 - k) as f32;
+        (0..frame.num_bands()).for_each(|b| {
+            iproduct!(0..frame.height, 0..frame.width).for_each(|(y, x)| {
+                let v =
+                    frame.get_band(b).get(x, y) + residual * self.master_dark.get_band(b).get(x, y);
+                frame.put(x, y, v, b);
+            });
+        });
+    }
+}
+
+/// Enables per-frame dark scaling for the job in `slot` using the master dark and exposure survey
+/// of its run
+pub fn set_active_dark_scaling(slot: usize, master_dark: &Image, survey: &ExposureSurvey) {
+    ACTIVE_DARK_SCALING.lock().unwrap().insert(
+        slot,
+        Arc::new(DarkScaling {
+            master_dark: master_dark.clone(),
+            dark_level: survey.dark_level,
+            reference_level: survey.reference_level(),
+        }),
+    );
+}
+
+pub fn clear_active_dark_scaling(slot: usize) {
+    ACTIVE_DARK_SCALING.lock().unwrap().remove(&slot);
+}
+
+pub fn active_dark_scaling(slot: usize) -> Option<Arc<DarkScaling>> {
+    ACTIVE_DARK_SCALING.lock().unwrap().get(&slot).cloned()
+}
@@ -0,0 +1,430 @@
+use std::cmp::Ordering;
+use std::fs;
+use std::iter::Peekable;
+use std::path::{Path, PathBuf};
+use std::str::Chars;
+
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use sciimg::prelude::{Image, ImageMode};
+use solhat::datasource::{DataFrame, DataSource};
+use solhat::ser::SerFile;
+use solhat::timestamp::TimeStamp;
+
+use crate::fits;
+use crate::process::bayer::BayerCorrection;
+use crate::process::serheader::{self, RepairedSerFile};
+
+/// Extensions of the still frames read from a frame folder
+pub const FRAME_EXTENSIONS: &[&str] = &["tif", "tiff", "png"];
+
+/// Extensions of single still images accepted in place of a SER, e.g. calibration masters made
+/// with other tools
+pub const STILL_IMAGE_EXTENSIONS: &[&str] = &["tif", "tiff", "png", "fits", "fit", "fts"];
+
+/// .NET ticks, the unit of SER timestamps, between 0001-01-01 and the Unix epoch
+pub const UNIX_EPOCH_TICKS: u64 = 621_355_968_000_000_000;
+
+/// SER timestamp, in .NET ticks, of `time`
+pub fn ser_ticks(time: &DateTime<Utc>) -> u64 {
+    let ticks = time.timestamp() * 10_000_000 + time.timestamp_subsec_nanos() as i64 / 100;
+    (UNIX_EPOCH_TICKS as i64 + ticks).max(0) as u64
+}
+
+/// Consumes the run of digits at the start of `chars`, returning it without leading zeros
+fn take_number(chars: &mut Peekable<Chars>) -> String {
+    let mut digits = String::new();
+    while let Some(c) = chars.next_if(|c| c.is_ascii_digit()) {
+        digits.push(c);
+    }
+    digits.trim_start_matches('0').to_owned()
+}
+
+/// Compares file names so embedded numbers sort by value, i.e. `frame_9` before `frame_10`
+pub fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let mut a_chars = a.chars().peekable();
+    let mut b_chars = b.chars().peekable();
+    loop {
+        match (a_chars.peek(), b_chars.peek()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(ac), Some(bc)) if ac.is_ascii_digit() && bc.is_ascii_digit() => {
+                let a_number = take_number(&mut a_chars);
+                let b_number = take_number(&mut b_chars);
+                let ordering = a_number
+                    .len()
+                    .cmp(&b_number.len())
+                    .then_with(|| a_number.cmp(&b_number));
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+            }
+            (Some(ac), Some(bc)) => {
+                let ordering = ac.to_ascii_lowercase().cmp(&bc.to_ascii_lowercase());
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+                a_chars.next();
+                b_chars.next();
+            }
+        }
+    }
+}
+
+/// The still frames in `directory`, in natural order of their file names
+pub fn list_frame_files(directory: &Path) -> Result<Vec<PathBuf>> {
+    let mut frames: Vec<PathBuf> = fs::read_dir(directory)?
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.is_file()
+                && path
+                    .extension()
+                    .map(|e| {
+                        FRAME_EXTENSIONS
+                            .iter()
+                            .any(|ext| e.to_string_lossy().eq_ignore_ascii_case(ext))
+                    })
+                    .unwrap_or(false)
+        })
+        .collect();
+    frames.sort_by(|a, b| {
+        natural_cmp(
+            &a.file_name().unwrap_or_default().to_string_lossy(),
+            &b.file_name().unwrap_or_default().to_string_lossy(),
+        )
+    });
+    if frames.is_empty() {
+        Err(anyhow!(
+            "No TIFF or PNG frames found in {}",
+            directory.display()
+        ))
+    } else {
+        Ok(frames)
+    }
+}
+
+pub fn is_still_image(path: &Path) -> bool {
+    path.extension()
+        .map(|e| {
+            STILL_IMAGE_EXTENSIONS
+                .iter()
+                .any(|ext| e.to_string_lossy().eq_ignore_ascii_case(ext))
+        })
+        .unwrap_or(false)
+}
+
+/// Opens a still image, which may be a FITS file
+pub fn open_still_image(path: &Path) -> Result<Image> {
+    if fits::is_fits_file(path) {
+        fits::read_fits(path)
+    } else {
+        Image::open(&path.to_string_lossy())
+    }
+}
+
+/// Timestamp of a frame file, taken from its modification time as still images carry none
+fn file_timestamp(path: &Path) -> TimeStamp {
+    let ticks = fs::metadata(path)
+        .and_then(|m| m.modified())
+        .ok()
+        .map(|t| ser_ticks(&DateTime::<Utc>::from(t)))
+        .unwrap_or(UNIX_EPOCH_TICKS);
+    TimeStamp::from_u64(ticks)
+}
+
+/// A folder of sequentially numbered still frames read as though it were a SER file. All frames
+/// must have the size of the first.
+pub struct FrameSequence {
+    directory: PathBuf,
+    frames: Vec<PathBuf>,
+    width: usize,
+    height: usize,
+    pixel_depth: usize,
+}
+
+impl DataSource for FrameSequence {
+    fn open(filenames: &[String]) -> Result<Self> {
+        let directory = PathBuf::from(
+            filenames
+                .first()
+                .ok_or_else(|| anyhow!("No frame folder given"))?,
+        );
+        let frames = list_frame_files(&directory)?;
+        let first = Image::open(&frames[0].to_string_lossy())?;
+        info!(
+            "Opened frame folder {:?} with {} frames of {}x{}",
+            directory,
+            frames.len(),
+            first.width,
+            first.height
+        );
+        Ok(FrameSequence {
+            directory,
+            frames,
+            width: first.width,
+            height: first.height,
+            pixel_depth: match first.get_mode() {
+                ImageMode::U8BIT => 8,
+                ImageMode::U12BIT => 12,
+                ImageMode::U16BIT => 16,
+            },
+        })
+    }
+
+    fn image_width(&self) -> usize {
+        self.width
+    }
+
+    fn image_height(&self) -> usize {
+        self.height
+    }
+
+    fn frame_count(&self) -> usize {
+        self.frames.len()
+    }
+
+    fn pixel_depth(&self) -> usize {
+        self.pixel_depth
+    }
+
+    fn get_frame(&self, frame_num: usize) -> Result<DataFrame> {
+        let path = self
+            .frames
+            .get(frame_num)
+            .ok_or_else(|| anyhow!("Frame {} is beyond the end of the sequence", frame_num))?;
+        let buffer = Image::open(&path.to_string_lossy())?;
+        if buffer.width != self.width || buffer.height != self.height {
+            return Err(anyhow!(
+                "Frame {} is {}x{}, other frames are {}x{}",
+                path.display(),
+                buffer.width,
+                buffer.height,
+                self.width,
+                self.height
+            ));
+        }
+        Ok(DataFrame {
+            buffer,
+            timestamp: file_timestamp(path),
+        })
+    }
+
+    fn source_file(&self) -> String {
+        self.directory.to_string_lossy().to_string()
+    }
+
+    fn observer(&self) -> String {
+        "".to_owned()
+    }
+
+    fn instrument(&self) -> String {
+        "".to_owned()
+    }
+
+    fn telescope(&self) -> String {
+        "".to_owned()
+    }
+
+    fn date_time_utc(&self) -> TimeStamp {
+        file_timestamp(&self.frames[0])
+    }
+}
+
+/// A single still image read as a SER file of one frame
+pub struct StillImage {
+    path: PathBuf,
+    image: Image,
+}
+
+impl DataSource for StillImage {
+    fn open(filenames: &[String]) -> Result<Self> {
+        let path = PathBuf::from(filenames.first().ok_or_else(|| anyhow!("No image given"))?);
+        let image = open_still_image(&path)?;
+        Ok(StillImage { path, image })
+    }
+
+    fn image_width(&self) -> usize {
+        self.image.width
+    }
+
+    fn image_height(&self) -> usize {
+        self.image.height
+    }
+
+    fn frame_count(&self) -> usize {
+        1
+    }
+
+    fn pixel_depth(&self) -> usize {
+        match self.image.get_mode() {
+            ImageMode::U8BIT => 8,
+            ImageMode::U12BIT => 12,
+            ImageMode::U16BIT => 16,
+        }
+    }
+
+    fn get_frame(&self, frame_num: usize) -> Result<DataFrame> {
+        if frame_num != 0 {
+            return Err(anyhow!("A still image has only one frame"));
+        }
+        Ok(DataFrame {
+            buffer: self.image.clone(),
+            timestamp: file_timestamp(&self.path),
+        })
+    }
+
+    fn source_file(&self) -> String {
+        self.path.to_string_lossy().to_string()
+    }
+
+    fn observer(&self) -> String {
+        "".to_owned()
+    }
+
+    fn instrument(&self) -> String {
+        "".to_owned()
+    }
+
+    fn telescope(&self) -> String {
+        "".to_owned()
+    }
+
+    fn date_time_utc(&self) -> TimeStamp {
+        file_timestamp(&self.path)
+    }
+}
+
+/// An input of frames: a SER file, a folder of still frames, or a single still image. SER frames
+/// are reinterpreted with any Bayer pattern override of the file, and SER files with a header
+/// override are read with the overridden values.
+pub enum FrameSource {
+    Ser(SerFile, Option<BayerCorrection>),
+    Repaired(RepairedSerFile, Option<BayerCorrection>),
+    Sequence(FrameSequence),
+    Still(StillImage),
+}
+
+impl DataSource for FrameSource {
+    fn open(filenames: &[String]) -> Result<Self> {
+        let path = filenames.first().map(Path::new);
+        if path.map(|p| p.is_dir()) == Some(true) {
+            Ok(FrameSource::Sequence(FrameSequence::open(filenames)?))
+        } else if path.map(is_still_image) == Some(true) {
+            Ok(FrameSource::Still(StillImage::open(filenames)?))
+        } else if let Some(header_override) = serheader::override_for(&filenames[0]) {
+            let ser_file = RepairedSerFile::open_with_override(&filenames[0], header_override)?;
+            let correction = BayerCorrection::for_file(&filenames[0])?;
+            Ok(FrameSource::Repaired(ser_file, correction))
+        } else {
+            let ser_file = SerFile::open(filenames)?;
+            let correction = BayerCorrection::for_file(&filenames[0])?;
+            Ok(FrameSource::Ser(ser_file, correction))
+        }
+    }
+
+    fn image_width(&self) -> usize {
+        match self {
+            FrameSource::Ser(s, _) => s.image_width(),
+            FrameSource::Repaired(s, _) => s.image_width(),
+            FrameSource::Sequence(s) => s.image_width(),
+            FrameSource::Still(s) => s.image_width(),
+        }
+    }
+
+    fn image_height(&self) -> usize {
+        match self {
+            FrameSource::Ser(s, _) => s.image_height(),
+            FrameSource::Repaired(s, _) => s.image_height(),
+            FrameSource::Sequence(s) => s.image_height(),
+            FrameSource::Still(s) => s.image_height(),
+        }
+    }
+
+    fn frame_count(&self) -> usize {
+        match self {
+            FrameSource::Ser(s, _) => s.frame_count(),
+            FrameSource::Repaired(s, _) => s.frame_count(),
+            FrameSource::Sequence(s) => s.frame_count(),
+            FrameSource::Still(s) => s.frame_count(),
+        }
+    }
+
+    fn pixel_depth(&self) -> usize {
+        match self {
+            FrameSource::Ser(s, _) => s.pixel_depth(),
+            FrameSource::Repaired(s, _) => s.pixel_depth(),
+            FrameSource::Sequence(s) => s.pixel_depth(),
+            FrameSource::Still(s) => s.pixel_depth(),
+        }
+    }
+
+    fn get_frame(&self, frame_num: usize) -> Result<DataFrame> {
+        match self {
+            FrameSource::Ser(s, None) => s.get_frame(frame_num),
+            FrameSource::Ser(s, Some(correction)) => {
+                let frame = s.get_frame(frame_num)?;
+                Ok(DataFrame {
+                    buffer: correction.apply(&frame.buffer)?,
+                    timestamp: frame.timestamp,
+                })
+            }
+            FrameSource::Repaired(s, None) => s.get_frame(frame_num),
+            FrameSource::Repaired(s, Some(correction)) => {
+                let frame = s.get_frame(frame_num)?;
+                Ok(DataFrame {
+                    buffer: correction.apply(&frame.buffer)?,
+                    timestamp: frame.timestamp,
+                })
+            }
+            FrameSource::Sequence(s) => s.get_frame(frame_num),
+            FrameSource::Still(s) => s.get_frame(frame_num),
+        }
+    }
+
+    fn source_file(&self) -> String {
+        match self {
+            FrameSource::Ser(s, _) => s.source_file(),
+            FrameSource::Repaired(s, _) => s.source_file(),
+            FrameSource::Sequence(s) => s.source_file(),
+            FrameSource::Still(s) => s.source_file(),
+        }
+    }
+
+    fn observer(&self) -> String {
+        match self {
+            FrameSource::Ser(s, _) => s.observer(),
+            FrameSource::Repaired(s, _) => s.observer(),
+            FrameSource::Sequence(s) => s.observer(),
+            FrameSource::Still(s) => s.observer(),
+        }
+    }
+
+    fn instrument(&self) -> String {
+        match self {
+            FrameSource::Ser(s, _) => s.instrument(),
+            FrameSource::Repaired(s, _) => s.instrument(),
+            FrameSource::Sequence(s) => s.instrument(),
+            FrameSource::Still(s) => s.instrument(),
+        }
+    }
+
+    fn telescope(&self) -> String {
+        match self {
+            FrameSource::Ser(s, _) => s.telescope(),
+            FrameSource::Repaired(s, _) => s.telescope(),
+            FrameSource::Sequence(s) => s.telescope(),
+            FrameSource::Still(s) => s.telescope(),
+        }
+    }
+
+    fn date_time_utc(&self) -> TimeStamp {
+        match self {
+            FrameSource::Ser(s, _) => s.date_time_utc(),
+            FrameSource::Repaired(s, _) => s.date_time_utc(),
+            FrameSource::Sequence(s) => s.date_time_utc(),
+            FrameSource::Still(s) => s.date_time_utc(),
+        }
+    }
+}
@@ -0,0 +1,63 @@
+use std::fs;
+use std::path::Path;
+
+use anyhow::Result;
+use itertools::iproduct;
+use sciimg::prelude::Image;
+use serde::{Deserialize, Serialize};
+
+use crate::imageutil;
+use crate::process::calibration;
+use crate::state::ApplicationState;
+
+/// A hot pixel map as read by solhat, listing the x/y coordinates of each hot pixel
+#[derive(Debug, Default, Deserialize, Serialize, Clone, PartialEq)]
+pub struct HotPixelMap {
+    pub sensor_width: usize,
+    pub sensor_height: usize,
+    pub hotpixels: Vec<[usize; 2]>,
+}
+
+impl HotPixelMap {
+    pub fn save(&self, path: &Path) -> Result<()> {
+        fs::write(path, toml::to_string(self)?)?;
+        Ok(())
+    }
+}
+
+/// Dark level of a master dark, kept so the detection threshold can be changed without going
+/// over the whole image again
+#[derive(Debug, Clone, Copy)]
+pub struct DarkLevel {
+    pub median: f32,
+    /// Median absolute deviation scaled to sigma, at least one count so darks quantized to a
+    /// single level don't flag every pixel above it
+    pub sigma: f32,
+}
+
+pub fn dark_level(image: &Image) -> DarkLevel {
+    let mut values: Vec<f32> = iproduct!(0..image.num_bands(), 0..image.height, 0..image.width)
+        .map(|(b, y, x)| image.get_band(b).get(x, y))
+        .filter(|v| v.is_finite())
+        .collect();
+    let median = imageutil::median(&mut values);
+    values.iter_mut().for_each(|v| *v = (*v - median).abs());
+    DarkLevel {
+        median,
+        sigma: (imageutil::median(&mut values) * imageutil::MAD_TO_SIGMA as f32).max(1.0),
+    }
+}
+
+/// Pixels of `image` brighter than the dark level by more than `threshold` sigma in any band
+pub fn detect_hot_pixels(image: &Image, level: DarkLevel, threshold: f32) -> Vec<[usize; 2]> {
+    let limit = level.median + threshold * level.sigma;
+    iproduct!(0..image.height, 0..image.width)
+        .filter(|(y, x)| (0..image.num_bands()).any(|b| image.get_band(b).get(*x, *y) > limit))
+        .map(|(y, x)| [x, y])
+        .collect()
+}
+
+/// Computes a master dark from the dark input, for detecting hot pixels in
+pub async fn master_dark_async(app_state: ApplicationState) -> Result<Image> {
+    calibration::compute_master_dark(&app_state)
+}
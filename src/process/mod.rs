@@ -1,9 +1,12 @@
-use std::path::PathBuf;
+use std::fs;
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
-use anyhow::{Error, Result};
+use anyhow::Result;
+use chrono::{DateTime, Utc};
 use sciimg::prelude::Image;
-use solhat::calibrationframe::{CalibrationImage, ComputeMethod};
+use solhat::calibrationframe::CalibrationImage;
 // use solhat::anaysis::frame_sigma_analysis_window_size;
 use solhat::context::{ProcessContext, ProcessParameters};
 use solhat::datasource::DataSource;
@@ -12,54 +15,318 @@ use solhat::ldcorrect;
 use solhat::limiting::frame_limit_determinate;
 // use solhat::offsetting::frame_offset_analysis;
 use solhat::rotation::frame_rotation_analysis;
-use solhat::ser::SerFile;
 use solhat::stacking::process_frame_stacking;
 
-use crate::analysis::sigma::frame_analysis_window_size;
+use crate::analysis::sigma::{frame_analysis_window_size, AnalysisOptions};
 use crate::cancel::*;
+use crate::colorprofile::{self, ColorProfile};
+use crate::fits::{self, FitsKeyword, FitsValue};
+use crate::history;
+use crate::imageutil;
+use crate::metadata::ResultMetadata;
+use crate::partialfile;
+use crate::process::alignedframes::AlignedFrameExport;
+use crate::process::badlines::CorrectedSerFile;
+use crate::process::contribution::StackGeometry;
+use crate::process::error::{Categorize, ProcessError};
+use crate::process::report::{RunReport, StageTimings};
+use crate::resources::{ResourceMonitor, ResourceUsage};
 use crate::state::*;
 use crate::taskstatus::*;
+use crate::tiffwriter::{self, TiffOptions};
+
+pub mod alignedframes;
+pub mod alignpoints;
+pub mod animation;
+pub mod badlines;
+pub mod bayer;
+pub mod calibration;
+pub mod caption;
+pub mod combine;
+pub mod comparison;
+pub mod composite;
+pub mod contribution;
+pub mod darkscale;
+pub mod dryrun;
+pub mod error;
+pub mod exposure;
+pub mod framesource;
+pub mod hotpixels;
+pub mod report;
+pub mod rotation;
+pub mod sampling;
+pub mod saturation;
+pub mod serheader;
+pub mod sidecar;
 
 #[derive(Clone)]
 pub struct RunResultsContainer {
     pub was_success: bool,
     pub image: Option<Image>,
-    pub error: Option<String>,
+    pub error: Option<ProcessError>,
     pub context: Option<ProcessParameters>,
+    /// Options the run was made with
+    pub options: Option<ApplicationState>,
     pub output_filename: Option<PathBuf>,
     pub num_frames_used: usize,
+    pub analysis_binning: AnalysisBinning,
+    /// One entry per frame of the light file, in capture order, set if the frame was stacked
+    pub frame_usage: Vec<bool>,
+    /// Computed rotation, in degrees, for each stacked frame as (frame id, rotation) pairs
+    pub frame_rotations: Vec<(usize, f64)>,
+    pub warnings: Vec<String>,
+    /// Color profile and TIFF options the output was saved with, for saving copies of it
+    pub color_profile: ColorProfile,
+    pub tiff_options: TiffOptions,
+    pub jpeg_quality: u8,
+    pub resources: ResourceUsage,
+    /// Mapping of the stacked frames onto the output, for inspecting their contributions
+    pub geometry: Option<StackGeometry>,
+    /// Header keywords describing the observation and stack, for saving the output as FITS
+    pub fits_header: Vec<FitsKeyword>,
+    /// Calibration masters computed for the run and saved at the user's request
+    pub saved_masters: Vec<PathBuf>,
+    /// Caption added beneath the output and copies saved of it
+    pub caption: Option<String>,
 }
 
-pub async fn run_async(
-    output_filename: PathBuf,
-    app_state: ApplicationState,
-) -> Result<RunResultsContainer> {
-    info!("Async task started");
+impl RunResultsContainer {
+    pub fn failed(error: ProcessError) -> Self {
+        RunResultsContainer {
+            was_success: false,
+            image: None,
+            error: Some(error),
+            context: None,
+            options: None,
+            output_filename: None,
+            num_frames_used: 0,
+            analysis_binning: AnalysisBinning::None,
+            color_profile: ColorProfile::None,
+            tiff_options: TiffOptions::default(),
+            jpeg_quality: 90,
+            resources: ResourceUsage::default(),
+            geometry: None,
+            fits_header: vec![],
+            saved_masters: vec![],
+            caption: None,
+            frame_usage: vec![],
+            frame_rotations: vec![],
+            warnings: vec![],
+        }
+    }
+
+    /// Saves `image`, the output or an adjusted copy of it, with the options the output was saved
+    /// with. The format is chosen by the extension of `path`.
+    pub fn save_image(&self, image: &Image, path: &Path) -> Result<()> {
+        save_output(
+            image,
+            path,
+            self.color_profile,
+            &self.tiff_options,
+            self.jpeg_quality,
+            &self.fits_header,
+            self.caption.as_deref(),
+        )
+    }
+}
+
+/// Frame counts and timing gathered while preparing the frames for stacking
+pub struct PreparationSummary {
+    pub frames_total: usize,
+    pub frames_analyzed: usize,
+    pub analysis_duration: Duration,
+    /// Quality of every frame analyzed, before limiting
+    pub analyzed_sigmas: Vec<f64>,
+    pub stages: StageTimings,
+    /// Paths of the calibration masters saved for the run
+    pub saved_masters: Vec<PathBuf>,
+    /// Problems with the input data which didn't prevent processing
+    pub warnings: Vec<String>,
+}
+
+/// Builds the process context with `masters` and runs frame analysis, limiting, sampling and
+/// rotation, leaving the context ready for stacking.
+fn prepare_context(
+    app_state: &ApplicationState,
+    masters: &CalibrationMasters,
+) -> Result<(ProcessContext<CorrectedSerFile>, PreparationSummary)> {
+    // Frames are read on rayon threads, which don't know which job they're working for
+    let slot = current_slot();
+    exposure::clear_active_dark_scaling(slot);
+    bayer::register_overrides(app_state);
+    let mut stages = StageTimings::default();
+    let stage_start = Instant::now();
+    let mut context: ProcessContext<CorrectedSerFile> = build_solhat_context(app_state, masters)?;
+    let bad_lines = if app_state.bad_line_correction {
+        app_state.bad_lines.clone()
+    } else {
+        badlines::BadLineMap::default()
+    };
+    context
+        .fp_map
+        .values_mut()
+        .for_each(|source| source.assign_to_job(slot, &bad_lines));
+    stages.record(
+        &t!("report.stage_calibration"),
+        masters.duration + stage_start.elapsed(),
+    );
+    let mut warnings = vec![];
+
+    let stage_start = Instant::now();
+    if let Some(light) = &app_state.light {
+        let master_dark = context.master_dark.image.as_ref();
+        let survey = exposure::survey_exposures(light, master_dark)?;
+        if survey.is_mixed(app_state.exposure_tolerance) {
+            warn!(
+                "Light frame levels vary by a factor of {:.2}; the capture may contain mixed exposures",
+                survey.level_spread()
+            );
+            match master_dark {
+                Some(master_dark) if app_state.dark_scaling => {
+                    info!("Applying per-frame dark scaling");
+                    exposure::set_active_dark_scaling(slot, master_dark, &survey);
+                    warnings.push(
+                        t!(
+                            "exposure.mixed_scaled",
+                            spread = format!("{:.2}", survey.level_spread())
+                        )
+                        .to_string(),
+                    );
+                }
+                _ => warnings.push(
+                    t!(
+                        "exposure.mixed_warning",
+                        spread = format!("{:.2}", survey.level_spread())
+                    )
+                    .to_string(),
+                ),
+            }
+        }
+    }
 
-    let mut context: ProcessContext<SerFile> = build_solhat_context(&app_state)?;
+    /////////////////////////////////////////////////////////////
+    /////////////////////////////////////////////////////////////
+
+    stages.record(&t!("report.stage_exposures"), stage_start.elapsed());
 
     /////////////////////////////////////////////////////////////
     /////////////////////////////////////////////////////////////
 
-    context.frame_records = frame_sigma_analysis(&context)?;
+    let frames_total = context.frame_records.len();
+    if let Some([first, last]) = app_state.frame_range {
+        info!("Stacking frames {} to {} of the capture", first, last);
+        context
+            .frame_records
+            .retain(|fr| (first..=last).contains(&fr.frame_id));
+    }
+    let analysis_start = Instant::now();
+    context.frame_records = frame_sigma_analysis(&context, &AnalysisOptions::from_state(app_state))
+        .categorize(ProcessError::AnalysisFailed)?;
+    let analysis_duration = analysis_start.elapsed();
+    stages.record(&t!("report.stage_analysis"), analysis_duration);
+    let mut summary = PreparationSummary {
+        frames_total,
+        frames_analyzed: context.frame_records.len(),
+        analysis_duration,
+        analyzed_sigmas: context.frame_records.iter().map(|fr| fr.sigma).collect(),
+        stages,
+        saved_masters: masters.saved.clone(),
+        warnings,
+    };
 
     /////////////////////////////////////////////////////////////
     /////////////////////////////////////////////////////////////
 
-    context.frame_records = frame_limiting(&context)?;
+    let stage_start = Instant::now();
+    context.frame_records = frame_limiting(&context).categorize(ProcessError::AnalysisFailed)?;
+
+    if app_state.frame_sampling != FrameSampling::Truncate {
+        info!(
+            "Sampling up to {} frames using {:?}",
+            app_state.max_frames, app_state.frame_sampling
+        );
+        context.frame_records = sampling::sample_frames(
+            &context.frame_records,
+            app_state.max_frames,
+            app_state.frame_sampling,
+            app_state.sampling_seed,
+        );
+    }
+
+    summary
+        .stages
+        .record(&t!("report.stage_limiting"), stage_start.elapsed());
 
     /////////////////////////////////////////////////////////////
     /////////////////////////////////////////////////////////////
 
-    context.frame_records = frame_rotation(&context)?;
+    let stage_start = Instant::now();
+    context.frame_records = match app_state.rotation_model {
+        RotationModel::Ephemeris => {
+            frame_rotation(&context).categorize(ProcessError::RotationFailed)?
+        }
+        RotationModel::RatePerMinute => {
+            info!(
+                "Using manual rotation rate of {} degrees per minute",
+                app_state.rotation_rate
+            );
+            rotation::manual_rotation(&context, app_state.rotation_model, app_state.rotation_rate)
+                .categorize(ProcessError::RotationFailed)?
+        }
+        RotationModel::FixedPerFrame => {
+            info!(
+                "Using fixed rotation of {} degrees per frame",
+                app_state.rotation_per_frame
+            );
+            rotation::manual_rotation(
+                &context,
+                app_state.rotation_model,
+                app_state.rotation_per_frame,
+            )
+            .categorize(ProcessError::RotationFailed)?
+        }
+    };
+
+    // Flipped frames rotate in the opposite direction. Stacking the frames as captured with the
+    // rotation reversed, then flipping the stack, is equivalent to flipping each frame first.
+    if app_state.frame_orientation.is_mirrored() {
+        context
+            .frame_records
+            .iter_mut()
+            .for_each(|fr| fr.computed_rotation = -fr.computed_rotation);
+    }
+    summary
+        .stages
+        .record(&t!("report.stage_rotation"), stage_start.elapsed());
+
+    Ok((context, summary))
+}
+
+pub async fn run_async(
+    output_filename: PathBuf,
+    app_state: ApplicationState,
+) -> Result<RunResultsContainer> {
+    info!("Async task started");
+    let run_start = Instant::now();
+    let resource_monitor = ResourceMonitor::start();
+
+    let masters = build_masters(&app_state)?;
+    let (context, mut summary) = prepare_context(&app_state, &masters)?;
 
     /////////////////////////////////////////////////////////////
     /////////////////////////////////////////////////////////////
 
     if context.frame_records.is_empty() {
-        Err(Error::msg("Zero frames to stack. Cannot continue"))
+        Err(ProcessError::NoFramesAfterLimiting.into())
     } else {
-        let stacked_buffer = drizzle_stacking(&context)?;
+        let stage_start = Instant::now();
+        let stacked_buffer =
+            stack_frames(&context, &app_state).categorize(ProcessError::StackingFailed)?;
+        summary
+            .stages
+            .record(&t!("report.stage_stacking"), stage_start.elapsed());
+
+        let stage_start = Instant::now();
 
         // check_cancel_status()?;
         // set_task_status(&t!("tasks.merging_stack_buffers"), 0, 0);
@@ -80,6 +347,17 @@ pub async fn run_async(
             stacked_buffer
         };
 
+        let invalid_pixels = imageutil::replace_invalid_pixels(&mut corrected_buffer);
+        if invalid_pixels > 0 {
+            warn!(
+                "Replaced {} NaN or infinite stack values with zero",
+                invalid_pixels
+            );
+            summary
+                .warnings
+                .push(t!("quality.invalid_pixels", count = invalid_pixels).to_string());
+        }
+
         // Let the user know some stuff...
         let (stackmin, stackmax) = corrected_buffer.get_min_max_all_channel();
         info!(
@@ -89,11 +367,34 @@ pub async fn run_async(
             context.frame_records.len()
         );
 
+        // Floating point output is written from the stack before normalization, only scaled so
+        // the 16-bit range maps to 0-1. The normalized copy is still made for display and for
+        // saving adjusted copies from the result view. Prominence composites are only made from
+        // normalized stacks.
+        let unnormalized_buffer = (app_state.float_output()
+            && app_state.prominence_light.is_none())
+        .then(|| corrected_buffer.clone());
+
         set_task_status(&t!("tasks.normalizing_data"), 0, 0);
-        if app_state.decorrelated_colors {
-            corrected_buffer.normalize_to_16bit_decorrelated();
-        } else {
-            corrected_buffer.normalize_to_16bit();
+        normalize(&mut corrected_buffer, &app_state);
+        summary
+            .stages
+            .record(&t!("report.stage_postprocessing"), stage_start.elapsed());
+
+        if let Some(prominence_light) = &app_state.prominence_light {
+            let stage_start = Instant::now();
+            let prominences = stack_prominences(&app_state, prominence_light, &masters)?;
+            set_task_status(&t!("tasks.compositing_prominences"), 0, 0);
+            corrected_buffer = composite::composite_prominences(
+                &corrected_buffer,
+                &prominences,
+                app_state.composite_blend_radius as f32,
+                app_state.solar_radius_pixels,
+            )
+            .categorize(ProcessError::StackingFailed)?;
+            summary
+                .stages
+                .record(&t!("report.stage_composite"), stage_start.elapsed());
         }
 
         set_task_status(&t!("tasks.saving_to_disk"), 0, 0);
@@ -104,7 +405,105 @@ pub async fn run_async(
 
         // Save finalized image to disk
         set_task_status(&t!("tasks.saving"), 0, 0);
-        corrected_buffer.save(output_filename.to_string_lossy().as_ref())?;
+        let stage_start = Instant::now();
+        let fits_header = fits_header(&app_state, &context);
+        let caption = match app_state.output_caption {
+            true => Some(
+                ResultMetadata::new(
+                    &fits_header,
+                    context.frame_records.len(),
+                    &app_state.session_notes,
+                    Some(&output_filename),
+                )
+                .fill(&app_state.caption_template)
+                .categorize(|reason| ProcessError::SaveFailed {
+                    path: output_filename.clone(),
+                    reason,
+                })?,
+            ),
+            false => None,
+        };
+        partialfile::write_atomically(&output_filename, |path| {
+            save_output(
+                unnormalized_buffer.as_ref().unwrap_or(&corrected_buffer),
+                path,
+                app_state.output_color_profile,
+                &app_state.output_tiff,
+                app_state.output_jpeg_quality,
+                &fits_header,
+                caption.as_deref(),
+            )
+        })
+        .categorize(|reason| ProcessError::SaveFailed {
+            path: output_filename.clone(),
+            reason,
+        })?;
+        if !app_state.session_notes.trim().is_empty() {
+            save_session_notes(&output_filename, &app_state.session_notes)?;
+        }
+        summary
+            .stages
+            .record(&t!("report.stage_saving"), stage_start.elapsed());
+        if app_state.export_aligned_frames != AlignedFrameExport::None {
+            let stage_start = Instant::now();
+            alignedframes::export(
+                app_state.export_aligned_frames,
+                &output_filename,
+                &context,
+                &app_state,
+            )?;
+            summary
+                .stages
+                .record(&t!("report.stage_export"), stage_start.elapsed());
+        }
+
+        let resources = resource_monitor.finish();
+        info!(
+            "Resources used: peak memory {}, CPU time {}, read {}",
+            resources.peak_memory_text(),
+            resources.cpu_time_text(),
+            resources.bytes_read_text()
+        );
+        if let Err(why) = sidecar::write(
+            app_state.output_sidecar,
+            &output_filename,
+            &app_state,
+            &context.parameters,
+            &context.frame_records,
+            resources,
+            &summary.saved_masters,
+        ) {
+            warn!("Failed to write run metadata: {}", why);
+        }
+        if app_state.output_report {
+            let stacked_sigmas: Vec<f64> =
+                context.frame_records.iter().map(|fr| fr.sigma).collect();
+            let report = RunReport {
+                light: app_state.light.as_deref(),
+                output_filename: &output_filename,
+                image: &corrected_buffer,
+                parameters: &context.parameters,
+                frames_total: summary.frames_total,
+                analyzed_sigmas: &summary.analyzed_sigmas,
+                stacked_sigmas: &stacked_sigmas,
+                stages: &summary.stages,
+                elapsed: run_start.elapsed(),
+                resources,
+                warnings: &summary.warnings,
+            };
+            if let Err(why) = report.write() {
+                warn!("Failed to write processing report: {}", why);
+            }
+        }
+        if let Err(why) = history::record(
+            &app_state,
+            output_filename.clone(),
+            context.frame_records.len(),
+            run_start.elapsed(),
+            resources,
+        ) {
+            warn!("Failed to record run in history: {}", why);
+        }
 
         // The user will likely never see this actually appear on screen
         set_task_status(&t!("tasks.done"), 1, 1);
@@ -114,106 +513,464 @@ pub async fn run_async(
             image: Some(corrected_buffer),
             error: None,
             context: Some(context.parameters),
+            options: Some(app_state.clone()),
             output_filename: Some(output_filename.to_owned()),
             num_frames_used: context.frame_records.len(),
+            analysis_binning: app_state.analysis_binning,
+            color_profile: app_state.output_color_profile,
+            tiff_options: app_state.output_tiff,
+            jpeg_quality: app_state.output_jpeg_quality,
+            resources,
+            geometry: StackGeometry::new(&app_state, &context.frame_records),
+            fits_header,
+            saved_masters: summary.saved_masters,
+            caption,
+            frame_usage: frame_usage(&context.frame_records, summary.frames_total),
+            frame_rotations: context
+                .frame_records
+                .iter()
+                .map(|fr| (fr.frame_id, fr.computed_rotation.to_degrees()))
+                .collect(),
+            warnings: summary.warnings,
         })
     }
 }
 
-fn build_solhat_context<F>(app_state: &ApplicationState) -> Result<ProcessContext<F>>
-where
-    F: DataSource + Send + Sync + 'static,
-{
-    let params = app_state.to_parameters();
+/// Stacks the prepared frames with the alignment options of `app_state`, oriented as the frames
+/// are set to be
+fn stack_frames(
+    context: &ProcessContext<CorrectedSerFile>,
+    app_state: &ApplicationState,
+) -> Result<Image> {
+    if app_state.quality_weighting && !app_state.quality_weighted() {
+        warn!(
+            "Frames are weighted by quality only in average and sigma clipped stacks without \
+             alignment points"
+        );
+    }
+    if app_state.custom_drizzle() && !app_state.custom_drizzled() {
+        warn!(
+            "Custom drizzle scales and drop sizes apply only to average, sigma clipped and \
+             maximum stacks without alignment points. Using the nearest preset scale."
+        );
+    }
+    let stacked_buffer = if app_state.alignment_mode == AlignmentMode::AlignmentPoints {
+        info!("Stacking on alignment points");
+        alignpoints::stack_with_alignment_points(context, app_state)?
+    } else if app_state.algorithm.solhat_algorithm().is_none()
+        || app_state.quality_weighted()
+        || app_state.custom_drizzled()
+    {
+        info!("Stacking with {:?}", app_state.algorithm);
+        combine::stack_combined(context, app_state)?
+    } else if app_state.saturation_exclusion {
+        info!("Stacking with saturated pixels excluded");
+        saturation::stack_excluding_saturated(context, app_state)?
+    } else {
+        drizzle_stacking(context)?
+    };
+    Ok(if app_state.frame_orientation != FrameOrientation::Normal {
+        info!("Applying {:?}", app_state.frame_orientation);
+        imageutil::reorient_image(&stacked_buffer, app_state.frame_orientation)
+    } else {
+        stacked_buffer
+    })
+}
+
+/// Normalizes a stack to the 16 bit range, keeping the color balance unless the colors are set to
+/// be decorrelated
+fn normalize(image: &mut Image, app_state: &ApplicationState) {
+    if app_state.decorrelated_colors {
+        image.normalize_to_16bit_decorrelated();
+    } else {
+        image.normalize_to_16bit();
+    }
+}
+
+/// Stacks `prominence_light` with the same options and calibration masters as the surface
+/// exposure, so the two stacks share their geometry, and normalizes it for compositing
+fn stack_prominences(
+    app_state: &ApplicationState,
+    prominence_light: &str,
+    masters: &CalibrationMasters,
+) -> Result<Image> {
+    info!("Stacking prominence exposure {}", prominence_light);
+    let mut prominence_state = app_state.clone();
+    prominence_state.light = Some(prominence_light.to_owned());
+    prominence_state.frame_range = None;
+    let (context, _) = prepare_context(&prominence_state, masters)?;
+    if context.frame_records.is_empty() {
+        return Err(ProcessError::NoFramesAfterLimiting.into());
+    }
+    set_task_status(&t!("tasks.stacking_prominences"), 0, 0);
+    let mut stacked =
+        stack_frames(&context, &prominence_state).categorize(ProcessError::StackingFailed)?;
+    imageutil::replace_invalid_pixels(&mut stacked);
+    normalize(&mut stacked, app_state);
+    Ok(stacked)
+}
+
+/// Saves `image` as FITS or, tagged with `color_profile` and with any `caption` beneath it, as
+/// any other format, by the extension of `path`. FITS files and floating point TIFFs are left
+/// untagged and uncaptioned as data, as the profile's transfer curve and the caption's text both
+/// assume normalized 16-bit levels.
+fn save_output(
+    image: &Image,
+    path: &Path,
+    color_profile: ColorProfile,
+    tiff_options: &TiffOptions,
+    jpeg_quality: u8,
+    fits_header: &[FitsKeyword],
+    caption: Option<&str>,
+) -> Result<()> {
+    if fits::is_fits_file(path) {
+        fits::write_fits(image, path, fits_header)
+    } else if tiff_options.float && tiffwriter::is_tiff_file(path) {
+        if caption.is_some() || color_profile != ColorProfile::None {
+            warn!("Floating point TIFF output is saved without a caption or color profile");
+        }
+        tiffwriter::save_tiff(image, path, tiff_options, None)
+    } else if let Some(caption) = caption {
+        let captioned = caption::add_caption(image, caption);
+        colorprofile::save_with_profile(&captioned, path, color_profile, tiff_options, jpeg_quality)
+    } else {
+        colorprofile::save_with_profile(image, path, color_profile, tiff_options, jpeg_quality)
+    }
+}
+
+/// Capture times of the first and last stacked frames, if they can be read
+fn capture_time_range(
+    context: &ProcessContext<CorrectedSerFile>,
+) -> Option<(DateTime<Utc>, DateTime<Utc>)> {
+    let first = context.frame_records.iter().min_by_key(|fr| fr.frame_id)?;
+    let last = context.frame_records.iter().max_by_key(|fr| fr.frame_id)?;
+    let start = first.get_frame(context).ok()?.timestamp.to_chrono_utc();
+    let end = last.get_frame(context).ok()?.timestamp.to_chrono_utc();
+    Some((start.min(end), start.max(end)))
+}
+
+/// FITS header keywords describing the observation and how it was stacked
+fn fits_header(
+    app_state: &ApplicationState,
+    context: &ProcessContext<CorrectedSerFile>,
+) -> Vec<FitsKeyword> {
+    let text = FitsValue::Text;
+    let mut header = vec![];
+    if let Some((start, end)) = capture_time_range(context) {
+        let format = |t: DateTime<Utc>| t.format("%Y-%m-%dT%H:%M:%S%.3f").to_string();
+        header.push(FitsKeyword::new(
+            "DATE-OBS",
+            text(format(start)),
+            "UTC start of the stacked frames",
+        ));
+        header.push(FitsKeyword::new(
+            "DATE-END",
+            text(format(end)),
+            "UTC end of the stacked frames",
+        ));
+    }
+    if let Some(source) = context.fp_map.values().next() {
+        [
+            ("OBSERVER", source.observer(), "Observer"),
+            ("INSTRUME", source.instrument(), "Camera"),
+            ("TELESCOP", source.telescope(), "Telescope"),
+        ]
+        .into_iter()
+        .filter(|(_, value, _)| !value.trim().is_empty())
+        .for_each(|(keyword, value, comment)| {
+            header.push(FitsKeyword::new(
+                keyword,
+                text(value.trim().to_owned()),
+                comment,
+            ))
+        });
+    }
+    header.extend([
+        FitsKeyword::new("OBJECT", text(app_state.target_name()), "Observed target"),
+        FitsKeyword::new(
+            "SITELAT",
+            FitsValue::Real(app_state.obs_latitude),
+            "Observer latitude, degrees north",
+        ),
+        FitsKeyword::new(
+            "SITELONG",
+            FitsValue::Real(app_state.obs_longitude),
+            "Observer longitude, degrees east",
+        ),
+        FitsKeyword::new(
+            "STACKALG",
+            text(format!("{:?}", app_state.algorithm)),
+            "Stacking algorithm",
+        ),
+        FitsKeyword::new(
+            "DRIZZLE",
+            FitsValue::Real(app_state.drizzle_factor() as f64),
+            "Drizzle upscale factor",
+        ),
+        FitsKeyword::new(
+            "PIXFRAC",
+            FitsValue::Real(if app_state.custom_drizzled() {
+                app_state.drizzle_pixfrac as f64
+            } else {
+                1.0
+            }),
+            "Drizzle drop size, fraction of a pixel",
+        ),
+        FitsKeyword::new(
+            "NCOMBINE",
+            FitsValue::Integer(context.frame_records.len() as i64),
+            "Number of frames stacked",
+        ),
+        FitsKeyword::new(
+            "SWCREATE",
+            text(format!("SolHat {}", env!("CARGO_PKG_VERSION"))),
+            "Software that created the file",
+        ),
+    ]);
+    header
+}
+
+/// Writes the session notes to a text file alongside the output image, sharing its file stem
+fn save_session_notes(output_filename: &Path, notes: &str) -> Result<()> {
+    let notes_filename = output_filename.with_extension("txt");
+    partialfile::write_atomically(&notes_filename, |path| Ok(fs::write(path, notes)?)).map_err(
+        |why| ProcessError::SaveFailed {
+            path: notes_filename.clone(),
+            reason: why.to_string(),
+        },
+    )?;
+    info!("Saved session notes to {:?}", notes_filename);
+    Ok(())
+}
+
+/// Flags which of the `frames_total` frames in the light file are present in `frame_records`.
+fn frame_usage(frame_records: &[FrameRecord], frames_total: usize) -> Vec<bool> {
+    let mut usage = vec![false; frames_total];
+    frame_records.iter().for_each(|fr| {
+        if fr.frame_id < frames_total {
+            usage[fr.frame_id] = true;
+        }
+    });
+    usage
+}
+
+/// Saves a computed master to the masters folder, or the output folder if none is set, if the
+/// user asked for masters to be kept. Returns where the master was saved.
+fn save_master(
+    master: &CalibrationImage,
+    name: &str,
+    app_state: &ApplicationState,
+) -> Result<Option<PathBuf>, ProcessError> {
+    let (true, Some(image)) = (app_state.save_masters, &master.image) else {
+        return Ok(None);
+    };
+    let folder = app_state
+        .masters_dir
+        .clone()
+        .or_else(|| app_state.output_dir.clone())
+        .unwrap_or_default();
+    let path =
+        Path::new(&folder).join(format!("{}.{}", name, app_state.masters_format.extension()));
+    let saved = fs::create_dir_all(&folder)
+        .map_err(anyhow::Error::from)
+        .and_then(|_| {
+            partialfile::write_atomically(&path, |partial| match app_state.masters_format {
+                MasterFormat::Tiff => image.save(&partial.to_string_lossy()),
+                MasterFormat::Fits => fits::write_fits(
+                    image,
+                    partial,
+                    &[FitsKeyword::new(
+                        "IMAGETYP",
+                        FitsValue::Text(name.to_owned()),
+                        "Calibration master",
+                    )],
+                ),
+            })
+        });
+    saved.map_err(|why| ProcessError::SaveFailed {
+        path: path.clone(),
+        reason: why.to_string(),
+    })?;
+    info!("Saved {} to {:?}", name, path);
+    Ok(Some(path))
+}
+
+/// Applies bad line correction to a master so it matches the corrected light frames
+fn correct_master(master: &mut CalibrationImage, app_state: &ApplicationState) {
+    if app_state.bad_line_correction {
+        if let Some(image) = &mut master.image {
+            app_state.bad_lines.correct_image(image);
+        }
+    }
+}
+
+/// Calibration masters computed once per run and shared by every light stacked in it
+struct CalibrationMasters {
+    flat: CalibrationImage,
+    darkflat: CalibrationImage,
+    dark: CalibrationImage,
+    bias: CalibrationImage,
+    /// Paths of the masters saved
+    saved: Vec<PathBuf>,
+    /// Time taken computing and saving the masters
+    duration: Duration,
+}
+
+/// Computes the calibration masters, saving them if the user asked for masters to be kept
+fn build_masters(app_state: &ApplicationState) -> Result<CalibrationMasters> {
+    let start = Instant::now();
+    let mut saved_masters = vec![];
 
     set_task_status(&t!("tasks.processing_master_flat"), 0, 0);
-    let master_flat = if let Some(inputs) = &params.flat_inputs {
+    let flat_inputs = app_state.flat_inputs();
+    let mut master_flat = if !flat_inputs.is_empty() {
         info!("Processing master flat...");
-        CalibrationImage::new_from_file(inputs, ComputeMethod::Mean)?
+        calibration::compute_master(
+            &flat_inputs,
+            app_state.flat_method,
+            app_state.calibration_max_frames,
+            |n, total| set_task_status(&t!("tasks.processing_master_flat"), total, n),
+        )
+        .categorize(|reason| ProcessError::CalibrationFailed {
+            frame: "flat",
+            reason,
+        })?
     } else {
         CalibrationImage::new_empty()
     };
-    if app_state.save_masters {
-        if let Some(mstr) = &master_flat.image {
-            mstr.save(&format!(
-                "{}/master_flat.tif",
-                app_state.output_dir.clone().unwrap_or("".to_string())
-            ))?;
-        }
-    }
+    correct_master(&mut master_flat, app_state);
+    saved_masters.extend(save_master(&master_flat, "master_flat", app_state)?);
 
     check_cancel_status()?;
 
     set_task_status(&t!("tasks.processing_master_dark_flat"), 0, 0);
-    let master_darkflat = if let Some(inputs) = &params.darkflat_inputs {
+    let darkflat_inputs = app_state.darkflat_inputs();
+    let mut master_darkflat = if !darkflat_inputs.is_empty() {
         info!("Processing master dark flat...");
-        CalibrationImage::new_from_file(inputs, ComputeMethod::Mean)?
+        calibration::compute_master(
+            &darkflat_inputs,
+            app_state.darkflat_method,
+            app_state.calibration_max_frames,
+            |n, total| set_task_status(&t!("tasks.processing_master_dark_flat"), total, n),
+        )
+        .categorize(|reason| ProcessError::CalibrationFailed {
+            frame: "dark flat",
+            reason,
+        })?
     } else {
         CalibrationImage::new_empty()
     };
+    correct_master(&mut master_darkflat, app_state);
 
-    if app_state.save_masters {
-        if let Some(mstr) = &master_darkflat.image {
-            mstr.save(&format!(
-                "{}/master_darkflat.tif",
-                app_state.output_dir.clone().unwrap_or("".to_string())
-            ))?;
-        }
-    }
+    saved_masters.extend(save_master(&master_darkflat, "master_darkflat", app_state)?);
 
     check_cancel_status()?;
 
     set_task_status(&t!("tasks.processing_master_dark"), 0, 0);
-    let master_dark = if let Some(inputs) = &params.dark_inputs {
+    let dark_inputs = app_state.dark_inputs();
+    let mut master_dark = if !dark_inputs.is_empty() {
         info!("Processing master dark...");
-        CalibrationImage::new_from_file(inputs, ComputeMethod::Mean)?
+        calibration::compute_master(
+            &dark_inputs,
+            app_state.dark_method,
+            app_state.calibration_max_frames,
+            |n, total| set_task_status(&t!("tasks.processing_master_dark"), total, n),
+        )
+        .categorize(|reason| ProcessError::CalibrationFailed {
+            frame: "dark",
+            reason,
+        })?
     } else {
         CalibrationImage::new_empty()
     };
+    correct_master(&mut master_dark, app_state);
 
-    if app_state.save_masters {
-        if let Some(mstr) = &master_dark.image {
-            mstr.save(&format!(
-                "{}/master_dark.tif",
-                app_state.output_dir.clone().unwrap_or("".to_string())
-            ))?;
-        }
-    }
+    saved_masters.extend(save_master(&master_dark, "master_dark", app_state)?);
 
     check_cancel_status()?;
 
     set_task_status(&t!("tasks.processing_master_bias"), 0, 0);
-    let master_bias = if let Some(inputs) = &params.bias_inputs {
+    let bias_inputs = app_state.bias_inputs();
+    let mut master_bias = if !bias_inputs.is_empty() {
         info!("Processing master bias...");
-        CalibrationImage::new_from_file(inputs, ComputeMethod::Mean)?
+        calibration::compute_master(
+            &bias_inputs,
+            app_state.bias_method,
+            app_state.calibration_max_frames,
+            |n, total| set_task_status(&t!("tasks.processing_master_bias"), total, n),
+        )
+        .categorize(|reason| ProcessError::CalibrationFailed {
+            frame: "bias",
+            reason,
+        })?
     } else {
         CalibrationImage::new_empty()
     };
+    correct_master(&mut master_bias, app_state);
 
-    if app_state.save_masters {
-        if let Some(mstr) = &master_bias.image {
-            mstr.save(&format!(
-                "{}/master_bias.tif",
-                app_state.output_dir.clone().unwrap_or("".to_string())
-            ))?;
-        }
-    }
+    saved_masters.extend(save_master(&master_bias, "master_bias", app_state)?);
 
     check_cancel_status()?;
 
+    Ok(CalibrationMasters {
+        flat: master_flat,
+        darkflat: master_darkflat,
+        dark: master_dark,
+        bias: master_bias,
+        saved: saved_masters,
+        duration: start.elapsed(),
+    })
+}
+
+/// Creates the process context with copies of `masters`, scaling the master dark to the light
+fn build_solhat_context<F>(
+    app_state: &ApplicationState,
+    masters: &CalibrationMasters,
+) -> Result<ProcessContext<F>>
+where
+    F: DataSource + Send + Sync + 'static,
+{
+    let params = app_state.to_parameters();
+    let copy = |master: &CalibrationImage| CalibrationImage {
+        image: master.image.clone(),
+    };
+    let mut master_dark = copy(&masters.dark);
+    if master_dark.image.is_some() {
+        let factor = match app_state.dark_scale_mode {
+            DarkScaleMode::Off => None,
+            DarkScaleMode::Manual => Some(app_state.dark_scale_factor),
+            DarkScaleMode::Automatic => match &app_state.light {
+                Some(light) => Some(darkscale::optimize_dark_scale(
+                    light,
+                    &master_dark,
+                    &masters.bias,
+                )?),
+                None => None,
+            },
+        };
+        if let Some(factor) = factor {
+            info!("Scaling the master dark by {:.3}", factor);
+            darkscale::scale_master_dark(&mut master_dark, &masters.bias, factor);
+        }
+    }
+
     info!("Creating process context struct");
     let context = ProcessContext::create_with_calibration_frames(
         &params,
-        master_flat,
-        master_darkflat,
+        copy(&masters.flat),
+        copy(&masters.darkflat),
         master_dark,
-        master_bias,
-    )?;
+        copy(&masters.bias),
+    )
+    .categorize(ProcessError::OpenLightFailed)?;
 
     Ok(context)
 }
 
-fn frame_sigma_analysis<F>(context: &ProcessContext<F>) -> Result<Vec<FrameRecord>>
+fn frame_sigma_analysis<F>(
+    context: &ProcessContext<F>,
+    options: &AnalysisOptions,
+) -> Result<Vec<FrameRecord>>
 where
     F: DataSource + Send + Sync + 'static,
 {
@@ -224,22 +981,28 @@ where
     set_task_status(&t!("tasks.frame_analysis"), frame_count, 0);
 
     let counter = Arc::new(Mutex::new(0));
+    // Frames are processed on the rayon pool, outside of the job's task
+    let slot = current_slot();
 
-    let frame_records = frame_analysis_window_size(
-        context,
-        context.parameters.analysis_window_size,
-        move |fr| {
-            info!(
-                "frame_sigma_analysis(): Frame processed with sigma {}",
-                fr.sigma
-            );
-            // check_cancel_status(&sender);
+    if options.binning > 1 {
+        info!(
+            "Frame analysis will be performed on {}x{} binned frames",
+            options.binning, options.binning
+        );
+    }
 
-            let mut c = counter.lock().unwrap();
-            *c += 1;
-            set_task_status(&t!("tasks.frame_analysis"), frame_count, *c)
-        },
-    )?;
+    let frame_records = frame_analysis_window_size(context, options, move |fr| {
+        info!(
+            "frame_sigma_analysis(): Frame processed with sigma {}",
+            fr.sigma
+        );
+        // check_cancel_status(&sender);
+        wait_while_slot_paused(slot);
+
+        let mut c = counter.lock().unwrap();
+        *c += 1;
+        set_slot_task_status(slot, &t!("tasks.frame_analysis"), frame_count, *c)
+    })?;
 
     Ok(frame_records)
 }
@@ -255,14 +1018,17 @@ where
     set_task_status(&t!("tasks.frame_limits"), frame_count, 0);
 
     let counter = Arc::new(Mutex::new(0));
+    // Frames are processed on the rayon pool, outside of the job's task
+    let slot = current_slot();
 
     let frame_records = frame_limit_determinate(context, move |_fr| {
         info!("frame_limit_determinate(): Frame processed.");
         // check_cancel_status(&sender);
+        wait_while_slot_paused(slot);
 
         let mut c = counter.lock().unwrap();
         *c += 1;
-        set_task_status(&t!("tasks.frame_limits"), frame_count, *c)
+        set_slot_task_status(slot, &t!("tasks.frame_limits"), frame_count, *c)
     })?;
 
     Ok(frame_records)
@@ -279,6 +1045,8 @@ where
     set_task_status(&t!("tasks.parallactic_angle"), frame_count, 0);
 
     let counter = Arc::new(Mutex::new(0));
+    // Frames are processed on the rayon pool, outside of the job's task
+    let slot = current_slot();
 
     let frame_records = frame_rotation_analysis(context, move |fr| {
         info!(
@@ -286,10 +1054,11 @@ where
             fr.computed_rotation.to_degrees()
         );
         // check_cancel_status(&sender);
+        wait_while_slot_paused(slot);
 
         let mut c = counter.lock().unwrap();
         *c += 1;
-        set_task_status(&t!("tasks.parallactic_angle"), frame_count, *c)
+        set_slot_task_status(slot, &t!("tasks.parallactic_angle"), frame_count, *c)
     })?;
 
     Ok(frame_records)
@@ -306,14 +1075,17 @@ where
     set_task_status(&t!("tasks.stacking"), frame_count, 0);
 
     let counter = Arc::new(Mutex::new(0));
+    // Frames are processed on the rayon pool, outside of the job's task
+    let slot = current_slot();
 
     // TODO: Implement cancel detection within solhat core.
     process_frame_stacking(context, move |_fr| {
         info!("process_frame_stacking(): Frame processed.");
         // check_cancel_status(&sender);
+        wait_while_slot_paused(slot);
 
         let mut c = counter.lock().unwrap();
         *c += 1;
-        set_task_status(&t!("tasks.stacking"), frame_count, *c)
+        set_slot_task_status(slot, &t!("tasks.stacking"), frame_count, *c)
     })
 }
@@ -0,0 +1,317 @@
+//! HTML report of a processing run, written next to the output so a run can be reviewed or
+//! shared without opening SolHat. The report is a single file, with its thumbnail embedded.
+
+use std::fmt::Write;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::Result;
+use base64::Engine;
+use itertools::iproduct;
+use sciimg::prelude::{Image, ImageMode};
+use solhat::context::ProcessParameters;
+
+use crate::imageutil;
+use crate::partialfile;
+use crate::process::sidecar::ParametersRecord;
+use crate::resources::ResourceUsage;
+
+/// Longest side, in pixels, of the thumbnail of the output
+const THUMBNAIL_SIZE: usize = 640;
+
+/// Bins of the sigma distribution chart
+const SIGMA_BINS: usize = 40;
+
+const CHART_WIDTH: f64 = 640.0;
+const CHART_HEIGHT: f64 = 200.0;
+
+/// Time taken by each stage of a run, in the order they ran
+#[derive(Debug, Clone, Default)]
+pub struct StageTimings {
+    stages: Vec<(String, Duration)>,
+}
+
+impl StageTimings {
+    pub fn record(&mut self, stage: &str, duration: Duration) {
+        self.stages.push((stage.to_owned(), duration));
+    }
+}
+
+/// What went into, and came out of, a run
+pub struct RunReport<'a> {
+    pub light: Option<&'a str>,
+    pub output_filename: &'a Path,
+    /// The normalized output image
+    pub image: &'a Image,
+    pub parameters: &'a ProcessParameters,
+    pub frames_total: usize,
+    /// Quality of every frame analyzed
+    pub analyzed_sigmas: &'a [f64],
+    /// Quality of the frames stacked
+    pub stacked_sigmas: &'a [f64],
+    pub stages: &'a StageTimings,
+    pub elapsed: Duration,
+    pub resources: ResourceUsage,
+    pub warnings: &'a [String],
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn format_duration(duration: Duration) -> String {
+    format!("{:.1} s", duration.as_secs_f64())
+}
+
+/// The output reduced to fit the thumbnail size, as a PNG data URI
+fn thumbnail_uri(image: &Image) -> Result<String> {
+    let factor = image
+        .width
+        .max(image.height)
+        .div_ceil(THUMBNAIL_SIZE)
+        .max(1);
+    let thumbnail = imageutil::downscale_image(image, factor);
+    let color_type = match thumbnail.num_bands() {
+        1 => image::ExtendedColorType::L8,
+        _ => image::ExtendedColorType::Rgb8,
+    };
+    let bands = thumbnail.num_bands().min(3);
+    let scale = if thumbnail.get_mode() == ImageMode::U8BIT {
+        1.0
+    } else {
+        1.0 / 257.0
+    };
+    let samples: Vec<u8> = iproduct!(0..thumbnail.height, 0..thumbnail.width, 0..bands)
+        .map(|(y, x, b)| {
+            (thumbnail.get_band(b).get(x, y) * scale)
+                .round()
+                .clamp(0.0, 255.0) as u8
+        })
+        .collect();
+    let mut png = vec![];
+    image::ImageEncoder::write_image(
+        image::codecs::png::PngEncoder::new(&mut png),
+        &samples,
+        thumbnail.width as u32,
+        thumbnail.height as u32,
+        color_type,
+    )?;
+    Ok(format!(
+        "data:image/png;base64,{}",
+        base64::engine::general_purpose::STANDARD.encode(png)
+    ))
+}
+
+/// Histogram of `analyzed` sigmas as an SVG bar chart, with the share of each bin which was
+/// stacked drawn over it
+fn sigma_chart(analyzed: &[f64], stacked: &[f64]) -> String {
+    let finite = || analyzed.iter().copied().filter(|s| s.is_finite());
+    let (Some(min), Some(max)) = (finite().reduce(f64::min), finite().reduce(f64::max)) else {
+        return format!("<p>{}</p>", escape(&t!("report.no_sigmas")));
+    };
+    let range = (max - min).max(f64::EPSILON);
+    let bin = |s: f64| (((s - min) / range * SIGMA_BINS as f64) as usize).min(SIGMA_BINS - 1);
+    let count = |sigmas: &[f64]| {
+        let mut bins = vec![0_usize; SIGMA_BINS];
+        sigmas
+            .iter()
+            .filter(|s| s.is_finite() && (min..=max).contains(*s))
+            .for_each(|s| bins[bin(*s)] += 1);
+        bins
+    };
+    let analyzed_bins = count(analyzed);
+    let stacked_bins = count(stacked);
+    let peak = analyzed_bins.iter().copied().max().unwrap_or(1).max(1) as f64;
+    let bar_width = CHART_WIDTH / SIGMA_BINS as f64;
+
+    let mut svg = format!(
+        "<svg width=\"{w}\" height=\"{h}\" viewBox=\"0 0 {w} {h}\" role=\"img\">",
+        w = CHART_WIDTH,
+        h = CHART_HEIGHT + 20.0
+    );
+    analyzed_bins
+        .iter()
+        .zip(stacked_bins.iter())
+        .enumerate()
+        .for_each(|(i, (analyzed, stacked))| {
+            let x = i as f64 * bar_width;
+            [(analyzed, "analyzed"), (stacked, "stacked")]
+                .iter()
+                .for_each(|(n, class)| {
+                    let height = **n as f64 / peak * CHART_HEIGHT;
+                    let _ = write!(
+                        svg,
+                        "<rect class=\"{}\" x=\"{:.1}\" y=\"{:.1}\" width=\"{:.1}\" height=\"{:.1}\"/>",
+                        class,
+                        x + 1.0,
+                        CHART_HEIGHT - height,
+                        bar_width - 2.0,
+                        height
+                    );
+                });
+        });
+    let _ = write!(
+        svg,
+        "<text x=\"0\" y=\"{y}\">{:.3}</text><text x=\"{w}\" y=\"{y}\" text-anchor=\"end\">{:.3}</text></svg>",
+        min,
+        max,
+        y = CHART_HEIGHT + 16.0,
+        w = CHART_WIDTH
+    );
+    svg
+}
+
+/// Rows of the parameter table, named as in the metadata file
+fn parameter_rows(parameters: &ProcessParameters) -> Result<String> {
+    let value = serde_json::to_value(ParametersRecord::from(parameters))?;
+    let mut rows = String::new();
+    if let Some(fields) = value.as_object() {
+        fields.iter().for_each(|(name, value)| {
+            let value = match value {
+                serde_json::Value::Null => "-".to_owned(),
+                serde_json::Value::String(s) => s.clone(),
+                other => other.to_string(),
+            };
+            let _ = write!(
+                rows,
+                "<tr><th>{}</th><td>{}</td></tr>",
+                escape(name),
+                escape(&value)
+            );
+        });
+    }
+    Ok(rows)
+}
+
+/// Path of the report written for `output_filename`
+pub fn report_path(output_filename: &Path) -> PathBuf {
+    let stem = output_filename
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_default();
+    output_filename.with_file_name(format!("{}_report.html", stem))
+}
+
+impl RunReport<'_> {
+    fn to_html(&self) -> Result<String> {
+        let title = t!(
+            "report.title",
+            name = self
+                .light
+                .and_then(|l| Path::new(l).file_name())
+                .map(|f| f.to_string_lossy().to_string())
+                .unwrap_or_default()
+        );
+        let frames_analyzed = self.analyzed_sigmas.len();
+        let frames_stacked = self.stacked_sigmas.len();
+
+        let mut html = String::new();
+        let _ = write!(
+            html,
+            "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>{title}</title><style>\
+             body{{font-family:sans-serif;margin:2em;color:#222}}\
+             table{{border-collapse:collapse;margin-bottom:1.5em}}\
+             th,td{{text-align:left;padding:3px 12px;border-bottom:1px solid #ddd}}\
+             img{{max-width:100%;border:1px solid #ccc}}\
+             rect.analyzed{{fill:#c8c8c8}}rect.stacked{{fill:#3572b0}}\
+             .warning{{color:#a05a00}}\
+             </style></head><body><h1>{title}</h1>",
+            title = escape(&title)
+        );
+        let _ = write!(
+            html,
+            "<p>{}<br>{}</p>",
+            escape(&t!(
+                "report.created",
+                time = chrono::Local::now().format("%Y-%m-%d %H:%M:%S")
+            )),
+            escape(&t!("report.output", path = self.output_filename.display()))
+        );
+        let _ = write!(
+            html,
+            "<img src=\"{}\" alt=\"{}\">",
+            thumbnail_uri(self.image)?,
+            escape(&t!("report.thumbnail"))
+        );
+        self.warnings.iter().for_each(|warning| {
+            let _ = write!(html, "<p class=\"warning\">{}</p>", escape(warning));
+        });
+
+        let _ = write!(
+            html,
+            "<h2>{}</h2><table>\
+             <tr><th>{}</th><td>{}</td></tr>\
+             <tr><th>{}</th><td>{}</td></tr>\
+             <tr><th>{}</th><td>{}</td></tr>\
+             <tr><th>{}</th><td>{}</td></tr></table>",
+            escape(&t!("report.frames")),
+            escape(&t!("report.frames_total")),
+            self.frames_total,
+            escape(&t!("report.frames_analyzed")),
+            frames_analyzed,
+            escape(&t!("report.frames_accepted")),
+            frames_stacked,
+            escape(&t!("report.frames_rejected")),
+            frames_analyzed.saturating_sub(frames_stacked),
+        );
+
+        let _ = write!(
+            html,
+            "<h2>{}</h2><p>{}</p>{}",
+            escape(&t!("report.sigma_distribution")),
+            escape(&t!("report.sigma_distribution_hint")),
+            sigma_chart(self.analyzed_sigmas, self.stacked_sigmas)
+        );
+
+        let _ = write!(html, "<h2>{}</h2><table>", escape(&t!("report.timing")));
+        self.stages.stages.iter().for_each(|(stage, duration)| {
+            let _ = write!(
+                html,
+                "<tr><th>{}</th><td>{}</td></tr>",
+                escape(stage),
+                format_duration(*duration)
+            );
+        });
+        let _ = write!(
+            html,
+            "<tr><th>{}</th><td>{}</td></tr>\
+             <tr><th>{}</th><td>{}</td></tr>\
+             <tr><th>{}</th><td>{}</td></tr>\
+             <tr><th>{}</th><td>{}</td></tr></table>",
+            escape(&t!("report.total")),
+            format_duration(self.elapsed),
+            escape(&t!("history.peak_memory")),
+            self.resources.peak_memory_text(),
+            escape(&t!("history.cpu_time")),
+            self.resources.cpu_time_text(),
+            escape(&t!("history.bytes_read")),
+            self.resources.bytes_read_text(),
+        );
+
+        let _ = write!(
+            html,
+            "<h2>{}</h2><table>{}</table>",
+            escape(&t!("report.parameters")),
+            parameter_rows(self.parameters)?
+        );
+        let _ = writeln!(
+            html,
+            "<p>SolHat {}</p></body></html>",
+            env!("CARGO_PKG_VERSION")
+        );
+        Ok(html)
+    }
+
+    /// Writes the report next to the output, returning where it was written
+    pub fn write(&self) -> Result<PathBuf> {
+        let html = self.to_html()?;
+        let path = report_path(self.output_filename);
+        partialfile::write_atomically(&path, |partial| Ok(fs::write(partial, &html)?))?;
+        info!("Saved processing report to {:?}", path);
+        Ok(path)
+    }
+}
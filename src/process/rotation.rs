@@ -0,0 +1,94 @@
+use std::sync::{Arc, Mutex};
+
+use anyhow::Result;
+use rayon::prelude::*;
+use solhat::context::ProcessContext;
+use solhat::datasource::DataSource;
+use solhat::framerecord::FrameRecord;
+
+use crate::cancel::*;
+use crate::state::RotationModel;
+use crate::taskstatus::*;
+
+/// Computes per-frame rotation from a user supplied model rather than the target ephemeris.
+/// Rotation is relative to the earliest frame in `context.frame_records`.
+///
+/// `value` is the rotation rate in degrees per minute for `RotationModel::RatePerMinute`, or the
+/// rotation increment in degrees between consecutive captured frames for
+/// `RotationModel::FixedPerFrame`.
+pub fn manual_rotation<F>(
+    context: &ProcessContext<F>,
+    model: RotationModel,
+    value: f64,
+) -> Result<Vec<FrameRecord>>
+where
+    F: DataSource + Send + Sync + 'static,
+{
+    let first_frame_id = context
+        .frame_records
+        .iter()
+        .map(|fr| fr.frame_id)
+        .min()
+        .unwrap_or(0);
+
+    match model {
+        RotationModel::Ephemeris => Ok(context.frame_records.clone()),
+        RotationModel::FixedPerFrame => Ok(context
+            .frame_records
+            .iter()
+            .map(|fr| {
+                let mut fr_copy = fr.clone();
+                fr_copy.computed_rotation =
+                    ((fr.frame_id - first_frame_id) as f64 * value).to_radians();
+                fr_copy
+            })
+            .collect()),
+        RotationModel::RatePerMinute => rotation_from_timestamps(context, first_frame_id, value),
+    }
+}
+
+fn rotation_from_timestamps<F>(
+    context: &ProcessContext<F>,
+    first_frame_id: usize,
+    degrees_per_minute: f64,
+) -> Result<Vec<FrameRecord>>
+where
+    F: DataSource + Send + Sync + 'static,
+{
+    let first_frame = context
+        .frame_records
+        .iter()
+        .find(|fr| fr.frame_id == first_frame_id);
+    let start_time = match first_frame {
+        Some(fr) => fr.get_frame(context)?.timestamp.to_chrono_utc(),
+        None => return Ok(vec![]),
+    };
+
+    let frame_count = context.frame_records.len();
+    set_task_status(&t!("tasks.parallactic_angle"), frame_count, 0);
+    let counter = Arc::new(Mutex::new(0));
+    // Frames are processed on the rayon pool, outside of the job's task
+    let slot = current_slot();
+
+    context
+        .frame_records
+        .par_iter()
+        .map(|fr| {
+            check_slot_cancel_status(slot)?;
+            let mut fr_copy = fr.clone();
+            let timestamp = fr.get_frame(context)?.timestamp.to_chrono_utc();
+            let minutes = (timestamp - start_time).num_milliseconds() as f64 / 60000.0;
+            fr_copy.computed_rotation = (minutes * degrees_per_minute).to_radians();
+            info!(
+                "Manual rotation for frame {} is {} degrees",
+                fr.frame_id,
+                fr_copy.computed_rotation.to_degrees()
+            );
+
+            let mut c = counter.lock().unwrap();
+            *c += 1;
+            set_slot_task_status(slot, &t!("tasks.parallactic_angle"), frame_count, *c);
+            Ok(fr_copy)
+        })
+        .collect()
+}
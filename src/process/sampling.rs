@@ -0,0 +1,54 @@
+use rand::rngs::StdRng;
+use rand::seq::index;
+use rand::SeedableRng;
+use solhat::framerecord::FrameRecord;
+
+use crate::state::FrameSampling;
+
+/// Reduces `frame_records` to at most `max_frames` entries using the requested strategy. The
+/// relative order of the retained frames is preserved.
+pub fn sample_frames(
+    frame_records: &[FrameRecord],
+    max_frames: usize,
+    strategy: FrameSampling,
+    seed: u64,
+) -> Vec<FrameRecord> {
+    if frame_records.len() <= max_frames {
+        return frame_records.to_vec();
+    }
+
+    let mut indices: Vec<usize> = match strategy {
+        FrameSampling::Truncate => (0..max_frames).collect(),
+        FrameSampling::EvenlySpaced => {
+            let step = frame_records.len() as f64 / max_frames as f64;
+            (0..max_frames)
+                .map(|i| (i as f64 * step).floor() as usize)
+                .collect()
+        }
+        FrameSampling::BestN => {
+            let mut ranked: Vec<usize> = (0..frame_records.len()).collect();
+            // Frames whose quality couldn't be computed rank last
+            let sigma = |i: &usize| {
+                let sigma = frame_records[*i].sigma;
+                if sigma.is_finite() {
+                    sigma
+                } else {
+                    f64::NEG_INFINITY
+                }
+            };
+            ranked.sort_by(|a, b| sigma(b).total_cmp(&sigma(a)));
+            ranked.truncate(max_frames);
+            ranked
+        }
+        FrameSampling::Random => {
+            let mut rng = StdRng::seed_from_u64(seed);
+            index::sample(&mut rng, frame_records.len(), max_frames).into_vec()
+        }
+    };
+
+    indices.sort();
+    indices
+        .into_iter()
+        .map(|i| frame_records[i].clone())
+        .collect()
+}
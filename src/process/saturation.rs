@@ -0,0 +1,184 @@
+use anyhow::{Error, Result};
+use itertools::iproduct;
+use rayon::prelude::*;
+use sciimg::prelude::{Image, ImageMode};
+use solhat::context::ProcessContext;
+use solhat::datasource::DataSource;
+
+use crate::cancel::*;
+use crate::process::error::ProcessError;
+use crate::process::framesource::FrameSource;
+use crate::state::ApplicationState;
+use crate::taskstatus::*;
+
+/// Per-pixel sums and sample counts of one band of the output image, of the unsaturated samples
+/// and of all of them
+#[derive(Clone)]
+struct BandSums {
+    sums: Vec<f32>,
+    counts: Vec<u32>,
+    all_sums: Vec<f32>,
+    all_counts: Vec<u32>,
+}
+
+impl BandSums {
+    fn new(len: usize) -> Self {
+        BandSums {
+            sums: vec![0.0; len],
+            counts: vec![0; len],
+            all_sums: vec![0.0; len],
+            all_counts: vec![0; len],
+        }
+    }
+
+    fn add(&mut self, i: usize, value: f32, saturated: bool) {
+        self.all_sums[i] += value;
+        self.all_counts[i] += 1;
+        if !saturated {
+            self.sums[i] += value;
+            self.counts[i] += 1;
+        }
+    }
+
+    /// Mean of the unsaturated samples at pixel `i`, or of all of them if it was saturated in
+    /// every frame, so it isn't left black
+    fn mean(&self, i: usize) -> Option<f32> {
+        if self.counts[i] > 0 {
+            Some(self.sums[i] / self.counts[i] as f32)
+        } else if self.all_counts[i] > 0 {
+            Some(self.all_sums[i] / self.all_counts[i] as f32)
+        } else {
+            None
+        }
+    }
+}
+
+/// Sums for each band of the output image
+struct Accumulator {
+    width: usize,
+    height: usize,
+    bands: Vec<BandSums>,
+}
+
+impl Accumulator {
+    fn new(width: usize, height: usize, num_bands: usize) -> Self {
+        Accumulator {
+            width,
+            height,
+            bands: vec![BandSums::new(width * height); num_bands],
+        }
+    }
+
+    fn into_image(self) -> Result<Image> {
+        let mut image =
+            Image::new_with_bands(self.width, self.height, self.bands.len(), ImageMode::U16BIT)?;
+        self.bands.iter().enumerate().for_each(|(b, band)| {
+            iproduct!(0..self.height, 0..self.width).for_each(|(y, x)| {
+                if let Some(mean) = band.mean(y * self.width + x) {
+                    image.put(x, y, mean, b);
+                }
+            });
+        });
+        Ok(image)
+    }
+}
+
+/// Raw pixel level at or above which a pixel of `pixel_depth` bits is saturated
+pub fn saturation_level(pixel_depth: usize, app_state: &ApplicationState) -> f32 {
+    let full_scale = ((1_u64 << pixel_depth) - 1) as f32;
+    full_scale * app_state.saturation_threshold as f32
+}
+
+/// True if any of the pixels of `band` of the raw frame contributing to a bilinear sample at
+/// (`x`, `y`) are at or above `saturation`
+pub fn saturated_near(raw: &Image, band: usize, x: f32, y: f32, saturation: f32) -> bool {
+    if x < 0.0 || y < 0.0 {
+        return false;
+    }
+    let (x0, y0) = (x.floor() as usize, y.floor() as usize);
+    let raw_band = raw.get_band(band);
+    iproduct!(y0..(y0 + 2).min(raw.height), x0..(x0 + 2).min(raw.width))
+        .any(|(py, px)| raw_band.get(px, py) >= saturation)
+}
+
+/// Bilinear sample of `band` at (`x`, `y`), or None outside the frame
+fn sample(calibrated: &Image, band: usize, x: f32, y: f32) -> Option<f32> {
+    if x < 0.0 || y < 0.0 {
+        return None;
+    }
+    let (x0, y0) = (x.floor() as usize, y.floor() as usize);
+    let (x1, y1) = (x0 + 1, y0 + 1);
+    if x1 >= calibrated.width || y1 >= calibrated.height {
+        return None;
+    }
+    let band = calibrated.get_band(band);
+    let (fx, fy) = (x - x0 as f32, y - y0 as f32);
+    let top = band.get(x0, y0) * (1.0 - fx) + band.get(x1, y0) * fx;
+    let bottom = band.get(x0, y1) * (1.0 - fx) + band.get(x1, y1) * fx;
+    Some(top * (1.0 - fy) + bottom * fy)
+}
+
+/// Average stacking which leaves saturated pixels out of the accumulation, so they don't drag
+/// down the detail around them. Each output pixel is the mean of only the frames in which it
+/// wasn't saturated, or of every frame if it was saturated in all of them.
+///
+/// Frames are aligned on their center of mass and derotated by their computed rotation, then
+/// resampled onto the drizzled output grid. Saturation is judged on the raw, uncalibrated frame
+/// against `saturation_threshold` of the sensor's full scale.
+pub fn stack_excluding_saturated<F>(
+    context: &ProcessContext<F>,
+    app_state: &ApplicationState,
+) -> Result<Image>
+where
+    F: DataSource + Send + Sync + 'static,
+{
+    let light = app_state
+        .light
+        .as_ref()
+        .ok_or(ProcessError::MissingInput("light"))?;
+    let raw_source = FrameSource::open(&[light.to_owned()])?;
+    let saturation = saturation_level(raw_source.pixel_depth(), app_state);
+
+    let frame_size = [raw_source.image_width(), raw_source.image_height()];
+    let [out_width, out_height] = app_state.output_dimensions(frame_size);
+    let scale = app_state.drizzle_factor();
+
+    let frame_count = context.frame_records.len();
+    set_task_status(&t!("tasks.stacking"), frame_count, 0);
+
+    let mut accumulator: Option<Accumulator> = None;
+    for (n, fr) in context.frame_records.iter().enumerate() {
+        check_cancel_status()?;
+        let calibrated = fr.get_frame(context)?.buffer;
+        let raw = raw_source.get_frame(fr.frame_id)?.buffer;
+        let acc = accumulator
+            .get_or_insert_with(|| Accumulator::new(out_width, out_height, calibrated.num_bands()));
+
+        // Output pixels map back to the frame around its center of mass, shifted by the user
+        // offsets, rotated by the frame's computed rotation.
+        let center_x =
+            (calibrated.width / 2) as f32 + fr.offset.h + context.parameters.horiz_offset as f32;
+        let center_y =
+            (calibrated.height / 2) as f32 + fr.offset.v + context.parameters.vert_offset as f32;
+        let (sin, cos) = (fr.computed_rotation as f32).sin_cos();
+
+        acc.bands.par_iter_mut().enumerate().for_each(|(b, band)| {
+            iproduct!(0..out_height, 0..out_width).for_each(|(y, x)| {
+                let dx = (x as f32 - out_width as f32 / 2.0) / scale;
+                let dy = (y as f32 - out_height as f32 / 2.0) / scale;
+                let src_x = center_x + dx * cos - dy * sin;
+                let src_y = center_y + dx * sin + dy * cos;
+                if let Some(v) = sample(&calibrated, b, src_x, src_y) {
+                    let saturated = saturated_near(&raw, b, src_x, src_y, saturation);
+                    band.add(y * out_width + x, v, saturated);
+                }
+            });
+        });
+
+        set_task_status(&t!("tasks.stacking"), frame_count, n + 1);
+    }
+
+    accumulator
+        .ok_or_else(|| Error::msg("Zero frames to stack. Cannot continue"))?
+        .into_image()
+}
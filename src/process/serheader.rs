@@ -0,0 +1,376 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use anyhow::{anyhow, Result};
+use sciimg::prelude::{Image, ImageMode};
+use solhat::datasource::{DataFrame, DataSource};
+use solhat::timestamp::TimeStamp;
+
+/// Length of the SER header
+pub const HEADER_SIZE: u64 = 178;
+
+/// Color IDs of files holding full color frames, with three values per pixel
+pub const COLOR_ID_RGB: i32 = 100;
+const COLOR_ID_BGR: i32 = 101;
+
+lazy_static! {
+    // Header overrides by SER path, for this session only. Keyed by path as files are opened
+    // through `DataSource::open`, which takes nothing but the file names.
+    static ref HEADER_OVERRIDES: Arc<Mutex<HashMap<String, HeaderOverride>>> =
+        Arc::new(Mutex::new(HashMap::new()));
+}
+
+fn text_field(bytes: &[u8]) -> String {
+    String::from_utf8_lossy(bytes)
+        .trim_end_matches('\0')
+        .trim()
+        .to_owned()
+}
+
+/// The fields of a SER header as they're written in the file
+#[derive(Debug, Clone, PartialEq)]
+pub struct SerHeader {
+    pub file_id: String,
+    pub lu_id: i32,
+    pub color_id: i32,
+    /// Raw value of the endianness field. Most capture software writes 0 for little endian data,
+    /// the opposite of the specification.
+    pub little_endian: i32,
+    pub width: usize,
+    pub height: usize,
+    pub pixel_depth: usize,
+    pub frame_count: usize,
+    pub observer: String,
+    pub instrument: String,
+    pub telescope: String,
+    pub date_time: u64,
+    pub date_time_utc: u64,
+    pub file_size: u64,
+}
+
+impl SerHeader {
+    pub fn read(path: &Path) -> Result<SerHeader> {
+        let mut bytes = [0_u8; HEADER_SIZE as usize];
+        let mut file = File::open(path)?;
+        file.read_exact(&mut bytes)
+            .map_err(|_| anyhow!("{} is too short to be a SER file", path.display()))?;
+        let int = |offset: usize| {
+            i32::from_le_bytes([
+                bytes[offset],
+                bytes[offset + 1],
+                bytes[offset + 2],
+                bytes[offset + 3],
+            ])
+        };
+        let long = |offset: usize| {
+            u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap_or_default())
+        };
+        Ok(SerHeader {
+            file_id: text_field(&bytes[0..14]),
+            lu_id: int(14),
+            color_id: int(18),
+            little_endian: int(22),
+            width: int(26).max(0) as usize,
+            height: int(30).max(0) as usize,
+            pixel_depth: int(34).max(0) as usize,
+            frame_count: int(38).max(0) as usize,
+            observer: text_field(&bytes[42..82]),
+            instrument: text_field(&bytes[82..122]),
+            telescope: text_field(&bytes[122..162]),
+            date_time: long(162),
+            date_time_utc: long(170),
+            file_size: file.metadata()?.len(),
+        })
+    }
+
+    /// The header as written at the start of a SER file
+    pub fn to_bytes(&self) -> [u8; HEADER_SIZE as usize] {
+        let mut bytes = [0_u8; HEADER_SIZE as usize];
+        let mut put = |offset: usize, value: &[u8], len: usize| {
+            let len = value.len().min(len);
+            bytes[offset..offset + len].copy_from_slice(&value[..len]);
+        };
+        put(0, self.file_id.as_bytes(), 14);
+        put(14, &self.lu_id.to_le_bytes(), 4);
+        put(18, &self.color_id.to_le_bytes(), 4);
+        put(22, &self.little_endian.to_le_bytes(), 4);
+        put(26, &(self.width as i32).to_le_bytes(), 4);
+        put(30, &(self.height as i32).to_le_bytes(), 4);
+        put(34, &(self.pixel_depth as i32).to_le_bytes(), 4);
+        put(38, &(self.frame_count as i32).to_le_bytes(), 4);
+        put(42, self.observer.as_bytes(), 40);
+        put(82, self.instrument.as_bytes(), 40);
+        put(122, self.telescope.as_bytes(), 40);
+        put(162, &self.date_time.to_le_bytes(), 8);
+        put(170, &self.date_time_utc.to_le_bytes(), 8);
+        bytes
+    }
+
+    pub fn color_name(&self) -> &'static str {
+        match self.color_id {
+            0 => "MONO",
+            8 => "BAYER_RGGB",
+            9 => "BAYER_GRBG",
+            10 => "BAYER_GBRG",
+            11 => "BAYER_BGGR",
+            16 => "BAYER_CYYM",
+            17 => "BAYER_YCMY",
+            18 => "BAYER_YMCY",
+            19 => "BAYER_MYYC",
+            COLOR_ID_RGB => "RGB",
+            COLOR_ID_BGR => "BGR",
+            _ => "Unknown",
+        }
+    }
+
+    pub fn planes(&self) -> usize {
+        if self.color_id == COLOR_ID_RGB || self.color_id == COLOR_ID_BGR {
+            3
+        } else {
+            1
+        }
+    }
+
+    /// Size in bytes of each frame, for a pixel depth of `pixel_depth`
+    pub fn frame_size(&self, pixel_depth: usize) -> u64 {
+        let bytes_per_value = if pixel_depth > 8 { 2 } else { 1 };
+        (self.width * self.height * self.planes() * bytes_per_value) as u64
+    }
+
+    /// Number of whole frames the file's data holds, going by its size
+    pub fn frames_in_file(&self, pixel_depth: usize) -> usize {
+        match self.frame_size(pixel_depth) {
+            0 => 0,
+            frame_size => (self.file_size.saturating_sub(HEADER_SIZE) / frame_size) as usize,
+        }
+    }
+
+    /// Values which look wrong, as messages to show the user
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = vec![];
+        if self.file_id != "LUCAM-RECORDER" {
+            problems.push(t!("serheader.problem_file_id").to_string());
+        }
+        if !(1..=16).contains(&self.pixel_depth) {
+            problems
+                .push(t!("serheader.problem_pixel_depth", depth = self.pixel_depth).to_string());
+        }
+        if self.width == 0 || self.height == 0 {
+            problems.push(t!("serheader.problem_size").to_string());
+        }
+        let frames_in_file = self.frames_in_file(self.pixel_depth);
+        if self.frame_count == 0 || self.frame_count > frames_in_file {
+            problems.push(
+                t!(
+                    "serheader.problem_frame_count",
+                    count = self.frame_count,
+                    fit = frames_in_file
+                )
+                .to_string(),
+            );
+        }
+        problems
+    }
+}
+
+/// Byte order of 16-bit values
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Endianness {
+    /// As given by the header, read as most capture software writes it
+    #[default]
+    FromHeader,
+    Little,
+    Big,
+}
+
+impl Endianness {
+    pub const ALL: [Endianness; 3] = [Endianness::FromHeader, Endianness::Little, Endianness::Big];
+
+    pub fn label(&self) -> String {
+        match self {
+            Endianness::FromHeader => t!("serheader.from_header").to_string(),
+            Endianness::Little => t!("serheader.little_endian").to_string(),
+            Endianness::Big => t!("serheader.big_endian").to_string(),
+        }
+    }
+}
+
+/// Values used in place of those in a SER header
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct HeaderOverride {
+    pub pixel_depth: Option<usize>,
+    pub endianness: Endianness,
+    pub frame_count: Option<usize>,
+}
+
+impl HeaderOverride {
+    pub fn is_empty(&self) -> bool {
+        *self == HeaderOverride::default()
+    }
+}
+
+pub fn set_override(path: &str, header_override: HeaderOverride) {
+    let mut overrides = HEADER_OVERRIDES.lock().unwrap();
+    if header_override.is_empty() {
+        overrides.remove(path);
+    } else {
+        overrides.insert(path.to_owned(), header_override);
+    }
+}
+
+pub fn override_for(path: &str) -> Option<HeaderOverride> {
+    HEADER_OVERRIDES.lock().unwrap().get(path).cloned()
+}
+
+/// A SER file read with its header values replaced by those of its override
+pub struct RepairedSerFile {
+    path: String,
+    header: SerHeader,
+    pixel_depth: usize,
+    little_endian: bool,
+    frame_count: usize,
+}
+
+impl RepairedSerFile {
+    pub fn open_with_override(path: &str, header_override: HeaderOverride) -> Result<Self> {
+        let header = SerHeader::read(Path::new(path))?;
+        let pixel_depth = header_override.pixel_depth.unwrap_or(header.pixel_depth);
+        if !(1..=16).contains(&pixel_depth) {
+            return Err(anyhow!("Unsupported pixel depth {}", pixel_depth));
+        }
+        let little_endian = match header_override.endianness {
+            Endianness::FromHeader => header.little_endian == 0,
+            Endianness::Little => true,
+            Endianness::Big => false,
+        };
+        let frame_count = header_override
+            .frame_count
+            .unwrap_or(header.frame_count)
+            .min(header.frames_in_file(pixel_depth));
+        info!(
+            "Opened {} with repaired header: {} bits, {} endian, {} frames",
+            path,
+            pixel_depth,
+            if little_endian { "little" } else { "big" },
+            frame_count
+        );
+        Ok(RepairedSerFile {
+            path: path.to_owned(),
+            header,
+            pixel_depth,
+            little_endian,
+            frame_count,
+        })
+    }
+
+    /// Timestamp of frame `frame_num` from the trailer following the frames, if the file has one
+    fn frame_timestamp(&self, file: &mut File, frame_num: usize) -> TimeStamp {
+        let trailer_offset =
+            HEADER_SIZE + self.header.frame_size(self.pixel_depth) * self.frame_count as u64;
+        let offset = trailer_offset + frame_num as u64 * 8;
+        let mut bytes = [0_u8; 8];
+        if offset + 8 <= self.header.file_size
+            && file.seek(SeekFrom::Start(offset)).is_ok()
+            && file.read_exact(&mut bytes).is_ok()
+        {
+            TimeStamp::from_u64(u64::from_le_bytes(bytes))
+        } else {
+            TimeStamp::from_u64(self.header.date_time_utc)
+        }
+    }
+}
+
+impl DataSource for RepairedSerFile {
+    fn open(filenames: &[String]) -> Result<Self> {
+        let path = filenames
+            .first()
+            .ok_or_else(|| anyhow!("No SER file given"))?;
+        RepairedSerFile::open_with_override(path, override_for(path).unwrap_or_default())
+    }
+
+    fn image_width(&self) -> usize {
+        self.header.width
+    }
+
+    fn image_height(&self) -> usize {
+        self.header.height
+    }
+
+    fn frame_count(&self) -> usize {
+        self.frame_count
+    }
+
+    fn pixel_depth(&self) -> usize {
+        self.pixel_depth
+    }
+
+    fn get_frame(&self, frame_num: usize) -> Result<DataFrame> {
+        if frame_num >= self.frame_count {
+            return Err(anyhow!("Frame {} is beyond the end of the file", frame_num));
+        }
+        let frame_size = self.header.frame_size(self.pixel_depth);
+        let mut file = File::open(&self.path)?;
+        file.seek(SeekFrom::Start(HEADER_SIZE + frame_size * frame_num as u64))?;
+        let mut bytes = vec![0_u8; frame_size as usize];
+        file.read_exact(&mut bytes)?;
+
+        let values: Vec<f32> = if self.pixel_depth > 8 {
+            bytes
+                .chunks_exact(2)
+                .map(|b| match self.little_endian {
+                    true => u16::from_le_bytes([b[0], b[1]]),
+                    false => u16::from_be_bytes([b[0], b[1]]),
+                } as f32)
+                .collect()
+        } else {
+            bytes.iter().map(|b| *b as f32).collect()
+        };
+
+        let (width, height, planes) = (self.header.width, self.header.height, self.header.planes());
+        let mode = if self.pixel_depth > 8 {
+            ImageMode::U16BIT
+        } else {
+            ImageMode::U8BIT
+        };
+        let mut buffer = Image::new_with_bands(width, height, planes, mode)?;
+        values.iter().enumerate().for_each(|(i, value)| {
+            let plane = i % planes;
+            // BGR files hold their planes in reverse
+            let band = if self.header.color_id == COLOR_ID_BGR {
+                planes - 1 - plane
+            } else {
+                plane
+            };
+            let pixel = i / planes;
+            buffer.put(pixel % width, pixel / width, *value, band);
+        });
+
+        Ok(DataFrame {
+            buffer,
+            timestamp: self.frame_timestamp(&mut file, frame_num),
+        })
+    }
+
+    fn source_file(&self) -> String {
+        self.path.clone()
+    }
+
+    fn observer(&self) -> String {
+        self.header.observer.clone()
+    }
+
+    fn instrument(&self) -> String {
+        self.header.instrument.clone()
+    }
+
+    fn telescope(&self) -> String {
+        self.header.telescope.clone()
+    }
+
+    fn date_time_utc(&self) -> TimeStamp {
+        TimeStamp::from_u64(self.header.date_time_utc)
+    }
+}
@@ -0,0 +1,180 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use solhat::context::ProcessParameters;
+use solhat::drizzle::{Scale, StackAlgorithm};
+use solhat::framerecord::FrameRecord;
+use solhat::target::Target;
+
+use crate::partialfile;
+use crate::resources::ResourceUsage;
+use crate::state::ApplicationState;
+
+/// Format of the metadata file written alongside the output image, if any
+#[derive(Debug, Eq, PartialEq, Deserialize, Serialize, Clone, Copy, Default)]
+pub enum SidecarFormat {
+    #[default]
+    None,
+    Toml,
+    Json,
+}
+
+impl SidecarFormat {
+    pub const ALL: [SidecarFormat; 3] = [
+        SidecarFormat::None,
+        SidecarFormat::Toml,
+        SidecarFormat::Json,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            SidecarFormat::None => "None",
+            SidecarFormat::Toml => "TOML",
+            SidecarFormat::Json => "JSON",
+        }
+    }
+
+    fn extension(&self) -> Option<&'static str> {
+        match *self {
+            SidecarFormat::None => None,
+            SidecarFormat::Toml => Some("toml"),
+            SidecarFormat::Json => Some("json"),
+        }
+    }
+}
+
+/// The parameters solhat processed the run with
+#[derive(Serialize)]
+pub struct ParametersRecord {
+    input_files: Vec<String>,
+    obj_detection_threshold: f64,
+    obs_latitude: f64,
+    obs_longitude: f64,
+    target: Target,
+    crop_width: Option<usize>,
+    crop_height: Option<usize>,
+    vert_offset: i32,
+    horiz_offset: i32,
+    max_frames: Option<usize>,
+    min_sigma: Option<f64>,
+    max_sigma: Option<f64>,
+    top_percentage: Option<f64>,
+    drizzle_scale: Scale,
+    algorithm: StackAlgorithm,
+    initial_rotation: f64,
+    flat_inputs: Option<String>,
+    dark_inputs: Option<String>,
+    darkflat_inputs: Option<String>,
+    bias_inputs: Option<String>,
+    hot_pixel_map: Option<String>,
+    analysis_window_size: usize,
+}
+
+impl From<&ProcessParameters> for ParametersRecord {
+    fn from(p: &ProcessParameters) -> Self {
+        ParametersRecord {
+            input_files: p.input_files.clone(),
+            obj_detection_threshold: p.obj_detection_threshold,
+            obs_latitude: p.obs_latitude,
+            obs_longitude: p.obs_longitude,
+            target: p.target,
+            crop_width: p.crop_width,
+            crop_height: p.crop_height,
+            vert_offset: p.vert_offset,
+            horiz_offset: p.horiz_offset,
+            max_frames: p.max_frames,
+            min_sigma: p.min_sigma,
+            max_sigma: p.max_sigma,
+            top_percentage: p.top_percentage,
+            drizzle_scale: p.drizzle_scale,
+            algorithm: p.algorithm,
+            initial_rotation: p.initial_rotation,
+            flat_inputs: p.flat_inputs.clone(),
+            dark_inputs: p.dark_inputs.clone(),
+            darkflat_inputs: p.darkflat_inputs.clone(),
+            bias_inputs: p.bias_inputs.clone(),
+            hot_pixel_map: p.hot_pixel_map.clone(),
+            analysis_window_size: p.analysis_window_size,
+        }
+    }
+}
+
+/// Quality range of the frames which were stacked
+#[derive(Serialize)]
+struct SigmaRange {
+    min: f64,
+    max: f64,
+}
+
+/// Everything needed to reproduce a run, written alongside its output
+#[derive(Serialize)]
+struct Sidecar {
+    software: String,
+    /// Local time the output was written, RFC 3339 formatted
+    created: String,
+    output_filename: PathBuf,
+    frames_used: usize,
+    /// Ids, in capture order, of the frames stacked
+    frame_ids: Vec<usize>,
+    /// Calibration masters saved by the run
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    saved_masters: Vec<PathBuf>,
+    stacked_sigma: Option<SigmaRange>,
+    resources: ResourceUsage,
+    parameters: ParametersRecord,
+    /// The full set of options the run was made with
+    options: ApplicationState,
+}
+
+/// Writes the run's parameters, frames and options to a file of `format` alongside the output,
+/// sharing its file stem. Does nothing if `format` is `None`.
+pub fn write(
+    format: SidecarFormat,
+    output_filename: &Path,
+    app_state: &ApplicationState,
+    parameters: &ProcessParameters,
+    frame_records: &[FrameRecord],
+    resources: ResourceUsage,
+    saved_masters: &[PathBuf],
+) -> Result<()> {
+    let Some(extension) = format.extension() else {
+        return Ok(());
+    };
+
+    let mut frame_ids: Vec<usize> = frame_records.iter().map(|fr| fr.frame_id).collect();
+    frame_ids.sort();
+    let sigmas = frame_records
+        .iter()
+        .map(|fr| fr.sigma)
+        .filter(|s| s.is_finite());
+    let stacked_sigma = sigmas.clone().reduce(f64::min).map(|min| SigmaRange {
+        min,
+        max: sigmas.fold(min, f64::max),
+    });
+
+    let mut options = app_state.clone();
+    options.window = Default::default();
+    let sidecar = Sidecar {
+        software: format!("SolHat {}", env!("CARGO_PKG_VERSION")),
+        created: chrono::Local::now().to_rfc3339(),
+        output_filename: output_filename.to_owned(),
+        frames_used: frame_records.len(),
+        frame_ids,
+        saved_masters: saved_masters.to_vec(),
+        stacked_sigma,
+        resources,
+        parameters: parameters.into(),
+        options,
+    };
+    let text = match format {
+        SidecarFormat::Json => serde_json::to_string_pretty(&sidecar)?,
+        _ => toml::to_string(&sidecar)?,
+    };
+
+    let sidecar_filename = output_filename.with_extension(extension);
+    partialfile::write_atomically(&sidecar_filename, |path| Ok(fs::write(path, &text)?))?;
+    info!("Saved run metadata to {:?}", sidecar_filename);
+    Ok(())
+}
@@ -0,0 +1,263 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use egui::{Context, TextureHandle, Ui};
+
+use crate::history::{self, HistoryEntry};
+use crate::imageutil;
+use crate::process::framesource;
+use crate::process::report;
+use crate::process::sidecar::ParametersRecord;
+use crate::state::ApplicationState;
+
+/// Longest side of the output thumbnail, in pixels
+const THUMBNAIL_SIZE: usize = 480;
+
+/// A project opened for review, without replacing the current options or needing its input
+/// files. Nothing shown can be edited.
+#[derive(Default)]
+pub struct ProjectViewer {
+    pub open: bool,
+    path: PathBuf,
+    project: Option<ApplicationState>,
+    /// Parameters as named in the report and metadata files
+    parameters: Vec<(String, String)>,
+    output: Option<PathBuf>,
+    report: Option<PathBuf>,
+    thumbnail: Option<TextureHandle>,
+    history: Vec<HistoryEntry>,
+}
+
+/// The first of `candidates` which exists
+fn find_existing(candidates: impl IntoIterator<Item = PathBuf>) -> Option<PathBuf> {
+    candidates.into_iter().find(|p| p.exists())
+}
+
+/// Where `path` might be found: where it was when the project was saved, or next to the project
+/// file, for projects copied from another machine along with their results
+fn candidate_paths(path: &Path, project_folder: Option<&Path>) -> Vec<PathBuf> {
+    let mut candidates = vec![path.to_path_buf()];
+    if let (Some(folder), Some(name)) = (project_folder, path.file_name()) {
+        candidates.push(folder.join(name));
+    }
+    candidates
+}
+
+impl ProjectViewer {
+    /// Reads the project at `path`, with its output, report and past runs where they can be
+    /// found
+    pub fn open(&mut self, ctx: &Context, path: &Path, current: &ApplicationState) -> Result<()> {
+        let project = current.load_project(path)?;
+        let folder = path.parent();
+
+        let value = serde_json::to_value(ParametersRecord::from(&project.to_parameters()))?;
+        self.parameters = value
+            .as_object()
+            .map(|fields| {
+                fields
+                    .iter()
+                    .map(|(name, value)| {
+                        let value = match value {
+                            serde_json::Value::Null => "-".to_owned(),
+                            serde_json::Value::String(s) => s.clone(),
+                            other => other.to_string(),
+                        };
+                        (name.clone(), value)
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        self.output = project
+            .assemble_output_filename()
+            .ok()
+            .and_then(|output| find_existing(candidate_paths(&output, folder)));
+        self.report = project.assemble_output_filename().ok().and_then(|output| {
+            let mut candidates = candidate_paths(&report::report_path(&output), folder);
+            if let Some(found) = &self.output {
+                candidates.push(report::report_path(found));
+            }
+            find_existing(candidates)
+        });
+        self.thumbnail =
+            self.output
+                .as_ref()
+                .and_then(|output| match framesource::open_still_image(output) {
+                    Ok(image) => {
+                        let factor = image.width.max(image.height).div_ceil(THUMBNAIL_SIZE);
+                        Some(ctx.load_texture(
+                            imageutil::gen_random_texture_name(),
+                            imageutil::sciimg_to_color_image(&imageutil::downscale_image(
+                                &image, factor,
+                            )),
+                            Default::default(),
+                        ))
+                    }
+                    Err(why) => {
+                        warn!("Failed to read project output {:?}: {}", output, why);
+                        None
+                    }
+                });
+
+        let light_name = project
+            .light
+            .as_ref()
+            .and_then(|l| Path::new(l).file_name())
+            .map(|f| f.to_string_lossy().to_string());
+        self.history = history::entries()
+            .into_iter()
+            .filter(|entry| Some(entry.light_name()) == light_name)
+            .collect();
+
+        info!("Viewing project {:?} read only", path);
+        self.path = path.to_path_buf();
+        self.project = Some(project);
+        self.open = true;
+        Ok(())
+    }
+
+    fn inputs_ui(ui: &mut Ui, project: &ApplicationState) {
+        egui::Grid::new("project_viewer_inputs")
+            .num_columns(3)
+            .spacing([20.0, 4.0])
+            .striped(true)
+            .show(ui, |ui| {
+                [
+                    (t!("light"), &project.light),
+                    (t!("composite.prominence_light"), &project.prominence_light),
+                    (t!("dark"), &project.dark),
+                    (t!("flat"), &project.flat),
+                    (t!("darkflat"), &project.darkflat),
+                    (t!("bias"), &project.bias),
+                    (t!("hotpixelmap"), &project.hot_pixel_map),
+                ]
+                .into_iter()
+                .for_each(|(name, input)| {
+                    let Some(input) = input else {
+                        return;
+                    };
+                    ui.label(name);
+                    ui.monospace(input);
+                    if Path::new(input).exists() {
+                        ui.label(t!("project_viewer.present"));
+                    } else {
+                        ui.colored_label(ui.visuals().warn_fg_color, t!("project_viewer.missing"));
+                    }
+                    ui.end_row();
+                });
+            });
+    }
+
+    fn parameters_ui(&self, ui: &mut Ui, project: &ApplicationState) {
+        egui::Grid::new("project_viewer_parameters")
+            .num_columns(2)
+            .spacing([40.0, 4.0])
+            .striped(true)
+            .show(ui, |ui| {
+                ui.label(t!("project_viewer.target"));
+                ui.label(project.target_name());
+                ui.end_row();
+
+                self.parameters.iter().for_each(|(name, value)| {
+                    ui.monospace(name);
+                    ui.label(value);
+                    ui.end_row();
+                });
+            });
+        if !project.session_notes.trim().is_empty() {
+            ui.add_space(6.0);
+            ui.strong(t!("project_viewer.notes"));
+            ui.label(&project.session_notes);
+        }
+    }
+
+    fn history_ui(&self, ui: &mut Ui) {
+        if self.history.is_empty() {
+            ui.weak(t!("project_viewer.no_history"));
+            return;
+        }
+        egui::Grid::new("project_viewer_history")
+            .num_columns(3)
+            .spacing([30.0, 4.0])
+            .striped(true)
+            .show(ui, |ui| {
+                ui.strong(t!("history.completed"));
+                ui.strong(t!("history.output"));
+                ui.strong(t!("history.frames_used"));
+                ui.end_row();
+                self.history.iter().for_each(|entry| {
+                    ui.label(
+                        chrono::DateTime::parse_from_rfc3339(&entry.completed)
+                            .map(|t| t.format("%Y-%m-%d %H:%M:%S").to_string())
+                            .unwrap_or_else(|_| entry.completed.clone()),
+                    );
+                    ui.monospace(entry.output_filename.to_string_lossy());
+                    ui.label(entry.frames_used.to_string());
+                    ui.end_row();
+                });
+            });
+    }
+
+    /// Shows the window. Returns the project's path if the user chose to open it for editing.
+    pub fn ui(&mut self, ctx: &Context) -> Option<PathBuf> {
+        let mut edit = false;
+        let mut open = self.open;
+        egui::Window::new(t!("project_viewer.title"))
+            .open(&mut open)
+            .default_width(640.0)
+            .show(ctx, |ui| {
+                let Some(project) = &self.project else {
+                    return;
+                };
+                ui.horizontal(|ui| {
+                    ui.colored_label(ui.visuals().warn_fg_color, t!("project_viewer.read_only"));
+                    ui.monospace(self.path.to_string_lossy());
+                });
+                ui.weak(t!("project_viewer.description"));
+                ui.horizontal(|ui| {
+                    if let Some(report) = &self.report {
+                        if ui.button(t!("project_viewer.open_report")).clicked() {
+                            ctx.open_url(egui::OpenUrl::new_tab(format!(
+                                "file://{}",
+                                report.display()
+                            )));
+                        }
+                    }
+                    edit = ui
+                        .button(t!("project_viewer.edit"))
+                        .on_hover_text(t!("project_viewer.edit_hint"))
+                        .clicked();
+                });
+                ui.separator();
+
+                egui::ScrollArea::vertical().show(ui, |ui| {
+                    ui.heading(t!("project_viewer.output"));
+                    match &self.output {
+                        Some(output) => {
+                            ui.monospace(output.to_string_lossy());
+                            if let Some(thumbnail) = &self.thumbnail {
+                                ui.add(egui::Image::new(thumbnail).shrink_to_fit());
+                            }
+                        }
+                        None => {
+                            ui.weak(t!("project_viewer.no_output"));
+                        }
+                    }
+                    ui.separator();
+
+                    ui.heading(t!("project_viewer.inputs"));
+                    Self::inputs_ui(ui, project);
+                    ui.separator();
+
+                    ui.heading(t!("project_viewer.parameters"));
+                    self.parameters_ui(ui, project);
+                    ui.separator();
+
+                    ui.heading(t!("history.title"));
+                    self.history_ui(ui);
+                });
+            });
+        self.open = open && !edit;
+        edit.then(|| self.path.clone())
+    }
+}
@@ -0,0 +1,342 @@
+//! Publishing of results: a web sized JPEG of the adjusted output, with a title and description
+//! filled in from the session, uploaded to an FTP or WebDAV folder. Uploads go through curl,
+//! which must be installed and on the PATH. The title and description are uploaded alongside the
+//! image as a text file, as neither protocol has anywhere to put them.
+
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use anyhow::{anyhow, Result};
+use egui::{Context, Ui};
+use sciimg::prelude::Image;
+use serde::{Deserialize, Serialize};
+
+use crate::colorprofile::{self, ColorProfile};
+use crate::curlconfig::CredentialsFile;
+use crate::imageutil;
+use crate::metadata::{self, ResultMetadata};
+use crate::taskstatus::*;
+use crate::tiffwriter::TiffOptions;
+
+/// Longest an upload may take, in seconds
+const UPLOAD_TIMEOUT: &str = "300";
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+#[serde(default)]
+pub struct PublishSettings {
+    /// Folder uploaded to, e.g. `ftp://ftp.example.com/solar/` or
+    /// `https://cloud.example.com/remote.php/dav/files/me/solar/`
+    pub destination_url: String,
+    pub username: String,
+    /// Stored unencrypted in the settings file, which is private to the user, and handed to curl
+    /// in a `CredentialsFile` for each upload
+    pub password: String,
+    /// Length of the longer side of the uploaded image, in pixels. Larger images are binned
+    /// down to fit.
+    pub long_side: usize,
+    pub jpeg_quality: u8,
+    pub title_template: String,
+    pub description_template: String,
+}
+
+impl Default for PublishSettings {
+    fn default() -> Self {
+        PublishSettings {
+            destination_url: String::new(),
+            username: String::new(),
+            password: String::new(),
+            long_side: 2048,
+            jpeg_quality: 90,
+            title_template: "{target} {date}".to_owned(),
+            description_template:
+                "{target} on {date} at {time} UTC. Best {frames} frames stacked with {software}.\n{notes}"
+                    .to_owned(),
+        }
+    }
+}
+
+impl PublishSettings {
+    fn has_destination(&self) -> bool {
+        !self.destination_url.trim().is_empty()
+    }
+
+    /// URL of `name` in the destination folder
+    fn url_of(&self, name: &str) -> String {
+        let folder = self.destination_url.trim();
+        if folder.ends_with('/') {
+            format!("{}{}", folder, name)
+        } else {
+            format!("{}/{}", folder, name)
+        }
+    }
+}
+
+/// `name` with anything but letters, digits, dashes and dots replaced, for use in a URL
+fn sanitize(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '.' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+/// An image ready to be uploaded, with its title and description
+pub struct Upload {
+    pub settings: PublishSettings,
+    pub image: Image,
+    pub color_profile: ColorProfile,
+    pub name: String,
+    pub title: String,
+    pub description: String,
+}
+
+/// Runs curl to upload `path` to `url`
+fn curl_upload(settings: &PublishSettings, path: &Path, url: &str) -> Result<()> {
+    let mut command = Command::new("curl");
+    command
+        .args([
+            "--silent",
+            "--show-error",
+            "--fail",
+            "--ftp-create-dirs",
+            "--max-time",
+            UPLOAD_TIMEOUT,
+        ])
+        .arg("--upload-file")
+        .arg(path)
+        .arg(url);
+    let credentials = if settings.username.is_empty() {
+        None
+    } else {
+        Some(CredentialsFile::new(
+            &settings.username,
+            &settings.password,
+        )?)
+    };
+    if let Some(credentials) = &credentials {
+        command.arg("--config").arg(credentials.path());
+    }
+    let output = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .output()
+        .map_err(|why| {
+            warn!("Failed to start curl: {}", why);
+            anyhow!(t!("publish.curl_missing").to_string())
+        })?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "curl failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(())
+}
+
+/// Writes the web sized JPEG and its text file to the temporary folder and uploads them. Returns
+/// the URL of the uploaded image.
+pub async fn upload_async(upload: Upload) -> Result<String> {
+    let settings = &upload.settings;
+    set_task_status(&t!("tasks.publishing"), 2, 0);
+    let long_side = upload.image.width.max(upload.image.height);
+    let factor = long_side.div_ceil(settings.long_side.max(1));
+    let image = imageutil::downscale_image(&upload.image, factor);
+
+    let folder = std::env::temp_dir();
+    let image_path: PathBuf = folder.join(format!("{}.jpg", upload.name));
+    let text_path: PathBuf = folder.join(format!("{}.txt", upload.name));
+    colorprofile::save_with_profile(
+        &image,
+        &image_path,
+        upload.color_profile,
+        &TiffOptions::default(),
+        settings.jpeg_quality,
+    )?;
+    std::fs::write(
+        &text_path,
+        format!("{}\n\n{}\n", upload.title, upload.description),
+    )?;
+
+    let image_url = settings.url_of(&format!("{}.jpg", upload.name));
+    let uploaded = curl_upload(settings, &image_path, &image_url).and_then(|_| {
+        set_task_status(&t!("tasks.publishing"), 2, 1);
+        curl_upload(
+            settings,
+            &text_path,
+            &settings.url_of(&format!("{}.txt", upload.name)),
+        )
+    });
+    let _ = std::fs::remove_file(&image_path);
+    let _ = std::fs::remove_file(&text_path);
+    uploaded?;
+    info!(
+        "Published {}x{} image to {}",
+        image.width, image.height, image_url
+    );
+    Ok(image_url)
+}
+
+/// Preferences for where results are published and how they're described
+pub fn settings_ui(ui: &mut Ui, settings: &mut PublishSettings) {
+    egui::Grid::new("preferences_publish")
+        .num_columns(2)
+        .spacing([40.0, 4.0])
+        .striped(true)
+        .show(ui, |ui| {
+            ui.label(t!("publish.destination_url"));
+            ui.add(
+                egui::TextEdit::singleline(&mut settings.destination_url)
+                    .hint_text("ftp://ftp.example.com/solar/"),
+            )
+            .on_hover_text(t!("publish.destination_url_hint"));
+            ui.end_row();
+
+            ui.label(t!("publish.username"));
+            ui.text_edit_singleline(&mut settings.username);
+            ui.end_row();
+
+            ui.label(t!("publish.password"));
+            ui.add(egui::TextEdit::singleline(&mut settings.password).password(true))
+                .on_hover_text(t!("publish.password_hint"));
+            ui.end_row();
+
+            ui.label(t!("publish.long_side"));
+            ui.add(
+                egui::DragValue::new(&mut settings.long_side)
+                    .clamp_range(256..=8192)
+                    .suffix(" px"),
+            )
+            .on_hover_text(t!("publish.long_side_hint"));
+            ui.end_row();
+
+            ui.label(t!("results.jpeg_quality"));
+            ui.add(egui::Slider::new(&mut settings.jpeg_quality, 1..=100));
+            ui.end_row();
+
+            ui.label(t!("publish.title_template"));
+            ui.text_edit_singleline(&mut settings.title_template);
+            ui.end_row();
+
+            ui.label(t!("publish.description_template"));
+            ui.text_edit_multiline(&mut settings.description_template);
+            ui.end_row();
+        });
+    ui.weak(t!("publish.tokens", tokens = metadata::TOKENS.join("}, {")));
+}
+
+/// Reviews the title and description of a result before it's uploaded
+#[derive(Default)]
+pub struct PublishWindow {
+    pub open: bool,
+    image: Option<Image>,
+    color_profile: ColorProfile,
+    name: String,
+    title: String,
+    description: String,
+    error: Option<String>,
+}
+
+impl PublishWindow {
+    /// Opens the window for the adjusted result `image`, with the title and description filled
+    /// in from `metadata`
+    pub fn show_for(
+        &mut self,
+        image: Image,
+        color_profile: ColorProfile,
+        metadata: &ResultMetadata,
+        settings: &PublishSettings,
+    ) {
+        let filled = metadata
+            .fill(&settings.title_template)
+            .and_then(|title| Ok((title, metadata.fill(&settings.description_template)?)));
+        (self.title, self.description, self.error) = match filled {
+            Ok((title, description)) => (title, description, None),
+            Err(why) => (String::new(), String::new(), Some(why.to_string())),
+        };
+        self.image = Some(image);
+        self.color_profile = color_profile;
+        self.name = match metadata.value("filename") {
+            "" => "solhat".to_owned(),
+            filename => sanitize(filename),
+        };
+        self.open = true;
+    }
+
+    /// Shows the window. Returns the upload once it's confirmed. Uploads can't be started while
+    /// `task_running`.
+    pub fn ui(
+        &mut self,
+        ctx: &Context,
+        settings: &PublishSettings,
+        task_running: bool,
+    ) -> Option<Upload> {
+        let mut upload = false;
+        let mut open = self.open;
+        egui::Window::new(t!("publish.title"))
+            .open(&mut open)
+            .resizable(false)
+            .show(ctx, |ui| {
+                let Some(image) = &self.image else {
+                    return;
+                };
+                if settings.has_destination() {
+                    ui.label(t!(
+                        "publish.destination",
+                        url = settings.url_of(&format!("{}.jpg", self.name))
+                    ));
+                } else {
+                    ui.colored_label(ui.visuals().warn_fg_color, t!("publish.not_configured"));
+                }
+                let factor = image
+                    .width
+                    .max(image.height)
+                    .div_ceil(settings.long_side.max(1));
+                ui.weak(t!(
+                    "publish.size",
+                    width = image.width / factor.max(1),
+                    height = image.height / factor.max(1)
+                ));
+                if let Some(error) = &self.error {
+                    ui.colored_label(ui.visuals().error_fg_color, error);
+                }
+                ui.separator();
+
+                egui::Grid::new("publish_text")
+                    .num_columns(2)
+                    .spacing([20.0, 6.0])
+                    .show(ui, |ui| {
+                        ui.label(t!("publish.image_title"));
+                        ui.text_edit_singleline(&mut self.title);
+                        ui.end_row();
+
+                        ui.label(t!("publish.description"));
+                        ui.text_edit_multiline(&mut self.description);
+                        ui.end_row();
+                    });
+
+                upload = ui
+                    .add_enabled(
+                        !task_running && settings.has_destination(),
+                        egui::Button::new(t!("publish.upload")),
+                    )
+                    .clicked();
+            });
+        self.open = open && !upload;
+        if !upload {
+            return None;
+        }
+        Some(Upload {
+            settings: settings.clone(),
+            image: self.image.take()?,
+            color_profile: self.color_profile,
+            name: self.name.clone(),
+            title: self.title.clone(),
+            description: self.description.clone(),
+        })
+    }
+}
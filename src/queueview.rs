@@ -0,0 +1,450 @@
+use egui::Ui;
+use solhat::drizzle::Scale;
+
+use crate::jobqueue::{self, Job, JobOverrides, JobStatus};
+use crate::schedule::QueueSchedule;
+use crate::state::{ApplicationState, OutputFormat, StackMethod};
+
+/// Job overrides being edited, applied to the job when the user accepts them
+struct OverridesEdit {
+    job_id: usize,
+    overrides: JobOverrides,
+    error: Option<String>,
+}
+
+/// Copy of a job's options being changed before it's queued as a new job
+struct DuplicateEdit {
+    job_name: String,
+    state: ApplicationState,
+    error: Option<String>,
+}
+
+/// Lists the queued jobs with their status, and controls for reordering and removing them
+#[derive(Default)]
+pub struct QueueViewPane {
+    editing: Option<OverridesEdit>,
+    duplicating: Option<DuplicateEdit>,
+    /// When the queue starts by itself, if scheduled
+    pub schedule: QueueSchedule,
+    schedule_error: Option<String>,
+}
+
+impl QueueViewPane {
+    pub fn is_empty(&self) -> bool {
+        jobqueue::jobs().is_empty()
+    }
+
+    fn status_label(ui: &mut Ui, status: &JobStatus) {
+        match status {
+            JobStatus::Pending => {
+                ui.label(t!("queue.pending"));
+            }
+            JobStatus::Running => {
+                ui.horizontal(|ui| {
+                    ui.label(t!("queue.running"));
+                    ui.spinner();
+                });
+            }
+            JobStatus::Completed => {
+                ui.colored_label(
+                    egui::Color32::from_rgb(100, 200, 100),
+                    t!("queue.completed"),
+                );
+            }
+            JobStatus::Failed(why) => {
+                ui.colored_label(ui.visuals().error_fg_color, t!("queue.failed"))
+                    .on_hover_text(why);
+            }
+            JobStatus::Cancelled => {
+                ui.colored_label(ui.visuals().warn_fg_color, t!("queue.cancelled"));
+            }
+        }
+    }
+
+    /// The scheduled start time with its countdown and controls, or the options for scheduling
+    /// one. Returns true if the user asked to start the queue now.
+    fn schedule_ui(&mut self, ui: &mut Ui, task_running: bool) -> bool {
+        let mut start_now = false;
+        if let (Some(start_at), Some(countdown)) =
+            (self.schedule.start_at, self.schedule.countdown())
+        {
+            ui.horizontal(|ui| {
+                ui.label(t!(
+                    "queue.scheduled_at",
+                    time = start_at.format("%Y-%m-%d %H:%M:%S")
+                ));
+                ui.strong(countdown);
+                ui.add_enabled_ui(!task_running, |ui| {
+                    if ui.button(t!("schedule.start_now")).clicked() {
+                        self.schedule.cancel();
+                        start_now = true;
+                    }
+                });
+                if ui.button(t!("schedule.cancel")).clicked() {
+                    self.schedule.cancel();
+                }
+            });
+            if task_running {
+                ui.weak(t!("queue.scheduled_waiting"));
+            }
+        } else {
+            egui::CollapsingHeader::new(t!("queue.start_later"))
+                .id_source("queue_schedule")
+                .show(ui, |ui| {
+                    self.schedule.time.ui(ui);
+                    ui.add_enabled_ui(jobqueue::has_pending_jobs(), |ui| {
+                        if ui.button(t!("schedule.schedule")).clicked() {
+                            self.schedule_error =
+                                self.schedule.schedule().err().map(|why| why.to_string());
+                        }
+                    });
+                    if let Some(error) = &self.schedule_error {
+                        ui.colored_label(ui.visuals().error_fg_color, error);
+                    }
+                });
+        }
+        start_now
+    }
+
+    /// Returns true if the user asked to run the queue
+    pub fn ui(&mut self, ui: &mut Ui, task_running: bool, concurrent_jobs: &mut usize) -> bool {
+        let mut run_requested = false;
+        let jobs = jobqueue::jobs();
+
+        ui.horizontal(|ui| {
+            ui.add_enabled_ui(!task_running && jobqueue::has_pending_jobs(), |ui| {
+                if ui.button(t!("queue.run")).clicked() {
+                    run_requested = true;
+                }
+            });
+            if ui.button(t!("queue.clear_finished")).clicked() {
+                jobqueue::clear_finished();
+            }
+            if ui.button(t!("queue.retry_failed")).clicked() {
+                jobqueue::requeue_unsuccessful();
+            }
+            ui.separator();
+            *concurrent_jobs = (*concurrent_jobs).max(1);
+            ui.add_enabled(
+                !task_running,
+                egui::Slider::new(concurrent_jobs, 1..=jobqueue::MAX_CONCURRENT_JOBS)
+                    .text(t!("queue.concurrent_jobs")),
+            )
+            .on_hover_text(t!("queue.concurrent_jobs_hint"));
+        });
+        if self.schedule_ui(ui, task_running) {
+            run_requested = true;
+        }
+        ui.separator();
+
+        egui::ScrollArea::vertical().show(ui, |ui| {
+            egui::Grid::new("job_queue")
+                .num_columns(5)
+                .spacing([40.0, 4.0])
+                .striped(true)
+                .show(ui, |ui| {
+                    ui.strong("#");
+                    ui.strong(t!("queue.light"));
+                    ui.strong(t!("queue.output"));
+                    ui.strong(t!("queue.status"));
+                    ui.end_row();
+
+                    jobs.iter().enumerate().for_each(|(i, job)| {
+                        let editable = job.status != JobStatus::Running;
+                        ui.label((i + 1).to_string());
+                        ui.label(job.name());
+                        ui.horizontal(|ui| {
+                            ui.monospace(
+                                job.output_filename
+                                    .file_name()
+                                    .map(|f| f.to_string_lossy().to_string())
+                                    .unwrap_or_default(),
+                            )
+                            .on_hover_text(job.output_filename.to_string_lossy());
+                            if !job.overrides.is_empty() {
+                                ui.label("✏").on_hover_text(t!("queue.has_overrides"));
+                            }
+                        });
+                        QueueViewPane::status_label(ui, &job.status);
+                        ui.horizontal(|ui| {
+                            ui.add_enabled_ui(editable, |ui| {
+                                if ui
+                                    .small_button("⬆")
+                                    .on_hover_text(t!("queue.move_up"))
+                                    .clicked()
+                                {
+                                    jobqueue::move_job(job.id, -1);
+                                }
+                                if ui
+                                    .small_button("⬇")
+                                    .on_hover_text(t!("queue.move_down"))
+                                    .clicked()
+                                {
+                                    jobqueue::move_job(job.id, 1);
+                                }
+                                if ui
+                                    .small_button("✏")
+                                    .on_hover_text(t!("queue.edit_overrides"))
+                                    .clicked()
+                                {
+                                    self.editing = Some(OverridesEdit {
+                                        job_id: job.id,
+                                        overrides: job.overrides.clone(),
+                                        error: None,
+                                    });
+                                }
+                                if ui
+                                    .small_button("✖")
+                                    .on_hover_text(t!("queue.remove"))
+                                    .clicked()
+                                {
+                                    jobqueue::remove_job(job.id);
+                                }
+                            });
+                            if ui
+                                .small_button("⧉")
+                                .on_hover_text(t!("queue.duplicate_hint"))
+                                .clicked()
+                            {
+                                self.duplicating = Some(DuplicateEdit {
+                                    job_name: job.name(),
+                                    state: job.effective_state(),
+                                    error: None,
+                                });
+                            }
+                        });
+                        ui.end_row();
+                    });
+                });
+
+            let editing_job = self.editing.as_ref().and_then(|edit| {
+                jobs.iter()
+                    .find(|j| j.id == edit.job_id && j.status != JobStatus::Running)
+            });
+            match editing_job {
+                Some(job) => {
+                    ui.separator();
+                    self.overrides_editor(ui, job);
+                }
+                None => self.editing = None,
+            }
+            if self.duplicating.is_some() {
+                ui.separator();
+                self.duplicate_editor(ui, &jobs);
+            }
+        });
+
+        run_requested
+    }
+
+    /// Editor for the options of a duplicated job which are most often varied between runs being
+    /// compared
+    fn duplicate_editor(&mut self, ui: &mut Ui, jobs: &[Job]) {
+        let Some(edit) = &mut self.duplicating else {
+            return;
+        };
+        let mut close = false;
+
+        ui.heading(t!("queue.duplicate_title", job = edit.job_name));
+        egui::Grid::new("job_duplicate")
+            .num_columns(2)
+            .spacing([40.0, 4.0])
+            .striped(true)
+            .show(ui, |ui| {
+                let state = &mut edit.state;
+
+                ui.label(t!("processoptions.drizzle"));
+                ui.horizontal(|ui| {
+                    for (scale, label) in [
+                        (Scale::Scale1_0, t!("processoptions.drizzle_none")),
+                        (Scale::Scale1_5, t!("processoptions.drizzle_15x")),
+                        (Scale::Scale2_0, t!("processoptions.drizzle_20x")),
+                        (Scale::Scale3_0, t!("processoptions.drizzle_30x")),
+                    ] {
+                        let selected =
+                            state.custom_drizzle_scale.is_none() && state.drizzle_scale == scale;
+                        if ui.selectable_label(selected, label).clicked() {
+                            state.drizzle_scale = scale;
+                            state.custom_drizzle_scale = None;
+                        }
+                    }
+                    if let Some(custom) = &mut state.custom_drizzle_scale {
+                        ui.add(
+                            egui::DragValue::new(custom)
+                                .clamp_range(1.0..=4.0)
+                                .speed(0.01)
+                                .fixed_decimals(2)
+                                .suffix("x"),
+                        );
+                    }
+                });
+                ui.end_row();
+
+                ui.label(t!("processoptions.algorithm"));
+                ui.horizontal(|ui| {
+                    for algorithm in StackMethod::ALL {
+                        ui.selectable_value(&mut state.algorithm, algorithm, algorithm.name());
+                    }
+                });
+                ui.end_row();
+
+                ui.label(t!("processoptions.use_max_frames"));
+                ui.add(egui::DragValue::new(&mut state.max_frames).speed(10.0));
+                ui.end_row();
+
+                ui.label(t!("processoptions.filename_free_text"));
+                ui.add(
+                    egui::TextEdit::singleline(&mut state.freetext)
+                        .hint_text(t!("processoptions.filename_hint")),
+                );
+                ui.end_row();
+
+                ui.label(t!("output.output_filename"));
+                match state.assemble_output_filename() {
+                    Ok(output_filename) => {
+                        ui.vertical(|ui| {
+                            ui.monospace(output_filename.to_string_lossy());
+                            if jobs.iter().any(|j| j.output_filename == output_filename) {
+                                ui.colored_label(
+                                    ui.visuals().warn_fg_color,
+                                    t!("queue.duplicate_same_output"),
+                                );
+                            }
+                        });
+                    }
+                    Err(why) => {
+                        ui.colored_label(ui.visuals().error_fg_color, why.to_string());
+                    }
+                }
+                ui.end_row();
+            });
+
+        if let Some(error) = &edit.error {
+            ui.colored_label(ui.visuals().error_fg_color, error);
+        }
+        ui.horizontal(|ui| {
+            if ui.button(t!("queue.duplicate_add")).clicked() {
+                match jobqueue::add_job(&edit.state) {
+                    Ok(()) => close = true,
+                    Err(why) => edit.error = Some(why.to_string()),
+                }
+            }
+            if ui.button(t!("cancel")).clicked() {
+                close = true;
+            }
+        });
+
+        if close {
+            self.duplicating = None;
+        }
+    }
+
+    /// Editor for the output overrides of `job`. Each option is either kept from the queued
+    /// options or overridden for this job alone.
+    fn overrides_editor(&mut self, ui: &mut Ui, job: &Job) {
+        let Some(edit) = &mut self.editing else {
+            return;
+        };
+        let mut close = false;
+
+        ui.heading(t!("queue.overrides_title", job = job.name()));
+        egui::Grid::new("job_overrides")
+            .num_columns(2)
+            .spacing([40.0, 4.0])
+            .striped(true)
+            .show(ui, |ui| {
+                let overrides = &mut edit.overrides;
+
+                let mut override_dir = overrides.output_dir.is_some();
+                ui.checkbox(&mut override_dir, t!("output.output_folder"));
+                ui.horizontal(|ui| {
+                    if override_dir {
+                        let output_dir = overrides.output_dir.get_or_insert_with(|| {
+                            job.state.output_dir.clone().unwrap_or_default()
+                        });
+                        ui.monospace(output_dir.as_str());
+                        if ui.button(t!("output.open_folder")).clicked() {
+                            if let Some(path) = rfd::FileDialog::new().pick_folder() {
+                                *output_dir = path.display().to_string();
+                            }
+                        }
+                    } else {
+                        overrides.output_dir = None;
+                        ui.weak(job.state.output_dir.clone().unwrap_or_default());
+                    }
+                });
+                ui.end_row();
+
+                let mut override_freetext = overrides.freetext.is_some();
+                ui.checkbox(
+                    &mut override_freetext,
+                    t!("processoptions.filename_free_text"),
+                );
+                if override_freetext {
+                    let freetext = overrides
+                        .freetext
+                        .get_or_insert_with(|| job.state.freetext.clone());
+                    ui.add(
+                        egui::TextEdit::singleline(freetext)
+                            .hint_text(t!("processoptions.filename_hint")),
+                    );
+                } else {
+                    overrides.freetext = None;
+                    ui.weak(job.state.freetext.as_str());
+                }
+                ui.end_row();
+
+                let mut override_format = overrides.output_format.is_some();
+                ui.checkbox(&mut override_format, t!("output.output_format"));
+                if override_format {
+                    let output_format = overrides
+                        .output_format
+                        .get_or_insert(job.state.output_format);
+                    ui.horizontal(|ui| {
+                        for format in OutputFormat::ALL {
+                            ui.selectable_value(output_format, format, format.as_str());
+                        }
+                    });
+                } else {
+                    overrides.output_format = None;
+                    ui.weak(job.state.output_format.as_str());
+                }
+                ui.end_row();
+
+                let mut preview = job.clone();
+                preview.overrides = overrides.clone();
+                ui.label(t!("output.output_filename"));
+                match preview.effective_state().assemble_output_filename() {
+                    Ok(output_filename) => {
+                        ui.monospace(output_filename.to_string_lossy());
+                    }
+                    Err(why) => {
+                        ui.colored_label(ui.visuals().error_fg_color, why.to_string());
+                    }
+                }
+                ui.end_row();
+            });
+
+        if let Some(error) = &edit.error {
+            ui.colored_label(ui.visuals().error_fg_color, error);
+        }
+        ui.horizontal(|ui| {
+            if ui.button(t!("queue.apply_overrides")).clicked() {
+                match jobqueue::set_job_overrides(edit.job_id, edit.overrides.clone()) {
+                    Ok(()) => close = true,
+                    Err(why) => edit.error = Some(why.to_string()),
+                }
+            }
+            if ui.button(t!("queue.clear_overrides")).clicked() {
+                edit.overrides = JobOverrides::default();
+            }
+            if ui.button(t!("cancel")).clicked() {
+                close = true;
+            }
+        });
+
+        if close {
+            self.editing = None;
+        }
+    }
+}
@@ -0,0 +1,267 @@
+//! Measures the memory, CPU time and file reads of processing runs, so users can see what a
+//! choice of frame count or drizzle scale costs on their hardware. Counters are process wide, so
+//! runs overlapping other work are charged for it too.
+
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::memory;
+
+/// How often memory is read while a run is in progress
+const SAMPLE_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Resources used by a run. Each is None if it can't be measured on this platform.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ResourceUsage {
+    pub peak_memory_bytes: Option<u64>,
+    pub cpu_secs: Option<f64>,
+    pub bytes_read: Option<u64>,
+}
+
+impl ResourceUsage {
+    pub fn is_empty(&self) -> bool {
+        self.peak_memory_bytes.is_none() && self.cpu_secs.is_none() && self.bytes_read.is_none()
+    }
+
+    pub fn peak_memory_text(&self) -> String {
+        self.peak_memory_bytes
+            .map(|b| format_bytes(b as usize))
+            .unwrap_or_else(|| "-".to_owned())
+    }
+
+    pub fn cpu_time_text(&self) -> String {
+        self.cpu_secs
+            .map(|s| format!("{:.1} s", s))
+            .unwrap_or_else(|| "-".to_owned())
+    }
+
+    pub fn bytes_read_text(&self) -> String {
+        self.bytes_read
+            .map(|b| format_bytes(b as usize))
+            .unwrap_or_else(|| "-".to_owned())
+    }
+}
+
+pub fn format_bytes(bytes: usize) -> String {
+    let units = ["B", "KB", "MB", "GB", "TB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < units.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, units[unit])
+}
+
+/// CPU time and bytes read by the process so far
+#[derive(Debug, Clone, Copy, Default)]
+struct ProcessCounters {
+    cpu_secs: Option<f64>,
+    bytes_read: Option<u64>,
+}
+
+/// Measures resource use from when it's started until it's finished. Memory is sampled on a
+/// thread of its own, as the run keeps the async task busy.
+pub struct ResourceMonitor {
+    start: ProcessCounters,
+    peak_memory: Arc<AtomicU64>,
+    running: Arc<AtomicBool>,
+    sampler: Option<JoinHandle<()>>,
+}
+
+impl ResourceMonitor {
+    pub fn start() -> Self {
+        let peak_memory = Arc::new(AtomicU64::new(0));
+        let running = Arc::new(AtomicBool::new(true));
+        let sampler = {
+            let peak_memory = peak_memory.clone();
+            let running = running.clone();
+            std::thread::spawn(move || {
+                while running.load(Ordering::Relaxed) {
+                    if let Some(status) = memory::read_memory_status() {
+                        peak_memory.fetch_max(status.process_bytes, Ordering::Relaxed);
+                    }
+                    std::thread::sleep(SAMPLE_INTERVAL);
+                }
+            })
+        };
+        ResourceMonitor {
+            start: platform::read_counters(),
+            peak_memory,
+            running,
+            sampler: Some(sampler),
+        }
+    }
+
+    pub fn finish(mut self) -> ResourceUsage {
+        self.stop();
+        if let Some(status) = memory::read_memory_status() {
+            self.peak_memory
+                .fetch_max(status.process_bytes, Ordering::Relaxed);
+        }
+        let end = platform::read_counters();
+        let peak_memory = self.peak_memory.load(Ordering::Relaxed);
+        ResourceUsage {
+            peak_memory_bytes: (peak_memory > 0).then_some(peak_memory),
+            cpu_secs: end
+                .cpu_secs
+                .zip(self.start.cpu_secs)
+                .map(|(end, start)| (end - start).max(0.0)),
+            bytes_read: end
+                .bytes_read
+                .zip(self.start.bytes_read)
+                .map(|(end, start)| end.saturating_sub(start)),
+        }
+    }
+
+    fn stop(&mut self) {
+        self.running.store(false, Ordering::Relaxed);
+        if let Some(sampler) = self.sampler.take() {
+            let _ = sampler.join();
+        }
+    }
+}
+
+impl Drop for ResourceMonitor {
+    // Runs that fail part way through drop the monitor without finishing it
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod platform {
+    use super::ProcessCounters;
+
+    /// Clock ticks per second of the times in /proc/self/stat, which is 100 on all common
+    /// architectures
+    const CLOCK_TICKS: f64 = 100.0;
+
+    /// User plus system time from /proc/self/stat. The command name, which may hold spaces, is
+    /// skipped by starting after its closing parenthesis.
+    fn cpu_secs() -> Option<f64> {
+        let stat = std::fs::read_to_string("/proc/self/stat").ok()?;
+        let fields: Vec<&str> = stat.rsplit_once(')')?.1.split_whitespace().collect();
+        let utime: u64 = fields.get(11)?.parse().ok()?;
+        let stime: u64 = fields.get(12)?.parse().ok()?;
+        Some((utime + stime) as f64 / CLOCK_TICKS)
+    }
+
+    /// Bytes read through read calls, whether from the disk or the page cache
+    fn bytes_read() -> Option<u64> {
+        std::fs::read_to_string("/proc/self/io")
+            .ok()?
+            .lines()
+            .find_map(|line| line.strip_prefix("rchar:"))
+            .and_then(|value| value.trim().parse().ok())
+    }
+
+    pub fn read_counters() -> ProcessCounters {
+        ProcessCounters {
+            cpu_secs: cpu_secs(),
+            bytes_read: bytes_read(),
+        }
+    }
+}
+
+#[cfg(target_os = "macos")]
+mod platform {
+    use std::process::Command;
+
+    use super::ProcessCounters;
+
+    /// CPU time from `ps`, formatted as `[[dd-]hh:]mm:ss.ss`
+    fn cpu_secs() -> Option<f64> {
+        let pid = std::process::id().to_string();
+        let output = Command::new("ps")
+            .args(["-o", "time=", "-p", &pid])
+            .output()
+            .ok()?;
+        let text = String::from_utf8_lossy(&output.stdout);
+        let (days, time) = match text.trim().split_once('-') {
+            Some((days, time)) => (days.parse::<f64>().ok()?, time.to_owned()),
+            None => (0.0, text.trim().to_owned()),
+        };
+        let seconds = time.split(':').try_fold(0.0, |total, part| {
+            Some(total * 60.0 + part.parse::<f64>().ok()?)
+        })?;
+        Some(days * 86400.0 + seconds)
+    }
+
+    pub fn read_counters() -> ProcessCounters {
+        ProcessCounters {
+            cpu_secs: cpu_secs(),
+            bytes_read: None,
+        }
+    }
+}
+
+#[cfg(target_os = "windows")]
+mod platform {
+    use std::ffi::c_void;
+
+    use super::ProcessCounters;
+
+    #[repr(C)]
+    #[derive(Default)]
+    struct FileTime {
+        low: u32,
+        high: u32,
+    }
+
+    impl FileTime {
+        /// Seconds in a duration given in 100 ns units
+        fn secs(&self) -> f64 {
+            (((self.high as u64) << 32) | self.low as u64) as f64 / 10_000_000.0
+        }
+    }
+
+    #[repr(C)]
+    #[derive(Default)]
+    struct IoCounters {
+        _read_operations: u64,
+        _write_operations: u64,
+        _other_operations: u64,
+        read_transfer: u64,
+        _write_transfer: u64,
+        _other_transfer: u64,
+    }
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn GetCurrentProcess() -> *mut c_void;
+        fn GetProcessTimes(
+            process: *mut c_void,
+            creation: *mut FileTime,
+            exit: *mut FileTime,
+            kernel: *mut FileTime,
+            user: *mut FileTime,
+        ) -> i32;
+        fn GetProcessIoCounters(process: *mut c_void, counters: *mut IoCounters) -> i32;
+    }
+
+    pub fn read_counters() -> ProcessCounters {
+        let mut creation = FileTime::default();
+        let mut exit = FileTime::default();
+        let mut kernel = FileTime::default();
+        let mut user = FileTime::default();
+        let mut io = IoCounters::default();
+        unsafe {
+            let process = GetCurrentProcess();
+            let cpu_secs =
+                (GetProcessTimes(process, &mut creation, &mut exit, &mut kernel, &mut user) != 0)
+                    .then(|| kernel.secs() + user.secs());
+            let bytes_read =
+                (GetProcessIoCounters(process, &mut io) != 0).then_some(io.read_transfer);
+            ProcessCounters {
+                cpu_secs,
+                bytes_read,
+            }
+        }
+    }
+}
@@ -1,11 +1,22 @@
+use crate::changes;
+use crate::filters::denoise::DenoiseMethod;
+use crate::filters::gradient::{self, BackgroundModelKind};
+use crate::filters::mask::SharpeningMask;
+use crate::filters::palette::{ColorStop, Palette, PaletteOptions};
+use crate::filters::tonemap::ToneMapping;
+use crate::filters::wavelets::WaveletOptions;
+use crate::filters::{self, FilterSettings};
+use crate::fits;
 use crate::histogram::Histogram;
 use crate::imageutil;
+use crate::partialfile;
+use crate::process::contribution::StackGeometry;
 use crate::process::RunResultsContainer;
+use crate::state::{AnalysisBinning, ApplicationState};
 use crate::toggle::toggle;
 use anyhow::{Error, Result};
 use egui::Ui;
-use sciimg::prelude::Image;
-use sciimg::unsharp::RgbImageUnsharpMask;
+use epaint::Color32;
 use std::fmt;
 use std::path::PathBuf;
 
@@ -25,17 +36,78 @@ impl fmt::Display for ZoomType {
     }
 }
 
+/// Draws a strip along the capture timeline colored by the fraction of frames used (green) versus
+/// rejected (red) in each column.
+fn frame_usage_strip(ui: &mut Ui, frame_usage: &[bool]) {
+    let num_used = frame_usage.iter().filter(|u| **u).count();
+    ui.horizontal(|ui| {
+        ui.label(t!(
+            "results.frames_used_of",
+            used = num_used,
+            total = frame_usage.len()
+        ));
+        let desired_size = egui::vec2(ui.available_width(), 14.0);
+        let (rect, response) = ui.allocate_exact_size(desired_size, egui::Sense::hover());
+        let columns = (rect.width().floor() as usize).clamp(1, frame_usage.len());
+        let column_width = rect.width() / columns as f32;
+        let painter = ui.painter();
+        (0..columns).for_each(|c| {
+            let start = c * frame_usage.len() / columns;
+            let end = ((c + 1) * frame_usage.len() / columns).max(start + 1);
+            let used = frame_usage[start..end].iter().filter(|u| **u).count();
+            let fraction = used as f32 / (end - start) as f32;
+            let color = Color32::from_rgb(
+                ((1.0 - fraction) * 200.0) as u8,
+                (fraction * 200.0) as u8,
+                40,
+            );
+            let x = rect.left() + c as f32 * column_width;
+            painter.rect_filled(
+                egui::Rect::from_min_max(
+                    egui::pos2(x, rect.top()),
+                    egui::pos2(x + column_width, rect.bottom()),
+                ),
+                0.0,
+                color,
+            );
+        });
+        if let Some(pos) = response.hover_pos() {
+            let frame_no = (((pos.x - rect.left()) / rect.width()) * frame_usage.len() as f32)
+                .clamp(0.0, (frame_usage.len() - 1) as f32) as usize;
+            response.on_hover_text(t!(
+                "results.frame_status",
+                frame = frame_no,
+                status = if frame_usage[frame_no] {
+                    t!("results.frame_used")
+                } else {
+                    t!("results.frame_rejected")
+                }
+            ));
+        }
+    });
+}
+
 pub struct ResultViewPane {
     texture_handle: Option<egui::TextureHandle>,
     texture_name: String,
     results: Option<RunResultsContainer>,
     histogram: Histogram,
-    exposure: f64,
-    gamma: f64,
-    unsharp_mask: bool,
-    unsharp_sigma: f64,
-    unsharp_amount: f64,
+    filters: FilterSettings,
+    show_sharpening_mask: bool,
+    placing_gradient_points: bool,
+    /// Pixel the context menu was opened over
+    menu_point: Option<[usize; 2]>,
+    /// Pixel whose frame contributions the user asked to inspect
+    inspection_request: Option<(StackGeometry, [usize; 2])>,
+    /// Adjusted image the user asked to publish
+    #[cfg(feature = "publish")]
+    publish_request: Option<sciimg::prelude::Image>,
+    selected_filter_preset: Option<String>,
+    new_filter_preset_name: String,
     zoom: ZoomType,
+    /// Binning of the adjusted result when it's displayed. Saved and published images keep the
+    /// stack's full resolution.
+    downscale: usize,
 }
 
 impl Default for ResultViewPane {
@@ -44,18 +116,28 @@ impl Default for ResultViewPane {
             texture_handle: None,
             texture_name: imageutil::gen_random_texture_name(),
             results: None,
-            histogram: Histogram::new(1500, 0.0, 65536.0),
-            exposure: 0.0,
-            gamma: 1.0,
-            unsharp_mask: false,
-            unsharp_amount: 1.0,
-            unsharp_sigma: 1.3,
+            histogram: Histogram::for_pixel_depth(16),
+            filters: FilterSettings::default(),
+            show_sharpening_mask: false,
+            placing_gradient_points: false,
+            menu_point: None,
+            inspection_request: None,
+            #[cfg(feature = "publish")]
+            publish_request: None,
+            selected_filter_preset: None,
+            new_filter_preset_name: String::new(),
             zoom: ZoomType::Fit,
+            downscale: 1,
         }
     }
 }
 
 impl ResultViewPane {
+    /// Options the displayed result was made with
+    pub fn options(&self) -> Option<&ApplicationState> {
+        self.results.as_ref().and_then(|r| r.options.as_ref())
+    }
+
     pub fn is_empty(&self) -> bool {
         self.texture_handle.is_none()
     }
@@ -73,24 +155,23 @@ impl ResultViewPane {
         }
     }
 
-    fn apply_filters(&self, image: &Image) -> Image {
-        let mut image_adjusted = image.clone();
-
-        image_adjusted.levels_with_gamma(0.0, 1.0 - self.exposure as f32, 1.0 / self.gamma as f32);
-
-        if self.unsharp_mask {
-            image_adjusted.unsharp_mask(self.unsharp_sigma as f32, self.unsharp_amount as f32);
-        }
-
-        image_adjusted
-    }
-
     fn update_texture(&mut self, ctx: &egui::Context) -> Result<()> {
         if let Some(results) = &self.results {
-            if results.image.is_some() {
-                let image_adjusted = self.apply_filters(&results.image.clone().unwrap());
-
-                let cimage = imageutil::sciimg_to_color_image(&image_adjusted);
+            if let Some(image) = &results.image {
+                let cimage = if self.filters.sharpens() && self.show_sharpening_mask {
+                    let image_adjusted = self.filters.apply_adjustments(image);
+                    let sharpened = self.filters.sharpened_copy(&image_adjusted);
+                    let weights = self
+                        .filters
+                        .sharpening_weights(&image_adjusted, &sharpened)
+                        .unwrap_or_else(|| vec![1.0; image.width * image.height]);
+                    imageutil::weights_to_color_image(image.width, image.height, &weights)
+                } else {
+                    imageutil::sciimg_to_color_image(&imageutil::downscale_image(
+                        &self.filters.apply(image),
+                        self.downscale,
+                    ))
+                };
                 self.texture_handle =
                     Some(ctx.load_texture(&self.texture_name, cimage, Default::default()));
             }
@@ -100,6 +181,19 @@ impl ResultViewPane {
         }
     }
 
+    /// Sets the binning factor applied to the displayed texture, reloading it if the factor
+    /// changed. Saved images are unaffected.
+    pub fn set_downscale(&mut self, ctx: &egui::Context, factor: usize) -> Result<()> {
+        let factor = factor.max(1);
+        if factor != self.downscale {
+            self.downscale = factor;
+            if self.texture_handle.is_some() {
+                self.update_texture(ctx)?;
+            }
+        }
+        Ok(())
+    }
+
     pub fn set_image(&mut self, results: &RunResultsContainer, ctx: &egui::Context) -> Result<()> {
         self.results = Some(results.clone());
         self.update_texture(ctx)?;
@@ -124,6 +218,53 @@ impl ResultViewPane {
         //     });
         // }
 
+        if let Some(results) = &self.results {
+            if results.analysis_binning != AnalysisBinning::None {
+                ui.horizontal(|ui| {
+                    ui.label(t!("results.binned_analysis"));
+                    ui.label(format!(
+                        "{}x{}",
+                        results.analysis_binning.factor(),
+                        results.analysis_binning.factor()
+                    ));
+                });
+            }
+        }
+
+        if let Some(results) = &self.results {
+            if !results.resources.is_empty() {
+                ui.label(t!(
+                    "results.resources",
+                    memory = results.resources.peak_memory_text(),
+                    cpu = results.resources.cpu_time_text(),
+                    read = results.resources.bytes_read_text()
+                ));
+            }
+        }
+
+        if let Some(results) = &self.results {
+            if !results.saved_masters.is_empty() {
+                egui::CollapsingHeader::new(t!(
+                    "results.saved_masters",
+                    count = results.saved_masters.len()
+                ))
+                .id_source("saved_masters")
+                .show(ui, |ui| {
+                    results.saved_masters.iter().for_each(|path| {
+                        ui.monospace(path.to_string_lossy());
+                    });
+                });
+            }
+        }
+
+        if let Some(results) = &self.results {
+            if !results.frame_usage.is_empty() {
+                frame_usage_strip(ui, &results.frame_usage);
+            }
+        }
+
+        self.filter_presets_ui(ui);
+
         let refresh_icon = egui::include_image!("../assets/refresh.svg");
 
         ui.horizontal(|ui| {
@@ -135,7 +276,7 @@ impl ResultViewPane {
                     .show(ui, |ui| {
                         ui.label(t!("results.exposure"));
                         if ui
-                            .add(egui::Slider::new(&mut self.exposure, 0.01..=0.99))
+                            .add(egui::Slider::new(&mut self.filters.exposure, 0.01..=0.99))
                             .changed()
                         {
                             self.update_texture(ui.ctx()).unwrap();
@@ -147,7 +288,7 @@ impl ResultViewPane {
                             ))
                             .clicked()
                         {
-                            self.exposure = 0.0;
+                            self.filters.exposure = 0.0;
                             self.update_texture(ui.ctx()).unwrap();
                         }
 
@@ -155,7 +296,7 @@ impl ResultViewPane {
 
                         ui.label(t!("results.gamma"));
                         if ui
-                            .add(egui::Slider::new(&mut self.gamma, 0.05..=10.0))
+                            .add(egui::Slider::new(&mut self.filters.gamma, 0.05..=10.0))
                             .changed()
                         {
                             self.update_texture(ui.ctx()).unwrap();
@@ -167,7 +308,7 @@ impl ResultViewPane {
                             ))
                             .clicked()
                         {
-                            self.gamma = 1.0;
+                            self.filters.gamma = 1.0;
                             self.update_texture(ui.ctx()).unwrap();
                         }
                         ui.end_row();
@@ -192,15 +333,23 @@ impl ResultViewPane {
                             });
 
                         ui.end_row();
+
+                        self.denoise_options_ui(ui);
+                        self.tone_mapping_ui(ui);
+                        self.wavelets_ui(ui);
+
                         ui.label(t!("results.unsharp_masking"));
-                        if ui.add(toggle(&mut self.unsharp_mask)).changed() {
+                        if ui.add(toggle(&mut self.filters.unsharp_mask)).changed() {
                             self.update_texture(ui.ctx()).unwrap();
                         }
                         ui.end_row();
 
                         ui.label(t!("results.sigma"));
                         if ui
-                            .add(egui::Slider::new(&mut self.unsharp_sigma, 0.05..=10.0))
+                            .add(egui::Slider::new(
+                                &mut self.filters.unsharp_sigma,
+                                0.05..=10.0,
+                            ))
                             .changed()
                         {
                             self.update_texture(ui.ctx()).unwrap();
@@ -209,19 +358,441 @@ impl ResultViewPane {
 
                         ui.label(t!("results.amount"));
                         if ui
-                            .add(egui::Slider::new(&mut self.unsharp_amount, 0.0..=100.0))
+                            .add(egui::Slider::new(
+                                &mut self.filters.unsharp_amount,
+                                0.0..=100.0,
+                            ))
                             .changed()
                         {
                             self.update_texture(ui.ctx()).unwrap();
                         }
+                        ui.end_row();
+
+                        self.sharpening_mask_ui(ui);
+                        self.palette_ui(ui);
+
+                        self.gradient_options_ui(ui);
                     });
             });
-            self.histogram.ui(ui);
+            if self.histogram.ui(ui) {
+                if let Err(why) = self.update_histogram() {
+                    error!("Failed to update histogram: {}", why);
+                }
+            }
         });
 
         Ok(())
     }
 
+    /// Loading and saving the filter settings as named presets
+    fn filter_presets_ui(&mut self, ui: &mut Ui) {
+        ui.horizontal(|ui| {
+            ui.label(t!("results.filter_preset"));
+            let mut load = None;
+            egui::ComboBox::from_id_source("filter_preset")
+                .selected_text(self.selected_filter_preset.clone().unwrap_or_default())
+                .show_ui(ui, |ui| {
+                    for name in filters::list_filter_presets() {
+                        let selected = self.selected_filter_preset.as_ref() == Some(&name);
+                        if ui.selectable_label(selected, &name).clicked() {
+                            load = Some(name);
+                        }
+                    }
+                });
+            if let Some(name) = load {
+                match filters::load_filter_preset(&name) {
+                    Ok(settings) => {
+                        // Keep any sample points placed on this image
+                        let points = self.filters.gradient.points.clone();
+                        self.filters = settings;
+                        self.filters.gradient.points = points;
+                        self.selected_filter_preset = Some(name);
+                        self.update_texture(ui.ctx()).unwrap();
+                    }
+                    Err(why) => error!("Failed to load filter preset '{}': {}", name, why),
+                }
+            }
+
+            ui.separator();
+            ui.add(
+                egui::TextEdit::singleline(&mut self.new_filter_preset_name)
+                    .hint_text(t!("results.filter_preset_name"))
+                    .desired_width(120.0),
+            );
+            if ui
+                .add_enabled(
+                    !self.new_filter_preset_name.trim().is_empty(),
+                    egui::Button::new(t!("results.save_filter_preset")),
+                )
+                .clicked()
+            {
+                let name = self.new_filter_preset_name.trim().to_owned();
+                match filters::save_filter_preset(&name, &self.filters) {
+                    Ok(_) => {
+                        self.selected_filter_preset = Some(name);
+                        self.new_filter_preset_name.clear();
+                    }
+                    Err(why) => error!("Failed to save filter preset '{}': {}", name, why),
+                }
+            }
+        });
+    }
+
+    fn denoise_options_ui(&mut self, ui: &mut Ui) {
+        let mut changed = false;
+
+        ui.label(t!("results.noise_reduction"));
+        egui::ComboBox::from_id_source("denoise_method")
+            .selected_text(self.filters.denoise.method.to_string())
+            .show_ui(ui, |ui| {
+                DenoiseMethod::all().into_iter().for_each(|method| {
+                    changed |= ui
+                        .selectable_value(
+                            &mut self.filters.denoise.method,
+                            method,
+                            method.to_string(),
+                        )
+                        .changed();
+                });
+            });
+        ui.end_row();
+
+        ui.label(t!("results.denoise_sigma"));
+        changed |= ui
+            .add_enabled(
+                self.filters.denoise.method.uses_sigma(),
+                egui::Slider::new(&mut self.filters.denoise.sigma, 0.3..=5.0),
+            )
+            .changed();
+        ui.end_row();
+
+        ui.label(t!("results.denoise_strength"));
+        changed |= ui
+            .add_enabled(
+                self.filters.denoise.method.uses_strength(),
+                egui::Slider::new(&mut self.filters.denoise.strength, 0.001..=0.3)
+                    .logarithmic(true),
+            )
+            .changed();
+        ui.end_row();
+
+        if changed {
+            self.update_texture(ui.ctx()).unwrap();
+        }
+    }
+
+    /// Wavelet sharpening, with the gain and noise threshold of each layer on its own row
+    fn wavelets_ui(&mut self, ui: &mut Ui) {
+        let mut changed = false;
+
+        ui.label(t!("results.wavelets"));
+        changed |= ui
+            .add(toggle(&mut self.filters.wavelets.enabled))
+            .on_hover_text(t!("results.wavelets_hint"))
+            .changed();
+        if ui
+            .add_enabled(
+                self.filters.wavelets != WaveletOptions::default(),
+                egui::Button::image_and_text(
+                    egui::include_image!("../assets/refresh.svg"),
+                    t!("results.reset"),
+                ),
+            )
+            .clicked()
+        {
+            self.filters.wavelets = WaveletOptions {
+                enabled: self.filters.wavelets.enabled,
+                ..Default::default()
+            };
+            changed = true;
+        }
+        ui.end_row();
+
+        if self.filters.wavelets.enabled {
+            self.filters
+                .wavelets
+                .layers
+                .iter_mut()
+                .enumerate()
+                .for_each(|(n, layer)| {
+                    ui.label(t!("results.wavelet_layer", layer = n + 1, scale = 1 << n));
+                    changed |= ui
+                        .add(
+                            egui::Slider::new(&mut layer.gain, 0.0..=20.0)
+                                .text(t!("results.wavelet_gain")),
+                        )
+                        .changed();
+                    changed |= ui
+                        .add(
+                            egui::Slider::new(&mut layer.denoise, 0.0..=3.0)
+                                .text(t!("results.wavelet_denoise")),
+                        )
+                        .on_hover_text(t!("results.wavelet_denoise_hint"))
+                        .changed();
+                    ui.end_row();
+                });
+        }
+
+        if changed {
+            self.update_texture(ui.ctx()).unwrap();
+        }
+    }
+
+    fn tone_mapping_ui(&mut self, ui: &mut Ui) {
+        let mut changed = false;
+
+        ui.label(t!("results.tone_mapping"));
+        egui::ComboBox::from_id_source("tone_mapping")
+            .selected_text(self.filters.tone_mapping.to_string())
+            .show_ui(ui, |ui| {
+                ToneMapping::all().into_iter().for_each(|m| {
+                    changed |= ui
+                        .selectable_value(&mut self.filters.tone_mapping, m, m.to_string())
+                        .changed();
+                });
+            });
+        ui.end_row();
+
+        ui.label(t!("results.tone_mapping_strength"));
+        changed |= ui
+            .add_enabled(
+                self.filters.tone_mapping != ToneMapping::None,
+                egui::Slider::new(&mut self.filters.tone_mapping_strength, 0.1..=1000.0)
+                    .logarithmic(true),
+            )
+            .changed();
+        ui.end_row();
+
+        if changed {
+            self.update_texture(ui.ctx()).unwrap();
+        }
+    }
+
+    /// Palette choice, with the stops of the custom gradient on their own rows when it's chosen
+    fn palette_ui(&mut self, ui: &mut Ui) {
+        let mut changed = false;
+
+        ui.label(t!("results.palette"));
+        egui::ComboBox::from_id_source("palette")
+            .selected_text(self.filters.palette.palette.to_string())
+            .show_ui(ui, |ui| {
+                Palette::all().into_iter().for_each(|p| {
+                    changed |= ui
+                        .selectable_value(&mut self.filters.palette.palette, p, p.to_string())
+                        .changed();
+                });
+            })
+            .response
+            .on_hover_text(t!("results.palette_hint"));
+        ui.end_row();
+
+        if self.filters.palette.palette == Palette::Custom {
+            let mut remove = None;
+            let stop_count = self.filters.palette.custom.len();
+            self.filters
+                .palette
+                .custom
+                .iter_mut()
+                .enumerate()
+                .for_each(|(n, stop)| {
+                    ui.label(t!("results.palette_stop", stop = n + 1));
+                    ui.horizontal(|ui| {
+                        changed |= ui.color_edit_button_srgb(&mut stop.color).changed();
+                        changed |= ui
+                            .add(egui::Slider::new(&mut stop.position, 0.0..=1.0))
+                            .changed();
+                        if stop_count > 2 && ui.small_button("✖").clicked() {
+                            remove = Some(n);
+                        }
+                    });
+                    ui.end_row();
+                });
+            if let Some(n) = remove {
+                self.filters.palette.custom.remove(n);
+                changed = true;
+            }
+            ui.label("");
+            ui.horizontal(|ui| {
+                if ui.button(t!("results.palette_add_stop")).clicked() {
+                    self.filters.palette.custom.push(ColorStop {
+                        position: 0.5,
+                        color: [128, 128, 128],
+                    });
+                    changed = true;
+                }
+                if ui.button(t!("results.palette_reset")).clicked() {
+                    self.filters.palette.custom = PaletteOptions::default().custom;
+                    changed = true;
+                }
+            });
+            ui.end_row();
+        }
+
+        if changed {
+            self.update_texture(ui.ctx()).unwrap();
+        }
+    }
+
+    fn sharpening_mask_ui(&mut self, ui: &mut Ui) {
+        let mut changed = false;
+
+        ui.label(t!("results.sharpening_mask"));
+        egui::ComboBox::from_id_source("sharpening_mask")
+            .selected_text(self.filters.sharpening_mask.to_string())
+            .show_ui(ui, |ui| {
+                SharpeningMask::all().into_iter().for_each(|m| {
+                    changed |= ui
+                        .selectable_value(&mut self.filters.sharpening_mask, m, m.to_string())
+                        .changed();
+                });
+            });
+        ui.end_row();
+
+        ui.label(t!("results.mask_feather"));
+        changed |= ui
+            .add_enabled(
+                self.filters.sharpening_mask != SharpeningMask::Everywhere,
+                egui::Slider::new(&mut self.filters.mask_feather, 1.0..=100.0),
+            )
+            .changed();
+        ui.end_row();
+
+        ui.label(t!("results.edge_threshold"));
+        changed |= ui
+            .add(egui::Slider::new(
+                &mut self.filters.edge_threshold,
+                0.0..=0.1,
+            ))
+            .on_hover_text(t!("results.edge_threshold_hint"))
+            .changed();
+        ui.end_row();
+
+        ui.label(t!("results.show_sharpening_mask"));
+        changed |= ui.add(toggle(&mut self.show_sharpening_mask)).changed();
+        ui.end_row();
+
+        if changed {
+            self.update_texture(ui.ctx()).unwrap();
+        }
+    }
+
+    fn gradient_options_ui(&mut self, ui: &mut Ui) {
+        let mut changed = false;
+
+        ui.label(t!("results.gradient_removal"));
+        changed |= ui.add(toggle(&mut self.filters.gradient_removal)).changed();
+        ui.end_row();
+
+        ui.label(t!("results.gradient_model"));
+        egui::ComboBox::from_id_source("gradient_model")
+            .selected_text(self.filters.gradient.model.to_string())
+            .show_ui(ui, |ui| {
+                BackgroundModelKind::all().into_iter().for_each(|m| {
+                    changed |= ui
+                        .selectable_value(&mut self.filters.gradient.model, m, m.to_string())
+                        .changed();
+                });
+            })
+            .response
+            .on_hover_text(t!("results.gradient_model_hint"));
+        ui.end_row();
+
+        if self.filters.gradient.model == BackgroundModelKind::Polynomial {
+            ui.label(t!("results.gradient_order"));
+            changed |= ui
+                .add(egui::Slider::new(&mut self.filters.gradient.order, 1..=4))
+                .changed();
+            ui.end_row();
+        }
+
+        ui.label(t!("results.gradient_samples"));
+        if self.filters.gradient.points.is_empty() {
+            changed |= ui
+                .add(
+                    egui::Slider::new(&mut self.filters.gradient.grid_size, 3..=16)
+                        .text(t!("results.gradient_auto_grid")),
+                )
+                .changed();
+        } else {
+            ui.label(t!(
+                "results.gradient_points_placed",
+                count = self.filters.gradient.points.len()
+            ));
+        }
+        ui.horizontal(|ui| {
+            ui.toggle_value(
+                &mut self.placing_gradient_points,
+                t!("results.gradient_place_points"),
+            )
+            .on_hover_text(t!("results.gradient_place_points_hint"));
+            if ui
+                .add_enabled(
+                    !self.filters.gradient.points.is_empty(),
+                    egui::Button::new(t!("results.gradient_clear_points")),
+                )
+                .clicked()
+            {
+                self.filters.gradient.points.clear();
+                changed = true;
+            }
+        });
+        ui.end_row();
+
+        ui.label(t!("results.gradient_show_model"));
+        changed |= ui
+            .add(toggle(&mut self.filters.show_background_model))
+            .changed();
+        ui.end_row();
+
+        if changed {
+            self.update_texture(ui.ctx()).unwrap();
+        }
+    }
+
+    /// Maps a screen position within the displayed image to image pixel coordinates
+    fn image_coordinates(&self, rect: egui::Rect, pos: egui::Pos2) -> Option<[usize; 2]> {
+        let image = self.results.as_ref()?.image.as_ref()?;
+        let x = (pos.x - rect.left()) / rect.width() * image.width as f32;
+        let y = (pos.y - rect.top()) / rect.height() * image.height as f32;
+        if x >= 0.0 && y >= 0.0 && (x as usize) < image.width && (y as usize) < image.height {
+            Some([x as usize, y as usize])
+        } else {
+            None
+        }
+    }
+
+    /// Draws the gradient sample points over the displayed image
+    fn draw_gradient_points(&self, ui: &Ui, rect: egui::Rect) {
+        let Some(image) = self.results.as_ref().and_then(|r| r.image.as_ref()) else {
+            return;
+        };
+        let points = gradient::sample_points(image, &self.filters.gradient);
+        let painter = ui.painter_at(rect);
+        points.iter().for_each(|[x, y]| {
+            let pos = egui::pos2(
+                rect.left() + *x as f32 / image.width as f32 * rect.width(),
+                rect.top() + *y as f32 / image.height as f32 * rect.height(),
+            );
+            painter.circle_stroke(pos, 5.0, egui::Stroke::new(1.5, Color32::YELLOW));
+        });
+    }
+
+    /// Takes the stack geometry and pixel of a requested contribution inspection, if one was
+    /// requested since the last call
+    pub fn take_inspection_request(&mut self) -> Option<(StackGeometry, [usize; 2])> {
+        self.inspection_request.take()
+    }
+
+    /// Takes the adjusted image the user asked to publish, with the results it came from, if
+    /// publishing was requested since the last call
+    #[cfg(feature = "publish")]
+    pub fn take_publish_request(
+        &mut self,
+    ) -> Option<(sciimg::prelude::Image, &RunResultsContainer)> {
+        let image = self.publish_request.take()?;
+        Some((image, self.results.as_ref()?))
+    }
+
     fn get_output_path(&self) -> PathBuf {
         if let Some(results) = &self.results {
             if results.output_filename.is_some() {
@@ -236,18 +807,68 @@ impl ResultViewPane {
 }
 
 impl ResultViewPane {
-    pub fn ui(&mut self, ui: &mut Ui) {
+    /// Banner shown while the options differ from those the displayed result was made with
+    fn settings_differ_ui(&self, ui: &mut Ui, current: &ApplicationState) {
+        let Some(last) = self.options() else {
+            return;
+        };
+        if !changes::settings_differ(last, current) {
+            return;
+        }
+        let mut hint = vec![t!("results.settings_differ_hint").to_string()];
+        hint.extend(
+            changes::changed_options(last, current)
+                .into_iter()
+                .map(|key| format!("• {}", t!(key))),
+        );
+        ui.colored_label(ui.visuals().warn_fg_color, t!("results.settings_differ"))
+            .on_hover_text(hint.join("\n"));
+    }
+
+    /// Shows the result, with a banner if `current`, the options as they are now, differ from
+    /// those the result was made with
+    pub fn ui(&mut self, ui: &mut Ui, current: &ApplicationState) {
+        self.settings_differ_ui(ui, current);
         self.options_ui(ui).unwrap();
-        if let Some(handle) = &self.texture_handle {
+        if let Some(handle) = self.texture_handle.clone() {
             //egui::ScrollArea::both().show(ui, |ui| {
 
+            let mut placed_point = None;
             egui::ScrollArea::both().show(ui, |ui| {
-                let image = egui::Image::from_texture(handle);
-                ui.add(match self.zoom {
+                let image = egui::Image::from_texture(&handle).sense(egui::Sense::click());
+                let response = ui.add(match self.zoom {
                     ZoomType::Fit => image.shrink_to_fit(),
                     ZoomType::FullSize => image,
-                })
-                .context_menu(|ui| {
+                });
+
+                if self.filters.gradient_removal {
+                    self.draw_gradient_points(ui, response.rect);
+                }
+                if response.secondary_clicked() {
+                    self.menu_point = response
+                        .interact_pointer_pos()
+                        .and_then(|pos| self.image_coordinates(response.rect, pos));
+                }
+                if self.placing_gradient_points && response.clicked() {
+                    placed_point = response
+                        .interact_pointer_pos()
+                        .and_then(|pos| self.image_coordinates(response.rect, pos));
+                }
+
+                response.context_menu(|ui| {
+                    let geometry = self.results.as_ref().and_then(|r| r.geometry.as_ref());
+                    if let (Some(geometry), Some(point)) = (geometry, self.menu_point) {
+                        if ui.button(t!("contribution.inspect")).clicked() {
+                            self.inspection_request = Some((geometry.clone(), point));
+                            ui.close_menu();
+                        }
+                    }
+                    if let Some(results) = &mut self.results {
+                        ui.horizontal(|ui| {
+                            ui.label(t!("results.jpeg_quality"));
+                            ui.add(egui::Slider::new(&mut results.jpeg_quality, 1..=100));
+                        });
+                    }
                     if ui.button(t!("results.save_as")).clicked() {
                         let output_path = self.get_output_path();
                         let filename = output_path.file_name().unwrap();
@@ -257,6 +878,9 @@ impl ResultViewPane {
                             .set_directory(output_path.parent().unwrap())
                             .set_file_name(filename.to_string_lossy())
                             .add_filter("TIFF", &["tif"])
+                            .add_filter("PNG", &["png"])
+                            .add_filter("JPEG", &["jpg", "jpeg"])
+                            .add_filter("FITS", fits::FITS_EXTENSIONS)
                             .save_file()
                         {
                             println!("Saving To Path: {:?}", path);
@@ -264,11 +888,12 @@ impl ResultViewPane {
                             if let Some(results) = &self.results {
                                 if results.image.is_some() {
                                     let image_adjusted =
-                                        self.apply_filters(&results.image.clone().unwrap());
+                                        self.filters.apply(&results.image.clone().unwrap());
 
-                                    image_adjusted
-                                        .save(path.to_string_lossy().as_ref())
-                                        .expect("Failed to save image");
+                                    partialfile::write_atomically(&path, |partial| {
+                                        results.save_image(&image_adjusted, partial)
+                                    })
+                                    .expect("Failed to save image");
                                 } else {
                                     panic!("Cannot save image: Process resulted in error")
                                 }
@@ -280,8 +905,23 @@ impl ResultViewPane {
                             ui.close_menu();
                         }
                     }
+                    #[cfg(feature = "publish")]
+                    if ui.button(t!("publish.publish")).clicked() {
+                        self.publish_request = self
+                            .results
+                            .as_ref()
+                            .and_then(|r| r.image.as_ref())
+                            .map(|image| self.filters.apply(image));
+                        ui.close_menu();
+                    }
                 });
             });
+
+            if let Some(point) = placed_point {
+                self.filters.gradient.points.push(point);
+                self.filters.gradient_removal = true;
+                self.update_texture(ui.ctx()).unwrap();
+            }
         }
     }
 }
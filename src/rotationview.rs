@@ -0,0 +1,103 @@
+use std::fs;
+use std::path::Path;
+
+use anyhow::Result;
+use egui::Ui;
+use egui_plot::{Legend, Line, LineStyle, Plot, PlotPoints};
+use epaint::Color32;
+
+/// Plot and table of the computed per-frame rotation angles of the last run
+#[derive(Clone, Default)]
+pub struct RotationChart {
+    /// (frame id, rotation in degrees) pairs in stacking order
+    pub rotations: Vec<(usize, f64)>,
+    show_table: bool,
+}
+
+impl RotationChart {
+    pub fn is_empty(&self) -> bool {
+        self.rotations.is_empty()
+    }
+
+    pub fn set_rotations(&mut self, rotations: &[(usize, f64)]) {
+        self.rotations = rotations.to_vec();
+    }
+
+    fn rotation_line(&self) -> Line {
+        let points: PlotPoints = self
+            .rotations
+            .iter()
+            .map(|(frame_id, degrees)| [*frame_id as f64, *degrees])
+            .collect();
+
+        Line::new(points)
+            .color(Color32::LIGHT_BLUE)
+            .style(LineStyle::Solid)
+            .width(2.0)
+            .name(t!("rotation.rotation_degrees"))
+    }
+
+    pub fn save_csv(&self, path: &Path) -> Result<()> {
+        let mut csv = String::from("frame_id,rotation_degrees\n");
+        self.rotations.iter().for_each(|(frame_id, degrees)| {
+            csv += &format!("{},{}\n", frame_id, degrees);
+        });
+        fs::write(path, csv)?;
+        info!("Saved frame rotations to {:?}", path);
+        Ok(())
+    }
+
+    fn options_ui(&mut self, ui: &mut Ui) {
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut self.show_table, t!("rotation.show_table"));
+            if ui.button(t!("rotation.export_csv")).clicked() {
+                if let Some(path) = rfd::FileDialog::new()
+                    .set_title(t!("rotation.export_csv"))
+                    .set_file_name("rotations.csv")
+                    .add_filter("CSV", &["csv"])
+                    .save_file()
+                {
+                    if let Err(why) = self.save_csv(&path) {
+                        error!("Failed to save rotations: {:?}", why);
+                    }
+                }
+            }
+            if let (Some(first), Some(last)) = (self.rotations.first(), self.rotations.last()) {
+                ui.label(t!(
+                    "rotation.span",
+                    span = format!("{:.3}", last.1 - first.1)
+                ));
+            }
+        });
+    }
+
+    pub fn ui(&mut self, ui: &mut Ui) {
+        self.options_ui(ui);
+
+        if self.show_table {
+            egui::ScrollArea::vertical().show(ui, |ui| {
+                egui::Grid::new("rotation_table")
+                    .num_columns(2)
+                    .spacing([40.0, 4.0])
+                    .striped(true)
+                    .show(ui, |ui| {
+                        ui.strong(t!("rotation.frame"));
+                        ui.strong(t!("rotation.rotation_degrees"));
+                        ui.end_row();
+                        self.rotations.iter().for_each(|(frame_id, degrees)| {
+                            ui.label(frame_id.to_string());
+                            ui.label(format!("{:.4}", degrees));
+                            ui.end_row();
+                        });
+                    });
+            });
+        } else {
+            Plot::new("frame_rotation")
+                .legend(Legend::default())
+                .y_axis_width(4)
+                .show(ui, |plot_ui| {
+                    plot_ui.line(self.rotation_line());
+                });
+        }
+    }
+}
@@ -0,0 +1,186 @@
+use std::path::PathBuf;
+
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Duration, Local, NaiveTime, TimeZone};
+use egui::Ui;
+
+use crate::state::ApplicationState;
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum DeferMode {
+    #[default]
+    AfterMinutes,
+    AtTime,
+}
+
+/// Time left until `start_at`, formatted as hours, minutes and seconds
+fn countdown(start_at: DateTime<Local>) -> String {
+    let remaining = (start_at - Local::now()).max(Duration::zero());
+    let seconds = remaining.num_seconds();
+    format!(
+        "{:02}:{:02}:{:02}",
+        seconds / 3600,
+        (seconds / 60) % 60,
+        seconds % 60
+    )
+}
+
+/// A run waiting for its start time, with the options it was scheduled with
+pub struct ScheduledRun {
+    pub start_at: DateTime<Local>,
+    pub state: ApplicationState,
+    pub output_filename: PathBuf,
+}
+
+impl ScheduledRun {
+    pub fn is_due(&self) -> bool {
+        Local::now() >= self.start_at
+    }
+
+    /// Time left until the start, formatted as hours, minutes and seconds
+    pub fn countdown(&self) -> String {
+        countdown(self.start_at)
+    }
+}
+
+/// When a deferred start happens, either after a delay or at a time of day
+pub struct StartTime {
+    pub mode: DeferMode,
+    pub minutes: u32,
+    pub hour: u32,
+    pub minute: u32,
+}
+
+impl Default for StartTime {
+    fn default() -> Self {
+        StartTime {
+            mode: DeferMode::AfterMinutes,
+            minutes: 30,
+            hour: 12,
+            minute: 0,
+        }
+    }
+}
+
+impl StartTime {
+    /// The next start time for the selected mode. A clock time which has already passed today
+    /// is taken to mean tomorrow.
+    fn next(&self) -> Result<DateTime<Local>> {
+        let now = Local::now();
+        match self.mode {
+            DeferMode::AfterMinutes => Ok(now + Duration::minutes(self.minutes as i64)),
+            DeferMode::AtTime => {
+                let time = NaiveTime::from_hms_opt(self.hour, self.minute, 0)
+                    .ok_or_else(|| anyhow!("Invalid start time"))?;
+                let mut date = now.date_naive();
+                if now.time() >= time {
+                    date = date
+                        .succ_opt()
+                        .ok_or_else(|| anyhow!("Invalid start date"))?;
+                }
+                Local
+                    .from_local_datetime(&date.and_time(time))
+                    .earliest()
+                    .ok_or_else(|| anyhow!("Start time doesn't exist in the local time zone"))
+            }
+        }
+    }
+
+    /// Controls for choosing the delay or time of day
+    pub fn ui(&mut self, ui: &mut Ui) {
+        ui.horizontal(|ui| {
+            ui.radio_value(
+                &mut self.mode,
+                DeferMode::AfterMinutes,
+                t!("schedule.after"),
+            );
+            ui.add_enabled(
+                self.mode == DeferMode::AfterMinutes,
+                egui::DragValue::new(&mut self.minutes)
+                    .clamp_range(1..=1440)
+                    .suffix(format!(" {}", t!("schedule.minutes"))),
+            );
+        });
+        ui.horizontal(|ui| {
+            ui.radio_value(&mut self.mode, DeferMode::AtTime, t!("schedule.at"));
+            ui.add_enabled_ui(self.mode == DeferMode::AtTime, |ui| {
+                ui.add(egui::DragValue::new(&mut self.hour).clamp_range(0..=23));
+                ui.label(":");
+                ui.add(
+                    egui::DragValue::new(&mut self.minute)
+                        .clamp_range(0..=59)
+                        .custom_formatter(|v, _| format!("{:02}", v as u32)),
+                );
+            });
+        });
+    }
+}
+
+/// Options for starting processing later rather than immediately
+#[derive(Default)]
+pub struct DeferredStart {
+    pub time: StartTime,
+    pub scheduled: Option<ScheduledRun>,
+}
+
+impl DeferredStart {
+    /// Schedules a run of `state`, fixing its options and output filename now
+    pub fn schedule(&mut self, state: &ApplicationState) -> Result<()> {
+        let output_filename = state.assemble_output_filename()?;
+        let start_at = self.time.next()?;
+        info!("Processing scheduled to start at {}", start_at);
+        self.scheduled = Some(ScheduledRun {
+            start_at,
+            state: state.clone(),
+            output_filename,
+        });
+        Ok(())
+    }
+
+    /// Removes and returns the scheduled run once its start time has arrived
+    pub fn take_due(&mut self) -> Option<ScheduledRun> {
+        if self.scheduled.as_ref().map(|s| s.is_due()).unwrap_or(false) {
+            self.scheduled.take()
+        } else {
+            None
+        }
+    }
+}
+
+/// A later start of the batch queue. The queue's jobs aren't fixed when it's scheduled, so jobs
+/// added in the meantime run too.
+#[derive(Default)]
+pub struct QueueSchedule {
+    pub time: StartTime,
+    pub start_at: Option<DateTime<Local>>,
+}
+
+impl QueueSchedule {
+    pub fn schedule(&mut self) -> Result<()> {
+        let start_at = self.time.next()?;
+        info!("Queue scheduled to start at {}", start_at);
+        self.start_at = Some(start_at);
+        Ok(())
+    }
+
+    pub fn cancel(&mut self) {
+        if self.start_at.take().is_some() {
+            info!("Queue schedule cancelled");
+        }
+    }
+
+    /// Time left until the queue starts, formatted as hours, minutes and seconds
+    pub fn countdown(&self) -> Option<String> {
+        self.start_at.map(countdown)
+    }
+
+    /// Clears the schedule and returns true once its start time has arrived
+    pub fn take_due(&mut self) -> bool {
+        if self.start_at.map(|t| Local::now() >= t).unwrap_or(false) {
+            self.start_at = None;
+            true
+        } else {
+            false
+        }
+    }
+}
@@ -0,0 +1,241 @@
+use std::path::Path;
+
+use egui::Context;
+
+use crate::process::serheader::{self, Endianness, HeaderOverride, SerHeader};
+
+/// Shows the raw header fields of an input SER file and edits the override of its values, which
+/// lasts for this session and leaves the file untouched
+#[derive(Default)]
+pub struct SerHeaderWindow {
+    pub open: bool,
+    path: Option<String>,
+    header: Option<SerHeader>,
+    edit: HeaderOverride,
+    error: Option<String>,
+}
+
+impl SerHeaderWindow {
+    fn load(&mut self, path: &str) {
+        self.path = Some(path.to_owned());
+        self.edit = serheader::override_for(path).unwrap_or_default();
+        match SerHeader::read(Path::new(path)) {
+            Ok(header) => {
+                self.header = Some(header);
+                self.error = None;
+            }
+            Err(why) => {
+                self.header = None;
+                self.error = Some(why.to_string());
+            }
+        }
+    }
+
+    /// Shows the window for choosing among `inputs`, as pairs of input names and SER paths.
+    /// Returns the path of the file whose override was changed, if any.
+    pub fn ui(&mut self, ctx: &Context, inputs: &[(String, String)]) -> Option<String> {
+        if !self.open {
+            return None;
+        }
+        let current = self.path.clone();
+        if current.is_none()
+            || !inputs
+                .iter()
+                .any(|(_, path)| Some(path) == current.as_ref())
+        {
+            match inputs.first() {
+                Some((_, path)) => self.load(path),
+                None => {
+                    self.path = None;
+                    self.header = None;
+                }
+            }
+        }
+
+        let mut changed = None;
+        let mut open = self.open;
+        egui::Window::new(t!("serheader.title"))
+            .open(&mut open)
+            .resizable(false)
+            .show(ctx, |ui| {
+                if inputs.is_empty() {
+                    ui.label(t!("serheader.no_inputs"));
+                    return;
+                }
+                let selected_name = inputs
+                    .iter()
+                    .find(|(_, path)| Some(path) == self.path.as_ref())
+                    .map(|(name, _)| name.clone())
+                    .unwrap_or_default();
+                let mut selected = None;
+                egui::ComboBox::from_label(t!("serheader.input"))
+                    .selected_text(selected_name)
+                    .show_ui(ui, |ui| {
+                        for (name, path) in inputs {
+                            if ui
+                                .selectable_label(Some(path) == self.path.as_ref(), name)
+                                .clicked()
+                            {
+                                selected = Some(path.clone());
+                            }
+                        }
+                    });
+                if let Some(path) = selected {
+                    self.load(&path);
+                }
+                if let Some(path) = &self.path {
+                    ui.label(path);
+                }
+                ui.separator();
+
+                if let Some(error) = &self.error {
+                    ui.colored_label(ui.visuals().error_fg_color, error);
+                }
+                let Some(header) = self.header.clone() else {
+                    return;
+                };
+                Self::header_grid(ui, &header);
+
+                let problems = header.problems();
+                if !problems.is_empty() {
+                    ui.separator();
+                    for problem in problems {
+                        ui.colored_label(ui.visuals().warn_fg_color, format!("⚠ {}", problem));
+                    }
+                }
+
+                ui.separator();
+                ui.heading(t!("serheader.override"));
+                ui.label(t!("serheader.override_hint"));
+                self.override_grid(ui, &header);
+
+                ui.horizontal(|ui| {
+                    let path = self.path.clone().unwrap_or_default();
+                    let applied = serheader::override_for(&path).unwrap_or_default();
+                    if ui
+                        .add_enabled(
+                            self.edit != applied,
+                            egui::Button::new(t!("serheader.apply")),
+                        )
+                        .clicked()
+                    {
+                        serheader::set_override(&path, self.edit);
+                        changed = Some(path.clone());
+                    }
+                    if ui
+                        .add_enabled(
+                            !applied.is_empty() || !self.edit.is_empty(),
+                            egui::Button::new(t!("serheader.clear")),
+                        )
+                        .clicked()
+                    {
+                        self.edit = HeaderOverride::default();
+                        if !applied.is_empty() {
+                            serheader::set_override(&path, self.edit);
+                            changed = Some(path);
+                        }
+                    }
+                });
+            });
+        self.open = open;
+        changed
+    }
+
+    fn header_grid(ui: &mut egui::Ui, header: &SerHeader) {
+        egui::Grid::new("ser_header_fields")
+            .num_columns(2)
+            .spacing([40.0, 4.0])
+            .striped(true)
+            .show(ui, |ui| {
+                let rows = [
+                    (t!("serheader.file_id"), header.file_id.clone()),
+                    (t!("serheader.lu_id"), header.lu_id.to_string()),
+                    (
+                        t!("serheader.color_id"),
+                        format!("{} ({})", header.color_id, header.color_name()),
+                    ),
+                    (t!("serheader.endianness"), header.little_endian.to_string()),
+                    (t!("preview.image_width"), header.width.to_string()),
+                    (t!("preview.image_height"), header.height.to_string()),
+                    (t!("preview.pixel_depth"), header.pixel_depth.to_string()),
+                    (t!("preview.frame_count"), header.frame_count.to_string()),
+                    (t!("preview.observer"), header.observer.clone()),
+                    (t!("preview.instrument"), header.instrument.clone()),
+                    (t!("preview.telescope"), header.telescope.clone()),
+                    (t!("serheader.date_time"), header.date_time.to_string()),
+                    (
+                        t!("serheader.date_time_utc"),
+                        header.date_time_utc.to_string(),
+                    ),
+                    (t!("serheader.file_size"), header.file_size.to_string()),
+                    (
+                        t!("serheader.frames_in_file"),
+                        header.frames_in_file(header.pixel_depth).to_string(),
+                    ),
+                ];
+                for (name, value) in rows {
+                    ui.label(name);
+                    ui.label(value);
+                    ui.end_row();
+                }
+            });
+    }
+
+    fn override_grid(&mut self, ui: &mut egui::Ui, header: &SerHeader) {
+        let edit = &mut self.edit;
+        egui::Grid::new("ser_header_override")
+            .num_columns(2)
+            .spacing([40.0, 4.0])
+            .striped(true)
+            .show(ui, |ui| {
+                let mut override_depth = edit.pixel_depth.is_some();
+                ui.checkbox(&mut override_depth, t!("serheader.pixel_depth"));
+                let mut pixel_depth = edit.pixel_depth.unwrap_or(header.pixel_depth.clamp(1, 16));
+                ui.add_enabled(
+                    override_depth,
+                    egui::DragValue::new(&mut pixel_depth)
+                        .clamp_range(1..=16)
+                        .suffix(format!(" {}", t!("preview.bits"))),
+                );
+                edit.pixel_depth = override_depth.then_some(pixel_depth);
+                ui.end_row();
+
+                ui.label(t!("serheader.byte_order"));
+                egui::ComboBox::from_id_source("ser_header_endianness")
+                    .selected_text(edit.endianness.label())
+                    .show_ui(ui, |ui| {
+                        for endianness in Endianness::ALL {
+                            ui.selectable_value(
+                                &mut edit.endianness,
+                                endianness,
+                                endianness.label(),
+                            );
+                        }
+                    });
+                ui.end_row();
+
+                let frames_in_file = header.frames_in_file(pixel_depth);
+                let mut override_count = edit.frame_count.is_some();
+                ui.checkbox(&mut override_count, t!("serheader.frame_count"));
+                let mut frame_count = edit
+                    .frame_count
+                    .unwrap_or(header.frame_count.min(frames_in_file));
+                ui.horizontal(|ui| {
+                    ui.add_enabled(
+                        override_count,
+                        egui::DragValue::new(&mut frame_count).clamp_range(0..=frames_in_file),
+                    );
+                    if ui
+                        .button(t!("serheader.fit_to_file"))
+                        .on_hover_text(t!("serheader.fit_to_file_hint", count = frames_in_file))
+                        .clicked()
+                    {
+                        override_count = true;
+                        frame_count = frames_in_file;
+                    }
+                });
+                edit.frame_count = override_count.then_some(frame_count);
+                ui.end_row();
+            });
+    }
+}
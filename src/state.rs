@@ -1,3 +1,4 @@
+use std::collections::BTreeSet;
 use std::fs;
 use std::fs::File;
 use std::io::Write;
@@ -12,6 +13,18 @@ use solhat::drizzle::Scale;
 use solhat::drizzle::StackAlgorithm;
 use solhat::target::Target;
 
+use crate::colorprofile::ColorProfile;
+use crate::filename;
+use crate::osrecent;
+use crate::power::PowerSettings;
+use crate::process::alignedframes::AlignedFrameExport;
+use crate::process::badlines::BadLineMap;
+use crate::process::bayer::BayerOverrides;
+use crate::process::caption;
+use crate::process::framesource;
+use crate::process::sidecar::SidecarFormat;
+use crate::tiffwriter::TiffOptions;
+
 #[derive(Default, Deserialize, Serialize, Eq, PartialEq, Clone)]
 pub enum PreviewPane {
     #[default]
@@ -21,7 +34,11 @@ pub enum PreviewPane {
     DarkFlat,
     Bias,
     Analysis,
+    Focus,
+    Rotation,
     Results,
+    Queue,
+    History,
 }
 
 #[derive(Debug, Eq, PartialEq, Deserialize, Serialize, Clone, Default)]
@@ -40,7 +57,301 @@ impl VisualTheme {
     }
 }
 
-#[derive(Default, Deserialize, Serialize, Clone)]
+#[derive(Debug, Eq, PartialEq, Deserialize, Serialize, Clone, Copy, Default)]
+pub enum AnalysisBinning {
+    #[default]
+    None,
+    Bin2x2,
+    Bin4x4,
+}
+
+impl AnalysisBinning {
+    pub fn factor(&self) -> usize {
+        match *self {
+            AnalysisBinning::None => 1,
+            AnalysisBinning::Bin2x2 => 2,
+            AnalysisBinning::Bin4x4 => 4,
+        }
+    }
+}
+
+/// Color channel on which frame quality is estimated. Ignored for monochrome data.
+#[derive(Debug, Eq, PartialEq, Deserialize, Serialize, Clone, Copy, Default)]
+pub enum AnalysisChannel {
+    #[default]
+    Red,
+    Green,
+    Blue,
+    Luminance,
+}
+
+/// Where in each frame quality is estimated
+#[derive(Debug, Eq, PartialEq, Deserialize, Serialize, Clone, Copy, Default)]
+pub enum AnalysisPoints {
+    /// A single window at the center of mass
+    #[default]
+    CenterOfMass,
+    /// A square grid of windows centered on the center of mass
+    Grid,
+    /// User specified offsets from the center of mass
+    Custom,
+}
+
+/// How frames are chosen when more frames pass the sigma limits than `max_frames` allows.
+#[derive(Debug, Eq, PartialEq, Deserialize, Serialize, Clone, Copy, Default)]
+pub enum FrameSampling {
+    /// Keep the first `max_frames` frames of the capture
+    #[default]
+    Truncate,
+    /// Pick `max_frames` frames spread evenly across the capture
+    EvenlySpaced,
+    /// Keep the `max_frames` highest quality frames
+    BestN,
+    /// Pick `max_frames` frames at random using a repeatable seed
+    Random,
+}
+
+/// How frames are lined up with each other for stacking
+#[derive(Debug, Eq, PartialEq, Deserialize, Serialize, Clone, Copy, Default)]
+pub enum AlignmentMode {
+    /// Each frame shifted as a whole onto its center of mass
+    #[default]
+    Centroid,
+    /// A grid of boxes over the target, each aligned and quality ranked separately
+    AlignmentPoints,
+}
+
+/// Source of the per-frame rotation used for derotation
+#[derive(Debug, Eq, PartialEq, Deserialize, Serialize, Clone, Copy, Default)]
+pub enum RotationModel {
+    /// Parallactic rotation derived from the target ephemeris, frame timestamps and observer location
+    #[default]
+    Ephemeris,
+    /// A fixed rotation rate, in degrees per minute, applied using frame timestamps
+    RatePerMinute,
+    /// A fixed rotation, in degrees, between consecutive captured frames
+    FixedPerFrame,
+}
+
+/// How the master dark is scaled for lights whose exposure or temperature differs from the darks'
+#[derive(Debug, Eq, PartialEq, Deserialize, Serialize, Clone, Copy, Default)]
+pub enum DarkScaleMode {
+    /// The master dark is subtracted as is
+    #[default]
+    Off,
+    /// The dark's level above the bias is multiplied by `dark_scale_factor`
+    Manual,
+    /// The factor is found by minimizing the noise left in the calibrated lights
+    Automatic,
+}
+
+/// Geometric correction applied uniformly to the frames before stacking, for mirrored or inverted
+/// optical trains
+#[derive(Debug, Eq, PartialEq, Deserialize, Serialize, Clone, Copy, Default)]
+pub enum FrameOrientation {
+    #[default]
+    Normal,
+    FlipHorizontal,
+    FlipVertical,
+    Rotate180,
+}
+
+impl FrameOrientation {
+    /// True if the correction reverses the handedness of the image, and therefore the direction
+    /// of field rotation
+    pub fn is_mirrored(&self) -> bool {
+        matches!(
+            *self,
+            FrameOrientation::FlipHorizontal | FrameOrientation::FlipVertical
+        )
+    }
+
+    pub fn flips_horizontal(&self) -> bool {
+        matches!(
+            *self,
+            FrameOrientation::FlipHorizontal | FrameOrientation::Rotate180
+        )
+    }
+
+    pub fn flips_vertical(&self) -> bool {
+        matches!(
+            *self,
+            FrameOrientation::FlipVertical | FrameOrientation::Rotate180
+        )
+    }
+
+    fn filename_token(&self) -> &str {
+        match *self {
+            FrameOrientation::Normal => "",
+            FrameOrientation::FlipHorizontal => "fliph",
+            FrameOrientation::FlipVertical => "flipv",
+            FrameOrientation::Rotate180 => "rot180",
+        }
+    }
+}
+
+/// Image file format of the stacked output
+#[derive(Debug, Eq, PartialEq, Deserialize, Serialize, Clone, Copy, Default)]
+pub enum OutputFormat {
+    #[default]
+    Tiff,
+    Png,
+    Jpeg,
+    Fits,
+}
+
+impl OutputFormat {
+    pub const ALL: [OutputFormat; 4] = [
+        OutputFormat::Tiff,
+        OutputFormat::Png,
+        OutputFormat::Jpeg,
+        OutputFormat::Fits,
+    ];
+
+    pub fn extension(&self) -> &'static str {
+        match *self {
+            OutputFormat::Tiff => "tif",
+            OutputFormat::Png => "png",
+            OutputFormat::Jpeg => "jpg",
+            OutputFormat::Fits => "fits",
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            OutputFormat::Tiff => "TIFF",
+            OutputFormat::Png => "PNG",
+            OutputFormat::Jpeg => "JPEG",
+            OutputFormat::Fits => "FITS",
+        }
+    }
+}
+
+/// How the aligned frames are combined into the stack
+#[derive(Debug, Eq, PartialEq, Deserialize, Serialize, Clone, Copy, Default)]
+pub enum StackMethod {
+    #[default]
+    Average,
+    Median,
+    Minimum,
+    /// Mean of the values left after repeatedly rejecting those more than `clip_kappa` standard
+    /// deviations from the mean
+    SigmaClipped,
+    Maximum,
+}
+
+impl StackMethod {
+    pub const ALL: [StackMethod; 5] = [
+        StackMethod::Average,
+        StackMethod::Median,
+        StackMethod::Minimum,
+        StackMethod::SigmaClipped,
+        StackMethod::Maximum,
+    ];
+
+    pub fn name(&self) -> String {
+        match *self {
+            StackMethod::Average => t!("processoptions.algorithm_average"),
+            StackMethod::Median => t!("processoptions.algorithm_median"),
+            StackMethod::Minimum => t!("processoptions.algorithm_minimum"),
+            StackMethod::SigmaClipped => t!("processoptions.algorithm_sigma_clipped"),
+            StackMethod::Maximum => t!("processoptions.algorithm_maximum"),
+        }
+        .to_string()
+    }
+
+    /// True if frames can be weighted by their quality in the stack
+    pub fn supports_weighting(&self) -> bool {
+        matches!(self, StackMethod::Average | StackMethod::SigmaClipped)
+    }
+
+    /// True if frames can be drizzled at any scale and drop size
+    pub fn supports_custom_drizzle(&self) -> bool {
+        !matches!(self, StackMethod::Median | StackMethod::Minimum)
+    }
+
+    /// The solhat algorithm stacking with this method, or None if it's stacked by this
+    /// application
+    pub fn solhat_algorithm(&self) -> Option<StackAlgorithm> {
+        match *self {
+            StackMethod::Average => Some(StackAlgorithm::Average),
+            StackMethod::Median => Some(StackAlgorithm::Median),
+            StackMethod::Minimum => Some(StackAlgorithm::Minimum),
+            StackMethod::SigmaClipped | StackMethod::Maximum => None,
+        }
+    }
+}
+
+/// How the frames of a calibration input are combined into its master
+#[derive(Debug, Eq, PartialEq, Deserialize, Serialize, Clone, Copy, Default)]
+pub enum MasterMethod {
+    #[default]
+    Mean,
+    /// Rejects outliers such as cosmic ray hits, but holds every frame in memory
+    Median,
+    /// Mean of the values left after repeatedly rejecting those far from the median
+    SigmaClippedMean,
+}
+
+impl MasterMethod {
+    pub const ALL: [MasterMethod; 3] = [
+        MasterMethod::Mean,
+        MasterMethod::Median,
+        MasterMethod::SigmaClippedMean,
+    ];
+
+    pub fn name(&self) -> String {
+        match *self {
+            MasterMethod::Mean => t!("inputs.method_mean"),
+            MasterMethod::Median => t!("inputs.method_median"),
+            MasterMethod::SigmaClippedMean => t!("inputs.method_sigma_clipped"),
+        }
+        .to_string()
+    }
+}
+
+/// Image file format computed calibration masters are saved in
+#[derive(Debug, Eq, PartialEq, Deserialize, Serialize, Clone, Copy, Default)]
+pub enum MasterFormat {
+    #[default]
+    Tiff,
+    Fits,
+}
+
+impl MasterFormat {
+    pub const ALL: [MasterFormat; 2] = [MasterFormat::Tiff, MasterFormat::Fits];
+
+    pub fn extension(&self) -> &'static str {
+        match *self {
+            MasterFormat::Tiff => "tif",
+            MasterFormat::Fits => "fits",
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            MasterFormat::Tiff => "TIFF",
+            MasterFormat::Fits => "FITS",
+        }
+    }
+}
+
+/// File extension used for saved processing projects
+pub const PROJECT_EXTENSION: &str = "shproj";
+
+/// Maximum number of entries kept in each of the recent files and projects lists
+pub const MAX_RECENT_ENTRIES: usize = 10;
+
+/// Drizzle scales solhat stacks, with their upscale factors
+const DRIZZLE_PRESETS: [(Scale, f32); 4] = [
+    (Scale::Scale1_0, 1.0),
+    (Scale::Scale1_5, 1.5),
+    (Scale::Scale2_0, 2.0),
+    (Scale::Scale3_0, 3.0),
+];
+
+#[derive(Default, Deserialize, Serialize, Clone, PartialEq)]
+#[serde(default)]
 pub struct WindowState {
     pub last_opened_folder: Option<PathBuf>,
     pub window_width: usize,
@@ -48,6 +359,32 @@ pub struct WindowState {
     pub fullscreen: bool,
     pub theme: VisualTheme,
     pub selected_preview_pane: PreviewPane,
+    pub current_project: Option<PathBuf>,
+    pub recent_files: Vec<String>,
+    pub recent_projects: Vec<PathBuf>,
+    /// Load the saved per-target defaults whenever the target is changed
+    pub use_target_defaults: bool,
+    /// Number of queued jobs run at the same time. Zero is treated as one.
+    pub concurrent_jobs: usize,
+    pub power: PowerSettings,
+    /// Run frame analysis whenever frames are added to a light file being watched live
+    pub live_analysis: bool,
+    /// Run frame analysis in the background as soon as a light file is selected
+    pub auto_analysis: bool,
+    /// Run the object detection threshold test as soon as a light file is selected
+    pub auto_threshold_test: bool,
+    /// Show every option rather than only the commonly changed ones
+    pub advanced_options: bool,
+    /// Sections of the options panel the user has collapsed
+    pub collapsed_option_sections: BTreeSet<String>,
+    /// Options locked against changes in the options panel, by label key
+    pub locked_options: BTreeSet<String>,
+    #[cfg(feature = "capture")]
+    pub capture: crate::capture::CaptureSettings,
+    #[cfg(feature = "notifications")]
+    pub notifications: crate::notifications::NotificationSettings,
+    #[cfg(feature = "publish")]
+    pub publish: crate::publish::PublishSettings,
 }
 
 impl WindowState {
@@ -68,6 +405,42 @@ impl WindowState {
         };
     }
 
+    /// Moves `path` to the top of the recently used input files, and of the operating system's
+    /// recent documents
+    pub fn add_recent_file(&mut self, path: &str) {
+        osrecent::note_recent_document(Path::new(path));
+        self.recent_files.retain(|p| p != path);
+        self.recent_files.insert(0, path.to_owned());
+        self.recent_files.truncate(MAX_RECENT_ENTRIES);
+    }
+
+    /// Moves `path` to the top of the recently used projects, and of the operating system's
+    /// recent documents
+    pub fn add_recent_project(&mut self, path: &Path) {
+        osrecent::note_recent_document(path);
+        self.recent_projects.retain(|p| p != path);
+        self.recent_projects.insert(0, path.to_path_buf());
+        self.recent_projects.truncate(MAX_RECENT_ENTRIES);
+    }
+
+    /// Recently used inputs an input taking files with the extension `ext` accepts, including
+    /// frame folders if it takes `folders` and still images, e.g. masters made with other tools,
+    /// if it takes `still_images`
+    pub fn recent_inputs(&self, ext: &str, folders: bool, still_images: bool) -> Vec<String> {
+        self.recent_files
+            .iter()
+            .filter(|f| {
+                let path = Path::new(f);
+                path.extension()
+                    .map(|e| e.to_string_lossy().eq_ignore_ascii_case(ext))
+                    .unwrap_or(false)
+                    || (still_images && framesource::is_still_image(path))
+                    || (folders && path.is_dir())
+            })
+            .cloned()
+            .collect()
+    }
+
     pub fn update_from_window_info(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
         if let Some(dimension) = ctx.input(|i| i.viewport().inner_rect) {
             self.window_width = dimension.width() as usize;
@@ -76,28 +449,138 @@ impl WindowState {
     }
 }
 
-#[derive(Deserialize, Serialize, Clone)]
+/// One target of a multi-target session, such as the full disk, an active region close-up or a
+/// prominence on the limb. Targets share the session's calibration frames, output folder and
+/// observer location.
+#[derive(Deserialize, Serialize, Clone, PartialEq, Default)]
+#[serde(default)]
+pub struct SessionTarget {
+    pub name: String,
+    pub light: Option<String>,
+    pub prominence_light: Option<String>,
+    pub frame_range: Option<[usize; 2]>,
+    /// Processing options, without paths
+    pub options: Box<ApplicationState>,
+}
+
+#[derive(Deserialize, Serialize, Clone, PartialEq)]
+#[serde(default)]
 pub struct ApplicationState {
     pub light: Option<String>,
+    /// Second light file of the same target exposed for prominences, stacked alongside `light`
+    /// and composited outside the limb
+    pub prominence_light: Option<String>,
+    /// Half the width, in output pixels, of the band around the limb across which a prominence
+    /// composite blends from one stack to the other
+    pub composite_blend_radius: f64,
     pub dark: Option<String>,
     pub flat: Option<String>,
     pub darkflat: Option<String>,
     pub bias: Option<String>,
+    /// Further files whose frames are combined with those of `dark` into the master dark, e.g. a
+    /// second batch of darks. Likewise for the other calibration inputs.
+    pub additional_darks: Vec<String>,
+    pub additional_flats: Vec<String>,
+    pub additional_darkflats: Vec<String>,
+    pub additional_biases: Vec<String>,
+    /// How the frames of each calibration input are combined into its master
+    pub dark_method: MasterMethod,
+    pub flat_method: MasterMethod,
+    pub darkflat_method: MasterMethod,
+    pub bias_method: MasterMethod,
+    /// Bayer patterns used in place of those given by the inputs' SER headers
+    pub bayer: BayerOverrides,
     pub hot_pixel_map: Option<String>,
+    pub bad_lines: BadLineMap,
+    pub bad_line_correction: bool,
+    /// Deviation, in robust standard deviations, above which a line is detected as bad
+    pub bad_line_threshold: f64,
     pub output_dir: Option<String>,
     pub freetext: String,
+    /// Output filename, without extension, with tokens such as `{basename}` filled in
+    pub output_template: String,
+    pub output_format: OutputFormat,
+    /// ICC profile the output is converted to and tagged with
+    pub output_color_profile: ColorProfile,
+    pub output_tiff: TiffOptions,
+    /// JPEG quality, 1-100, of output saved as JPEG
+    pub output_jpeg_quality: u8,
+    /// Metadata file written alongside the output to make the run reproducible
+    pub output_sidecar: SidecarFormat,
+    /// Where each stacked frame is also written, aligned as it was stacked
+    pub export_aligned_frames: AlignedFrameExport,
+    /// Write an HTML report of each run alongside its output
+    pub output_report: bool,
+    /// Add a bar beneath exported images describing the observation and processing
+    pub output_caption: bool,
+    /// Caption text, with tokens such as `{frames}` filled in
+    pub caption_template: String,
+    /// Free form observing notes written alongside the stacked output
+    pub session_notes: String,
+    /// Targets of a multi-target session, empty for a single target. The light file and options
+    /// of the active target are the ones in this state; its entry is only brought up to date when
+    /// switching targets or saving.
+    pub session_targets: Vec<SessionTarget>,
+    pub active_target: usize,
     pub obs_latitude: f64,
     pub obs_longitude: f64,
     pub target: Target,
+    /// A planet or other small bright disk, tracked by fitting the disk. Processed without
+    /// ephemeris, with `target` set to None.
+    pub planetary: bool,
+    pub rotation_model: RotationModel,
+    pub rotation_rate: f64,
+    pub rotation_per_frame: f64,
     pub obj_detection_threshold: f64,
     pub drizzle_scale: Scale,
-    pub algorithm: StackAlgorithm,
+    /// Upscale factor entered by the user, used in place of `drizzle_scale` if set
+    pub custom_drizzle_scale: Option<f32>,
+    /// Size of the drop each frame pixel is shrunk to before it's drizzled onto the output, as a
+    /// fraction of the pixel. Smaller drops keep more resolution but need more frames to cover
+    /// the output.
+    pub drizzle_pixfrac: f32,
+    pub algorithm: StackMethod,
+    /// Standard deviations from the mean beyond which values are rejected by sigma clipping
+    pub clip_kappa: f64,
+    /// Rejection passes of sigma clipping
+    pub clip_iterations: usize,
+    /// Weight each frame by its quality rather than stacking the frames equally. Applies to the
+    /// average and sigma clipped stacks.
+    pub quality_weighting: bool,
+    /// Power the quality of a frame, relative to the best frame, is raised to for its weight.
+    /// Higher powers favor the sharpest frames more strongly.
+    pub quality_weight_power: f64,
+    /// Leave saturated pixels out of the stack rather than averaging them in
+    pub saturation_exclusion: bool,
+    /// Fraction of the sensor's full scale at or above which a raw pixel is saturated
+    pub saturation_threshold: f64,
+    pub alignment_mode: AlignmentMode,
+    /// Width and height, in frame pixels, of each alignment point's box. Points are placed half a
+    /// box apart.
+    pub ap_size: usize,
+    /// Furthest, in frame pixels, an alignment point is searched for from its global position
+    pub ap_search_radius: usize,
+    /// Percentage of frames, sharpest first, stacked at each alignment point
+    pub ap_keep_percentage: f64,
+    /// Fraction of the reference's peak level below which no alignment points are placed
+    pub ap_min_brightness: f64,
     pub max_frames: usize,
+    /// First and last frame ids, inclusive, of the part of the capture stacked. All frames if
+    /// not set.
+    pub frame_range: Option<[usize; 2]>,
+    pub frame_sampling: FrameSampling,
+    pub sampling_seed: u64,
     pub min_sigma: f64,
     pub max_sigma: f64,
     pub top_percentage: f64,
     pub decorrelated_colors: bool,
     pub analysis_window_size: usize,
+    pub analysis_binning: AnalysisBinning,
+    pub analysis_points: AnalysisPoints,
+    pub analysis_channel: AnalysisChannel,
+    pub analysis_grid_size: usize,
+    pub analysis_grid_spacing: usize,
+    pub analysis_custom_points: Vec<[i32; 2]>,
     pub ld_correction: bool,
     pub ld_coefficient: f64,
     pub solar_radius_pixels: usize,
@@ -105,7 +588,20 @@ pub struct ApplicationState {
     pub crop_height: usize,
     pub vert_offset: i32,
     pub horiz_offset: i32,
+    pub frame_orientation: FrameOrientation,
     pub save_masters: bool,
+    /// Folder computed masters are saved to, the output folder if not set
+    pub masters_dir: Option<String>,
+    pub masters_format: MasterFormat,
+    pub calibration_max_frames: usize,
+    /// Scale the master dark to each frame's exposure when mixed exposures are detected
+    pub dark_scaling: bool,
+    pub dark_scale_mode: DarkScaleMode,
+    /// Factor the master dark's level above the bias is multiplied by in manual dark scaling
+    pub dark_scale_factor: f64,
+    /// Variation in frame level, as a fraction, above which a capture is treated as having mixed
+    /// exposures
+    pub exposure_tolerance: f64,
     pub window: WindowState,
 }
 
@@ -113,26 +609,79 @@ impl Default for ApplicationState {
     fn default() -> Self {
         Self {
             light: None,
+            prominence_light: None,
+            composite_blend_radius: 8.0,
             dark: None,
             flat: None,
             darkflat: None,
             bias: None,
+            additional_darks: vec![],
+            additional_flats: vec![],
+            additional_darkflats: vec![],
+            additional_biases: vec![],
+            dark_method: MasterMethod::Mean,
+            flat_method: MasterMethod::Mean,
+            darkflat_method: MasterMethod::Mean,
+            bias_method: MasterMethod::Mean,
+            bayer: BayerOverrides::default(),
             output_dir: None,
             freetext: "v1".to_owned(),
+            output_template: filename::DEFAULT_TEMPLATE.to_owned(),
+            output_format: OutputFormat::Tiff,
+            output_color_profile: ColorProfile::None,
+            output_tiff: TiffOptions::default(),
+            output_jpeg_quality: 90,
+            output_sidecar: SidecarFormat::None,
+            export_aligned_frames: AlignedFrameExport::None,
+            output_report: false,
+            output_caption: false,
+            caption_template: caption::DEFAULT_TEMPLATE.to_owned(),
+            session_notes: String::new(),
+            session_targets: vec![],
+            active_target: 0,
             obs_latitude: 34.0,
             obs_longitude: -118.0,
             target: Target::Sun,
+            planetary: false,
+            rotation_model: RotationModel::Ephemeris,
+            rotation_rate: 0.0,
+            rotation_per_frame: 0.0,
             drizzle_scale: Scale::Scale1_0,
-            algorithm: StackAlgorithm::Average,
+            custom_drizzle_scale: None,
+            drizzle_pixfrac: 1.0,
+            algorithm: StackMethod::Average,
+            clip_kappa: 2.5,
+            clip_iterations: 2,
+            quality_weighting: false,
+            quality_weight_power: 2.0,
+            saturation_exclusion: false,
+            saturation_threshold: 0.98,
+            alignment_mode: AlignmentMode::Centroid,
+            ap_size: 64,
+            ap_search_radius: 8,
+            ap_keep_percentage: 50.0,
+            ap_min_brightness: 0.2,
             obj_detection_threshold: 20000.0,
             hot_pixel_map: None,
+            bad_lines: BadLineMap::default(),
+            bad_line_correction: false,
+            bad_line_threshold: 5.0,
             max_frames: 5000,
+            frame_range: None,
+            frame_sampling: FrameSampling::Truncate,
+            sampling_seed: 42,
             min_sigma: 0.0,
             max_sigma: 1000.0,
             top_percentage: 100.0,
             window: WindowState::default(),
             decorrelated_colors: false,
             analysis_window_size: 128,
+            analysis_binning: AnalysisBinning::None,
+            analysis_points: AnalysisPoints::CenterOfMass,
+            analysis_channel: AnalysisChannel::Red,
+            analysis_grid_size: 3,
+            analysis_grid_spacing: 256,
+            analysis_custom_points: vec![],
             ld_correction: false,
             ld_coefficient: 0.56,
             solar_radius_pixels: 768,
@@ -140,12 +689,91 @@ impl Default for ApplicationState {
             crop_width: 0,
             vert_offset: 0,
             horiz_offset: 0,
+            frame_orientation: FrameOrientation::Normal,
             save_masters: false,
+            masters_dir: None,
+            masters_format: MasterFormat::Tiff,
+            calibration_max_frames: 0,
+            dark_scaling: false,
+            dark_scale_mode: DarkScaleMode::Off,
+            dark_scale_factor: 1.0,
+            exposure_tolerance: 0.2,
+        }
+    }
+}
+
+fn solhat_config_dir() -> PathBuf {
+    dirs::home_dir().unwrap().join(".solhat/")
+}
+
+/// Writes `contents` to `path`, readable and writable only by the user as the settings hold
+/// passwords
+fn write_private(path: &Path, contents: &str) -> Result<()> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+        options.mode(0o600);
+        // The mode only applies to new files, so tighten those written by earlier versions
+        if path.exists() {
+            fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
         }
     }
+    options.open(path)?.write_all(contents.as_bytes())?;
+    Ok(())
+}
+
+fn session_marker_path() -> PathBuf {
+    solhat_config_dir().join("session.lock")
+}
+
+/// Records that a session has started. Returns true if the previous session did not shut down
+/// cleanly, i.e. its marker was never removed.
+pub fn mark_session_started() -> bool {
+    let marker = session_marker_path();
+    let unclean = marker.exists();
+    if let Err(why) = fs::create_dir_all(solhat_config_dir()).and_then(|_| File::create(&marker)) {
+        warn!("Failed to create session marker: {:?}", why);
+    }
+    unclean
+}
+
+/// Records that the session has shut down cleanly.
+pub fn mark_session_ended() {
+    if let Err(why) = fs::remove_file(session_marker_path()) {
+        warn!("Failed to remove session marker: {:?}", why);
+    }
+}
+
+/// The primary calibration input followed by its additional inputs. Additional inputs are ignored
+/// without a primary input.
+fn calibration_inputs(primary: &Option<String>, additional: &[String]) -> Vec<String> {
+    match primary {
+        Some(primary) => std::iter::once(primary.to_owned())
+            .chain(additional.iter().cloned())
+            .collect(),
+        None => vec![],
+    }
 }
 
 impl ApplicationState {
+    pub fn dark_inputs(&self) -> Vec<String> {
+        calibration_inputs(&self.dark, &self.additional_darks)
+    }
+
+    pub fn flat_inputs(&self) -> Vec<String> {
+        calibration_inputs(&self.flat, &self.additional_flats)
+    }
+
+    pub fn darkflat_inputs(&self) -> Vec<String> {
+        calibration_inputs(&self.darkflat, &self.additional_darkflats)
+    }
+
+    pub fn bias_inputs(&self) -> Vec<String> {
+        calibration_inputs(&self.bias, &self.additional_biases)
+    }
+
     pub fn to_parameters(&self) -> ProcessParameters {
         ProcessParameters {
             input_files: if let Some(light) = &self.light {
@@ -156,7 +784,11 @@ impl ApplicationState {
             obj_detection_threshold: self.obj_detection_threshold,
             obs_latitude: self.obs_latitude,
             obs_longitude: self.obs_longitude,
-            target: self.target,
+            target: if self.planetary {
+                Target::None
+            } else {
+                self.target
+            },
             crop_width: if self.crop_width == 0 {
                 None
             } else {
@@ -167,14 +799,33 @@ impl ApplicationState {
             } else {
                 Some(self.crop_height)
             },
-            vert_offset: self.vert_offset,
-            horiz_offset: self.horiz_offset,
-            max_frames: Some(self.max_frames),
+            // Offsets are entered relative to the corrected orientation while solhat applies them
+            // to the frames as captured
+            vert_offset: if self.frame_orientation.flips_vertical() {
+                -self.vert_offset
+            } else {
+                self.vert_offset
+            },
+            horiz_offset: if self.frame_orientation.flips_horizontal() {
+                -self.horiz_offset
+            } else {
+                self.horiz_offset
+            },
+            // Sampling strategies other than truncation are applied after solhat's limiting
+            max_frames: if self.frame_sampling == FrameSampling::Truncate {
+                Some(self.max_frames)
+            } else {
+                None
+            },
             min_sigma: Some(self.min_sigma),
             max_sigma: Some(self.max_sigma),
             top_percentage: Some(self.top_percentage),
-            drizzle_scale: self.drizzle_scale,
-            algorithm: self.algorithm,
+            drizzle_scale: self.preset_drizzle_scale(),
+            // Methods solhat doesn't provide are stacked by `process::combine` instead
+            algorithm: self
+                .algorithm
+                .solhat_algorithm()
+                .unwrap_or(StackAlgorithm::Average),
             initial_rotation: 0.0,
             flat_inputs: self.flat.to_owned(),
             dark_inputs: self.dark.to_owned(),
@@ -207,10 +858,214 @@ impl ApplicationState {
             fs::create_dir(&solhat_config_dir).expect("Failed to create config directory");
         }
         let config_file_path = solhat_config_dir.join("window-config.toml");
-        let mut f = File::create(config_file_path).expect("Failed to create config file");
-        f.write_all(toml_str.as_bytes())
-            .expect("Failed to write to config file");
-        debug!("{}", toml_str);
+        write_private(&config_file_path, &toml_str).expect("Failed to write to config file");
+        debug!("Saved window state to {:?}", config_file_path);
+    }
+
+    /// Writes the state to the autosave file, used to recover from an unclean shutdown. Failures
+    /// are logged rather than interrupting the user.
+    pub fn autosave(&self) {
+        let result = (|| -> Result<()> {
+            fs::create_dir_all(solhat_config_dir())?;
+            write_private(
+                &solhat_config_dir().join("autosave.toml"),
+                &toml::to_string(&self)?,
+            )?;
+            Ok(())
+        })();
+        if let Err(why) = result {
+            warn!("Failed to autosave application state: {:?}", why);
+        }
+    }
+
+    pub fn load_autosave() -> Result<Self> {
+        let t = std::fs::read_to_string(solhat_config_dir().join("autosave.toml"))?;
+        Ok(toml::from_str(&t)?)
+    }
+
+    /// Returns a copy of the state with all input and output paths and their Bayer pattern
+    /// overrides, the observer location, session notes and targets, frame range and window state
+    /// removed, leaving only the processing options.
+    pub fn without_paths(&self) -> Self {
+        let defaults = ApplicationState::default();
+        ApplicationState {
+            light: None,
+            prominence_light: None,
+            dark: None,
+            flat: None,
+            darkflat: None,
+            bias: None,
+            additional_darks: vec![],
+            additional_flats: vec![],
+            additional_darkflats: vec![],
+            additional_biases: vec![],
+            bayer: BayerOverrides::default(),
+            hot_pixel_map: None,
+            output_dir: None,
+            masters_dir: None,
+            obs_latitude: defaults.obs_latitude,
+            obs_longitude: defaults.obs_longitude,
+            session_notes: defaults.session_notes,
+            session_targets: vec![],
+            active_target: 0,
+            frame_range: None,
+            window: WindowState::default(),
+            ..self.clone()
+        }
+    }
+
+    /// Replaces the processing options with those of `other`, keeping the paths, Bayer pattern
+    /// overrides, observer location, session notes and targets, frame range and window state of
+    /// this state.
+    pub fn apply_options_from(&mut self, other: &ApplicationState) {
+        *self = ApplicationState {
+            light: self.light.take(),
+            prominence_light: self.prominence_light.take(),
+            dark: self.dark.take(),
+            flat: self.flat.take(),
+            darkflat: self.darkflat.take(),
+            bias: self.bias.take(),
+            additional_darks: std::mem::take(&mut self.additional_darks),
+            additional_flats: std::mem::take(&mut self.additional_flats),
+            additional_darkflats: std::mem::take(&mut self.additional_darkflats),
+            additional_biases: std::mem::take(&mut self.additional_biases),
+            bayer: self.bayer.clone(),
+            hot_pixel_map: self.hot_pixel_map.take(),
+            output_dir: self.output_dir.take(),
+            masters_dir: self.masters_dir.take(),
+            obs_latitude: self.obs_latitude,
+            obs_longitude: self.obs_longitude,
+            session_notes: self.session_notes.clone(),
+            session_targets: std::mem::take(&mut self.session_targets),
+            active_target: self.active_target,
+            frame_range: self.frame_range,
+            window: self.window.clone(),
+            ..other.clone()
+        };
+    }
+
+    /// Copies the light files, frame range and options into the active target's entry
+    pub fn store_active_target(&mut self) {
+        let light = self.light.clone();
+        let prominence_light = self.prominence_light.clone();
+        let frame_range = self.frame_range;
+        let options = Box::new(self.without_paths());
+        if let Some(target) = self.session_targets.get_mut(self.active_target) {
+            target.light = light;
+            target.prominence_light = prominence_light;
+            target.frame_range = frame_range;
+            target.options = options;
+        }
+    }
+
+    /// Makes the target at `index` the active one, keeping the current target's light file and
+    /// options in its entry
+    pub fn switch_target(&mut self, index: usize) {
+        if index == self.active_target || index >= self.session_targets.len() {
+            return;
+        }
+        self.store_active_target();
+        let target = self.session_targets[index].clone();
+        self.apply_options_from(&target.options);
+        self.light = target.light;
+        self.prominence_light = target.prominence_light;
+        self.frame_range = target.frame_range;
+        self.active_target = index;
+    }
+
+    /// Adds a target named `name` with a copy of the current light file and options, and makes it
+    /// the active one. Adding the first target also adds an entry, named `current_name`, for what
+    /// was being worked on.
+    pub fn add_target(&mut self, current_name: &str, name: &str) {
+        if self.session_targets.is_empty() {
+            self.session_targets.push(SessionTarget {
+                name: current_name.to_owned(),
+                ..Default::default()
+            });
+            self.active_target = 0;
+        }
+        self.store_active_target();
+        let mut target = self.session_targets[self.active_target].clone();
+        target.name = name.to_owned();
+        self.session_targets.push(target);
+        self.active_target = self.session_targets.len() - 1;
+    }
+
+    /// Inserts a copy of the target at `index`, named `name`, after it and makes the copy the
+    /// active one, so a variant of its options can be tried
+    pub fn duplicate_target(&mut self, index: usize, name: &str) {
+        if index >= self.session_targets.len() {
+            return;
+        }
+        self.store_active_target();
+        let mut target = self.session_targets[index].clone();
+        target.name = name.to_owned();
+        self.session_targets.insert(index + 1, target);
+        if self.active_target > index {
+            self.active_target += 1;
+        }
+        self.switch_target(index + 1);
+    }
+
+    /// Removes the target at `index`, switching to a neighbouring one if it's active. Removing
+    /// the second to last target leaves a single target session.
+    pub fn remove_target(&mut self, index: usize) {
+        if index >= self.session_targets.len() {
+            return;
+        }
+        if index == self.active_target {
+            self.switch_target(if index == 0 { 1 } else { index - 1 });
+        }
+        self.session_targets.remove(index);
+        if self.active_target > index {
+            self.active_target -= 1;
+        }
+        if self.session_targets.len() < 2 {
+            self.session_targets.clear();
+            self.active_target = 0;
+        }
+    }
+
+    /// The full state of each target, as it would be processed with that target active
+    pub fn target_states(&self) -> Vec<ApplicationState> {
+        (0..self.session_targets.len())
+            .map(|index| {
+                let mut state = self.clone();
+                state.switch_target(index);
+                state
+            })
+            .collect()
+    }
+
+    /// Writes the processing state to a project file. Window state is not part of a project.
+    pub fn save_project(&self, path: &Path) -> Result<()> {
+        let mut project = self.clone();
+        project.store_active_target();
+        project.window = WindowState::default();
+        let toml_str = toml::to_string(&project)?;
+        let mut f = File::create(path)?;
+        f.write_all(toml_str.as_bytes())?;
+        info!("Saved project to {:?}", path);
+        Ok(())
+    }
+
+    /// Reads a project file, keeping the current window state.
+    pub fn load_project(&self, path: &Path) -> Result<Self> {
+        let t = std::fs::read_to_string(path)?;
+        let mut project: ApplicationState = toml::from_str(&t)?;
+        project.window = self.window.clone();
+        project.window.current_project = Some(path.to_path_buf());
+        info!("Loaded project from {:?}", path);
+        Ok(project)
+    }
+
+    /// Name of the observed target, "Planet" in planetary mode
+    pub fn target_name(&self) -> String {
+        if self.planetary {
+            "Planet".to_owned()
+        } else {
+            format!("{:?}", self.target)
+        }
     }
 
     pub fn assemble_output_filename(&self) -> Result<PathBuf> {
@@ -228,31 +1083,154 @@ impl ApplicationState {
             return Err(anyhow!("Input light file not provided"));
         };
 
-        let freetext = if !self.freetext.is_empty() {
-            format!("_{}", self.freetext)
-        } else {
-            "".to_owned()
-        };
-
-        let drizzle = match self.drizzle_scale {
-            Scale::Scale1_0 => "".to_owned(),
-            _ => format!(
-                "_{}",
-                self.drizzle_scale.to_string().replace([' ', '.'], "")
-            ),
+        let mut drizzle = match self.custom_drizzle_scale {
+            Some(custom) if self.custom_drizzled() => {
+                format!("{}x", (custom * 100.0).round() / 100.0).replace('.', "")
+            }
+            _ => match self.preset_drizzle_scale() {
+                Scale::Scale1_0 => "".to_owned(),
+                scale => scale.to_string().replace([' ', '.'], ""),
+            },
         };
+        if self.custom_drizzled() && self.drizzle_pixfrac < 1.0 {
+            drizzle.push_str(&format!("pf{}", (self.drizzle_pixfrac * 100.0).round()));
+        }
 
-        let output_filename = format!(
-            "{}_{:?}{}{}.tif",
-            base_filename.to_string_lossy().as_ref(),
-            self.target,
-            drizzle,
-            freetext
-        );
+        let name = filename::render(&self.output_template, |token| match token {
+            "basename" => Some(base_filename.to_string_lossy().to_string()),
+            "target" => Some(self.target_name()),
+            "drizzle" => Some(drizzle.clone()),
+            "orientation" => Some(self.frame_orientation.filename_token().to_owned()),
+            "date" => Some(chrono::Local::now().format("%Y%m%d").to_string()),
+            "freetext" => Some(self.freetext.clone()),
+            _ => None,
+        })?;
+        let output_filename = format!("{}.{}", name, self.output_format.extension());
         let output_path: PathBuf = Path::new(output_dir).join(output_filename);
         Ok(output_path)
     }
 
+    /// Quality sample point offsets, relative to the frame center of mass, for the selected
+    /// analysis point mode.
+    pub fn analysis_point_offsets(&self) -> Vec<[i32; 2]> {
+        match self.analysis_points {
+            AnalysisPoints::CenterOfMass => vec![[0, 0]],
+            AnalysisPoints::Grid => {
+                let n = self.analysis_grid_size.max(1) as i32;
+                let spacing = self.analysis_grid_spacing as i32;
+                let half = (n - 1) as f32 / 2.0;
+                (0..n)
+                    .flat_map(|gy| {
+                        (0..n).map(move |gx| {
+                            [
+                                ((gx as f32 - half) * spacing as f32) as i32,
+                                ((gy as f32 - half) * spacing as f32) as i32,
+                            ]
+                        })
+                    })
+                    .collect()
+            }
+            AnalysisPoints::Custom => {
+                if self.analysis_custom_points.is_empty() {
+                    vec![[0, 0]]
+                } else {
+                    self.analysis_custom_points.clone()
+                }
+            }
+        }
+    }
+
+    /// True if a custom drizzle scale or a drop size smaller than a pixel is set, which solhat
+    /// can't stack
+    pub fn custom_drizzle(&self) -> bool {
+        self.custom_drizzle_scale.is_some() || self.drizzle_pixfrac < 1.0
+    }
+
+    /// True if the frames are drizzled at the custom scale and drop size. Other stacks use the
+    /// nearest preset scale.
+    pub fn custom_drizzled(&self) -> bool {
+        self.custom_drizzle()
+            && self.algorithm.supports_custom_drizzle()
+            && self.alignment_mode != AlignmentMode::AlignmentPoints
+    }
+
+    /// The drizzle scale solhat stacks with: the selected preset, or the preset nearest the
+    /// custom scale
+    pub fn preset_drizzle_scale(&self) -> Scale {
+        match self.custom_drizzle_scale {
+            Some(custom) => DRIZZLE_PRESETS
+                .iter()
+                .min_by(|(_, a), (_, b)| (a - custom).abs().total_cmp(&(b - custom).abs()))
+                .map(|(scale, _)| *scale)
+                .unwrap_or(self.drizzle_scale),
+            None => self.drizzle_scale,
+        }
+    }
+
+    /// Upscale factor applied by the selected drizzle scale, or the custom scale if the frames are
+    /// drizzled at it
+    pub fn drizzle_factor(&self) -> f32 {
+        match self.custom_drizzle_scale {
+            Some(custom) if self.custom_drizzled() => custom,
+            _ => {
+                let scale = self.preset_drizzle_scale();
+                DRIZZLE_PRESETS
+                    .iter()
+                    .find(|(preset, _)| *preset == scale)
+                    .map(|(_, factor)| *factor)
+                    .unwrap_or(1.0)
+            }
+        }
+    }
+
+    /// Number of bytes used to store each output sample
+    pub fn output_bytes_per_sample(&self) -> usize {
+        match self.output_format {
+            OutputFormat::Jpeg => 1,
+            _ if self.float_output() => 4,
+            _ => 2,
+        }
+    }
+
+    /// Whether the output is written as unnormalized 32-bit floating point samples
+    pub fn float_output(&self) -> bool {
+        self.output_format == OutputFormat::Tiff && self.output_tiff.float
+    }
+
+    /// Computes the dimensions of the stacked output given the light frame dimensions. An unset
+    /// (zero) crop falls back to the full frame.
+    pub fn output_dimensions(&self, frame_size: [usize; 2]) -> [usize; 2] {
+        let width = if self.crop_width == 0 {
+            frame_size[0]
+        } else {
+            self.crop_width
+        };
+        let height = if self.crop_height == 0 {
+            frame_size[1]
+        } else {
+            self.crop_height
+        };
+        let factor = self.drizzle_factor();
+        [
+            (width as f32 * factor).round() as usize,
+            (height as f32 * factor).round() as usize,
+        ]
+    }
+
+    /// Estimates the uncompressed size, in bytes, of the output image
+    pub fn estimated_output_bytes(&self, frame_size: [usize; 2], num_bands: usize) -> usize {
+        let [width, height] = self.output_dimensions(frame_size);
+        width * height * num_bands * self.output_bytes_per_sample()
+    }
+
+    /// True if the frames are stacked weighted by their quality. Stacking on alignment points
+    /// selects the measurements of each point by their own quality instead.
+    pub fn quality_weighted(&self) -> bool {
+        self.quality_weighting
+            && self.algorithm.supports_weighting()
+            && self.alignment_mode != AlignmentMode::AlignmentPoints
+    }
+
     pub fn enforce_value_bounds(&mut self) {
         if self.obs_latitude > 90.0 {
             self.obs_latitude = 90.0; // Hello North Pole!
@@ -1,3 +1,4 @@
+use std::collections::BTreeMap;
 use std::sync::{Arc, Mutex};
 
 #[derive(Clone)]
@@ -5,57 +6,181 @@ pub enum TaskStatus {
     TaskPercentage(String, usize, usize),
 }
 
+impl TaskStatus {
+    /// Fraction of the current task which has been completed, in the range 0.0 to 1.0
+    pub fn fraction(&self) -> f32 {
+        match self {
+            TaskStatus::TaskPercentage(_, len, cnt) => {
+                if *len > 0 {
+                    *cnt as f32 / *len as f32
+                } else {
+                    0.0
+                }
+            }
+        }
+    }
+}
+
+/// Status of the task running in one job slot
 #[derive(Default, Clone)]
 pub struct TaskStatusContainer {
+    /// Name of the job running in the slot, if it's one of several
+    pub label: Option<String>,
     pub status: Option<TaskStatus>,
 }
 
+/// Slot of tasks which aren't part of a parallel run, and of the run itself
+pub const PRIMARY_SLOT: usize = 0;
+
+tokio::task_local! {
+    // Slot whose status container `set_task_status` updates from within a job
+    static JOB_SLOT: usize;
+}
+
+/// Whether a multi-job run is made of queued jobs or of the files of a batch folder
+#[derive(Default, Clone, Copy, PartialEq)]
+pub enum QueueKind {
+    #[default]
+    Jobs,
+    Files,
+}
+
+/// Position of the running job within a multi-job run (1-based `job_number`).
+#[derive(Default, Clone, Copy)]
+pub struct QueueProgress {
+    pub job_number: usize,
+    pub job_count: usize,
+    pub kind: QueueKind,
+}
+
+impl QueueProgress {
+    /// Describes the position of the running job, e.g. "File 3 of 12"
+    pub fn describe(&self) -> String {
+        match self.kind {
+            QueueKind::Jobs => t!(
+                "queue.job_of",
+                job = self.job_number,
+                count = self.job_count
+            ),
+            QueueKind::Files => t!(
+                "queue.file_of",
+                file = self.job_number,
+                count = self.job_count
+            ),
+        }
+        .to_string()
+    }
+
+    /// Estimates the fraction of all work done, treating each job as an equal share and crediting
+    /// the running job with the progress of its current task.
+    pub fn overall_fraction(&self, task_fraction: f32) -> f32 {
+        if self.job_count == 0 {
+            0.0
+        } else {
+            (self.job_number.saturating_sub(1) as f32 + task_fraction) / self.job_count as f32
+        }
+    }
+}
+
 lazy_static! {
-    static ref TASK_STATUS_QUEUE: Arc<Mutex<TaskStatusContainer>> =
-        Arc::new(Mutex::new(TaskStatusContainer::default()));
+    static ref TASK_STATUS_QUEUE: Arc<Mutex<BTreeMap<usize, TaskStatusContainer>>> =
+        Arc::new(Mutex::new(BTreeMap::new()));
+    static ref QUEUE_PROGRESS: Arc<Mutex<Option<QueueProgress>>> = Arc::new(Mutex::new(None));
+}
+
+/// Runs `future` with its task status reported in `slot`, so jobs running alongside each other
+/// each get their own progress.
+pub async fn run_in_slot<F: std::future::Future>(slot: usize, future: F) -> F::Output {
+    JOB_SLOT.scope(slot, future).await
+}
+
+/// Slot of the job running on the current task, or the primary slot outside of a job. Work handed
+/// off to other threads, such as rayon pools, has to be given the slot of its job.
+pub fn current_slot() -> usize {
+    JOB_SLOT.try_with(|slot| *slot).unwrap_or(PRIMARY_SLOT)
 }
 
 pub fn is_task_running() -> bool {
-    if let Ok(status) = TASK_STATUS_QUEUE.lock() {
-        status.status.is_some()
+    if let Ok(statuses) = TASK_STATUS_QUEUE.lock() {
+        statuses.values().any(|s| s.status.is_some())
     } else {
         false
     }
 }
 
+/// Status of the primary slot
 pub fn get_task_status() -> Option<TaskStatus> {
-    if let Ok(status) = TASK_STATUS_QUEUE.lock() {
-        status.status.clone()
+    if let Ok(statuses) = TASK_STATUS_QUEUE.lock() {
+        statuses.get(&PRIMARY_SLOT).and_then(|s| s.status.clone())
     } else {
         None
     }
 }
 
+/// Statuses of the jobs running in slots other than the primary, with their slots
+pub fn get_job_statuses() -> Vec<(usize, TaskStatusContainer)> {
+    if let Ok(statuses) = TASK_STATUS_QUEUE.lock() {
+        statuses
+            .iter()
+            .filter(|(slot, s)| **slot != PRIMARY_SLOT && s.status.is_some())
+            .map(|(slot, s)| (*slot, s.clone()))
+            .collect()
+    } else {
+        vec![]
+    }
+}
+
+/// Names the job running in the current slot
+pub fn set_task_label(label: &str) {
+    if let Ok(mut statuses) = TASK_STATUS_QUEUE.lock() {
+        statuses.entry(current_slot()).or_default().label = Some(label.to_owned());
+    }
+}
+
 pub fn set_task_status(task_name: &str, num_parts: usize, progress: usize) {
-    // let mut status = TASK_STATUS_QUEUE.lock().unwrap();
-    if let Ok(mut status) = TASK_STATUS_QUEUE.lock() {
-        status.status = Some(TaskStatus::TaskPercentage(
+    set_slot_task_status(current_slot(), task_name, num_parts, progress);
+}
+
+pub fn set_slot_task_status(slot: usize, task_name: &str, num_parts: usize, progress: usize) {
+    if let Ok(mut statuses) = TASK_STATUS_QUEUE.lock() {
+        statuses.entry(slot).or_default().status = Some(TaskStatus::TaskPercentage(
             task_name.to_owned(),
             num_parts,
             progress,
         ));
     }
-    // sender
-    //     .send(TaskStatusContainer {
-    //         status: Some(TaskStatus::TaskPercentage(
-    //             task_name.to_owned(),
-    //             num_parts,
-    //             progress,
-    //         )),
-    //     })
-    //     .expect("Failed to sent task status");
 }
 
 pub fn set_task_completed() {
-    if let Ok(mut status) = TASK_STATUS_QUEUE.lock() {
-        status.status = None;
+    set_slot_task_completed(current_slot());
+}
+
+pub fn set_slot_task_completed(slot: usize) {
+    if let Ok(mut statuses) = TASK_STATUS_QUEUE.lock() {
+        statuses.remove(&slot);
+    }
+}
+
+pub fn set_queue_progress(job_number: usize, job_count: usize, kind: QueueKind) {
+    if let Ok(mut progress) = QUEUE_PROGRESS.lock() {
+        *progress = Some(QueueProgress {
+            job_number,
+            job_count,
+            kind,
+        });
+    }
+}
+
+pub fn clear_queue_progress() {
+    if let Ok(mut progress) = QUEUE_PROGRESS.lock() {
+        *progress = None;
+    }
+}
+
+pub fn get_queue_progress() -> Option<QueueProgress> {
+    if let Ok(progress) = QUEUE_PROGRESS.lock() {
+        *progress
+    } else {
+        None
     }
-    // sender
-    //     .send(TaskStatusContainer { status: None })
-    //     .expect("Failed to sent task status");
 }
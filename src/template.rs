@@ -0,0 +1,67 @@
+//! Templates with tokens in braces, e.g. `{target}`, shared by output filenames and result
+//! captions and descriptions.
+
+use anyhow::{anyhow, Result};
+
+/// Separators between tokens, dropped along with empty tokens by `Separators::Collapse`
+const SEPARATORS: &[char] = &['_', '-', ' ', '.'];
+
+/// What to do with the separator next to a token that renders empty
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Separators {
+    /// Leave the text around the token as written
+    Keep,
+    /// Drop one separator along with the token, so there are no doubled separators
+    Collapse,
+}
+
+/// Replaces each token in `template` with its value from `value_of`, which returns None for
+/// tokens it doesn't recognize. Fails on unknown tokens and unbalanced braces.
+pub fn render<F>(template: &str, separators: Separators, value_of: F) -> Result<String>
+where
+    F: Fn(&str) -> Option<String>,
+{
+    let mut rendered = String::new();
+    let mut rest = template;
+    let mut drop_next_separator = false;
+    loop {
+        let literal_end = rest.find(['{', '}']).unwrap_or(rest.len());
+        let mut literal = &rest[..literal_end];
+        if drop_next_separator {
+            literal = literal.strip_prefix(SEPARATORS).unwrap_or(literal);
+            drop_next_separator = false;
+        }
+        rendered.push_str(literal);
+        rest = &rest[literal_end..];
+
+        if rest.is_empty() {
+            break;
+        } else if rest.starts_with('}') {
+            return Err(anyhow!("Unmatched '}}' in template"));
+        }
+        let end = rest
+            .find('}')
+            .ok_or_else(|| anyhow!("Unclosed '{{' in template"))?;
+        let token = &rest[1..end];
+        let value =
+            value_of(token).ok_or_else(|| anyhow!("Unknown token {{{}}} in template", token))?;
+        if value.is_empty() && separators == Separators::Collapse {
+            if rendered.ends_with(SEPARATORS) {
+                rendered.pop();
+            } else {
+                drop_next_separator = true;
+            }
+        }
+        rendered.push_str(&value);
+        rest = &rest[end + 1..];
+    }
+    Ok(rendered)
+}
+
+/// Checks `template` for tokens missing from `tokens` and unbalanced braces
+pub fn validate(template: &str, tokens: &[&str]) -> Result<()> {
+    render(template, Separators::Keep, |token| {
+        tokens.contains(&token).then(|| token.to_owned())
+    })
+    .map(|_| ())
+}
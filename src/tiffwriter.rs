@@ -0,0 +1,287 @@
+//! Writes exported TIFFs with the compression and data layout chosen in the output options, which
+//! the image encoder has no settings for. Compressed data uses the horizontal differencing
+//! predictor, which shrinks smooth 16-bit solar data considerably more than compression alone.
+//! Floating point output is written without a predictor, as few readers support the floating
+//! point one.
+
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+
+use anyhow::{anyhow, Result};
+use flate2::write::ZlibEncoder;
+use itertools::iproduct;
+use sciimg::prelude::{Image, ImageMode};
+use serde::{Deserialize, Serialize};
+
+const TAG_IMAGE_WIDTH: u16 = 256;
+const TAG_IMAGE_LENGTH: u16 = 257;
+const TAG_BITS_PER_SAMPLE: u16 = 258;
+const TAG_COMPRESSION: u16 = 259;
+const TAG_PHOTOMETRIC: u16 = 262;
+const TAG_STRIP_OFFSETS: u16 = 273;
+const TAG_SAMPLES_PER_PIXEL: u16 = 277;
+const TAG_ROWS_PER_STRIP: u16 = 278;
+const TAG_STRIP_BYTE_COUNTS: u16 = 279;
+const TAG_PLANAR_CONFIGURATION: u16 = 284;
+const TAG_PREDICTOR: u16 = 317;
+const TAG_SAMPLE_FORMAT: u16 = 339;
+const TAG_ICC_PROFILE: u16 = 34675;
+
+const TYPE_SHORT: u16 = 3;
+const TYPE_LONG: u16 = 4;
+const TYPE_UNDEFINED: u16 = 7;
+
+/// Compression of the image data of exported TIFFs
+#[derive(Debug, Eq, PartialEq, Deserialize, Serialize, Clone, Copy, Default)]
+pub enum TiffCompression {
+    #[default]
+    None,
+    Lzw,
+    Deflate,
+}
+
+impl TiffCompression {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            TiffCompression::None => "None",
+            TiffCompression::Lzw => "LZW",
+            TiffCompression::Deflate => "Deflate",
+        }
+    }
+
+    /// Value of the TIFF compression tag
+    fn tag_value(&self) -> u16 {
+        match *self {
+            TiffCompression::None => 1,
+            TiffCompression::Lzw => 5,
+            TiffCompression::Deflate => 8,
+        }
+    }
+
+    fn compress(&self, data: Vec<u8>) -> Result<Vec<u8>> {
+        match *self {
+            TiffCompression::None => Ok(data),
+            TiffCompression::Lzw => {
+                weezl::encode::Encoder::with_tiff_size_switch(weezl::BitOrder::Msb, 8)
+                    .encode(&data)
+                    .map_err(|why| anyhow!("LZW compression failed: {}", why))
+            }
+            TiffCompression::Deflate => {
+                let mut encoder = ZlibEncoder::new(vec![], flate2::Compression::default());
+                encoder.write_all(&data)?;
+                Ok(encoder.finish()?)
+            }
+        }
+    }
+}
+
+/// How exported TIFFs are compressed and laid out
+#[derive(Debug, Eq, PartialEq, Deserialize, Serialize, Clone, Copy)]
+#[serde(default)]
+pub struct TiffOptions {
+    pub compression: TiffCompression,
+    /// Store each color band as a separate plane rather than interleaving the bands of each pixel
+    pub planar: bool,
+    pub rows_per_strip: u32,
+    /// Write the stack unnormalized as 32-bit floating point samples, with the 16-bit range
+    /// scaled to 0-1, for further processing in other tools
+    pub float: bool,
+}
+
+impl Default for TiffOptions {
+    fn default() -> Self {
+        TiffOptions {
+            compression: TiffCompression::None,
+            planar: false,
+            rows_per_strip: 64,
+            float: false,
+        }
+    }
+}
+
+/// An image directory entry, with its values in file byte order
+struct Entry {
+    tag: u16,
+    field_type: u16,
+    count: u32,
+    values: Vec<u8>,
+}
+
+impl Entry {
+    fn shorts(tag: u16, values: &[u16]) -> Entry {
+        Entry {
+            tag,
+            field_type: TYPE_SHORT,
+            count: values.len() as u32,
+            values: values.iter().flat_map(|v| v.to_le_bytes()).collect(),
+        }
+    }
+
+    fn longs(tag: u16, values: &[u32]) -> Entry {
+        Entry {
+            tag,
+            field_type: TYPE_LONG,
+            count: values.len() as u32,
+            values: values.iter().flat_map(|v| v.to_le_bytes()).collect(),
+        }
+    }
+}
+
+/// Samples of rows `rows` of the bands `bands` of `image`, interleaved by pixel, as 32-bit floats.
+/// Values are divided by 65535 so the 16-bit range maps to 0-1; anything outside that range in an
+/// unnormalized stack is kept rather than clipped.
+fn float_strip_data(image: &Image, rows: std::ops::Range<usize>, bands: &[usize]) -> Vec<u8> {
+    iproduct!(rows, 0..image.width, bands.iter())
+        .flat_map(|(y, x, b)| (image.get_band(*b).get(x, y) / 65535.0).to_le_bytes())
+        .collect()
+}
+
+/// Samples of rows `rows` of the bands `bands` of `image`, interleaved by pixel, with the
+/// horizontal differencing predictor applied if `predict`
+fn strip_data(
+    image: &Image,
+    rows: std::ops::Range<usize>,
+    bands: &[usize],
+    bits: usize,
+    predict: bool,
+) -> Vec<u8> {
+    let max = ((1_u32 << bits) - 1) as f32;
+    let samples_per_row = image.width * bands.len();
+    let mut data = Vec::with_capacity(rows.len() * samples_per_row * bits / 8);
+    for y in rows {
+        let mut row: Vec<u16> = (0..image.width)
+            .flat_map(|x| {
+                bands
+                    .iter()
+                    .map(move |b| image.get_band(*b).get(x, y).round().clamp(0.0, max) as u16)
+            })
+            .collect();
+        if predict {
+            // Each sample becomes its difference from the same sample of the pixel before it
+            for i in (bands.len()..samples_per_row).rev() {
+                row[i] = row[i].wrapping_sub(row[i - bands.len()]) & max as u16;
+            }
+        }
+        if bits == 8 {
+            data.extend(row.iter().map(|v| *v as u8));
+        } else {
+            data.extend(row.iter().flat_map(|v| v.to_le_bytes()));
+        }
+    }
+    data
+}
+
+pub fn is_tiff_file(path: &Path) -> bool {
+    path.extension()
+        .map(|e| {
+            let e = e.to_string_lossy();
+            e.eq_ignore_ascii_case("tif") || e.eq_ignore_ascii_case("tiff")
+        })
+        .unwrap_or(false)
+}
+
+/// Saves `image` as a little endian TIFF with `options`, tagged with `icc_profile` if given. 8-bit
+/// images are written with 8 bits per sample, all others with 16, unless floating point output is
+/// chosen.
+pub fn save_tiff(
+    image: &Image,
+    path: &Path,
+    options: &TiffOptions,
+    icc_profile: Option<&[u8]>,
+) -> Result<()> {
+    let num_bands = image.num_bands();
+    if num_bands != 1 && num_bands != 3 {
+        return Err(anyhow!(
+            "Can't write a TIFF of an image with {} bands",
+            num_bands
+        ));
+    }
+    let bits = if options.float {
+        32
+    } else if image.get_mode() == ImageMode::U8BIT {
+        8
+    } else {
+        16
+    };
+    let planar = options.planar && num_bands > 1;
+    let predict = options.compression != TiffCompression::None && !options.float;
+    let rows_per_strip = (options.rows_per_strip.max(1) as usize).min(image.height.max(1));
+    let planes: Vec<Vec<usize>> = if planar {
+        (0..num_bands).map(|b| vec![b]).collect()
+    } else {
+        vec![(0..num_bands).collect()]
+    };
+
+    let mut file = b"II*\0\0\0\0\0".to_vec();
+    let mut strip_offsets = vec![];
+    let mut strip_byte_counts = vec![];
+    for bands in planes.iter() {
+        for start in (0..image.height).step_by(rows_per_strip) {
+            let rows = start..(start + rows_per_strip).min(image.height);
+            let data = if options.float {
+                float_strip_data(image, rows, bands)
+            } else {
+                strip_data(image, rows, bands, bits, predict)
+            };
+            let strip = options.compression.compress(data)?;
+            strip_offsets.push(file.len() as u32);
+            strip_byte_counts.push(strip.len() as u32);
+            file.extend_from_slice(&strip);
+            file.resize(file.len().div_ceil(2) * 2, 0);
+        }
+    }
+
+    let mut entries = vec![
+        Entry::longs(TAG_IMAGE_WIDTH, &[image.width as u32]),
+        Entry::longs(TAG_IMAGE_LENGTH, &[image.height as u32]),
+        Entry::shorts(TAG_BITS_PER_SAMPLE, &vec![bits as u16; num_bands]),
+        Entry::shorts(TAG_COMPRESSION, &[options.compression.tag_value()]),
+        // Black is zero grayscale, or RGB
+        Entry::shorts(TAG_PHOTOMETRIC, &[if num_bands == 1 { 1 } else { 2 }]),
+        Entry::longs(TAG_STRIP_OFFSETS, &strip_offsets),
+        Entry::shorts(TAG_SAMPLES_PER_PIXEL, &[num_bands as u16]),
+        Entry::longs(TAG_ROWS_PER_STRIP, &[rows_per_strip as u32]),
+        Entry::longs(TAG_STRIP_BYTE_COUNTS, &strip_byte_counts),
+        Entry::shorts(TAG_PLANAR_CONFIGURATION, &[if planar { 2 } else { 1 }]),
+    ];
+    if predict {
+        entries.push(Entry::shorts(TAG_PREDICTOR, &[2]));
+    }
+    if options.float {
+        // IEEE floating point samples
+        entries.push(Entry::shorts(TAG_SAMPLE_FORMAT, &vec![3; num_bands]));
+    }
+    if let Some(icc_profile) = icc_profile {
+        entries.push(Entry {
+            tag: TAG_ICC_PROFILE,
+            field_type: TYPE_UNDEFINED,
+            count: icc_profile.len() as u32,
+            values: icc_profile.to_vec(),
+        });
+    }
+
+    // Values too long to fit in their entry go ahead of the directory
+    let mut directory = (entries.len() as u16).to_le_bytes().to_vec();
+    for entry in entries.iter() {
+        directory.extend_from_slice(&entry.tag.to_le_bytes());
+        directory.extend_from_slice(&entry.field_type.to_le_bytes());
+        directory.extend_from_slice(&entry.count.to_le_bytes());
+        if entry.values.len() <= 4 {
+            let mut values = entry.values.clone();
+            values.resize(4, 0);
+            directory.extend_from_slice(&values);
+        } else {
+            directory.extend_from_slice(&(file.len() as u32).to_le_bytes());
+            file.extend_from_slice(&entry.values);
+            file.resize(file.len().div_ceil(2) * 2, 0);
+        }
+    }
+    directory.extend_from_slice(&0_u32.to_le_bytes());
+
+    let directory_offset = file.len() as u32;
+    file[4..8].copy_from_slice(&directory_offset.to_le_bytes());
+    file.extend_from_slice(&directory);
+    fs::write(path, file)?;
+    Ok(())
+}
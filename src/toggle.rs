@@ -27,3 +27,18 @@ pub fn toggle_ui(ui: &mut egui::Ui, on: &mut bool) -> egui::Response {
 pub fn toggle(on: &mut bool) -> impl egui::Widget + '_ {
     move |ui: &mut egui::Ui| toggle_ui(ui, on)
 }
+
+/// Padlock button which holds an option fixed while `locked`
+pub fn lock_ui(ui: &mut egui::Ui, locked: &mut bool) -> egui::Response {
+    let icon = if *locked { "🔒" } else { "🔓" };
+    let mut response = ui.add(egui::SelectableLabel::new(*locked, icon));
+    if response.clicked() {
+        *locked = !*locked;
+        response.mark_changed();
+    }
+    response
+}
+
+pub fn lock(locked: &mut bool) -> impl egui::Widget + '_ {
+    move |ui: &mut egui::Ui| lock_ui(ui, locked)
+}
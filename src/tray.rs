@@ -0,0 +1,178 @@
+//! System tray icon shown while processing runs in the background with the main window hidden.
+//! The icon's tooltip follows the task progress and its menu brings the window back or cancels
+//! the run.
+
+use anyhow::Result;
+use tray_icon::menu::{Menu, MenuEvent, MenuItem};
+use tray_icon::{Icon, TrayIcon, TrayIconBuilder};
+
+const SHOW_MENU_ID: &str = "solhat-tray-show";
+const CANCEL_MENU_ID: &str = "solhat-tray-cancel";
+
+/// Selections made from the tray icon menu
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TrayAction {
+    ShowWindow,
+    Cancel,
+}
+
+/// RGBA pixels of the tray icon image
+#[derive(Clone)]
+pub struct TrayIconImage {
+    pub rgba: Vec<u8>,
+    pub width: u32,
+    pub height: u32,
+}
+
+fn build_tray_icon(image: &TrayIconImage, tooltip: &str) -> Result<TrayIcon> {
+    let menu = Menu::new();
+    menu.append(&MenuItem::with_id(
+        SHOW_MENU_ID,
+        t!("tray.show_window"),
+        true,
+        None,
+    ))?;
+    menu.append(&MenuItem::with_id(
+        CANCEL_MENU_ID,
+        t!("tray.cancel"),
+        true,
+        None,
+    ))?;
+    let icon = Icon::from_rgba(image.rgba.clone(), image.width, image.height)?;
+    Ok(TrayIconBuilder::new()
+        .with_menu(Box::new(menu))
+        .with_tooltip(tooltip)
+        .with_icon(icon)
+        .build()?)
+}
+
+/// Next selection made from the tray icon menu, if any
+pub fn poll_action() -> Option<TrayAction> {
+    while let Ok(event) = MenuEvent::receiver().try_recv() {
+        match event.id.as_ref() {
+            SHOW_MENU_ID => return Some(TrayAction::ShowWindow),
+            CANCEL_MENU_ID => return Some(TrayAction::Cancel),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Shows a desktop notification. Sent from its own thread as some platforms block until the
+/// notification server responds.
+pub fn notify(summary: &str, body: &str) {
+    let summary = summary.to_owned();
+    let body = body.to_owned();
+    std::thread::spawn(move || {
+        if let Err(why) = notify_rust::Notification::new()
+            .appname(&t!("apptitle"))
+            .summary(&summary)
+            .body(&body)
+            .show()
+        {
+            warn!("Failed to show notification: {}", why);
+        }
+    });
+}
+
+/// The tray icon. It's removed when dropped.
+pub struct Tray {
+    handle: platform::TrayHandle,
+}
+
+impl Tray {
+    pub fn show(image: &TrayIconImage, tooltip: &str) -> Result<Tray> {
+        Ok(Tray {
+            handle: platform::TrayHandle::show(image, tooltip)?,
+        })
+    }
+
+    pub fn set_tooltip(&self, tooltip: &str) {
+        self.handle.set_tooltip(tooltip);
+    }
+}
+
+/// Windows and macOS run tray icons from the thread with the application's event loop, which is
+/// the one the UI updates from.
+#[cfg(not(target_os = "linux"))]
+mod platform {
+    use anyhow::Result;
+    use tray_icon::TrayIcon;
+
+    use super::{build_tray_icon, TrayIconImage};
+
+    pub struct TrayHandle {
+        tray_icon: TrayIcon,
+    }
+
+    impl TrayHandle {
+        pub fn show(image: &TrayIconImage, tooltip: &str) -> Result<TrayHandle> {
+            Ok(TrayHandle {
+                tray_icon: build_tray_icon(image, tooltip)?,
+            })
+        }
+
+        pub fn set_tooltip(&self, tooltip: &str) {
+            if let Err(why) = self.tray_icon.set_tooltip(Some(tooltip)) {
+                warn!("Failed to update tray tooltip: {}", why);
+            }
+        }
+    }
+}
+
+/// On Linux the tray icon lives on the thread running the gtk main loop
+#[cfg(target_os = "linux")]
+mod platform {
+    use std::cell::RefCell;
+
+    use anyhow::Result;
+    use tray_icon::TrayIcon;
+
+    use super::{build_tray_icon, TrayIconImage};
+    use crate::gtkthread::run_on_gtk_thread;
+
+    thread_local! {
+        // Only ever set on the gtk thread
+        static TRAY_ICON: RefCell<Option<TrayIcon>> = const { RefCell::new(None) };
+    }
+
+    pub struct TrayHandle;
+
+    impl TrayHandle {
+        pub fn show(image: &TrayIconImage, tooltip: &str) -> Result<TrayHandle> {
+            let image = image.clone();
+            let tooltip = tooltip.to_owned();
+            run_on_gtk_thread(move || {
+                let tray_icon = build_tray_icon(&image, &tooltip)
+                    .map_err(|why| error!("Failed to create tray icon: {}", why))
+                    .ok();
+                TRAY_ICON.with(|t| *t.borrow_mut() = tray_icon);
+            })?;
+            Ok(TrayHandle)
+        }
+
+        pub fn set_tooltip(&self, tooltip: &str) {
+            let tooltip = tooltip.to_owned();
+            let sent = run_on_gtk_thread(move || {
+                TRAY_ICON.with(|t| {
+                    if let Some(tray_icon) = t.borrow().as_ref() {
+                        if let Err(why) = tray_icon.set_tooltip(Some(tooltip)) {
+                            warn!("Failed to update tray tooltip: {}", why);
+                        }
+                    }
+                })
+            });
+            if let Err(why) = sent {
+                warn!("Failed to update tray tooltip: {}", why);
+            }
+        }
+    }
+
+    impl Drop for TrayHandle {
+        fn drop(&mut self) {
+            if let Err(why) = run_on_gtk_thread(|| TRAY_ICON.with(|t| *t.borrow_mut() = None)) {
+                warn!("Failed to remove tray icon: {}", why);
+            }
+        }
+    }
+}
@@ -0,0 +1,79 @@
+use crate::state::{ApplicationState, WindowState};
+
+/// Maximum number of undo steps retained
+const MAX_UNDO_STEPS: usize = 100;
+
+/// Undo and redo history of the processing options. Window state is not tracked.
+#[derive(Default)]
+pub struct UndoHistory {
+    undo_stack: Vec<ApplicationState>,
+    redo_stack: Vec<ApplicationState>,
+    committed: Option<ApplicationState>,
+}
+
+fn tracked(state: &ApplicationState) -> ApplicationState {
+    ApplicationState {
+        window: WindowState::default(),
+        ..state.clone()
+    }
+}
+
+impl UndoHistory {
+    /// Records `state` as a new undo step if it differs from the last recorded state. Callers
+    /// should wait until an edit is finished (e.g. a drag is released) so that it's recorded as
+    /// a single step.
+    pub fn track(&mut self, state: &ApplicationState) {
+        let current = tracked(state);
+        match self.committed.take() {
+            Some(committed) if committed != current => {
+                self.undo_stack.push(committed);
+                if self.undo_stack.len() > MAX_UNDO_STEPS {
+                    self.undo_stack.remove(0);
+                }
+                self.redo_stack.clear();
+            }
+            _ => {}
+        }
+        self.committed = Some(current);
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo_stack.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo_stack.is_empty()
+    }
+
+    /// Reverts `state` to the previous undo step, keeping its window state. Returns false if
+    /// there is nothing to undo.
+    pub fn undo(&mut self, state: &mut ApplicationState) -> bool {
+        if let Some(previous) = self.undo_stack.pop() {
+            self.redo_stack.push(tracked(state));
+            self.restore(state, previous);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Reapplies the most recently undone step to `state`. Returns false if there is nothing to
+    /// redo.
+    pub fn redo(&mut self, state: &mut ApplicationState) -> bool {
+        if let Some(next) = self.redo_stack.pop() {
+            self.undo_stack.push(tracked(state));
+            self.restore(state, next);
+            true
+        } else {
+            false
+        }
+    }
+
+    fn restore(&mut self, state: &mut ApplicationState, restored: ApplicationState) {
+        *state = ApplicationState {
+            window: state.window.clone(),
+            ..restored.clone()
+        };
+        self.committed = Some(restored);
+    }
+}
@@ -0,0 +1,125 @@
+use egui::{Color32, RichText, Ui};
+use solhat::target::Target;
+
+use crate::state::ApplicationState;
+
+/// Options which can be flagged by validation
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum ValidatedOption {
+    LimbDarkeningCorrection,
+    SolarRadius,
+    MaxFrames,
+    MinimumSigma,
+    MaximumSigma,
+    CropWidth,
+    CropHeight,
+    QualityWeighting,
+    Drizzle,
+}
+
+#[derive(Debug, Clone)]
+pub struct OptionWarning {
+    pub option: ValidatedOption,
+    pub message: String,
+}
+
+impl OptionWarning {
+    fn new(option: ValidatedOption, message: String) -> Self {
+        OptionWarning { option, message }
+    }
+}
+
+/// Checks the application state for option combinations which are unlikely to produce a useful
+/// result. `frame_size` is the size of the light frames, if loaded, and `sigma_list` holds the
+/// most recent analysis results, if any.
+pub fn validate_options(
+    state: &ApplicationState,
+    frame_size: Option<[usize; 2]>,
+    sigma_list: &[f64],
+) -> Vec<OptionWarning> {
+    let mut warnings = vec![];
+
+    if state.ld_correction && state.target != Target::Sun {
+        warnings.push(OptionWarning::new(
+            ValidatedOption::LimbDarkeningCorrection,
+            t!("validation.ld_correction_not_sun").to_string(),
+        ));
+    }
+
+    if let Some(frame_size) = frame_size {
+        let [out_width, out_height] = state.output_dimensions(frame_size);
+        if state.ld_correction && state.solar_radius_pixels * 2 > out_width.max(out_height) {
+            warnings.push(OptionWarning::new(
+                ValidatedOption::SolarRadius,
+                t!("validation.solar_radius_too_large").to_string(),
+            ));
+        }
+
+        if state.crop_width > frame_size[0] {
+            warnings.push(OptionWarning::new(
+                ValidatedOption::CropWidth,
+                t!("validation.crop_exceeds_frame").to_string(),
+            ));
+        }
+
+        if state.crop_height > frame_size[1] {
+            warnings.push(OptionWarning::new(
+                ValidatedOption::CropHeight,
+                t!("validation.crop_exceeds_frame").to_string(),
+            ));
+        }
+    }
+
+    if state.max_frames == 0 {
+        warnings.push(OptionWarning::new(
+            ValidatedOption::MaxFrames,
+            t!("validation.max_frames_zero").to_string(),
+        ));
+    }
+
+    if state.quality_weighting && !state.quality_weighted() {
+        warnings.push(OptionWarning::new(
+            ValidatedOption::QualityWeighting,
+            t!("validation.quality_weighting_unsupported").to_string(),
+        ));
+    }
+
+    if state.custom_drizzle() && !state.custom_drizzled() {
+        warnings.push(OptionWarning::new(
+            ValidatedOption::Drizzle,
+            t!("validation.custom_drizzle_unsupported").to_string(),
+        ));
+    }
+
+    if !sigma_list.is_empty()
+        && !sigma_list
+            .iter()
+            .any(|s| *s >= state.min_sigma && *s <= state.max_sigma)
+    {
+        let option = if sigma_list.iter().all(|s| *s < state.min_sigma) {
+            ValidatedOption::MinimumSigma
+        } else {
+            ValidatedOption::MaximumSigma
+        };
+        warnings.push(OptionWarning::new(
+            option,
+            t!("validation.sigma_excludes_all").to_string(),
+        ));
+    }
+
+    warnings
+}
+
+/// Draws a warning marker, with the warning messages as a tooltip, if any warnings apply to
+/// `option`.
+pub fn warning_marker(ui: &mut Ui, warnings: &[OptionWarning], option: ValidatedOption) {
+    let messages: Vec<&str> = warnings
+        .iter()
+        .filter(|w| w.option == option)
+        .map(|w| w.message.as_str())
+        .collect();
+    if !messages.is_empty() {
+        ui.label(RichText::new("⚠").color(Color32::YELLOW))
+            .on_hover_text(messages.join("\n"));
+    }
+}